@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use zettabgp::bmp::prelude::*;
+
+#[path = "../../src/wireparse.rs"]
+#[allow(dead_code)]
+mod wireparse;
+
+fuzz_target!(|data: &[u8]| {
+    // message following common header, as bmppeer passes it
+    let mut sess = BMPSession::default();
+    let _ = wireparse::decode_bmp(&mut sess, data);
+});
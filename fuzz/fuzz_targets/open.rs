@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use zettabgp::prelude::*;
+
+#[path = "../../src/wireparse.rs"]
+#[allow(dead_code)]
+mod wireparse;
+
+fuzz_target!(|data: &[u8]| {
+    let params = BgpSessionParams::new(
+        64512,
+        180,
+        BgpTransportMode::IPv4,
+        std::net::Ipv4Addr::new(1, 1, 1, 1),
+        Vec::new(),
+    );
+    let _ = wireparse::decode_open(&params, data);
+});
@@ -0,0 +1,30 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use zettabgp::prelude::*;
+
+#[path = "../../src/wireparse.rs"]
+#[allow(dead_code)]
+mod wireparse;
+
+fuzz_target!(|data: &[u8]| {
+    // first byte selects session flavour so both 2 and 4 byte AS paths get covered
+    let (sel, body) = match data.split_first() {
+        Some(v) => v,
+        None => return,
+    };
+    let mut caps = vec![BgpCapability::SafiIPv4u, BgpCapability::SafiIPv6u];
+    if sel & 1 != 0 {
+        caps.push(BgpCapability::CapASN32(64512));
+    }
+    let mut params = BgpSessionParams::new(
+        64512,
+        180,
+        BgpTransportMode::IPv4,
+        std::net::Ipv4Addr::new(1, 1, 1, 1),
+        caps.clone(),
+    );
+    params.match_caps(&caps);
+    if wireparse::decode_update(&params, body).is_err() {
+        wireparse::treat_as_withdraw(body, sel & 2 != 0);
+    }
+});
@@ -0,0 +1,204 @@
+//! special-use address classification: transition mechanisms (6to4, Teredo, NAT64
+//! translation prefixes, IPv4-mapped addresses) and other IPv4 and IPv6 special-use blocks,
+//! so route annotations, statistics and reports can tell them from globally routed space
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddrClass {
+    /// 2002::/16
+    #[serde(rename = "6to4")]
+    SixToFour,
+    /// 192.88.99.0/24 anycast relays of 6to4
+    #[serde(rename = "6to4-relay")]
+    SixToFourRelay,
+    /// 2001::/32
+    Teredo,
+    /// 64:ff9b::/96 well-known and 64:ff9b:1::/48 local-use translation prefixes
+    Nat64,
+    /// ::ffff:0:0/96
+    #[serde(rename = "ipv4-mapped")]
+    V4Mapped,
+    ThisNetwork,
+    Private,
+    /// 100.64.0.0/10 carrier grade NAT space
+    SharedAddress,
+    Loopback,
+    LinkLocal,
+    ProtocolAssignments,
+    Documentation,
+    Benchmarking,
+    Orchid,
+    Discard,
+    UniqueLocal,
+    Multicast,
+    Reserved,
+}
+impl AddrClass {
+    /// prefix serves IPv4/IPv6 transition mechanism
+    pub fn is_transition(&self) -> bool {
+        matches!(
+            self,
+            AddrClass::SixToFour
+                | AddrClass::SixToFourRelay
+                | AddrClass::Teredo
+                | AddrClass::Nat64
+                | AddrClass::V4Mapped
+        )
+    }
+}
+
+/// special-use blocks, more specific ones go first
+const SPECIAL_V4: [([u8; 4], u8, AddrClass); 16] = [
+    ([0, 0, 0, 0], 8, AddrClass::ThisNetwork),
+    ([10, 0, 0, 0], 8, AddrClass::Private),
+    ([100, 64, 0, 0], 10, AddrClass::SharedAddress),
+    ([127, 0, 0, 0], 8, AddrClass::Loopback),
+    ([169, 254, 0, 0], 16, AddrClass::LinkLocal),
+    ([172, 16, 0, 0], 12, AddrClass::Private),
+    ([192, 0, 0, 0], 24, AddrClass::ProtocolAssignments),
+    ([192, 0, 2, 0], 24, AddrClass::Documentation),
+    ([192, 88, 99, 0], 24, AddrClass::SixToFourRelay),
+    ([192, 168, 0, 0], 16, AddrClass::Private),
+    ([198, 18, 0, 0], 15, AddrClass::Benchmarking),
+    ([198, 51, 100, 0], 24, AddrClass::Documentation),
+    ([203, 0, 113, 0], 24, AddrClass::Documentation),
+    ([224, 0, 0, 0], 4, AddrClass::Multicast),
+    ([240, 0, 0, 0], 4, AddrClass::Reserved),
+    ([255, 255, 255, 255], 32, AddrClass::Reserved),
+];
+const SPECIAL_V6: [([u16; 8], u8, AddrClass); 16] = [
+    ([0, 0, 0, 0, 0, 0, 0, 0], 128, AddrClass::ThisNetwork),
+    ([0, 0, 0, 0, 0, 0, 0, 1], 128, AddrClass::Loopback),
+    ([0, 0, 0, 0, 0, 0xffff, 0, 0], 96, AddrClass::V4Mapped),
+    ([0x64, 0xff9b, 0, 0, 0, 0, 0, 0], 96, AddrClass::Nat64),
+    ([0x64, 0xff9b, 1, 0, 0, 0, 0, 0], 48, AddrClass::Nat64),
+    ([0x100, 0, 0, 0, 0, 0, 0, 0], 64, AddrClass::Discard),
+    ([0x2001, 0, 0, 0, 0, 0, 0, 0], 32, AddrClass::Teredo),
+    ([0x2001, 2, 0, 0, 0, 0, 0, 0], 48, AddrClass::Benchmarking),
+    ([0x2001, 0x10, 0, 0, 0, 0, 0, 0], 28, AddrClass::Orchid),
+    ([0x2001, 0x20, 0, 0, 0, 0, 0, 0], 28, AddrClass::Orchid),
+    (
+        [0x2001, 0, 0, 0, 0, 0, 0, 0],
+        23,
+        AddrClass::ProtocolAssignments,
+    ),
+    (
+        [0x2001, 0xdb8, 0, 0, 0, 0, 0, 0],
+        32,
+        AddrClass::Documentation,
+    ),
+    ([0x2002, 0, 0, 0, 0, 0, 0, 0], 16, AddrClass::SixToFour),
+    ([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7, AddrClass::UniqueLocal),
+    ([0xfe80, 0, 0, 0, 0, 0, 0, 0], 10, AddrClass::LinkLocal),
+    ([0xff00, 0, 0, 0, 0, 0, 0, 0], 8, AddrClass::Multicast),
+];
+
+/// prefix lies within block of netlen, covering prefixes (like default route) do not
+fn within(net: u128, netlen: u8, addr: u128, len: u8, width: u32) -> bool {
+    if len < netlen {
+        return false;
+    }
+    let shift = width - netlen as u32;
+    net.checked_shr(shift).unwrap_or(0) == addr.checked_shr(shift).unwrap_or(0)
+}
+
+pub fn classify_v4(addr: &Ipv4Addr, len: u8) -> Option<AddrClass> {
+    let a = u32::from(*addr) as u128;
+    SPECIAL_V4
+        .iter()
+        .find(|(net, netlen, _)| within(u32::from_be_bytes(*net) as u128, *netlen, a, len, 32))
+        .map(|(_, _, c)| *c)
+}
+pub fn classify_v6(addr: &Ipv6Addr, len: u8) -> Option<AddrClass> {
+    let a = u128::from(*addr);
+    SPECIAL_V6
+        .iter()
+        .find(|(s, netlen, _)| {
+            let net = Ipv6Addr::new(s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7]);
+            within(u128::from(net), *netlen, a, len, 128)
+        })
+        .map(|(_, _, c)| *c)
+}
+
+/// prefixes with at least one active route by class, maintained along with prefix length
+/// statistics
+#[derive(Debug, Default, Clone)]
+pub struct AddrClassStats {
+    counts: BTreeMap<AddrClass, u64>,
+}
+impl AddrClassStats {
+    pub fn track(&mut self, class: AddrClass, was: bool, now: bool) {
+        match (was, now) {
+            (false, true) => *self.counts.entry(class).or_default() += 1,
+            (true, false) => {
+                if let Some(n) = self.counts.get_mut(&class) {
+                    *n = n.saturating_sub(1);
+                    if *n == 0 {
+                        self.counts.remove(&class);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+    pub fn report(&self) -> AddrClassReport {
+        AddrClassReport {
+            special: self.counts.values().sum(),
+            transition: self
+                .counts
+                .iter()
+                .filter(|(c, _)| c.is_transition())
+                .map(|(_, n)| n)
+                .sum(),
+            classes: self.counts.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AddrClassReport {
+    /// prefixes in any special-use block
+    pub special: u64,
+    /// prefixes of transition mechanisms, included in special
+    pub transition: u64,
+    pub classes: BTreeMap<AddrClass, u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addrclass() {
+        let v6 = |s: &str, l| classify_v6(&s.parse().unwrap(), l);
+        let v4 = |s: &str, l| classify_v4(&s.parse().unwrap(), l);
+        assert_eq!(v6("2002:c000:0204::", 48), Some(AddrClass::SixToFour));
+        assert_eq!(v6("2001::", 32), Some(AddrClass::Teredo));
+        assert_eq!(v6("2001:4::", 32), Some(AddrClass::ProtocolAssignments));
+        assert_eq!(v6("64:ff9b::", 96), Some(AddrClass::Nat64));
+        assert_eq!(v6("2001:db8:1::", 48), Some(AddrClass::Documentation));
+        assert_eq!(v6("2a00:1450::", 32), None);
+        assert_eq!(v6("::", 0), None);
+        assert_eq!(v4("192.88.99.0", 24), Some(AddrClass::SixToFourRelay));
+        assert_eq!(v4("100.64.1.0", 24), Some(AddrClass::SharedAddress));
+        assert_eq!(v4("172.32.0.0", 16), None);
+        assert_eq!(v4("0.0.0.0", 0), None);
+        assert!(AddrClass::Teredo.is_transition());
+        let mut st = AddrClassStats::default();
+        st.track(AddrClass::Teredo, false, true);
+        st.track(AddrClass::Private, false, true);
+        st.track(AddrClass::Private, true, false);
+        let r = st.report();
+        assert_eq!((r.special, r.transition), (1, 1));
+        assert_eq!(
+            serde_json::to_string(&r.classes).unwrap(),
+            r#"{"teredo":1}"#
+        );
+    }
+}
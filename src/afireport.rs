@@ -0,0 +1,119 @@
+//! address families of OPEN negotiation: offered by peer but not enabled in caps and
+//! enabled but not offered by peer, answer to "why don't I see IPv6 routes"
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::net::IpAddr;
+use zettabgp::prelude::*;
+
+/// caps option name of multiprotocol capability
+pub fn afi_name(cap: &BgpCapability) -> Option<&'static str> {
+    Some(match cap {
+        BgpCapability::SafiIPv4u => "ipv4u",
+        BgpCapability::SafiIPv4m => "ipv4m",
+        BgpCapability::SafiIPv4lu => "ipv4lu",
+        BgpCapability::SafiIPv4fu => "ipv4fu",
+        BgpCapability::SafiVPNv4u => "vpnv4u",
+        BgpCapability::SafiVPNv4m => "vpnv4m",
+        BgpCapability::SafiVPNv4fu => "vpnv4fu",
+        BgpCapability::SafiIPv4mdt => "ipv4mdt",
+        BgpCapability::SafiIPv4mvpn => "mvpn",
+        BgpCapability::SafiVPLS => "vpls",
+        BgpCapability::SafiEVPN => "evpn",
+        BgpCapability::SafiIPv6u => "ipv6u",
+        BgpCapability::SafiIPv6lu => "ipv6lu",
+        BgpCapability::SafiIPv6fu => "ipv6fu",
+        BgpCapability::SafiVPNv6u => "vpnv6u",
+        BgpCapability::SafiVPNv6m => "vpnv6m",
+        BgpCapability::SafiIPv6mdt => "ipv6mdt",
+        _ => return None,
+    })
+}
+
+fn names(caps: &[BgpCapability]) -> Vec<&'static str> {
+    let mut ret: Vec<&'static str> = caps.iter().filter_map(afi_name).collect();
+    ret.sort_unstable();
+    ret.dedup();
+    ret
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AfiReport {
+    pub time: Timestamp,
+    pub peer: IpAddr,
+    pub negotiated: Vec<&'static str>,
+    /// offered by peer, not enabled by collector
+    pub peer_only: Vec<&'static str>,
+    /// enabled by collector, not offered by peer
+    pub local_only: Vec<&'static str>,
+}
+impl AfiReport {
+    pub fn new(peer: IpAddr, local: &[BgpCapability], remote: &[BgpCapability]) -> AfiReport {
+        let (local, remote) = (names(local), names(remote));
+        AfiReport {
+            time: Timestamp::now(),
+            peer,
+            negotiated: local
+                .iter()
+                .filter(|a| remote.contains(a))
+                .cloned()
+                .collect(),
+            peer_only: remote
+                .iter()
+                .filter(|a| !local.contains(a))
+                .cloned()
+                .collect(),
+            local_only: local
+                .iter()
+                .filter(|a| !remote.contains(a))
+                .cloned()
+                .collect(),
+        }
+    }
+    pub fn is_mismatch(&self) -> bool {
+        !self.peer_only.is_empty() || !self.local_only.is_empty()
+    }
+    /// what to change to get routes of missing address families
+    pub fn hint(&self) -> Option<String> {
+        let mut ret = Vec::new();
+        if !self.peer_only.is_empty() {
+            ret.push(format!(
+                "peer offers {} which is not enabled, add to caps of peer section",
+                self.peer_only.join(",")
+            ));
+        }
+        if !self.local_only.is_empty() {
+            ret.push(format!(
+                "peer does not offer {}, activate these address families for neighbor on router",
+                self.local_only.join(",")
+            ));
+        }
+        if ret.is_empty() {
+            None
+        } else {
+            Some(ret.join("; "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_afi_report() {
+        let local = vec![BgpCapability::SafiIPv4u, BgpCapability::CapASN32(0)];
+        let remote = vec![
+            BgpCapability::SafiIPv6u,
+            BgpCapability::SafiIPv4u,
+            BgpCapability::CapASN32(64500),
+        ];
+        let r = AfiReport::new("192.0.2.1".parse().unwrap(), &local, &remote);
+        assert_eq!(r.negotiated, vec!["ipv4u"]);
+        assert_eq!(r.peer_only, vec!["ipv6u"]);
+        assert!(r.local_only.is_empty());
+        assert!(r.hint().unwrap().starts_with("peer offers ipv6u"));
+        let r = AfiReport::new("192.0.2.1".parse().unwrap(), &remote, &remote);
+        assert!(!r.is_mismatch());
+        assert_eq!(r.hint(), None);
+    }
+}
@@ -0,0 +1,101 @@
+//! global allocator selected with jemalloc or mimalloc cargo feature and its statistics
+//! for /api/admin/memory, jemalloc wins when both are enabled (cargo build --all-features)
+use serde::Serialize;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+pub const NAME: &str = if cfg!(feature = "jemalloc") {
+    "jemalloc"
+} else if cfg!(feature = "mimalloc") {
+    "mimalloc"
+} else {
+    "system"
+};
+
+/// sizes in bytes, allocator counters are known for jemalloc only
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AllocStats {
+    pub allocator: &'static str,
+    /// process resident set size
+    pub rss: Option<u64>,
+    /// requested by application
+    pub allocated: Option<u64>,
+    /// in pages holding allocations
+    pub active: Option<u64>,
+    /// in physically resident allocator pages, including metadata
+    pub resident: Option<u64>,
+    pub mapped: Option<u64>,
+    /// unmapped but kept for reuse
+    pub retained: Option<u64>,
+    /// share of active pages not used by allocations
+    pub fragmentation: Option<f64>,
+}
+
+pub fn stats() -> AllocStats {
+    let mut st = AllocStats {
+        allocator: NAME,
+        rss: rss(),
+        ..Default::default()
+    };
+    allocator_stats(&mut st);
+    if let (Some(allocated), Some(active)) = (st.allocated, st.active) {
+        if active > 0 {
+            st.fragmentation = Some(1.0 - allocated as f64 / active as f64);
+        }
+    }
+    st
+}
+
+#[cfg(feature = "jemalloc")]
+fn allocator_stats(st: &mut AllocStats) {
+    use tikv_jemalloc_ctl::{epoch, stats};
+    // counters are cached until epoch is advanced
+    if let Err(e) = epoch::advance() {
+        warn!("jemalloc epoch: {}", e);
+        return;
+    }
+    st.allocated = stats::allocated::read().ok().map(|v| v as u64);
+    st.active = stats::active::read().ok().map(|v| v as u64);
+    st.resident = stats::resident::read().ok().map(|v| v as u64);
+    st.mapped = stats::mapped::read().ok().map(|v| v as u64);
+    st.retained = stats::retained::read().ok().map(|v| v as u64);
+}
+#[cfg(not(feature = "jemalloc"))]
+fn allocator_stats(_st: &mut AllocStats) {}
+
+#[cfg(target_os = "linux")]
+fn rss() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let pagesize = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if pagesize <= 0 {
+        return None;
+    }
+    Some(pages * pagesize as u64)
+}
+#[cfg(not(target_os = "linux"))]
+fn rss() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let st = stats();
+        assert_eq!(st.allocator, NAME);
+        #[cfg(target_os = "linux")]
+        assert!(st.rss.unwrap() > 0);
+        if let Some(f) = st.fragmentation {
+            assert!((0.0..1.0).contains(&f));
+        }
+    }
+}
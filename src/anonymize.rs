@@ -0,0 +1,563 @@
+//! anonymization mode for public demos: configured AS numbers and addresses within configured
+//! prefixes are pseudonymized in API output with keyed hash, same value always gets same
+//! pseudonym and addresses keep their prefix relations, so pathes and subnets keep structure.
+//! Only AS-bearing fields (AS path, origin and aggregator AS, communities) and address or
+//! prefix strings are rewritten, other numbers like med, counts and times are kept.
+use crate::config::ErrorConfig;
+use hmac::Mac;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+/// AS pseudonyms are taken from private range 4200000000-4294967294
+const PRIVATE_AS_BASE: u32 = 4_200_000_000;
+const PRIVATE_AS_COUNT: u32 = 94_967_295;
+/// pseudonyms of AS in community high half, 2-octet private range 64512-65534
+const PRIVATE_AS16_BASE: u32 = 64512;
+const PRIVATE_AS16_COUNT: u32 = 1023;
+
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    key: Vec<u8>,
+    asns: Vec<(u32, u32)>,
+    prefixes: Vec<(IpAddr, u8)>,
+    /// addresses with optional length or port, AS tokens like AS64500
+    pattern: regex::Regex,
+    digits: regex::Regex,
+    /// standard and large communities like 64500:100 and 64500:1:2
+    community: regex::Regex,
+}
+
+/// what kind of value field (JSON key or CSV column) holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// AS numbers, or text with AS numbers only like AS path
+    As,
+    /// standard communities, packed numbers or text
+    Comms,
+    /// large communities or text with them
+    LComms,
+    /// only addresses and prefixes are rewritten
+    Plain,
+}
+impl Field {
+    fn of(key: &str) -> Field {
+        match key.to_ascii_lowercase().as_str() {
+            "aspath" | "origin" | "origin_as" | "originas" | "aggregatoras" | "confed" | "asn"
+            | "as" | "as_num" | "peeras" | "peer_as" | "remote_as" | "local_as" | "ga" => Field::As,
+            "comms" | "communities" | "community" => Field::Comms,
+            "lcomms" | "large_communities" => Field::LComms,
+            _ => Field::Plain,
+        }
+    }
+}
+
+fn parse_asns(s: &str) -> Result<Vec<(u32, u32)>, String> {
+    let mut ret = Vec::new();
+    for item in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let item = item.trim_start_matches("AS").trim_start_matches("as");
+        let (a, b) = item.split_once('-').unwrap_or((item, item));
+        let num = |v: &str| -> Result<u32, String> {
+            v.trim()
+                .parse()
+                .map_err(|_| format!("invalid AS number {}", v))
+        };
+        let (a, b) = (num(a)?, num(b)?);
+        if a > b {
+            return Err(format!("invalid AS range {}", item));
+        }
+        ret.push((a, b));
+    }
+    Ok(ret)
+}
+
+fn parse_prefixes(s: &str) -> Result<Vec<(IpAddr, u8)>, String> {
+    let mut ret = Vec::new();
+    for item in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let (a, l) = item
+            .split_once('/')
+            .ok_or_else(|| format!("{} is not address/length", item))?;
+        let addr: IpAddr = a.parse().map_err(|_| format!("invalid address {}", a))?;
+        let len: u8 = l.parse().map_err(|_| format!("invalid length {}", l))?;
+        if len > width(&addr) as u8 {
+            return Err(format!("invalid length {}", l));
+        }
+        ret.push((mask(addr, len), len));
+    }
+    Ok(ret)
+}
+
+fn width(a: &IpAddr) -> u32 {
+    match a {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+fn to_bits(a: &IpAddr) -> u128 {
+    match a {
+        IpAddr::V4(a) => u32::from(*a) as u128,
+        IpAddr::V6(a) => u128::from(*a),
+    }
+}
+fn from_bits(like: &IpAddr, bits: u128) -> IpAddr {
+    match like {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(bits as u32)),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(bits)),
+    }
+}
+fn mask(a: IpAddr, len: u8) -> IpAddr {
+    let w = width(&a);
+    let len = (len as u32).min(w);
+    let keep = if len == 0 {
+        0
+    } else {
+        (u128::MAX >> (128 - w)) & !u128::MAX.checked_shr(128 - w + len).unwrap_or(0)
+    };
+    from_bits(&a, to_bits(&a) & keep)
+}
+
+impl Anonymizer {
+    pub fn new(key: &str, asns: Vec<(u32, u32)>, prefixes: Vec<(IpAddr, u8)>) -> Anonymizer {
+        Anonymizer {
+            key: key.as_bytes().to_vec(),
+            asns,
+            prefixes,
+            pattern: regex::Regex::new(
+                r"(?P<ip>[0-9A-Fa-f:.]*[:.][0-9A-Fa-f:.]*[0-9A-Fa-f:])(?:/(?P<len>\d{1,3}))?|\b(?P<as>AS|as)(?P<asn>\d+)\b",
+            )
+            .unwrap(),
+            digits: regex::Regex::new(r"\d+").unwrap(),
+            community: regex::Regex::new(r"\b(?P<ga>\d+):(?P<lo>\d+)(?P<large>:\d+)?\b").unwrap(),
+        }
+    }
+    /// anonymize_asns and anonymize_prefixes of main section, None if both are empty
+    pub fn from_ini(
+        mainsection: &HashMap<String, Option<String>>,
+    ) -> Result<Option<Anonymizer>, ErrorConfig> {
+        let asns = match mainsection.get("anonymize_asns") {
+            Some(Some(s)) => parse_asns(s)
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid anonymize_asns - {}", e)))?,
+            _ => Vec::new(),
+        };
+        let prefixes = match mainsection.get("anonymize_prefixes") {
+            Some(Some(s)) => parse_prefixes(s).map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid anonymize_prefixes - {}", e))
+            })?,
+            _ => Vec::new(),
+        };
+        if asns.is_empty() && prefixes.is_empty() {
+            return Ok(None);
+        }
+        match mainsection.get("anonymize_key") {
+            Some(Some(k)) if !k.trim().is_empty() => {
+                Ok(Some(Anonymizer::new(k.trim(), asns, prefixes)))
+            }
+            _ => Err(ErrorConfig::from_str(
+                "anonymize_key is required for anonymization",
+            )),
+        }
+    }
+    fn prf(&self, tag: &[u8]) -> [u8; 32] {
+        let mut mac =
+            hmac::Hmac::<sha2::Sha256>::new_from_slice(&self.key).expect("any key length");
+        mac.update(tag);
+        mac.finalize().into_bytes().into()
+    }
+    fn anonymized_as(&self, asn: u32) -> bool {
+        self.asns.iter().any(|(a, b)| *a <= asn && asn <= *b)
+    }
+    pub fn asn(&self, asn: u32) -> u32 {
+        if !self.anonymized_as(asn) {
+            return asn;
+        }
+        let mut tag = b"as".to_vec();
+        tag.extend_from_slice(&asn.to_be_bytes());
+        let h = self.prf(&tag);
+        PRIVATE_AS_BASE + u32::from_be_bytes([h[0], h[1], h[2], h[3]]) % PRIVATE_AS_COUNT
+    }
+    /// 2-octet pseudonym, for AS in high half of standard community
+    fn asn16(&self, asn: u32) -> u32 {
+        if !self.anonymized_as(asn) {
+            return asn;
+        }
+        let mut tag = b"as16".to_vec();
+        tag.extend_from_slice(&asn.to_be_bytes());
+        let h = self.prf(&tag);
+        PRIVATE_AS16_BASE + u32::from_be_bytes([h[0], h[1], h[2], h[3]]) % PRIVATE_AS16_COUNT
+    }
+    /// packed standard community, well-known ones (high half 0 or 65535) are kept
+    fn community(&self, c: u32) -> u32 {
+        match c >> 16 {
+            0 | 0xffff => c,
+            hi => (self.asn16(hi) << 16) | (c & 0xffff),
+        }
+    }
+    /// every bit is flipped by hash of bits before it, so common prefixes stay common.
+    /// One hash per octet keyed by preceding octets gives 255 flip bits, one for each
+    /// node of binary tree of bits within octet
+    pub fn addr(&self, a: IpAddr) -> IpAddr {
+        if !self
+            .prefixes
+            .iter()
+            .any(|(p, l)| width(p) == width(&a) && mask(a, *l) == *p)
+        {
+            return a;
+        }
+        let octets = match a {
+            IpAddr::V4(a) => a.octets().to_vec(),
+            IpAddr::V6(a) => a.octets().to_vec(),
+        };
+        let mut out = Vec::with_capacity(octets.len());
+        for (i, octet) in octets.iter().enumerate() {
+            let mut tag = vec![octets.len() as u8, i as u8];
+            tag.extend_from_slice(&octets[..i]);
+            let flips = self.prf(&tag);
+            let mut res = 0u8;
+            for bit in 0..8 {
+                // node index of bits before this one in heap order
+                let node = (1usize << bit) - 1 + (*octet as usize >> (8 - bit));
+                let flip = (flips[node / 8] >> (node % 8)) & 1;
+                res |= (((octet >> (7 - bit)) & 1) ^ flip) << (7 - bit);
+            }
+            out.push(res);
+        }
+        match a {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(out[0], out[1], out[2], out[3])),
+            IpAddr::V6(_) => {
+                let mut b = [0u8; 16];
+                b.copy_from_slice(&out);
+                IpAddr::V6(Ipv6Addr::from(b))
+            }
+        }
+    }
+    pub fn prefix(&self, a: IpAddr, len: u8) -> IpAddr {
+        mask(self.addr(a), len)
+    }
+    /// text with addresses, prefixes (also address:port) and AS64500 like tokens replaced,
+    /// other numbers are kept
+    pub fn text(&self, s: &str) -> String {
+        self.pattern
+            .replace_all(s, |c: &regex::Captures| {
+                if let Some(n) = c.name("asn") {
+                    return match n.as_str().parse::<u32>() {
+                        Ok(asn) => format!("{}{}", &c["as"], self.asn(asn)),
+                        Err(_) => c[0].to_string(),
+                    };
+                }
+                let ip = &c["ip"];
+                let len = c.name("len").and_then(|l| l.as_str().parse::<u8>().ok());
+                match (ip.parse::<IpAddr>(), len) {
+                    (Ok(a), Some(l)) if l as u32 <= width(&a) => {
+                        format!("{}/{}", self.prefix(a, l), l)
+                    }
+                    (Ok(a), None) => self.addr(a).to_string(),
+                    _ => match ip.parse::<SocketAddr>() {
+                        Ok(sa) => format!("{}:{}", self.addr(sa.ip()), sa.port()),
+                        Err(_) => c[0].to_string(),
+                    },
+                }
+            })
+            .to_string()
+    }
+    /// text of field kind, AS pathes have every number replaced
+    fn field_text(&self, s: &str, field: Field) -> String {
+        match field {
+            Field::Plain => self.text(s),
+            Field::As => self
+                .digits
+                .replace_all(s, |c: &regex::Captures| match c[0].parse::<u32>() {
+                    Ok(asn) => self.asn(asn).to_string(),
+                    Err(_) => c[0].to_string(),
+                })
+                .to_string(),
+            Field::Comms | Field::LComms => self
+                .community
+                .replace_all(&self.text(s), |c: &regex::Captures| {
+                    let ga = match c["ga"].parse::<u32>() {
+                        Ok(ga) if c.name("large").is_some() => self.asn(ga),
+                        Ok(ga) if ga <= 0xffff => self.asn16(ga),
+                        _ => return c[0].to_string(),
+                    };
+                    format!(
+                        "{}:{}{}",
+                        ga,
+                        &c["lo"],
+                        c.name("large").map(|m| m.as_str()).unwrap_or("")
+                    )
+                })
+                .to_string(),
+        }
+    }
+    /// JSON value with AS-bearing fields, addresses and prefixes replaced, split prefixes
+    /// ({"address":..,"length":..}) keep zero host bits
+    pub fn json(&self, v: Value) -> Value {
+        self.json_field(v, Field::Plain)
+    }
+    fn json_field(&self, v: Value, field: Field) -> Value {
+        match v {
+            Value::String(s) => Value::String(self.field_text(&s, field)),
+            Value::Number(n) => match (n.as_u64(), field) {
+                (Some(u), Field::As) if u <= u32::MAX as u64 => Value::from(self.asn(u as u32)),
+                (Some(u), Field::Comms) if u <= u32::MAX as u64 => {
+                    Value::from(self.community(u as u32))
+                }
+                _ => Value::Number(n),
+            },
+            Value::Array(a) => {
+                Value::Array(a.into_iter().map(|x| self.json_field(x, field)).collect())
+            }
+            Value::Object(o) => {
+                let len = o.get("length").and_then(|l| l.as_u64());
+                Value::Object(
+                    o.into_iter()
+                        .map(|(k, v)| {
+                            let v = match (k.as_str(), &v, len) {
+                                ("address", Value::String(s), Some(l)) => match s.parse::<IpAddr>()
+                                {
+                                    Ok(a) => Value::String(self.prefix(a, l as u8).to_string()),
+                                    Err(_) => self.json(v),
+                                },
+                                _ => self.json_field(v, Field::of(&k)),
+                            };
+                            (self.text(&k), v)
+                        })
+                        .collect(),
+                )
+            }
+            v => v,
+        }
+    }
+    /// response body of content type anonymized, None if it can not be rewritten
+    pub fn body(self: &Arc<Self>, content_type: &str, body: &[u8]) -> Option<Vec<u8>> {
+        if let Some(mut rw) = LineRewriter::new(self.clone(), content_type) {
+            let mut ret = rw.push(body);
+            ret.extend(rw.finish());
+            return Some(ret);
+        }
+        if content_type.contains("json") {
+            return match serde_json::from_slice::<Value>(body) {
+                Ok(v) => serde_json::to_vec(&self.json(v)).ok(),
+                Err(_) => Some(self.text(&String::from_utf8_lossy(body)).into_bytes()),
+            };
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    NdJson,
+    Csv,
+    Text,
+}
+
+/// rewrites line oriented bodies (ndjson, csv with header, plain text) as chunks arrive,
+/// so streamed responses are not buffered whole
+pub struct LineRewriter {
+    anon: Arc<Anonymizer>,
+    kind: LineKind,
+    pending: Vec<u8>,
+    /// field kinds of csv columns, from header line
+    columns: Option<Vec<Field>>,
+}
+impl LineRewriter {
+    /// None for single JSON documents and content which is not text
+    pub fn new(anon: Arc<Anonymizer>, content_type: &str) -> Option<LineRewriter> {
+        let kind = if content_type.contains("ndjson") {
+            LineKind::NdJson
+        } else if content_type.contains("csv") {
+            LineKind::Csv
+        } else if content_type.starts_with("text/") && !content_type.contains("json") {
+            LineKind::Text
+        } else {
+            return None;
+        };
+        Some(LineRewriter {
+            anon,
+            kind,
+            pending: Vec::new(),
+            columns: None,
+        })
+    }
+    /// rewritten complete lines of chunk, incomplete tail is kept for next chunk
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(chunk);
+        let end = match self.pending.iter().rposition(|b| *b == b'\n') {
+            Some(p) => p + 1,
+            None => return Vec::new(),
+        };
+        let lines: Vec<u8> = self.pending.drain(..end).collect();
+        let mut ret = Vec::with_capacity(lines.len());
+        for line in String::from_utf8_lossy(&lines).split_inclusive('\n') {
+            let (text, eol) = match line.strip_suffix('\n') {
+                Some(t) => (t, "\n"),
+                None => (line, ""),
+            };
+            ret.extend(self.line(text).into_bytes());
+            ret.extend(eol.as_bytes());
+        }
+        ret
+    }
+    /// rest of body without final line break
+    pub fn finish(&mut self) -> Vec<u8> {
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        let rest = std::mem::take(&mut self.pending);
+        self.line(&String::from_utf8_lossy(&rest)).into_bytes()
+    }
+    fn line(&mut self, line: &str) -> String {
+        match self.kind {
+            LineKind::Text => self.anon.text(line),
+            LineKind::NdJson => match serde_json::from_str::<Value>(line) {
+                Ok(v) => self.anon.json(v).to_string(),
+                Err(_) => self.anon.text(line),
+            },
+            LineKind::Csv => {
+                let fields = csv_split(line.trim_end_matches('\r'));
+                let columns = match self.columns {
+                    Some(ref c) => c,
+                    None => {
+                        self.columns = Some(fields.iter().map(|f| Field::of(f)).collect());
+                        return line.to_string();
+                    }
+                };
+                let out: Vec<String> = fields
+                    .iter()
+                    .enumerate()
+                    .map(|(i, f)| {
+                        let field = columns.get(i).copied().unwrap_or(Field::Plain);
+                        crate::histexport::csv_field(&self.anon.field_text(f, field))
+                    })
+                    .collect();
+                out.join(",")
+            }
+        }
+    }
+}
+
+/// fields of csv record, quoted fields unquoted
+fn csv_split(line: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut cur = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                cur.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => ret.push(std::mem::take(&mut cur)),
+            (c, _) => cur.push(c),
+        }
+    }
+    ret.push(cur);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymizer() {
+        let anon = Anonymizer::new(
+            "demo",
+            parse_asns("64512-64520, AS65001").unwrap(),
+            parse_prefixes("10.0.0.0/8, 2001:db8::/32").unwrap(),
+        );
+        assert!(parse_prefixes("10.0.0.0/33").is_err());
+        assert_eq!(anon.asn(64500), 64500);
+        let a = anon.asn(65001);
+        assert!(a >= PRIVATE_AS_BASE);
+        assert_eq!(anon.asn(65001), a);
+        let net = anon.prefix("10.1.2.0".parse().unwrap(), 24);
+        let host = anon.addr("10.1.2.3".parse().unwrap());
+        assert_ne!(net, "10.1.2.0".parse::<IpAddr>().unwrap());
+        assert_eq!(mask(host, 24), net);
+        assert_eq!(
+            anon.addr("192.0.2.1".parse().unwrap()).to_string(),
+            "192.0.2.1"
+        );
+        let v6 = anon.addr("2001:db8:1::1".parse().unwrap());
+        assert_eq!(
+            mask(v6, 48),
+            anon.prefix("2001:db8:1::".parse().unwrap(), 48)
+        );
+        assert_eq!(mask(v6, 32), anon.prefix("2001:db8::".parse().unwrap(), 32));
+        // bare numbers, times and dates are kept, AS tokens and addresses are not
+        assert_eq!(
+            anon.text("65001 at 12:04:28 2024-01-01 from AS65001 via 192.0.2.1"),
+            format!("65001 at 12:04:28 2024-01-01 from AS{} via 192.0.2.1", a)
+        );
+        assert_eq!(anon.text("10.1.2.0/24"), format!("{}/24", net));
+        assert_eq!(anon.text("10.1.2.3:179"), format!("{}:179", host));
+        let c16 = anon.asn16(65001);
+        assert!((PRIVATE_AS16_BASE..PRIVATE_AS16_BASE + PRIVATE_AS16_COUNT).contains(&c16));
+        assert_eq!(
+            anon.field_text("65001:100 65535:65281", Field::Comms),
+            format!("{}:100 65535:65281", c16)
+        );
+        assert_eq!(
+            anon.field_text("ASPath [64496, 65001]", Field::As),
+            format!("ASPath [64496, {}]", a)
+        );
+        let v = anon.json(serde_json::json!({
+            "10.1.2.0/24": {"Aspath": [64496, 65001], "Med": 65001, "Localpref": 64512,
+                "Comms": [(65001u32 << 16) | 100], "LComms": [{"ga": 65001, "ldp1": 65001, "ldp2": 1}],
+                "Aggregatoras": {"asn": 65001, "addr": "10.1.2.3"}, "count": 64515,
+                "time": "12:04:28"},
+            "split": {"address": "10.1.2.0", "length": 24}
+        }));
+        let r = &v[format!("{}/24", net)];
+        assert_eq!(r["Aspath"], serde_json::json!([64496, a]));
+        assert_eq!(r["Med"], serde_json::json!(65001));
+        assert_eq!(r["Localpref"], serde_json::json!(64512));
+        assert_eq!(r["count"], serde_json::json!(64515));
+        assert_eq!(r["time"], serde_json::json!("12:04:28"));
+        assert_eq!(r["Comms"], serde_json::json!([(c16 << 16) | 100]));
+        assert_eq!(
+            r["LComms"][0],
+            serde_json::json!({"ga": a, "ldp1": 65001, "ldp2": 1})
+        );
+        assert_eq!(r["Aggregatoras"]["asn"], serde_json::json!(a));
+        assert_eq!(
+            r["Aggregatoras"]["addr"],
+            serde_json::json!(host.to_string())
+        );
+        assert_eq!(v["split"]["address"], serde_json::json!(net.to_string()));
+    }
+    #[test]
+    fn test_line_rewriter() {
+        let anon = Arc::new(Anonymizer::new(
+            "demo",
+            parse_asns("65001").unwrap(),
+            parse_prefixes("10.0.0.0/8").unwrap(),
+        ));
+        let a = anon.asn(65001);
+        let host = anon.addr("10.1.2.3".parse().unwrap());
+        let mut rw = LineRewriter::new(anon.clone(), "text/csv; charset=utf-8").unwrap();
+        let mut out = rw.push(b"prefix,start,aspath,med\n10.1.2.3/32,12:04:28,");
+        assert_eq!(out, b"prefix,start,aspath,med\n".to_vec());
+        out.extend(rw.push(b"\"ASPath [65001, 64496]\",65001"));
+        out.extend(rw.finish());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "prefix,start,aspath,med\n{}/32,12:04:28,\"ASPath [{}, 64496]\",65001",
+                host, a
+            )
+        );
+        let mut rw = LineRewriter::new(anon.clone(), "application/x-ndjson").unwrap();
+        let out = rw.push(b"{\"aspath\":\"65001\",\"med\":65001}\n");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{{\"aspath\":\"{}\",\"med\":65001}}\n", a)
+        );
+        assert!(LineRewriter::new(anon.clone(), "application/json").is_none());
+        assert!(LineRewriter::new(anon, "text/plain; version=0.0.4").is_some());
+    }
+}
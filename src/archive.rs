@@ -0,0 +1,384 @@
+//! "sqlite" route processor archiving ipv4u/ipv6u history into normalized SQLite schema,
+//! available with sqlite feature
+use crate::bgpattrs::BgpAttrs;
+use crate::bgpsvc::BgpSessionId;
+use crate::config::{ErrorConfig, SvcConfig};
+use crate::processor::RouteProcessor;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use zettabgp::prelude::*;
+
+/// events waiting for writer at most, newer ones are dropped
+const QUEUE_SIZE: usize = 100000;
+
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS prefixes (
+    id INTEGER PRIMARY KEY,
+    prefix TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS attr_bundles (
+    id INTEGER PRIMARY KEY,
+    origin_as INTEGER,
+    aspath TEXT NOT NULL,
+    attrs TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY,
+    ts_us INTEGER NOT NULL,
+    session INTEGER NOT NULL,
+    prefix_id INTEGER NOT NULL REFERENCES prefixes(id),
+    bundle_id INTEGER REFERENCES attr_bundles(id),
+    kind TEXT NOT NULL CHECK (kind IN ('announce', 'withdraw'))
+);
+CREATE INDEX IF NOT EXISTS events_prefix ON events(prefix_id, ts_us);
+CREATE INDEX IF NOT EXISTS events_ts ON events(ts_us);
+CREATE INDEX IF NOT EXISTS bundles_origin ON attr_bundles(origin_as);
+";
+
+/// one archived route change, bundle is None for withdraws
+#[derive(Debug, Clone)]
+pub struct ArchiveEvent {
+    pub ts_us: i64,
+    pub session: BgpSessionId,
+    pub prefix: String,
+    pub bundle: Option<Arc<AttrBundle>>,
+}
+
+/// denormalized attribute set, attrs is its JSON and the bundle identity
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttrBundle {
+    pub origin_as: Option<u32>,
+    pub aspath: String,
+    pub attrs: String,
+}
+impl AttrBundle {
+    pub fn new(attrs: &BgpAttrs) -> AttrBundle {
+        AttrBundle {
+            origin_as: attrs.aspath.value.last().map(|x| x.value),
+            aspath: attrs
+                .aspath
+                .value
+                .iter()
+                .map(|x| x.value.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            attrs: serde_json::to_string(attrs).unwrap_or_default(),
+        }
+    }
+}
+
+/// wall clock in microseconds; processors run on dispatch task, where thread local
+/// Timestamp::now() override of update being applied is not set
+fn now_micros() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+/// ipv4u/ipv6u prefixes of NLRI
+pub fn unicast_prefixes(addrs: &BgpAddrs) -> Vec<String> {
+    match addrs {
+        BgpAddrs::IPV4U(v) => v.iter().map(|r| r.to_string()).collect(),
+        BgpAddrs::IPV4UP(v) => v.iter().map(|r| r.nlri.to_string()).collect(),
+        BgpAddrs::IPV6U(v) => v.iter().map(|r| r.to_string()).collect(),
+        BgpAddrs::IPV6UP(v) => v.iter().map(|r| r.nlri.to_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+pub struct SqliteArchive {
+    queue: std::sync::mpsc::SyncSender<ArchiveEvent>,
+    dropped: AtomicU64,
+}
+impl SqliteArchive {
+    pub fn create(
+        _cfg: &SvcConfig,
+        opts: &BTreeMap<String, String>,
+    ) -> Result<Arc<dyn RouteProcessor>, ErrorConfig> {
+        let path = match opts.get("path") {
+            Some(p) => p.clone(),
+            None => return Err(ErrorConfig::from_str("sqlite.path is required")),
+        };
+        let batch: usize = match opts.get("batch") {
+            Some(s) => s
+                .parse()
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid sqlite.batch - {}", e)))?,
+            None => 1000,
+        };
+        let (tx, rx) = std::sync::mpsc::sync_channel(QUEUE_SIZE);
+        writer::start(path, batch.max(1), rx)?;
+        Ok(Arc::new(SqliteArchive {
+            queue: tx,
+            dropped: AtomicU64::new(0),
+        }))
+    }
+    fn push(&self, evt: ArchiveEvent) {
+        if self.queue.try_send(evt).is_err() {
+            let n = self.dropped.fetch_add(1, Ordering::Relaxed);
+            if n.is_multiple_of(10000) {
+                warn!("SQLite archive is behind, {} events dropped", n + 1);
+            }
+        }
+    }
+}
+impl RouteProcessor for SqliteArchive {
+    fn on_update(&self, sid: BgpSessionId, attrs: &BgpAttrs, addrs: &BgpAddrs) {
+        let prefixes = unicast_prefixes(addrs);
+        if prefixes.is_empty() {
+            return;
+        }
+        let ts_us = now_micros();
+        let bundle = Arc::new(AttrBundle::new(attrs));
+        for prefix in prefixes {
+            self.push(ArchiveEvent {
+                ts_us,
+                session: sid,
+                prefix,
+                bundle: Some(bundle.clone()),
+            });
+        }
+    }
+    fn on_withdraw(&self, sid: BgpSessionId, addrs: &BgpAddrs) {
+        let ts_us = now_micros();
+        for prefix in unicast_prefixes(addrs) {
+            self.push(ArchiveEvent {
+                ts_us,
+                session: sid,
+                prefix,
+                bundle: None,
+            });
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod writer {
+    use super::*;
+    use rusqlite::{params, Connection, OptionalExtension, Transaction};
+    use std::collections::HashMap;
+    use std::sync::mpsc::{Receiver, RecvTimeoutError};
+    use std::time::Duration;
+
+    /// attribute bundle ids kept in memory at most
+    const BUNDLE_CACHE: usize = 100000;
+
+    struct Ids {
+        prefixes: HashMap<String, i64>,
+        bundles: HashMap<String, i64>,
+    }
+
+    fn prefix_id(tx: &Transaction, ids: &mut Ids, prefix: &str) -> rusqlite::Result<i64> {
+        if let Some(id) = ids.prefixes.get(prefix) {
+            return Ok(*id);
+        }
+        tx.execute(
+            "INSERT OR IGNORE INTO prefixes (prefix) VALUES (?1)",
+            params![prefix],
+        )?;
+        let id: i64 = tx.query_row(
+            "SELECT id FROM prefixes WHERE prefix = ?1",
+            params![prefix],
+            |r| r.get(0),
+        )?;
+        ids.prefixes.insert(prefix.to_string(), id);
+        Ok(id)
+    }
+
+    fn bundle_id(tx: &Transaction, ids: &mut Ids, b: &AttrBundle) -> rusqlite::Result<i64> {
+        if let Some(id) = ids.bundles.get(&b.attrs) {
+            return Ok(*id);
+        }
+        let found: Option<i64> = tx
+            .query_row(
+                "SELECT id FROM attr_bundles WHERE attrs = ?1",
+                params![b.attrs],
+                |r| r.get(0),
+            )
+            .optional()?;
+        let id = match found {
+            Some(id) => id,
+            None => {
+                tx.execute(
+                    "INSERT INTO attr_bundles (origin_as, aspath, attrs) VALUES (?1, ?2, ?3)",
+                    params![b.origin_as, b.aspath, b.attrs],
+                )?;
+                tx.last_insert_rowid()
+            }
+        };
+        if ids.bundles.len() >= BUNDLE_CACHE {
+            ids.bundles.clear();
+        }
+        ids.bundles.insert(b.attrs.clone(), id);
+        Ok(id)
+    }
+
+    fn write_event(tx: &Transaction, ids: &mut Ids, e: &ArchiveEvent) -> rusqlite::Result<()> {
+        let pid = prefix_id(tx, ids, &e.prefix)?;
+        let bid = match e.bundle {
+            Some(ref b) => Some(bundle_id(tx, ids, b)?),
+            None => None,
+        };
+        tx.execute(
+            "INSERT INTO events (ts_us, session, prefix_id, bundle_id, kind) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                e.ts_us,
+                e.session,
+                pid,
+                bid,
+                if bid.is_some() { "announce" } else { "withdraw" }
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn write_batch(
+        conn: &mut Connection,
+        ids: &mut Ids,
+        batch: &[ArchiveEvent],
+    ) -> rusqlite::Result<()> {
+        let tx = conn.transaction()?;
+        for e in batch.iter() {
+            write_event(&tx, ids, e)?;
+        }
+        tx.commit()
+    }
+
+    /// writes batch in one transaction, after failure retries events one by one,
+    /// so only failing events are lost; returns number of lost events
+    fn write_all(conn: &mut Connection, ids: &mut Ids, batch: &[ArchiveEvent]) -> usize {
+        let err = match write_batch(conn, ids, batch) {
+            Ok(()) => return 0,
+            Err(e) => e,
+        };
+        // ids of rolled back inserts are cached
+        ids.prefixes.clear();
+        ids.bundles.clear();
+        let mut lost = 0;
+        for e in batch.iter() {
+            if write_batch(conn, ids, std::slice::from_ref(e)).is_err() {
+                ids.prefixes.clear();
+                ids.bundles.clear();
+                lost += 1;
+            }
+        }
+        if lost > 0 {
+            warn!(
+                "SQLite archive: {} of {} events lost, first error: {}",
+                lost,
+                batch.len(),
+                err
+            );
+        }
+        lost
+    }
+
+    pub fn start(
+        path: String,
+        batch: usize,
+        rx: Receiver<ArchiveEvent>,
+    ) -> Result<(), ErrorConfig> {
+        let mut conn = Connection::open(path.as_str())
+            .and_then(|c| c.execute_batch(SCHEMA).map(|_| c))
+            .map_err(|e| ErrorConfig::from_string(format!("SQLite {} - {}", path, e)))?;
+        std::thread::Builder::new()
+            .name("sqlite_archive".into())
+            .spawn(move || {
+                let mut ids = Ids {
+                    prefixes: HashMap::new(),
+                    bundles: HashMap::new(),
+                };
+                let mut pending = Vec::with_capacity(batch);
+                loop {
+                    let closed = match rx.recv_timeout(Duration::from_secs(1)) {
+                        Ok(e) => {
+                            pending.push(e);
+                            false
+                        }
+                        Err(RecvTimeoutError::Timeout) => false,
+                        Err(RecvTimeoutError::Disconnected) => true,
+                    };
+                    // queued events are written together, up to batch per transaction
+                    while pending.len() < batch {
+                        match rx.try_recv() {
+                            Ok(e) => pending.push(e),
+                            Err(_) => break,
+                        }
+                    }
+                    if !pending.is_empty() {
+                        write_all(&mut conn, &mut ids, &pending);
+                        pending.clear();
+                    }
+                    if closed {
+                        return;
+                    }
+                }
+            })
+            .map_err(|e| ErrorConfig::from_string(format!("SQLite writer - {}", e)))?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_write_all() {
+            let mut conn = Connection::open_in_memory().unwrap();
+            conn.execute_batch(SCHEMA).unwrap();
+            conn.execute_batch(
+                "CREATE TRIGGER reject_13 BEFORE INSERT ON events WHEN NEW.session = 13
+                 BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+            )
+            .unwrap();
+            let mut ids = Ids {
+                prefixes: HashMap::new(),
+                bundles: HashMap::new(),
+            };
+            let batch: Vec<ArchiveEvent> = [1, 13, 2]
+                .iter()
+                .map(|s| ArchiveEvent {
+                    ts_us: 1,
+                    session: *s,
+                    prefix: format!("10.0.{}.0/24", s),
+                    bundle: None,
+                })
+                .collect();
+            assert_eq!(write_all(&mut conn, &mut ids, &batch), 1);
+            let n: i64 = conn
+                .query_row("SELECT count(*) FROM events", [], |r| r.get(0))
+                .unwrap();
+            assert_eq!(n, 2);
+        }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+mod writer {
+    use super::*;
+
+    pub fn start(
+        _path: String,
+        _batch: usize,
+        _rx: std::sync::mpsc::Receiver<ArchiveEvent>,
+    ) -> Result<(), ErrorConfig> {
+        Err(ErrorConfig::from_str("built without sqlite feature"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::CompactASPath;
+
+    #[test]
+    fn test_bundle() {
+        let mut attrs = BgpAttrs::new();
+        attrs.aspath = Arc::new(CompactASPath::from(vec![64501, 64500]));
+        let b = AttrBundle::new(&attrs);
+        assert_eq!(b.origin_as, Some(64500));
+        assert_eq!(b.aspath, "64501 64500");
+        assert_eq!(b, AttrBundle::new(&attrs));
+    }
+}
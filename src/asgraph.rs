@@ -0,0 +1,176 @@
+use crate::bgprib::*;
+use crate::compact::CompactASPath;
+use crate::ribfilter::{FilterMatchRoute, RouteFilter};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use zettabgp::prelude::*;
+
+/// AS graph node
+#[derive(Debug, Clone, Serialize)]
+pub struct AsGraphNode {
+    pub id: u32,
+    /// how many pathes traverse this AS
+    pub count: u64,
+}
+
+/// AS graph edge between adjacent ASes of path
+#[derive(Debug, Clone, Serialize)]
+pub struct AsGraphEdge {
+    pub source: u32,
+    pub target: u32,
+    pub count: u64,
+}
+
+/// AS level graph built from observed pathes
+#[derive(Debug, Clone, Serialize)]
+pub struct AsGraph {
+    pub routes: usize,
+    pub nodes: Vec<AsGraphNode>,
+    pub edges: Vec<AsGraphEdge>,
+}
+
+/// Accumulates pathes into AS graph
+#[derive(Debug, Default)]
+pub struct AsGraphBuilder {
+    routes: usize,
+    nodes: BTreeMap<u32, u64>,
+    edges: BTreeMap<(u32, u32), u64>,
+}
+impl AsGraphBuilder {
+    pub fn new() -> AsGraphBuilder {
+        Self::default()
+    }
+    /// adds AS path, prepends are collapsed
+    pub fn add_path(&mut self, aspath: &CompactASPath) {
+        let mut prev: Option<u32> = None;
+        for asn in aspath.value.iter().map(|x| x.value) {
+            if prev == Some(asn) {
+                continue;
+            }
+            *self.nodes.entry(asn).or_insert(0) += 1;
+            if let Some(p) = prev {
+                *self.edges.entry((p, asn)).or_insert(0) += 1;
+            }
+            prev = Some(asn);
+        }
+    }
+    /// adds active pathes of route from all sessions
+    pub fn add_route(&mut self, bse: &BgpSessionEntry) {
+        self.routes += 1;
+        for (_, pe) in bse.items.iter() {
+            for (_, hist) in pe.items.iter() {
+                if let Some((_, last)) = hist.items.iter().last() {
+                    if last.active {
+                        self.add_path(last.attrs.aspath.as_ref());
+                    }
+                }
+            }
+        }
+    }
+    pub fn build(self) -> AsGraph {
+        AsGraph {
+            routes: self.routes,
+            nodes: self
+                .nodes
+                .into_iter()
+                .map(|(id, count)| AsGraphNode { id, count })
+                .collect(),
+            edges: self
+                .edges
+                .into_iter()
+                .map(|((source, target), count)| AsGraphEdge {
+                    source,
+                    target,
+                    count,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// graph for routes matching prefix filter, falls back to covering route
+pub fn graph_by_prefix<T: FilterMatchRoute + BgpRIBKey>(
+    safi: &BgpRIBSafi<T>,
+    filter: &RouteFilter,
+    limit: usize,
+) -> AsGraph {
+    let mut builder = AsGraphBuilder::new();
+    for (_, v) in filter.iter_nets(safi, 1, true).take(limit) {
+        builder.add_route(v);
+    }
+    if builder.routes < 1 {
+        if let Some((_, v)) = filter.find_best_supernet(safi, 1, true) {
+            builder.add_route(v);
+        }
+    }
+    builder.build()
+}
+
+/// graph for routes having specified AS in path, uses AS path index
+pub fn graph_by_asn<T: BgpRIBKey>(safi: &BgpRIBSafi<T>, asn: u32, limit: usize) -> AsGraph {
+    let mut builder = AsGraphBuilder::new();
+    if let Some(keys) = safi.idx_aspath.idx.get(&BgpAS::new(asn)) {
+        for v in keys.iter().filter_map(|k| safi.items.get(k)).take(limit) {
+            builder.add_route(v);
+        }
+    }
+    builder.build()
+}
+
+impl BgpRIB {
+    pub fn as_graph_by_prefix(
+        &self,
+        ribname: &str,
+        filter: &RouteFilter,
+        limit: usize,
+    ) -> Option<AsGraph> {
+        match ribname {
+            "ipv4u" => Some(graph_by_prefix(&self.ipv4u, filter, limit)),
+            "ipv4m" => Some(graph_by_prefix(&self.ipv4m, filter, limit)),
+            "ipv4lu" => Some(graph_by_prefix(&self.ipv4lu, filter, limit)),
+            "vpnv4u" => Some(graph_by_prefix(&self.vpnv4u, filter, limit)),
+            "vpnv4m" => Some(graph_by_prefix(&self.vpnv4m, filter, limit)),
+            "ipv6u" => Some(graph_by_prefix(&self.ipv6u, filter, limit)),
+            "ipv6m" => Some(graph_by_prefix(&self.ipv6m, filter, limit)),
+            "ipv6lu" => Some(graph_by_prefix(&self.ipv6lu, filter, limit)),
+            "vpnv6u" => Some(graph_by_prefix(&self.vpnv6u, filter, limit)),
+            "vpnv6m" => Some(graph_by_prefix(&self.vpnv6m, filter, limit)),
+            _ => None,
+        }
+    }
+    pub fn as_graph_by_asn(&self, ribname: &str, asn: u32, limit: usize) -> Option<AsGraph> {
+        match ribname {
+            "ipv4u" => Some(graph_by_asn(&self.ipv4u, asn, limit)),
+            "ipv4m" => Some(graph_by_asn(&self.ipv4m, asn, limit)),
+            "ipv4lu" => Some(graph_by_asn(&self.ipv4lu, asn, limit)),
+            "vpnv4u" => Some(graph_by_asn(&self.vpnv4u, asn, limit)),
+            "vpnv4m" => Some(graph_by_asn(&self.vpnv4m, asn, limit)),
+            "ipv6u" => Some(graph_by_asn(&self.ipv6u, asn, limit)),
+            "ipv6m" => Some(graph_by_asn(&self.ipv6m, asn, limit)),
+            "ipv6lu" => Some(graph_by_asn(&self.ipv6lu, asn, limit)),
+            "vpnv6u" => Some(graph_by_asn(&self.vpnv6u, asn, limit)),
+            "vpnv6m" => Some(graph_by_asn(&self.vpnv6m, asn, limit)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asgraph_path() {
+        let mut b = AsGraphBuilder::new();
+        b.add_path(&CompactASPath::from(vec![100, 100, 200, 300]));
+        b.add_path(&CompactASPath::from(vec![100, 400, 300]));
+        let g = b.build();
+        assert_eq!(g.nodes.len(), 4);
+        assert_eq!(g.nodes[0].id, 100);
+        assert_eq!(g.nodes[0].count, 2);
+        assert_eq!(g.edges.len(), 4);
+        assert_eq!(g.edges[0].source, 100);
+        assert_eq!(g.edges[0].target, 200);
+        assert_eq!(g.edges[0].count, 1);
+    }
+}
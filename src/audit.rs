@@ -0,0 +1,114 @@
+//! audit trail of admin API calls, appended to audit_file as JSON lines
+use crate::timestamp::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::sync::Mutex;
+
+/// entries kept in memory for /api/audit
+const RECENT: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: Timestamp,
+    /// token name, "-" when request carried no valid token
+    pub token: String,
+    pub action: String,
+    pub params: BTreeMap<String, String>,
+    /// ok, denied or error text
+    pub outcome: String,
+}
+
+pub struct AuditLog {
+    file: Option<String>,
+    recent: Mutex<VecDeque<AuditEntry>>,
+}
+impl AuditLog {
+    /// recent entries are restored from file so /api/audit survives restarts
+    pub fn new(file: Option<String>) -> AuditLog {
+        let mut recent = VecDeque::new();
+        if let Some(f) = file.as_ref() {
+            if let Ok(fl) = std::fs::File::open(f) {
+                for line in std::io::BufReader::new(fl).lines().map_while(Result::ok) {
+                    if let Ok(e) = serde_json::from_str::<AuditEntry>(&line) {
+                        if recent.len() >= RECENT {
+                            recent.pop_front();
+                        }
+                        recent.push_back(e);
+                    }
+                }
+            }
+        }
+        AuditLog {
+            file,
+            recent: Mutex::new(recent),
+        }
+    }
+    pub fn record(&self, entry: AuditEntry) {
+        info!(
+            "audit: {} {} {:?} - {}",
+            entry.token, entry.action, entry.params, entry.outcome
+        );
+        if let Some(f) = self.file.as_ref() {
+            let res = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(f)
+                .and_then(|mut fl| {
+                    let mut line = serde_json::to_vec(&entry)?;
+                    line.push(b'\n');
+                    fl.write_all(&line)
+                });
+            if let Err(e) = res {
+                warn!("Audit file {} write error: {}", f, e);
+            }
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() >= RECENT {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+    }
+    /// last entries, newest first
+    pub fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        self.recent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(action: &str) -> AuditEntry {
+        AuditEntry {
+            time: Timestamp::now(),
+            token: "ops".to_string(),
+            action: action.to_string(),
+            params: BTreeMap::new(),
+            outcome: "ok".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_audit_persist() {
+        let path = std::env::temp_dir().join(format!("bgpexplorer-audit-{}", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog::new(Some(path.clone()));
+        log.record(entry("flush"));
+        log.record(entry("snapshot"));
+        let restored = AuditLog::new(Some(path.clone()));
+        let r = restored.recent(10);
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].action, "snapshot");
+        assert_eq!(restored.recent(1).len(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+}
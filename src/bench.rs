@@ -0,0 +1,290 @@
+//! `bgpexplorer bench`: loads synthetic full table into ipv4 unicast RIB, applies churn and
+//! measures ingest throughput, lookup latency and memory
+use crate::bgpattrs::BgpAttrs;
+use crate::bgprib::BgpRIBSafi;
+use crate::compact::{CompactASPath, CompactCommunityList};
+use crate::config::HistoryChangeMode;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use zettabgp::prelude::*;
+
+/// prefixes announced in one synthetic UPDATE
+const UPDATE_PREFIXES: usize = 200;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchParams {
+    /// full table size, at most 2^24 /24 prefixes
+    pub routes: usize,
+    /// distinct attribute sets (AS path and communities)
+    pub paths: usize,
+    /// share of routes changed in each churn round, half withdrawn and half reannounced
+    pub churn: f64,
+    pub rounds: usize,
+    pub lookups: usize,
+    pub historydepth: usize,
+    pub seed: u64,
+}
+impl Default for BenchParams {
+    fn default() -> Self {
+        BenchParams {
+            routes: 1_000_000,
+            paths: 100_000,
+            churn: 0.05,
+            rounds: 5,
+            lookups: 100_000,
+            historydepth: 10,
+            seed: 1,
+        }
+    }
+}
+impl BenchParams {
+    /// parses `bench` arguments like `--routes 500000`
+    pub fn from_args(args: &[String]) -> Result<BenchParams, String> {
+        let mut ret = BenchParams::default();
+        let mut it = args.iter();
+        while let Some(a) = it.next() {
+            let v = it
+                .next()
+                .ok_or_else(|| format!("Missing value for {}\n{}", a, USAGE))?;
+            match a.as_str() {
+                "--routes" => ret.routes = parse_arg(a, v)?,
+                "--paths" => ret.paths = parse_arg(a, v)?,
+                "--churn" => ret.churn = parse_arg(a, v)?,
+                "--rounds" => ret.rounds = parse_arg(a, v)?,
+                "--lookups" => ret.lookups = parse_arg(a, v)?,
+                "--historydepth" => ret.historydepth = parse_arg(a, v)?,
+                "--seed" => ret.seed = parse_arg(a, v)?,
+                _ => return Err(USAGE.to_string()),
+            }
+        }
+        if ret.routes == 0 || ret.routes > 1 << 24 {
+            return Err("routes should be between 1 and 16777216".to_string());
+        }
+        if ret.paths == 0 || ret.historydepth == 0 {
+            return Err("paths and historydepth should be positive".to_string());
+        }
+        if !(0.0..=1.0).contains(&ret.churn) {
+            return Err("churn should be between 0 and 1".to_string());
+        }
+        Ok(ret)
+    }
+}
+
+fn parse_arg<F: std::str::FromStr>(name: &str, value: &str) -> Result<F, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {} for {}", value, name))
+}
+
+pub const USAGE: &str = "usage: bgpexplorer bench [--routes N] [--paths N] [--churn 0..1] \
+[--rounds N] [--lookups N] [--historydepth N] [--seed N]";
+
+/// xorshift64*, synthetic tables are repeatable for given seed
+struct Rng(u64);
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+pub struct SyntheticTable {
+    pub prefixes: Vec<BgpAddrV4>,
+    pub attrs: Vec<Arc<BgpAttrs>>,
+    /// attribute set index of each prefix
+    pub route_attrs: Vec<usize>,
+}
+impl SyntheticTable {
+    /// /24 prefixes spread over address space, AS pathes of 1-8 hops from peer AS 64500
+    pub fn generate(params: &BenchParams) -> SyntheticTable {
+        let mut rng = Rng::new(params.seed);
+        let prefixes = (0..params.routes as u32)
+            .map(|i| {
+                // odd multiplier keeps prefixes unique and out of order
+                let net = i.wrapping_mul(2_654_435_761) & 0x00ff_ffff;
+                BgpAddrV4::new(std::net::Ipv4Addr::from(net << 8), 24)
+            })
+            .collect();
+        let attrs = (0..params.paths)
+            .map(|_| {
+                let mut path = vec![64500];
+                for _ in 0..rng.below(8) {
+                    path.push(1 + rng.below(399_999) as u32);
+                }
+                let comms = (0..rng.below(4))
+                    .map(|_| BgpCommunity::from(64500, rng.below(1000) as u16))
+                    .collect();
+                let mut a = BgpAttrs::new();
+                a.nexthop = BgpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+                a.aspath = Arc::new(CompactASPath::from(path));
+                a.comms = Arc::new(CompactCommunityList::from_vec(comms));
+                Arc::new(a)
+            })
+            .collect();
+        let route_attrs = (0..params.routes)
+            .map(|_| rng.below(params.paths))
+            .collect();
+        SyntheticTable {
+            prefixes,
+            attrs,
+            route_attrs,
+        }
+    }
+    /// UPDATEs announcing listed routes, prefixes sharing attributes are packed together
+    fn updates(&self, routes: &[usize]) -> Vec<(Arc<BgpAttrs>, Vec<BgpAddrV4>)> {
+        let mut by_attrs: std::collections::BTreeMap<usize, Vec<BgpAddrV4>> = Default::default();
+        for r in routes {
+            by_attrs
+                .entry(self.route_attrs[*r])
+                .or_default()
+                .push(self.prefixes[*r].clone());
+        }
+        by_attrs
+            .into_iter()
+            .flat_map(|(a, pfxs)| {
+                pfxs.chunks(UPDATE_PREFIXES)
+                    .map(|c| (self.attrs[a].clone(), c.to_vec()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BenchReport {
+    pub routes: usize,
+    pub paths: usize,
+    pub load_seconds: f64,
+    pub load_routes_per_second: f64,
+    pub churn_updates: usize,
+    pub churn_withdraws: usize,
+    pub churn_seconds: f64,
+    pub churn_changes_per_second: f64,
+    pub lookup_avg_ns: u64,
+    pub lookup_p99_ns: u64,
+    /// resident set size growth over table load and churn
+    pub rss_bytes: Option<u64>,
+    pub rss_bytes_per_route: Option<u64>,
+    /// jemalloc allocated bytes growth
+    pub allocated_bytes: Option<u64>,
+    pub allocator: &'static str,
+}
+
+fn rate(count: usize, d: Duration) -> f64 {
+    count as f64 / d.as_secs_f64().max(1e-9)
+}
+
+pub fn run_bench(params: &BenchParams) -> BenchReport {
+    let table = SyntheticTable::generate(params);
+    let mut rng = Rng::new(params.seed.wrapping_add(1));
+    let mut rep = BenchReport {
+        routes: params.routes,
+        paths: params.paths,
+        allocator: crate::allocator::NAME,
+        ..Default::default()
+    };
+    let all: Vec<usize> = (0..params.routes).collect();
+    let updates = table.updates(&all);
+    let mem_before = crate::allocator::stats();
+    let mut safi: BgpRIBSafi<BgpAddrV4> =
+        BgpRIBSafi::new(params.historydepth, HistoryChangeMode::OnlyDiffer);
+    let started = Instant::now();
+    for (attrs, pfxs) in updates.into_iter() {
+        safi.handle_updates_afi(1, &pfxs, attrs);
+    }
+    let took = started.elapsed();
+    rep.load_seconds = took.as_secs_f64();
+    rep.load_routes_per_second = rate(params.routes, took);
+
+    let changed = ((params.routes as f64) * params.churn) as usize;
+    let mut churn_time = Duration::default();
+    for _ in 0..params.rounds {
+        let picked: Vec<usize> = (0..changed).map(|_| rng.below(params.routes)).collect();
+        let (withdrawn, reannounced) = picked.split_at(changed / 2);
+        let withdraws: Vec<BgpAddrV4> = withdrawn
+            .iter()
+            .map(|r| table.prefixes[*r].clone())
+            .collect();
+        let mut moved = table.updates(reannounced);
+        for upd in moved.iter_mut() {
+            upd.0 = table.attrs[rng.below(params.paths)].clone();
+        }
+        let started = Instant::now();
+        for w in withdraws.chunks(UPDATE_PREFIXES) {
+            safi.handle_withdraws_afi(1, w, false);
+        }
+        for (attrs, pfxs) in moved.into_iter() {
+            safi.handle_updates_afi(1, &pfxs, attrs);
+        }
+        churn_time += started.elapsed();
+        rep.churn_withdraws += withdrawn.len();
+        rep.churn_updates += reannounced.len();
+    }
+    rep.churn_seconds = churn_time.as_secs_f64();
+    rep.churn_changes_per_second = rate(rep.churn_updates + rep.churn_withdraws, churn_time);
+
+    let mut lookups: Vec<u64> = (0..params.lookups)
+        .map(|_| {
+            let pfx = &table.prefixes[rng.below(params.routes)];
+            let started = Instant::now();
+            let found = safi.items.get(pfx).and_then(|e| e.get_last_attr(1, 0));
+            let took = started.elapsed();
+            std::hint::black_box(found);
+            took.as_nanos() as u64
+        })
+        .collect();
+    lookups.sort_unstable();
+    if !lookups.is_empty() {
+        rep.lookup_avg_ns = lookups.iter().sum::<u64>() / lookups.len() as u64;
+        rep.lookup_p99_ns = lookups[(lookups.len() - 1) * 99 / 100];
+    }
+
+    let mem_after = crate::allocator::stats();
+    let growth = |a: Option<u64>, b: Option<u64>| Some(b?.saturating_sub(a?));
+    rep.rss_bytes = growth(mem_before.rss, mem_after.rss);
+    rep.rss_bytes_per_route = rep.rss_bytes.map(|b| b / params.routes as u64);
+    rep.allocated_bytes = growth(mem_before.allocated, mem_after.allocated);
+    std::hint::black_box(&safi);
+    rep
+}
+
+/// handles `bench` arguments, report is JSON
+pub fn run(args: &[String]) -> Result<String, String> {
+    let params = BenchParams::from_args(args)?;
+    let rep = run_bench(&params);
+    serde_json::to_string_pretty(&rep).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench() {
+        let args: Vec<String> = ["--routes", "2000", "--paths", "50", "--lookups", "100"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let params = BenchParams::from_args(&args).unwrap();
+        assert_eq!(params.routes, 2000);
+        assert_eq!(params.rounds, 5);
+        assert!(BenchParams::from_args(&["--churn".to_string(), "2".to_string()]).is_err());
+        assert!(BenchParams::from_args(&["--routes".to_string()]).is_err());
+        let table = SyntheticTable::generate(&params);
+        let uniq: std::collections::BTreeSet<_> = table.prefixes.iter().collect();
+        assert_eq!(uniq.len(), 2000);
+        let rep = run_bench(&params);
+        assert_eq!(rep.routes, 2000);
+        assert_eq!(rep.churn_withdraws + rep.churn_updates, 5 * 100);
+        assert!(rep.load_routes_per_second > 0.0);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::bgpattrs::*;
+use crate::bgprib::*;
+use crate::bgpsvc::BgpSessionId;
+use std::cmp::Ordering;
+use zettabgp::prelude::*;
+
+/// default local preference used when attribute is absent
+pub const BESTPATH_DEFAULT_LOCALPREF: u32 = 100;
+
+fn origin_rank(o: &BgpAttrOrigin) -> u8 {
+    match o {
+        BgpAttrOrigin::Igp => 0,
+        BgpAttrOrigin::Egp => 1,
+        _ => 2,
+    }
+}
+
+fn neighbor_as(a: &BgpAttrs) -> Option<u32> {
+    a.aspath.value.first().map(|x| x.value)
+}
+
+/// compares route attributes using standard BGP tie-breakers,
+/// Ordering::Less means that `a` is preferred over `b`
+pub fn compare_attrs(a: &BgpAttrs, b: &BgpAttrs) -> Ordering {
+    // highest local preference
+    let lpa = a.localpref.unwrap_or(BESTPATH_DEFAULT_LOCALPREF);
+    let lpb = b.localpref.unwrap_or(BESTPATH_DEFAULT_LOCALPREF);
+    lpb.cmp(&lpa)
+        // shortest AS path
+        .then_with(|| a.aspath.value.len().cmp(&b.aspath.value.len()))
+        // lowest origin
+        .then_with(|| origin_rank(&a.origin).cmp(&origin_rank(&b.origin)))
+        // lowest MED, only between routes from the same neighbor AS
+        .then_with(|| {
+            if neighbor_as(a) == neighbor_as(b) {
+                a.med.unwrap_or(0).cmp(&b.med.unwrap_or(0))
+            } else {
+                Ordering::Equal
+            }
+        })
+        // shortest cluster list
+        .then_with(|| {
+            let cla = a.clusterlist.as_ref().map(|c| c.value.len()).unwrap_or(0);
+            let clb = b.clusterlist.as_ref().map(|c| c.value.len()).unwrap_or(0);
+            cla.cmp(&clb)
+        })
+        // lowest originator id
+        .then_with(|| a.originator.cmp(&b.originator))
+}
+
+/// selects best active path for route among all sessions and pathes.
+/// Remaining ties are broken by lowest session id and path id.
+pub fn select_best(bse: &BgpSessionEntry) -> Option<(BgpSessionId, BgpPathId)> {
+    let mut best: Option<(BgpSessionId, BgpPathId, &BgpAttrs)> = None;
+    for (sid, pe) in bse.items.iter() {
+        for (pid, hist) in pe.items.iter() {
+            let last = match hist.items.iter().last() {
+                Some((_, l)) if l.active => l,
+                _ => continue,
+            };
+            let better = match best {
+                None => true,
+                Some((_, _, battrs)) => {
+                    compare_attrs(last.attrs.as_ref(), battrs) == Ordering::Less
+                }
+            };
+            if better {
+                best = Some((*sid, *pid, last.attrs.as_ref()));
+            }
+        }
+    }
+    best.map(|(s, p, _)| (s, p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::CompactASPath;
+    use std::sync::Arc;
+
+    fn mkattrs(localpref: Option<u32>, aspath: &[u32], med: Option<u32>) -> BgpAttrs {
+        let mut a = BgpAttrs::new();
+        a.localpref = localpref;
+        a.med = med;
+        a.aspath = Arc::new(CompactASPath::from(aspath.to_vec()));
+        a
+    }
+
+    #[test]
+    fn test_bestpath_compare() {
+        let a = mkattrs(Some(200), &[1, 2, 3], None);
+        let b = mkattrs(None, &[1], None);
+        assert_eq!(compare_attrs(&a, &b), Ordering::Less);
+        let a = mkattrs(None, &[1, 2], None);
+        let b = mkattrs(None, &[1], None);
+        assert_eq!(compare_attrs(&a, &b), Ordering::Greater);
+        let a = mkattrs(None, &[1, 2], Some(10));
+        let b = mkattrs(None, &[1, 3], Some(5));
+        assert_eq!(compare_attrs(&a, &b), Ordering::Greater);
+        // MED is not compared between different neighbor AS
+        let b = mkattrs(None, &[4, 3], Some(5));
+        assert_eq!(compare_attrs(&a, &b), Ordering::Equal);
+    }
+}
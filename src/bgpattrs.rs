@@ -1,5 +1,10 @@
 use crate::bgprib::BgpRIB;
+use crate::compact::{CompactASPath, CompactCommunityList};
+use crate::confed::BgpConfedPath;
+use crate::prefixsid::BgpPrefixSid;
+use crate::timestamp::Timestamp;
 use serde::ser::SerializeStruct;
+use std::collections::BTreeSet;
 use std::sync::Arc;
 use std::sync::Mutex;
 use zettabgp::prelude::*;
@@ -21,8 +26,8 @@ pub fn rib_get<'a>() -> std::sync::MutexGuard<'a, Option<BgpRIB>> {
 pub struct BgpAttrs {
     pub origin: BgpAttrOrigin,
     pub nexthop: BgpAddr,
-    pub aspath: Arc<BgpASpath>,
-    pub comms: Arc<BgpCommunityList>,
+    pub aspath: Arc<CompactASPath>,
+    pub comms: Arc<CompactCommunityList>,
     pub lcomms: Arc<BgpLargeCommunityList>,
     pub extcomms: Arc<BgpExtCommunityList>,
     pub med: Option<u32>,
@@ -32,6 +37,9 @@ pub struct BgpAttrs {
     pub originator: Option<std::net::IpAddr>,
     pub clusterlist: Option<Arc<BgpClusterList>>,
     pub pmsi_ta: Option<Arc<BgpPMSITunnel>>,
+    pub prefix_sid: Option<Arc<BgpPrefixSid>>,
+    /// AS_CONFED_SEQUENCE and AS_CONFED_SET segments, not part of aspath unless confed_in_aspath
+    pub confed: Option<Arc<BgpConfedPath>>,
 }
 enum BgpAttrsField {
     Origin,
@@ -47,8 +55,10 @@ enum BgpAttrsField {
     Originator,
     Clusterlist,
     PmsiTa,
+    PrefixSid,
+    Confed,
 }
-const BA_VARS: [&str; 13] = [
+const BA_VARS: [&str; 15] = [
     "Origin",
     "Nexthop",
     "Aspath",
@@ -62,6 +72,8 @@ const BA_VARS: [&str; 13] = [
     "Originator",
     "Clusterlist",
     "PmsiTa",
+    "PrefixSid",
+    "Confed",
 ];
 impl<'de> serde::de::Deserialize<'de> for BgpAttrsField {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -89,6 +101,8 @@ impl<'de> serde::de::Deserialize<'de> for BgpAttrsField {
                     "Originator" => Ok(BgpAttrsField::Originator),
                     "Clusterlist" => Ok(BgpAttrsField::Clusterlist),
                     "PmsiTa" => Ok(BgpAttrsField::PmsiTa),
+                    "PrefixSid" => Ok(BgpAttrsField::PrefixSid),
+                    "Confed" => Ok(BgpAttrsField::Confed),
                     _ => Err(serde::de::Error::unknown_field(value, &BA_VARS)),
                 }
             }
@@ -145,23 +159,28 @@ impl<'de> serde::de::Visitor<'de> for BgpAttrsVisitor {
         let pmsi_ta: Option<BgpPMSITunnel> = seq
             .next_element()?
             .ok_or_else(|| serde::de::Error::invalid_length(12, &self))?;
+        // absent in snapshots made before Prefix-SID support
+        let prefix_sid: Option<BgpPrefixSid> = seq.next_element()?.unwrap_or(None);
+        let confed: Option<BgpConfedPath> = seq.next_element()?.unwrap_or(None);
         let mut mrib = rib_get();
         match (*mrib).as_mut() {
             None => Ok(BgpAttrs::new()),
             Some(rib) => Ok(BgpAttrs {
                 origin,
                 nexthop,
-                aspath: rib.pathes.get(Arc::new(aspath)).unwrap(),
-                comms: rib.comms.get(Arc::new(comms)).unwrap(),
-                lcomms: rib.lcomms.get(Arc::new(lcomms)).unwrap(),
-                extcomms: rib.extcomms.get(Arc::new(extcomms)).unwrap(),
+                aspath: rib.pathes.intern(aspath),
+                comms: rib.comms.intern(comms),
+                lcomms: rib.lcomms.intern(lcomms),
+                extcomms: rib.extcomms.intern(extcomms),
                 med,
                 localpref,
                 atomicaggregate,
                 aggregatoras,
                 originator,
-                clusterlist: clusterlist.map(|x| rib.clusters.get(Arc::new(x)).unwrap()),
-                pmsi_ta: pmsi_ta.map(|x| rib.pmsi_ta_s.get(Arc::new(x)).unwrap()),
+                clusterlist: clusterlist.map(|x| rib.clusters.intern(x)),
+                pmsi_ta: pmsi_ta.map(|x| rib.pmsi_ta_s.intern(x)),
+                prefix_sid: prefix_sid.map(|x| rib.prefix_sids.intern(x)),
+                confed: confed.map(|x| rib.confeds.intern(x)),
             }),
         }
     }
@@ -183,6 +202,8 @@ impl<'de> serde::de::Visitor<'de> for BgpAttrsVisitor {
         let mut originator = None;
         let mut clusterlist: Option<Option<BgpClusterList>> = None;
         let mut pmsi_ta: Option<Option<BgpPMSITunnel>> = None;
+        let mut prefix_sid: Option<Option<BgpPrefixSid>> = None;
+        let mut confed: Option<Option<BgpConfedPath>> = None;
         while let Some(key) = map.next_key()? {
             match key {
                 BgpAttrsField::Origin => {
@@ -263,6 +284,18 @@ impl<'de> serde::de::Visitor<'de> for BgpAttrsVisitor {
                     }
                     pmsi_ta = Some(map.next_value()?);
                 }
+                BgpAttrsField::PrefixSid => {
+                    if prefix_sid.is_some() {
+                        return Err(serde::de::Error::duplicate_field(BA_VARS[13]));
+                    }
+                    prefix_sid = Some(map.next_value()?);
+                }
+                BgpAttrsField::Confed => {
+                    if confed.is_some() {
+                        return Err(serde::de::Error::duplicate_field(BA_VARS[14]));
+                    }
+                    confed = Some(map.next_value()?);
+                }
             }
         }
 
@@ -282,23 +315,27 @@ impl<'de> serde::de::Visitor<'de> for BgpAttrsVisitor {
         let clusterlist =
             clusterlist.ok_or_else(|| serde::de::Error::missing_field(BA_VARS[11]))?;
         let pmsi_ta = pmsi_ta.ok_or_else(|| serde::de::Error::missing_field(BA_VARS[12]))?;
+        let prefix_sid = prefix_sid.unwrap_or(None);
+        let confed = confed.unwrap_or(None);
         let mut mrib = rib_get();
         match (*mrib).as_mut() {
             None => Ok(BgpAttrs::new()),
             Some(rib) => Ok(BgpAttrs {
                 origin,
                 nexthop,
-                aspath: rib.pathes.get(Arc::new(aspath)).unwrap(),
-                comms: rib.comms.get(Arc::new(comms)).unwrap(),
-                lcomms: rib.lcomms.get(Arc::new(lcomms)).unwrap(),
-                extcomms: rib.extcomms.get(Arc::new(extcomms)).unwrap(),
+                aspath: rib.pathes.intern(aspath),
+                comms: rib.comms.intern(comms),
+                lcomms: rib.lcomms.intern(lcomms),
+                extcomms: rib.extcomms.intern(extcomms),
                 med,
                 localpref,
                 atomicaggregate,
                 aggregatoras,
                 originator,
-                clusterlist: clusterlist.map(|x| rib.clusters.get(Arc::new(x)).unwrap()),
-                pmsi_ta: pmsi_ta.map(|x| rib.pmsi_ta_s.get(Arc::new(x)).unwrap()),
+                clusterlist: clusterlist.map(|x| rib.clusters.intern(x)),
+                pmsi_ta: pmsi_ta.map(|x| rib.pmsi_ta_s.intern(x)),
+                prefix_sid: prefix_sid.map(|x| rib.prefix_sids.intern(x)),
+                confed: confed.map(|x| rib.confeds.intern(x)),
             }),
         }
     }
@@ -308,7 +345,7 @@ impl serde::Serialize for BgpAttrs {
     where
         S: serde::Serializer,
     {
-        let mut map = serializer.serialize_struct("BgpAttrs", 13)?;
+        let mut map = serializer.serialize_struct("BgpAttrs", 15)?;
         map.serialize_field(BA_VARS[0], &self.origin)?;
         map.serialize_field(BA_VARS[1], &self.nexthop)?;
         map.serialize_field(BA_VARS[2], self.aspath.as_ref())?;
@@ -322,6 +359,8 @@ impl serde::Serialize for BgpAttrs {
         map.serialize_field(BA_VARS[10], &self.originator)?;
         map.serialize_field(BA_VARS[11], &self.clusterlist.as_ref().map(|x| x.as_ref()))?;
         map.serialize_field(BA_VARS[12], &self.pmsi_ta.as_ref().map(|x| x.as_ref()))?;
+        map.serialize_field(BA_VARS[13], &self.prefix_sid.as_ref().map(|x| x.as_ref()))?;
+        map.serialize_field(BA_VARS[14], &self.confed.as_ref().map(|x| x.as_ref()))?;
         map.end()
     }
 }
@@ -343,8 +382,8 @@ impl BgpAttrs {
         BgpAttrs {
             origin: BgpAttrOrigin::Incomplete,
             nexthop: BgpAddr::None,
-            aspath: Arc::new(BgpASpath::new()),
-            comms: Arc::new(BgpCommunityList::new()),
+            aspath: Arc::new(CompactASPath::new()),
+            comms: Arc::new(CompactCommunityList::new()),
             lcomms: Arc::new(BgpLargeCommunityList::new()),
             extcomms: Arc::new(BgpExtCommunityList::new()),
             med: None,
@@ -354,21 +393,60 @@ impl BgpAttrs {
             originator: None,
             clusterlist: None,
             pmsi_ta: None,
+            prefix_sid: None,
+            confed: None,
         }
     }
+    /// attribute field names in serialization order, bit positions for diff_mask
+    pub fn field_names() -> &'static [&'static str] {
+        &BA_VARS
+    }
+    /// bit mask of fields differing from other attributes
+    pub fn diff_mask(&self, other: &BgpAttrs) -> u32 {
+        let differs = [
+            self.origin != other.origin,
+            self.nexthop != other.nexthop,
+            self.aspath != other.aspath,
+            self.comms != other.comms,
+            self.lcomms != other.lcomms,
+            self.extcomms != other.extcomms,
+            self.med != other.med,
+            self.localpref != other.localpref,
+            self.atomicaggregate != other.atomicaggregate,
+            self.aggregatoras != other.aggregatoras,
+            self.originator != other.originator,
+            self.clusterlist != other.clusterlist,
+            self.pmsi_ta != other.pmsi_ta,
+            self.prefix_sid != other.prefix_sid,
+            self.confed != other.confed,
+        ];
+        differs
+            .iter()
+            .enumerate()
+            .filter(|x| *x.1)
+            .fold(0, |m, (i, _)| m | (1 << i))
+    }
 }
 #[derive(Debug, Clone)]
 pub struct BgpAttrEntry {
     pub active: bool,
     pub attrs: Arc<BgpAttrs>,
-    pub labels: Option<MplsLabels>,
+    /// boxed, labeled families are rare and inline labels would double record size
+    pub labels: Option<Box<MplsLabels>>,
+    /// router timestamp from BMP per-peer header, history key is collector receive time
+    pub peer_time: Option<Timestamp>,
+    /// diff_mask against previous record of path, set on history insert
+    pub changed: u32,
 }
 impl BgpAttrEntry {
+    /// peer_time is taken from Timestamp::peer_clock()
     pub fn new(act: bool, atr: Arc<BgpAttrs>, lbl: Option<MplsLabels>) -> BgpAttrEntry {
         BgpAttrEntry {
             active: act,
             attrs: atr,
-            labels: lbl,
+            labels: lbl.map(Box::new),
+            peer_time: Timestamp::peer_clock(),
+            changed: 0,
         }
     }
 }
@@ -377,10 +455,194 @@ impl serde::Serialize for BgpAttrEntry {
     where
         S: serde::Serializer,
     {
-        let mut map = serializer.serialize_struct("BgpAttrEntry", 3)?;
+        let mut map = serializer.serialize_struct(
+            "BgpAttrEntry",
+            3 + self.peer_time.is_some() as usize + (self.changed != 0) as usize,
+        )?;
         map.serialize_field(BAE_VARS[0], &self.active)?;
         map.serialize_field(BAE_VARS[1], self.attrs.as_ref())?;
         map.serialize_field(BAE_VARS[2], &self.labels)?;
+        if let Some(ref t) = self.peer_time {
+            map.serialize_field(BAE_VARS[3], t)?;
+        }
+        if self.changed != 0 {
+            map.serialize_field(BAE_VARS[4], &self.changed)?;
+        }
+        map.end()
+    }
+}
+/// history record serialized with optional best path mark and fields selection
+pub struct BgpAttrEntryView<'a> {
+    pub entry: &'a BgpAttrEntry,
+    /// collector receive time, history key of entry
+    pub received: Timestamp,
+    pub best: bool,
+    /// route carries blackhole community
+    pub blackhole: bool,
+    /// age and churn counters of path, set on its last record
+    pub stability: Option<crate::stability::RouteStability>,
+    /// json_history_format=diff, oldest record shows its stored diff mask
+    pub diff: bool,
+    /// previous record attributes, entry is rendered as diff against them
+    pub previous: Option<&'a BgpAttrs>,
+    /// lowercase names of fields to serialize, all fields if None
+    pub fields: Option<&'a BTreeSet<String>>,
+}
+impl<'a> BgpAttrEntryView<'a> {
+    fn selected(&self, name: &str) -> bool {
+        match self.fields {
+            None => true,
+            Some(f) => f.contains(&name.to_lowercase()),
+        }
+    }
+}
+impl<'a> serde::Serialize for BgpAttrEntryView<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_struct("BgpAttrEntry", 4)?;
+        if self.selected(BAE_VARS[0]) {
+            map.serialize_field(BAE_VARS[0], &self.entry.active)?;
+        }
+        let mask = match self.previous {
+            Some(prev) => prev.diff_mask(self.entry.attrs.as_ref()),
+            None if self.diff => self.entry.changed,
+            None => 0,
+        };
+        let changed: Vec<&str> = BA_VARS
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| mask & (1 << i) != 0)
+            .map(|(_, n)| *n)
+            .collect();
+        if !changed.is_empty() {
+            map.serialize_field("Changed", &changed)?;
+        }
+        match (self.fields, self.previous) {
+            (_, Some(prev)) => {
+                let fields: BTreeSet<String> = changed
+                    .iter()
+                    .map(|n| n.to_lowercase())
+                    .filter(|n| self.selected(n))
+                    .collect();
+                map.serialize_field(
+                    BAE_VARS[1],
+                    &BgpAttrsView {
+                        attrs: self.entry.attrs.as_ref(),
+                        fields: &fields,
+                    },
+                )?;
+                map.serialize_field(
+                    "Previous",
+                    &BgpAttrsView {
+                        attrs: prev,
+                        fields: &fields,
+                    },
+                )?;
+            }
+            (None, None) => map.serialize_field(BAE_VARS[1], self.entry.attrs.as_ref())?,
+            (Some(fields), None) => map.serialize_field(
+                BAE_VARS[1],
+                &BgpAttrsView {
+                    attrs: self.entry.attrs.as_ref(),
+                    fields,
+                },
+            )?,
+        }
+        if self.selected(BAE_VARS[2]) {
+            map.serialize_field(BAE_VARS[2], &self.entry.labels)?;
+        }
+        if self.selected("Time") {
+            map.serialize_field("ReceivedUs", &self.received.timestamp_micros())?;
+            if let Some(t) = self.entry.peer_time {
+                map.serialize_field("PeerTimeUs", &t.timestamp_micros())?;
+            }
+        }
+        if self.best {
+            map.serialize_field("Best", &true)?;
+        }
+        if self.blackhole {
+            map.serialize_field("Blackhole", &true)?;
+        }
+        if let Some(st) = self.stability.filter(|_| self.selected("Stability")) {
+            if let Some(t) = st.first_seen {
+                map.serialize_field("FirstSeenUs", &t.timestamp_micros())?;
+            }
+            if let Some(t) = st.last_changed {
+                map.serialize_field("LastChangedUs", &t.timestamp_micros())?;
+            }
+            map.serialize_field("Changes", &st.changes)?;
+            map.serialize_field("ChangesToday", &st.changes_today())?;
+        }
+        if self.selected(BA_VARS[3]) {
+            let names = crate::communities::names(&self.entry.attrs.comms);
+            if !names.is_empty() {
+                map.serialize_field("CommunityNames", &names)?;
+            }
+        }
+        map.end()
+    }
+}
+/// route attributes with only selected fields
+pub struct BgpAttrsView<'a> {
+    pub attrs: &'a BgpAttrs,
+    pub fields: &'a BTreeSet<String>,
+}
+impl<'a> serde::Serialize for BgpAttrsView<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let sel = |i: usize| self.fields.contains(&BA_VARS[i].to_lowercase());
+        let a = self.attrs;
+        let mut map = serializer
+            .serialize_struct("BgpAttrs", (0..BA_VARS.len()).filter(|i| sel(*i)).count())?;
+        if sel(0) {
+            map.serialize_field(BA_VARS[0], &a.origin)?;
+        }
+        if sel(1) {
+            map.serialize_field(BA_VARS[1], &a.nexthop)?;
+        }
+        if sel(2) {
+            map.serialize_field(BA_VARS[2], a.aspath.as_ref())?;
+        }
+        if sel(3) {
+            map.serialize_field(BA_VARS[3], a.comms.as_ref())?;
+        }
+        if sel(4) {
+            map.serialize_field(BA_VARS[4], a.lcomms.as_ref())?;
+        }
+        if sel(5) {
+            map.serialize_field(BA_VARS[5], a.extcomms.as_ref())?;
+        }
+        if sel(6) {
+            map.serialize_field(BA_VARS[6], &a.med)?;
+        }
+        if sel(7) {
+            map.serialize_field(BA_VARS[7], &a.localpref)?;
+        }
+        if sel(8) {
+            map.serialize_field(BA_VARS[8], &a.atomicaggregate)?;
+        }
+        if sel(9) {
+            map.serialize_field(BA_VARS[9], &a.aggregatoras)?;
+        }
+        if sel(10) {
+            map.serialize_field(BA_VARS[10], &a.originator)?;
+        }
+        if sel(11) {
+            map.serialize_field(BA_VARS[11], &a.clusterlist.as_ref().map(|x| x.as_ref()))?;
+        }
+        if sel(12) {
+            map.serialize_field(BA_VARS[12], &a.pmsi_ta.as_ref().map(|x| x.as_ref()))?;
+        }
+        if sel(13) {
+            map.serialize_field(BA_VARS[13], &a.prefix_sid.as_ref().map(|x| x.as_ref()))?;
+        }
+        if sel(14) {
+            map.serialize_field(BA_VARS[14], &a.confed.as_ref().map(|x| x.as_ref()))?;
+        }
         map.end()
     }
 }
@@ -388,8 +650,10 @@ enum BgpAttrEntryField {
     Active,
     Attrs,
     Labels,
+    PeerTime,
+    ChangedMask,
 }
-const BAE_VARS: [&str; 3] = ["Active", "Attrs", "Labels"];
+const BAE_VARS: [&str; 5] = ["Active", "Attrs", "Labels", "PeerTime", "ChangedMask"];
 impl<'de> serde::de::Deserialize<'de> for BgpAttrEntryField {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -406,6 +670,8 @@ impl<'de> serde::de::Deserialize<'de> for BgpAttrEntryField {
                     "Active" => Ok(BgpAttrEntryField::Active),
                     "Attrs" => Ok(BgpAttrEntryField::Attrs),
                     "Labels" => Ok(BgpAttrEntryField::Labels),
+                    "PeerTime" => Ok(BgpAttrEntryField::PeerTime),
+                    "ChangedMask" => Ok(BgpAttrEntryField::ChangedMask),
                     _ => Err(serde::de::Error::unknown_field(value, &BAE_VARS)),
                 }
             }
@@ -432,13 +698,23 @@ impl<'de> serde::de::Visitor<'de> for BgpAttrEntryVisitor {
         let labels = seq
             .next_element()?
             .ok_or_else(|| serde::de::Error::invalid_length(2, &self))?;
+        let peer_time = seq.next_element()?.unwrap_or(None);
+        let changed = seq.next_element()?.unwrap_or(0);
         let mut mrib = rib_get();
         match (*mrib).as_mut() {
-            None => Ok(BgpAttrEntry::new(active, Arc::new(attrs), labels)),
+            None => Ok(BgpAttrEntry {
+                active,
+                attrs: Arc::new(attrs),
+                labels,
+                peer_time,
+                changed,
+            }),
             Some(rib) => Ok(BgpAttrEntry {
                 active,
-                attrs: rib.attrs.get(Arc::new(attrs)).unwrap(),
+                attrs: rib.attrs.intern(attrs),
                 labels,
+                peer_time,
+                changed,
             }),
         }
     }
@@ -450,6 +726,8 @@ impl<'de> serde::de::Visitor<'de> for BgpAttrEntryVisitor {
         let mut active = None;
         let mut attrs = None;
         let mut labels = None;
+        let mut peer_time = None;
+        let mut changed = None;
         while let Some(key) = map.next_key()? {
             match key {
                 BgpAttrEntryField::Active => {
@@ -470,19 +748,40 @@ impl<'de> serde::de::Visitor<'de> for BgpAttrEntryVisitor {
                     }
                     labels = Some(map.next_value()?);
                 }
+                BgpAttrEntryField::PeerTime => {
+                    if peer_time.is_some() {
+                        return Err(serde::de::Error::duplicate_field(BAE_VARS[3]));
+                    }
+                    peer_time = Some(map.next_value()?);
+                }
+                BgpAttrEntryField::ChangedMask => {
+                    if changed.is_some() {
+                        return Err(serde::de::Error::duplicate_field(BAE_VARS[4]));
+                    }
+                    changed = Some(map.next_value()?);
+                }
             }
         }
 
         let active = active.ok_or_else(|| serde::de::Error::missing_field(BA_VARS[0]))?;
         let attrs = attrs.ok_or_else(|| serde::de::Error::missing_field(BAE_VARS[1]))?;
         let labels = labels.ok_or_else(|| serde::de::Error::missing_field(BAE_VARS[2]))?;
+        let changed = changed.unwrap_or(0);
         let mut mrib = rib_get();
         match (*mrib).as_mut() {
-            None => Ok(BgpAttrEntry::new(active, Arc::new(attrs), labels)),
+            None => Ok(BgpAttrEntry {
+                active,
+                attrs: Arc::new(attrs),
+                labels,
+                peer_time,
+                changed,
+            }),
             Some(rib) => Ok(BgpAttrEntry {
                 active,
-                attrs: rib.attrs.get(Arc::new(attrs)).unwrap(),
+                attrs: rib.attrs.intern(attrs),
                 labels,
+                peer_time,
+                changed,
             }),
         }
     }
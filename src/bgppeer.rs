@@ -1,4 +1,6 @@
+//! BGP session driver: OPEN exchange, keepalives and UPDATE reception
 use crate::bgpsvc::*;
+use crate::liveness::LivenessEvent;
 use chrono::prelude::*;
 use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
@@ -9,9 +11,41 @@ use zettabgp::prelude::*;
 pub struct BgpPeer<'a, H: BgpUpdateHandler> {
     pub params: BgpSessionParams,
     peersock: tokio::net::TcpStream,
+    /// messages and bytes of this session
+    traffic: Option<Arc<crate::traffic::SessionTraffic>>,
     keepalive_sent: DateTime<Local>,
+    /// last message from peer, for hold timer
+    last_received: DateTime<Local>,
     sessionid: BgpSessionId,
     update_handler: &'a H,
+    /// header of message being received, kept only when tracing
+    trace_head: Option<[u8; 19]>,
+    /// advertise Extended Next Hop Encoding for IPv4 unicast (RFC 8950), passive session
+    /// mirrors peer
+    pub ext_nexthop: bool,
+    /// AS presented to peer instead of configured (active) or mirrored (passive) one
+    pub localas: Option<u32>,
+    /// do not prepend localas to routes received over eBGP
+    pub localas_no_prepend: bool,
+    /// soft clear requests, answered with ROUTE-REFRESH for negotiated AFI/SAFIs
+    pub refresh: Option<Arc<tokio::sync::Notify>>,
+    /// peer advertised route refresh capability, ROUTE-REFRESH may be sent (RFC 2918)
+    pub route_refresh: bool,
+    /// workarounds for peer platform, see vendor_quirks
+    pub quirks: crate::quirks::Quirks,
+}
+
+fn has_route_refresh(bom: &BgpOpenMessage) -> bool {
+    bom.caps
+        .iter()
+        .any(|c| matches!(c, BgpCapability::CapRR | BgpCapability::CapEnhancedRR))
+}
+
+async fn refresh_requested(n: Option<Arc<tokio::sync::Notify>>) {
+    match n {
+        Some(n) => n.notified().await,
+        None => std::future::pending().await,
+    }
 }
 
 impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
@@ -24,9 +58,18 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
         let mut ret = BgpPeer::<H> {
             params: pars,
             peersock: stream,
+            traffic: handler.start_traffic("bgp", peerip, None),
             keepalive_sent: Local::now(),
+            last_received: Local::now(),
             update_handler: handler,
             sessionid: 0,
+            trace_head: None,
+            ext_nexthop: false,
+            localas: None,
+            localas_no_prepend: false,
+            refresh: None,
+            route_refresh: false,
+            quirks: Default::default(),
         };
         ret.params.peer_mode = if peerip.is_ipv4() {
             BgpTransportMode::IPv4
@@ -41,7 +84,30 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
             Err(e) => Err(e.into()),
         }
     }
+    fn trace(&self, dir: &'static str, msg: &[u8]) {
+        let peer = match self.peersock.peer_addr() {
+            Ok(a) => a.ip(),
+            Err(_) => IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+        };
+        crate::trace::record(
+            "BGP",
+            dir,
+            peer,
+            crate::trace::bgp_msgtype(msg),
+            msg.len(),
+            msg,
+        );
+    }
     async fn write_socket(&mut self, buf: &[u8]) -> Result<(), BgpError> {
+        if crate::trace::enabled() {
+            self.trace("out", buf);
+        }
+        if let Some(t) = buf.get(18) {
+            self.update_handler.record_message(true, *t);
+            if let Some(tr) = self.traffic.as_ref() {
+                tr.record(true, crate::protostats::msgtype_name(*t), buf.len());
+            }
+        }
         match self.peersock.write_all(buf).await {
             Ok(_) => Ok(()),
             Err(e) => Err(e.into()),
@@ -50,7 +116,35 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
     async fn recv_message_head(&mut self) -> Result<(BgpMessageType, usize), BgpError> {
         let mut buf = [0u8; 19];
         self.read_socket(&mut buf).await?;
-        self.params.decode_message_head(&buf)
+        if crate::trace::enabled() {
+            self.trace_head = Some(buf);
+        }
+        self.update_handler.record_message(false, buf[18]);
+        let head = self.params.decode_message_head(&buf)?;
+        if let Some(tr) = self.traffic.as_ref() {
+            tr.record(false, crate::protostats::msgtype_name(buf[18]), 19 + head.1);
+        }
+        self.last_received = Local::now();
+        self.update_handler.record_liveness(LivenessEvent::Message);
+        if head.0 == BgpMessageType::Keepalive {
+            self.update_handler
+                .record_liveness(LivenessEvent::Keepalive);
+        }
+        Ok(head)
+    }
+    /// reads message body of len bytes into buf, fails on length exceeding buffer
+    async fn read_message_body(&mut self, buf: &mut [u8], len: usize) -> Result<(), BgpError> {
+        if len > buf.len() {
+            return Err(crate::wireparse::ParseError::Length(len + 19).into());
+        }
+        let buf = &mut buf[0..len];
+        self.read_socket(buf).await?;
+        if let Some(head) = self.trace_head.take() {
+            let mut msg = head.to_vec();
+            msg.extend_from_slice(buf);
+            self.trace("in", &msg);
+        }
+        Ok(())
     }
     fn get_message_body_ref(buf: &mut [u8]) -> Result<&mut [u8], BgpError> {
         if buf.len() < 19 {
@@ -58,6 +152,45 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
         }
         Ok(&mut buf[19..])
     }
+    /// OPEN body length after adding Extended Next Hop Encoding capability when enabled
+    fn add_ext_nexthop(&self, buf: &mut [u8], sz: usize) -> usize {
+        if !self.ext_nexthop {
+            return sz;
+        }
+        match crate::wireparse::add_ext_nexthop(
+            &mut buf[19..],
+            sz,
+            &crate::wireparse::EXT_NEXTHOP_CAP,
+        ) {
+            Some(sz) => sz,
+            None => {
+                warn!("Unable to add extended next hop capability to OPEN");
+                sz
+            }
+        }
+    }
+    /// logs address families enabled on one side only and passes them to handler
+    fn report_afis(&self, peer: IpAddr, local: &[BgpCapability], remote: &[BgpCapability]) {
+        let report = crate::afireport::AfiReport::new(peer, local, remote);
+        match report.hint() {
+            Some(hint) => warn!("Address families of {}: {}", peer, hint),
+            None => info!(
+                "Address families of {}: {}",
+                peer,
+                report.negotiated.join(",")
+            ),
+        }
+        self.update_handler.record_afi_report(report);
+    }
+    /// tells handler whether localas should be prepended to routes from remote AS
+    fn set_localas_prepend(&self, remote_as: u32) {
+        let prepend = match self.localas {
+            Some(asn) if !self.localas_no_prepend && asn != remote_as => Some(asn),
+            _ => None,
+        };
+        self.update_handler
+            .set_localas_prepend(self.sessionid, prepend);
+    }
     async fn send_message_buf(
         &mut self,
         buf: &mut [u8],
@@ -70,8 +203,7 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
         self.write_socket(&buf[0..blen]).await
     }
     pub async fn start_passive(&mut self) -> Result<(), BgpError> {
-        let mut bom = BgpOpenMessage::new();
-        let mut buf = [255u8; 255];
+        let mut buf = [255u8; 4096];
         let msg = match self.recv_message_head().await {
             Err(e) => return Err(e),
             Ok(msg) => msg,
@@ -79,17 +211,34 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
         if msg.0 != BgpMessageType::Open {
             return Err(BgpError::static_str("Invalid state to start_passive"));
         }
-        self.read_socket(&mut buf[0..msg.1]).await?;
-        bom.decode_from(&self.params, &buf[0..msg.1])?;
+        self.read_message_body(&mut buf, msg.1).await?;
+        let mut bom =
+            crate::wireparse::decode_open(&self.params, &self.quirks.open(&buf[0..msg.1]))?;
+        self.ext_nexthop = crate::wireparse::open_ext_nexthop(&buf[0..msg.1]).is_some();
+        self.route_refresh = has_route_refresh(&bom);
         let remsess = BgpPeerDesc::new(self.peersock.peer_addr().unwrap().ip(), bom.clone());
         bom.router_id = self.params.router_id;
         self.params.as_num = bom.as_num;
         self.params.hold_time = bom.hold_time;
+        let local = self.params.caps.clone();
         self.params.match_caps(&bom.caps);
-        let sz = match bom.encode_to(&self.params, BgpPeer::<H>::get_message_body_ref(&mut buf)?) {
-            Err(e) => return Err(e),
-            Ok(sz) => sz,
-        };
+        self.report_afis(remsess.addr, &local, &bom.caps);
+        let remote_as = crate::wireparse::open_as(&bom);
+        if let Some(asn) = self.localas {
+            self.params.as_num = asn;
+            bom.as_num = asn;
+            for c in bom.caps.iter_mut() {
+                if let BgpCapability::CapASN32(n) = c {
+                    *n = asn;
+                }
+            }
+        }
+        let mut sz =
+            match bom.encode_to(&self.params, BgpPeer::<H>::get_message_body_ref(&mut buf)?) {
+                Err(e) => return Err(e),
+                Ok(sz) => sz,
+            };
+        sz = self.add_ext_nexthop(&mut buf, sz);
         self.send_message_buf(&mut buf, BgpMessageType::Open, sz)
             .await?;
         let mysess = BgpPeerDesc::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), bom.clone());
@@ -97,20 +246,25 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
             .update_handler
             .register_session(Arc::new(BgpSessionDesc::new(mysess, remsess)))
             .await;
+        if let Some(tr) = self.traffic.as_ref() {
+            tr.set_session(self.sessionid);
+        }
+        self.set_localas_prepend(remote_as);
         Ok(())
     }
     pub async fn start_active(&mut self) -> Result<(), BgpError> {
         info!("start_active");
         loop {
             let bom = self.params.open_message();
-            let mut buf = [255u8; 255];
-            let sz =
+            let mut buf = [255u8; 4096];
+            let mut sz =
                 match bom.encode_to(&self.params, BgpPeer::<H>::get_message_body_ref(&mut buf)?) {
                     Err(e) => {
                         return Err(e);
                     }
                     Ok(sz) => sz,
                 };
+            sz = self.add_ext_nexthop(&mut buf, sz);
             let mysess = BgpPeerDesc::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), bom.clone());
             self.send_message_buf(&mut buf, BgpMessageType::Open, sz)
                 .await?;
@@ -122,27 +276,36 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
             };
             match msg.0 {
                 BgpMessageType::Open => {
-                    self.read_socket(&mut buf[0..msg.1]).await?;
-                    let mut bomrcv = self.params.open_message();
-                    bomrcv.decode_from(&self.params, &buf[0..msg.1])?;
+                    self.read_message_body(&mut buf, msg.1).await?;
+                    let bomrcv = crate::wireparse::decode_open(
+                        &self.params,
+                        &self.quirks.open(&buf[0..msg.1]),
+                    )?;
                     let remsess =
                         BgpPeerDesc::new(self.peersock.peer_addr().unwrap().ip(), bomrcv.clone());
                     self.params.hold_time = bomrcv.hold_time;
+                    self.route_refresh = has_route_refresh(&bomrcv);
                     self.params.match_caps(&bomrcv.caps);
+                    self.report_afis(remsess.addr, &bom.caps, &bomrcv.caps);
                     self.sessionid = self
                         .update_handler
                         .register_session(Arc::new(BgpSessionDesc::new(mysess, remsess)))
                         .await;
+                    if let Some(tr) = self.traffic.as_ref() {
+                        tr.set_session(self.sessionid);
+                    }
+                    self.set_localas_prepend(crate::wireparse::open_as(&bomrcv));
                     return Ok(());
                 }
                 BgpMessageType::Notification => {
-                    self.read_socket(&mut buf[0..msg.1]).await?;
+                    self.read_message_body(&mut buf, msg.1).await?;
                     let mut bnrcv = BgpNotificationMessage::new();
                     bnrcv.decode_from(&self.params, &buf[0..msg.1])?;
                     warn!("Notification: {}", bnrcv.error_text());
                     if bnrcv.error_code == 2 && bnrcv.error_subcode == 7 {
                         //unsupported capability
-                        let (cap, _) = BgpCapability::from_buffer(&buf[2..msg.1])?;
+                        let (cap, _) =
+                            BgpCapability::from_buffer(buf.get(2..msg.1).unwrap_or(&[]))?;
                         if let Ok(cap) = cap {
                             warn!("Unsupported capability: {:?} in {:?}", cap, self.params);
                             self.params.remove_capability(&cap);
@@ -176,10 +339,68 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
             Err(e) => Err(e),
         }
     }
+    /// ROUTE-REFRESH for each negotiated AFI/SAFI, IPv4 unicast when none was
+    pub async fn send_route_refresh(&mut self) -> Result<(), BgpError> {
+        if !self.route_refresh {
+            return Err(BgpError::static_str(
+                "peer did not advertise route refresh capability",
+            ));
+        }
+        let mut afs: Vec<(u16, u8)> = self
+            .params
+            .caps
+            .iter()
+            .filter_map(crate::sessionctl::afi_safi)
+            .collect();
+        if afs.is_empty() {
+            afs.push((1, 1));
+        }
+        for (afi, safi) in afs.into_iter() {
+            self.write_socket(&crate::sessionctl::route_refresh(afi, safi))
+                .await?;
+        }
+        Ok(())
+    }
+    /// completes OPEN exchange like OpenConfirm state: sends KEEPALIVE and waits for
+    /// peer's one, NOTIFICATION is returned as error
+    pub async fn confirm(&mut self) -> Result<(), BgpError> {
+        let mut buf = [255u8; 4096];
+        self.send_keepalive().await?;
+        loop {
+            let msg = self.recv_message_head().await?;
+            self.read_message_body(&mut buf, msg.1).await?;
+            match msg.0 {
+                BgpMessageType::Keepalive => return Ok(()),
+                BgpMessageType::Notification => {
+                    let mut bnrcv = BgpNotificationMessage::new();
+                    bnrcv.decode_from(&self.params, &buf[0..msg.1])?;
+                    return Err(BgpError::from_string(format!(
+                        "Notification received: {}",
+                        bnrcv.error_text()
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+    pub async fn send_cease(&mut self, subcode: u8) -> Result<(), BgpError> {
+        self.write_socket(&crate::sessionctl::cease(subcode)).await
+    }
     pub async fn lifecycle(&mut self, cancel: tokio_util::sync::CancellationToken) {
         let mut buf = [255u8; 4096];
         let keep_interval = chrono::Duration::seconds((self.params.hold_time / 3) as i64);
+        let hold = chrono::Duration::seconds(self.params.hold_time as i64);
         loop {
+            // zero hold time means no keepalives are expected
+            if self.params.hold_time > 0 && Local::now() - self.last_received > hold {
+                warn!(
+                    "Hold timer expired: nothing received for {} seconds",
+                    self.params.hold_time
+                );
+                self.update_handler
+                    .record_liveness(LivenessEvent::HoldExpired);
+                break;
+            }
             let mut tosleep = Local::now() - self.keepalive_sent;
             if tosleep >= keep_interval {
                 match self.send_keepalive().await {
@@ -191,10 +412,17 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
                 tosleep = Local::now() - self.keepalive_sent;
             }
             tosleep = keep_interval - tosleep;
+            if self.params.hold_time > 0 {
+                let hold_left = hold - (Local::now() - self.last_received);
+                if hold_left < tosleep {
+                    tosleep = hold_left;
+                }
+            }
             let tosleepstd = match tosleep.to_std() {
                 Ok(s) => s,
                 Err(_) => std::time::Duration::from_secs(1),
             };
+            let refresh = self.refresh.clone();
             let msg = select! {
                 _ = cancel.cancelled() => {
                     // The token was cancelled
@@ -203,6 +431,13 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
                 _ = tokio::time::sleep(tosleepstd) => {
                     (BgpMessageType::Keepalive,0)
                 }
+                _ = refresh_requested(refresh) => {
+                    info!("Soft clear, sending route refresh");
+                    if let Err(e) = self.send_route_refresh().await {
+                        warn!("Route refresh sending error: {:?}", e);
+                    }
+                    continue;
+                }
                 msgin = self.recv_message_head() => {
                     match msgin {
                         Err(e) => {
@@ -213,8 +448,10 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
                     }
                 }
             };
-            if let Err(e) = self.read_socket(&mut buf[0..msg.1]).await {
+            if let Err(e) = self.read_message_body(&mut buf, msg.1).await {
+                // stream can not be resynchronized
                 warn!("recv_message: {:?}", e);
+                break;
             };
             match msg.0 {
                 BgpMessageType::Open => {
@@ -244,10 +481,37 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
                     break;
                 }
                 BgpMessageType::Update => {
-                    let mut msgupdate = BgpUpdateMessage::new();
-                    if let Err(e) = msgupdate.decode_from(&self.params, &buf[0..msg.1]) {
-                        error!("BGP update decode error: {:?}", e);
-                        continue;
+                    self.update_handler.record_update_body(
+                        &buf[0..msg.1],
+                        crate::wireparse::has_addpath(&self.params),
+                    );
+                    let msgupdate = match crate::wireparse::decode_update(
+                        &self.params,
+                        &self.quirks.update(&buf[0..msg.1]),
+                    ) {
+                        Ok(u) => u,
+                        Err(e) => {
+                            error!("BGP update decode error: {}", e);
+                            if !self.update_handler.strict_parsing() {
+                                let addpath = crate::wireparse::has_addpath(&self.params);
+                                for w in
+                                    crate::wireparse::treat_as_withdraw(&buf[0..msg.1], addpath)
+                                {
+                                    let mut upd = BgpUpdateMessage::new();
+                                    upd.withdraws = w;
+                                    self.update_handler.handle_update(self.sessionid, upd).await;
+                                }
+                            }
+                            continue;
+                        }
+                    };
+                    if self.update_handler.keep_raw() {
+                        let mut raw = vec![255u8; 16];
+                        raw.extend_from_slice(&((msg.1 + 19) as u16).to_be_bytes());
+                        raw.push(2);
+                        raw.extend_from_slice(&buf[0..msg.1]);
+                        self.update_handler
+                            .record_raw(self.sessionid, &msgupdate, raw);
                     }
                     self.update_handler
                         .handle_update(self.sessionid, msgupdate)
@@ -265,3 +529,11 @@ impl<'a, H: BgpUpdateHandler> BgpPeer<'a, H> {
         }
     }
 }
+
+impl<'a, H: BgpUpdateHandler> Drop for BgpPeer<'a, H> {
+    fn drop(&mut self) {
+        if let Some(tr) = self.traffic.take() {
+            self.update_handler.end_traffic(&tr);
+        }
+    }
+}
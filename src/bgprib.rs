@@ -1,7 +1,13 @@
+//! routing information base: per-AFI tables of routes with per-session change history
 use crate::bgpattrs::*;
 use crate::bgpsvc::BgpSessionId;
 use crate::config::*;
+use crate::generation::RibGenerations;
+use crate::inlinemap::InlineMap;
+use crate::peerstats::*;
+use crate::prefixsid::*;
 use crate::ribfilter::RouteFilter;
+use crate::stability::RouteStability;
 use crate::timestamp::Timestamp;
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
@@ -9,11 +15,12 @@ use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::io::{BufReader, BufWriter};
 use std::iter::Iterator;
 use std::ops::Deref;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use tokio::sync::broadcast;
 use zettabgp::prelude::*;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum BgpRibKind {
     IpV4u,
     IpV4m,
@@ -30,6 +37,7 @@ pub enum BgpRibKind {
     Fs4u,
     IpV4mdt,
     Ipv6mdt,
+    IpV6m,
 }
 impl BgpRibKind {
     pub fn from_bgp_addrs(addrs: &BgpAddrs) -> Option<BgpRibKind> {
@@ -49,8 +57,8 @@ impl BgpRibKind {
             BgpAddrs::IPV4MDTP(_) => Some(BgpRibKind::IpV4mdt),
             BgpAddrs::IPV6U(_) => Some(BgpRibKind::IpV6u),
             BgpAddrs::IPV6UP(_) => Some(BgpRibKind::IpV6u),
-            BgpAddrs::IPV6M(_) => None,
-            BgpAddrs::IPV6MP(_) => None,
+            BgpAddrs::IPV6M(_) => Some(BgpRibKind::IpV6m),
+            BgpAddrs::IPV6MP(_) => Some(BgpRibKind::IpV6m),
             BgpAddrs::IPV6LU(_) => Some(BgpRibKind::IpV6LU),
             BgpAddrs::IPV6LUP(_) => Some(BgpRibKind::IpV6LU),
             BgpAddrs::VPNV6U(_) => Some(BgpRibKind::VpnV6u),
@@ -59,7 +67,7 @@ impl BgpRibKind {
             BgpAddrs::VPNV6MP(_) => Some(BgpRibKind::VpnV6m),
             BgpAddrs::IPV6MDT(_) => Some(BgpRibKind::Ipv6mdt),
             BgpAddrs::IPV6MDTP(_) => Some(BgpRibKind::Ipv6mdt),
-            BgpAddrs::L2VPLS(_) => Some(BgpRibKind::Ipv6mdt),
+            BgpAddrs::L2VPLS(_) => Some(BgpRibKind::L2vpls),
             BgpAddrs::MVPN(_) => Some(BgpRibKind::MVpn),
             BgpAddrs::EVPN(_) => Some(BgpRibKind::EVpn),
             BgpAddrs::FS4U(_) => Some(BgpRibKind::Fs4u),
@@ -92,6 +100,7 @@ impl std::str::FromStr for BgpRibKind {
             "fs4u" => Ok(BgpRibKind::Fs4u),
             "ipv4mdt" => Ok(BgpRibKind::IpV4mdt),
             "ipv6mdt" => Ok(BgpRibKind::Ipv6mdt),
+            "ipv6m" => Ok(BgpRibKind::IpV6m),
             _ => Err(BgpError::static_str("Invalid RIB kind")),
         }
     }
@@ -114,6 +123,7 @@ impl std::fmt::Display for BgpRibKind {
             BgpRibKind::Fs4u => f.write_str("fs4u"),
             BgpRibKind::IpV4mdt => f.write_str("ipv4mdt"),
             BgpRibKind::Ipv6mdt => f.write_str("ipv6mdt"),
+            BgpRibKind::IpV6m => f.write_str("ipv6m"),
         }
     }
 }
@@ -129,6 +139,12 @@ impl<T: std::hash::Hash + Eq + Ord> RibItem<T> {
         Arc::strong_count(&self.item) < 2
     }
 }
+/// lets store be searched by value, so duplicates are found without allocating
+impl<T: std::hash::Hash + Eq + Ord> std::borrow::Borrow<T> for RibItem<T> {
+    fn borrow(&self) -> &T {
+        &self.item
+    }
+}
 impl<T: std::hash::Hash + Eq + Ord + serde::Serialize> serde::Serialize for RibItem<T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -154,6 +170,9 @@ impl<T: std::hash::Hash + Eq + PartialOrd + Ord> RibItemStore<T> {
     pub fn len(&self) -> usize {
         self.items.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
     pub fn clear(&mut self) {
         self.items.clear();
     }
@@ -172,25 +191,46 @@ impl<T: std::hash::Hash + Eq + PartialOrd + Ord> RibItemStore<T> {
         }
     }
     pub fn get(&mut self, item: Arc<T>) -> Result<Arc<T>, Box<dyn std::error::Error>> {
-        match self.items.get(&RibItem::fromrc(&item)) {
+        match self.items.get(&*item) {
             Some(n) => Ok(n.item.clone()),
             None => {
                 self.items.insert(RibItem::fromrc(&item));
-                match self.items.get(&RibItem::fromrc(&item)) {
-                    Some(n) => Ok(n.item.clone()),
-                    None => Err(Box::new(BgpError::from_string(format!(
-                        "Unable to register {}",
-                        std::any::type_name::<T>()
-                    )))),
-                }
+                Ok(item)
             }
         }
     }
+    /// shared copy of item, heap allocation is made only for items not stored yet
+    pub fn intern(&mut self, item: T) -> Arc<T> {
+        if let Some(n) = self.items.get(&item) {
+            return n.item.clone();
+        }
+        let rc = Arc::new(item);
+        self.items.insert(RibItem::fromrc(&rc));
+        rc
+    }
+    /// same as intern, item is cloned only when not stored yet
+    pub fn intern_ref(&mut self, item: &T) -> Arc<T>
+    where
+        T: Clone,
+    {
+        match self.items.get(item) {
+            Some(n) => n.item.clone(),
+            None => self.intern(item.clone()),
+        }
+    }
 }
 pub trait BgpRIBKey: std::hash::Hash + std::cmp::Eq + std::cmp::Ord + Clone {
     fn getlabels(&self) -> Option<MplsLabels> {
         None
     }
+    /// length of plain IP prefix, counted in prefix length statistics
+    fn prefixlen(&self) -> Option<u8> {
+        None
+    }
+    /// special-use block of plain IP prefix
+    fn addrclass(&self) -> Option<crate::addrclass::AddrClass> {
+        None
+    }
 }
 impl<T: BgpItem<T> + std::hash::Hash + std::cmp::Eq + std::cmp::Ord + Clone> BgpRIBKey
     for Labeled<T>
@@ -209,8 +249,22 @@ impl BgpRIBKey for BgpAddrL2 {
         Some(self.labels.clone())
     }
 }
-impl BgpRIBKey for BgpAddrV4 {}
-impl BgpRIBKey for BgpAddrV6 {}
+impl BgpRIBKey for BgpAddrV4 {
+    fn prefixlen(&self) -> Option<u8> {
+        Some(self.prefixlen)
+    }
+    fn addrclass(&self) -> Option<crate::addrclass::AddrClass> {
+        crate::addrclass::classify_v4(&self.addr, self.prefixlen)
+    }
+}
+impl BgpRIBKey for BgpAddrV6 {
+    fn prefixlen(&self) -> Option<u8> {
+        Some(self.prefixlen)
+    }
+    fn addrclass(&self) -> Option<crate::addrclass::AddrClass> {
+        crate::addrclass::classify_v6(&self.addr, self.prefixlen)
+    }
+}
 impl BgpRIBKey for BgpMVPN {}
 impl BgpRIBKey for BgpEVPN {}
 impl BgpRIBKey for BgpFlowSpec<BgpAddrV4> {}
@@ -236,6 +290,15 @@ impl<K: Eq + Ord + Clone, T: BgpRIBKey> BgpRIBIndex<K, T> {
             self.idx.get_mut(k).unwrap().insert(t.clone());
         }
     }
+    /// drops t from k, and k once no keys left
+    pub fn remove(&mut self, k: &K, t: &T) {
+        if let Some(set) = self.idx.get_mut(k) {
+            set.remove(t);
+            if set.is_empty() {
+                self.idx.remove(k);
+            }
+        }
+    }
     pub fn clear(&mut self) {
         self.idx.clear();
     }
@@ -267,6 +330,11 @@ impl<'a, K, V> std::iter::Iterator for ClonableIterator<'a, K, V> {
 pub struct EmptyIter<'a, K: BgpRIBKey, T> {
     phantom: std::marker::PhantomData<(&'a K, &'a T)>,
 }
+impl<'a, K: BgpRIBKey, T> Default for EmptyIter<'a, K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<'a, K: BgpRIBKey, T> EmptyIter<'a, K, T> {
     pub fn new() -> Self {
         Self {
@@ -306,10 +374,11 @@ impl<'a, 'b, K: BgpRIBKey, T> std::iter::Iterator for MapFilter<'a, 'b, K, T> {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(transparent)]
+#[derive(Debug)]
 pub struct BgpAttrHistory {
-    pub items: BTreeMap<Timestamp, BgpAttrEntry>,
+    pub items: InlineMap<Timestamp, BgpAttrEntry>,
+    /// allocated with second record, single record path derives it from history
+    stability: Option<Box<RouteStability>>,
 }
 impl Default for BgpAttrHistory {
     fn default() -> Self {
@@ -319,7 +388,8 @@ impl Default for BgpAttrHistory {
 impl BgpAttrHistory {
     pub fn new() -> BgpAttrHistory {
         BgpAttrHistory {
-            items: BTreeMap::new(),
+            items: InlineMap::new(),
+            stability: None,
         }
     }
     fn shrink_hist(&mut self, maxlen: usize) {
@@ -341,14 +411,118 @@ impl BgpAttrHistory {
     pub fn get_last_attr(&self) -> Option<BgpAttrEntry> {
         self.items.iter().last().map(|v| (*v.1).clone())
     }
-    pub fn insert(&mut self, when: Timestamp, entry: BgpAttrEntry) {
+    pub fn insert(&mut self, when: Timestamp, mut entry: BgpAttrEntry) {
+        if let Some(prev) = self.items.values().last() {
+            entry.changed = prev.attrs.diff_mask(entry.attrs.as_ref());
+        }
+        if self.stability.is_none() && !self.items.is_empty() {
+            self.stability = Some(Box::new(RouteStability::from_history(self.items.iter())));
+        }
+        if let Some(st) = self.stability.as_mut() {
+            st.record(when, &entry);
+        }
         self.items.insert(when, entry);
     }
+    /// first seen, last changed and changes count of path
+    pub fn stability(&self) -> RouteStability {
+        match self.stability.as_deref() {
+            Some(st) => *st,
+            None => RouteStability::from_history(self.items.iter()),
+        }
+    }
+}
+/// snapshot form is map of records by time, counters of multi-record path are added
+/// under "Stability" key, so snapshots without them still load
+impl Serialize for BgpAttrHistory {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+        let mut map =
+            serializer.serialize_map(Some(self.items.len() + self.stability.is_some() as usize))?;
+        for (k, v) in self.items.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        if let Some(st) = self.stability.as_deref() {
+            map.serialize_entry("Stability", st)?;
+        }
+        map.end()
+    }
+}
+enum BgpAttrHistoryKey {
+    Time(Timestamp),
+    Stability,
+}
+struct BgpAttrHistoryKeyVisitor(bool);
+impl<'de> serde::de::Visitor<'de> for BgpAttrHistoryKeyVisitor {
+    type Value = BgpAttrHistoryKey;
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("timestamp or Stability")
+    }
+    fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(BgpAttrHistoryKey::Time(Timestamp::from_serialized(
+            v, self.0,
+        )))
+    }
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        match std::convert::TryFrom::try_from(v) {
+            Ok(v) => self.visit_i64(v),
+            Err(_) => Err(E::invalid_value(serde::de::Unexpected::Unsigned(v), &self)),
+        }
+    }
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        match v {
+            "Stability" => Ok(BgpAttrHistoryKey::Stability),
+            _ => Err(E::unknown_field(v, &["Stability"])),
+        }
+    }
+}
+impl<'de> Deserialize<'de> for BgpAttrHistoryKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let human = deserializer.is_human_readable();
+        deserializer.deserialize_any(BgpAttrHistoryKeyVisitor(human))
+    }
+}
+struct BgpAttrHistoryVisitor;
+impl<'de> serde::de::Visitor<'de> for BgpAttrHistoryVisitor {
+    type Value = BgpAttrHistory;
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("map of history records")
+    }
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut ret = BgpAttrHistory::new();
+        while let Some(key) = map.next_key()? {
+            match key {
+                BgpAttrHistoryKey::Time(t) => {
+                    ret.items.insert(t, map.next_value()?);
+                }
+                BgpAttrHistoryKey::Stability => {
+                    ret.stability = Some(Box::new(map.next_value()?));
+                }
+            }
+        }
+        if let (Some(st), Some(last)) = (ret.stability.as_mut(), ret.items.values().last()) {
+            st.resync(last);
+        }
+        Ok(ret)
+    }
+}
+impl<'de> Deserialize<'de> for BgpAttrHistory {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(BgpAttrHistoryVisitor)
+    }
 }
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct BgpPathEntry {
-    pub items: BTreeMap<BgpPathId, BgpAttrHistory>,
+    pub items: InlineMap<BgpPathId, BgpAttrHistory>,
 }
 impl Default for BgpPathEntry {
     fn default() -> Self {
@@ -358,7 +532,7 @@ impl Default for BgpPathEntry {
 impl BgpPathEntry {
     pub fn new() -> BgpPathEntry {
         BgpPathEntry {
-            items: BTreeMap::new(),
+            items: InlineMap::new(),
         }
     }
     fn shrink_hist(&mut self, maxlen: usize) {
@@ -384,7 +558,7 @@ impl BgpPathEntry {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct BgpSessionEntry {
-    pub items: BTreeMap<BgpSessionId, BgpPathEntry>,
+    pub items: InlineMap<BgpSessionId, BgpPathEntry>,
 }
 impl Default for BgpSessionEntry {
     fn default() -> Self {
@@ -394,12 +568,20 @@ impl Default for BgpSessionEntry {
 impl BgpSessionEntry {
     pub fn new() -> BgpSessionEntry {
         BgpSessionEntry {
-            items: BTreeMap::new(),
+            items: InlineMap::new(),
         }
     }
     fn shrink_hist(&mut self, maxlen: usize) {
         self.items.iter_mut().for_each(|x| x.1.shrink_hist(maxlen))
     }
+    /// any path of any session is active
+    pub fn has_active(&self) -> bool {
+        self.items.values().any(|pe| {
+            pe.items
+                .values()
+                .any(|h| h.items.values().last().map(|r| r.active).unwrap_or(false))
+        })
+    }
     pub fn get_last_attr(&self, sess: BgpSessionId, path: BgpPathId) -> Option<BgpAttrEntry> {
         match self.items.get(&sess) {
             None => None,
@@ -422,34 +604,106 @@ impl BgpSessionEntry {
         };
         pe.insert(path, when, atr)
     }
+    /// time and entry of last history record of path
+    pub fn get_last(
+        &self,
+        sess: BgpSessionId,
+        path: BgpPathId,
+    ) -> Option<(Timestamp, &BgpAttrEntry)> {
+        self.items
+            .get(&sess)?
+            .items
+            .get(&path)?
+            .items
+            .iter()
+            .last()
+            .map(|(t, e)| (*t, e))
+    }
+    /// announcement repeats last record of path and came within window
+    pub fn is_repeated(
+        &self,
+        sess: BgpSessionId,
+        path: BgpPathId,
+        rec: &BgpAttrEntry,
+        now: Timestamp,
+        window: chrono::Duration,
+    ) -> bool {
+        match self.get_last(sess, path) {
+            Some((t, l)) => {
+                l.active && l.attrs == rec.attrs && l.labels == rec.labels && *now - *t < window
+            }
+            None => false,
+        }
+    }
 }
 pub struct BgpRIBSafi<T: BgpRIBKey> {
     pub log_size: usize,
     pub history_mode: HistoryChangeMode,
+    /// window of EveryUpdate mode deduplication, None - disabled
+    pub dedup_window: Option<chrono::Duration>,
+    /// announcements not recorded by deduplication
+    pub dedup_suppressed: u64,
+    /// positions of routes of last handled message which did not change RIB (deduplicated
+    /// or repeated announcement, withdraw of unknown route), they get no table version
+    pub skipped: Vec<usize>,
     pub items: BTreeMap<T, BgpSessionEntry>,
     pub idx_aspath: BgpRIBIndex<BgpAS, T>,
     pub idx_community: BgpRIBIndex<BgpCommunity, T>,
     pub idx_extcommunity: BgpRIBIndex<BgpExtCommunity, T>,
+    pub idx_sort: crate::sortindex::SortIndex<T>,
+    pub prefixlen: crate::prefixlen::PrefixLenStats,
+    pub addrclass: crate::addrclass::AddrClassStats,
+    pub nexthops: crate::nexthop::NexthopInventory,
+    pub retention: crate::retention::RetentionRules,
+    /// keys changed since last snapshot, written by differential snapshots
+    pub dirty: std::sync::Mutex<BTreeSet<T>>,
 }
 impl<T: BgpRIBKey> BgpRIBSafi<T> {
     pub fn new(logsize: usize, historymode: HistoryChangeMode) -> BgpRIBSafi<T> {
         BgpRIBSafi {
             log_size: logsize,
             history_mode: historymode,
+            dedup_window: None,
+            dedup_suppressed: 0,
+            skipped: Vec::new(),
             items: BTreeMap::new(),
             idx_aspath: BgpRIBIndex::new(),
             idx_community: BgpRIBIndex::new(),
             idx_extcommunity: BgpRIBIndex::new(),
+            idx_sort: Default::default(),
+            prefixlen: Default::default(),
+            addrclass: Default::default(),
+            nexthops: Default::default(),
+            retention: Default::default(),
+            dirty: Default::default(),
         }
     }
     pub fn from_config(cfg: &SvcConfig) -> BgpRIBSafi<T> {
         BgpRIBSafi {
             log_size: cfg.historydepth,
             history_mode: cfg.historymode.clone(),
+            dedup_window: cfg
+                .dedup_window
+                .and_then(|d| chrono::Duration::from_std(d).ok()),
+            dedup_suppressed: 0,
+            skipped: Vec::new(),
             items: BTreeMap::new(),
             idx_aspath: BgpRIBIndex::new(),
             idx_community: BgpRIBIndex::new(),
             idx_extcommunity: BgpRIBIndex::new(),
+            idx_sort: Default::default(),
+            prefixlen: Default::default(),
+            addrclass: Default::default(),
+            nexthops: Default::default(),
+            retention: crate::retention::RetentionRules::new(&cfg.retention),
+            dirty: Default::default(),
+        }
+    }
+    /// deduplication window in effect, only EveryUpdate mode records identical announcements
+    fn dedup(&self) -> Option<chrono::Duration> {
+        match self.history_mode {
+            HistoryChangeMode::EveryUpdate => self.dedup_window,
+            HistoryChangeMode::OnlyDiffer => None,
         }
     }
     pub fn clear(&mut self) {
@@ -457,12 +711,86 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
         self.idx_aspath.clear();
         self.idx_community.clear();
         self.idx_extcommunity.clear();
+        self.idx_sort.clear();
+        self.prefixlen.clear();
+        self.addrclass.clear();
+        self.nexthops.clear();
+    }
+    /// applies to records inserted from now on, existing histories are trimmed on next change
+    pub fn set_history(&mut self, depth: usize, mode: HistoryChangeMode) {
+        self.log_size = depth;
+        self.history_mode = mode;
     }
     pub fn len(&self) -> usize {
         self.items.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    /// prefix length and whether prefix has active route, None for RIBs without prefix
+    /// length statistics
+    fn active_prefix(&self, k: &T) -> Option<(u8, bool)> {
+        let len = k.prefixlen()?;
+        Some((
+            len,
+            self.items.get(k).map(|e| e.has_active()).unwrap_or(false),
+        ))
+    }
+    /// moves route in sort indexes from values it had before change
+    fn reindex_sort(&mut self, k: &T, was: &[(crate::sortindex::SortKey, Option<u32>)]) {
+        let now = crate::sortindex::entry_values(self.items.get(k));
+        self.idx_sort.update(k, was, &now);
+    }
+    fn track_prefixlen(&mut self, k: &T, was: Option<(u8, bool)>) {
+        if let Some((len, was)) = was {
+            let now = self.items.get(k).map(|e| e.has_active()).unwrap_or(false);
+            self.prefixlen.track(len, was, now);
+            if let Some(c) = k.addrclass() {
+                self.addrclass.track(c, was, now);
+            }
+        }
+    }
+    fn touch(&mut self, k: &T) {
+        self.dirty.get_mut().unwrap().insert(k.clone());
+    }
+    /// changed entries for differential snapshot
+    fn write_dirty<W: std::io::Write>(
+        &self,
+        file: W,
+    ) -> Result<(), ciborium::ser::Error<std::io::Error>>
+    where
+        T: Serialize,
+    {
+        let dirty = self.dirty.lock().unwrap();
+        let m: BTreeMap<&T, &BgpSessionEntry> = dirty
+            .iter()
+            .filter_map(|k| self.items.get_key_value(k))
+            .collect();
+        ciborium::ser::into_writer(&m, file)
+    }
     pub fn assign(&mut self, items: BTreeMap<T, BgpSessionEntry>) {
         self.items = items;
+        self.prefixlen.clear();
+        self.addrclass.clear();
+        self.nexthops.clear();
+        for (k, sess) in self.items.iter() {
+            if let Some(len) = k.prefixlen() {
+                self.prefixlen.track(len, false, sess.has_active());
+            }
+            if let Some(c) = k.addrclass() {
+                self.addrclass.track(c, false, sess.has_active());
+            }
+            for nh in sess
+                .items
+                .values()
+                .flat_map(|pe| pe.items.values())
+                .filter_map(|hist| hist.items.values().last())
+                .filter(|last| last.active)
+                .map(|last| &last.attrs.nexthop)
+            {
+                self.nexthops.track(None, Some(nh));
+            }
+        }
         for (i, sess) in self.items.iter() {
             for (_, sess_ent) in sess.items.iter() {
                 for (_, p_ent) in sess_ent.items.iter() {
@@ -484,8 +812,28 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
                     }
                 }
             }
+            let sorted = crate::sortindex::entry_values(Some(sess));
+            self.idx_sort.update(i, &[], &sorted);
         }
     }
+    /// active routes count and AS path length sum for each session
+    pub fn active_stats(&self) -> BTreeMap<BgpSessionId, BgpRibDelta> {
+        let mut ret: BTreeMap<BgpSessionId, BgpRibDelta> = BTreeMap::new();
+        for (_, sess) in self.items.iter() {
+            for (sessid, sess_ent) in sess.items.iter() {
+                for (_, p_ent) in sess_ent.items.iter() {
+                    if let Some((_, last)) = p_ent.items.iter().last() {
+                        if last.active {
+                            ret.entry(*sessid)
+                                .or_default()
+                                .track(None, Some(last.attrs.aspath.value.len()));
+                        }
+                    }
+                }
+            }
+        }
+        ret
+    }
     /// Build filters chain
     pub fn get_iter<'b>(
         &'b self,
@@ -514,24 +862,51 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
                 return clone_iter!(EmptyIter::new());
             };
         }
+        for (key, ord, n) in filter.find_bound_items() {
+            ret = clone_iter!(ret.filter(move |(_, e)| key.entry_matches(e, ord, n)));
+        }
         ret
     }
-    pub fn handle_withdraws_afi(&mut self, session: BgpSessionId, v: &[T]) {
+    /// burst - withdraws are part of withdraw burst, only routes which were active get
+    /// history records
+    pub fn handle_withdraws_afi(
+        &mut self,
+        session: BgpSessionId,
+        v: &[T],
+        burst: bool,
+    ) -> BgpRibDelta {
+        let mut delta = BgpRibDelta::default();
+        self.skipped.clear();
         if v.is_empty() {
-            return;
+            return delta;
         }
         let now = Timestamp::now();
-        for i in v.iter() {
+        for (n, i) in v.iter().enumerate() {
+            self.touch(i);
+            let was = self.active_prefix(i);
+            let sorted = crate::sortindex::entry_values(self.items.get(i));
             //TODO: indexes cleanup
             match self.items.get_mut(i) {
-                None => {}
+                None => self.skipped.push(n),
                 Some(hist) => {
-                    hist.shrink_hist(self.log_size - 1);
+                    let depth = match hist.get_last_attr(session, 0) {
+                        Some(lrec) => self.retention.depth(&lrec.attrs, self.log_size),
+                        None => self.log_size,
+                    };
+                    hist.shrink_hist(depth - 1);
                     let lrec = match hist.get_last_attr(session, 0) {
-                        None => continue,
+                        None => {
+                            self.skipped.push(n);
+                            continue;
+                        }
                         Some(x) => x,
                     };
+                    delta.track(Some((lrec.active, lrec.attrs.aspath.value.len())), None);
+                    self.nexthops.track(Some(&lrec), None);
                     match self.history_mode {
+                        HistoryChangeMode::EveryUpdate if burst && !lrec.active => {
+                            self.skipped.push(n);
+                        }
                         HistoryChangeMode::EveryUpdate => {
                             hist.insert(
                                 session,
@@ -548,19 +923,37 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
                                     now,
                                     BgpAttrEntry::new(false, lrec.attrs.clone(), i.getlabels()),
                                 );
+                            } else {
+                                self.skipped.push(n);
                             }
                         }
                     };
                 }
             }
+            self.reindex_sort(i, &sorted);
+            self.track_prefixlen(i, was);
         }
+        delta
     }
-    pub fn handle_updates_afi(&mut self, session: BgpSessionId, v: &[T], rattr: Arc<BgpAttrs>) {
+    pub fn handle_updates_afi(
+        &mut self,
+        session: BgpSessionId,
+        v: &[T],
+        rattr: Arc<BgpAttrs>,
+    ) -> BgpRibDelta {
+        let mut delta = BgpRibDelta::default();
+        self.skipped.clear();
         if v.is_empty() {
-            return;
+            return delta;
         }
+        let pathlen = rattr.aspath.value.len();
+        let depth = self.retention.depth(&rattr, self.log_size);
+        let dedup = self.dedup();
         let now = Timestamp::now();
-        for i in v.iter() {
+        for (n, i) in v.iter().enumerate() {
+            self.touch(i);
+            let was = self.active_prefix(i);
+            let sorted = crate::sortindex::entry_values(self.items.get(i));
             for aspathitem in rattr.aspath.value.iter() {
                 self.idx_aspath.set(aspathitem, i);
             }
@@ -576,12 +969,28 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
             let histrec = BgpAttrEntry::new(true, rattr.clone(), i.getlabels());
             match self.items.get_mut(i) {
                 None => {
+                    delta.track(None, Some(pathlen));
+                    self.nexthops.track(None, Some(&rattr.nexthop));
                     let mut hist = BgpSessionEntry::new();
                     hist.insert(session, 0, now, histrec);
                     self.items.insert(i.clone(), hist);
                 }
                 Some(hist) => {
-                    hist.shrink_hist(self.log_size - 1);
+                    let prev = hist.get_last_attr(session, 0);
+                    delta.track(
+                        prev.as_ref()
+                            .map(|x| (x.active, x.attrs.aspath.value.len())),
+                        Some(pathlen),
+                    );
+                    self.nexthops.track(prev.as_ref(), Some(&rattr.nexthop));
+                    if let Some(w) = dedup {
+                        if hist.is_repeated(session, 0, &histrec, now, w) {
+                            self.dedup_suppressed += 1;
+                            self.skipped.push(n);
+                            continue;
+                        }
+                    }
+                    hist.shrink_hist(depth - 1);
                     match self.history_mode {
                         HistoryChangeMode::EveryUpdate => {
                             hist.insert(session, 0, now, histrec);
@@ -594,6 +1003,8 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
                                 Some(lrec) => {
                                     if !lrec.active || lrec.attrs != histrec.attrs {
                                         hist.insert(session, 0, now, histrec);
+                                    } else {
+                                        self.skipped.push(n);
                                     }
                                 }
                             };
@@ -601,24 +1012,49 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
                     };
                 }
             };
+            self.reindex_sort(i, &sorted);
+            self.track_prefixlen(i, was);
         }
+        delta
     }
-    pub fn handle_withdraws_afi_pathid(&mut self, session: BgpSessionId, v: &[WithPathId<T>]) {
+    pub fn handle_withdraws_afi_pathid(
+        &mut self,
+        session: BgpSessionId,
+        v: &[WithPathId<T>],
+        burst: bool,
+    ) -> BgpRibDelta {
+        let mut delta = BgpRibDelta::default();
+        self.skipped.clear();
         if v.is_empty() {
-            return;
+            return delta;
         }
         let now = Timestamp::now();
-        for i in v.iter() {
+        for (n, i) in v.iter().enumerate() {
+            self.touch(&i.nlri);
+            let was = self.active_prefix(&i.nlri);
+            let sorted = crate::sortindex::entry_values(self.items.get(&i.nlri));
             //TODO: indexes cleanup
             match self.items.get_mut(&i.nlri) {
-                None => {}
+                None => self.skipped.push(n),
                 Some(hist) => {
-                    hist.shrink_hist(self.log_size - 1);
+                    let depth = match hist.get_last_attr(session, i.pathid) {
+                        Some(lrec) => self.retention.depth(&lrec.attrs, self.log_size),
+                        None => self.log_size,
+                    };
+                    hist.shrink_hist(depth - 1);
                     let lrec = match hist.get_last_attr(session, i.pathid) {
-                        None => continue,
+                        None => {
+                            self.skipped.push(n);
+                            continue;
+                        }
                         Some(x) => x,
                     };
+                    delta.track(Some((lrec.active, lrec.attrs.aspath.value.len())), None);
+                    self.nexthops.track(Some(&lrec), None);
                     match self.history_mode {
+                        HistoryChangeMode::EveryUpdate if burst && !lrec.active => {
+                            self.skipped.push(n);
+                        }
                         HistoryChangeMode::EveryUpdate => {
                             hist.insert(
                                 session,
@@ -639,23 +1075,36 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
                                         i.nlri.getlabels(),
                                     ),
                                 );
+                            } else {
+                                self.skipped.push(n);
                             }
                         }
                     };
                 }
             }
+            self.reindex_sort(&i.nlri, &sorted);
+            self.track_prefixlen(&i.nlri, was);
         }
+        delta
     }
     pub fn handle_updates_afi_pathid(
         &mut self,
         session: BgpSessionId,
         v: &[WithPathId<T>],
         rattr: Arc<BgpAttrs>,
-    ) {
+    ) -> BgpRibDelta {
+        let mut delta = BgpRibDelta::default();
+        self.skipped.clear();
         if v.is_empty() {
-            return;
+            return delta;
         }
-        for i in v.iter() {
+        let pathlen = rattr.aspath.value.len();
+        let depth = self.retention.depth(&rattr, self.log_size);
+        let dedup = self.dedup();
+        for (n, i) in v.iter().enumerate() {
+            self.touch(&i.nlri);
+            let was = self.active_prefix(&i.nlri);
+            let sorted = crate::sortindex::entry_values(self.items.get(&i.nlri));
             for aspathitem in rattr.aspath.value.iter() {
                 self.idx_aspath.set(aspathitem, &i.nlri);
             }
@@ -672,12 +1121,28 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
             let now = Timestamp::now();
             match self.items.get_mut(&i.nlri) {
                 None => {
+                    delta.track(None, Some(pathlen));
+                    self.nexthops.track(None, Some(&rattr.nexthop));
                     let mut hist = BgpSessionEntry::new();
                     hist.insert(session, i.pathid, now, histrec);
                     self.items.insert(i.nlri.clone(), hist);
                 }
                 Some(hist) => {
-                    hist.shrink_hist(self.log_size - 1);
+                    let prev = hist.get_last_attr(session, i.pathid);
+                    delta.track(
+                        prev.as_ref()
+                            .map(|x| (x.active, x.attrs.aspath.value.len())),
+                        Some(pathlen),
+                    );
+                    self.nexthops.track(prev.as_ref(), Some(&rattr.nexthop));
+                    if let Some(w) = dedup {
+                        if hist.is_repeated(session, i.pathid, &histrec, now, w) {
+                            self.dedup_suppressed += 1;
+                            self.skipped.push(n);
+                            continue;
+                        }
+                    }
+                    hist.shrink_hist(depth - 1);
                     match self.history_mode {
                         HistoryChangeMode::EveryUpdate => {
                             hist.insert(session, i.pathid, now, histrec);
@@ -690,6 +1155,8 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
                                 Some(lrec) => {
                                     if !lrec.active || lrec.attrs != histrec.attrs {
                                         hist.insert(session, i.pathid, now, histrec);
+                                    } else {
+                                        self.skipped.push(n);
                                     }
                                 }
                             };
@@ -697,21 +1164,92 @@ impl<T: BgpRIBKey> BgpRIBSafi<T> {
                     };
                 }
             };
+            self.reindex_sort(&i.nlri, &sorted);
+            self.track_prefixlen(&i.nlri, was);
         }
+        delta
     }
 }
 #[derive(Clone)]
 pub enum BgpEvent {
     Update(BgpSessionId, Arc<BgpAttrs>, Arc<BgpAddrs>),
     Withdraw(BgpSessionId, Arc<BgpAddrs>),
+    /// withdraws of session burst coalesced by withdraw_burst_threshold
+    WithdrawBatch(BgpSessionId, Arc<crate::withdrawburst::WithdrawBatch>),
+}
+/// one RIB map of differential snapshot
+fn read_diff_part<T: Ord + serde::de::DeserializeOwned, R: std::io::Read>(
+    rd: R,
+) -> Result<BTreeMap<T, BgpSessionEntry>, ciborium::de::Error<std::io::Error>> {
+    ciborium::de::from_reader(rd)
 }
+/// RIBs in snapshot stream, see BgpRIB::write_snapshot_part
+pub const SNAPSHOT_PARTS: usize = 16;
+/// FNV-1a hash of bytes passed through, identifies full snapshot content in its diffs
+pub struct ContentHash<T> {
+    inner: T,
+    hash: u64,
+}
+impl<T> ContentHash<T> {
+    pub fn new(inner: T) -> ContentHash<T> {
+        ContentHash {
+            inner,
+            hash: 0xcbf29ce484222325,
+        }
+    }
+    fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.hash = (self.hash ^ *b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    /// hash of content so far, never 0 which means no base snapshot
+    pub fn finish(&self) -> u64 {
+        self.hash.max(1)
+    }
+}
+impl<T: std::io::Read> std::io::Read for ContentHash<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+}
+impl<T: std::io::Write> std::io::Write for ContentHash<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+/// snapshot part added in later version, empty when snapshot ends before it
+fn read_optional_part<T: Ord + serde::de::DeserializeOwned, R: std::io::Read>(
+    mut rd: R,
+) -> Result<BTreeMap<T, BgpSessionEntry>, ciborium::de::Error<std::io::Error>> {
+    use std::io::Read;
+    let mut first = [0u8; 1];
+    loop {
+        match rd.read(&mut first) {
+            Ok(0) => return Ok(BTreeMap::new()),
+            Ok(_) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(ciborium::de::Error::Io(e)),
+        }
+    }
+    ciborium::de::from_reader((&first[..]).chain(rd))
+}
+/// all address families with shared attribute stores
 pub struct BgpRIB {
-    pub pathes: RibItemStore<BgpASpath>,
-    pub comms: RibItemStore<BgpCommunityList>,
+    pub pathes: RibItemStore<crate::compact::CompactASPath>,
+    pub comms: RibItemStore<crate::compact::CompactCommunityList>,
     pub lcomms: RibItemStore<BgpLargeCommunityList>,
     pub extcomms: RibItemStore<BgpExtCommunityList>,
     pub clusters: RibItemStore<BgpClusterList>,
     pub pmsi_ta_s: RibItemStore<BgpPMSITunnel>,
+    pub prefix_sids: RibItemStore<BgpPrefixSid>,
+    pub confeds: RibItemStore<crate::confed::BgpConfedPath>,
     pub attrs: RibItemStore<BgpAttrs>,
     pub ipv4u: BgpRIBSafi<BgpAddrV4>,
     pub ipv4m: BgpRIBSafi<BgpAddrV4>,
@@ -719,6 +1257,7 @@ pub struct BgpRIB {
     pub vpnv4u: BgpRIBSafi<Labeled<WithRd<BgpAddrV4>>>,
     pub vpnv4m: BgpRIBSafi<Labeled<WithRd<BgpAddrV4>>>,
     pub ipv6u: BgpRIBSafi<BgpAddrV6>,
+    pub ipv6m: BgpRIBSafi<BgpAddrV6>,
     pub ipv6lu: BgpRIBSafi<Labeled<BgpAddrV6>>,
     pub vpnv6u: BgpRIBSafi<Labeled<WithRd<BgpAddrV6>>>,
     pub vpnv6m: BgpRIBSafi<Labeled<WithRd<BgpAddrV6>>>,
@@ -730,7 +1269,11 @@ pub struct BgpRIB {
     pub ipv6mdt: BgpRIBSafi<WithRd<BgpMdtV6>>,
     pub cnt_updates: u64,
     pub cnt_withdraws: u64,
+    pub peer_stats: BTreeMap<BgpSessionId, BgpPeerStats>,
+    pub generations: RibGenerations,
     pub events: broadcast::Sender<BgpEvent>,
+    pub withdraw_bursts: crate::withdrawburst::WithdrawCoalescer,
+    pub versions: crate::tableversion::TableVersion,
     cnt_purge: u64,
     purge_after_withdraws: u64,
     purge_every: chrono::Duration,
@@ -738,6 +1281,19 @@ pub struct BgpRIB {
     snapshot_file: Option<String>,
     snapshot_every: Option<chrono::Duration>,
     snapshot_saved: Timestamp,
+    snapshot_diff_every: Option<chrono::Duration>,
+    snapshot_diff_saved: Timestamp,
+    /// differential snapshots written since full one
+    snapshot_seq: std::sync::atomic::AtomicU64,
+    /// size of full snapshot, diffs are applied only to the same base
+    snapshot_base: std::sync::atomic::AtomicU64,
+    /// routes were removed, next snapshot has to be full
+    snapshot_full_needed: std::sync::atomic::AtomicBool,
+    inbound: Option<crate::inbound::InboundFilter>,
+    /// routes dropped by inbound lists and script
+    pub filtered: crate::shadow::ShadowTable,
+    /// keep confederation members in front of aspath
+    confed_in_aspath: bool,
 }
 unsafe impl Sync for BgpRIB {}
 unsafe impl Send for BgpRIB {}
@@ -753,6 +1309,8 @@ impl BgpRIB {
             extcomms: RibItemStore::new(),
             clusters: RibItemStore::new(),
             pmsi_ta_s: RibItemStore::new(),
+            prefix_sids: RibItemStore::new(),
+            confeds: RibItemStore::new(),
             attrs: RibItemStore::new(),
             ipv4u: BgpRIBSafi::from_config(cfg),
             ipv4m: BgpRIBSafi::from_config(cfg),
@@ -760,6 +1318,7 @@ impl BgpRIB {
             vpnv4u: BgpRIBSafi::from_config(cfg),
             vpnv4m: BgpRIBSafi::from_config(cfg),
             ipv6u: BgpRIBSafi::from_config(cfg),
+            ipv6m: BgpRIBSafi::from_config(cfg),
             ipv6lu: BgpRIBSafi::from_config(cfg),
             vpnv6u: BgpRIBSafi::from_config(cfg),
             vpnv6m: BgpRIBSafi::from_config(cfg),
@@ -771,7 +1330,13 @@ impl BgpRIB {
             ipv6mdt: BgpRIBSafi::from_config(cfg),
             cnt_updates: 0,
             cnt_withdraws: 0,
+            peer_stats: BTreeMap::new(),
+            generations: RibGenerations::new(),
             events: tx,
+            withdraw_bursts: crate::withdrawburst::WithdrawCoalescer::new(
+                cfg.withdraw_burst_threshold,
+            ),
+            versions: crate::tableversion::TableVersion::new(cfg.version_journal),
             cnt_purge: 0,
             purge_after_withdraws: cfg.purge_after_withdraws,
             purge_every: cfg.purge_every,
@@ -779,11 +1344,21 @@ impl BgpRIB {
             snapshot_file: cfg.snapshot_file.clone(),
             snapshot_every: cfg.snapshot_every,
             snapshot_saved: now,
+            snapshot_diff_every: cfg.snapshot_diff_every,
+            snapshot_diff_saved: now,
+            snapshot_seq: Default::default(),
+            snapshot_base: Default::default(),
+            snapshot_full_needed: Default::default(),
+            inbound: cfg.inbound.clone(),
+            filtered: crate::shadow::ShadowTable::new(cfg.filtered_shadow_size),
+            confed_in_aspath: cfg.confed_in_aspath,
         }
     }
     pub fn purge(&mut self) {
         self.attrs.purge();
         self.clusters.purge();
+        self.prefix_sids.purge();
+        self.confeds.purge();
         self.extcomms.purge();
         self.lcomms.purge();
         self.comms.purge();
@@ -809,25 +1384,140 @@ impl BgpRIB {
         }
         (chrono::Local::now() - *(self.purged.deref())) > self.purge_every
     }
-    fn write_snapshot<W>(&self, mut file: W) -> Result<(), ciborium::ser::Error<std::io::Error>>
+    pub fn needs_snapshot_diff(&self) -> bool {
+        match (
+            self.snapshot_file.as_ref(),
+            self.snapshot_diff_every.as_ref(),
+        ) {
+            (Some(_), Some(de)) => {
+                (chrono::Local::now() - *(self.snapshot_diff_saved.deref())) > *de
+            }
+            _ => false,
+        }
+    }
+    pub fn write_snapshot<W>(&self, mut file: W) -> Result<(), ciborium::ser::Error<std::io::Error>>
     where
         W: std::io::Write,
     {
-        ciborium::ser::into_writer(&self.ipv4u.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.ipv4m.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.ipv4lu.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.vpnv4u.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.vpnv4m.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.ipv6u.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.ipv6lu.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.vpnv6u.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.vpnv6m.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.l2vpls.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.mvpn.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.evpn.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.fs4u.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.ipv4mdt.items, file.by_ref())?;
-        ciborium::ser::into_writer(&self.ipv6mdt.items, file.by_ref())?;
+        for part in 0..SNAPSHOT_PARTS {
+            self.write_snapshot_part(part, file.by_ref())?;
+        }
+        Ok(())
+    }
+    /// writes one RIB of snapshot, parts 0..SNAPSHOT_PARTS in order make write_snapshot stream,
+    /// so callers may release RIB lock between parts
+    pub fn write_snapshot_part<W>(
+        &self,
+        part: usize,
+        file: W,
+    ) -> Result<(), ciborium::ser::Error<std::io::Error>>
+    where
+        W: std::io::Write,
+    {
+        match part {
+            0 => ciborium::ser::into_writer(&self.ipv4u.items, file),
+            1 => ciborium::ser::into_writer(&self.ipv4m.items, file),
+            2 => ciborium::ser::into_writer(&self.ipv4lu.items, file),
+            3 => ciborium::ser::into_writer(&self.vpnv4u.items, file),
+            4 => ciborium::ser::into_writer(&self.vpnv4m.items, file),
+            5 => ciborium::ser::into_writer(&self.ipv6u.items, file),
+            6 => ciborium::ser::into_writer(&self.ipv6lu.items, file),
+            7 => ciborium::ser::into_writer(&self.vpnv6u.items, file),
+            8 => ciborium::ser::into_writer(&self.vpnv6m.items, file),
+            9 => ciborium::ser::into_writer(&self.l2vpls.items, file),
+            10 => ciborium::ser::into_writer(&self.mvpn.items, file),
+            11 => ciborium::ser::into_writer(&self.evpn.items, file),
+            12 => ciborium::ser::into_writer(&self.fs4u.items, file),
+            13 => ciborium::ser::into_writer(&self.ipv4mdt.items, file),
+            14 => ciborium::ser::into_writer(&self.ipv6mdt.items, file),
+            15 => ciborium::ser::into_writer(&self.ipv6m.items, file),
+            _ => Ok(()),
+        }
+    }
+    /// sequence number, base snapshot content hash and changed entries of each RIB in write_snapshot order
+    pub fn write_snapshot_diff<W>(
+        &self,
+        seq: u64,
+        base: u64,
+        mut file: W,
+    ) -> Result<(), ciborium::ser::Error<std::io::Error>>
+    where
+        W: std::io::Write,
+    {
+        ciborium::ser::into_writer(&(seq, base), file.by_ref())?;
+        self.ipv4u.write_dirty(file.by_ref())?;
+        self.ipv4m.write_dirty(file.by_ref())?;
+        self.ipv4lu.write_dirty(file.by_ref())?;
+        self.vpnv4u.write_dirty(file.by_ref())?;
+        self.vpnv4m.write_dirty(file.by_ref())?;
+        self.ipv6u.write_dirty(file.by_ref())?;
+        self.ipv6lu.write_dirty(file.by_ref())?;
+        self.vpnv6u.write_dirty(file.by_ref())?;
+        self.vpnv6m.write_dirty(file.by_ref())?;
+        self.l2vpls.write_dirty(file.by_ref())?;
+        self.mvpn.write_dirty(file.by_ref())?;
+        self.evpn.write_dirty(file.by_ref())?;
+        self.fs4u.write_dirty(file.by_ref())?;
+        self.ipv4mdt.write_dirty(file.by_ref())?;
+        self.ipv6mdt.write_dirty(file.by_ref())?;
+        self.ipv6m.write_dirty(file.by_ref())?;
+        Ok(())
+    }
+    fn snapshot_diff_name(base: &str, seq: u64) -> String {
+        format!("{}.diff.{:06}", base, seq)
+    }
+    /// next snapshot is written in full, for changes not tracked as dirty keys
+    pub fn invalidate_snapshot_diffs(&self) {
+        self.snapshot_full_needed.store(true, Ordering::Relaxed);
+    }
+    fn clear_dirty(&self) {
+        self.ipv4u.dirty.lock().unwrap().clear();
+        self.ipv4m.dirty.lock().unwrap().clear();
+        self.ipv4lu.dirty.lock().unwrap().clear();
+        self.vpnv4u.dirty.lock().unwrap().clear();
+        self.vpnv4m.dirty.lock().unwrap().clear();
+        self.ipv6u.dirty.lock().unwrap().clear();
+        self.ipv6lu.dirty.lock().unwrap().clear();
+        self.vpnv6u.dirty.lock().unwrap().clear();
+        self.vpnv6m.dirty.lock().unwrap().clear();
+        self.l2vpls.dirty.lock().unwrap().clear();
+        self.mvpn.dirty.lock().unwrap().clear();
+        self.evpn.dirty.lock().unwrap().clear();
+        self.fs4u.dirty.lock().unwrap().clear();
+        self.ipv4mdt.dirty.lock().unwrap().clear();
+        self.ipv6mdt.dirty.lock().unwrap().clear();
+        self.ipv6m.dirty.lock().unwrap().clear();
+    }
+    /// writes next differential snapshot, or full one after routes were removed
+    pub fn store_snapshot_diff(&mut self) -> std::io::Result<()> {
+        self.snapshot_diff_saved = Timestamp::now();
+        let fnm = match self.snapshot_file.as_ref() {
+            None => return Ok(()),
+            Some(f) => f.clone(),
+        };
+        if self.snapshot_full_needed.load(Ordering::Relaxed)
+            || self.snapshot_base.load(Ordering::Relaxed) == 0
+            || !std::path::Path::new(&fnm).exists()
+        {
+            return self.store_snapshot();
+        }
+        let seq = self.snapshot_seq.load(Ordering::Relaxed) + 1;
+        let dname = Self::snapshot_diff_name(&fnm, seq);
+        let ftmp = dname.clone() + ".tmp";
+        {
+            let file = BufWriter::with_capacity(4096, std::fs::File::create(&ftmp)?);
+            if let Err(e) =
+                self.write_snapshot_diff(seq, self.snapshot_base.load(Ordering::Relaxed), file)
+            {
+                warn!("Unable to save snapshot diff: {}", e);
+                let _ = std::fs::remove_file(&ftmp);
+                return Ok(());
+            }
+        }
+        std::fs::rename(&ftmp, &dname)?;
+        debug!("Snapshot diff {} saved", dname);
+        self.snapshot_seq.store(seq, Ordering::Relaxed);
+        self.clear_dirty();
         Ok(())
     }
     pub async fn shutdown(&self) {
@@ -840,20 +1530,35 @@ impl BgpRIB {
             return Ok(());
         }
         let ftmp = self.snapshot_file.as_ref().unwrap().clone() + ".tmp";
-        {
-            let file = BufWriter::with_capacity(4096, std::fs::File::create(&ftmp)?);
+        let base = {
+            let mut file = ContentHash::new(BufWriter::with_capacity(
+                4096,
+                std::fs::File::create(&ftmp)?,
+            ));
             info!(
                 "Creating snapshot: {}",
                 self.snapshot_file.as_ref().unwrap()
             );
-            if let Err(e) = self.write_snapshot(file) {
+            if let Err(e) = self.write_snapshot(&mut file) {
                 warn!("Unable to save snapshot: {}", e);
                 let _ = std::fs::remove_file(&ftmp);
                 return Ok(());
             }
+            std::io::Write::flush(&mut file)?;
+            file.finish()
+        };
+        let fnm = self.snapshot_file.as_ref().unwrap();
+        let _ = std::fs::remove_file(fnm);
+        std::fs::rename(&ftmp, fnm)?;
+        // diffs of previous base are ignored on load, removed to save space
+        let mut seq = 1;
+        while std::fs::remove_file(Self::snapshot_diff_name(fnm, seq)).is_ok() {
+            seq += 1;
         }
-        let _ = std::fs::remove_file(self.snapshot_file.as_ref().unwrap());
-        std::fs::rename(&ftmp, self.snapshot_file.as_ref().unwrap())?;
+        self.snapshot_base.store(base, Ordering::Relaxed);
+        self.snapshot_seq.store(0, Ordering::Relaxed);
+        self.snapshot_full_needed.store(false, Ordering::Relaxed);
+        self.clear_dirty();
         Ok(())
     }
     pub fn clear(&mut self) {
@@ -863,6 +1568,7 @@ impl BgpRIB {
         self.vpnv4u.clear();
         self.vpnv4m.clear();
         self.ipv6u.clear();
+        self.ipv6m.clear();
         self.ipv6lu.clear();
         self.vpnv6u.clear();
         self.vpnv6m.clear();
@@ -874,10 +1580,83 @@ impl BgpRIB {
         self.ipv6mdt.clear();
         self.attrs.clear();
         self.clusters.clear();
+        self.prefix_sids.clear();
+        self.confeds.clear();
         self.extcomms.clear();
         self.lcomms.clear();
         self.comms.clear();
         self.pathes.clear();
+        self.snapshot_full_needed.store(true, Ordering::Relaxed);
+    }
+    /// routes of last handled message in RIB of kind which did not change it
+    fn take_skipped(&mut self, kind: Option<BgpRibKind>) -> Vec<usize> {
+        let skipped = match kind {
+            Some(BgpRibKind::IpV4u) => &mut self.ipv4u.skipped,
+            Some(BgpRibKind::IpV4m) => &mut self.ipv4m.skipped,
+            Some(BgpRibKind::IpV4LU) => &mut self.ipv4lu.skipped,
+            Some(BgpRibKind::VpnV4u) => &mut self.vpnv4u.skipped,
+            Some(BgpRibKind::VpnV4m) => &mut self.vpnv4m.skipped,
+            Some(BgpRibKind::IpV6u) => &mut self.ipv6u.skipped,
+            Some(BgpRibKind::IpV6m) => &mut self.ipv6m.skipped,
+            Some(BgpRibKind::IpV6LU) => &mut self.ipv6lu.skipped,
+            Some(BgpRibKind::VpnV6u) => &mut self.vpnv6u.skipped,
+            Some(BgpRibKind::VpnV6m) => &mut self.vpnv6m.skipped,
+            Some(BgpRibKind::L2vpls) => &mut self.l2vpls.skipped,
+            Some(BgpRibKind::MVpn) => &mut self.mvpn.skipped,
+            Some(BgpRibKind::EVpn) => &mut self.evpn.skipped,
+            Some(BgpRibKind::Fs4u) => &mut self.fs4u.skipped,
+            Some(BgpRibKind::IpV4mdt) => &mut self.ipv4mdt.skipped,
+            Some(BgpRibKind::Ipv6mdt) => &mut self.ipv6mdt.skipped,
+            None => return Vec::new(),
+        };
+        std::mem::take(skipped)
+    }
+    /// announcements not recorded by deduplication in all RIBs
+    pub fn dedup_suppressed(&self) -> u64 {
+        self.ipv4u.dedup_suppressed
+            + self.ipv4m.dedup_suppressed
+            + self.ipv4lu.dedup_suppressed
+            + self.vpnv4u.dedup_suppressed
+            + self.vpnv4m.dedup_suppressed
+            + self.ipv6u.dedup_suppressed
+            + self.ipv6m.dedup_suppressed
+            + self.ipv6lu.dedup_suppressed
+            + self.vpnv6u.dedup_suppressed
+            + self.vpnv6m.dedup_suppressed
+            + self.l2vpls.dedup_suppressed
+            + self.mvpn.dedup_suppressed
+            + self.evpn.dedup_suppressed
+            + self.fs4u.dedup_suppressed
+            + self.ipv4mdt.dedup_suppressed
+            + self.ipv6mdt.dedup_suppressed
+    }
+    /// history and purge knobs changed at runtime
+    pub fn apply_settings(&mut self, s: &crate::settings::Settings) {
+        self.ipv4u.set_history(s.historydepth, s.history_mode());
+        self.ipv4m.set_history(s.historydepth, s.history_mode());
+        self.ipv4lu.set_history(s.historydepth, s.history_mode());
+        self.vpnv4u.set_history(s.historydepth, s.history_mode());
+        self.vpnv4m.set_history(s.historydepth, s.history_mode());
+        self.ipv6u.set_history(s.historydepth, s.history_mode());
+        self.ipv6m.set_history(s.historydepth, s.history_mode());
+        self.ipv6lu.set_history(s.historydepth, s.history_mode());
+        self.vpnv6u.set_history(s.historydepth, s.history_mode());
+        self.vpnv6m.set_history(s.historydepth, s.history_mode());
+        self.l2vpls.set_history(s.historydepth, s.history_mode());
+        self.mvpn.set_history(s.historydepth, s.history_mode());
+        self.evpn.set_history(s.historydepth, s.history_mode());
+        self.fs4u.set_history(s.historydepth, s.history_mode());
+        self.ipv4mdt.set_history(s.historydepth, s.history_mode());
+        self.ipv6mdt.set_history(s.historydepth, s.history_mode());
+        self.purge_after_withdraws = s.purge_after_withdraws;
+        self.purge_every = chrono::Duration::seconds(s.purge_every as i64);
+    }
+    /// drops all routes, used by admin flush
+    pub fn flush(&mut self) {
+        self.clear();
+        // new start time changes every entity tag
+        self.generations = Default::default();
+        self.versions.reset();
     }
     pub fn load_snapshot<P: AsRef<std::path::Path>>(
         cfg: &SvcConfig,
@@ -887,23 +1666,85 @@ impl BgpRIB {
             return Ok(BgpRIB::new(cfg));
         }
         info!("Loading snapshot: {}", cfg.snapshot_file.as_ref().unwrap());
-        let mut fl = BufReader::new(std::fs::File::open(fnm)?);
+        let mut diffs = Vec::new();
+        let fname = fnm.as_ref().to_string_lossy().to_string();
+        while let Ok(f) =
+            std::fs::File::open(Self::snapshot_diff_name(&fname, diffs.len() as u64 + 1))
+        {
+            diffs.push(BufReader::new(f));
+        }
+        BgpRIB::read_snapshot_diffs(cfg, BufReader::new(std::fs::File::open(fnm)?), diffs)
+    }
+    /// builds RIB from snapshot stream written by write_snapshot
+    pub fn read_snapshot<R: std::io::Read>(
+        cfg: &SvcConfig,
+        fl: R,
+    ) -> Result<BgpRIB, Box<dyn std::error::Error>> {
+        BgpRIB::read_snapshot_diffs(cfg, fl, Vec::<R>::new())
+    }
+    /// builds RIB from full snapshot and differential snapshots written against its content, applied in order
+    pub fn read_snapshot_diffs<R: std::io::Read, D: std::io::Read>(
+        cfg: &SvcConfig,
+        fl: R,
+        diffs: Vec<D>,
+    ) -> Result<BgpRIB, Box<dyn std::error::Error>> {
+        lazy_static! {
+            static ref SNAPSHOT_READ: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        }
+        // deserialization interns attributes into global RIB, so one snapshot at a time
+        let _guard = SNAPSHOT_READ.lock().unwrap();
         rib_set(BgpRIB::new(cfg));
-        let ipv4u = ciborium::de::from_reader(&mut fl)?;
-        let ipv4m = ciborium::de::from_reader(&mut fl)?;
-        let ipv4lu = ciborium::de::from_reader(&mut fl)?;
-        let vpnv4u = ciborium::de::from_reader(&mut fl)?;
-        let vpnv4m = ciborium::de::from_reader(&mut fl)?;
-        let ipv6u = ciborium::de::from_reader(&mut fl)?;
-        let ipv6lu = ciborium::de::from_reader(&mut fl)?;
-        let vpnv6u = ciborium::de::from_reader(&mut fl)?;
-        let vpnv6m = ciborium::de::from_reader(&mut fl)?;
-        let l2vpls = ciborium::de::from_reader(&mut fl)?;
-        let mvpn = ciborium::de::from_reader(&mut fl)?;
-        let evpn = ciborium::de::from_reader(&mut fl)?;
-        let fs4u = ciborium::de::from_reader(&mut fl)?;
-        let ipv4mdt = ciborium::de::from_reader(&mut fl)?;
-        let ipv6mdt = ciborium::de::from_reader(&mut fl)?;
+        let mut fl = ContentHash::new(fl);
+        let mut ipv4u: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut ipv4m: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut ipv4lu: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut vpnv4u: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut vpnv4m: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut ipv6u: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut ipv6lu: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut vpnv6u: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut vpnv6m: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut l2vpls: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut mvpn: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut evpn: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut fs4u: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut ipv4mdt: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        let mut ipv6mdt: BTreeMap<_, BgpSessionEntry> = ciborium::de::from_reader(&mut fl)?;
+        // absent in snapshots written before ipv6m support
+        let mut ipv6m: BTreeMap<_, BgpSessionEntry> = read_optional_part(&mut fl)?;
+        std::io::copy(&mut fl, &mut std::io::sink())?;
+        let base = fl.finish();
+        let mut applied = 0;
+        for mut d in diffs {
+            let (seq, dbase): (u64, u64) = ciborium::de::from_reader(&mut d)?;
+            if seq != applied + 1 || dbase != base {
+                warn!(
+                    "Snapshot diff {} does not match snapshot, ignored with later diffs",
+                    seq
+                );
+                break;
+            }
+            ipv4u.extend(read_diff_part(&mut d)?);
+            ipv4m.extend(read_diff_part(&mut d)?);
+            ipv4lu.extend(read_diff_part(&mut d)?);
+            vpnv4u.extend(read_diff_part(&mut d)?);
+            vpnv4m.extend(read_diff_part(&mut d)?);
+            ipv6u.extend(read_diff_part(&mut d)?);
+            ipv6lu.extend(read_diff_part(&mut d)?);
+            vpnv6u.extend(read_diff_part(&mut d)?);
+            vpnv6m.extend(read_diff_part(&mut d)?);
+            l2vpls.extend(read_diff_part(&mut d)?);
+            mvpn.extend(read_diff_part(&mut d)?);
+            evpn.extend(read_diff_part(&mut d)?);
+            fs4u.extend(read_diff_part(&mut d)?);
+            ipv4mdt.extend(read_diff_part(&mut d)?);
+            ipv6mdt.extend(read_diff_part(&mut d)?);
+            ipv6m.extend(read_diff_part(&mut d)?);
+            applied = seq;
+        }
+        if applied > 0 {
+            info!("Applied {} snapshot diffs", applied);
+        }
         let mut rib = rib_take();
         rib.ipv4u.assign(ipv4u);
         rib.ipv4m.assign(ipv4m);
@@ -920,45 +1761,169 @@ impl BgpRIB {
         rib.fs4u.assign(fs4u);
         rib.ipv4mdt.assign(ipv4mdt);
         rib.ipv6mdt.assign(ipv6mdt);
+        rib.ipv6m.assign(ipv6m);
+        rib.recalc_peer_stats();
+        rib.snapshot_seq.store(applied, Ordering::Relaxed);
+        rib.snapshot_base.store(base, Ordering::Relaxed);
         Ok(rib)
     }
+    /// replaces RIB content with other one, keeping subscribers and generations
+    pub fn replace_from(&mut self, other: BgpRIB) {
+        self.snapshot_full_needed.store(true, Ordering::Relaxed);
+        self.versions.reset();
+        self.pathes = other.pathes;
+        self.comms = other.comms;
+        self.lcomms = other.lcomms;
+        self.extcomms = other.extcomms;
+        self.clusters = other.clusters;
+        self.pmsi_ta_s = other.pmsi_ta_s;
+        self.prefix_sids = other.prefix_sids;
+        self.confeds = other.confeds;
+        self.attrs = other.attrs;
+        self.ipv4u = other.ipv4u;
+        self.ipv4m = other.ipv4m;
+        self.ipv4lu = other.ipv4lu;
+        self.vpnv4u = other.vpnv4u;
+        self.vpnv4m = other.vpnv4m;
+        self.ipv6u = other.ipv6u;
+        self.ipv6m = other.ipv6m;
+        self.ipv6lu = other.ipv6lu;
+        self.vpnv6u = other.vpnv6u;
+        self.vpnv6m = other.vpnv6m;
+        self.l2vpls = other.l2vpls;
+        self.mvpn = other.mvpn;
+        self.evpn = other.evpn;
+        self.fs4u = other.fs4u;
+        self.ipv4mdt = other.ipv4mdt;
+        self.ipv6mdt = other.ipv6mdt;
+        self.peer_stats = other.peer_stats;
+        for kind in [
+            BgpRibKind::IpV4u,
+            BgpRibKind::IpV4m,
+            BgpRibKind::IpV4LU,
+            BgpRibKind::VpnV4u,
+            BgpRibKind::VpnV4m,
+            BgpRibKind::IpV6u,
+            BgpRibKind::IpV6m,
+            BgpRibKind::IpV6LU,
+            BgpRibKind::VpnV6u,
+            BgpRibKind::VpnV6m,
+            BgpRibKind::L2vpls,
+            BgpRibKind::MVpn,
+            BgpRibKind::EVpn,
+            BgpRibKind::Fs4u,
+            BgpRibKind::IpV4mdt,
+            BgpRibKind::Ipv6mdt,
+        ] {
+            self.generations.bump(Some(kind));
+        }
+    }
+    /// rebuild active routes counters for all peers from RIB content
+    pub fn recalc_peer_stats(&mut self) {
+        let kinds = [
+            (BgpRibKind::IpV4u, self.ipv4u.active_stats()),
+            (BgpRibKind::IpV4m, self.ipv4m.active_stats()),
+            (BgpRibKind::IpV4LU, self.ipv4lu.active_stats()),
+            (BgpRibKind::VpnV4u, self.vpnv4u.active_stats()),
+            (BgpRibKind::VpnV4m, self.vpnv4m.active_stats()),
+            (BgpRibKind::IpV6u, self.ipv6u.active_stats()),
+            (BgpRibKind::IpV6m, self.ipv6m.active_stats()),
+            (BgpRibKind::IpV6LU, self.ipv6lu.active_stats()),
+            (BgpRibKind::VpnV6u, self.vpnv6u.active_stats()),
+            (BgpRibKind::VpnV6m, self.vpnv6m.active_stats()),
+            (BgpRibKind::L2vpls, self.l2vpls.active_stats()),
+            (BgpRibKind::MVpn, self.mvpn.active_stats()),
+            (BgpRibKind::EVpn, self.evpn.active_stats()),
+            (BgpRibKind::Fs4u, self.fs4u.active_stats()),
+            (BgpRibKind::IpV4mdt, self.ipv4mdt.active_stats()),
+            (BgpRibKind::Ipv6mdt, self.ipv6mdt.active_stats()),
+        ];
+        for (kind, stats) in kinds.iter() {
+            for (sess, delta) in stats.iter() {
+                self.peer_stats
+                    .entry(*sess)
+                    .or_default()
+                    .reset_active(*kind, delta);
+            }
+        }
+    }
     pub fn handle_withdraws(&mut self, session: BgpSessionId, withdraws: BgpAddrs) {
-        match &withdraws {
-            BgpAddrs::IPV4U(v) => self.ipv4u.handle_withdraws_afi(session, v),
-            BgpAddrs::IPV4M(v) => self.ipv4m.handle_withdraws_afi(session, v),
-            BgpAddrs::IPV4LU(v) => self.ipv4lu.handle_withdraws_afi(session, v),
-            BgpAddrs::VPNV4U(v) => self.vpnv4u.handle_withdraws_afi(session, v),
-            BgpAddrs::VPNV4M(v) => self.vpnv4m.handle_withdraws_afi(session, v),
-            BgpAddrs::IPV6U(v) => self.ipv6u.handle_withdraws_afi(session, v),
-            BgpAddrs::IPV6LU(v) => self.ipv6lu.handle_withdraws_afi(session, v),
-            BgpAddrs::VPNV6U(v) => self.vpnv6u.handle_withdraws_afi(session, v),
-            BgpAddrs::VPNV6M(v) => self.vpnv6m.handle_withdraws_afi(session, v),
-            BgpAddrs::L2VPLS(v) => self.l2vpls.handle_withdraws_afi(session, v),
-            BgpAddrs::MVPN(v) => self.mvpn.handle_withdraws_afi(session, v),
-            BgpAddrs::EVPN(v) => self.evpn.handle_withdraws_afi(session, v),
-            BgpAddrs::FS4U(v) => self.fs4u.handle_withdraws_afi(session, v),
-            BgpAddrs::IPV4UP(v) => self.ipv4u.handle_withdraws_afi_pathid(session, v),
-            BgpAddrs::IPV4MP(v) => self.ipv4m.handle_withdraws_afi_pathid(session, v),
-            BgpAddrs::IPV4LUP(v) => self.ipv4lu.handle_withdraws_afi_pathid(session, v),
-            BgpAddrs::VPNV4UP(v) => self.vpnv4u.handle_withdraws_afi_pathid(session, v),
-            BgpAddrs::VPNV4MP(v) => self.vpnv4m.handle_withdraws_afi_pathid(session, v),
-            BgpAddrs::IPV6UP(v) => self.ipv6u.handle_withdraws_afi_pathid(session, v),
-            //BgpAddrs::IPV6MP(v) => self.ipv6m.handle_withdraws_afi_pathid(session, v),
-            BgpAddrs::IPV6LUP(v) => self.ipv6lu.handle_withdraws_afi_pathid(session, v),
-            BgpAddrs::VPNV6UP(v) => self.vpnv6u.handle_withdraws_afi_pathid(session, v),
-            BgpAddrs::VPNV6MP(v) => self.vpnv6m.handle_withdraws_afi_pathid(session, v),
-            BgpAddrs::IPV4MDT(v) => self.ipv4mdt.handle_withdraws_afi(session, v),
-            BgpAddrs::IPV6MDT(v) => self.ipv6mdt.handle_withdraws_afi(session, v),
-            _ => {}
+        let burst = self.withdraw_bursts.in_burst(session);
+        let delta = match &withdraws {
+            BgpAddrs::IPV4U(v) => self.ipv4u.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::IPV4M(v) => self.ipv4m.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::IPV4LU(v) => self.ipv4lu.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::VPNV4U(v) => self.vpnv4u.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::VPNV4M(v) => self.vpnv4m.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::IPV6U(v) => self.ipv6u.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::IPV6M(v) => self.ipv6m.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::IPV6LU(v) => self.ipv6lu.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::VPNV6U(v) => self.vpnv6u.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::VPNV6M(v) => self.vpnv6m.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::L2VPLS(v) => self.l2vpls.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::MVPN(v) => self.mvpn.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::EVPN(v) => self.evpn.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::FS4U(v) => self.fs4u.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::IPV4UP(v) => self.ipv4u.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::IPV4MP(v) => self.ipv4m.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::IPV4LUP(v) => self.ipv4lu.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::VPNV4UP(v) => self.vpnv4u.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::VPNV4MP(v) => self.vpnv4m.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::IPV6UP(v) => self.ipv6u.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::IPV6MP(v) => self.ipv6m.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::IPV6LUP(v) => self.ipv6lu.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::VPNV6UP(v) => self.vpnv6u.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::VPNV6MP(v) => self.vpnv6m.handle_withdraws_afi_pathid(session, v, burst),
+            BgpAddrs::IPV4MDT(v) => self.ipv4mdt.handle_withdraws_afi(session, v, burst),
+            BgpAddrs::IPV6MDT(v) => self.ipv6mdt.handle_withdraws_afi(session, v, burst),
+            _ => BgpRibDelta::default(),
         };
+        let kind = BgpRibKind::from_bgp_addrs(&withdraws);
+        self.generations.bump(kind);
+        if let Some(kind) = kind {
+            self.peer_stats
+                .entry(session)
+                .or_default()
+                .on_withdraws(kind, withdraws.len(), &delta);
+        }
+        let rib = kind.map(|k| k.to_string()).unwrap_or_default();
+        let now = Timestamp::now();
+        let withdraws = Arc::new(withdraws);
+        let skipped = self.take_skipped(kind);
+        self.versions.record(
+            session,
+            &crate::tableversion::without(&withdraws, &skipped),
+            None,
+            now,
+        );
+        let single = self
+            .withdraw_bursts
+            .on_withdraws(session, rib.as_str(), withdraws, now);
         if self.events.receiver_count() > 0 {
-            if let Err(e) = self
-                .events
-                .send(BgpEvent::Withdraw(session, Arc::new(withdraws)))
-            {
-                warn!("Publish withdraw event error: {}", e);
+            if let Some(addrs) = single {
+                if let Err(e) = self.events.send(BgpEvent::Withdraw(session, addrs)) {
+                    warn!("Publish withdraw event error: {}", e);
+                }
             }
         }
+        self.publish_withdraw_batches(now);
+    }
+    /// sends coalesced withdraws of bursts which are due
+    pub fn publish_withdraw_batches(&mut self, now: Timestamp) {
+        for b in self.withdraw_bursts.take_ready(now) {
+            if self.events.receiver_count() > 0 {
+                if let Err(e) = self
+                    .events
+                    .send(BgpEvent::WithdrawBatch(b.session, Arc::new(b)))
+                {
+                    warn!("Publish withdraw batch error: {}", e);
+                }
+            }
+        }
+    }
+    /// bursts in progress, their batches are published even when withdraws stop
+    pub fn needs_withdraw_flush(&self) -> bool {
+        self.withdraw_bursts.pending()
     }
     pub fn handle_updates(
         &mut self,
@@ -967,13 +1932,14 @@ impl BgpRIB {
         updates: BgpAddrs,
     ) {
         let ra = rattr.clone();
-        match &updates {
+        let delta = match &updates {
             BgpAddrs::IPV4U(v) => self.ipv4u.handle_updates_afi(session, v, rattr),
             BgpAddrs::IPV4M(v) => self.ipv4m.handle_updates_afi(session, v, rattr),
             BgpAddrs::IPV4LU(v) => self.ipv4lu.handle_updates_afi(session, v, rattr),
             BgpAddrs::VPNV4U(v) => self.vpnv4u.handle_updates_afi(session, v, rattr),
             BgpAddrs::VPNV4M(v) => self.vpnv4m.handle_updates_afi(session, v, rattr),
             BgpAddrs::IPV6U(v) => self.ipv6u.handle_updates_afi(session, v, rattr),
+            BgpAddrs::IPV6M(v) => self.ipv6m.handle_updates_afi(session, v, rattr),
             BgpAddrs::IPV6LU(v) => self.ipv6lu.handle_updates_afi(session, v, rattr),
             BgpAddrs::VPNV6U(v) => self.vpnv6u.handle_updates_afi(session, v, rattr),
             BgpAddrs::VPNV6M(v) => self.vpnv6m.handle_updates_afi(session, v, rattr),
@@ -987,33 +1953,111 @@ impl BgpRIB {
             BgpAddrs::VPNV4UP(v) => self.vpnv4u.handle_updates_afi_pathid(session, v, rattr),
             BgpAddrs::VPNV4MP(v) => self.vpnv4m.handle_updates_afi_pathid(session, v, rattr),
             BgpAddrs::IPV6UP(v) => self.ipv6u.handle_updates_afi_pathid(session, v, rattr),
-            //BgpAddrs::IPV6MP(v) => self.ipv6m.handle_updates_afi_pathid(session, v, rattr),
+            BgpAddrs::IPV6MP(v) => self.ipv6m.handle_updates_afi_pathid(session, v, rattr),
             BgpAddrs::IPV6LUP(v) => self.ipv6lu.handle_updates_afi_pathid(session, v, rattr),
             BgpAddrs::VPNV6UP(v) => self.vpnv6u.handle_updates_afi_pathid(session, v, rattr),
             BgpAddrs::VPNV6MP(v) => self.vpnv6m.handle_updates_afi_pathid(session, v, rattr),
             BgpAddrs::IPV4MDT(v) => self.ipv4mdt.handle_updates_afi(session, v, rattr),
             BgpAddrs::IPV6MDT(v) => self.ipv6mdt.handle_updates_afi(session, v, rattr),
-            _ => {}
+            _ => BgpRibDelta::default(),
         };
+        let kind = BgpRibKind::from_bgp_addrs(&updates);
+        self.generations.bump(kind);
+        if let Some(kind) = kind {
+            self.peer_stats
+                .entry(session)
+                .or_default()
+                .on_updates(kind, updates.len(), &delta);
+        }
+        let updates = Arc::new(updates);
+        let skipped = self.take_skipped(kind);
+        self.versions.record(
+            session,
+            &crate::tableversion::without(&updates, &skipped),
+            Some(&ra),
+            Timestamp::now(),
+        );
         if self.events.receiver_count() > 0 {
-            if let Err(e) = self
-                .events
-                .send(BgpEvent::Update(session, ra, Arc::new(updates)))
-            {
+            if let Some(b) = self.withdraw_bursts.take_session(session) {
+                if let Err(e) = self
+                    .events
+                    .send(BgpEvent::WithdrawBatch(session, Arc::new(b)))
+                {
+                    warn!("Publish withdraw batch error: {}", e);
+                }
+            }
+            if let Err(e) = self.events.send(BgpEvent::Update(session, ra, updates)) {
                 warn!("Publish update event error: {}", e);
             }
         }
     }
+    /// passes updates through inbound lists and applies update script decisions,
+    /// dropped routes are withdrawn
+    fn handle_updates_filtered(
+        &mut self,
+        session: BgpSessionId,
+        rattr: Arc<BgpAttrs>,
+        updates: BgpAddrs,
+        decisions: &crate::script::Decisions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.inbound.is_none() && decisions.is_empty() {
+            self.handle_updates(session, rattr, updates);
+            return Ok(());
+        }
+        let (inbound, filtered) = (&self.inbound, &mut self.filtered);
+        let origin = rattr.aspath.value.last().map(|a| a.value);
+        let now = Timestamp::now();
+        let (groups, dropped) = crate::script::split_addrs(updates, &mut |p| {
+            if let Some(flt) = inbound {
+                if let Some(reason) = flt.rejected_by(p, &rattr) {
+                    filtered.record(p, origin, reason, session, now);
+                    return crate::script::Verdict::Drop;
+                }
+            }
+            match decisions.get(&p.to_string()) {
+                Some(crate::script::Verdict::Drop) => {
+                    filtered.record(p, origin, "update_script", session, now);
+                    crate::script::Verdict::Drop
+                }
+                Some(v) => v.clone(),
+                None => crate::script::Verdict::Keep,
+            }
+        });
+        if let Some(w) = dropped {
+            self.handle_withdraws(session, w);
+        }
+        for (attrs, addrs) in groups.into_iter() {
+            let ra = match attrs {
+                None => rattr.clone(),
+                Some(edit) => {
+                    let mut a = edit.apply(&rattr);
+                    a.comms = BgpRIB::register_shared(&mut self.comms, a.comms.as_ref())?;
+                    BgpRIB::register_shared(&mut self.attrs, &a)?
+                }
+            };
+            self.handle_updates(session, ra, addrs);
+        }
+        Ok(())
+    }
     fn register_shared<T: Clone + Eq + Ord + std::hash::Hash + std::fmt::Debug>(
         hset: &mut RibItemStore<T>,
         item: &T,
     ) -> Result<Arc<T>, Box<dyn std::error::Error>> {
-        hset.get(Arc::new(item.clone()))
+        Ok(hset.intern_ref(item))
     }
     pub fn handle_update(
         &mut self,
         sessionid: BgpSessionId,
         upd: BgpUpdateMessage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.handle_update_decided(sessionid, upd, &Default::default())
+    }
+    /// applies update with update script decisions taken by BgpRIBts::script_decisions
+    pub fn handle_update_decided(
+        &mut self,
+        sessionid: BgpSessionId,
+        upd: BgpUpdateMessage,
+        decisions: &crate::script::Decisions,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let mut attr = BgpAttrs {
             origin: match upd.get_attr_origin() {
@@ -1031,13 +2075,15 @@ impl BgpRIB {
                 },
             },
             aspath: match upd.get_attr_aspath() {
-                None => BgpRIB::register_shared(&mut self.pathes, &BgpASpath::new()),
-                Some(n) => BgpRIB::register_shared(&mut self.pathes, n),
-            }?,
+                None => self.pathes.intern(Default::default()),
+                Some(n) => self
+                    .pathes
+                    .intern(crate::compact::CompactASPath::from_aspath(n)),
+            },
             comms: match upd.get_attr_communitylist() {
-                None => BgpRIB::register_shared(&mut self.comms, &BgpCommunityList::new()),
-                Some(n) => BgpRIB::register_shared(&mut self.comms, n),
-            }?,
+                None => self.comms.intern(Default::default()),
+                Some(n) => self.comms.intern(n.into()),
+            },
             lcomms: match upd.get_attr_largecommunitylist() {
                 None => BgpRIB::register_shared(&mut self.lcomms, &BgpLargeCommunityList::new()),
                 Some(n) => BgpRIB::register_shared(&mut self.lcomms, n),
@@ -1053,6 +2099,8 @@ impl BgpRIB {
             originator: None,
             clusterlist: None,
             pmsi_ta: None,
+            prefix_sid: None,
+            confed: None,
         };
         for i in upd.attrs.iter() {
             match i {
@@ -1072,10 +2120,33 @@ impl BgpRIB {
                     attr.originator = Some(n.value);
                 }
                 BgpAttrItem::ClusterList(n) => {
-                    attr.clusterlist = Some(self.clusters.get(Arc::new(n.clone()))?);
+                    attr.clusterlist = Some(self.clusters.intern_ref(n));
                 }
                 BgpAttrItem::PMSITunnel(n) => {
-                    attr.pmsi_ta = Some(self.pmsi_ta_s.get(Arc::new(n.clone()))?);
+                    attr.pmsi_ta = Some(self.pmsi_ta_s.intern_ref(n));
+                }
+                BgpAttrItem::Unknown(n) if n.params.typecode == BGP_ATTR_PREFIX_SID => {
+                    match BgpPrefixSid::decode_from(&n.value) {
+                        Ok(psid) => {
+                            attr.prefix_sid = Some(self.prefix_sids.intern(psid));
+                        }
+                        Err(e) => warn!("Prefix-SID decode error: {:?}", e),
+                    }
+                }
+                BgpAttrItem::Unknown(n)
+                    if n.params.typecode == crate::wireparse::ATTR_CONFED_PATH =>
+                {
+                    match crate::confed::BgpConfedPath::decode_from(&n.value) {
+                        Ok(confed) => {
+                            if self.confed_in_aspath {
+                                let mut path = confed.leading_asns();
+                                path.extend(attr.aspath.value.iter().map(|a| a.value));
+                                attr.aspath = self.pathes.intern(path.into());
+                            }
+                            attr.confed = Some(self.confeds.intern(confed));
+                        }
+                        Err(e) => warn!("Confederation path decode error: {:?}", e),
+                    }
                 }
                 BgpAttrItem::Unknown(_) => {
                     warn!("{}\tBGP Unknown: {:?}", Timestamp::now(), upd);
@@ -1088,7 +2159,7 @@ impl BgpRIB {
         let mut updates_count: usize = upd.updates.len();
         let mut withdraws_count: usize = upd.withdraws.len();
         self.handle_withdraws(sessionid, upd.withdraws);
-        self.handle_updates(sessionid, rattr.clone(), upd.updates);
+        self.handle_updates_filtered(sessionid, rattr.clone(), upd.updates, decisions)?;
         for i in upd.attrs.into_iter() {
             match i {
                 BgpAttrItem::MPUpdates(n) => {
@@ -1099,7 +2170,7 @@ impl BgpRIB {
                         BgpRIB::register_shared(&mut self.attrs, &attr)?
                     };
                     updates_count += n.addrs.len();
-                    self.handle_updates(sessionid, cattr.clone(), n.addrs);
+                    self.handle_updates_filtered(sessionid, cattr.clone(), n.addrs, decisions)?;
                 }
                 BgpAttrItem::MPWithdraws(n) => {
                     withdraws_count += n.addrs.len();
@@ -1131,4 +2202,107 @@ mod tests {
         teststore.purge();
         assert_eq!(teststore.len(), 0);
     }
+    #[test]
+    fn test_read_optional_part() {
+        let empty: &[u8] = &[];
+        let m: BTreeMap<BgpAddrV6, BgpSessionEntry> = read_optional_part(empty).unwrap();
+        assert!(m.is_empty());
+        let mut buf = Vec::new();
+        let mut src: BTreeMap<BgpAddrV6, BgpSessionEntry> = BTreeMap::new();
+        src.insert(
+            BgpAddrV6::new("ff0e::".parse().unwrap(), 16),
+            BgpSessionEntry::new(),
+        );
+        ciborium::ser::into_writer(&src, &mut buf).unwrap();
+        let m: BTreeMap<BgpAddrV6, BgpSessionEntry> = read_optional_part(&buf[..]).unwrap();
+        assert_eq!(m.len(), 1);
+        let r: Result<BTreeMap<BgpAddrV6, BgpSessionEntry>, _> =
+            read_optional_part(&buf[..buf.len() - 1]);
+        assert!(r.is_err());
+    }
+    #[test]
+    fn test_content_hash() {
+        use std::io::{Read, Write};
+        let mut w = ContentHash::new(Vec::new());
+        w.write_all(b"snapshot").unwrap();
+        let mut r = ContentHash::new(&w.inner[..]);
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf).unwrap();
+        assert_eq!(w.finish(), r.finish());
+        // same size, other content
+        let mut o = ContentHash::new(Vec::new());
+        o.write_all(b"Snapshot").unwrap();
+        assert_ne!(w.finish(), o.finish());
+        assert_ne!(ContentHash::new(std::io::sink()).finish(), 0);
+    }
+    #[test]
+    fn test_ribitemstore_intern() {
+        let mut teststore = RibItemStore::<Vec<u32>>::new();
+        let a = teststore.intern(vec![64500, 64501]);
+        let b = teststore.intern_ref(&vec![64500, 64501]);
+        let c = teststore.get(Arc::new(vec![64500, 64501])).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(Arc::ptr_eq(&a, &c));
+        assert_eq!(teststore.len(), 1);
+        let d = teststore.intern(vec![64502]);
+        assert!(!Arc::ptr_eq(&a, &d));
+        assert_eq!(teststore.len(), 2);
+    }
+    #[test]
+    fn test_history_changed_mask() {
+        let mut hist = BgpAttrHistory::new();
+        let a = Arc::new(BgpAttrs::new());
+        let mut b = BgpAttrs::new();
+        b.med = Some(10);
+        let now = Timestamp::now();
+        hist.insert(now, BgpAttrEntry::new(true, a, None));
+        hist.insert(
+            Timestamp::from_unix(now.timestamp() + 1, 0),
+            BgpAttrEntry::new(true, Arc::new(b), None),
+        );
+        let masks: Vec<u32> = hist.items.values().map(|e| e.changed).collect();
+        let med = BgpAttrs::field_names()
+            .iter()
+            .position(|n| *n == "Med")
+            .unwrap();
+        assert_eq!(masks, vec![0, 1 << med]);
+        assert_eq!(hist.stability().changes, 1);
+        // mask is kept in snapshots
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(&hist.items, &mut buf).unwrap();
+        let back: InlineMap<Timestamp, BgpAttrEntry> = ciborium::de::from_reader(&buf[..]).unwrap();
+        let masks: Vec<u32> = back.values().map(|e| e.changed).collect();
+        assert_eq!(masks, vec![0, 1 << med]);
+        // counters survive snapshot, older snapshots without them still load
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(&hist, &mut buf).unwrap();
+        let back: BgpAttrHistory = ciborium::de::from_reader(&buf[..]).unwrap();
+        assert_eq!(back.items.len(), 2);
+        assert_eq!(back.stability().changes, 1);
+        assert!(back.stability.is_some());
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(&hist.items, &mut buf).unwrap();
+        let back: BgpAttrHistory = ciborium::de::from_reader(&buf[..]).unwrap();
+        assert_eq!(back.items.len(), 2);
+        assert!(back.stability.is_none());
+        // mask fits padding, labels are boxed
+        #[cfg(target_pointer_width = "64")]
+        assert!(std::mem::size_of::<BgpAttrEntry>() <= 40);
+    }
+    #[test]
+    fn test_dedup_window() {
+        let mut rib = BgpRIBSafi::<BgpAddrV4>::new(10, HistoryChangeMode::EveryUpdate);
+        rib.dedup_window = Some(chrono::Duration::seconds(60));
+        let k = BgpAddrV4::new(std::net::Ipv4Addr::new(192, 0, 2, 0), 24);
+        let a = Arc::new(BgpAttrs::new());
+        let mut b = BgpAttrs::new();
+        b.med = Some(10);
+        rib.handle_updates_afi(1, &[k.clone()], a.clone());
+        rib.handle_updates_afi(1, &[k.clone()], a);
+        assert_eq!(rib.dedup_suppressed, 1);
+        rib.handle_updates_afi(1, &[k.clone()], Arc::new(b));
+        let hist = &rib.items[&k].items[&1].items[&0];
+        assert_eq!(hist.items.len(), 2);
+        assert_eq!(rib.dedup_suppressed, 1);
+    }
 }
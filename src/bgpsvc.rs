@@ -1,13 +1,21 @@
+//! collection engine: owns RIB, drives configured BGP/BMP sessions and serves RIB API
+use crate::bestpath::select_best;
+use crate::bgpattrs::BgpAttrEntryView;
 use crate::bgppeer::*;
 use crate::bgprib::*;
 use crate::bmppeer::*;
+use crate::federation::FederationSource;
+use crate::peerstats::BgpPeerStats;
+use crate::processor::{RouteProcessor, SessionEvent};
 use crate::ribservice::*;
+use crate::syslog::SyslogSink;
+use crate::timestamp::Timestamp;
 use crate::*;
 use async_trait::async_trait;
 use hyper::{Body, Request, Response, StatusCode};
 use serde::ser::{SerializeMap, SerializeStruct};
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::hash::{Hash, Hasher};
 use std::net::{IpAddr, SocketAddr};
 use std::thread::JoinHandle;
@@ -21,9 +29,54 @@ use zettabgp::prelude::*;
 
 pub type BgpSessionId = u16;
 #[async_trait]
+/// receiver of updates from BGP/BMP session drivers, UPDATE may carry decoder's internal
+/// attribute (wireparse::ATTR_CONFED_PATH), wireparse::strip_internal removes it before encoding
 pub trait BgpUpdateHandler {
     async fn handle_update(&self, peerid: BgpSessionId, upd: BgpUpdateMessage);
+    /// update with router side timestamp, BMP per-peer header time
+    async fn handle_update_timed(
+        &self,
+        peerid: BgpSessionId,
+        upd: BgpUpdateMessage,
+        _peer_time: Option<Timestamp>,
+    ) where
+        Self: Sync,
+    {
+        self.handle_update(peerid, upd).await
+    }
     async fn register_session(&self, sess: Arc<BgpSessionDesc>) -> BgpSessionId;
+    /// liveness of session connections
+    fn record_liveness(&self, _evt: crate::liveness::LivenessEvent) {}
+    /// router timestamp of BMP message received from router
+    fn record_peer_clock(&self, _router: IpAddr, _peer_time: Timestamp) {}
+    /// whether raw UPDATE messages should be passed to record_raw
+    fn keep_raw(&self) -> bool {
+        false
+    }
+    fn record_raw(&self, _peerid: BgpSessionId, _upd: &BgpUpdateMessage, _raw: Vec<u8>) {}
+    /// BGP message type seen on the wire, sent or received
+    fn record_message(&self, _sent: bool, _msgtype: u8) {}
+    /// traffic counters of new BGP session or BMP feed of peer (monitored is peer of BMP
+    /// router), its connection counts messages there and drops them with end_traffic
+    fn start_traffic(
+        &self,
+        _proto: &'static str,
+        _peer: IpAddr,
+        _monitored: Option<IpAddr>,
+    ) -> Option<Arc<crate::traffic::SessionTraffic>> {
+        None
+    }
+    fn end_traffic(&self, _traffic: &crate::traffic::SessionTraffic) {}
+    /// received UPDATE body for attribute and NLRI statistics
+    fn record_update_body(&self, _body: &[u8], _addpath: bool) {}
+    /// drop malformed UPDATE messages instead of withdrawing prefixes they carry
+    fn strict_parsing(&self) -> bool {
+        true
+    }
+    /// AS to prepend to AS path of routes received in session, see localas option
+    fn set_localas_prepend(&self, _peerid: BgpSessionId, _asn: Option<u32>) {}
+    /// address families negotiated in OPEN exchange
+    fn record_afi_report(&self, _report: crate::afireport::AfiReport) {}
 }
 #[derive(Hash, PartialEq, Eq, Debug, Clone)]
 pub struct BgpPeerDesc {
@@ -161,7 +214,7 @@ impl BgpSessionStorage {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum BgpSessionState {
     Idle,
     Connect,
@@ -171,33 +224,221 @@ pub enum BgpSessionState {
     Established,
     BMP,
 }
+/// update waiting for RIB: session, message, collector receive time, router time
+/// and read instant for latency accounting
+pub type QueuedUpdate = (
+    BgpSessionId,
+    BgpUpdateMessage,
+    Timestamp,
+    Option<Timestamp>,
+    std::time::Instant,
+);
+/// collection engine, implements BgpUpdateHandler for all session drivers
 pub struct BgpSvr {
     pub config: Arc<SvcConfig>,
     pub cancellation: tokio_util::sync::CancellationToken,
     pub rib: BgpRIBts,
+    pub federation: Vec<Arc<FederationSource>>,
+    pub syslog: Option<Arc<SyslogSink>>,
     pub session_state: std::sync::Mutex<BgpSessionState>,
+    pub raw_updates: Option<std::sync::Mutex<crate::rawupdates::RawUpdateStore>>,
+    pub processors: Vec<Arc<dyn RouteProcessor>>,
+    /// RIB events route processors lost by lagging behind
+    pub processor_dropped: Arc<std::sync::atomic::AtomicU64>,
+    pub tokens: crate::tenancy::ApiTokens,
+    pub limits: crate::ratelimit::QueryLimits,
+    pub anonymizer: Option<Arc<crate::anonymize::Anonymizer>>,
+    pub audit: crate::audit::AuditLog,
+    pub clock_skew: crate::clockskew::ClockSkew,
+    pub liveness: crate::liveness::Liveness,
+    /// last integrity check result
+    pub integrity: std::sync::Mutex<crate::integrity::IntegrityReport>,
+    pub protocol: crate::protostats::ProtocolStats,
+    /// messages and bytes per BGP session and BMP feed
+    pub traffic: crate::traffic::TrafficStats,
+    /// eBGP sanity check counters, enforced ones treat routes as withdrawn
+    pub sanity: crate::sanity::SanityChecks,
+    /// local and peer AS of registered sessions, so updates do not wait for session storage lock
+    session_as: std::sync::RwLock<BTreeMap<BgpSessionId, (u32, u32)>>,
+    /// localas prepended to routes of sessions, after sanity checks
+    localas_prepend: std::sync::Mutex<BTreeMap<BgpSessionId, u32>>,
+    /// Web UI locale bundles
+    pub l10n: crate::l10n::Catalog,
+    /// knobs changed with /api/admin/settings
+    pub settings: crate::settings::RuntimeSettings,
+    /// polled /api/subscriptions change feeds
+    pub feeds: std::sync::Mutex<crate::changefeed::ChangeFeeds>,
+    /// named views of /api/views with their response caches
+    pub views: std::sync::Mutex<crate::views::Views>,
+    /// held from view change until views file is written, so files are written in order
+    views_store: tokio::sync::Mutex<()>,
+    /// recent session events for incident bundles
+    pub session_log: crate::incident::SessionLog,
+    /// administrative shutdown and clear of sessions
+    pub session_ctl: crate::sessionctl::SessionControl,
+    /// address families of last OPEN exchange
+    pub afi_report: std::sync::Mutex<Option<crate::afireport::AfiReport>>,
     sessions: Arc<RwLock<BgpSessionStorage>>,
-    upd: Option<Sender<Option<(BgpSessionId, BgpUpdateMessage)>>>,
+    upd: Option<Sender<Option<QueuedUpdate>>>,
     updater: Option<JoinHandle<()>>,
 }
 #[async_trait]
 impl BgpUpdateHandler for BgpSvr {
     async fn handle_update(&self, sid: BgpSessionId, upd: BgpUpdateMessage) {
-        match self.upd {
-            None => warn!("Skip update"),
-            Some(ref updch) => match updch.send(Some((sid, upd))).await {
-                Ok(_) => {}
-                Err(e) => warn!("Queued update error: {:?}", e),
-            },
+        self.handle_update_timed(sid, upd, None).await
+    }
+    async fn handle_update_timed(
+        &self,
+        sid: BgpSessionId,
+        upd: BgpUpdateMessage,
+        peer_time: Option<Timestamp>,
+    ) {
+        let read = std::time::Instant::now();
+        let received = Timestamp::now();
+        let asns = self.session_as.read().unwrap().get(&sid).cloned();
+        let mut upds = match asns {
+            Some((local_as, peer_as)) if self.sanity.reject(local_as, peer_as, &upd) => {
+                crate::sanity::treat_as_withdraw(upd)
+            }
+            _ => vec![upd],
         };
+        if let Some(asn) = self.localas_prepend.lock().unwrap().get(&sid) {
+            for upd in upds.iter_mut() {
+                prepend_aspath(upd, *asn);
+            }
+        }
+        let updch = match self.upd {
+            None => {
+                warn!("Skip update");
+                return;
+            }
+            Some(ref updch) => updch,
+        };
+        for upd in upds.into_iter() {
+            if let Err(e) = updch
+                .send(Some((sid, upd, received, peer_time, read)))
+                .await
+            {
+                warn!("Queued update error: {:?}", e);
+            }
+        }
     }
     async fn register_session(&self, sess: Arc<BgpSessionDesc>) -> BgpSessionId {
-        self.sessions.write().await.register_session(sess)
+        let sid = {
+            let mut sessions = self.sessions.write().await;
+            let sid = sessions.register_session(sess.clone());
+            // session storage keeps remote side as peer1
+            let peer_as = crate::wireparse::open_as(&sessions.ss_ids[&sid].peer1.bom);
+            let local_as = crate::wireparse::open_as(&sessions.ss_ids[&sid].peer2.bom);
+            self.rib.peer_as.write().unwrap().insert(sid, peer_as);
+            self.session_as
+                .write()
+                .unwrap()
+                .insert(sid, (local_as, peer_as));
+            sid
+        };
+        let evt = SessionEvent::Started(sid, sess);
+        self.session_log.record(&evt);
+        for p in self.processors.iter() {
+            p.on_session_event(&evt);
+        }
+        sid
+    }
+    fn record_liveness(&self, evt: crate::liveness::LivenessEvent) {
+        self.liveness.record(evt);
+    }
+    fn record_peer_clock(&self, router: IpAddr, peer_time: Timestamp) {
+        self.clock_skew.observe(router, peer_time, Timestamp::now());
+    }
+    fn keep_raw(&self) -> bool {
+        self.raw_updates.is_some()
+    }
+    fn record_raw(&self, sid: BgpSessionId, upd: &BgpUpdateMessage, raw: Vec<u8>) {
+        if let Some(store) = self.raw_updates.as_ref() {
+            store.lock().unwrap().record(sid, upd, raw);
+        }
+    }
+    fn record_message(&self, sent: bool, msgtype: u8) {
+        self.protocol.record_message(sent, msgtype);
+    }
+    fn start_traffic(
+        &self,
+        proto: &'static str,
+        peer: IpAddr,
+        monitored: Option<IpAddr>,
+    ) -> Option<Arc<crate::traffic::SessionTraffic>> {
+        Some(self.traffic.start(proto, peer, monitored))
+    }
+    fn end_traffic(&self, traffic: &crate::traffic::SessionTraffic) {
+        self.traffic.end(traffic);
+    }
+    fn record_update_body(&self, body: &[u8], addpath: bool) {
+        self.protocol.record_update(body, addpath);
+    }
+    fn strict_parsing(&self) -> bool {
+        self.config.strictparsing
+    }
+    fn record_afi_report(&self, report: crate::afireport::AfiReport) {
+        *self.afi_report.lock().unwrap() = Some(report);
+    }
+    fn set_localas_prepend(&self, sid: BgpSessionId, asn: Option<u32>) {
+        let mut prepend = self.localas_prepend.lock().unwrap();
+        match asn {
+            Some(asn) => prepend.insert(sid, asn),
+            None => prepend.remove(&sid),
+        };
+    }
+}
+/// TCP connection of active session with ttlsecurity, ebgp_multihop and localaddr applied
+pub async fn connect_active(
+    fpeer: &ProtoPeer,
+    peeraddr: SocketAddr,
+) -> io::Result<tokio::net::TcpStream> {
+    let socket = if peeraddr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    if let Some(ttl) = fpeer.ttlsecurity {
+        crate::gtsm::apply(&socket, peeraddr.is_ipv6(), ttl)?;
+    } else if let Some(hops) = fpeer.ebgp_multihop {
+        crate::gtsm::set_ttl(&socket, peeraddr.is_ipv6(), hops as u32)?;
+    }
+    if let Some(la) = fpeer.localaddr {
+        socket.bind(SocketAddr::new(la, 0))?;
+    }
+    socket.connect(peeraddr).await
+}
+/// puts AS in front of AS path like router with local-as does for eBGP routes
+fn prepend_aspath(upd: &mut BgpUpdateMessage, asn: u32) {
+    for a in upd.attrs.iter_mut() {
+        if let BgpAttrItem::ASPath(p) = a {
+            match p.value.first_mut() {
+                Some(BgpASitem::Seq(s)) if s.value.len() < 255 => {
+                    s.value.insert(0, BgpAS::new(asn))
+                }
+                _ => p.value.insert(
+                    0,
+                    BgpASitem::Seq(BgpASseq {
+                        value: vec![BgpAS::new(asn)],
+                    }),
+                ),
+            }
+        }
     }
 }
 impl BgpSvr {
-    pub fn new(cfg: Arc<SvcConfig>, cancel_token: tokio_util::sync::CancellationToken) -> BgpSvr {
-        let rib = match cfg.snapshot_file {
+    /// loads snapshot when configured, sessions are started by run(), fails when configured route processor can not be built
+    pub fn new(
+        cfg: Arc<SvcConfig>,
+        cancel_token: tokio_util::sync::CancellationToken,
+    ) -> Result<BgpSvr, ErrorConfig> {
+        crate::communities::register(&cfg.community_names);
+        let processors = crate::processor::build(&cfg)?;
+        let settings = crate::settings::RuntimeSettings::new(&cfg);
+        let effective = settings.effective(&cfg);
+        log::set_max_level(effective.log_filter());
+        let mut rib = match cfg.snapshot_file {
             None => BgpRIB::new(&cfg),
             Some(ref s) => match BgpRIB::load_snapshot(&cfg, s) {
                 Err(e) => {
@@ -207,19 +448,72 @@ impl BgpSvr {
                 Ok(o) => o,
             },
         };
-        BgpSvr {
+        rib.apply_settings(&effective);
+        let mut feeds = crate::changefeed::ChangeFeeds::new(
+            cfg.subscriptions_file.clone(),
+            cfg.subscription_retention,
+        );
+        for (id, asn) in feeds.unseeded() {
+            feeds.seed(id, asn, &rib.ipv4u);
+            feeds.seed(id, asn, &rib.ipv6u);
+        }
+        let rib = BgpRIBts::new(&cfg, rib);
+        rib.budget
+            .store(effective.query_budget, std::sync::atomic::Ordering::Relaxed);
+        Ok(BgpSvr {
             config: cfg.clone(),
             cancellation: cancel_token,
-            rib: BgpRIBts::new(&cfg, rib),
+            rib,
+            federation: FederationSource::from_config(&cfg),
+            syslog: cfg
+                .syslog
+                .as_ref()
+                .map(|t| SyslogSink::start(t.clone(), cfg.syslog_facility)),
             sessions: Arc::new(RwLock::new(BgpSessionStorage::new())),
             session_state: std::sync::Mutex::new(BgpSessionState::Idle),
+            raw_updates: if cfg.keep_raw_updates {
+                Some(std::sync::Mutex::new(Default::default()))
+            } else {
+                None
+            },
+            processors,
+            processor_dropped: Default::default(),
+            tokens: crate::tenancy::ApiTokens::new(&cfg.api_tokens),
+            limits: crate::ratelimit::QueryLimits::new(&effective.limits()),
+            anonymizer: cfg.anonymize.clone().map(Arc::new),
+            audit: crate::audit::AuditLog::new(cfg.audit_file.clone()),
+            clock_skew: crate::clockskew::ClockSkew::new(cfg.clock_skew_threshold),
+            liveness: Default::default(),
+            integrity: Default::default(),
+            protocol: Default::default(),
+            traffic: Default::default(),
+            sanity: crate::sanity::SanityChecks::new(&cfg.ebgp_checks),
+            session_as: Default::default(),
+            localas_prepend: Default::default(),
+            l10n: crate::l10n::Catalog::load(&cfg),
+            settings,
+            feeds: std::sync::Mutex::new(feeds),
+            views: std::sync::Mutex::new(crate::views::Views::new(
+                &cfg.views,
+                cfg.views_file.clone(),
+            )),
+            views_store: Default::default(),
+            session_log: Default::default(),
+            session_ctl: Default::default(),
+            afi_report: Default::default(),
             upd: None,
             updater: None,
-        }
+        })
+    }
+    /// adds route processor, must be called before run()
+    pub fn add_processor(&mut self, p: Arc<dyn RouteProcessor>) {
+        self.processors.push(p);
     }
+    /// stream of RIB changes
     pub async fn subscribe_bgp(&self) -> tokio::sync::broadcast::Receiver<BgpEvent> {
         self.rib.rib.read().await.events.subscribe()
     }
+    /// starts task applying queued updates to RIB, must be called before run()
     pub async fn start_updates(&mut self) {
         if self.updater.is_some() {
             return;
@@ -228,25 +522,106 @@ impl BgpSvr {
         self.upd = Some(tx);
         self.updater = Some(self.rib.run(rx));
     }
+    /// process health for systemd watchdog: update queue and RIB lock must not stay
+    /// blocked for two checks in a row; session state is not checked, sessions reconnect
+    /// on their own and restart would not help them
+    fn watchdog_check(&self) -> impl FnMut() -> Result<(), String> + Send + 'static {
+        let rib = self.rib.rib.clone();
+        let upd = self.upd.as_ref().map(|s| s.downgrade());
+        let (mut queue_full, mut rib_locked) = (false, false);
+        move || {
+            let full = match upd.as_ref() {
+                None => false,
+                Some(w) => match w.upgrade() {
+                    None => return Err("update queue closed".to_string()),
+                    Some(s) => s.capacity() == 0,
+                },
+            };
+            let locked = rib.try_read().is_err();
+            let ret = if full && queue_full {
+                Err("update queue is full, RIB updates do not progress".to_string())
+            } else if locked && rib_locked {
+                Err("RIB is locked for writing since previous check".to_string())
+            } else {
+                Ok(())
+            };
+            queue_full = full;
+            rib_locked = locked;
+            ret
+        }
+    }
     pub fn set_state(&self, new_state: BgpSessionState) {
+        if new_state == BgpSessionState::Established || new_state == BgpSessionState::BMP {
+            crate::systemd::ready(self.watchdog_check());
+        }
         let mut wg = self.session_state.lock().unwrap();
+        if *wg != new_state {
+            if let Some(sl) = self.syslog.as_ref() {
+                sl.send(
+                    crate::syslog::SEVERITY_NOTICE,
+                    "SESSION",
+                    format!("session state {:?} -> {:?}", *wg, new_state).as_str(),
+                );
+            }
+            let evt = SessionEvent::StateChanged(*wg, new_state);
+            self.session_ctl.state_changed();
+            self.session_log.record(&evt);
+            for p in self.processors.iter() {
+                p.on_session_event(&evt);
+            }
+        }
         *wg = new_state;
     }
     pub async fn run_listen(self: Arc<Self>, sockaddr: SocketAddr) -> io::Result<()> {
-        let socket = if sockaddr.is_ipv4() {
-            TcpSocket::new_v4()?
-        } else {
-            TcpSocket::new_v6()?
+        let ttlsecurity = self
+            .config
+            .peers
+            .iter()
+            .find(|p| p.mode == PeerMode::BgpPassive && p.protolisten == Some(sockaddr))
+            .and_then(|p| p.ttlsecurity);
+        let listener = match crate::systemd::take_listener(&sockaddr) {
+            Some(l) => {
+                info!("Listening on {} (socket activated)", sockaddr);
+                if let Some(ttl) = ttlsecurity {
+                    crate::gtsm::apply(&l, sockaddr.is_ipv6(), ttl)?;
+                }
+                l.set_nonblocking(true)?;
+                tokio::net::TcpListener::from_std(l)?
+            }
+            None => {
+                let socket = if sockaddr.is_ipv4() {
+                    TcpSocket::new_v4()?
+                } else {
+                    TcpSocket::new_v6()?
+                };
+                if let Some(ttl) = ttlsecurity {
+                    crate::gtsm::apply(&socket, sockaddr.is_ipv6(), ttl)?;
+                    info!("TTL security on {}, minimum TTL {}", sockaddr, ttl);
+                }
+                socket.bind(sockaddr)?;
+                info!("Listening on {}", sockaddr);
+                socket.listen(1)?
+            }
         };
-        socket.bind(sockaddr)?;
-        info!("Listening on {}", sockaddr);
         self.set_state(BgpSessionState::Idle);
-        let listener = socket.listen(1)?;
+        let secs = |n: u64| match n {
+            0 => None,
+            n => Some(std::time::Duration::from_secs(n)),
+        };
+        let idle_timeout = secs(self.config.passive_idle_timeout);
+        let bmp_idle_timeout = secs(self.config.bmp_idle_timeout);
         loop {
             let client = match listener.accept().await {
                 Ok(acc) => acc,
                 Err(e) => return Err(e),
             };
+            if self.session_ctl.is_down(client.1.ip()) {
+                info!(
+                    "Refused connection from {}, session is administratively down",
+                    client.1
+                );
+                continue;
+            }
             self.set_state(BgpSessionState::Connect);
             info!("Incoming connected from {}", client.1);
             let fpeer: Arc<ProtoPeer> = match self.config.peers.iter().find(|p| {
@@ -268,11 +643,13 @@ impl BgpSvr {
                     continue;
                 }
             };
+            let ctl = self.session_ctl.peer(client.1.ip());
             match fpeer.mode {
                 PeerMode::BmpPassive => {
                     self.set_state(BgpSessionState::BMP);
                     let mut peer = BmpPeer::new(client.0, fpeer, &*self);
-                    peer.lifecycle(self.cancellation.clone()).await;
+                    peer.set_idle_timeout(bmp_idle_timeout);
+                    peer.lifecycle(ctl.session_token(&self.cancellation)).await;
                     self.set_state(BgpSessionState::Idle);
                     peer.close().await;
                 }
@@ -280,7 +657,7 @@ impl BgpSvr {
                     let mut peer = BgpPeer::new(
                         BgpSessionParams::new(
                             fpeer.bgppeeras,
-                            180,
+                            crate::config::HOLD_TIME,
                             if client.1.is_ipv4() {
                                 BgpTransportMode::IPv4
                             } else {
@@ -292,15 +669,31 @@ impl BgpSvr {
                         client.0,
                         &*self,
                     );
+                    peer.localas = fpeer.localas;
+                    peer.localas_no_prepend = fpeer.localas_no_prepend;
+                    peer.refresh = Some(ctl.session_refresh());
+                    peer.quirks = fpeer.vendor_quirks;
                     let mut scs: bool = true;
                     self.set_state(BgpSessionState::OpenSent);
-                    if let Err(e) = peer.start_passive().await {
+                    let opened = match idle_timeout {
+                        None => peer.start_passive().await,
+                        Some(t) => match timeout(t, peer.start_passive()).await {
+                            Ok(r) => r,
+                            Err(_) => {
+                                self.record_liveness(crate::liveness::LivenessEvent::IdleTimeout);
+                                Err(BgpError::static_str("no OPEN received"))
+                            }
+                        },
+                    };
+                    if let Err(e) = opened {
                         error!("failed to create BGP peer; err = {:?}", e);
                         scs = false;
                     }
                     if scs {
+                        ctl.set_route_refresh(peer.route_refresh);
                         self.set_state(BgpSessionState::Established);
-                        peer.lifecycle(self.cancellation.clone()).await;
+                        peer.lifecycle(ctl.session_token(&self.cancellation)).await;
+                        self.send_cease(&ctl, &mut peer).await;
                         info!("Session done {}", client.1);
                     };
                     self.set_state(BgpSessionState::Idle);
@@ -311,6 +704,18 @@ impl BgpSvr {
             tokio::time::sleep(std::time::Duration::from_millis(100)).await;
         }
     }
+    /// Cease NOTIFICATION when session was shut down or cleared by operator
+    async fn send_cease(
+        &self,
+        ctl: &crate::sessionctl::PeerControl,
+        peer: &mut BgpPeer<'_, BgpSvr>,
+    ) {
+        if let Some(subcode) = ctl.cease_subcode() {
+            if let Err(e) = peer.send_cease(subcode).await {
+                warn!("Cease sending error: {:?}", e);
+            }
+        }
+    }
     pub async fn run_peer_active(self: Arc<Self>, fpeer: Arc<ProtoPeer>) -> io::Result<()> {
         let peeraddr = match fpeer.peer {
             None => {
@@ -321,9 +726,11 @@ impl BgpSvr {
             }
             Some(l) => l,
         };
+        let ctl = self.session_ctl.peer(peeraddr.ip());
+        ctl.wait_enabled().await;
         self.set_state(BgpSessionState::Connect);
         info!("Connecting to {}", peeraddr);
-        let peertcp = match tokio::net::TcpStream::connect(peeraddr).await {
+        let peertcp = match connect_active(&fpeer, peeraddr).await {
             Err(e) => {
                 return Err(e);
             }
@@ -334,11 +741,16 @@ impl BgpSvr {
             PeerMode::BmpActive => {
                 let mut peer = BmpPeer::new(peertcp, fpeer, &*self);
                 self.set_state(BgpSessionState::BMP);
-                peer.lifecycle(self.cancellation.clone()).await;
+                peer.lifecycle(ctl.session_token(&self.cancellation)).await;
                 peer.close().await;
             }
             PeerMode::BgpActive => {
                 let mut peer = BgpPeer::new(fpeer.get_session_params(), peertcp, &*self);
+                peer.ext_nexthop = fpeer.ext_nexthop;
+                peer.localas = fpeer.localas;
+                peer.localas_no_prepend = fpeer.localas_no_prepend;
+                peer.refresh = Some(ctl.session_refresh());
+                peer.quirks = fpeer.vendor_quirks;
                 let mut scs: bool = true;
                 self.set_state(BgpSessionState::OpenSent);
                 if let Err(e) = peer.start_active().await {
@@ -348,7 +760,10 @@ impl BgpSvr {
                 }
                 self.set_state(BgpSessionState::OpenConfirm);
                 if scs {
-                    peer.lifecycle(self.cancellation.clone()).await;
+                    ctl.set_route_refresh(peer.route_refresh);
+                    self.set_state(BgpSessionState::Established);
+                    peer.lifecycle(ctl.session_token(&self.cancellation)).await;
+                    self.send_cease(&ctl, &mut peer).await;
                     info!("Session done {}", peeraddr);
                 };
                 peer.close().await;
@@ -358,7 +773,120 @@ impl BgpSvr {
         self.set_state(BgpSessionState::Idle);
         Ok(())
     }
+    /// runs configured sessions and background tasks until cancel token fires
     pub async fn run(self: Arc<Self>) {
+        if !self.processors.is_empty() {
+            let canceltok = self.cancellation.clone();
+            let processors = self.processors.clone();
+            let dropped = self.processor_dropped.clone();
+            let rcv = self.subscribe_bgp().await;
+            tokio::spawn(async move {
+                select! {
+                    _ = canceltok.cancelled() => {}
+                    _ = crate::processor::run_dispatch(processors, rcv, dropped) => {}
+                }
+            });
+        }
+        {
+            let canceltok = self.cancellation.clone();
+            let svr = self.clone();
+            let mut rcv = self.subscribe_bgp().await;
+            tokio::spawn(async move {
+                loop {
+                    let evt = select! {
+                        _ = canceltok.cancelled() => break,
+                        r = rcv.recv() => r,
+                    };
+                    match evt {
+                        Ok(e) => svr.feeds.lock().unwrap().on_event(&e),
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("Change feeds skipped {} events", n);
+                        }
+                        Err(_) => break,
+                    }
+                }
+            });
+        }
+        if let (Some(sl), Some(watch)) = (self.syslog.as_ref(), self.config.syslog_watch.as_ref()) {
+            let canceltok = self.cancellation.clone();
+            let sl = sl.clone();
+            let watch = crate::ribfilter::RouteFilter::fromstr(watch.as_str());
+            let rcv = self.subscribe_bgp().await;
+            tokio::spawn(async move {
+                select! {
+                    _ = canceltok.cancelled() => {}
+                    _ = sl.run_watch(watch, rcv) => {}
+                }
+            });
+        }
+        if let (Some(smtp), Some(alerts)) =
+            (self.config.smtp.as_ref(), self.config.smtp_alerts.as_ref())
+        {
+            let canceltok = self.cancellation.clone();
+            let (smtp, alerts) = (smtp.clone(), alerts.clone());
+            let rcv = self.subscribe_bgp().await;
+            tokio::spawn(async move {
+                select! {
+                    _ = canceltok.cancelled() => {}
+                    _ = crate::smtp::run_alerts(smtp, alerts, rcv) => {}
+                }
+            });
+        }
+        if let Some(path) = self.config.replay_file.as_ref() {
+            let canceltok = self.cancellation.clone();
+            let (slf, path) = (self.clone(), path.clone());
+            let (speed, original) = (self.config.replay_speed, self.config.replay_original_time);
+            tokio::spawn(async move {
+                select! {
+                    _ = canceltok.cancelled() => {}
+                    r = crate::replay::run_replay(slf, path, speed, original) => {
+                        if let Err(e) = r {
+                            warn!("Replay error: {}", e);
+                        }
+                    }
+                }
+            });
+        }
+        if let Some(path) = self.config.pcap_file.as_ref() {
+            let canceltok = self.cancellation.clone();
+            let (slf, path) = (self.clone(), path.clone());
+            let original = self.config.replay_original_time;
+            tokio::spawn(async move {
+                select! {
+                    _ = canceltok.cancelled() => {}
+                    r = crate::pcap::run_pcap(slf, path, original) => {
+                        if let Err(e) = r {
+                            warn!("Pcap error: {}", e);
+                        }
+                    }
+                }
+            });
+        }
+        if let Some(url) = self.config.preload_url.as_ref() {
+            let canceltok = self.cancellation.clone();
+            let (slf, url) = (self.clone(), url.clone());
+            tokio::spawn(async move {
+                select! {
+                    _ = canceltok.cancelled() => {}
+                    r = crate::preload::run_preload(slf, url.clone()) => {
+                        if let Err(e) = r {
+                            warn!("Preload from {} failed: {}", url, e);
+                        }
+                    }
+                }
+            });
+        }
+        for src in self.federation.iter() {
+            let canceltok = self.cancellation.clone();
+            let cfg = self.config.clone();
+            let src = src.clone();
+            tokio::spawn(async move {
+                select! {
+                    _ = canceltok.cancelled() => {}
+                    _ = src.run(cfg) => {}
+                }
+            });
+        }
         let mut lstns: BTreeSet<SocketAddr> = BTreeSet::new();
         for p in self.config.peers.iter() {
             if p.mode == PeerMode::BgpPassive || p.mode == PeerMode::BmpPassive {
@@ -408,6 +936,7 @@ impl BgpSvr {
             }
         }
     }
+    /// saves snapshot when configured
     pub async fn shutdown(&self) {
         self.rib.shutdown().await
     }
@@ -433,6 +962,91 @@ impl BgpSvr {
             .header("Content-type", "text/plain")
             .body(state.into())
     }
+    /// session control of peer URL parameter, which may be left out with one peer
+    fn select_peer(
+        &self,
+        paramshm: &HashMap<String, String>,
+    ) -> Result<Arc<crate::sessionctl::PeerControl>, String> {
+        let addr = match paramshm.get("peer") {
+            None => None,
+            Some(p) => Some(
+                p.parse::<IpAddr>()
+                    .map_err(|_| format!("invalid peer address {}", p))?,
+            ),
+        };
+        self.session_ctl.select(addr)
+    }
+    fn session_fsm(&self) -> crate::sessionctl::SessionFsm {
+        let state = *self.session_state.lock().unwrap();
+        self.session_ctl.fsm(state)
+    }
+    /// FSM state, time of last transition and administrative status
+    pub async fn say_session(&self) -> Result<Response<Body>, hyper::http::Error> {
+        json_response(&self.session_fsm())
+    }
+    /// Prometheus text exposition served at /metrics
+    pub fn say_metrics(&self) -> Result<Response<Body>, hyper::http::Error> {
+        let mut out = String::new();
+        self.rib.latency.write_prometheus(&mut out);
+        self.traffic.write_prometheus(&mut out);
+        {
+            use std::fmt::Write;
+            let name = "bgpexplorer_processor_dropped_events_total";
+            let _ = writeln!(
+                out,
+                "# HELP {} RIB events lost by route processors lagging behind",
+                name
+            );
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(
+                out,
+                "{} {}",
+                name,
+                self.processor_dropped
+                    .load(std::sync::atomic::Ordering::Relaxed)
+            );
+        }
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-type", "text/plain; version=0.0.4")
+            .body(out.into())
+    }
+    pub async fn say_status(&self) -> Result<Response<Body>, hyper::http::Error> {
+        let afi = self.afi_report.lock().unwrap().clone();
+        let hint = afi.as_ref().and_then(|r| r.hint());
+        let version = self.rib.table_version().await;
+        json_response(&serde_json::json!({
+            "version": version,
+            "afi": afi,
+            "afi_hint": hint,
+            "state": format!("{:?}", self.session_state.lock().unwrap()),
+            "session": self.session_fsm(),
+            "clock_skew": self.clock_skew.routers(),
+            "liveness": self.liveness.stats(),
+            "integrity": self.integrity.lock().unwrap().clone(),
+            "sanity": self.sanity.stats(),
+            "limits": self.limits.report(),
+            "processor_dropped": self.processor_dropped.load(std::sync::atomic::Ordering::Relaxed),
+        }))
+    }
+    /// native snapshot for preload_url of other instances
+    pub async fn say_snapshot(&self) -> Result<Response<Body>, hyper::http::Error> {
+        let rib = match timeout(self.rib.locktimeout, self.rib.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let mut buf = Vec::new();
+        if let Err(e) = rib.write_snapshot(&mut buf) {
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header("Content-type", "text/plain")
+                .body(format!("Snapshot error: {:?}", e).into());
+        }
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-type", crate::preload::SNAPSHOT_CONTENT_TYPE)
+            .body(buf.into())
+    }
     pub async fn say_sessions(&self) -> Result<Response<Body>, hyper::http::Error> {
         let sess = match timeout(std::time::Duration::new(5, 0), self.sessions.read()).await {
             Ok(r) => r,
@@ -454,9 +1068,117 @@ impl BgpSvr {
                 .body(format!("Error: {:?}", e).into()),
         }
     }
+    /// registered sessions and session events between from and to
+    pub async fn incident_sessions(
+        &self,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Option<serde_json::Value> {
+        let sess = timeout(std::time::Duration::new(5, 0), self.sessions.read())
+            .await
+            .ok()?;
+        Some(serde_json::json!({
+            "sessions": &*sess,
+            "events": self.session_log.between(from, to),
+        }))
+    }
+    pub async fn say_peer_stats(&self) -> Result<Response<Body>, hyper::http::Error> {
+        let sess = match timeout(std::time::Duration::new(5, 0), self.sessions.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let rib = match timeout(self.rib.locktimeout, self.rib.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let rsp: BTreeMap<BgpSessionId, PeerStatsItem> = rib
+            .peer_stats
+            .iter()
+            .map(|(k, v)| {
+                (
+                    *k,
+                    PeerStatsItem {
+                        session: sess.ss_ids.get(k).map(|x| x.as_ref()),
+                        stats: v,
+                    },
+                )
+            })
+            .collect();
+        json_response(&rsp)
+    }
+    /// SNMP variables for current sessions and RIB state
+    pub async fn snmp_mib(&self, base: &[u32]) -> crate::snmp::SnmpMib {
+        let sessions: Vec<(BgpSessionId, Arc<BgpSessionDesc>)> = self
+            .sessions
+            .read()
+            .await
+            .ss_ids
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
+        let localas = self
+            .config
+            .peers
+            .iter()
+            .map(|p| p.localas.unwrap_or(p.bgppeeras))
+            .find(|a| *a != 0)
+            .unwrap_or(0);
+        let rib = self.rib.rib.read().await;
+        let state = self.session_state.lock().unwrap();
+        crate::snmp::build_mib(base, localas, &state, &sessions, &rib)
+    }
+    /// hex encoded raw UPDATE messages last seen for prefix from each session
+    pub fn say_raw_update(&self, prefix: &str) -> Result<Response<Body>, hyper::http::Error> {
+        let store = match self.raw_updates.as_ref() {
+            Some(s) => s,
+            None => {
+                return Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .header("Content-type", "text/plain")
+                    .body("Raw updates are not kept, set keep_raw_updates=true".into());
+            }
+        };
+        let prefix = match crate::rawupdates::normalize_prefix(prefix) {
+            Some(p) => p,
+            None => return bad_request("Invalid prefix"),
+        };
+        let updates = store.lock().unwrap().get(prefix.as_str());
+        json_response(&serde_json::json!({"prefix": prefix, "updates": updates}))
+    }
+    /// locale bundle: requested language, negotiated by Accept-Language without it;
+    /// format=po returns gettext template for translators
+    fn say_l10n(
+        &self,
+        lang: Option<&str>,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let lang = match lang.filter(|l| !l.is_empty()) {
+            Some(l) if self.l10n.has(l) || l == "template" => l,
+            Some(_) => return Ok(not_found()),
+            None => self.l10n.negotiate(
+                req.headers()
+                    .get(hyper::header::ACCEPT_LANGUAGE)
+                    .and_then(|v| v.to_str().ok()),
+            ),
+        };
+        let params = get_url_params(req);
+        if get_url_param::<String>(&params, "format").as_deref() == Some("po") {
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-type", "text/x-gettext-translation; charset=utf-8")
+                .body(self.l10n.to_po(lang).into());
+        }
+        json_response(&serde_json::json!({
+            "language": lang,
+            "default": self.l10n.default,
+            "languages": self.l10n.languages(),
+            "strings": self.l10n.bundle(lang),
+        }))
+    }
     pub async fn handle_query(
         &self,
         req: &Request<Body>,
+        scope: Option<Arc<crate::tenancy::ApiScope>>,
     ) -> Result<Response<Body>, hyper::http::Error> {
         let requri = req.uri().path();
         let urlparts: Vec<&str> = requri.split('/').collect();
@@ -466,23 +1188,627 @@ impl BgpSvr {
         if urlparts[1] != "api" {
             return Ok(not_found());
         }
+        // scoped tokens only reach queries filtered by scope
+        if scope.is_some() && !matches!(urlparts[2], "json" | "range" | "subtree" | "ui" | "views")
+        {
+            return Ok(access_denied(StatusCode::FORBIDDEN));
+        }
         match urlparts[2] {
             "statistics" => self.rib.say_statistics().await,
+            "filtered" => self.rib.say_filtered(req).await,
             "sessions" => self.say_sessions().await,
             "state" => self.say_state().await,
+            "session" => self.say_session().await,
+            "status" => self.say_status().await,
+            "snapshot" => self.say_snapshot().await,
+            "ui" => match urlparts.get(3) {
+                Some(&"l10n") => self.say_l10n(urlparts.get(4).copied(), req),
+                Some(&"config") => json_response(&self.config.ui),
+                _ => Ok(not_found()),
+            },
+            "stats" => match urlparts.get(3) {
+                Some(&"peers") => self.say_peer_stats().await,
+                Some(&"prefixlen") => self.rib.say_prefixlen().await,
+                Some(&"addrclass") => self.rib.say_addrclass().await,
+                Some(&"bursts") => self.rib.say_withdraw_bursts().await,
+                Some(&"protocol") => json_response(&self.protocol.report()),
+                Some(&"traffic") => json_response(&self.traffic.report()),
+                _ => Ok(not_found()),
+            },
             "json" => {
                 if urlparts.len() < 4 {
                     Ok(not_found())
                 } else {
-                    self.rib.say_jsonrib(urlparts[3], req).await
+                    self.rib.say_jsonrib(urlparts[3], req, scope).await
+                }
+            }
+            "nexthop" => {
+                if urlparts.len() < 4 {
+                    Ok(not_found())
+                } else {
+                    self.rib.say_nexthop(urlparts[3], req).await
+                }
+            }
+            "nexthops" => self.rib.say_nexthops(req).await,
+            "views" => self.say_views(req, urlparts.get(3).copied(), scope).await,
+            "changes" => self.rib.say_changes(req).await,
+            "prefix" => match urlparts.last() {
+                Some(&"raw") if urlparts.len() > 4 => {
+                    self.say_raw_update(&urlparts[3..urlparts.len() - 1].join("/"))
+                }
+                Some(&"history") if urlparts.len() > 4 => {
+                    self.rib
+                        .say_prefix_history(&urlparts[3..urlparts.len() - 1].join("/"), req)
+                        .await
+                }
+                _ => Ok(not_found()),
+            },
+            "trace" => json_response(&crate::trace::dump()),
+            "graph" => self.rib.say_graph(req).await,
+            "blackholes" => self.rib.say_blackholes().await,
+            "clusterpaths" => self.rib.say_clusterpaths(req).await,
+            "range" => self.rib.say_range(req, scope).await,
+            "subtree" => {
+                if urlparts.len() < 4 {
+                    Ok(not_found())
+                } else {
+                    self.rib
+                        .say_subtree(&urlparts[3..].join("/"), req, scope)
+                        .await
+                }
+            }
+            "timeline" => {
+                if urlparts.len() < 4 {
+                    Ok(not_found())
+                } else {
+                    self.rib.say_timeline(urlparts[3], req).await
+                }
+            }
+            "federated" => {
+                if urlparts.len() < 4 {
+                    Ok(not_found())
+                } else {
+                    self.rib
+                        .say_federated(
+                            urlparts[3],
+                            req,
+                            &self.config.federation_name,
+                            &self.federation,
+                        )
+                        .await
                 }
             }
             _ => Ok(not_found()),
         }
     }
+    /// POST /api/admin/<action> and GET /api/audit, need token with admin = true
+    async fn say_admin(
+        &self,
+        req: &Request<Body>,
+        action: &str,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let scope = self.tokens.identify(req);
+        let allowed = scope.as_ref().map(|s| s.admin).unwrap_or(false);
+        let paramshm = get_url_params(req);
+        // session/shutdown, session/enable and session/clear
+        let action = match action {
+            "session" => format!(
+                "session/{}",
+                req.uri().path().split('/').nth(4).unwrap_or("")
+            ),
+            a => a.to_string(),
+        };
+        let action = action.as_str();
+        if action == "audit" || action == "memory" {
+            if !allowed {
+                return Ok(access_denied(match scope {
+                    None => StatusCode::UNAUTHORIZED,
+                    Some(_) => StatusCode::FORBIDDEN,
+                }));
+            }
+            if action == "memory" {
+                return json_response(&crate::allocator::stats());
+            }
+            let limit = get_url_param::<usize>(&paramshm, "limit").unwrap_or(100);
+            return json_response(&self.audit.recent(limit));
+        }
+        if req.method() != hyper::Method::POST {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("Allow", "POST")
+                .body(Body::empty());
+        }
+        let outcome: Result<(), String> = if !allowed {
+            Err("denied".to_string())
+        } else {
+            match action {
+                "flush" => match timeout(self.rib.locktimeout, self.rib.rib.write()).await {
+                    Ok(mut rib) => {
+                        rib.flush();
+                        Ok(())
+                    }
+                    Err(_) => Err("RIB lock timed out".to_string()),
+                },
+                "snapshot" if self.config.snapshot_file.is_none() => {
+                    Err("snapshot_file is not configured".to_string())
+                }
+                "snapshot" => match timeout(self.rib.locktimeout, self.rib.rib.read()).await {
+                    Ok(rib) => rib.store_snapshot().map_err(|e| e.to_string()),
+                    Err(_) => Err("RIB lock timed out".to_string()),
+                },
+                "session/shutdown" => self.select_peer(&paramshm).map(|ctl| ctl.shutdown()),
+                "session/enable" => self.select_peer(&paramshm).map(|ctl| ctl.enable()),
+                "session/clear" => match paramshm
+                    .get("mode")
+                    .map(|m| m.parse::<crate::sessionctl::ClearMode>())
+                    .unwrap_or(Ok(crate::sessionctl::ClearMode::Hard))
+                {
+                    Ok(mode) => self.select_peer(&paramshm).and_then(|ctl| {
+                        let state = *self.session_state.lock().unwrap();
+                        ctl.clear(mode, state)
+                    }),
+                    Err(e) => Err(e),
+                },
+                _ => return Ok(not_found()),
+            }
+        };
+        self.audit.record(crate::audit::AuditEntry {
+            time: Timestamp::now(),
+            token: scope
+                .as_ref()
+                .map(|s| s.name.clone())
+                .unwrap_or_else(|| "-".to_string()),
+            action: action.to_string(),
+            params: paramshm.into_iter().filter(|(k, _)| k != "token").collect(),
+            outcome: match outcome {
+                Ok(_) => "ok".to_string(),
+                Err(ref e) => e.clone(),
+            },
+        });
+        match outcome {
+            Ok(_) => json_response(&serde_json::json!({"action": action, "outcome": "ok"})),
+            Err(_) if !allowed => Ok(access_denied(match scope {
+                None => StatusCode::UNAUTHORIZED,
+                Some(_) => StatusCode::FORBIDDEN,
+            })),
+            Err(e) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header("Content-type", "text/plain")
+                .body(e.into()),
+        }
+    }
+    /// GET lists subscriptions, POST with {"origin":AS} or {"prefixes":[..]} creates one,
+    /// GET <id>/changes polls its change feed and DELETE <id> drops it; subscription
+    /// belongs to token which created it, admin token can reach all of them
+    pub async fn say_subscriptions(
+        &self,
+        req: Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let urlparts: Vec<String> = req.uri().path().split('/').map(String::from).collect();
+        let id = match urlparts.get(3).filter(|s| !s.is_empty()) {
+            None => None,
+            Some(s) => match s.parse::<u64>() {
+                Ok(n) => Some(n),
+                Err(_) => return Ok(not_found()),
+            },
+        };
+        let scope = self.tokens.identify(&req);
+        let owner = scope.as_ref().map(|s| s.name.clone());
+        let admin = self.tokens.is_empty() || scope.as_ref().map(|s| s.admin).unwrap_or(false);
+        // other token's subscription is reported as missing
+        if let Some(id) = id {
+            match self.feeds.lock().unwrap().owner(id) {
+                Some(o) if admin || o == owner.as_deref() => {}
+                _ => return Ok(not_found()),
+            }
+        }
+        let method = req.method().clone();
+        let action = format!("subscriptions/{}", method.as_str().to_lowercase());
+        let mut params = BTreeMap::new();
+        let outcome = match (method, id, urlparts.get(4).map(|s| s.as_str())) {
+            (hyper::Method::GET, None, _) => {
+                let feeds = self.feeds.lock().unwrap();
+                return json_response(&feeds.list(if admin { None } else { owner.as_deref() }));
+            }
+            (hyper::Method::GET, Some(id), Some("changes")) => {
+                let paramshm = get_url_params(&req);
+                let since = get_url_param::<u64>(&paramshm, "since");
+                let limit = get_url_param::<usize>(&paramshm, "limit").unwrap_or(10000);
+                return match self.feeds.lock().unwrap().poll(id, since, limit) {
+                    Some(p) => json_response(&p),
+                    None => Ok(not_found()),
+                };
+            }
+            (hyper::Method::POST, None, _) => {
+                let spec: crate::changefeed::SubscriptionSpec =
+                    match read_body(req.into_body(), MAX_API_BODY)
+                        .await
+                        .and_then(|b| serde_json::from_slice(&b).map_err(|e| e.to_string()))
+                    {
+                        Ok(s) => s,
+                        Err(e) => return bad_request(format!("Invalid body: {}", e).as_str()),
+                    };
+                if let Some(asn) = spec.origin {
+                    params.insert("origin".to_string(), asn.to_string());
+                }
+                if !spec.prefixes.is_empty() {
+                    params.insert("prefixes".to_string(), spec.prefixes.join(","));
+                }
+                let origin = spec.origin;
+                let cap = self.config.subscriptions_per_token;
+                let created = {
+                    let mut feeds = self.feeds.lock().unwrap();
+                    if cap > 0 && feeds.owned_by(owner.as_deref()) >= cap {
+                        Err((
+                            StatusCode::FORBIDDEN,
+                            format!("At most {} subscriptions per token", cap),
+                        ))
+                    } else {
+                        feeds
+                            .create(spec, owner.clone())
+                            .map_err(|e| (StatusCode::BAD_REQUEST, e))
+                    }
+                };
+                match (created, origin) {
+                    (Ok(id), Some(asn)) => {
+                        params.insert("id".to_string(), id.to_string());
+                        // routes of origin are walked on blocking pool, not under feeds lock
+                        let rib =
+                            match timeout(self.rib.locktimeout, self.rib.rib.clone().read_owned())
+                                .await
+                            {
+                                Ok(r) => r,
+                                Err(_) => {
+                                    let _ = self.feeds.lock().unwrap().remove(id);
+                                    return timed_out();
+                                }
+                            };
+                        let routes = tokio::task::spawn_blocking(move || {
+                            let mut v = crate::changefeed::originated(asn, &rib.ipv4u);
+                            v.extend(crate::changefeed::originated(asn, &rib.ipv6u));
+                            v
+                        })
+                        .await
+                        .unwrap_or_default();
+                        self.feeds.lock().unwrap().track(id, routes);
+                        Ok(id)
+                    }
+                    (Ok(id), None) => {
+                        params.insert("id".to_string(), id.to_string());
+                        Ok(id)
+                    }
+                    (Err(e), _) => Err(e),
+                }
+            }
+            (hyper::Method::DELETE, Some(id), None) => {
+                params.insert("id".to_string(), id.to_string());
+                match self.feeds.lock().unwrap().remove(id) {
+                    Ok(true) => Ok(id),
+                    Ok(false) => return Ok(not_found()),
+                    Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e)),
+                }
+            }
+            _ => return Ok(not_found()),
+        };
+        self.audit.record(crate::audit::AuditEntry {
+            time: Timestamp::now(),
+            token: owner.unwrap_or_else(|| "-".to_string()),
+            action,
+            params,
+            outcome: match outcome {
+                Ok(_) => "ok".to_string(),
+                Err((_, ref e)) => e.clone(),
+            },
+        });
+        match outcome {
+            Ok(id) => json_response(&serde_json::json!({ "id": id })),
+            Err((status, e)) => Response::builder()
+                .status(status)
+                .header("Content-type", "text/plain")
+                .body(e.into()),
+        }
+    }
+    /// GET lists views or serves one as /api/json over view's RIB, cached for view's
+    /// cache time
+    async fn say_views(
+        &self,
+        req: &Request<Body>,
+        name: Option<&str>,
+        scope: Option<Arc<crate::tenancy::ApiScope>>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let view = match name.filter(|s| !s.is_empty()) {
+            None => return json_response(&self.views.lock().unwrap().list(scope.as_deref())),
+            Some(n) => match self.views.lock().unwrap().get(n) {
+                Some(v) => v,
+                None => return Ok(not_found()),
+            },
+        };
+        let uri = view.json_uri(req.uri().query());
+        // same view returns different routes for different scopes
+        let key = match scope {
+            None => uri.clone(),
+            Some(ref s) => format!("{}#{}", uri, s.name),
+        };
+        let now = std::time::Instant::now();
+        let cached = self.views.lock().unwrap().cached(&view.name, &key, now);
+        let body = match cached {
+            Some(b) => b,
+            None => {
+                let mut vreq = Request::builder().uri(uri).body(Body::empty())?;
+                *vreq.headers_mut() = req.headers().clone();
+                let rsp = self.rib.say_jsonrib(&view.rib, &vreq, scope).await?;
+                if rsp.status() != StatusCode::OK {
+                    return Ok(rsp);
+                }
+                let body = match hyper::body::to_bytes(rsp.into_body()).await {
+                    Ok(b) => b,
+                    Err(e) => {
+                        return Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .header("Content-type", "text/plain")
+                            .body(e.to_string().into())
+                    }
+                };
+                self.views
+                    .lock()
+                    .unwrap()
+                    .store_cached(&view.name, &key, body.clone(), now);
+                body
+            }
+        };
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-type", "text/json")
+            .body(body.into())
+    }
+    /// PUT /api/views/<name> with view JSON creates or replaces view, DELETE drops it;
+    /// admin token is required, views from config can not be changed
+    pub async fn say_view_change(
+        &self,
+        req: Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let scope = self.tokens.identify(&req);
+        if !scope.as_ref().map(|s| s.admin).unwrap_or(false) {
+            return Ok(access_denied(match scope {
+                None => StatusCode::UNAUTHORIZED,
+                Some(_) => StatusCode::FORBIDDEN,
+            }));
+        }
+        let name = match req.uri().path().split('/').nth(3) {
+            Some(n) if !n.is_empty() => n.to_string(),
+            _ => return Ok(not_found()),
+        };
+        let method = req.method().clone();
+        let mut params = BTreeMap::new();
+        params.insert("name".to_string(), name.clone());
+        let _store = self.views_store.lock().await;
+        let prev = self.views.lock().unwrap().get(&name);
+        let outcome = if method == hyper::Method::DELETE {
+            self.views.lock().unwrap().remove(&name)
+        } else {
+            match read_body(req.into_body(), MAX_API_BODY)
+                .await
+                .and_then(|b| {
+                    serde_json::from_slice::<crate::views::ViewConfig>(&b)
+                        .map_err(|e| e.to_string())
+                }) {
+                Err(e) => Err(format!("Invalid body: {}", e)),
+                Ok(mut v) => {
+                    v.name = name.clone();
+                    params.insert("rib".to_string(), v.rib.clone());
+                    params.insert("q".to_string(), v.q.clone());
+                    params.insert("filter".to_string(), v.filter.clone());
+                    self.views.lock().unwrap().put(v)
+                }
+            }
+        };
+        // views file is written out of views lock, change is undone when it fails
+        let outcome = match outcome {
+            Ok(changed) => {
+                let stored = self.views.lock().unwrap().stored();
+                let written = match stored {
+                    Ok(None) => Ok(()),
+                    Ok(Some((f, data))) => tokio::task::spawn_blocking(move || {
+                        crate::views::Views::write_file(&f, &data)
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string())),
+                    Err(e) => Err(e),
+                };
+                match written {
+                    Ok(_) => Ok(changed),
+                    Err(e) => {
+                        self.views.lock().unwrap().restore(&name, prev);
+                        Err(e)
+                    }
+                }
+            }
+            Err(e) => Err(e),
+        };
+        self.audit.record(crate::audit::AuditEntry {
+            time: Timestamp::now(),
+            token: scope
+                .as_ref()
+                .map(|s| s.name.clone())
+                .unwrap_or_else(|| "-".to_string()),
+            action: format!("views/{}", method.as_str().to_lowercase()),
+            params,
+            outcome: match outcome {
+                Ok(_) => "ok".to_string(),
+                Err(ref e) => e.clone(),
+            },
+        });
+        match outcome {
+            Ok(false) if method == hyper::Method::DELETE => Ok(not_found()),
+            Ok(created) => json_response(&serde_json::json!({
+                "name": name,
+                "created": created && method == hyper::Method::PUT,
+            })),
+            Err(e) => bad_request(e.as_str()),
+        }
+    }
+    /// GET shows settings in effect and overrides, PATCH with JSON object changes
+    /// overrides (null drops one); admin token is required for both
+    pub async fn say_settings(
+        &self,
+        req: Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let scope = self.tokens.identify(&req);
+        let allowed = scope.as_ref().map(|s| s.admin).unwrap_or(false);
+        let denied = || {
+            Ok(access_denied(match scope {
+                None => StatusCode::UNAUTHORIZED,
+                Some(_) => StatusCode::FORBIDDEN,
+            }))
+        };
+        if req.method() == hyper::Method::GET {
+            if !allowed {
+                return denied();
+            }
+            return json_response(&serde_json::json!({
+                "settings": self.settings.effective(&self.config),
+                "overrides": self.settings.overrides(),
+            }));
+        }
+        if req.method() != hyper::Method::PATCH {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("Allow", "GET, PATCH")
+                .body(Body::empty());
+        }
+        let mut params = BTreeMap::new();
+        let outcome: Result<(), (StatusCode, String)> = if !allowed {
+            Err((StatusCode::FORBIDDEN, "denied".to_string()))
+        } else {
+            match read_body(req.into_body(), MAX_API_BODY)
+                .await
+                .and_then(|b| {
+                    serde_json::from_slice::<serde_json::Value>(&b).map_err(|e| e.to_string())
+                }) {
+                Err(e) => Err((StatusCode::BAD_REQUEST, format!("Invalid body: {}", e))),
+                Ok(patch) => {
+                    if let Some(items) = patch.as_object() {
+                        params.extend(items.iter().map(|(k, v)| (k.clone(), v.to_string())));
+                    }
+                    match timeout(self.rib.locktimeout, self.rib.rib.write()).await {
+                        Err(_) => Err((
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "RIB lock timed out".to_string(),
+                        )),
+                        Ok(mut rib) => match self.settings.patch(&patch) {
+                            Err(e) => Err((StatusCode::BAD_REQUEST, e)),
+                            Ok(_) => {
+                                let s = self.settings.effective(&self.config);
+                                log::set_max_level(s.log_filter());
+                                self.limits.set_limits(&s.limits());
+                                self.rib
+                                    .budget
+                                    .store(s.query_budget, std::sync::atomic::Ordering::Relaxed);
+                                rib.apply_settings(&s);
+                                Ok(())
+                            }
+                        },
+                    }
+                }
+            }
+        };
+        self.audit.record(crate::audit::AuditEntry {
+            time: Timestamp::now(),
+            token: scope
+                .as_ref()
+                .map(|s| s.name.clone())
+                .unwrap_or_else(|| "-".to_string()),
+            action: "settings".to_string(),
+            params,
+            outcome: match outcome {
+                Ok(_) => "ok".to_string(),
+                Err((_, ref e)) => e.clone(),
+            },
+        });
+        match outcome {
+            Ok(_) => json_response(&serde_json::json!({
+                "settings": self.settings.effective(&self.config),
+                "overrides": self.settings.overrides(),
+            })),
+            Err(_) if !allowed => denied(),
+            Err((code, e)) => Response::builder()
+                .status(code)
+                .header("Content-type", "text/plain")
+                .body(e.into()),
+        }
+    }
+    /// RIB generation scope of API request: None - response does not depend on RIB,
+    /// Some(None) - whole RIB, Some(Some(kind)) - only specified RIB
+    fn generation_scope(urlparts: &[&str]) -> Option<Option<BgpRibKind>> {
+        match urlparts.get(2) {
+            Some(&"stats") if matches!(urlparts.get(3), Some(&"protocol") | Some(&"traffic")) => {
+                None
+            }
+            Some(&"prefix") if urlparts.last() == Some(&"history") => Some(None),
+            Some(&"json") | Some(&"timeline") => Some(urlparts.get(3).and_then(|r| r.parse().ok())),
+            Some(&"statistics")
+            | Some(&"stats")
+            | Some(&"nexthop")
+            | Some(&"nexthops")
+            | Some(&"changes")
+            | Some(&"graph")
+            | Some(&"range")
+            | Some(&"subtree")
+            | Some(&"blackholes")
+            | Some(&"clusterpaths") => Some(None),
+            _ => None,
+        }
+    }
     pub async fn response_fn(&self, req: &Request<Body>) -> Result<Response<Body>, hyper::Error> {
-        match self.handle_query(req).await {
-            Ok(v) => Ok(v),
+        {
+            let urlparts: Vec<&str> = req.uri().path().split('/').collect();
+            let action = match (urlparts.get(2), urlparts.get(3)) {
+                (Some(&"admin"), Some(a)) => Some(*a),
+                (Some(&"audit"), None) => Some("audit"),
+                _ => None,
+            };
+            if let Some(a) = action {
+                return Ok(self.say_admin(req, a).await.unwrap_or_else(|_| not_found()));
+            }
+        }
+        let scope = match self.tokens.authorize(req) {
+            Ok(s) => s,
+            Err(code) => return Ok(access_denied(code)),
+        };
+        let urlparts: Vec<&str> = req.uri().path().split('/').collect();
+        let tag = match Self::generation_scope(&urlparts) {
+            None => None,
+            Some(kind) => {
+                // same query returns different routes for different scopes
+                let query = match scope {
+                    None => req.uri().to_string(),
+                    Some(ref s) => format!("{}#{}", req.uri(), s.name),
+                };
+                self.rib.generation_tag(kind, query.as_str()).await
+            }
+        };
+        if let Some((etag, changed)) = tag.as_ref() {
+            if is_not_modified(req, etag, changed) {
+                if let Ok(v) = not_modified(etag, changed) {
+                    return Ok(v);
+                }
+            }
+        }
+        match self.handle_query(req, scope).await {
+            Ok(mut v) => {
+                if let Some((etag, changed)) = tag {
+                    if v.status() == StatusCode::OK {
+                        let headers = v.headers_mut();
+                        if let Ok(hv) = etag.parse() {
+                            headers.insert(hyper::header::ETAG, hv);
+                        }
+                        if let Ok(hv) = http_date(&changed).parse() {
+                            headers.insert(hyper::header::LAST_MODIFIED, hv);
+                        }
+                    }
+                }
+                Ok(v)
+            }
             Err(e) => Ok(Response::builder()
                 .status(StatusCode::NOT_FOUND)
                 .body(format!("BgpSvc error: {:?}", e).into())
@@ -490,13 +1816,28 @@ impl BgpSvr {
         }
     }
 }
+#[derive(serde::Serialize)]
+struct PeerStatsItem<'a> {
+    session: Option<&'a BgpSessionDesc>,
+    stats: &'a BgpPeerStats,
+}
 pub struct BAHItems<'a, 'b> {
     bah: &'a BgpAttrHistory,
     params: &'b RibResponseParams,
+    best: bool,
 }
 impl<'a, 'b> BAHItems<'a, 'b> {
     pub fn new(bah: &'a BgpAttrHistory, params: &'b RibResponseParams) -> Self {
-        BAHItems { bah, params }
+        BAHItems {
+            bah,
+            params,
+            best: false,
+        }
+    }
+    /// marks last history record as best path
+    pub fn best(mut self, best: bool) -> Self {
+        self.best = best;
+        self
     }
     pub fn is_empty(&self) -> bool {
         !self.bah.items.iter().any(|x| {
@@ -514,7 +1855,8 @@ impl<'a, 'b> serde::Serialize for BAHItems<'a, 'b> {
         S: serde::Serializer,
     {
         let mut state = serializer.serialize_map(None)?;
-
+        let lastkey = self.bah.items.keys().last();
+        let diff = self.params.format.history == crate::jsonfmt::HistoryFormat::Diff;
         for (k, v) in self
             .bah
             .items
@@ -533,7 +1875,31 @@ impl<'a, 'b> serde::Serialize for BAHItems<'a, 'b> {
                 self.bah.items.len()
             })
         {
-            state.serialize_entry(&format!("{}", k.timestamp_millis()), &v)?;
+            state.serialize_entry(
+                &crate::jsonfmt::time_key(k, self.params.format.time),
+                &BgpAttrEntryView {
+                    entry: v,
+                    received: *k,
+                    best: self.best && Some(k) == lastkey,
+                    blackhole: self.params.blackhole.matches(&v.attrs),
+                    stability: if Some(k) == lastkey {
+                        Some(self.bah.stability())
+                    } else {
+                        None
+                    },
+                    diff,
+                    previous: if diff {
+                        self.bah
+                            .items
+                            .range(..*k)
+                            .next_back()
+                            .map(|(_, p)| p.attrs.as_ref())
+                    } else {
+                        None
+                    },
+                    fields: self.params.fields.as_ref().map(|f| f.as_ref()),
+                },
+            )?;
         }
         state.end()
     }
@@ -541,10 +1907,20 @@ impl<'a, 'b> serde::Serialize for BAHItems<'a, 'b> {
 pub struct BPEItems<'a, 'b> {
     bpe: &'a BgpPathEntry,
     params: &'b RibResponseParams,
+    best: Option<BgpPathId>,
 }
 impl<'a, 'b> BPEItems<'a, 'b> {
     pub fn new(bpe: &'a BgpPathEntry, params: &'b RibResponseParams) -> Self {
-        BPEItems { bpe, params }
+        BPEItems {
+            bpe,
+            params,
+            best: None,
+        }
+    }
+    /// marks specified path as best one
+    pub fn best(mut self, best: Option<BgpPathId>) -> Self {
+        self.best = best;
+        self
     }
     pub fn is_empty(&self) -> bool {
         !self.bpe.items.iter().any(|x| {
@@ -561,7 +1937,7 @@ impl<'a, 'b> serde::Serialize for BPEItems<'a, 'b> {
         let mut state = serializer.serialize_map(Some(self.bpe.items.len()))?;
 
         for (k, v) in self.bpe.items.iter() {
-            let v = BAHItems::new(v, self.params);
+            let v = BAHItems::new(v, self.params).best(self.best == Some(*k));
             if v.is_empty() {
                 continue;
             }
@@ -591,9 +1967,12 @@ impl<'a, 'b> serde::Serialize for BSEItems<'a, 'b> {
         S: serde::Serializer,
     {
         let mut state = serializer.serialize_map(Some(self.bse.items.len()))?;
-
+        let best = match self.params.bestpath {
+            BestPathMode::Standard => select_best(self.bse),
+            BestPathMode::All => None,
+        };
         for (k, v) in self.bse.items.iter() {
-            let v = BPEItems::new(v, self.params);
+            let v = BPEItems::new(v, self.params).best(best.filter(|b| b.0 == *k).map(|b| b.1));
             if v.is_empty() {
                 continue;
             }
@@ -606,19 +1985,36 @@ pub struct RibItems<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string
     ribsafi: &'a BgpRIBSafi<T>,
     filter: &'a ribfilter::RouteFilter,
     params: RibResponseParams,
+    /// special-use classes of serialized prefixes
+    classes: std::cell::RefCell<BTreeMap<String, crate::addrclass::AddrClass>>,
+    /// matching routes, counted while items are serialized
+    found: std::cell::Cell<usize>,
 }
 
 impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> RibItems<'a, T> {
-    pub fn count(&self) -> usize {
-        if self.filter.terms.is_empty() {
-            self.ribsafi.items.len()
-        } else {
-            //self.hashmap.iter().filter(|p|{!(self.filter.match_route(p.0, p.1) != ribfilter::FilterItemMatchResult::Yes)}).count()
-            self.filter
-                .iter_nets(self.ribsafi, self.params.maxdepth, self.params.onlyactive)
-                .count()
+    fn iter_nets(&self) -> ribfilter::RouteFilterSubnets<'a, 'a, T> {
+        match self.params.sort {
+            None => {
+                self.filter
+                    .iter_nets(self.ribsafi, self.params.maxdepth, self.params.onlyactive)
+            }
+            Some((key, desc)) => self.filter.iter_sorted(
+                self.ribsafi,
+                self.params.maxdepth,
+                self.params.onlyactive,
+                key,
+                desc,
+            ),
         }
     }
+    /// every route matches, so found count is RIB size
+    fn matches_all(&self) -> bool {
+        self.filter.terms.is_empty() && self.params.scope.is_none() && self.params.sort.is_none()
+    }
+    /// matching routes, valid after items are serialized
+    pub fn found(&self) -> usize {
+        self.found.get()
+    }
 }
 
 impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> serde::Serialize
@@ -628,28 +2024,46 @@ impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> ser
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_map(Some(self.params.limit))?;
-        let mut cnt: usize = 0;
+        let mut items = Vec::new();
+        let matches_all = self.matches_all();
+        let end = self.params.skip.saturating_add(self.params.limit);
+        let mut found = 0;
         for (k, v) in self
-            .filter
-            .iter_nets(self.ribsafi, self.params.maxdepth, self.params.onlyactive)
-            .skip(self.params.skip)
-            .take(self.params.limit)
+            .iter_nets()
+            .take_while(|_| !self.params.expired())
+            .filter(|(k, v)| self.params.in_scope(*k, v))
         {
+            found += 1;
+            if found > end {
+                if matches_all {
+                    break;
+                }
+                continue;
+            }
+            if found <= self.params.skip {
+                continue;
+            }
             let v1 = BSEItems::new(v, &self.params);
             if v1.is_empty() {
                 continue;
             }
-            state.serialize_entry(&k.to_string(), &v1)?;
-            cnt += 1;
+            if let Some(c) = k.addrclass() {
+                self.classes.borrow_mut().insert(k.to_string(), c);
+            }
+            items.push((k.to_string(), v1));
         }
-        if cnt < 1 {
+        self.found.set(if matches_all {
+            self.ribsafi.items.len()
+        } else {
+            found
+        });
+        if items.is_empty() {
             for (k, v) in ribfilter::SortIter::new(
-                &mut self.filter.iter_super_nets(
-                    self.ribsafi,
-                    self.params.maxdepth,
-                    self.params.onlyactive,
-                ),
+                &mut self
+                    .filter
+                    .iter_super_nets(self.ribsafi, self.params.maxdepth, self.params.onlyactive)
+                    .take_while(|_| !self.params.expired())
+                    .filter(|(k, v)| self.params.in_scope(*k, v)),
                 &|a, b| {
                     let alen = a.0.len();
                     let blen = b.0.len();
@@ -663,10 +2077,13 @@ impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> ser
                 if v.is_empty() {
                     continue;
                 }
-                state.serialize_entry(&k.to_string(), &v)?;
+                if let Some(c) = k.addrclass() {
+                    self.classes.borrow_mut().insert(k.to_string(), c);
+                }
+                items.push((k.to_string(), v));
             }
         }
-        state.end()
+        crate::jsonfmt::serialize_items(serializer, items, self.params.format.prefix)
     }
 }
 
@@ -690,6 +2107,8 @@ impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> Rib
                 ribsafi: rib,
                 filter,
                 params,
+                classes: Default::default(),
+                found: Default::default(),
             },
         }
     }
@@ -708,8 +2127,121 @@ impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> ser
         state.serialize_field("limit", &self.params.limit)?;
         state.serialize_field("maxdepth", &self.params.maxdepth)?;
         state.serialize_field("onlyactive", &self.params.onlyactive)?;
-        state.serialize_field("found", &self.items.count())?;
+        // items first, found is counted by the same scan
+        state.serialize_field("items", &self.items)?;
+        state.serialize_field("found", &self.items.found())?;
+        if !self.items.classes.borrow().is_empty() {
+            state.serialize_field("classes", &*self.items.classes.borrow())?;
+        }
+        if self.params.is_partial() {
+            state.serialize_field("partial", &true)?;
+        }
+        state.end()
+    }
+}
+
+/// RIB items with keys in range, served by BTreeMap range lookup
+pub struct RangeItems<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> {
+    ribsafi: &'a BgpRIBSafi<T>,
+    range: std::ops::RangeInclusive<T>,
+    within: Option<ribfilter::FilterItem>,
+    params: RibResponseParams,
+    /// special-use classes of serialized prefixes
+    classes: std::cell::RefCell<BTreeMap<String, crate::addrclass::AddrClass>>,
+    /// matching routes, counted while items are serialized
+    found: std::cell::Cell<usize>,
+}
+impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> RangeItems<'a, T> {
+    fn iter(&self) -> impl Iterator<Item = (&'a T, &'a BgpSessionEntry)> + '_ {
+        let ribsafi: &'a BgpRIBSafi<T> = self.ribsafi;
+        ribsafi
+            .items
+            .range(self.range.clone())
+            .take_while(move |_| !self.params.expired())
+            .filter(move |(k, _)| match self.within {
+                None => true,
+                Some(ref fi) => k.match_item(fi) == ribfilter::FilterItemMatchResult::Yes,
+            })
+            .filter(move |(k, v)| self.params.in_scope(*k, v))
+            .filter(move |(_, v)| !BSEItems::new(v, &self.params).is_empty())
+    }
+    /// matching routes, valid after items are serialized
+    pub fn found(&self) -> usize {
+        self.found.get()
+    }
+}
+impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> serde::Serialize
+    for RangeItems<'a, T>
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut items = Vec::new();
+        let mut found = 0;
+        for (i, (k, v)) in self.iter().enumerate() {
+            found = i + 1;
+            if i < self.params.skip || items.len() >= self.params.limit {
+                continue;
+            }
+            if let Some(c) = k.addrclass() {
+                self.classes.borrow_mut().insert(k.to_string(), c);
+            }
+            items.push((k.to_string(), BSEItems::new(v, &self.params)));
+        }
+        self.found.set(found);
+        crate::jsonfmt::serialize_items(serializer, items, self.params.format.prefix)
+    }
+}
+pub struct RangeResponse<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> {
+    pub ribtype: String,
+    pub length: usize,
+    pub items: RangeItems<'a, T>,
+}
+impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> RangeResponse<'a, T> {
+    pub fn new(
+        rib: &'a BgpRIBSafi<T>,
+        range: std::ops::RangeInclusive<T>,
+        within: Option<ribfilter::FilterItem>,
+        params: RibResponseParams,
+    ) -> RangeResponse<'a, T> {
+        RangeResponse::<'a, T> {
+            ribtype: std::any::type_name::<T>().to_string(),
+            length: rib.items.len(),
+            items: RangeItems::<'a, T> {
+                ribsafi: rib,
+                range,
+                within,
+                params,
+                classes: Default::default(),
+                found: Default::default(),
+            },
+        }
+    }
+}
+impl<'a, T: ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString> serde::Serialize
+    for RangeResponse<'a, T>
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("RangeResponse", 8)?;
+        state.serialize_field("ribtype", &self.ribtype)?;
+        state.serialize_field("length", &self.length)?;
+        state.serialize_field("skip", &self.items.params.skip)?;
+        state.serialize_field("limit", &self.items.params.limit)?;
+        state.serialize_field("maxdepth", &self.items.params.maxdepth)?;
+        state.serialize_field("onlyactive", &self.items.params.onlyactive)?;
+        // items first, found is counted by the same scan
         state.serialize_field("items", &self.items)?;
+        state.serialize_field("found", &self.items.found())?;
+        if !self.items.classes.borrow().is_empty() {
+            state.serialize_field("classes", &*self.items.classes.borrow())?;
+        }
+        if self.items.params.is_partial() {
+            state.serialize_field("partial", &true)?;
+        }
         state.end()
     }
 }
@@ -751,3 +2283,59 @@ impl serde::Serialize for BgpSessionStorage {
         state.end()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::RangeResponse;
+    use crate::bgprib::BgpRIBSafi;
+    use crate::config::HistoryChangeMode;
+    use crate::ribfilter::{FilterItem, FilterMatchRoute};
+    use crate::ribservice::RibResponseParams;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_range_response() {
+        let mut safi = BgpRIBSafi::<BgpAddrV4>::new(10, HistoryChangeMode::EveryUpdate);
+        let net = |a: [u8; 4], len: u8| BgpAddrV4::new(Ipv4Addr::from(a), len);
+        safi.handle_updates_afi(
+            1,
+            &[
+                net([9, 0, 0, 0], 8),
+                net([10, 0, 0, 0], 8),
+                net([10, 1, 0, 0], 16),
+                net([10, 1, 2, 0], 24),
+                net([10, 2, 0, 0], 16),
+                net([11, 0, 0, 0], 8),
+            ],
+            Arc::new(crate::bgpattrs::BgpAttrs::new()),
+        );
+        let keys = |range, within| {
+            let resp = RangeResponse::new(
+                &safi,
+                range,
+                within,
+                RibResponseParams::new(0, 1000, 10, false),
+            );
+            let v = serde_json::to_value(&resp).unwrap();
+            let mut keys: Vec<String> = v["items"].as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            assert_eq!(v["found"], keys.len());
+            keys
+        };
+        let start: std::net::IpAddr = "10.0.0.0".parse().unwrap();
+        let end: std::net::IpAddr = "10.1.255.255".parse().unwrap();
+        assert_eq!(
+            keys(BgpAddrV4::get_addr_range(&start, &end).unwrap(), None),
+            vec!["10.0.0.0/8", "10.1.0.0/16", "10.1.2.0/24"]
+        );
+        assert!(BgpAddrV4::get_addr_range(&end, &start).is_none());
+        assert!(BgpAddrV4::get_addr_range(&start, &"::1".parse().unwrap()).is_none());
+        let fi = FilterItem::parse("10.1.0.0/16");
+        assert_eq!(
+            keys(BgpAddrV4::get_subnet_range(&fi).unwrap(), Some(fi)),
+            vec!["10.1.0.0/16", "10.1.2.0/24"]
+        );
+    }
+}
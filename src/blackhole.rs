@@ -0,0 +1,149 @@
+//! remotely triggered blackhole routes, recognized by BLACKHOLE (RFC 7999) and
+//! provider-specific communities
+use crate::bgpattrs::BgpAttrs;
+use crate::bgprib::{BgpRIBKey, BgpRIBSafi};
+use crate::bgpsvc::BgpSessionId;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use zettabgp::prelude::*;
+
+/// well-known BLACKHOLE community 65535:666
+pub fn well_known() -> BgpCommunity {
+    BgpCommunity::from(65535, 666)
+}
+
+#[derive(Debug, Clone)]
+pub struct BlackholeCommunities {
+    comms: BTreeSet<BgpCommunity>,
+}
+impl Default for BlackholeCommunities {
+    fn default() -> Self {
+        BlackholeCommunities {
+            comms: vec![well_known()].into_iter().collect(),
+        }
+    }
+}
+impl BlackholeCommunities {
+    /// comma or space separated communities in addition to well-known one
+    pub fn parse(s: &str) -> Result<BlackholeCommunities, String> {
+        let mut ret = BlackholeCommunities::default();
+        for c in s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|x| !x.is_empty())
+        {
+            match c
+                .split_once(':')
+                .and_then(|(h, l)| Some((h.parse().ok()?, l.parse().ok()?)))
+            {
+                Some((h, l)) => {
+                    ret.comms.insert(BgpCommunity::from(h, l));
+                }
+                None => return Err(format!("invalid community {}", c)),
+            }
+        }
+        Ok(ret)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &BgpCommunity> {
+        self.comms.iter()
+    }
+    /// blackhole communities carried by route
+    pub fn matching(&self, attrs: &BgpAttrs) -> Vec<BgpCommunity> {
+        attrs
+            .comms
+            .value
+            .iter()
+            .filter(|c| self.comms.contains(c))
+            .cloned()
+            .collect()
+    }
+    pub fn matches(&self, attrs: &BgpAttrs) -> bool {
+        attrs.comms.value.iter().any(|c| self.comms.contains(c))
+    }
+}
+
+/// currently signaled blackhole
+#[derive(Debug, Serialize)]
+pub struct BlackholeRoute {
+    pub rib: &'static str,
+    pub prefix: String,
+    pub session: BgpSessionId,
+    pub pathid: BgpPathId,
+    /// first record of current uninterrupted blackhole announcement
+    pub since: Timestamp,
+    pub communities: Vec<String>,
+    pub nexthop: String,
+}
+
+/// active routes with blackhole communities, candidates come from community index
+pub fn find<T: BgpRIBKey + std::string::ToString>(
+    rib: &'static str,
+    safi: &BgpRIBSafi<T>,
+    bh: &BlackholeCommunities,
+    out: &mut Vec<BlackholeRoute>,
+) {
+    let mut keys: BTreeSet<&T> = BTreeSet::new();
+    for c in bh.iter() {
+        if let Some(s) = safi.idx_community.idx.get(c) {
+            keys.extend(s.iter());
+        }
+    }
+    for k in keys {
+        let entry = match safi.items.get(k) {
+            Some(e) => e,
+            None => continue,
+        };
+        for (sid, pe) in entry.items.iter() {
+            for (pathid, hist) in pe.items.iter() {
+                let mut since = None;
+                let mut last = None;
+                for (ts, rec) in hist.items.iter().rev() {
+                    if !rec.active || !bh.matches(&rec.attrs) {
+                        break;
+                    }
+                    if last.is_none() {
+                        last = Some(rec);
+                    }
+                    since = Some(*ts);
+                }
+                if let (Some(since), Some(rec)) = (since, last) {
+                    out.push(BlackholeRoute {
+                        rib,
+                        prefix: k.to_string(),
+                        session: *sid,
+                        pathid: *pathid,
+                        since,
+                        communities: bh
+                            .matching(&rec.attrs)
+                            .iter()
+                            .map(|c| c.to_string())
+                            .collect(),
+                        nexthop: rec.attrs.nexthop.to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::CompactCommunityList;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_blackhole_communities() {
+        let bh = BlackholeCommunities::parse("64500:9999, 64501:666").unwrap();
+        assert!(BlackholeCommunities::parse("64500").is_err());
+        let mut attrs = BgpAttrs::new();
+        assert!(!bh.matches(&attrs));
+        attrs.comms = Arc::new(CompactCommunityList::from_vec(vec![
+            BgpCommunity::from(64500, 100),
+            well_known(),
+        ]));
+        assert!(bh.matches(&attrs));
+        assert_eq!(bh.matching(&attrs), vec![well_known()]);
+        assert!(BlackholeCommunities::default().matches(&attrs));
+    }
+}
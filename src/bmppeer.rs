@@ -1,33 +1,95 @@
+//! BMP station driver, feeds monitored sessions into update handler
 use crate::*;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use zettabgp::bmp::prelude::*;
 use zettabgp::prelude::*;
 
+/// timestamp of per-peer header following message type byte, None when router leaves it zero
+pub fn peer_header_time(msg: &[u8]) -> Option<crate::timestamp::Timestamp> {
+    if msg.len() < 43 {
+        return None;
+    }
+    let secs = u32::from_be_bytes([msg[35], msg[36], msg[37], msg[38]]);
+    let micros = u32::from_be_bytes([msg[39], msg[40], msg[41], msg[42]]);
+    if secs == 0 || micros >= 1_000_000 {
+        return None;
+    }
+    Some(crate::timestamp::Timestamp::from_unix(secs as i64, micros))
+}
+
+/// End-of-RIB marker (RFC 4724): empty IPv4 unicast update or update with empty MP_UNREACH_NLRI only
+fn is_end_of_rib(upd: &BgpUpdateMessage) -> bool {
+    upd.updates.is_empty()
+        && upd.withdraws.is_empty()
+        && upd.attrs.iter().all(|a| match a {
+            BgpAttrItem::MPWithdraws(w) => w.addrs.is_empty(),
+            _ => false,
+        })
+}
+
+/// completes after t, never when t is None
+async fn idle_wait(t: Option<std::time::Duration>) {
+    match t {
+        Some(d) => tokio::time::sleep(d).await,
+        None => futures::future::pending().await,
+    }
+}
+
 pub struct BmpPeer<'a, H: BgpUpdateHandler> {
     peersock: tokio::net::TcpStream,
+    /// router address, for traffic accounting
+    routerip: std::net::IpAddr,
+    /// messages and bytes of router itself (initiation, termination) and of its
+    /// monitored peers, from peer up to peer down
+    traffic: Option<Arc<crate::traffic::SessionTraffic>>,
+    monitored: HashMap<std::net::IpAddr, Arc<crate::traffic::SessionTraffic>>,
     peer: Arc<ProtoPeer>,
     sess: BMPSession,
     sessids: BTreeMap<BgpSessionKey, BgpSessionId>,
+    /// peers past initial table dump (End-of-RIB seen), dump timestamps are stale
+    /// and not used for clock skew
+    dumped: BTreeSet<BgpSessionKey>,
     update_handler: &'a H,
+    /// connection is closed when no message arrives within it
+    idle_timeout: Option<std::time::Duration>,
 }
 
-impl<'a, H: BgpUpdateHandler> BmpPeer<'a, H> {
+impl<'a, H: BgpUpdateHandler + Sync> BmpPeer<'a, H> {
     pub fn new(
         sock: tokio::net::TcpStream,
         peer: Arc<ProtoPeer>,
         handler: &'a H,
     ) -> BmpPeer<'a, H> {
+        let routerip = match sock.peer_addr() {
+            Ok(a) => a.ip(),
+            Err(_) => std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
+        };
         BmpPeer {
             peersock: sock,
+            routerip,
+            traffic: handler.start_traffic("bmp", routerip, None),
+            monitored: HashMap::new(),
             peer,
             sess: Default::default(),
             sessids: BTreeMap::new(),
+            dumped: BTreeSet::new(),
             update_handler: handler,
+            idle_timeout: None,
         }
     }
-    pub async fn processmsg(&mut self, msg: BmpMessage) -> Result<(), BgpError> {
+    pub fn set_idle_timeout(&mut self, t: Option<std::time::Duration>) {
+        self.idle_timeout = t;
+    }
+    /// processes decoded message, raw is BGP message of route monitoring when kept,
+    /// peer_time is per-peer header timestamp
+    pub async fn processmsg(
+        &mut self,
+        msg: BmpMessage,
+        raw: Option<Vec<u8>>,
+        peer_time: Option<crate::timestamp::Timestamp>,
+    ) -> Result<(), BgpError> {
         match msg {
             BmpMessage::PeerUpNotification(pu) => {
                 if let Some(ref filter_rd) = self.peer.flt_rd {
@@ -42,10 +104,15 @@ impl<'a, H: BgpUpdateHandler> BmpPeer<'a, H> {
                     .register_session(Arc::new(BgpSessionDesc::from_bmppeerup(&pu)))
                     .await;
                 info!("Register session id {} for peer {:?}", sessid, pu);
+                if let Some(tr) = self.monitored.get(&pu.peer.peeraddress) {
+                    tr.set_session(sessid);
+                }
+                self.dumped.remove(&BgpSessionKey::from(&pu.peer));
                 self.sessids.insert(BgpSessionKey::from(&pu.peer), sessid);
             }
             BmpMessage::RouteMonitoring(rm) => {
-                let sessid = match self.sessids.get(&BgpSessionKey::from(&rm.peer)) {
+                let key = BgpSessionKey::from(&rm.peer);
+                let sessid = match self.sessids.get(&key) {
                     None => {
                         if let Some(ref filter_rd) = self.peer.flt_rd {
                             if rm.peer.peerdistinguisher == *filter_rd {
@@ -56,7 +123,20 @@ impl<'a, H: BgpUpdateHandler> BmpPeer<'a, H> {
                     }
                     Some(x) => *x,
                 };
-                self.update_handler.handle_update(sessid, rm.update).await;
+                if let Some(t) = peer_time {
+                    if self.dumped.contains(&key) {
+                        self.update_handler.record_peer_clock(self.routerip, t);
+                    }
+                }
+                if is_end_of_rib(&rm.update) {
+                    self.dumped.insert(key);
+                }
+                if let Some(raw) = raw {
+                    self.update_handler.record_raw(sessid, &rm.update, raw);
+                }
+                self.update_handler
+                    .handle_update_timed(sessid, rm.update, peer_time)
+                    .await;
             }
             _ => info!("BMP: {:?}", msg),
         };
@@ -69,18 +149,18 @@ impl<'a, H: BgpUpdateHandler> BmpPeer<'a, H> {
               _ = cancel.cancelled() => {
                   break;
               }
+              _ = idle_wait(self.idle_timeout) => {
+                  warn!("BMP connection idle for {:?}, closing", self.idle_timeout.unwrap_or_default());
+                  self.update_handler.record_liveness(crate::liveness::LivenessEvent::IdleTimeout);
+                  break;
+              }
               r = self.peersock.read_exact(&mut buf[0..1]) => {
                   match r {
                       Err(e) => {
                           error!("BMP reading error: {:?}",e);
                           break;
                       }
-                      Ok(_) => {
-                          if buf[0]!=3 {
-                              // protocol error?
-                              continue;
-                          }
-                      }
+                      Ok(_) => {}
                   }
               }
             };
@@ -95,35 +175,86 @@ impl<'a, H: BgpUpdateHandler> BmpPeer<'a, H> {
                 }
               }
             };
-            let bmph = match BmpMessageHeader::decode_from(buf.as_ref()) {
+            let (version, msglength) = match crate::wireparse::bmp_common_header(&buf[0..5]) {
                 Err(e) => {
-                    error!("BmpMessageHeader decode error: {}", e);
-                    continue;
+                    // misparsing rest of stream would feed garbage into RIB
+                    error!("BMP header error, closing connection: {}", e);
+                    break;
                 }
                 Ok(v) => v,
             };
-            if bmph.0.msglength > 65535 {
+            if msglength > 65535 {
                 continue;
             }
+            if msglength < 6 {
+                // header and message type at least, stream is out of sync otherwise
+                error!("BMP invalid message length {}", msglength);
+                break;
+            }
             select! {
                 _ = cancel.cancelled() => {
                     break;
                 }
-                r = self.peersock.read_exact(&mut buf[0..(bmph.0.msglength-5)]) => {
+                r = self.peersock.read_exact(&mut buf[0..(msglength-5)]) => {
                     if let Err(e) = r {
                         error!("BMP reading error: {:?}",e);
                         break;
                     }
                 }
             };
-            let msg = match self.sess.decode_from(&buf[0..(bmph.0.msglength - 5)]) {
+            let msglen = match crate::wireparse::bmp_to_v3(version, buf.as_mut(), msglength - 5) {
                 Err(e) => {
-                    warn!("BMP decode error: {:?}", e);
+                    warn!("BMP v{} decode error: {}", version, e);
                     continue;
                 }
-                Ok(m) => m,
+                Ok(l) => l,
+            };
+            self.update_handler
+                .record_liveness(crate::liveness::LivenessEvent::Message);
+            self.record_traffic(&buf[0..msglen], msglength);
+            if crate::trace::enabled() {
+                let peer = match self.peersock.peer_addr() {
+                    Ok(a) => a.ip(),
+                    Err(_) => std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
+                };
+                crate::trace::record(
+                    "BMP",
+                    "in",
+                    peer,
+                    crate::trace::bmp_msgtype(buf[0]),
+                    msglength,
+                    &buf[0..msglen],
+                );
+            }
+            let quirks = self.peer.vendor_quirks;
+            let msglen = quirks.bmp_len(&buf[0..msglen]);
+            let msg =
+                match crate::wireparse::decode_bmp(&mut self.sess, &quirks.bmp(&buf[0..msglen])) {
+                    Err(e) => {
+                        warn!("BMP decode error: {}", e);
+                        continue;
+                    }
+                    Ok(m) => m,
+                };
+            // route monitoring: type byte, 42 bytes of per-peer header, BGP message
+            let raw = if self.update_handler.keep_raw() && buf[0] == 0 && msglen > 43 {
+                Some(buf[43..msglen].to_vec())
+            } else {
+                None
+            };
+            if buf[0] == 0 && msglen > 43 + 19 {
+                self.update_handler.record_message(false, buf[43 + 18]);
+                if buf[43 + 18] == 2 {
+                    self.update_handler
+                        .record_update_body(&buf[43 + 19..msglen], false);
+                }
+            }
+            let peer_time = if buf[0] == 0 && !quirks.bmp_local_time {
+                peer_header_time(&buf[0..msglen])
+            } else {
+                None
             };
-            if let Err(e) = self.processmsg(msg).await {
+            if let Err(e) = self.processmsg(msg, raw, peer_time).await {
                 warn!("BMP process error: {:?}", e);
                 break;
             };
@@ -134,4 +265,93 @@ impl<'a, H: BgpUpdateHandler> BmpPeer<'a, H> {
             warn!("socket shutdown error: {}", e)
         }
     }
+    /// counts message of size bytes on wire, per monitored peer when it has per-peer header;
+    /// peer up starts counters of peer from zero, peer down drops them
+    fn record_traffic(&mut self, msg: &[u8], bytes: usize) {
+        let msgtype = crate::traffic::bmp_msgtype(msg);
+        let peer = match crate::traffic::bmp_monitored(msg) {
+            None => {
+                if let Some(tr) = self.traffic.as_ref() {
+                    tr.record(false, msgtype, bytes);
+                }
+                return;
+            }
+            Some(p) => p,
+        };
+        if msg[0] == 3 {
+            if let Some(tr) = self.monitored.remove(&peer) {
+                self.update_handler.end_traffic(&tr);
+            }
+        }
+        if !self.monitored.contains_key(&peer) {
+            match self
+                .update_handler
+                .start_traffic("bmp", self.routerip, Some(peer))
+            {
+                Some(tr) => self.monitored.insert(peer, tr),
+                None => return,
+            };
+        }
+        if let Some(tr) = self.monitored.get(&peer) {
+            tr.record(false, msgtype, bytes);
+        }
+        if msg[0] == 2 {
+            if let Some(tr) = self.monitored.remove(&peer) {
+                self.update_handler.end_traffic(&tr);
+            }
+        }
+    }
+}
+
+impl<'a, H: BgpUpdateHandler> Drop for BmpPeer<'a, H> {
+    fn drop(&mut self) {
+        for tr in self
+            .traffic
+            .take()
+            .into_iter()
+            .chain(self.monitored.drain().map(|(_, t)| t))
+        {
+            self.update_handler.end_traffic(&tr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_end_of_rib, peer_header_time};
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_peer_header_time() {
+        let mut msg = vec![0u8; 48];
+        assert_eq!(peer_header_time(&msg), None);
+        msg[35..39].copy_from_slice(&1700000000u32.to_be_bytes());
+        msg[39..43].copy_from_slice(&250u32.to_be_bytes());
+        assert_eq!(
+            peer_header_time(&msg).map(|t| t.timestamp_micros()),
+            Some(1700000000000250)
+        );
+        assert_eq!(peer_header_time(&msg[0..40]), None);
+    }
+
+    #[test]
+    fn test_end_of_rib() {
+        let params = BgpSessionParams::new(
+            64500,
+            90,
+            BgpTransportMode::IPv4,
+            std::net::Ipv4Addr::new(10, 0, 0, 1),
+            vec![BgpCapability::SafiIPv4u, BgpCapability::SafiIPv6u],
+        );
+        let decode = |body: &[u8]| {
+            let mut upd = BgpUpdateMessage::new();
+            upd.decode_from(&params, body).unwrap();
+            upd
+        };
+        assert!(is_end_of_rib(&decode(&[0, 0, 0, 0])));
+        // MP_UNREACH_NLRI for ipv6 unicast without prefixes
+        assert!(is_end_of_rib(&decode(&[0, 0, 0, 6, 0x80, 15, 3, 0, 2, 1])));
+        // withdraw of 10.0.0.0/8
+        assert!(!is_end_of_rib(&decode(&[0, 2, 8, 10, 0, 0])));
+    }
 }
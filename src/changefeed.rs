@@ -0,0 +1,463 @@
+//! polled change feeds for cron-based tooling: subscription on origin AS or prefix list
+//! collects ipv4u/ipv6u route changes, /api/subscriptions/<id>/changes returns them in
+//! order since previous poll. Definitions are kept in subscriptions_file, changes only in
+//! memory for subscription_retention.
+use crate::bgpattrs::BgpAttrs;
+use crate::bgprib::{BgpEvent, BgpRIBKey, BgpRIBSafi};
+use crate::bgpsvc::BgpSessionId;
+use crate::inbound::PrefixList;
+use crate::script::RoutePrefix;
+use crate::timestamp::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use zettabgp::prelude::*;
+
+/// changes kept per subscription regardless of retention time
+pub const MAX_CHANGES: usize = 100_000;
+
+/// what to follow, either origin AS or prefixes (routes equal to or more specific)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriptionSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prefixes: Vec<String>,
+}
+impl SubscriptionSpec {
+    pub fn prefix_list(&self) -> Result<PrefixList, String> {
+        match (self.origin, self.prefixes.is_empty()) {
+            (Some(_), true) => Ok(PrefixList::default()),
+            (None, false) => PrefixList::parse(self.prefixes.join(",").as_str()),
+            _ => Err("either origin or prefixes should be given".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub seq: u64,
+    pub time: Timestamp,
+    pub session: BgpSessionId,
+    pub prefix: String,
+    /// announced/withdrawn
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aspath: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nexthop: Option<String>,
+}
+
+/// response of one poll
+#[derive(Debug, Serialize)]
+pub struct ChangesPoll {
+    pub id: u64,
+    pub changes: Vec<Change>,
+    /// changes dropped by retention before they were polled
+    pub lost: u64,
+    /// more changes are waiting beyond limit
+    pub more: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscriptionInfo {
+    pub id: u64,
+    #[serde(flatten)]
+    pub spec: SubscriptionSpec,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    pub created: Timestamp,
+    pub pending: usize,
+}
+
+/// subscriptions_file entry, owner is name of API token which created subscription
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredSubscription {
+    #[serde(flatten)]
+    spec: SubscriptionSpec,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+}
+
+struct Subscription {
+    spec: SubscriptionSpec,
+    owner: Option<String>,
+    prefixes: PrefixList,
+    created: Timestamp,
+    /// routes announced with subscribed origin, to report their withdraws
+    tracked: BTreeSet<(BgpSessionId, String)>,
+    changes: VecDeque<Change>,
+    /// last seq returned by poll
+    cursor: u64,
+    lost: u64,
+}
+impl Subscription {
+    fn new(spec: SubscriptionSpec, owner: Option<String>) -> Result<Subscription, String> {
+        Ok(Subscription {
+            prefixes: spec.prefix_list()?,
+            spec,
+            owner,
+            created: Timestamp::now(),
+            tracked: BTreeSet::new(),
+            changes: VecDeque::new(),
+            cursor: 0,
+            lost: 0,
+        })
+    }
+    /// route key when route change is followed
+    fn route_change(
+        &mut self,
+        session: BgpSessionId,
+        prefix: &RoutePrefix,
+        attrs: Option<&BgpAttrs>,
+    ) -> Option<String> {
+        let key = prefix.to_string();
+        match self.spec.origin {
+            None => {
+                if self.prefixes.covers(prefix) {
+                    Some(key)
+                } else {
+                    None
+                }
+            }
+            Some(asn) => {
+                let origin = attrs.and_then(|a| a.aspath.value.last().map(|x| x.value));
+                if origin == Some(asn) {
+                    self.tracked.insert((session, key.clone()));
+                    Some(key)
+                } else if self.tracked.remove(&(session, key.clone())) {
+                    // route left subscribed origin, new path shows where to
+                    Some(key)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+    fn expire(&mut self, now: &Timestamp, retention_ms: i64) {
+        while let Some(c) = self.changes.front() {
+            if self.changes.len() <= MAX_CHANGES
+                && now.timestamp_millis() - c.time.timestamp_millis() <= retention_ms
+            {
+                break;
+            }
+            if c.seq > self.cursor {
+                self.lost += 1;
+            }
+            self.changes.pop_front();
+        }
+    }
+}
+
+pub struct ChangeFeeds {
+    file: Option<String>,
+    retention_ms: i64,
+    next_id: u64,
+    next_seq: u64,
+    subs: BTreeMap<u64, Subscription>,
+}
+impl ChangeFeeds {
+    /// restores definitions from file, unreadable file is ignored with warning
+    pub fn new(file: Option<String>, retention: std::time::Duration) -> ChangeFeeds {
+        let mut ret = ChangeFeeds {
+            file,
+            retention_ms: retention.as_millis() as i64,
+            next_id: 1,
+            next_seq: 1,
+            subs: BTreeMap::new(),
+        };
+        let stored: BTreeMap<u64, StoredSubscription> = match ret.file.as_ref() {
+            None => BTreeMap::new(),
+            Some(f) => match std::fs::read_to_string(f) {
+                Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                    warn!("Subscriptions file {}: {}", f, e);
+                    BTreeMap::new()
+                }),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => {
+                    warn!("Subscriptions file {}: {}", f, e);
+                    BTreeMap::new()
+                }
+            },
+        };
+        for (id, st) in stored.into_iter() {
+            match Subscription::new(st.spec, st.owner) {
+                Ok(s) => {
+                    ret.subs.insert(id, s);
+                    ret.next_id = ret.next_id.max(id + 1);
+                }
+                Err(e) => warn!("Subscription {}: {}", id, e),
+            }
+        }
+        ret
+    }
+    fn store(&self) -> Result<(), String> {
+        let f = match self.file.as_ref() {
+            None => return Ok(()),
+            Some(f) => f,
+        };
+        let specs: BTreeMap<u64, StoredSubscription> = self
+            .subs
+            .iter()
+            .map(|(k, v)| {
+                (
+                    *k,
+                    StoredSubscription {
+                        spec: v.spec.clone(),
+                        owner: v.owner.clone(),
+                    },
+                )
+            })
+            .collect();
+        let tmp = format!("{}.tmp", f);
+        serde_json::to_vec_pretty(&specs)
+            .map_err(|e| e.to_string())
+            .and_then(|b| std::fs::write(&tmp, b).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&tmp, f).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Subscriptions file {}: {}", f, e))
+    }
+    pub fn create(&mut self, spec: SubscriptionSpec, owner: Option<String>) -> Result<u64, String> {
+        let s = Subscription::new(spec, owner)?;
+        let id = self.next_id;
+        self.subs.insert(id, s);
+        if let Err(e) = self.store() {
+            self.subs.remove(&id);
+            return Err(e);
+        }
+        self.next_id += 1;
+        Ok(id)
+    }
+    pub fn remove(&mut self, id: u64) -> Result<bool, String> {
+        match self.subs.remove(&id) {
+            None => Ok(false),
+            Some(_) => self.store().map(|_| true),
+        }
+    }
+    /// owner of subscription, None when there is no such subscription
+    pub fn owner(&self, id: u64) -> Option<Option<&str>> {
+        self.subs.get(&id).map(|s| s.owner.as_deref())
+    }
+    /// number of subscriptions created by owner
+    pub fn owned_by(&self, owner: Option<&str>) -> usize {
+        self.subs
+            .values()
+            .filter(|s| s.owner.as_deref() == owner)
+            .count()
+    }
+    /// subscriptions of owner, all of them when owner is None
+    pub fn list(&self, owner: Option<&str>) -> Vec<SubscriptionInfo> {
+        self.subs
+            .iter()
+            .filter(|(_, s)| owner.is_none() || s.owner.as_deref() == owner)
+            .map(|(id, s)| SubscriptionInfo {
+                id: *id,
+                spec: s.spec.clone(),
+                owner: s.owner.clone(),
+                created: s.created,
+                pending: s.changes.iter().filter(|c| c.seq > s.cursor).count(),
+            })
+            .collect()
+    }
+    /// origin subscriptions without tracked routes, they should be seeded from RIB
+    pub fn unseeded(&self) -> Vec<(u64, u32)> {
+        self.subs
+            .iter()
+            .filter(|(_, s)| s.tracked.is_empty())
+            .filter_map(|(id, s)| s.spec.origin.map(|asn| (*id, asn)))
+            .collect()
+    }
+    /// remembers active routes originated by subscribed AS, so their withdraws are reported
+    pub fn seed<T: BgpRIBKey + std::string::ToString>(
+        &mut self,
+        id: u64,
+        asn: u32,
+        safi: &BgpRIBSafi<T>,
+    ) {
+        self.track(id, originated(asn, safi));
+    }
+    /// adds routes collected by originated to subscription
+    pub fn track(&mut self, id: u64, routes: Vec<(BgpSessionId, String)>) {
+        if let Some(s) = self.subs.get_mut(&id) {
+            s.tracked.extend(routes);
+        }
+    }
+    /// changes after since, by default after previous poll; only default poll moves poll
+    /// position, explicit since re-reads changes without affecting it
+    pub fn poll(&mut self, id: u64, since: Option<u64>, limit: usize) -> Option<ChangesPoll> {
+        let s = self.subs.get_mut(&id)?;
+        let from = since.unwrap_or(s.cursor);
+        let mut pending = s.changes.iter().filter(|c| c.seq > from);
+        let changes: Vec<Change> = pending.by_ref().take(limit).cloned().collect();
+        let more = pending.next().is_some();
+        if let (None, Some(last)) = (since, changes.last()) {
+            s.cursor = s.cursor.max(last.seq);
+        }
+        let lost = std::mem::take(&mut s.lost);
+        Some(ChangesPoll {
+            id,
+            changes,
+            lost,
+            more,
+        })
+    }
+    pub fn on_event(&mut self, evt: &BgpEvent) {
+        let (session, kind, attrs, prefixes) = match evt {
+            BgpEvent::Update(sid, attrs, addrs) => (
+                *sid,
+                "announced",
+                Some(attrs.as_ref()),
+                unicast_prefixes(addrs),
+            ),
+            BgpEvent::Withdraw(sid, addrs) => (*sid, "withdrawn", None, unicast_prefixes(addrs)),
+            // whole batch in one pass, subscribers only see their tracked routes of it
+            BgpEvent::WithdrawBatch(sid, batch) => (
+                *sid,
+                "withdrawn",
+                None,
+                batch
+                    .addrs
+                    .iter()
+                    .flat_map(|a| unicast_prefixes(a))
+                    .collect(),
+            ),
+        };
+        if prefixes.is_empty() || self.subs.is_empty() {
+            return;
+        }
+        let now = Timestamp::now();
+        for s in self.subs.values_mut() {
+            for p in prefixes.iter() {
+                if let Some(prefix) = s.route_change(session, p, attrs) {
+                    let c = Change {
+                        seq: self.next_seq,
+                        time: now,
+                        session,
+                        prefix,
+                        kind,
+                        aspath: attrs.map(|a| a.aspath.to_string()),
+                        nexthop: attrs.map(|a| a.nexthop.to_string()),
+                    };
+                    self.next_seq += 1;
+                    s.changes.push_back(c);
+                }
+            }
+            s.expire(&now, self.retention_ms);
+        }
+    }
+}
+
+/// active routes originated by asn, walked without change feeds lock held
+pub fn originated<T: BgpRIBKey + std::string::ToString>(
+    asn: u32,
+    safi: &BgpRIBSafi<T>,
+) -> Vec<(BgpSessionId, String)> {
+    let keys = match safi.idx_aspath.idx.get(&BgpAS::new(asn)) {
+        Some(k) => k,
+        None => return Vec::new(),
+    };
+    let mut ret = Vec::new();
+    for k in keys.iter() {
+        let entry = match safi.items.get(k) {
+            Some(e) => e,
+            None => continue,
+        };
+        for (sid, pe) in entry.items.iter() {
+            let found = pe
+                .items
+                .values()
+                .filter_map(|hist| hist.items.values().last())
+                .any(|r| r.active && r.attrs.aspath.value.last().map(|x| x.value) == Some(asn));
+            if found {
+                ret.push((*sid, k.to_string()));
+            }
+        }
+    }
+    ret
+}
+
+fn unicast_prefixes(addrs: &BgpAddrs) -> Vec<RoutePrefix> {
+    match addrs {
+        BgpAddrs::IPV4U(v) => v.iter().map(|r| RoutePrefix::V4(r.clone())).collect(),
+        BgpAddrs::IPV4UP(v) => v.iter().map(|r| RoutePrefix::V4(r.nlri.clone())).collect(),
+        BgpAddrs::IPV6U(v) => v.iter().map(|r| RoutePrefix::V6(r.clone())).collect(),
+        BgpAddrs::IPV6UP(v) => v.iter().map(|r| RoutePrefix::V6(r.nlri.clone())).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::CompactASPath;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_change_feed() {
+        let mut feeds = ChangeFeeds::new(None, std::time::Duration::from_secs(3600));
+        assert!(feeds.create(SubscriptionSpec::default(), None).is_err());
+        let id = feeds
+            .create(
+                SubscriptionSpec {
+                    origin: Some(64500),
+                    prefixes: Vec::new(),
+                },
+                None,
+            )
+            .unwrap();
+        let mut attrs = BgpAttrs::new();
+        attrs.aspath = Arc::new(CompactASPath::from(vec![64510, 64500]));
+        let net = BgpAddrV4::new(Ipv4Addr::new(10, 0, 0, 0), 8);
+        let upd = |a: &BgpAttrs| {
+            BgpEvent::Update(
+                1,
+                Arc::new(a.clone()),
+                Arc::new(BgpAddrs::IPV4U(vec![net.clone()])),
+            )
+        };
+        let wdr = BgpEvent::Withdraw(1, Arc::new(BgpAddrs::IPV4U(vec![net.clone()])));
+        feeds.on_event(&upd(&attrs));
+        // not originated by 64500 and not tracked
+        feeds.on_event(&BgpEvent::Withdraw(
+            2,
+            Arc::new(BgpAddrs::IPV4U(vec![net.clone()])),
+        ));
+        feeds.on_event(&wdr);
+        let p = feeds.poll(id, None, 100).unwrap();
+        assert_eq!(p.changes.len(), 2);
+        assert_eq!(p.changes[0].kind, "announced");
+        assert_eq!(p.changes[1].kind, "withdrawn");
+        assert!(feeds.poll(id, None, 100).unwrap().changes.is_empty());
+        assert_eq!(feeds.poll(id, Some(0), 1).unwrap().changes.len(), 1);
+        // origin moved away from 64500 is reported once
+        feeds.on_event(&upd(&attrs));
+        attrs.aspath = Arc::new(CompactASPath::from(vec![64510, 64501]));
+        feeds.on_event(&upd(&attrs));
+        feeds.on_event(&upd(&attrs));
+        assert_eq!(feeds.poll(id, None, 100).unwrap().changes.len(), 2);
+        assert!(feeds.remove(id).unwrap());
+        assert!(feeds.poll(id, None, 100).is_none());
+    }
+
+    #[test]
+    fn test_change_feed_owner() {
+        let path = std::env::temp_dir().join(format!("bgpexplorer-subs-{}", std::process::id()));
+        let file = Some(path.to_string_lossy().to_string());
+        let retention = std::time::Duration::from_secs(3600);
+        let mut feeds = ChangeFeeds::new(file.clone(), retention);
+        let spec = SubscriptionSpec {
+            origin: None,
+            prefixes: vec!["10.0.0.0/8".to_string()],
+        };
+        let id = feeds.create(spec.clone(), Some("ops".to_string())).unwrap();
+        feeds.create(spec, None).unwrap();
+        assert_eq!(feeds.owned_by(Some("ops")), 1);
+        assert_eq!(feeds.list(Some("ops")).len(), 1);
+        assert_eq!(feeds.list(None).len(), 2);
+        // owner survives restart
+        let feeds = ChangeFeeds::new(file, retention);
+        assert_eq!(feeds.owner(id), Some(Some("ops")));
+        assert_eq!(feeds.owned_by(None), 1);
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,188 @@
+//! route visibility assertions for automation, POST /api/check
+use crate::bgpattrs::BgpAttrs;
+use crate::bgprib::{BgpRIB, BgpSessionEntry};
+use serde::{Deserialize, Serialize};
+use zettabgp::prelude::*;
+
+/// items accepted in one request at most
+pub const MAX_ITEMS: usize = 10000;
+/// POST body size limit, room for MAX_ITEMS items
+pub const MAX_BODY: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckItem {
+    pub prefix: String,
+    #[serde(default)]
+    pub expected_origin: Option<u32>,
+    /// asn:value or symbolic names
+    #[serde(default)]
+    pub required_communities: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub prefix: String,
+    pub pass: bool,
+    /// prefix has active paths
+    pub found: bool,
+    /// origin ASNs of active paths
+    pub origins: Vec<u32>,
+    /// required communities absent on best matching path
+    pub missing_communities: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+impl CheckResult {
+    fn fail(prefix: &str, reason: String) -> CheckResult {
+        CheckResult {
+            prefix: prefix.to_string(),
+            pass: false,
+            found: false,
+            origins: Vec::new(),
+            missing_communities: Vec::new(),
+            reason: Some(reason),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckResponse {
+    pub pass: bool,
+    pub results: Vec<CheckResult>,
+}
+
+fn parse_community(s: &str) -> Option<BgpCommunity> {
+    match s.trim().split_once(':') {
+        Some((h, l)) => Some(BgpCommunity::from(h.parse().ok()?, l.parse().ok()?)),
+        None => crate::communities::lookup(s),
+    }
+}
+
+/// last records of active paths
+fn active_attrs(entry: &BgpSessionEntry) -> Vec<&BgpAttrs> {
+    entry
+        .items
+        .values()
+        .flat_map(|pe| pe.items.values())
+        .filter_map(|hist| hist.items.values().last())
+        .filter(|rec| rec.active)
+        .map(|rec| rec.attrs.as_ref())
+        .collect()
+}
+
+/// item passes when any active path has expected origin and all required communities
+pub fn evaluate(item: &CheckItem, paths: &[&BgpAttrs]) -> CheckResult {
+    let mut required = Vec::new();
+    for c in item.required_communities.iter() {
+        match parse_community(c) {
+            Some(v) => required.push((c.clone(), v)),
+            None => return CheckResult::fail(&item.prefix, format!("invalid community {}", c)),
+        }
+    }
+    let mut origins: Vec<u32> = paths
+        .iter()
+        .filter_map(|a| a.aspath.value.last().map(|x| x.value))
+        .collect();
+    origins.sort_unstable();
+    origins.dedup();
+    let mut missing: Option<Vec<String>> = None;
+    for a in paths.iter() {
+        if item.expected_origin.is_some()
+            && a.aspath.value.last().map(|x| x.value) != item.expected_origin
+        {
+            continue;
+        }
+        let m: Vec<String> = required
+            .iter()
+            .filter(|(_, c)| !a.comms.value.contains(c))
+            .map(|(n, _)| n.clone())
+            .collect();
+        if missing.as_ref().map(|x| m.len() < x.len()).unwrap_or(true) {
+            missing = Some(m);
+        }
+    }
+    let reason = if paths.is_empty() {
+        Some("not found".to_string())
+    } else {
+        match missing {
+            None => Some("origin mismatch".to_string()),
+            Some(ref m) if !m.is_empty() => Some("missing communities".to_string()),
+            Some(_) => None,
+        }
+    };
+    CheckResult {
+        prefix: item.prefix.clone(),
+        pass: reason.is_none(),
+        found: !paths.is_empty(),
+        origins,
+        missing_communities: missing.unwrap_or_default(),
+        reason,
+    }
+}
+
+impl BgpRIB {
+    /// checks items against exact ipv4u/ipv6u routes
+    pub fn check(&self, items: &[CheckItem]) -> CheckResponse {
+        let mut results = Vec::new();
+        for item in items.iter() {
+            let (a, l) = match item.prefix.trim().split_once('/') {
+                Some((a, l)) => (a, l.parse::<u8>().ok()),
+                None => (item.prefix.trim(), None),
+            };
+            let entry = match a.parse::<std::net::IpAddr>() {
+                Ok(std::net::IpAddr::V4(v)) if l.unwrap_or(32) <= 32 => {
+                    self.ipv4u.items.get(&BgpAddrV4::new(v, l.unwrap_or(32)))
+                }
+                Ok(std::net::IpAddr::V6(v)) if l.unwrap_or(128) <= 128 => {
+                    self.ipv6u.items.get(&BgpAddrV6::new(v, l.unwrap_or(128)))
+                }
+                _ => {
+                    results.push(CheckResult::fail(
+                        &item.prefix,
+                        "invalid prefix".to_string(),
+                    ));
+                    continue;
+                }
+            };
+            let paths = entry.map(active_attrs).unwrap_or_default();
+            results.push(evaluate(item, &paths));
+        }
+        CheckResponse {
+            pass: results.iter().all(|r| r.pass),
+            results,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::{CompactASPath, CompactCommunityList};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_evaluate() {
+        let item: CheckItem = serde_json::from_str(
+            r#"{"prefix":"192.0.2.0/24","expected_origin":64500,"required_communities":["64500:100","no-export"]}"#,
+        )
+        .unwrap();
+        let mut attrs = BgpAttrs::new();
+        attrs.aspath = Arc::new(CompactASPath::from(vec![64501, 64500]));
+        let mut cl = vec![BgpCommunity::from(64500, 100)];
+        attrs.comms = Arc::new(CompactCommunityList::from_vec(cl.clone()));
+        let r = evaluate(&item, &[&attrs]);
+        assert!(!r.pass);
+        assert_eq!(r.origins, vec![64500]);
+        assert_eq!(r.missing_communities, vec!["no-export".to_string()]);
+        cl.push(BgpCommunity::from(65535, 65281));
+        attrs.comms = Arc::new(CompactCommunityList::from_vec(cl));
+        assert!(evaluate(&item, &[&attrs]).pass);
+        let mut other = item.clone();
+        other.expected_origin = Some(64502);
+        assert_eq!(
+            evaluate(&other, &[&attrs]).reason,
+            Some("origin mismatch".to_string())
+        );
+        assert!(!evaluate(&item, &[]).found);
+    }
+}
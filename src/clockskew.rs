@@ -0,0 +1,104 @@
+//! offset between BMP per-peer header timestamps and collector receive time per router
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+/// weight of new sample in average offset is 1/SMOOTHING
+const SMOOTHING: i64 = 16;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouterClock {
+    pub router: IpAddr,
+    /// receive time minus router time of last message, includes transport latency
+    pub offset_ms: i64,
+    /// moving average of offset
+    pub avg_offset_ms: i64,
+    pub samples: u64,
+    /// average offset exceeds threshold
+    pub skewed: bool,
+    pub updated: Timestamp,
+}
+
+pub struct ClockSkew {
+    /// milliseconds, 0 - never skewed
+    threshold_ms: i64,
+    routers: Mutex<BTreeMap<IpAddr, RouterClock>>,
+}
+impl ClockSkew {
+    pub fn new(threshold_secs: u64) -> ClockSkew {
+        ClockSkew {
+            threshold_ms: threshold_secs as i64 * 1000,
+            routers: Mutex::new(BTreeMap::new()),
+        }
+    }
+    /// accounts router timestamp, warns when router becomes skewed or recovers
+    pub fn observe(&self, router: IpAddr, peer_time: Timestamp, received: Timestamp) {
+        let offset = received.timestamp_millis() - peer_time.timestamp_millis();
+        let mut routers = self.routers.lock().unwrap();
+        let rc = routers.entry(router).or_insert_with(|| RouterClock {
+            router,
+            offset_ms: offset,
+            avg_offset_ms: offset,
+            samples: 0,
+            skewed: false,
+            updated: received,
+        });
+        rc.offset_ms = offset;
+        if rc.samples > 0 {
+            rc.avg_offset_ms += (offset - rc.avg_offset_ms) / SMOOTHING;
+        }
+        rc.samples += 1;
+        rc.updated = received;
+        let skewed = self.threshold_ms > 0 && rc.avg_offset_ms.abs() > self.threshold_ms;
+        if skewed != rc.skewed {
+            rc.skewed = skewed;
+            if skewed {
+                warn!(
+                    "BMP router {} clock is off by {} ms from collector",
+                    router, rc.avg_offset_ms
+                );
+            } else {
+                info!("BMP router {} clock skew is back within threshold", router);
+            }
+        }
+    }
+    pub fn routers(&self) -> Vec<RouterClock> {
+        self.routers.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skew() {
+        let cs = ClockSkew::new(5);
+        let r: IpAddr = "192.0.2.1".parse().unwrap();
+        cs.observe(
+            r,
+            Timestamp::from_unix(1000, 0),
+            Timestamp::from_unix(1000, 20000),
+        );
+        let s = cs.routers();
+        assert_eq!(s[0].offset_ms, 20);
+        assert!(!s[0].skewed);
+        cs.observe(
+            r,
+            Timestamp::from_unix(1000, 0),
+            Timestamp::from_unix(1060, 0),
+        );
+        cs.observe(
+            r,
+            Timestamp::from_unix(1000, 0),
+            Timestamp::from_unix(1060, 0),
+        );
+        let s = cs.routers();
+        assert_eq!(s[0].samples, 3);
+        assert_eq!(s[0].offset_ms, 60000);
+        assert!(s[0].avg_offset_ms > 5000);
+        assert!(s[0].skewed);
+    }
+}
@@ -0,0 +1,115 @@
+//! concurrent identical lookups share one in-flight request
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+pub struct Coalescer<K, V> {
+    inflight: Mutex<HashMap<K, broadcast::Sender<Result<V, String>>>>,
+    /// lookups served from other request result
+    coalesced: AtomicU64,
+}
+
+/// removes in-flight entry when leading request completes or is dropped
+struct InflightGuard<'a, K: Eq + Hash, V> {
+    map: &'a Mutex<HashMap<K, broadcast::Sender<Result<V, String>>>>,
+    key: Option<K>,
+}
+impl<'a, K: Eq + Hash, V> Drop for InflightGuard<'a, K, V> {
+    fn drop(&mut self) {
+        if let Some(k) = self.key.take() {
+            self.map.lock().unwrap().remove(&k);
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Default for Coalescer<K, V> {
+    fn default() -> Self {
+        Coalescer {
+            inflight: Mutex::new(HashMap::new()),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Coalescer<K, V> {
+    /// runs lookup unless the same key is already in flight, then waits for its result.
+    /// If the leading request is dropped, one of waiters takes over.
+    pub async fn run<F, Fut>(&self, key: K, lookup: F) -> Result<V, String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<V, String>>,
+    {
+        loop {
+            let rcv = {
+                let mut inflight = self.inflight.lock().unwrap();
+                match inflight.get(&key) {
+                    Some(tx) => Some(tx.subscribe()),
+                    None => {
+                        let (tx, _) = broadcast::channel(1);
+                        inflight.insert(key.clone(), tx);
+                        None
+                    }
+                }
+            };
+            match rcv {
+                Some(mut rcv) => {
+                    if let Ok(r) = rcv.recv().await {
+                        self.coalesced.fetch_add(1, Ordering::Relaxed);
+                        return r;
+                    }
+                }
+                None => {
+                    let mut guard = InflightGuard {
+                        map: &self.inflight,
+                        key: Some(key.clone()),
+                    };
+                    let r = lookup().await;
+                    if let Some(tx) = self.inflight.lock().unwrap().remove(&key) {
+                        let _ = tx.send(r.clone());
+                    }
+                    guard.key = None;
+                    return r;
+                }
+            }
+        }
+    }
+    pub fn inflight(&self) -> usize {
+        self.inflight.lock().unwrap().len()
+    }
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_coalesce() {
+        let c: Arc<Coalescer<String, String>> = Arc::new(Coalescer::default());
+        let calls = Arc::new(AtomicU64::new(0));
+        let mut tasks = Vec::new();
+        for _ in 0..8 {
+            let c = c.clone();
+            let calls = calls.clone();
+            tasks.push(tokio::spawn(async move {
+                c.run("AS64500".to_string(), || async move {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    Ok("aut-num: AS64500".to_string())
+                })
+                .await
+            }));
+        }
+        for t in tasks {
+            assert_eq!(t.await.unwrap().unwrap(), "aut-num: AS64500");
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(c.coalesced(), 7);
+        assert_eq!(c.inflight(), 0);
+    }
+}
@@ -0,0 +1,100 @@
+//! symbolic names of well-known communities, extended with community_names option
+use crate::compact::CompactCommunityList;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use zettabgp::prelude::*;
+
+/// IANA well-known communities
+const WELL_KNOWN: &[(&str, u16, u16)] = &[
+    ("graceful-shutdown", 65535, 0),
+    ("accept-own", 65535, 1),
+    ("route-filter-translated-v4", 65535, 2),
+    ("route-filter-v4", 65535, 3),
+    ("route-filter-translated-v6", 65535, 4),
+    ("route-filter-v6", 65535, 5),
+    ("llgr-stale", 65535, 6),
+    ("no-llgr", 65535, 7),
+    ("accept-own-nexthop", 65535, 8),
+    ("standby-pe", 65535, 9),
+    ("blackhole", 65535, 666),
+    ("no-export", 65535, 65281),
+    ("no-advertise", 65535, 65282),
+    ("no-export-subconfed", 65535, 65283),
+    ("no-peer", 65535, 65284),
+];
+
+lazy_static! {
+    static ref SYMBOLS: RwLock<BTreeMap<String, BgpCommunity>> = RwLock::new(
+        WELL_KNOWN
+            .iter()
+            .map(|(n, h, l)| (n.to_string(), BgpCommunity::from(*h, *l)))
+            .collect()
+    );
+}
+
+/// lowercase with dashes, so NO_EXPORT and no-export are the same name
+pub fn normalize(name: &str) -> String {
+    name.trim().to_lowercase().replace('_', "-")
+}
+
+/// parses "name=asn:value" pairs separated by comma
+pub fn parse_symbols(s: &str) -> Result<Vec<(String, BgpCommunity)>, String> {
+    let mut ret = Vec::new();
+    for item in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let (name, comm) = item
+            .split_once('=')
+            .ok_or_else(|| format!("missing = in {}", item))?;
+        let c = comm
+            .trim()
+            .split_once(':')
+            .and_then(|(h, l)| Some(BgpCommunity::from(h.parse().ok()?, l.parse().ok()?)))
+            .ok_or_else(|| format!("invalid community in {}", item))?;
+        ret.push((normalize(name), c));
+    }
+    Ok(ret)
+}
+
+/// adds or overrides symbols
+pub fn register(symbols: &[(String, BgpCommunity)]) {
+    let mut m = SYMBOLS.write().unwrap();
+    for (n, c) in symbols.iter() {
+        m.insert(normalize(n), c.clone());
+    }
+}
+
+pub fn lookup(name: &str) -> Option<BgpCommunity> {
+    SYMBOLS.read().unwrap().get(&normalize(name)).cloned()
+}
+
+/// symbolic names of communities in list, in community order
+pub fn names(comms: &CompactCommunityList) -> Vec<String> {
+    let m = SYMBOLS.read().unwrap();
+    comms
+        .value
+        .iter()
+        .filter_map(|c| m.iter().find(|(_, v)| *v == c).map(|(n, _)| n.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(lookup("NO_EXPORT"), Some(BgpCommunity::from(65535, 65281)));
+        let s = parse_symbols("customer-rtbh=64500:9999, Backup = 64500:80").unwrap();
+        assert_eq!(s[1].0, "backup");
+        assert!(parse_symbols("x=1").is_err());
+        register(&s);
+        assert_eq!(
+            lookup("customer-rtbh"),
+            Some(BgpCommunity::from(64500, 9999))
+        );
+        let cl = CompactCommunityList::from_vec(vec![
+            BgpCommunity::from(65535, 65282),
+            BgpCommunity::from(64500, 1),
+        ]);
+        assert_eq!(names(&cl), vec!["no-advertise".to_string()]);
+    }
+}
@@ -0,0 +1,173 @@
+//! compact forms of AS path and community list kept in RIB: AS_SEQUENCE and AS_SET
+//! segments of zettabgp path are flattened into one hop list, and path of up to
+//! ASPATH_INLINE hops lives inside its shared entry without separate heap buffer;
+//! communities are sorted packed u32 slice instead of tree set nodes. Both serialize
+//! as plain arrays, so snapshots and API output do not change.
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+use zettabgp::prelude::*;
+
+/// AS path hops stored inline
+pub const ASPATH_INLINE: usize = 8;
+
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CompactASPath {
+    pub value: SmallVec<[BgpAS; ASPATH_INLINE]>,
+}
+impl CompactASPath {
+    pub fn new() -> CompactASPath {
+        CompactASPath {
+            value: SmallVec::new(),
+        }
+    }
+    /// flattens path segments, members of AS_SET are kept in set order
+    pub fn from_aspath(p: &BgpASpath) -> CompactASPath {
+        let mut value = SmallVec::new();
+        for item in p.value.iter() {
+            match item {
+                BgpASitem::Seq(s) => value.extend(s.value.iter().cloned()),
+                BgpASitem::Set(s) => value.extend(s.value.iter().cloned()),
+            }
+        }
+        CompactASPath { value }
+    }
+    /// zettabgp form, one AS_SEQUENCE segment
+    pub fn to_aspath(&self) -> BgpASpath {
+        if self.value.is_empty() {
+            return BgpASpath::new();
+        }
+        BgpASpath {
+            value: vec![BgpASitem::Seq(BgpASseq {
+                value: self.value.to_vec(),
+            })],
+        }
+    }
+}
+impl<T, I> From<T> for CompactASPath
+where
+    T: IntoIterator<Item = I>,
+    I: Into<BgpAS>,
+{
+    fn from(v: T) -> Self {
+        CompactASPath {
+            value: v.into_iter().map(|q| q.into()).collect(),
+        }
+    }
+}
+impl std::fmt::Display for CompactASPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ASPath {:?}", self.value)
+    }
+}
+
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct CompactCommunityList {
+    /// sorted, without duplicates
+    pub value: Box<[BgpCommunity]>,
+}
+impl CompactCommunityList {
+    pub fn new() -> CompactCommunityList {
+        CompactCommunityList {
+            value: Box::new([]),
+        }
+    }
+    pub fn from_vec(mut v: Vec<BgpCommunity>) -> CompactCommunityList {
+        v.sort();
+        v.dedup();
+        CompactCommunityList {
+            value: v.into_boxed_slice(),
+        }
+    }
+    pub fn contains(&self, c: &BgpCommunity) -> bool {
+        self.value.binary_search(c).is_ok()
+    }
+    pub fn len(&self) -> usize {
+        self.value.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+    /// zettabgp form, for code working with wire attributes
+    pub fn to_list(&self) -> BgpCommunityList {
+        BgpCommunityList::from_vec(self.value.to_vec())
+    }
+}
+impl From<&BgpCommunityList> for CompactCommunityList {
+    fn from(v: &BgpCommunityList) -> Self {
+        // tree set iterates in order
+        CompactCommunityList {
+            value: v.value.iter().cloned().collect(),
+        }
+    }
+}
+impl<'de> Deserialize<'de> for CompactCommunityList {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(CompactCommunityList::from_vec(Vec::deserialize(
+            deserializer,
+        )?))
+    }
+}
+impl std::fmt::Display for CompactCommunityList {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.to_list().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_aspath() {
+        let mut set = std::collections::BTreeSet::new();
+        set.insert(BgpAS::new(64503));
+        set.insert(BgpAS::new(64502));
+        let path = BgpASpath {
+            value: vec![
+                BgpASitem::Seq(BgpASseq {
+                    value: vec![BgpAS::new(64500), BgpAS::new(64501)],
+                }),
+                BgpASitem::Set(BgpASset { value: set }),
+            ],
+        };
+        let cpath = CompactASPath::from_aspath(&path);
+        assert!(!cpath.value.spilled());
+        assert_eq!(cpath, CompactASPath::from(vec![64500, 64501, 64502, 64503]));
+        assert_eq!(
+            serde_json::to_string(&cpath).unwrap(),
+            "[64500,64501,64502,64503]"
+        );
+        assert_eq!(CompactASPath::from_aspath(&cpath.to_aspath()), cpath);
+        assert_eq!(CompactASPath::new().to_aspath(), BgpASpath::new());
+        let long = CompactASPath::from(vec![64500; ASPATH_INLINE + 1]);
+        assert_eq!(long.value.len(), ASPATH_INLINE + 1);
+    }
+    #[test]
+    fn test_compact_communities() {
+        let cl = BgpCommunityList::from_vec(vec![
+            BgpCommunity::from(65000, 2),
+            BgpCommunity::from(65000, 1),
+        ]);
+        let ccl = CompactCommunityList::from(&cl);
+        assert!(ccl.contains(&BgpCommunity::from(65000, 1)));
+        assert!(!ccl.contains(&BgpCommunity::from(65000, 3)));
+        let json = serde_json::to_string(&ccl).unwrap();
+        assert_eq!(json, serde_json::to_string(&cl).unwrap());
+        let back: CompactCommunityList = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, ccl);
+        assert_eq!(
+            CompactCommunityList::from_vec(vec![
+                BgpCommunity::from(65000, 2),
+                BgpCommunity::from(65000, 1),
+                BgpCommunity::from(65000, 2),
+            ]),
+            ccl
+        );
+        assert_eq!(ccl.to_string(), cl.to_string());
+    }
+}
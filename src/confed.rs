@@ -0,0 +1,106 @@
+use crate::wireparse::{AS_CONFED_SEQUENCE, AS_CONFED_SET};
+use serde::{Deserialize, Serialize};
+use zettabgp::prelude::*;
+
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfedKind {
+    Sequence,
+    Set,
+}
+
+/// confederation segments taken out of AS_PATH, in path order
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BgpConfedPath {
+    pub segments: Vec<(ConfedKind, Vec<u32>)>,
+}
+impl BgpConfedPath {
+    /// decodes value of internal confederation attribute: segment type, count and
+    /// 4-octet members of each segment
+    pub fn decode_from(buf: &[u8]) -> Result<BgpConfedPath, BgpError> {
+        let mut ret = BgpConfedPath::default();
+        let mut pos: usize = 0;
+        while pos < buf.len() {
+            if pos + 2 > buf.len() {
+                return Err(BgpError::static_str(
+                    "Confederation segment header truncated",
+                ));
+            }
+            let kind = match buf[pos] {
+                AS_CONFED_SEQUENCE => ConfedKind::Sequence,
+                AS_CONFED_SET => ConfedKind::Set,
+                _ => return Err(BgpError::static_str("Invalid confederation segment type")),
+            };
+            let end = pos + 2 + buf[pos + 1] as usize * 4;
+            if end > buf.len() {
+                return Err(BgpError::static_str("Confederation segment truncated"));
+            }
+            let asns = buf[pos + 2..end]
+                .chunks_exact(4)
+                .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+                .collect();
+            ret.segments.push((kind, asns));
+            pos = end;
+        }
+        Ok(ret)
+    }
+    /// members put in front of AS path with confed_in_aspath, set is one hop of its lowest
+    /// member as set counts once in path length
+    pub fn leading_asns(&self) -> Vec<u32> {
+        let mut ret = Vec::new();
+        for (kind, asns) in self.segments.iter() {
+            match kind {
+                ConfedKind::Sequence => ret.extend_from_slice(asns),
+                ConfedKind::Set => ret.extend(asns.iter().min()),
+            }
+        }
+        ret
+    }
+}
+/// "(65001 65002 {65003 65004})"
+impl std::fmt::Display for BgpConfedPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let items: Vec<String> = self
+            .segments
+            .iter()
+            .map(|(kind, asns)| {
+                let s: Vec<String> = asns.iter().map(|a| a.to_string()).collect();
+                match kind {
+                    ConfedKind::Sequence => s.join(" "),
+                    ConfedKind::Set => format!("{{{}}}", s.join(" ")),
+                }
+            })
+            .collect();
+        write!(f, "({})", items.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confed_decode() {
+        let buf = [
+            3, 2, 0, 0, 0xfd, 0xe9, 0, 0, 0xfd, 0xea, // sequence 65001 65002
+            4, 2, 0, 0, 0xfd, 0xec, 0, 0, 0xfd, 0xeb, // set 65004 65003
+        ];
+        let confed = BgpConfedPath::decode_from(&buf).unwrap();
+        assert_eq!(
+            confed.segments,
+            vec![
+                (ConfedKind::Sequence, vec![65001, 65002]),
+                (ConfedKind::Set, vec![65004, 65003])
+            ]
+        );
+        assert_eq!(confed.to_string(), "(65001 65002 {65004 65003})");
+        assert_eq!(confed.leading_asns(), vec![65001, 65002, 65003]);
+        assert_eq!(
+            serde_json::to_string(&confed).unwrap(),
+            r#"[["sequence",[65001,65002]],["set",[65004,65003]]]"#
+        );
+        assert!(BgpConfedPath::decode_from(&buf[..8]).is_err());
+        assert!(BgpConfedPath::decode_from(&[2, 0]).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+//! service configuration parsed from bgpexplorer.ini
 use std::error::Error;
 use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
@@ -6,6 +7,9 @@ use std::sync::Arc;
 use whois_rust::WhoIs;
 use zettabgp::prelude::*;
 
+/// BGP hold time offered in OPEN, keepalives are sent every third of it
+pub const HOLD_TIME: u16 = 180;
+
 /// peer protocol mode
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PeerMode {
@@ -26,6 +30,32 @@ pub enum HistoryChangeMode {
     /// history record made only if route attributes is differ
     OnlyDiffer,
 }
+/// HTTP protocol versions served
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersionMode {
+    /// HTTP/1.1 and HTTP/2 (prior knowledge)
+    Auto,
+    Http1Only,
+    Http2Only,
+}
+/// best path marking mode for routes received from multiple sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BestPathMode {
+    /// show all pathes without marking
+    All,
+    /// mark path selected by standard BGP tie-breakers
+    Standard,
+}
+/// hot standby replication role
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicationMode {
+    /// no replication
+    Off,
+    /// serve RIB snapshots to secondary
+    Primary,
+    /// mirror primary RIB and take over when it is lost
+    Secondary,
+}
 
 /// peer
 #[derive(Debug, Clone)]
@@ -38,6 +68,20 @@ pub struct ProtoPeer {
     pub flt_rd: Option<zettabgp::afi::BgpRD>,
     pub bgpsessionparams: Arc<std::sync::Mutex<Option<BgpSessionParams>>>,
     pub caps: Vec<BgpCapability>,
+    /// Extended Next Hop Encoding capability (RFC 8950) for active session
+    pub ext_nexthop: bool,
+    /// AS presented to peer instead of peeras, or instead of mirrored one in passive mode
+    pub localas: Option<u32>,
+    /// do not prepend localas to AS path of received eBGP routes
+    pub localas_no_prepend: bool,
+    /// GTSM minimum TTL of received packets, sent ones get TTL 255
+    pub ttlsecurity: Option<u8>,
+    /// TTL of packets sent by active session
+    pub ebgp_multihop: Option<u8>,
+    /// source address of active session
+    pub localaddr: Option<IpAddr>,
+    /// workarounds for peer platform deviations
+    pub vendor_quirks: crate::quirks::Quirks,
 }
 impl PartialEq for ProtoPeer {
     fn eq(&self, other: &Self) -> bool {
@@ -186,6 +230,86 @@ impl ProtoPeer {
         } else {
             Some(zettabgp::afi::BgpRD::new(0, 0))
         };
+        let localas: Option<u32> = match svcsection.get("localas") {
+            Some(Some(s)) => match s.parse() {
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!("Invalid localas - {}", e)));
+                }
+                Ok(a) => Some(a),
+            },
+            _ => None,
+        };
+        let localas_no_prepend = match svcsection.get("localas_no_prepend") {
+            Some(Some(s)) => match s.trim().parse() {
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid localas_no_prepend - {}",
+                        e
+                    )));
+                }
+                Ok(a) => a,
+            },
+            _ => false,
+        };
+        let ttlsecurity: Option<u8> = match svcsection.get("ttlsecurity") {
+            Some(Some(s)) => match s.parse::<u8>() {
+                Ok(0) => None,
+                Ok(n) => Some(n),
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid ttlsecurity - {}",
+                        e
+                    )));
+                }
+            },
+            _ => None,
+        };
+        let ebgp_multihop: Option<u8> = match svcsection.get("ebgp_multihop") {
+            Some(Some(s)) => match s.parse::<u8>() {
+                Ok(0) => {
+                    return Err(ErrorConfig::from_str(
+                        "Invalid ebgp_multihop - TTL must be 1..255",
+                    ));
+                }
+                Ok(n) => Some(n),
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid ebgp_multihop - {}",
+                        e
+                    )));
+                }
+            },
+            _ => None,
+        };
+        let localaddr: Option<IpAddr> = match svcsection.get("localaddr") {
+            Some(Some(s)) => match s.parse() {
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid localaddr - {}",
+                        e
+                    )));
+                }
+                Ok(a) => Some(a),
+            },
+            _ => None,
+        };
+        if let (Some(la), Some(p)) = (localaddr.as_ref(), peer.as_ref()) {
+            if la.is_ipv4() != p.is_ipv4() {
+                return Err(ErrorConfig::from_str(
+                    "localaddr and peer address families differ",
+                ));
+            }
+        }
+        let vendor_quirks: crate::quirks::Quirks = match svcsection.get("vendor_quirks") {
+            Some(Some(s)) => s
+                .parse()
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid vendor_quirks - {}", e)))?,
+            _ => Default::default(),
+        };
+        let ext_nexthop = match svcsection.get("caps") {
+            Some(Some(s)) => s.split(',').any(|c| c == "extnexthop"),
+            _ => false,
+        };
         let caps: Vec<BgpCapability> = if svcsection.contains_key("caps") {
             match svcsection["caps"]
                 .as_ref()
@@ -210,6 +334,7 @@ impl ProtoPeer {
                     for cs in cps {
                         match cs {
                             "ipv4u" => caps.push(BgpCapability::SafiIPv4u),
+                            "ipv4m" => caps.push(BgpCapability::SafiIPv4m),
                             "ipv4lu" => caps.push(BgpCapability::SafiIPv4lu),
                             "vpnv4u" => caps.push(BgpCapability::SafiVPNv4u),
                             "vpnv4m" => caps.push(BgpCapability::SafiVPNv4m),
@@ -224,6 +349,7 @@ impl ProtoPeer {
                             "vpnv6m" => caps.push(BgpCapability::SafiVPNv6m),
                             "ipv6mdt" => caps.push(BgpCapability::SafiIPv6mdt),
                             "addpath" => addpath = true,
+                            "extnexthop" => {}
                             x => warn!("Unknown capability code: {}", x),
                         }
                     }
@@ -259,6 +385,13 @@ impl ProtoPeer {
             flt_rd,
             bgpsessionparams: Arc::new(std::sync::Mutex::new(None)),
             caps,
+            ext_nexthop,
+            localas,
+            localas_no_prepend,
+            ttlsecurity,
+            ebgp_multihop,
+            localaddr,
+            vendor_quirks,
         })
     }
     pub fn set_session_params(&self, params: BgpSessionParams) {
@@ -315,12 +448,13 @@ impl ProtoPeer {
                 SocketAddr::V6(_) => BgpTransportMode::IPv6,
             },
         };
+        let asn = self.localas.unwrap_or(self.bgppeeras);
         let pbsp = BgpSessionParams::new(
-            self.bgppeeras,
-            180,
+            asn,
+            HOLD_TIME,
             peeraddrmode,
             self.routerid,
-            self.def_caps(self.bgppeeras),
+            self.def_caps(asn),
         );
         *lck = Some(pbsp.clone());
         pbsp
@@ -328,22 +462,125 @@ impl ProtoPeer {
 }
 
 #[derive(Debug, Clone)]
+/// all options of main section and peer sections
 pub struct SvcConfig {
-    pub httplisten: std::net::SocketAddr,
+    /// HTTP listen addresses, at least one
+    pub httplisten: Vec<std::net::SocketAddr>,
     pub httproot: String,
+    /// default Web UI language
+    pub ui_language: String,
+    /// directory with <lang>.json and <lang>.po locale bundles
+    pub l10n_dir: String,
+    pub ui: crate::uiconfig::UiSettings,
     pub historydepth: usize,
     pub httptimeout: u64,
+    pub httpversion: HttpVersionMode,
+    pub http2_max_streams: u32,
+    pub http_keepalive: bool,
+    pub http_idle_timeout: Option<std::time::Duration>,
     pub historymode: HistoryChangeMode,
+    /// identical announcements of path within window are not recorded in every mode
+    pub dedup_window: Option<std::time::Duration>,
+    pub bestpath: BestPathMode,
     pub whoisconfig: WhoIs,
     pub whoisdb: String,
     pub whoisreqtimeout: u64,
     pub whoiscachesecs: i64,
+    pub irr_server: String,
     pub whoisdnses: Vec<std::net::SocketAddr>,
     pub peers: Vec<Arc<ProtoPeer>>,
     pub purge_after_withdraws: u64,
+    /// withdrawn routes per second of session coalesced into batch events, 0 - disabled
+    pub withdraw_burst_threshold: usize,
+    /// route mutations kept for /api/changes
+    pub version_journal: usize,
     pub purge_every: chrono::Duration,
     pub snapshot_file: Option<String>,
     pub snapshot_every: Option<chrono::Duration>,
+    pub snapshot_diff_every: Option<chrono::Duration>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub pidfile: Option<String>,
+    pub daemonize: bool,
+    pub replication: ReplicationMode,
+    pub replication_listen: Option<SocketAddr>,
+    pub replication_peer: Option<SocketAddr>,
+    pub replication_interval: std::time::Duration,
+    pub replication_timeout: std::time::Duration,
+    /// shared secret of replication and federation streams
+    pub replication_secret: Option<String>,
+    /// largest accepted replication frame, bytes
+    pub replication_max_frame: usize,
+    pub federation_name: String,
+    pub federation: Vec<(String, SocketAddr)>,
+    pub snmp_agentx: Option<String>,
+    pub snmp_base_oid: Vec<u32>,
+    pub syslog: Option<crate::syslog::SyslogTarget>,
+    pub syslog_facility: u8,
+    pub syslog_watch: Option<String>,
+    pub report_dir: Option<String>,
+    pub report_period: crate::report::ReportPeriod,
+    pub report_top: usize,
+    pub report_email: bool,
+    pub smtp: Option<crate::smtp::SmtpSettings>,
+    pub smtp_alerts: Option<crate::smtp::SmtpAlertSettings>,
+    pub protocoltrace: bool,
+    pub protocoltrace_buffer: usize,
+    pub keep_raw_updates: bool,
+    pub strictparsing: bool,
+    /// eBGP sanity checks treating violating routes as withdrawn
+    pub ebgp_checks: Vec<crate::sanity::Check>,
+    /// keep confederation segments in AS path for origin and path length analytics
+    pub confed_in_aspath: bool,
+    pub processors: Vec<crate::processor::ProcessorConfig>,
+    pub update_script: Option<String>,
+    pub inbound: Option<crate::inbound::InboundFilter>,
+    /// entries of shadow table of routes dropped by inbound lists and script, 0 - disabled
+    pub filtered_shadow_size: usize,
+    pub retention: Vec<crate::retention::RetentionRule>,
+    pub api_tokens: Vec<crate::tenancy::TokenConfig>,
+    pub audit_file: Option<String>,
+    /// runtime settings overrides made via /api/admin/settings
+    pub settings_file: Option<String>,
+    /// /api/subscriptions definitions kept across restarts
+    pub subscriptions_file: Option<String>,
+    /// named views from view: sections
+    pub views: Vec<crate::views::ViewConfig>,
+    /// views created via /api/views kept across restarts
+    pub views_file: Option<String>,
+    /// /api/graphql endpoint is enabled
+    pub graphql: bool,
+    /// validated ROA payloads of rpki_roas file
+    pub rpki_roas: Option<Arc<crate::rpki::RoaTable>>,
+    /// concurrency and per minute limits of API query classes
+    pub query_limits:
+        std::collections::BTreeMap<crate::ratelimit::QueryClass, crate::ratelimit::ClassLimit>,
+    /// pseudonymization of configured AS numbers and prefixes in API output
+    pub anonymize: Option<crate::anonymize::Anonymizer>,
+    /// how long polled change feeds keep changes
+    pub subscription_retention: std::time::Duration,
+    /// subscriptions one API token may create, 0 - unlimited
+    pub subscriptions_per_token: usize,
+    pub clock_skew_threshold: u64,
+    pub passive_idle_timeout: u64,
+    /// quiet BMP router may still be healthy, so off by default
+    pub bmp_idle_timeout: u64,
+    pub query_budget: u64,
+    pub integrity_check_every: Option<std::time::Duration>,
+    pub integrity_repair: bool,
+    pub preload_url: Option<String>,
+    /// largest preload body, bytes
+    pub preload_max_size: usize,
+    pub objstore: Option<crate::objstore::ObjectStoreSettings>,
+    pub json_time_format: crate::jsonfmt::TimeFormat,
+    pub json_prefix_format: crate::jsonfmt::PrefixFormat,
+    pub json_history_format: crate::jsonfmt::HistoryFormat,
+    pub blackhole_communities: crate::blackhole::BlackholeCommunities,
+    pub community_names: Vec<(String, BgpCommunity)>,
+    pub replay_file: Option<String>,
+    pub pcap_file: Option<String>,
+    pub replay_speed: crate::replay::ReplaySpeed,
+    pub replay_original_time: bool,
 }
 
 #[derive(Debug)]
@@ -411,29 +648,444 @@ impl FromStr for HistoryChangeMode {
     }
 }
 
+impl FromStr for HttpVersionMode {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "on" | "auto" => Ok(HttpVersionMode::Auto),
+            "off" => Ok(HttpVersionMode::Http1Only),
+            "only" => Ok(HttpVersionMode::Http2Only),
+            _ => Err(ErrorConfig::from_str("invalid http2 mode")),
+        }
+    }
+}
+
+impl FromStr for BestPathMode {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "all" => Ok(BestPathMode::All),
+            "standard" => Ok(BestPathMode::Standard),
+            _ => Err(ErrorConfig::from_str("invalid best path mode")),
+        }
+    }
+}
+
+impl FromStr for ReplicationMode {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "off" => Ok(ReplicationMode::Off),
+            "primary" => Ok(ReplicationMode::Primary),
+            "secondary" => Ok(ReplicationMode::Secondary),
+            _ => Err(ErrorConfig::from_str("invalid replication mode")),
+        }
+    }
+}
+
+/// options of section over options of its templates: `template = name` refers to
+/// [template:name] section, which may have own template
+pub fn resolve_template(
+    conf: &std::collections::HashMap<String, std::collections::HashMap<String, Option<String>>>,
+    section: &str,
+) -> Result<std::collections::HashMap<String, Option<String>>, ErrorConfig> {
+    let mut ret = match conf.get(section) {
+        Some(s) => s.clone(),
+        None => {
+            return Err(ErrorConfig::from_string(format!(
+                "Missing section {}",
+                section
+            )))
+        }
+    };
+    let mut seen = vec![section.to_string()];
+    let mut next = ret.remove("template").flatten();
+    while let Some(t) = next {
+        let name = format!("template:{}", t.trim());
+        if seen.contains(&name) {
+            return Err(ErrorConfig::from_string(format!(
+                "Template loop at {}",
+                name
+            )));
+        }
+        let tmpl = match conf.get(&name) {
+            Some(s) => s,
+            None => return Err(ErrorConfig::from_string(format!("Unknown template {}", t))),
+        };
+        for (k, v) in tmpl.iter().filter(|(k, _)| k.as_str() != "template") {
+            ret.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+        next = tmpl.get("template").cloned().flatten();
+        seen.push(name);
+    }
+    Ok(ret)
+}
+
+/// time option value: number of seconds or units like "90s", "5m", "2h30m";
+/// units are s, m, h, d and w
+pub fn parse_duration(s: &str) -> Result<std::time::Duration, ErrorConfig> {
+    let s = s.trim();
+    let err = || {
+        ErrorConfig::from_string(format!(
+            "invalid duration {}, expected seconds or like 90s, 5m, 2h30m",
+            s
+        ))
+    };
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(std::time::Duration::from_secs(secs));
+    }
+    let mut total: u64 = 0;
+    let mut num: Option<u64> = None;
+    for c in s.chars() {
+        if let Some(d) = c.to_digit(10) {
+            num = Some(
+                num.unwrap_or(0)
+                    .checked_mul(10)
+                    .and_then(|n| n.checked_add(d as u64))
+                    .ok_or_else(err)?,
+            );
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return Err(err()),
+        };
+        total = num
+            .take()
+            .ok_or_else(err)?
+            .checked_mul(unit)
+            .and_then(|v| total.checked_add(v))
+            .ok_or_else(err)?;
+    }
+    if s.is_empty() || num.is_some() {
+        return Err(err());
+    }
+    Ok(std::time::Duration::from_secs(total))
+}
+
+/// time option of section, default when absent; malformed value is an error
+fn duration_option(
+    section: &std::collections::HashMap<String, Option<String>>,
+    name: &str,
+    default: std::time::Duration,
+) -> Result<std::time::Duration, ErrorConfig> {
+    match section.get(name) {
+        Some(Some(s)) => parse_duration(s)
+            .map_err(|e| ErrorConfig::from_string(format!("Invalid {} - {}", name, e))),
+        _ => Ok(default),
+    }
+}
+
+/// time option as chrono duration, values beyond chrono range are an error
+fn chrono_duration(name: &str, d: std::time::Duration) -> Result<chrono::Duration, ErrorConfig> {
+    chrono::Duration::from_std(d)
+        .map_err(|e| ErrorConfig::from_string(format!("Invalid {} - {}", name, e)))
+}
+
+/// shell-like match of file name against pattern with * and ?
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], name)
+                || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// files matching include pattern, relative patterns are resolved against main config
+/// directory; wildcards are allowed in file name only
+pub fn include_files(
+    pattern: &str,
+    basedir: &std::path::Path,
+) -> Result<Vec<std::path::PathBuf>, ErrorConfig> {
+    let path = basedir.join(pattern.trim());
+    let fname = match path.file_name() {
+        Some(f) => f.to_string_lossy().to_string(),
+        None => {
+            return Err(ErrorConfig::from_string(format!(
+                "Invalid include {}",
+                pattern
+            )))
+        }
+    };
+    if !fname.contains('*') && !fname.contains('?') {
+        return Ok(vec![path]);
+    }
+    let dir = path.parent().unwrap_or(basedir);
+    let mut ret: Vec<std::path::PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| ErrorConfig::from_string(format!("include {} - {}", dir.display(), e)))?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| match p.file_name() {
+            Some(n) => wildcard_match(fname.as_bytes(), n.to_string_lossy().as_bytes()),
+            None => false,
+        })
+        .collect();
+    ret.sort();
+    Ok(ret)
+}
+
+/// adds sections of files from main section include option (comma separated patterns),
+/// included files can not redefine main or already defined sections
+pub fn load_includes(
+    conf: &mut std::collections::HashMap<String, std::collections::HashMap<String, Option<String>>>,
+    basedir: &std::path::Path,
+) -> Result<(), ErrorConfig> {
+    let patterns = match conf.get("main").and_then(|m| m.get("include")) {
+        Some(Some(s)) => s.clone(),
+        _ => return Ok(()),
+    };
+    for pattern in patterns.split(',').filter(|p| !p.trim().is_empty()) {
+        for file in include_files(pattern, basedir)? {
+            let fname = file.to_string_lossy().to_string();
+            let included = ini!(safe fname.as_str())
+                .map_err(|e| ErrorConfig::from_string(format!("include {} - {}", fname, e)))?;
+            for (name, section) in included {
+                if name == "main" {
+                    return Err(ErrorConfig::from_string(format!(
+                        "include {} - main section is not allowed in included file",
+                        fname
+                    )));
+                }
+                // options before first section header
+                if name == "default" && section.is_empty() {
+                    continue;
+                }
+                if conf.contains_key(&name) {
+                    return Err(ErrorConfig::from_string(format!(
+                        "include {} - section {} is already defined",
+                        fname, name
+                    )));
+                }
+                conf.insert(name, section);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// sections other than main, token, view and template ones describe peers
+fn is_peer_section(name: &str) -> bool {
+    name != "main"
+        && !name.starts_with("token:")
+        && !name.starts_with("view:")
+        && !name.starts_with("template:")
+}
+
+/// peer options which only BMP modes use
+const BMP_ONLY_OPTIONS: &[&str] = &["filter_rd"];
+/// peer options which only BGP modes use
+const BGP_ONLY_OPTIONS: &[&str] = &["ttlsecurity", "ebgp_multihop"];
+/// peer options which only active modes use
+const ACTIVE_ONLY_OPTIONS: &[&str] = &["ebgp_multihop", "localaddr"];
+
+/// same port on same or wildcard address
+fn listen_collides(a: &SocketAddr, b: &SocketAddr) -> bool {
+    a.port() == b.port() && (a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified())
+}
+
+/// semantic conflicts between options which parse fine one by one,
+/// each as "[section] key: problem"
+pub fn check_conflicts(
+    conf: &std::collections::HashMap<String, std::collections::HashMap<String, Option<String>>>,
+    httplisten: &[SocketAddr],
+    replication_listen: Option<&SocketAddr>,
+) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut names: Vec<&String> = conf.keys().filter(|k| is_peer_section(k)).collect();
+    names.sort();
+    let mut listens: Vec<(&String, PeerMode, SocketAddr)> = Vec::new();
+    for name in names {
+        // broken peers are reported by parser itself
+        let section = match resolve_template(conf, name) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let peer = match ProtoPeer::from_ini(&section) {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        let mode = section["mode"].clone().unwrap_or_default();
+        let bmp = peer.mode == PeerMode::BmpActive || peer.mode == PeerMode::BmpPassive;
+        for key in BMP_ONLY_OPTIONS
+            .iter()
+            .filter(|k| !bmp && section.contains_key(**k))
+        {
+            ret.push(format!(
+                "[{}] {}: used only in bmpactive and bmppassive modes, mode is {}",
+                name, key, mode
+            ));
+        }
+        for key in BGP_ONLY_OPTIONS
+            .iter()
+            .filter(|k| bmp && section.contains_key(**k))
+        {
+            ret.push(format!(
+                "[{}] {}: used only in bgpactive and bgppassive modes, mode is {}",
+                name, key, mode
+            ));
+        }
+        if peer.ttlsecurity.is_some() && peer.ebgp_multihop.is_some() {
+            ret.push(format!(
+                "[{}] ebgp_multihop: ignored, ttlsecurity sends packets with TTL 255",
+                name
+            ));
+        }
+        if peer.mode == PeerMode::BgpPassive || peer.mode == PeerMode::BmpPassive {
+            for key in ACTIVE_ONLY_OPTIONS
+                .iter()
+                .filter(|k| section.contains_key(**k))
+            {
+                ret.push(format!(
+                    "[{}] {}: used only in bgpactive and bmpactive modes, mode is {}",
+                    name, key, mode
+                ));
+            }
+        }
+        if peer.mode == PeerMode::BgpActive || peer.mode == PeerMode::BmpActive {
+            if section.contains_key("protolisten") {
+                ret.push(format!(
+                    "[{}] protolisten: ignored in {} mode, set mode to {} to accept connections",
+                    name,
+                    mode,
+                    if bmp { "bmppassive" } else { "bgppassive" }
+                ));
+            }
+            continue;
+        }
+        let listen = match peer.protolisten {
+            Some(l) => l,
+            None => continue,
+        };
+        for hl in httplisten.iter().filter(|hl| listen_collides(&listen, hl)) {
+            ret.push(format!(
+                "[{}] protolisten: {} collides with [main] httplisten {}",
+                name, listen, hl
+            ));
+        }
+        if let Some(rl) = replication_listen.filter(|rl| listen_collides(&listen, rl)) {
+            ret.push(format!(
+                "[{}] protolisten: {} collides with [main] replication_listen {}",
+                name, listen, rl
+            ));
+        }
+        // connections are dispatched by listen address, so mixed protocols can not share it
+        if let Some((other, _, _)) = listens
+            .iter()
+            .find(|(_, m, l)| *l == listen && *m != peer.mode)
+        {
+            ret.push(format!(
+                "[{}] protolisten: {} is used by [{}] with other protocol",
+                name, listen, other
+            ));
+        }
+        listens.push((name, peer.mode.clone(), listen));
+    }
+    if let Some(rl) = replication_listen {
+        for hl in httplisten.iter().filter(|hl| listen_collides(rl, hl)) {
+            ret.push(format!(
+                "[main] replication_listen: {} collides with httplisten {}",
+                rl, hl
+            ));
+        }
+    }
+    ret
+}
+
+/// options which work but likely not as intended, logged as warnings
+pub fn check_warnings(peers: &[Arc<ProtoPeer>], purge_every: chrono::Duration) -> Vec<String> {
+    let mut ret = Vec::new();
+    let keepalive = chrono::Duration::seconds(HOLD_TIME as i64 / 3);
+    let bgp_peers = peers
+        .iter()
+        .any(|p| p.mode == PeerMode::BgpActive || p.mode == PeerMode::BgpPassive);
+    if bgp_peers && purge_every < keepalive {
+        ret.push(format!(
+            "[main] purge_every: {}s is shorter than BGP keepalive interval {}s",
+            purge_every.num_seconds(),
+            keepalive.num_seconds()
+        ));
+    }
+    ret
+}
+
 impl SvcConfig {
+    /// makes relative file and directory options absolute against base, so they still
+    /// point to the same files after daemonize changes working directory to /
+    pub fn resolve_paths(&mut self, base: &std::path::Path) {
+        let abs = |p: &mut String| {
+            if std::path::Path::new(p.as_str()).is_relative() {
+                *p = base.join(p.as_str()).to_string_lossy().to_string();
+            }
+        };
+        abs(&mut self.httproot);
+        abs(&mut self.l10n_dir);
+        abs(&mut self.whoisdb);
+        for p in [
+            &mut self.snapshot_file,
+            &mut self.pidfile,
+            &mut self.report_dir,
+            &mut self.update_script,
+            &mut self.audit_file,
+            &mut self.settings_file,
+            &mut self.subscriptions_file,
+            &mut self.views_file,
+            &mut self.replay_file,
+            &mut self.pcap_file,
+        ] {
+            if let Some(p) = p.as_mut() {
+                abs(p);
+            }
+        }
+        for pc in self.processors.iter_mut() {
+            if let Some(p) = pc.options.get_mut("path") {
+                abs(p);
+            }
+        }
+    }
+    /// parses and validates ini file
     pub fn from_inifile(inifile: &str) -> Result<SvcConfig, ErrorConfig> {
-        let conf = ini!(inifile);
+        let mut conf = ini!(inifile);
+        let basedir = std::path::Path::new(inifile)
+            .parent()
+            .unwrap_or_else(|| std::path::Path::new("."));
+        load_includes(&mut conf, basedir)?;
+        crate::secrets::resolve_secrets(&mut conf)?;
         if !conf.contains_key("main") {
             return Err(ErrorConfig::from_str("Missing section 'main' in ini file"));
         }
         let mainsection = &conf["main"];
         let peers: Vec<Arc<ProtoPeer>> = conf
             .iter()
-            .filter(|x| x.0 != "main")
-            .filter_map(|x| match ProtoPeer::from_ini(x.1) {
-                Err(e) => {
-                    warn!("Peer {} error: {}", x.0, e);
-                    None
+            .filter(|x| is_peer_section(x.0))
+            .filter_map(|x| {
+                match resolve_template(&conf, x.0).and_then(|s| ProtoPeer::from_ini(&s)) {
+                    Err(e) => {
+                        warn!("Peer {} error: {}", x.0, e);
+                        None
+                    }
+                    Ok(p) => Some(p),
                 }
-                Ok(p) => Some(p),
             })
             .map(Arc::new)
             .collect();
         if peers.is_empty() {
             return Err(ErrorConfig::from_str("No valid peers or listens specified"));
         }
-        let httplisten: std::net::SocketAddr = match (if mainsection.contains_key("httplisten") {
+        let mut httplisten = Vec::<std::net::SocketAddr>::new();
+        for s in (if mainsection.contains_key("httplisten") {
             match mainsection["httplisten"] {
                 Some(ref s) => s.to_string(),
                 None => "0.0.0.0:8080".to_string(),
@@ -441,23 +1093,93 @@ impl SvcConfig {
         } else {
             "0.0.0.0:8080".to_string()
         })
-        .parse()
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
         {
-            Ok(sa) => sa,
-            Err(e) => {
-                return Err(ErrorConfig::from_string(format!(
-                    "Invalid httplisten - {}",
-                    e
-                )));
+            match s.parse() {
+                Ok(sa) if !httplisten.contains(&sa) => httplisten.push(sa),
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid httplisten {} - {}",
+                        s, e
+                    )));
+                }
             }
+        }
+        if httplisten.is_empty() {
+            return Err(ErrorConfig::from_str("Invalid httplisten - no address"));
+        }
+        let httptimeout = duration_option(
+            mainsection,
+            "httptimeout",
+            std::time::Duration::from_secs(120),
+        )?
+        .as_secs();
+        let httpversion: HttpVersionMode = if mainsection.contains_key("http2") {
+            match mainsection["http2"] {
+                None => {
+                    return Err(ErrorConfig::from_str("invalid http2 was specified"));
+                }
+                Some(ref s) => match s.parse() {
+                    Err(e) => {
+                        return Err(ErrorConfig::from_string(format!("Invalid http2 - {}", e)));
+                    }
+                    Ok(a) => a,
+                },
+            }
+        } else {
+            HttpVersionMode::Auto
+        };
+        let http2_max_streams: u32 = if mainsection.contains_key("http2_max_streams") {
+            match mainsection["http2_max_streams"] {
+                Some(ref s) => match s.trim().parse::<u32>() {
+                    Ok(n) if n > 0 => n,
+                    _ => {
+                        return Err(ErrorConfig::from_string(format!(
+                            "Invalid http2_max_streams - {}",
+                            s
+                        )));
+                    }
+                },
+                None => 100,
+            }
+        } else {
+            100
         };
-        let httptimeout = if mainsection.contains_key("httptimeout") {
-            match mainsection["httptimeout"] {
-                Some(ref s) => s.parse().unwrap_or(120),
-                None => 120,
+        let http_keepalive: bool = if mainsection.contains_key("http_keepalive") {
+            match mainsection["http_keepalive"] {
+                Some(ref s) => match s.trim().parse() {
+                    Err(e) => {
+                        return Err(ErrorConfig::from_string(format!(
+                            "Invalid http_keepalive - {}",
+                            e
+                        )));
+                    }
+                    Ok(a) => a,
+                },
+                None => true,
             }
         } else {
-            120
+            true
+        };
+        let http_idle_timeout = if mainsection.contains_key("http_idle_timeout") {
+            match mainsection["http_idle_timeout"] {
+                Some(ref s) => match parse_duration(s) {
+                    Err(e) => {
+                        return Err(ErrorConfig::from_string(format!(
+                            "Invalid http_idle_timeout - {}",
+                            e
+                        )));
+                    }
+                    Ok(a) if a.as_secs() == 0 => None,
+                    Ok(a) => Some(a),
+                },
+                None => None,
+            }
+        } else {
+            None
         };
         let httproot = if mainsection.contains_key("httproot") {
             match mainsection["httproot"] {
@@ -472,16 +1194,739 @@ impl SvcConfig {
         } else {
             None
         };
+        let replication: ReplicationMode = if mainsection.contains_key("replication") {
+            match mainsection["replication"] {
+                None => {
+                    return Err(ErrorConfig::from_str("invalid replication was specified"));
+                }
+                Some(ref s) => match s.parse() {
+                    Err(e) => {
+                        return Err(ErrorConfig::from_string(format!(
+                            "Invalid replication - {}",
+                            e
+                        )));
+                    }
+                    Ok(a) => a,
+                },
+            }
+        } else {
+            ReplicationMode::Off
+        };
+        let mut replication_listen: Option<SocketAddr> = None;
+        let mut replication_peer: Option<SocketAddr> = None;
+        for (key, val) in [
+            ("replication_listen", &mut replication_listen),
+            ("replication_peer", &mut replication_peer),
+        ] {
+            if mainsection.contains_key(key) {
+                if let Some(ref s) = mainsection[key] {
+                    match s.parse() {
+                        Ok(sa) => *val = Some(sa),
+                        Err(e) => {
+                            return Err(ErrorConfig::from_string(format!(
+                                "Invalid {} - {}",
+                                key, e
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+        if replication == ReplicationMode::Primary && replication_listen.is_none() {
+            return Err(ErrorConfig::from_str(
+                "replication_listen is required for primary replication mode",
+            ));
+        }
+        if replication == ReplicationMode::Secondary && replication_peer.is_none() {
+            return Err(ErrorConfig::from_str(
+                "replication_peer is required for secondary replication mode",
+            ));
+        }
+        let replication_interval = duration_option(
+            mainsection,
+            "replication_interval",
+            std::time::Duration::from_secs(10),
+        )?
+        .max(std::time::Duration::from_secs(1));
+        let replication_timeout = duration_option(
+            mainsection,
+            "replication_timeout",
+            std::time::Duration::from_secs(30),
+        )?;
+        let federation_name = if mainsection.contains_key("federation_name") {
+            mainsection["federation_name"]
+                .as_ref()
+                .map(|s| s.trim().to_string())
+                .unwrap_or_else(|| "local".to_string())
+        } else {
+            "local".to_string()
+        };
+        let mut federation = Vec::<(String, SocketAddr)>::new();
+        if mainsection.contains_key("federation") {
+            if let Some(ref s) = mainsection["federation"] {
+                for src in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+                    let (name, addr) = match src.split_once('=') {
+                        Some((n, a)) => (n.trim().to_string(), a.trim()),
+                        None => (src.to_string(), src),
+                    };
+                    match addr.parse() {
+                        Ok(sa) => federation.push((name, sa)),
+                        Err(e) => {
+                            return Err(ErrorConfig::from_string(format!(
+                                "Invalid federation source {} - {}",
+                                src, e
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+        let replication_secret = match mainsection.get("replication_secret") {
+            Some(Some(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            _ => None,
+        };
+        if replication_secret.is_none()
+            && (replication != ReplicationMode::Off || !federation.is_empty())
+        {
+            return Err(ErrorConfig::from_str(
+                "replication_secret is required for replication and federation",
+            ));
+        }
+        let replication_max_frame = match mainsection.get("replication_max_frame_mb") {
+            Some(Some(s)) => match s.trim().parse::<usize>() {
+                Ok(n) if n > 0 => n.saturating_mul(1024 * 1024),
+                _ => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid replication_max_frame_mb - {}",
+                        s
+                    )));
+                }
+            },
+            _ => 4096usize.saturating_mul(1024 * 1024),
+        };
+        let snmp_agentx = if mainsection.contains_key("snmp_agentx") {
+            mainsection["snmp_agentx"].as_ref().map(|s| s.to_string())
+        } else {
+            None
+        };
+        let snmp_base_oid = match if mainsection.contains_key("snmp_base_oid") {
+            mainsection["snmp_base_oid"].as_ref().map(|s| s.to_string())
+        } else {
+            None
+        } {
+            None => vec![1, 3, 6, 1, 4, 1, 8072, 9999, 9999, 179],
+            Some(s) => match crate::snmp::parse_oid(s.as_str()) {
+                Some(o) => o,
+                None => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid snmp_base_oid - {}",
+                        s
+                    )));
+                }
+            },
+        };
+        let syslog: Option<crate::syslog::SyslogTarget> = if mainsection.contains_key("syslog") {
+            match mainsection["syslog"] {
+                None => None,
+                Some(ref s) => match s.parse() {
+                    Err(e) => {
+                        return Err(ErrorConfig::from_string(format!("Invalid syslog - {}", e)));
+                    }
+                    Ok(a) => Some(a),
+                },
+            }
+        } else {
+            None
+        };
+        let syslog_facility: u8 = if mainsection.contains_key("syslog_facility") {
+            match mainsection["syslog_facility"]
+                .as_ref()
+                .and_then(|s| crate::syslog::parse_facility(s))
+            {
+                Some(f) => f,
+                None => return Err(ErrorConfig::from_str("Invalid syslog_facility")),
+            }
+        } else {
+            3
+        };
+        let syslog_watch = if mainsection.contains_key("syslog_watch") {
+            mainsection["syslog_watch"].as_ref().map(|s| s.to_string())
+        } else {
+            None
+        };
+        let report_dir = if mainsection.contains_key("report_dir") {
+            mainsection["report_dir"].as_ref().map(|s| s.to_string())
+        } else {
+            None
+        };
+        let report_period: crate::report::ReportPeriod =
+            if mainsection.contains_key("report_period") {
+                match mainsection["report_period"] {
+                    None => {
+                        return Err(ErrorConfig::from_str("invalid report_period was specified"));
+                    }
+                    Some(ref s) => match s.parse() {
+                        Err(e) => {
+                            return Err(ErrorConfig::from_string(format!(
+                                "Invalid report_period - {}",
+                                e
+                            )));
+                        }
+                        Ok(a) => a,
+                    },
+                }
+            } else {
+                crate::report::ReportPeriod::Daily
+            };
+        let report_top: usize = if mainsection.contains_key("report_top") {
+            mainsection["report_top"]
+                .as_ref()
+                .map(|s| s.parse().unwrap_or(20))
+                .unwrap_or(20)
+        } else {
+            20
+        };
+        let report_email: bool = if mainsection.contains_key("report_email") {
+            match mainsection["report_email"] {
+                Some(ref s) => s.parse().unwrap_or(false),
+                None => false,
+            }
+        } else {
+            false
+        };
+        let smtp = if mainsection.contains_key("smtp_relay") {
+            match mainsection["smtp_relay"] {
+                None => None,
+                Some(ref relay) => {
+                    let from = match mainsection.get("smtp_from") {
+                        Some(Some(s)) => s.to_string(),
+                        _ => return Err(ErrorConfig::from_str("smtp_from is required")),
+                    };
+                    let to: Vec<String> = match mainsection.get("smtp_to") {
+                        Some(Some(s)) => s
+                            .split(',')
+                            .map(|x| x.trim().to_string())
+                            .filter(|x| !x.is_empty())
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    if to.is_empty() {
+                        return Err(ErrorConfig::from_str("smtp_to is required"));
+                    }
+                    let tls: crate::smtp::SmtpTls = match mainsection.get("smtp_tls") {
+                        Some(Some(s)) => match s.parse() {
+                            Ok(t) => t,
+                            Err(e) => {
+                                return Err(ErrorConfig::from_string(format!(
+                                    "Invalid smtp_tls - {}",
+                                    e
+                                )));
+                            }
+                        },
+                        _ => crate::smtp::SmtpTls::None,
+                    };
+                    let user = mainsection
+                        .get("smtp_user")
+                        .and_then(|s| s.as_ref().map(|s| s.to_string()));
+                    if user.is_some() && tls == crate::smtp::SmtpTls::None {
+                        return Err(ErrorConfig::from_str(
+                            "smtp_user requires smtp_tls starttls or tls",
+                        ));
+                    }
+                    Some(crate::smtp::SmtpSettings {
+                        relay: relay.to_string(),
+                        tls,
+                        user,
+                        password: mainsection
+                            .get("smtp_password")
+                            .and_then(|s| s.as_ref().map(|s| s.to_string())),
+                        from,
+                        to,
+                    })
+                }
+            }
+        } else {
+            None
+        };
+        let smtp_watch = match mainsection.get("smtp_watch") {
+            Some(Some(s)) if !s.trim().is_empty() => Some(s.to_string()),
+            _ => None,
+        };
+        let hijack_prefixes = match mainsection.get("smtp_hijack_prefixes") {
+            Some(Some(s)) => crate::inbound::PrefixList::parse(s).map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid smtp_hijack_prefixes - {}", e))
+            })?,
+            _ => crate::inbound::PrefixList::default(),
+        };
+        let hijack_origins = match mainsection.get("smtp_hijack_origins") {
+            Some(Some(s)) => crate::inbound::InboundFilter::parse_origins(s).map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid smtp_hijack_origins - {}", e))
+            })?,
+            _ => std::collections::BTreeSet::new(),
+        };
+        if !hijack_prefixes.is_empty() && hijack_origins.is_empty() {
+            return Err(ErrorConfig::from_str(
+                "smtp_hijack_prefixes requires smtp_hijack_origins",
+            ));
+        }
+        let smtp_alerts = match (smtp_watch, hijack_prefixes.is_empty()) {
+            (None, true) => None,
+            (watch, _) => {
+                if smtp.is_none() {
+                    return Err(ErrorConfig::from_str(
+                        "smtp_watch and smtp_hijack_prefixes require smtp_relay",
+                    ));
+                }
+                Some(crate::smtp::SmtpAlertSettings {
+                    watch,
+                    hijack_prefixes,
+                    hijack_origins,
+                    subject: match mainsection.get("smtp_alert_subject") {
+                        Some(Some(s)) => s.to_string(),
+                        _ => "bgpexplorer: {count} alerts on {hostname}".to_string(),
+                    },
+                    body: match mainsection.get("smtp_alert_body") {
+                        Some(Some(s)) => s.to_string(),
+                        _ => "Alerts on {hostname}:\\n{events}".to_string(),
+                    },
+                    batch: duration_option(
+                        mainsection,
+                        "smtp_alert_batch",
+                        std::time::Duration::from_secs(60),
+                    )?,
+                    max_events: match mainsection.get("smtp_alert_max") {
+                        Some(Some(s)) => s.trim().parse().map_err(|e| {
+                            ErrorConfig::from_string(format!("Invalid smtp_alert_max - {}", e))
+                        })?,
+                        _ => 100,
+                    },
+                })
+            }
+        };
+        let protocoltrace: bool = if mainsection.contains_key("protocoltrace") {
+            match mainsection["protocoltrace"] {
+                Some(ref s) => s.parse().unwrap_or(false),
+                None => false,
+            }
+        } else {
+            false
+        };
+        let protocoltrace_buffer: usize = if mainsection.contains_key("protocoltrace_buffer") {
+            mainsection["protocoltrace_buffer"]
+                .as_ref()
+                .map(|s| s.parse().unwrap_or(0))
+                .unwrap_or(0)
+        } else {
+            0
+        };
+        let keep_raw_updates: bool = if mainsection.contains_key("keep_raw_updates") {
+            match mainsection["keep_raw_updates"] {
+                Some(ref s) => s.parse().unwrap_or(false),
+                None => false,
+            }
+        } else {
+            false
+        };
+        let strictparsing: bool = if mainsection.contains_key("strictparsing") {
+            match mainsection["strictparsing"] {
+                Some(ref s) => s.parse().unwrap_or(true),
+                None => true,
+            }
+        } else {
+            true
+        };
+        let ebgp_checks = match mainsection.get("ebgp_checks") {
+            Some(Some(s)) => match crate::sanity::Check::parse_list(s) {
+                Ok(v) => v,
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid ebgp_checks: {}",
+                        e
+                    )))
+                }
+            },
+            _ => Vec::new(),
+        };
+        let confed_in_aspath = match mainsection.get("confed_in_aspath") {
+            Some(Some(s)) => s.parse().unwrap_or(false),
+            _ => false,
+        };
+        let mut processors = Vec::new();
+        if let Some(Some(s)) = mainsection.get("processors") {
+            for name in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+                if !crate::processor::is_registered(name) {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Unknown route processor {}",
+                        name
+                    )));
+                }
+                let prefix = format!("{}.", name);
+                let options = mainsection
+                    .iter()
+                    .filter_map(|(k, v)| {
+                        Some((k.strip_prefix(prefix.as_str())?.to_string(), v.clone()?))
+                    })
+                    .collect();
+                processors.push(crate::processor::ProcessorConfig {
+                    name: name.to_string(),
+                    options,
+                });
+            }
+        }
+        let update_script = match mainsection.get("update_script") {
+            Some(Some(s)) => {
+                if let Err(e) = crate::script::UpdateScript::load(s.as_str()) {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid update_script - {}",
+                        e
+                    )));
+                }
+                Some(s.to_string())
+            }
+            _ => None,
+        };
+        let mut inbound = crate::inbound::InboundFilter::default();
+        if let Some(Some(s)) = mainsection.get("acceptprefixes") {
+            inbound.accept = crate::inbound::PrefixList::parse(s)
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid acceptprefixes - {}", e)))?;
+        }
+        if let Some(Some(s)) = mainsection.get("rejectprefixes") {
+            inbound.reject = crate::inbound::PrefixList::parse(s)
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid rejectprefixes - {}", e)))?;
+        }
+        if let Some(Some(s)) = mainsection.get("acceptorigins") {
+            inbound.origins = crate::inbound::InboundFilter::parse_origins(s)
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid acceptorigins - {}", e)))?;
+        }
+        let inbound = if inbound.is_empty() {
+            None
+        } else {
+            Some(inbound)
+        };
+        let filtered_shadow_size: usize = match mainsection.get("filtered_shadow_size") {
+            Some(Some(s)) => s.parse().map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid filtered_shadow_size - {}", e))
+            })?,
+            _ => 0,
+        };
+        let retention = match mainsection.get("retention") {
+            Some(Some(s)) => crate::retention::parse_rules(s)
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid retention - {}", e)))?,
+            _ => Vec::new(),
+        };
+        let api_tokens = crate::tenancy::parse_tokens(&conf)?;
+        let audit_file = match mainsection.get("audit_file") {
+            Some(Some(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let settings_file = match mainsection.get("settings_file") {
+            Some(Some(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            _ => None,
+        };
+        let subscriptions_file = match mainsection.get("subscriptions_file") {
+            Some(Some(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            _ => None,
+        };
+        let views = crate::views::parse_views(&conf)?;
+        let views_file = match mainsection.get("views_file") {
+            Some(Some(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            _ => None,
+        };
+        let graphql: bool = match mainsection.get("graphql") {
+            Some(Some(s)) => s.parse().unwrap_or(false),
+            _ => false,
+        };
+        let rpki_roas = match mainsection.get("rpki_roas") {
+            Some(Some(s)) if !s.trim().is_empty() => Some(Arc::new(
+                crate::rpki::RoaTable::load(s.trim())
+                    .map_err(|e| ErrorConfig::from_string(format!("Invalid rpki_roas - {}", e)))?,
+            )),
+            _ => None,
+        };
+        let query_limits = match mainsection.get("query_limits") {
+            Some(Some(s)) => crate::ratelimit::parse_limits(s)
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid query_limits - {}", e)))?,
+            _ => std::collections::BTreeMap::new(),
+        };
+        let anonymize = crate::anonymize::Anonymizer::from_ini(mainsection)?;
+        let subscription_retention = match mainsection.get("subscription_retention") {
+            Some(Some(s)) => parse_duration(s).map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid subscription_retention - {}", e))
+            })?,
+            _ => std::time::Duration::from_secs(86400),
+        };
+        let subscriptions_per_token: usize = match mainsection.get("subscriptions_per_token") {
+            Some(Some(s)) => s.trim().parse().map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid subscriptions_per_token - {}", e))
+            })?,
+            _ => 32,
+        };
+        let clock_skew_threshold: u64 = match mainsection.get("clock_skew_threshold") {
+            Some(Some(s)) => parse_duration(s)
+                .map_err(|e| {
+                    ErrorConfig::from_string(format!("Invalid clock_skew_threshold - {}", e))
+                })?
+                .as_secs(),
+            _ => 5,
+        };
+        let passive_idle_timeout: u64 = match mainsection.get("passive_idle_timeout") {
+            Some(Some(s)) => parse_duration(s)
+                .map_err(|e| {
+                    ErrorConfig::from_string(format!("Invalid passive_idle_timeout - {}", e))
+                })?
+                .as_secs(),
+            _ => 600,
+        };
+        let bmp_idle_timeout: u64 = match mainsection.get("bmp_idle_timeout") {
+            Some(Some(s)) => parse_duration(s)
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid bmp_idle_timeout - {}", e)))?
+                .as_secs(),
+            _ => 0,
+        };
+        let query_budget: u64 = match mainsection.get("query_budget") {
+            Some(Some(s)) => s
+                .parse()
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid query_budget - {}", e)))?,
+            _ => 100_000_000,
+        };
+        let integrity_check_every = match mainsection.get("integrity_check_every") {
+            Some(Some(s)) => match parse_duration(s) {
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid integrity_check_every - {}",
+                        e
+                    )));
+                }
+                Ok(a) if a.as_secs() == 0 => None,
+                Ok(a) => Some(a),
+            },
+            _ => Some(std::time::Duration::from_secs(3600)),
+        };
+        let integrity_repair: bool = match mainsection.get("integrity_repair") {
+            Some(Some(s)) => s.parse().unwrap_or(false),
+            _ => false,
+        };
+        let preload_url = match mainsection.get("preload_url") {
+            Some(Some(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            _ => None,
+        };
+        let preload_max_size = match mainsection.get("preload_max_size_mb") {
+            Some(Some(s)) => match s.trim().parse::<usize>() {
+                Ok(n) if n > 0 => n.saturating_mul(1024 * 1024),
+                _ => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid preload_max_size_mb - {}",
+                        s
+                    )));
+                }
+            },
+            _ => 4096usize.saturating_mul(1024 * 1024),
+        };
+        let objstore = match mainsection.get("objstore_endpoint") {
+            Some(Some(endpoint)) => {
+                // signing and TLS are compiled in by features, so missing ones are known now
+                let (tls, _) = crate::objstore::parse_endpoint(endpoint).map_err(|e| {
+                    ErrorConfig::from_string(format!("Invalid objstore_endpoint - {}", e))
+                })?;
+                if !cfg!(feature = "s3") {
+                    return Err(ErrorConfig::from_str(
+                        "objstore_endpoint requires build with s3 feature",
+                    ));
+                }
+                if tls && !cfg!(feature = "tls") {
+                    return Err(ErrorConfig::from_str(
+                        "https objstore_endpoint requires build with tls feature",
+                    ));
+                }
+                let required = |name: &str| match mainsection.get(name) {
+                    Some(Some(s)) if !s.is_empty() => Ok(s.to_string()),
+                    _ => Err(ErrorConfig::from_string(format!(
+                        "{} is required for objstore_endpoint",
+                        name
+                    ))),
+                };
+                let days: i64 = match mainsection.get("objstore_retention_days") {
+                    Some(Some(s)) => s.parse().map_err(|e| {
+                        ErrorConfig::from_string(format!("Invalid objstore_retention_days - {}", e))
+                    })?,
+                    _ => 0,
+                };
+                let sync_every: u64 = match mainsection.get("objstore_sync_every") {
+                    Some(Some(s)) => parse_duration(s)
+                        .map_err(|e| {
+                            ErrorConfig::from_string(format!("Invalid objstore_sync_every - {}", e))
+                        })?
+                        .as_secs(),
+                    _ => 300,
+                };
+                Some(crate::objstore::ObjectStoreSettings {
+                    endpoint: endpoint.to_string(),
+                    bucket: required("objstore_bucket")?,
+                    region: match mainsection.get("objstore_region") {
+                        Some(Some(s)) => s.to_string(),
+                        _ => "us-east-1".to_string(),
+                    },
+                    access_key: required("objstore_access_key")?,
+                    secret_key: required("objstore_secret_key")?,
+                    prefix: match mainsection.get("objstore_prefix") {
+                        Some(Some(s)) => s.to_string(),
+                        _ => "bgpexplorer/".to_string(),
+                    },
+                    retention: if days > 0 {
+                        Some(chrono::Duration::days(days))
+                    } else {
+                        None
+                    },
+                    sync_every: std::time::Duration::from_secs(sync_every.max(1)),
+                })
+            }
+            _ => None,
+        };
+        let ui_language = match mainsection.get("ui_language") {
+            Some(Some(s)) => s.trim().to_lowercase(),
+            _ => "en".to_string(),
+        };
+        let l10n_dir = match mainsection.get("l10n_dir") {
+            Some(Some(s)) => s.clone(),
+            _ => format!("{}/l10n", httproot),
+        };
+        let uitheme = match mainsection.get("uitheme") {
+            Some(Some(s)) => s
+                .parse()
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid uitheme - {}", e)))?,
+            _ => Default::default(),
+        };
+        let ui_string = |key: &str| match mainsection.get(key) {
+            Some(Some(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            _ => None,
+        };
+        let ui = crate::uiconfig::UiSettings {
+            theme: uitheme,
+            brand_name: ui_string("ui_brand_name"),
+            logo_url: ui_string("ui_logo_url"),
+            stylesheet_url: ui_string("ui_stylesheet_url"),
+            default_rib: ui_string("ui_default_rib"),
+            default_filter: ui_string("ui_default_filter"),
+        };
+        let json_time_format = match mainsection.get("json_time_format") {
+            Some(Some(s)) => s.parse().map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid json_time_format - {}", e))
+            })?,
+            _ => Default::default(),
+        };
+        let json_prefix_format = match mainsection.get("json_prefix_format") {
+            Some(Some(s)) => s.parse().map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid json_prefix_format - {}", e))
+            })?,
+            _ => Default::default(),
+        };
+        let json_history_format = match mainsection.get("json_history_format") {
+            Some(Some(s)) => s.parse().map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid json_history_format - {}", e))
+            })?,
+            _ => Default::default(),
+        };
+        let blackhole_communities = match mainsection.get("blackhole_communities") {
+            Some(Some(s)) => crate::blackhole::BlackholeCommunities::parse(s).map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid blackhole_communities - {}", e))
+            })?,
+            _ => Default::default(),
+        };
+        let community_names = match mainsection.get("community_names") {
+            Some(Some(s)) => crate::communities::parse_symbols(s).map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid community_names - {}", e))
+            })?,
+            _ => Vec::new(),
+        };
+        let replay_file = if mainsection.contains_key("replay_file") {
+            mainsection["replay_file"].as_ref().map(|s| s.to_string())
+        } else {
+            None
+        };
+        let pcap_file = if mainsection.contains_key("pcap_file") {
+            mainsection["pcap_file"].as_ref().map(|s| s.to_string())
+        } else {
+            None
+        };
+        let replay_speed: crate::replay::ReplaySpeed = if mainsection.contains_key("replay_speed") {
+            match mainsection["replay_speed"] {
+                None => {
+                    return Err(ErrorConfig::from_str("invalid replay_speed was specified"));
+                }
+                Some(ref s) => match s.parse() {
+                    Err(e) => {
+                        return Err(ErrorConfig::from_string(format!(
+                            "Invalid replay_speed - {}",
+                            e
+                        )));
+                    }
+                    Ok(a) => a,
+                },
+            }
+        } else {
+            crate::replay::ReplaySpeed::Factor(1.0)
+        };
+        let replay_original_time: bool = if mainsection.contains_key("replay_clock") {
+            match mainsection["replay_clock"].as_deref() {
+                Some("original") => true,
+                Some("now") => false,
+                _ => {
+                    return Err(ErrorConfig::from_str(
+                        "replay_clock should be original or now",
+                    ));
+                }
+            }
+        } else {
+            true
+        };
+        let user = if mainsection.contains_key("user") {
+            mainsection["user"].as_ref().map(|s| s.to_string())
+        } else {
+            None
+        };
+        let group = if mainsection.contains_key("group") {
+            mainsection["group"].as_ref().map(|s| s.to_string())
+        } else {
+            None
+        };
+        let pidfile = if mainsection.contains_key("pidfile") {
+            mainsection["pidfile"].as_ref().map(|s| s.to_string())
+        } else {
+            None
+        };
+        let daemonize: bool = if mainsection.contains_key("daemonize") {
+            match mainsection["daemonize"] {
+                Some(ref s) => s.parse().unwrap_or(false),
+                None => false,
+            }
+        } else {
+            false
+        };
         let snapshot_every = if mainsection.contains_key("snapshot_every") {
-            Some(chrono::Duration::seconds(
-                mainsection["snapshot_every"]
-                    .as_ref()
-                    .map(|s| s.parse().unwrap_or(43200))
-                    .unwrap_or(43200),
-            ))
+            Some(chrono_duration(
+                "snapshot_every",
+                duration_option(
+                    mainsection,
+                    "snapshot_every",
+                    std::time::Duration::from_secs(43200),
+                )?,
+            )?)
         } else {
             None
         };
+        let snapshot_diff_every = match mainsection.get("snapshot_diff_every") {
+            Some(Some(s)) => match parse_duration(s) {
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid snapshot_diff_every - {}",
+                        e
+                    )));
+                }
+                Ok(a) if a.as_secs() == 0 => None,
+                Ok(a) => Some(chrono_duration("snapshot_diff_every", a)?),
+            },
+            _ => None,
+        };
         let historydepth: usize = if mainsection.contains_key("historydepth") {
             match mainsection["historydepth"] {
                 None => {
@@ -500,6 +1945,18 @@ impl SvcConfig {
         } else {
             10
         };
+        let withdraw_burst_threshold: usize = match mainsection.get("withdraw_burst_threshold") {
+            Some(Some(s)) => s.parse().map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid withdraw_burst_threshold - {}", e))
+            })?,
+            _ => 0,
+        };
+        let version_journal: usize = match mainsection.get("version_journal") {
+            Some(Some(s)) => s.parse().map_err(|e| {
+                ErrorConfig::from_string(format!("Invalid version_journal - {}", e))
+            })?,
+            _ => 100_000,
+        };
         let historymode: HistoryChangeMode = if mainsection.contains_key("historymode") {
             match mainsection["historymode"] {
                 None => {
@@ -518,6 +1975,37 @@ impl SvcConfig {
         } else {
             HistoryChangeMode::OnlyDiffer
         };
+        let dedup_window = match mainsection.get("dedup_window") {
+            Some(Some(s)) => match parse_duration(s) {
+                Err(e) => {
+                    return Err(ErrorConfig::from_string(format!(
+                        "Invalid dedup_window - {}",
+                        e
+                    )));
+                }
+                Ok(a) if a.as_secs() == 0 => None,
+                Ok(a) => Some(a),
+            },
+            _ => None,
+        };
+        let bestpath: BestPathMode = if mainsection.contains_key("bestpath") {
+            match mainsection["bestpath"] {
+                None => {
+                    return Err(ErrorConfig::from_str("invalid bestpath was specified"));
+                }
+                Some(ref s) => match s.parse() {
+                    Err(e) => {
+                        return Err(ErrorConfig::from_string(format!(
+                            "Invalid bestpath - {}",
+                            e
+                        )));
+                    }
+                    Ok(a) => a,
+                },
+            }
+        } else {
+            BestPathMode::All
+        };
         let purge_after_withdraws: u64 = if mainsection.contains_key("purge_after_withdraws") {
             match mainsection["purge_after_withdraws"] {
                 None => {
@@ -543,34 +2031,37 @@ impl SvcConfig {
                 None => {
                     return Err(ErrorConfig::from_str("invalid purge_every was specified"));
                 }
-                Some(ref s) => chrono::Duration::seconds(match s.parse() {
+                Some(ref s) => match parse_duration(s) {
                     Err(e) => {
                         return Err(ErrorConfig::from_string(format!(
                             "Invalid purge_every - {}",
                             e
                         )));
                     }
-                    Ok(a) => a,
-                }),
+                    Ok(a) => chrono_duration("purge_every", a)?,
+                },
             }
         } else {
             chrono::Duration::minutes(5)
         };
-        let whoisreqtimeout: u64 = if mainsection.contains_key("whois_request_timeout") {
-            match mainsection["whois_request_timeout"] {
-                Some(ref s) => s.parse().unwrap_or(30),
-                None => 30,
-            }
-        } else {
-            30
-        };
-        let whoiscachesecs: i64 = if mainsection.contains_key("whois_cache_seconds") {
-            match mainsection["whois_cache_seconds"] {
-                Some(ref s) => s.parse().unwrap_or(1800),
-                None => 1800,
-            }
-        } else {
-            1800
+        let whoisreqtimeout: u64 = duration_option(
+            mainsection,
+            "whois_request_timeout",
+            std::time::Duration::from_secs(30),
+        )?
+        .as_secs();
+        let whoiscachesecs: i64 = chrono_duration(
+            "whois_cache_seconds",
+            duration_option(
+                mainsection,
+                "whois_cache_seconds",
+                std::time::Duration::from_secs(1800),
+            )?,
+        )?
+        .num_seconds();
+        let irr_server = match mainsection.get("irr_server") {
+            Some(Some(s)) if !s.trim().is_empty() => s.trim().to_string(),
+            _ => "whois.radb.net".to_string(),
         };
         let whois: WhoIs = if mainsection.contains_key("whoisjsonconfig") {
             match mainsection["whoisjsonconfig"] {
@@ -616,22 +2107,372 @@ impl SvcConfig {
         if dnses.is_empty() {
             dnses.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 53));
         };
+        let conflicts = check_conflicts(&conf, &httplisten, replication_listen.as_ref());
+        if !conflicts.is_empty() {
+            return Err(ErrorConfig::from_string(format!(
+                "{} conflicting options:\n  {}",
+                conflicts.len(),
+                conflicts.join("\n  ")
+            )));
+        }
+        for w in check_warnings(&peers, purge_every) {
+            warn!("{}", w);
+        }
         Ok(SvcConfig {
             httplisten,
             httptimeout,
+            httpversion,
+            http2_max_streams,
+            http_keepalive,
+            http_idle_timeout,
             httproot,
+            ui_language,
+            l10n_dir,
+            ui,
             historydepth,
             historymode,
+            dedup_window,
+            bestpath,
             whoisconfig: whois,
             whoisdb,
             whoisdnses: dnses,
             whoisreqtimeout,
             whoiscachesecs,
+            irr_server,
             purge_after_withdraws,
+            withdraw_burst_threshold,
+            version_journal,
             purge_every,
             peers,
             snapshot_file,
             snapshot_every,
+            snapshot_diff_every,
+            user,
+            group,
+            pidfile,
+            daemonize,
+            replication,
+            replication_listen,
+            replication_peer,
+            replication_interval,
+            replication_timeout,
+            replication_secret,
+            replication_max_frame,
+            federation_name,
+            federation,
+            snmp_agentx,
+            snmp_base_oid,
+            syslog,
+            syslog_facility,
+            syslog_watch,
+            report_dir,
+            report_period,
+            report_top,
+            report_email,
+            smtp,
+            smtp_alerts,
+            protocoltrace,
+            protocoltrace_buffer,
+            keep_raw_updates,
+            strictparsing,
+            ebgp_checks,
+            confed_in_aspath,
+            processors,
+            update_script,
+            inbound,
+            filtered_shadow_size,
+            retention,
+            api_tokens,
+            audit_file,
+            settings_file,
+            subscriptions_file,
+            views,
+            views_file,
+            graphql,
+            rpki_roas,
+            query_limits,
+            anonymize,
+            subscription_retention,
+            subscriptions_per_token,
+            clock_skew_threshold,
+            passive_idle_timeout,
+            bmp_idle_timeout,
+            query_budget,
+            integrity_check_every,
+            integrity_repair,
+            preload_url,
+            preload_max_size,
+            objstore,
+            json_time_format,
+            json_prefix_format,
+            json_history_format,
+            blackhole_communities,
+            community_names,
+            replay_file,
+            pcap_file,
+            replay_speed,
+            replay_original_time,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn section(items: &[(&str, &str)]) -> HashMap<String, Option<String>> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), Some(v.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_template() {
+        let mut conf = HashMap::new();
+        conf.insert(
+            "template:base".to_string(),
+            section(&[("routerid", "10.0.0.1"), ("caps", "min")]),
+        );
+        conf.insert(
+            "template:transit".to_string(),
+            section(&[
+                ("template", "base"),
+                ("mode", "bgpactive"),
+                ("caps", "ipv4u,ipv6u"),
+            ]),
+        );
+        conf.insert(
+            "peer1".to_string(),
+            section(&[
+                ("template", "transit"),
+                ("peer", "192.0.2.1"),
+                ("mode", "bgppassive"),
+            ]),
+        );
+        let s = resolve_template(&conf, "peer1").unwrap();
+        assert_eq!(s["mode"].as_deref(), Some("bgppassive"));
+        assert_eq!(s["caps"].as_deref(), Some("ipv4u,ipv6u"));
+        assert_eq!(s["routerid"].as_deref(), Some("10.0.0.1"));
+        assert!(!s.contains_key("template"));
+        conf.insert(
+            "template:base".to_string(),
+            section(&[("template", "transit")]),
+        );
+        assert!(resolve_template(&conf, "peer1").is_err());
+        conf.insert("peer2".to_string(), section(&[("template", "missing")]));
+        assert!(resolve_template(&conf, "peer2").is_err());
+    }
+
+    #[test]
+    fn test_check_conflicts() {
+        let http: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        let mut conf = HashMap::new();
+        conf.insert("main".to_string(), section(&[]));
+        conf.insert(
+            "r1".to_string(),
+            section(&[
+                ("mode", "bgpactive"),
+                ("peer", "192.0.2.1"),
+                ("filter_rd", "0:0"),
+            ]),
+        );
+        conf.insert(
+            "r0".to_string(),
+            section(&[
+                ("mode", "bmpactive"),
+                ("peer", "192.0.2.2"),
+                ("filter_rd", "0:0"),
+            ]),
+        );
+        conf.insert(
+            "r2".to_string(),
+            section(&[("mode", "bmppassive"), ("protolisten", "127.0.0.1:8080")]),
+        );
+        conf.insert(
+            "r3".to_string(),
+            section(&[("mode", "bgppassive"), ("protolisten", "127.0.0.1:8080")]),
+        );
+        let c = check_conflicts(&conf, &[http], None);
+        assert_eq!(
+            c,
+            vec![
+                "[r1] filter_rd: used only in bmpactive and bmppassive modes, mode is bgpactive",
+                "[r2] protolisten: 127.0.0.1:8080 collides with [main] httplisten 0.0.0.0:8080",
+                "[r3] protolisten: 127.0.0.1:8080 collides with [main] httplisten 0.0.0.0:8080",
+                "[r3] protolisten: 127.0.0.1:8080 is used by [r2] with other protocol",
+            ]
+        );
+        conf.remove("r1");
+        conf.remove("r3");
+        let http: SocketAddr = "127.0.0.2:8080".parse().unwrap();
+        assert!(check_conflicts(&conf, &[http], None).is_empty());
+        let peers: Vec<Arc<ProtoPeer>> = ["r0", "r2"]
+            .iter()
+            .map(|n| Arc::new(ProtoPeer::from_ini(&conf[*n]).unwrap()))
+            .collect();
+        assert!(check_warnings(&peers, chrono::Duration::seconds(30)).is_empty());
+        let bgp = section(&[("mode", "bgpactive"), ("peer", "192.0.2.1")]);
+        let peers = vec![Arc::new(ProtoPeer::from_ini(&bgp).unwrap())];
+        assert_eq!(
+            check_warnings(&peers, chrono::Duration::seconds(30)),
+            vec!["[main] purge_every: 30s is shorter than BGP keepalive interval 60s"]
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let secs = |s: &str| parse_duration(s).map(|d| d.as_secs()).ok();
+        assert_eq!(secs("300"), Some(300));
+        assert_eq!(secs("90s"), Some(90));
+        assert_eq!(secs("5m"), Some(300));
+        assert_eq!(secs(" 2h30m "), Some(9000));
+        assert_eq!(secs("1d"), Some(86400));
+        assert_eq!(secs("1w"), Some(604800));
+        assert_eq!(secs(""), None);
+        assert_eq!(secs("5x"), None);
+        assert_eq!(secs("m"), None);
+        assert_eq!(secs("2h30"), None);
+        let mut main = std::collections::HashMap::new();
+        main.insert("httptimeout".to_string(), Some("2x".to_string()));
+        main.insert("purge_every".to_string(), Some("5m".to_string()));
+        assert!(duration_option(&main, "httptimeout", std::time::Duration::from_secs(1)).is_err());
+        assert_eq!(
+            duration_option(
+                &main,
+                "whois_request_timeout",
+                std::time::Duration::from_secs(1)
+            )
+            .unwrap()
+            .as_secs(),
+            1
+        );
+        let huge = parse_duration(&u64::MAX.to_string()).unwrap();
+        assert!(chrono_duration("purge_every", huge).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match(b"*.ini", b"peer1.ini"));
+        assert!(wildcard_match(b"peer?.ini", b"peer1.ini"));
+        assert!(!wildcard_match(b"*.ini", b"peer1.ini.bak"));
+        assert!(wildcard_match(b"*", b""));
+    }
+
+    #[test]
+    fn test_localas() {
+        let peer = ProtoPeer::from_ini(&section(&[
+            ("mode", "bgpactive"),
+            ("peer", "192.0.2.1"),
+            ("peeras", "64500"),
+            ("localas", "4200000001"),
+            ("caps", "ipv4u,asn32"),
+        ]))
+        .unwrap();
+        assert_eq!(peer.localas, Some(4200000001));
+        assert!(!peer.localas_no_prepend);
+        let params = peer.get_session_params();
+        assert_eq!(params.as_num, 4200000001);
+        assert!(params
+            .caps
+            .iter()
+            .any(|c| matches!(c, BgpCapability::CapASN32(4200000001))));
+        assert!(
+            ProtoPeer::from_ini(&section(&[("mode", "bgppassive"), ("localas", "x")])).is_err()
+        );
+        assert!(ProtoPeer::from_ini(&section(&[
+            ("mode", "bgppassive"),
+            ("localas", "64501"),
+            ("localas_no_prepend", "yes")
+        ]))
+        .is_err());
+        let peer = ProtoPeer::from_ini(&section(&[
+            ("mode", "bgppassive"),
+            ("localas", "64501"),
+            ("localas_no_prepend", "true"),
+        ]))
+        .unwrap();
+        assert!(peer.localas_no_prepend);
+    }
+
+    #[test]
+    fn test_ttlsecurity() {
+        let peer = ProtoPeer::from_ini(&section(&[
+            ("mode", "bgppassive"),
+            ("protolisten", "0.0.0.0:179"),
+            ("ttlsecurity", "254"),
+        ]))
+        .unwrap();
+        assert_eq!(peer.ttlsecurity, Some(254));
+        assert!(
+            ProtoPeer::from_ini(&section(&[("mode", "bgppassive"), ("ttlsecurity", "256")]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_multihop_localaddr() {
+        let peer = ProtoPeer::from_ini(&section(&[
+            ("mode", "bgpactive"),
+            ("peer", "192.0.2.1"),
+            ("peeras", "64500"),
+            ("ebgp_multihop", "3"),
+            ("localaddr", "198.51.100.7"),
+        ]))
+        .unwrap();
+        assert_eq!(peer.ebgp_multihop, Some(3));
+        assert_eq!(peer.localaddr, Some("198.51.100.7".parse().unwrap()));
+        assert!(ProtoPeer::from_ini(&section(&[
+            ("mode", "bgpactive"),
+            ("peer", "192.0.2.1"),
+            ("localaddr", "2001:db8::1"),
+        ]))
+        .is_err());
+    }
+    #[test]
+    fn test_resolve_paths() {
+        let ini = std::env::temp_dir().join("bgpexplorer-test-resolve-paths.ini");
+        std::fs::write(
+            &ini,
+            "[main]\nwhoisjsonconfig=whois.json\nhttproot=contrib\nsnapshot=rib.snapshot\npidfile=/run/bgpexplorer.pid\n[bmp]\nmode=bmppassive\nprotolisten=127.0.0.1:11632\n",
+        )
+        .unwrap();
+        let mut conf = SvcConfig::from_inifile(ini.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(&ini);
+        conf.resolve_paths(std::path::Path::new("/srv/bgp"));
+        assert_eq!(conf.httproot, "/srv/bgp/contrib");
+        assert_eq!(conf.whoisdb, "/srv/bgp/whoiscache.db");
+        assert_eq!(conf.snapshot_file.as_deref(), Some("/srv/bgp/rib.snapshot"));
+        assert_eq!(conf.pidfile.as_deref(), Some("/run/bgpexplorer.pid"));
+    }
+    #[test]
+    fn test_http_options() {
+        let ini = std::env::temp_dir().join("bgpexplorer-test-http-options.ini");
+        let load = |main: &str| {
+            std::fs::write(
+                &ini,
+                format!(
+                    "[main]\nwhoisjsonconfig=whois.json\n{}\n[bmp]\nmode=bmppassive\nprotolisten=127.0.0.1:11632\n",
+                    main
+                ),
+            )
+            .unwrap();
+            SvcConfig::from_inifile(ini.to_str().unwrap())
+        };
+        let conf = load("http2=off\nhttp2_max_streams=10\nhttp_keepalive=false").unwrap();
+        assert_eq!(conf.httpversion, HttpVersionMode::Http1Only);
+        assert_eq!(conf.http2_max_streams, 10);
+        assert!(!conf.http_keepalive);
+        for bad in [
+            "http2=maybe",
+            "http2_max_streams=many",
+            "http2_max_streams=0",
+            "http_keepalive=yes",
+            "http_idle_timeout=soon",
+        ] {
+            assert!(load(bad).is_err(), "{}", bad);
+        }
+        let _ = std::fs::remove_file(&ini);
+    }
+}
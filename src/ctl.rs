@@ -0,0 +1,203 @@
+//! `bgpexplorer ctl`: operator commands against running daemon over its HTTP API, so
+//! status, flush, export and watchlist (subscriptions) need no curl
+use hyper::{Body, Method, Request};
+
+pub const USAGE: &str = "usage: bgpexplorer ctl [--url URL] [--token TOKEN] COMMAND
+  URL defaults to BGPEXPLORER_URL or http://127.0.0.1:8080, TOKEN to BGPEXPLORER_TOKEN
+commands:
+  status                        collector status
+  sessions                      BGP and BMP sessions
+  flush                         drop all routes from RIB (admin)
+  snapshot                      write snapshot now (admin)
+  session                       session FSM state
+  clear [soft|hard] [PEER]      reset session, hard by default (admin)
+  shutdown [PEER]               administratively shut session down (admin)
+  enable [PEER]                 bring shut down session back (admin)
+  PEER is peer address, needed when daemon has several peers
+  export PREFIX [csv|ndjson]    retained prefix history
+  watch list                    list subscriptions
+  watch add AS|PREFIX[,PREFIX]  subscribe to origin AS or prefixes
+  watch rm ID                   remove subscription
+  watch poll ID                 changes since previous poll";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CtlArgs {
+    pub url: String,
+    pub token: Option<String>,
+    pub command: Vec<String>,
+}
+impl CtlArgs {
+    pub fn from_args(args: &[String]) -> Result<CtlArgs, String> {
+        let mut ret = CtlArgs {
+            url: std::env::var("BGPEXPLORER_URL")
+                .unwrap_or_else(|_| "http://127.0.0.1:8080".to_string()),
+            token: std::env::var("BGPEXPLORER_TOKEN").ok(),
+            command: Vec::new(),
+        };
+        let mut it = args.iter();
+        while let Some(a) = it.next() {
+            match a.as_str() {
+                "--url" | "--token" => {
+                    let v = it
+                        .next()
+                        .ok_or_else(|| format!("Missing value for {}\n{}", a, USAGE))?;
+                    if a == "--url" {
+                        ret.url = v.trim_end_matches('/').to_string();
+                    } else {
+                        ret.token = Some(v.clone());
+                    }
+                }
+                _ => ret.command.push(a.clone()),
+            }
+        }
+        if ret.command.is_empty() {
+            return Err(USAGE.to_string());
+        }
+        Ok(ret)
+    }
+}
+
+/// method, API path and JSON body of command
+pub fn command_request(cmd: &[String]) -> Result<(Method, String, Option<String>), String> {
+    let words: Vec<&str> = cmd.iter().map(|s| s.as_str()).collect();
+    Ok(match words.as_slice() {
+        ["status"] => (Method::GET, "/api/status".to_string(), None),
+        ["sessions"] => (Method::GET, "/api/sessions".to_string(), None),
+        ["flush"] => (Method::POST, "/api/admin/flush".to_string(), None),
+        ["snapshot"] => (Method::POST, "/api/admin/snapshot".to_string(), None),
+        ["session"] => (Method::GET, "/api/session".to_string(), None),
+        ["clear"] => (Method::POST, "/api/admin/session/clear".to_string(), None),
+        ["clear", mode] => (
+            Method::POST,
+            format!("/api/admin/session/clear?mode={}", mode),
+            None,
+        ),
+        ["clear", mode, peer] => (
+            Method::POST,
+            format!("/api/admin/session/clear?mode={}&peer={}", mode, peer),
+            None,
+        ),
+        ["shutdown"] | ["enable"] => (
+            Method::POST,
+            format!("/api/admin/session/{}", words[0]),
+            None,
+        ),
+        ["shutdown", peer] | ["enable", peer] => (
+            Method::POST,
+            format!("/api/admin/session/{}?peer={}", words[0], peer),
+            None,
+        ),
+        ["export", prefix] => (Method::GET, format!("/api/prefix/{}/history", prefix), None),
+        ["export", prefix, fmt] => (
+            Method::GET,
+            format!("/api/prefix/{}/history?format={}", prefix, fmt),
+            None,
+        ),
+        ["watch", "list"] => (Method::GET, "/api/subscriptions".to_string(), None),
+        ["watch", "add", what] => {
+            let asn = what.trim_start_matches("AS").trim_start_matches("as");
+            let body = match asn.parse::<u32>() {
+                Ok(n) => serde_json::json!({ "origin": n }),
+                Err(_) => serde_json::json!({ "prefixes": what.split(',').collect::<Vec<_>>() }),
+            };
+            (
+                Method::POST,
+                "/api/subscriptions".to_string(),
+                Some(body.to_string()),
+            )
+        }
+        ["watch", "rm", id] => (Method::DELETE, format!("/api/subscriptions/{}", id), None),
+        ["watch", "poll", id] => (
+            Method::GET,
+            format!("/api/subscriptions/{}/changes", id),
+            None,
+        ),
+        _ => return Err(USAGE.to_string()),
+    })
+}
+
+async fn perform(args: &CtlArgs) -> Result<String, String> {
+    let (method, path, body) = command_request(&args.command)?;
+    let uri: hyper::Uri = format!("{}{}", args.url, path)
+        .parse()
+        .map_err(|e| format!("Invalid url: {}", e))?;
+    let mut req = Request::builder().method(method).uri(uri);
+    if let Some(t) = args.token.as_ref() {
+        req = req.header(hyper::header::AUTHORIZATION, format!("Bearer {}", t));
+    }
+    if body.is_some() {
+        req = req.header(hyper::header::CONTENT_TYPE, "application/json");
+    }
+    let req = req
+        .body(body.map(Body::from).unwrap_or_else(Body::empty))
+        .map_err(|e| e.to_string())?;
+    let rsp = hyper::Client::new()
+        .request(req)
+        .await
+        .map_err(|e| format!("{}: {}", args.url, e))?;
+    let status = rsp.status();
+    let bytes = hyper::body::to_bytes(rsp.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+    let text = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(v) => serde_json::to_string_pretty(&v).unwrap_or_default(),
+        Err(_) => String::from_utf8_lossy(&bytes).trim_end().to_string(),
+    };
+    if !status.is_success() {
+        return Err(format!("{} {}", status, text));
+    }
+    Ok(text)
+}
+
+/// runs `ctl` arguments, returns response text to print
+pub fn run(args: &[String]) -> Result<String, String> {
+    let args = CtlArgs::from_args(args)?;
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?
+        .block_on(perform(&args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_ctl_commands() {
+        let a = CtlArgs::from_args(&words("--url http://192.0.2.1:8080/ watch list")).unwrap();
+        assert_eq!(a.url, "http://192.0.2.1:8080");
+        assert_eq!(a.command, words("watch list"));
+        assert!(CtlArgs::from_args(&words("--token")).is_err());
+        let (m, p, b) = command_request(&words("watch add AS64500")).unwrap();
+        assert_eq!((m, p.as_str()), (Method::POST, "/api/subscriptions"));
+        assert_eq!(b.unwrap(), r#"{"origin":64500}"#);
+        let (_, _, b) = command_request(&words("watch add 192.0.2.0/24,198.51.100.0/24")).unwrap();
+        assert_eq!(
+            b.unwrap(),
+            r#"{"prefixes":["192.0.2.0/24","198.51.100.0/24"]}"#
+        );
+        let (m, p, _) = command_request(&words("export 10.0.0.0/8 csv")).unwrap();
+        assert_eq!(
+            (m, p.as_str()),
+            (Method::GET, "/api/prefix/10.0.0.0/8/history?format=csv")
+        );
+        assert_eq!(
+            command_request(&words("watch rm 3")).unwrap().0,
+            Method::DELETE
+        );
+        assert_eq!(
+            command_request(&words("clear soft")).unwrap().1,
+            "/api/admin/session/clear?mode=soft"
+        );
+        assert_eq!(
+            command_request(&words("shutdown 192.0.2.1")).unwrap().1,
+            "/api/admin/session/shutdown?peer=192.0.2.1"
+        );
+        assert!(command_request(&words("reboot")).is_err());
+    }
+}
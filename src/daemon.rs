@@ -0,0 +1,190 @@
+//! daemonizing, pidfile and privilege drop
+use crate::config::SvcConfig;
+use std::io;
+
+/// binds HTTP and passive protocol listeners in advance, so they survive privilege drop
+pub fn prebind(conf: &SvcConfig) -> io::Result<()> {
+    let dual = conf.httplisten.len() > 1;
+    let mut addrs: Vec<(std::net::SocketAddr, bool)> =
+        conf.httplisten.iter().map(|a| (*a, dual)).collect();
+    addrs.extend(
+        conf.peers
+            .iter()
+            .filter_map(|p| p.protolisten)
+            .map(|a| (a, false)),
+    );
+    for (a, only_v6) in addrs {
+        if crate::systemd::has_listener(&a) {
+            continue;
+        }
+        let l = bind_listener(&a, only_v6)?;
+        info!("Pre-bound listener {}", a);
+        crate::systemd::put_listener(l);
+    }
+    Ok(())
+}
+
+/// TCP listener, IPv6 one accepts only IPv6 with only_v6, so [::] and 0.0.0.0
+/// on the same port can be bound together
+pub fn bind_listener(
+    addr: &std::net::SocketAddr,
+    only_v6: bool,
+) -> io::Result<std::net::TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let sock = Socket::new(
+        Domain::for_address(*addr),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if addr.is_ipv6() {
+        sock.set_only_v6(only_v6)?;
+    }
+    #[cfg(unix)]
+    sock.set_reuse_address(true)?;
+    sock.bind(&(*addr).into())?;
+    sock.listen(1024)?;
+    Ok(sock.into())
+}
+
+/// detaches from controlling terminal, should be called before any threads are started.
+/// Working directory becomes /, standard streams are redirected to /dev/null
+#[cfg(unix)]
+pub fn daemonize() -> io::Result<()> {
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let root = std::ffi::CString::new("/").unwrap();
+        if libc::chdir(root.as_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::umask(0o022);
+        let devnull = std::ffi::CString::new("/dev/null").unwrap();
+        let fd = libc::open(devnull.as_ptr(), libc::O_RDWR);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for std_fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            if libc::dup2(fd, std_fd) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if fd > libc::STDERR_FILENO {
+            libc::close(fd);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "daemonize is not supported on this platform",
+    ))
+}
+
+/// writes current process id to pidfile, owned by user and group privileges are dropped to
+pub fn write_pidfile(path: &str, user: Option<&str>, group: Option<&str>) -> io::Result<()> {
+    std::fs::write(path, format!("{}\n", std::process::id()))?;
+    #[cfg(unix)]
+    if user.is_some() || group.is_some() {
+        let (uid, gid) = lookup_ids(user, group)?;
+        let cpath = std::ffi::CString::new(path)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid pidfile path"))?;
+        // -1 keeps owner or group unchanged
+        let uid = uid.unwrap_or(libc::uid_t::MAX);
+        let gid = gid.unwrap_or(libc::gid_t::MAX);
+        if unsafe { libc::chown(cpath.as_ptr(), uid, gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (user, group);
+    Ok(())
+}
+
+/// removes pidfile on shutdown
+pub fn remove_pidfile(path: &str) {
+    if let Err(e) = std::fs::remove_file(path) {
+        warn!("Unable to remove pidfile {}: {}", path, e);
+    }
+}
+
+/// uid and gid of configured user and group, group defaults to primary group of user
+#[cfg(unix)]
+fn lookup_ids(
+    user: Option<&str>,
+    group: Option<&str>,
+) -> io::Result<(Option<libc::uid_t>, Option<libc::gid_t>)> {
+    use std::ffi::CString;
+    let cstr = |s: &str| {
+        CString::new(s).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid name"))
+    };
+    let mut uid: Option<libc::uid_t> = None;
+    let mut gid: Option<libc::gid_t> = None;
+    if let Some(u) = user {
+        let cu = cstr(u)?;
+        let pw = unsafe { libc::getpwnam(cu.as_ptr()) };
+        if pw.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unknown user {}", u),
+            ));
+        }
+        uid = Some(unsafe { (*pw).pw_uid });
+        gid = Some(unsafe { (*pw).pw_gid });
+    }
+    if let Some(g) = group {
+        let cg = cstr(g)?;
+        let gr = unsafe { libc::getgrnam(cg.as_ptr()) };
+        if gr.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unknown group {}", g),
+            ));
+        }
+        gid = Some(unsafe { (*gr).gr_gid });
+    }
+    Ok((uid, gid))
+}
+
+/// switches process to configured group and user
+#[cfg(unix)]
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> io::Result<()> {
+    let (uid, gid) = lookup_ids(user, group)?;
+    if let Some(g) = gid {
+        unsafe {
+            if libc::setgroups(1, &g) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::setgid(g) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    if let Some(u) = uid {
+        if unsafe { libc::setuid(u) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    info!(
+        "Running as uid {} gid {}",
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() }
+    );
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(_user: Option<&str>, _group: Option<&str>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "privilege drop is not supported on this platform",
+    ))
+}
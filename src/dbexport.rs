@@ -0,0 +1,365 @@
+//! "clickhouse" and "postgres" route processors exporting ipv4u/ipv6u events in batches.
+//! Events are queued without blocking RIB updates, a full queue drops new events.
+use crate::archive::{unicast_prefixes, AttrBundle};
+use crate::bgpattrs::BgpAttrs;
+use crate::bgpsvc::BgpSessionId;
+use crate::config::{ErrorConfig, SvcConfig};
+use crate::processor::RouteProcessor;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use zettabgp::prelude::*;
+
+/// failed batch is retried this many times before it is dropped
+const MAX_RETRIES: u32 = 5;
+
+/// exported row
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportRow {
+    pub ts_us: i64,
+    pub session: BgpSessionId,
+    pub prefix: String,
+    /// announce/withdraw
+    pub kind: &'static str,
+    pub origin_as: Option<u32>,
+    pub aspath: String,
+    /// attributes JSON, empty for withdraws
+    pub attrs: String,
+}
+
+/// batching options common to database sinks
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub table: String,
+    pub batch: usize,
+    pub flush: Duration,
+    pub queue: usize,
+}
+impl ExportOptions {
+    pub fn parse(
+        name: &str,
+        opts: &BTreeMap<String, String>,
+    ) -> Result<ExportOptions, ErrorConfig> {
+        let num = |key: &str, default: u64| -> Result<u64, ErrorConfig> {
+            match opts.get(key) {
+                Some(s) => s.parse().map_err(|e| {
+                    ErrorConfig::from_string(format!("Invalid {}.{} - {}", name, key, e))
+                }),
+                None => Ok(default),
+            }
+        };
+        let table = opts
+            .get("table")
+            .cloned()
+            .unwrap_or_else(|| "bgp_events".to_string());
+        if !table
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            return Err(ErrorConfig::from_string(format!(
+                "Invalid {}.table - only letters, digits, _ and . are allowed",
+                name
+            )));
+        }
+        Ok(ExportOptions {
+            table,
+            batch: num("batch", 1000)?.max(1) as usize,
+            flush: match opts.get("flush") {
+                Some(s) => crate::config::parse_duration(s).map_err(|e| {
+                    ErrorConfig::from_string(format!("Invalid {}.flush - {}", name, e))
+                })?,
+                None => Duration::from_secs(5),
+            }
+            .max(Duration::from_secs(1)),
+            queue: num("queue", 100000)?.max(1) as usize,
+        })
+    }
+}
+
+/// database writer
+enum Sink {
+    ClickHouse {
+        url: hyper::Uri,
+        user: Option<String>,
+        password: Option<String>,
+    },
+    #[cfg(feature = "postgres")]
+    Postgres {
+        dsn: String,
+        client: Option<tokio_postgres::Client>,
+    },
+}
+impl Sink {
+    async fn write(&mut self, table: &str, rows: &[ExportRow]) -> Result<(), String> {
+        match self {
+            Sink::ClickHouse {
+                url,
+                user,
+                password,
+            } => {
+                let mut body = String::new();
+                for r in rows.iter() {
+                    body.push_str(&serde_json::to_string(r).map_err(|e| e.to_string())?);
+                    body.push('\n');
+                }
+                let query = crate::objstore::uri_encode(
+                    format!("INSERT INTO {} FORMAT JSONEachRow", table).as_str(),
+                    false,
+                );
+                let uri = format!(
+                    "{}://{}/?query={}",
+                    url.scheme_str().unwrap_or("http"),
+                    url.authority()
+                        .map(|a| a.as_str())
+                        .unwrap_or("localhost:8123"),
+                    query
+                );
+                let mut req = hyper::Request::post(uri);
+                if let Some(u) = user.as_ref() {
+                    req = req.header("X-ClickHouse-User", u.as_str());
+                }
+                if let Some(p) = password.as_ref() {
+                    req = req.header("X-ClickHouse-Key", p.as_str());
+                }
+                let req = req
+                    .body(hyper::Body::from(body))
+                    .map_err(|e| e.to_string())?;
+                let rsp = hyper::Client::new()
+                    .request(req)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if !rsp.status().is_success() {
+                    let status = rsp.status();
+                    let text = hyper::body::to_bytes(rsp.into_body())
+                        .await
+                        .map(|b| String::from_utf8_lossy(&b).to_string())
+                        .unwrap_or_default();
+                    return Err(format!("{} {}", status, text.trim()));
+                }
+                Ok(())
+            }
+            #[cfg(feature = "postgres")]
+            Sink::Postgres { dsn, client } => {
+                if client.as_ref().map(|c| c.is_closed()).unwrap_or(true) {
+                    let (c, conn) = tokio_postgres::connect(dsn.as_str(), tokio_postgres::NoTls)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    tokio::spawn(async move {
+                        if let Err(e) = conn.await {
+                            warn!("PostgreSQL connection: {}", e);
+                        }
+                    });
+                    *client = Some(c);
+                }
+                let c = client.as_ref().unwrap();
+                // one round trip per batch, columns are passed as arrays
+                let stmt = format!(
+                    "INSERT INTO {} (ts, session, prefix, kind, origin_as, aspath, attrs) \
+                     SELECT to_timestamp(t / 1000000.0), s, p, k, o, a, NULLIF(j, '')::jsonb \
+                     FROM unnest($1::bigint[], $2::int[], $3::text[], $4::text[], $5::bigint[], $6::text[], $7::text[]) \
+                     AS x(t, s, p, k, o, a, j)",
+                    table
+                );
+                let ts: Vec<i64> = rows.iter().map(|r| r.ts_us).collect();
+                let sess: Vec<i32> = rows.iter().map(|r| r.session as i32).collect();
+                let pfx: Vec<&str> = rows.iter().map(|r| r.prefix.as_str()).collect();
+                let kind: Vec<&str> = rows.iter().map(|r| r.kind).collect();
+                let origin: Vec<Option<i64>> =
+                    rows.iter().map(|r| r.origin_as.map(|x| x as i64)).collect();
+                let aspath: Vec<&str> = rows.iter().map(|r| r.aspath.as_str()).collect();
+                let attrs: Vec<&str> = rows.iter().map(|r| r.attrs.as_str()).collect();
+                c.execute(
+                    stmt.as_str(),
+                    &[&ts, &sess, &pfx, &kind, &origin, &aspath, &attrs],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// writes queued rows in batches, flushes partial batch every flush interval
+async fn run_writer(
+    name: &'static str,
+    mut sink: Sink,
+    opts: ExportOptions,
+    mut rx: mpsc::Receiver<ExportRow>,
+) {
+    let mut pending: Vec<ExportRow> = Vec::with_capacity(opts.batch);
+    // armed by first row of batch, later rows do not push it back
+    let mut deadline: Option<tokio::time::Instant> = None;
+    loop {
+        let next = match deadline {
+            Some(d) => tokio::time::timeout_at(d, rx.recv()).await,
+            None => Ok(rx.recv().await),
+        };
+        let closed = match next {
+            Ok(Some(r)) => {
+                if pending.is_empty() {
+                    deadline = Some(tokio::time::Instant::now() + opts.flush);
+                }
+                pending.push(r);
+                if pending.len() < opts.batch {
+                    continue;
+                }
+                false
+            }
+            Ok(None) => true,
+            Err(_) => false,
+        };
+        if !pending.is_empty() {
+            let mut retries = 0;
+            while let Err(e) = sink.write(opts.table.as_str(), &pending).await {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    warn!("{} export: {}, {} rows dropped", name, e, pending.len());
+                    break;
+                }
+                warn!("{} export: {}, retry {}", name, e, retries);
+                tokio::time::sleep(Duration::from_secs(1 << retries)).await;
+            }
+            pending.clear();
+        }
+        deadline = None;
+        if closed {
+            return;
+        }
+    }
+}
+
+pub struct DbExport {
+    name: &'static str,
+    queue: mpsc::Sender<ExportRow>,
+    dropped: AtomicU64,
+}
+impl DbExport {
+    fn start(
+        name: &'static str,
+        sink: Sink,
+        opts: ExportOptions,
+    ) -> Result<Arc<dyn RouteProcessor>, ErrorConfig> {
+        let handle = tokio::runtime::Handle::try_current().map_err(|_| {
+            ErrorConfig::from_string(format!("{} export needs tokio runtime", name))
+        })?;
+        let (tx, rx) = mpsc::channel(opts.queue);
+        handle.spawn(run_writer(name, sink, opts, rx));
+        Ok(Arc::new(DbExport {
+            name,
+            queue: tx,
+            dropped: AtomicU64::new(0),
+        }))
+    }
+    /// clickhouse.url - HTTP interface, clickhouse.user and clickhouse.password are optional
+    pub fn create_clickhouse(
+        _cfg: &SvcConfig,
+        opts: &BTreeMap<String, String>,
+    ) -> Result<Arc<dyn RouteProcessor>, ErrorConfig> {
+        let url: hyper::Uri = match opts.get("url") {
+            Some(s) => s
+                .parse()
+                .map_err(|e| ErrorConfig::from_string(format!("Invalid clickhouse.url - {}", e)))?,
+            None => return Err(ErrorConfig::from_str("clickhouse.url is required")),
+        };
+        if url.scheme_str() != Some("http") {
+            return Err(ErrorConfig::from_str(
+                "clickhouse.url should be http:// URL",
+            ));
+        }
+        let sink = Sink::ClickHouse {
+            url,
+            user: opts.get("user").cloned(),
+            password: opts.get("password").cloned(),
+        };
+        DbExport::start(
+            "clickhouse",
+            sink,
+            ExportOptions::parse("clickhouse", opts)?,
+        )
+    }
+    /// postgres.dsn - libpq style connection string
+    #[cfg(feature = "postgres")]
+    pub fn create_postgres(
+        _cfg: &SvcConfig,
+        opts: &BTreeMap<String, String>,
+    ) -> Result<Arc<dyn RouteProcessor>, ErrorConfig> {
+        let dsn = match opts.get("dsn") {
+            Some(s) => s.clone(),
+            None => return Err(ErrorConfig::from_str("postgres.dsn is required")),
+        };
+        let sink = Sink::Postgres { dsn, client: None };
+        DbExport::start("postgres", sink, ExportOptions::parse("postgres", opts)?)
+    }
+    #[cfg(not(feature = "postgres"))]
+    pub fn create_postgres(
+        _cfg: &SvcConfig,
+        _opts: &BTreeMap<String, String>,
+    ) -> Result<Arc<dyn RouteProcessor>, ErrorConfig> {
+        Err(ErrorConfig::from_str("built without postgres feature"))
+    }
+    fn push(&self, row: ExportRow) {
+        if self.queue.try_send(row).is_err() {
+            let n = self.dropped.fetch_add(1, Ordering::Relaxed);
+            if n.is_multiple_of(10000) {
+                warn!("{} export is behind, {} events dropped", self.name, n + 1);
+            }
+        }
+    }
+}
+impl RouteProcessor for DbExport {
+    fn on_update(&self, sid: BgpSessionId, attrs: &BgpAttrs, addrs: &BgpAddrs) {
+        let prefixes = unicast_prefixes(addrs);
+        if prefixes.is_empty() {
+            return;
+        }
+        let ts_us = Timestamp::now().timestamp_micros();
+        let b = AttrBundle::new(attrs);
+        for prefix in prefixes {
+            self.push(ExportRow {
+                ts_us,
+                session: sid,
+                prefix,
+                kind: "announce",
+                origin_as: b.origin_as,
+                aspath: b.aspath.clone(),
+                attrs: b.attrs.clone(),
+            });
+        }
+    }
+    fn on_withdraw(&self, sid: BgpSessionId, addrs: &BgpAddrs) {
+        let ts_us = Timestamp::now().timestamp_micros();
+        for prefix in unicast_prefixes(addrs) {
+            self.push(ExportRow {
+                ts_us,
+                session: sid,
+                prefix,
+                kind: "withdraw",
+                origin_as: None,
+                aspath: String::new(),
+                attrs: String::new(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_options() {
+        let mut opts = BTreeMap::new();
+        opts.insert("batch".to_string(), "500".to_string());
+        let o = ExportOptions::parse("clickhouse", &opts).unwrap();
+        assert_eq!(o.batch, 500);
+        assert_eq!(o.table, "bgp_events");
+        assert_eq!(o.flush, Duration::from_secs(5));
+        opts.insert("table".to_string(), "x; DROP TABLE y".to_string());
+        assert!(ExportOptions::parse("clickhouse", &opts).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! federation: mirrors RIBs of other bgpexplorer instances for merged multi-collector view
+use crate::bgprib::{BgpRIB, BgpRIBKey, BgpRIBSafi};
+use crate::bgpsvc::BSEItems;
+use crate::config::SvcConfig;
+use crate::jsonfmt::PrefixFormat;
+use crate::ribfilter::{FilterMatchRoute, RouteFilter};
+use crate::ribservice::RibResponseParams;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::RwLock;
+
+/// remote instance serving replication stream and its mirrored RIB
+pub struct FederationSource {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub rib: Arc<RwLock<BgpRIB>>,
+}
+impl FederationSource {
+    pub fn new(cfg: &SvcConfig, name: &str, addr: SocketAddr) -> FederationSource {
+        FederationSource {
+            name: name.to_string(),
+            addr,
+            rib: Arc::new(RwLock::new(BgpRIB::new(cfg))),
+        }
+    }
+    pub fn from_config(cfg: &SvcConfig) -> Vec<Arc<FederationSource>> {
+        cfg.federation
+            .iter()
+            .map(|(name, addr)| Arc::new(FederationSource::new(cfg, name, *addr)))
+            .collect()
+    }
+    /// keeps mirroring remote RIB, reconnects on failures
+    pub async fn run(self: Arc<Self>, cfg: Arc<SvcConfig>) {
+        loop {
+            let mut last_seen = Instant::now();
+            if let Err(e) =
+                crate::replication::mirror(&cfg, &self.rib, self.addr, &mut last_seen).await
+            {
+                warn!("Federation source {} ({}): {}", self.name, self.addr, e);
+            }
+            tokio::time::sleep(cfg.replication_interval).await;
+        }
+    }
+}
+
+/// sessions of one prefix by source name
+type MergedRoute<'a> = (String, Vec<(&'a str, BSEItems<'a, 'a>)>);
+
+/// routes of several RIBs matching filter, merged by prefix in RIB order
+pub struct MergedItems<'a> {
+    pub items: Vec<MergedRoute<'a>>,
+    pub found: usize,
+    format: PrefixFormat,
+}
+impl<'a> MergedItems<'a> {
+    /// walks RIBs side by side, so merged routes keep prefix order and skip/limit apply to
+    /// merged view; result sort is not supported here
+    pub fn new<T: FilterMatchRoute + BgpRIBKey + ToString>(
+        ribs: &[(&'a str, &'a BgpRIBSafi<T>)],
+        filter: &'a RouteFilter,
+        params: &'a RibResponseParams,
+    ) -> MergedItems<'a> {
+        let mut iters: Vec<_> = ribs
+            .iter()
+            .map(|(name, safi)| {
+                let it = filter
+                    .iter_nets(safi, params.maxdepth, params.onlyactive)
+                    .filter(move |(k, v)| params.in_scope(*k, v))
+                    .filter(move |(_, v)| !BSEItems::new(v, params).is_empty())
+                    .peekable();
+                (*name, it)
+            })
+            .collect();
+        let end = params.skip.saturating_add(params.limit);
+        let mut ret = MergedItems {
+            items: Vec::new(),
+            found: 0,
+            format: params.format.prefix,
+        };
+        while !params.expired() {
+            let first: Option<&'a T> = iters
+                .iter_mut()
+                .filter_map(|(_, it)| it.peek().map(|(k, _)| *k))
+                .min();
+            let first = match first {
+                None => break,
+                Some(k) => k,
+            };
+            let mut route = Vec::new();
+            for (name, it) in iters.iter_mut() {
+                if it.peek().map(|(k, _)| *k == first).unwrap_or(false) {
+                    if let Some((_, v)) = it.next() {
+                        route.push((*name, BSEItems::new(v, params)));
+                    }
+                }
+            }
+            ret.found += 1;
+            if ret.found > params.skip && ret.found <= end {
+                ret.items.push((first.to_string(), route));
+            }
+        }
+        ret
+    }
+}
+/// sessions of prefix keyed by source name
+struct MergedSources<'r, 'a>(&'r [(&'a str, BSEItems<'a, 'a>)]);
+impl<'r, 'a> serde::Serialize for MergedSources<'r, 'a> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut state = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, v) in self.0.iter() {
+            state.serialize_entry(name, v)?;
+        }
+        state.end()
+    }
+}
+impl<'a> serde::Serialize for MergedItems<'a> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::jsonfmt::serialize_items(
+            serializer,
+            self.items
+                .iter()
+                .map(|(k, v)| (k.clone(), MergedSources(v)))
+                .collect(),
+            self.format,
+        )
+    }
+}
+
+/// merged view of named RIB over local and mirrored RIBs as json, None for unknown RIB
+pub fn merged_json(
+    ribs: &[(&str, &BgpRIB)],
+    ribname: &str,
+    filter: &RouteFilter,
+    params: &RibResponseParams,
+) -> Option<serde_json::Value> {
+    macro_rules! merged {
+        ($safi:ident) => {{
+            let safis: Vec<_> = ribs.iter().map(|(n, r)| (*n, &r.$safi)).collect();
+            let m = MergedItems::new(&safis, filter, params);
+            serde_json::json!({"found": m.found, "items": m})
+        }};
+    }
+    Some(match ribname {
+        "ipv4u" => merged!(ipv4u),
+        "ipv4m" => merged!(ipv4m),
+        "ipv4lu" => merged!(ipv4lu),
+        "vpnv4u" => merged!(vpnv4u),
+        "vpnv4m" => merged!(vpnv4m),
+        "ipv6u" => merged!(ipv6u),
+        "ipv6m" => merged!(ipv6m),
+        "ipv6lu" => merged!(ipv6lu),
+        "vpnv6u" => merged!(vpnv6u),
+        "vpnv6m" => merged!(vpnv6m),
+        "l2vpls" => merged!(l2vpls),
+        "mvpn" => merged!(mvpn),
+        "evpn" => merged!(evpn),
+        "fs4u" => merged!(fs4u),
+        "ipv4mdt" => merged!(ipv4mdt),
+        "ipv6mdt" => merged!(ipv6mdt),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HistoryChangeMode;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_merged_items() {
+        let net = |o: u8, len: u8| BgpAddrV4::new(std::net::Ipv4Addr::new(10, o, 0, 0), len);
+        let attrs = Arc::new(crate::bgpattrs::BgpAttrs::new());
+        let mut local = BgpRIBSafi::<BgpAddrV4>::new(10, HistoryChangeMode::EveryUpdate);
+        local.handle_updates_afi(1, &[net(0, 8), net(2, 16)], attrs.clone());
+        let mut rs1 = BgpRIBSafi::<BgpAddrV4>::new(10, HistoryChangeMode::EveryUpdate);
+        rs1.handle_updates_afi(7, &[net(1, 16), net(2, 16)], attrs);
+        let ribs = [("local", &local), ("rs1", &rs1)];
+        let filter = RouteFilter::new();
+        let params = RibResponseParams::new(0, 1000, 10, false);
+        let m = MergedItems::new(&ribs, &filter, &params);
+        assert_eq!(m.found, 3);
+        let routes: Vec<(&str, Vec<&str>)> = m
+            .items
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.iter().map(|x| x.0).collect()))
+            .collect();
+        assert_eq!(
+            routes,
+            vec![
+                ("10.0.0.0/8", vec!["local"]),
+                ("10.1.0.0/16", vec!["rs1"]),
+                ("10.2.0.0/16", vec!["local", "rs1"]),
+            ]
+        );
+        let v = serde_json::to_value(&m).unwrap();
+        assert!(v["10.2.0.0/16"]["rs1"]["7"].is_object());
+        let params = RibResponseParams::new(1, 1, 10, false);
+        let m = MergedItems::new(&ribs, &filter, &params);
+        assert_eq!(m.found, 3);
+        assert_eq!(m.items.len(), 1);
+        assert_eq!(m.items[0].0, "10.1.0.0/16");
+    }
+}
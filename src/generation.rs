@@ -0,0 +1,89 @@
+use crate::bgprib::BgpRibKind;
+use crate::timestamp::Timestamp;
+use std::collections::BTreeMap;
+
+/// Change counter with time of last change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RibGeneration {
+    pub counter: u64,
+    pub changed: Timestamp,
+}
+
+/// RIB generation counters, global and for each RIB kind
+#[derive(Debug, Clone)]
+pub struct RibGenerations {
+    /// RIB creation time, makes counters unique across restarts
+    pub started: Timestamp,
+    pub global: RibGeneration,
+    pub ribs: BTreeMap<BgpRibKind, RibGeneration>,
+}
+impl Default for RibGenerations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl RibGenerations {
+    pub fn new() -> RibGenerations {
+        let now = Timestamp::now();
+        RibGenerations {
+            started: now,
+            global: RibGeneration {
+                counter: 0,
+                changed: now,
+            },
+            ribs: BTreeMap::new(),
+        }
+    }
+    /// account RIB change
+    pub fn bump(&mut self, kind: Option<BgpRibKind>) {
+        let now = Timestamp::now();
+        self.global.counter += 1;
+        self.global.changed = now;
+        if let Some(k) = kind {
+            let g = self.ribs.entry(k).or_insert(RibGeneration {
+                counter: 0,
+                changed: now,
+            });
+            g.counter += 1;
+            g.changed = now;
+        }
+    }
+    /// generation for RIB kind scope, or global one
+    pub fn get(&self, kind: Option<BgpRibKind>) -> RibGeneration {
+        match kind {
+            None => self.global,
+            Some(k) => self.ribs.get(&k).copied().unwrap_or(RibGeneration {
+                counter: 0,
+                changed: self.started,
+            }),
+        }
+    }
+    /// entity tag for scope and query text
+    pub fn etag(&self, kind: Option<BgpRibKind>, query: &str) -> String {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        query.hash(&mut hasher);
+        format!(
+            "W/\"{:x}-{}-{:x}\"",
+            self.started.timestamp_millis(),
+            self.get(kind).counter,
+            hasher.finish()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation_scope() {
+        let mut g = RibGenerations::new();
+        let e4 = g.etag(Some(BgpRibKind::IpV4u), "/api/json/ipv4u");
+        let e6 = g.etag(Some(BgpRibKind::IpV6u), "/api/json/ipv6u");
+        g.bump(Some(BgpRibKind::IpV6u));
+        assert_eq!(e4, g.etag(Some(BgpRibKind::IpV4u), "/api/json/ipv4u"));
+        assert_ne!(e6, g.etag(Some(BgpRibKind::IpV6u), "/api/json/ipv6u"));
+        assert_eq!(g.get(None).counter, 1);
+    }
+}
@@ -0,0 +1,772 @@
+//! /api/graphql: GraphQL subset over RIB and enrichment data, so frontends get prefix with
+//! its history, whois, RPKI state and origin AS names in one request shaped as needed.
+//! Queries with variables and aliases are supported, fragments, directives, mutations and
+//! introspection (except __typename) are not.
+//!
+//! ```text
+//! type Query { prefix(prefix: String!, rib: String): Prefix  asn(asn: Int!): AS }
+//! type Prefix { prefix rib active history(limit: Int): [HistoryRow] origins: [AS] whois rpki: Rpki }
+//! type Rpki { state roas: [Roa] }
+//! type AS { asn name whois prefixes(limit: Int): [String] }
+//! ```
+use crate::bgpsvc::BgpSvr;
+use crate::rpki::{RoaTable, RpkiState};
+use crate::whoissvc::WhoisSvr;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// nesting depth of selection sets and of list/object values
+const MAX_DEPTH: usize = 8;
+/// fields in query document
+const MAX_FIELDS: usize = 256;
+/// POST body size limit
+pub const MAX_BODY: usize = crate::service::MAX_API_BODY;
+/// RIB lookups per request, prefix history and originated prefixes of AS
+pub const RIB_LIMIT: usize = 16;
+/// whois lookups per request, names and whois fields of one object share lookup
+pub const WHOIS_LIMIT: usize = 10;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct GraphQlRequest {
+    pub query: String,
+    #[serde(default, rename = "operationName")]
+    pub operation_name: Option<String>,
+    #[serde(default)]
+    pub variables: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Punct(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+fn lex(s: &str) -> Result<Vec<Tok>, String> {
+    let mut ret = Vec::new();
+    let mut it = s.chars().peekable();
+    while let Some(c) = it.next() {
+        match c {
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {}
+            '#' => {
+                while it.peek().map(|c| *c != '\n').unwrap_or(false) {
+                    it.next();
+                }
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '!' | '$' | '=' | '@' | '|' | '&' => {
+                ret.push(Tok::Punct(c))
+            }
+            '.' => {
+                if it.next() != Some('.') || it.next() != Some('.') {
+                    return Err("unexpected .".to_string());
+                }
+                ret.push(Tok::Spread);
+            }
+            '"' => {
+                let mut v = String::new();
+                loop {
+                    match it.next() {
+                        None | Some('\n') => return Err("unterminated string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => match it.next() {
+                            Some('n') => v.push('\n'),
+                            Some('t') => v.push('\t'),
+                            Some('r') => v.push('\r'),
+                            Some('b') => v.push('\u{8}'),
+                            Some('f') => v.push('\u{c}'),
+                            Some('u') => {
+                                let hex: String = (0..4).filter_map(|_| it.next()).collect();
+                                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                                    Some(ch) => v.push(ch),
+                                    None => return Err(format!("invalid escape \\u{}", hex)),
+                                }
+                            }
+                            Some(ch @ '"') | Some(ch @ '\\') | Some(ch @ '/') => v.push(ch),
+                            _ => return Err("invalid escape in string".to_string()),
+                        },
+                        Some(ch) => v.push(ch),
+                    }
+                }
+                ret.push(Tok::Str(v));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut v = c.to_string();
+                while let Some(ch) = it.peek().copied() {
+                    if ch.is_ascii_digit() || ".eE+-".contains(ch) {
+                        v.push(ch);
+                        it.next();
+                    } else {
+                        break;
+                    }
+                }
+                if v.contains(|ch| ".eE".contains(ch)) {
+                    ret.push(Tok::Float(
+                        v.parse().map_err(|_| format!("invalid number {}", v))?,
+                    ));
+                } else {
+                    ret.push(Tok::Int(
+                        v.parse().map_err(|_| format!("invalid number {}", v))?,
+                    ));
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut v = c.to_string();
+                while let Some(ch) = it.peek().copied() {
+                    if ch.is_ascii_alphanumeric() || ch == '_' {
+                        v.push(ch);
+                        it.next();
+                    } else {
+                        break;
+                    }
+                }
+                ret.push(Tok::Name(v));
+            }
+            c => return Err(format!("unexpected {}", c)),
+        }
+    }
+    Ok(ret)
+}
+
+/// argument value, variables are substituted at execution
+#[derive(Debug, Clone, PartialEq)]
+enum Lit {
+    Var(String),
+    Json(Value),
+    List(Vec<Lit>),
+    Object(Vec<(String, Lit)>),
+}
+impl Lit {
+    fn eval(&self, vars: &Map<String, Value>) -> Value {
+        match self {
+            Lit::Var(n) => vars.get(n).cloned().unwrap_or(Value::Null),
+            Lit::Json(v) => v.clone(),
+            Lit::List(l) => Value::Array(l.iter().map(|x| x.eval(vars)).collect()),
+            Lit::Object(o) => {
+                Value::Object(o.iter().map(|(k, x)| (k.clone(), x.eval(vars))).collect())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub alias: Option<String>,
+    pub name: String,
+    args: Vec<(String, Lit)>,
+    pub selection: Vec<Field>,
+}
+impl Field {
+    /// key of field in response
+    pub fn key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+    pub fn arg(&self, name: &str, vars: &Map<String, Value>) -> Value {
+        self.args
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.eval(vars))
+            .unwrap_or(Value::Null)
+    }
+}
+
+struct Operation {
+    name: Option<String>,
+    defaults: Map<String, Value>,
+    selection: Vec<Field>,
+}
+
+struct Parser {
+    toks: Vec<Tok>,
+    pos: usize,
+    fields: usize,
+}
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos)
+    }
+    fn next(&mut self) -> Option<Tok> {
+        let t = self.toks.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+    fn is_punct(&self, c: char) -> bool {
+        self.peek() == Some(&Tok::Punct(c))
+    }
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next() {
+            Some(Tok::Punct(p)) if p == c => Ok(()),
+            t => Err(format!("expected {}, got {}", c, describe(t.as_ref()))),
+        }
+    }
+    fn name(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Tok::Name(n)) => Ok(n),
+            t => Err(format!("expected name, got {}", describe(t.as_ref()))),
+        }
+    }
+    fn document(&mut self) -> Result<Vec<Operation>, String> {
+        let mut ret = Vec::new();
+        while let Some(t) = self.peek() {
+            let op = match t {
+                Tok::Punct('{') => Operation {
+                    name: None,
+                    defaults: Map::new(),
+                    selection: self.selection(0)?,
+                },
+                Tok::Name(n) if n == "query" => {
+                    self.next();
+                    let name = match self.peek() {
+                        Some(Tok::Name(_)) => Some(self.name()?),
+                        _ => None,
+                    };
+                    let defaults = if self.is_punct('(') {
+                        self.variables()?
+                    } else {
+                        Map::new()
+                    };
+                    if self.is_punct('@') {
+                        return Err("directives are not supported".to_string());
+                    }
+                    Operation {
+                        name,
+                        defaults,
+                        selection: self.selection(0)?,
+                    }
+                }
+                Tok::Name(n) if n == "mutation" || n == "subscription" => {
+                    return Err("only queries are supported".to_string())
+                }
+                Tok::Name(n) if n == "fragment" => {
+                    return Err("fragments are not supported".to_string())
+                }
+                t => return Err(format!("unexpected {}", describe(Some(t)))),
+            };
+            ret.push(op);
+        }
+        if ret.is_empty() {
+            return Err("empty query".to_string());
+        }
+        Ok(ret)
+    }
+    /// variable definitions, types are not checked, returns default values
+    fn variables(&mut self) -> Result<Map<String, Value>, String> {
+        let mut ret = Map::new();
+        self.expect('(')?;
+        while !self.is_punct(')') {
+            self.expect('$')?;
+            let name = self.name()?;
+            self.expect(':')?;
+            self.var_type()?;
+            if self.is_punct('=') {
+                self.next();
+                ret.insert(name, self.value(true, 0)?.eval(&Map::new()));
+            }
+        }
+        self.next();
+        Ok(ret)
+    }
+    fn var_type(&mut self) -> Result<(), String> {
+        if self.is_punct('[') {
+            self.next();
+            self.var_type()?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        if self.is_punct('!') {
+            self.next();
+        }
+        Ok(())
+    }
+    fn selection(&mut self, depth: usize) -> Result<Vec<Field>, String> {
+        if depth >= MAX_DEPTH {
+            return Err(format!("selections nested deeper than {}", MAX_DEPTH));
+        }
+        self.expect('{')?;
+        let mut ret = Vec::new();
+        while !self.is_punct('}') {
+            if self.peek() == Some(&Tok::Spread) {
+                return Err("fragments are not supported".to_string());
+            }
+            self.fields += 1;
+            if self.fields > MAX_FIELDS {
+                return Err(format!("more than {} fields", MAX_FIELDS));
+            }
+            let mut name = self.name()?;
+            let mut alias = None;
+            if self.is_punct(':') {
+                self.next();
+                alias = Some(name);
+                name = self.name()?;
+            }
+            let mut args = Vec::new();
+            if self.is_punct('(') {
+                self.next();
+                while !self.is_punct(')') {
+                    let n = self.name()?;
+                    self.expect(':')?;
+                    args.push((n, self.value(false, 0)?));
+                }
+                self.next();
+            }
+            if self.is_punct('@') {
+                return Err("directives are not supported".to_string());
+            }
+            let selection = if self.is_punct('{') {
+                self.selection(depth + 1)?
+            } else {
+                Vec::new()
+            };
+            ret.push(Field {
+                alias,
+                name,
+                args,
+                selection,
+            });
+        }
+        self.next();
+        if ret.is_empty() {
+            return Err("empty selection".to_string());
+        }
+        Ok(ret)
+    }
+    fn value(&mut self, constant: bool, depth: usize) -> Result<Lit, String> {
+        if depth >= MAX_DEPTH {
+            return Err(format!("values nested deeper than {}", MAX_DEPTH));
+        }
+        Ok(match self.next() {
+            Some(Tok::Punct('$')) if !constant => Lit::Var(self.name()?),
+            Some(Tok::Int(n)) => Lit::Json(json!(n)),
+            Some(Tok::Float(f)) => Lit::Json(json!(f)),
+            Some(Tok::Str(s)) => Lit::Json(Value::String(s)),
+            Some(Tok::Name(n)) => Lit::Json(match n.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                _ => Value::String(n),
+            }),
+            Some(Tok::Punct('[')) => {
+                let mut l = Vec::new();
+                while !self.is_punct(']') {
+                    l.push(self.value(constant, depth + 1)?);
+                }
+                self.next();
+                Lit::List(l)
+            }
+            Some(Tok::Punct('{')) => {
+                let mut o = Vec::new();
+                while !self.is_punct('}') {
+                    let n = self.name()?;
+                    self.expect(':')?;
+                    o.push((n, self.value(constant, depth + 1)?));
+                }
+                self.next();
+                Lit::Object(o)
+            }
+            t => return Err(format!("expected value, got {}", describe(t.as_ref()))),
+        })
+    }
+}
+
+fn describe(t: Option<&Tok>) -> String {
+    match t {
+        None => "end of query".to_string(),
+        Some(Tok::Punct(c)) => c.to_string(),
+        Some(Tok::Spread) => "...".to_string(),
+        Some(Tok::Name(n)) => n.clone(),
+        Some(Tok::Int(n)) => n.to_string(),
+        Some(Tok::Float(f)) => f.to_string(),
+        Some(Tok::Str(s)) => format!("\"{}\"", s),
+    }
+}
+
+/// root selection and variables of operation to run
+pub fn prepare(req: &GraphQlRequest) -> Result<(Vec<Field>, Map<String, Value>), String> {
+    let mut ops = Parser {
+        toks: lex(req.query.as_str())?,
+        pos: 0,
+        fields: 0,
+    }
+    .document()?;
+    let idx = match req.operation_name.as_deref() {
+        Some(n) => ops
+            .iter()
+            .position(|o| o.name.as_deref() == Some(n))
+            .ok_or_else(|| format!("unknown operation {}", n))?,
+        None if ops.len() == 1 => 0,
+        None => return Err("operationName is required for several operations".to_string()),
+    };
+    let op = ops.swap_remove(idx);
+    let mut vars = op.defaults;
+    if let Some(v) = req.variables.as_ref() {
+        vars.extend(v.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+    Ok((op.selection, vars))
+}
+
+/// shapes resolved value by selection
+pub fn project(v: &Value, sel: &[Field]) -> Value {
+    if sel.is_empty() {
+        return v.clone();
+    }
+    match v {
+        Value::Array(a) => Value::Array(a.iter().map(|x| project(x, sel)).collect()),
+        Value::Object(o) => Value::Object(
+            sel.iter()
+                .map(|f| {
+                    (
+                        f.key().to_string(),
+                        project(o.get(&f.name).unwrap_or(&Value::Null), &f.selection),
+                    )
+                })
+                .collect(),
+        ),
+        v => v.clone(),
+    }
+}
+
+/// "addr/len" or host address
+fn parse_prefix(s: &str) -> Option<(std::net::IpAddr, u8)> {
+    let (a, l) = match s.split_once('/') {
+        Some((a, l)) => (a.parse().ok()?, l.parse().ok()?),
+        None => {
+            let a: std::net::IpAddr = s.parse().ok()?;
+            (a, if a.is_ipv4() { 32 } else { 128 })
+        }
+    };
+    Some((a, l))
+}
+
+/// origin AS of history row with aspath string
+fn row_origin(row: &Value) -> Option<u32> {
+    row.get("aspath")?
+        .as_str()?
+        .split_whitespace()
+        .last()?
+        .trim_matches(|c: char| !c.is_ascii_digit())
+        .parse()
+        .ok()
+}
+
+/// RFC 6811 state of prefix announced by current origins with covering ROAs, null state
+/// without current routes, worst state of origins when they differ
+fn rpki_json(roas: &RoaTable, prefix: (std::net::IpAddr, u8), origins: &[Option<u32>]) -> Value {
+    let states: Vec<RpkiState> = origins
+        .iter()
+        .map(|o| roas.validate(prefix.0, prefix.1, *o))
+        .collect();
+    let state = [RpkiState::Invalid, RpkiState::NotFound, RpkiState::Valid]
+        .iter()
+        .find(|s| states.contains(s));
+    json!({
+        "state": state,
+        "roas": roas.covering(prefix.0, prefix.1),
+    })
+}
+
+struct Exec<'a> {
+    bgp: Option<&'a BgpSvr>,
+    whois: &'a Arc<WhoisSvr>,
+    vars: Map<String, Value>,
+    errors: Vec<String>,
+    whois_cache: HashMap<String, Option<String>>,
+    rib_lookups: usize,
+}
+impl<'a> Exec<'a> {
+    /// false with error when request used up its RIB lookups
+    fn rib_budget(&mut self, what: &str) -> bool {
+        if self.rib_lookups >= RIB_LIMIT {
+            self.errors
+                .push(format!("{}: more than {} RIB lookups", what, RIB_LIMIT));
+            return false;
+        }
+        self.rib_lookups += 1;
+        true
+    }
+    async fn whois_text(&mut self, q: String) -> Option<String> {
+        if let Some(v) = self.whois_cache.get(&q) {
+            return v.clone();
+        }
+        if self.whois_cache.len() >= WHOIS_LIMIT {
+            self.errors
+                .push(format!("whois {}: more than {} lookups", q, WHOIS_LIMIT));
+            return None;
+        }
+        let r = match tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            self.whois.query_whois(q.clone(), Arc::new(None)),
+        )
+        .await
+        {
+            Ok(Ok(s)) => Some(s),
+            Ok(Err(e)) => {
+                self.errors.push(format!("whois {}: {:?}", q, e));
+                None
+            }
+            Err(_) => {
+                self.errors.push(format!("whois {}: timed out", q));
+                None
+            }
+        };
+        self.whois_cache.insert(q, r.clone());
+        r
+    }
+    async fn query(&mut self, sel: &[Field]) -> Value {
+        let mut ret = Map::new();
+        for f in sel.iter() {
+            let v = match f.name.as_str() {
+                "__typename" => json!("Query"),
+                "prefix" => self.prefix(f).await,
+                "asn" => match f.arg("asn", &self.vars).as_u64() {
+                    Some(n) if n <= u32::MAX as u64 => self.asn(&f.selection, n as u32).await,
+                    _ => {
+                        self.errors
+                            .push("asn: asn argument is required".to_string());
+                        Value::Null
+                    }
+                },
+                x => {
+                    self.errors.push(format!("unknown field {} of Query", x));
+                    Value::Null
+                }
+            };
+            ret.insert(f.key().to_string(), v);
+        }
+        Value::Object(ret)
+    }
+    async fn prefix(&mut self, f: &Field) -> Value {
+        let prefix = match f.arg("prefix", &self.vars).as_str().and_then(parse_prefix) {
+            Some(p) => p,
+            None => {
+                self.errors
+                    .push("prefix: valid prefix argument is required".to_string());
+                return Value::Null;
+            }
+        };
+        let rib = match f.arg("rib", &self.vars) {
+            Value::String(s) => s,
+            _ => if prefix.0.is_ipv4() { "ipv4u" } else { "ipv6u" }.to_string(),
+        };
+        let bgp = match self.bgp {
+            Some(b) => b,
+            None => return Value::Null,
+        };
+        if !self.rib_budget("prefix") {
+            return Value::Null;
+        }
+        let rows = match bgp.rib.prefix_history_json(prefix.0, prefix.1, &rib).await {
+            None => {
+                self.errors.push("prefix: RIB lock timed out".to_string());
+                return Value::Null;
+            }
+            Some(Err(e)) => {
+                self.errors.push(format!("prefix: {}", e));
+                return Value::Null;
+            }
+            Some(Ok(None)) => return Value::Null,
+            Some(Ok(Some(rows))) => rows,
+        };
+        let name = format!("{}/{}", prefix.0, prefix.1);
+        let current = |r: &&Value| {
+            r.get("active") == Some(&Value::Bool(true)) && r.get("end") == Some(&Value::Null)
+        };
+        let mut ret = Map::new();
+        for sf in f.selection.iter() {
+            let v = match sf.name.as_str() {
+                "__typename" => json!("Prefix"),
+                "prefix" => json!(name),
+                "rib" => json!(rib),
+                "active" => json!(rows.iter().any(|r| current(&r))),
+                "history" => {
+                    let skip = match sf.arg("limit", &self.vars).as_u64() {
+                        Some(n) => rows.len().saturating_sub(n as usize),
+                        None => 0,
+                    };
+                    project(&Value::Array(rows[skip..].to_vec()), &sf.selection)
+                }
+                "origins" => {
+                    let mut origins: Vec<u32> =
+                        rows.iter().filter(current).filter_map(row_origin).collect();
+                    origins.sort_unstable();
+                    origins.dedup();
+                    let mut v = Vec::new();
+                    for asn in origins {
+                        v.push(self.asn(&sf.selection, asn).await);
+                    }
+                    Value::Array(v)
+                }
+                "whois" => json!(self.whois_text(name.clone()).await),
+                "rpki" => match bgp.config.rpki_roas.as_ref() {
+                    Some(roas) => {
+                        // origin of path ending with AS_SET is NONE
+                        let origins: Vec<Option<u32>> = rows
+                            .iter()
+                            .filter(current)
+                            .map(|r| match r.get("aspath").and_then(|p| p.as_str()) {
+                                Some(p) if p.trim_end().ends_with('}') => None,
+                                _ => row_origin(r),
+                            })
+                            .collect();
+                        project(&rpki_json(roas, prefix, &origins), &sf.selection)
+                    }
+                    None => {
+                        self.errors
+                            .push("rpki: rpki_roas is not configured".to_string());
+                        Value::Null
+                    }
+                },
+                x => {
+                    self.errors.push(format!("unknown field {} of Prefix", x));
+                    Value::Null
+                }
+            };
+            ret.insert(sf.key().to_string(), v);
+        }
+        Value::Object(ret)
+    }
+    async fn asn(&mut self, sel: &[Field], asn: u32) -> Value {
+        if sel.is_empty() {
+            return json!(asn);
+        }
+        let mut ret = Map::new();
+        for f in sel.iter() {
+            let v = match f.name.as_str() {
+                "__typename" => json!("AS"),
+                "asn" => json!(asn),
+                "name" => json!(self
+                    .whois_text(format!("AS{}", asn))
+                    .await
+                    .and_then(|s| crate::whoissvc::as_name(s.as_str()))),
+                "whois" => json!(self.whois_text(format!("AS{}", asn)).await),
+                "prefixes" => {
+                    let bgp = match self.bgp {
+                        Some(b) => b,
+                        None => return Value::Null,
+                    };
+                    if !self.rib_budget("prefixes") {
+                        return Value::Null;
+                    }
+                    let set = std::iter::once(asn).collect();
+                    match bgp.rib.originated(&set).await {
+                        None => {
+                            self.errors.push("prefixes: RIB lock timed out".to_string());
+                            Value::Null
+                        }
+                        Some(m) => {
+                            let limit = f
+                                .arg("limit", &self.vars)
+                                .as_u64()
+                                .map(|n| n as usize)
+                                .unwrap_or(usize::MAX);
+                            json!(m
+                                .get(&asn)
+                                .map(|s| s.iter().take(limit).cloned().collect::<Vec<_>>())
+                                .unwrap_or_default())
+                        }
+                    }
+                }
+                x => {
+                    self.errors.push(format!("unknown field {} of AS", x));
+                    Value::Null
+                }
+            };
+            ret.insert(f.key().to_string(), v);
+        }
+        Value::Object(ret)
+    }
+}
+
+/// GraphQL response, Err for queries which can not be parsed
+pub async fn execute(
+    req: &GraphQlRequest,
+    bgp: Option<&BgpSvr>,
+    whois: &Arc<WhoisSvr>,
+) -> Result<Value, String> {
+    let (sel, vars) = prepare(req)?;
+    let mut exec = Exec {
+        bgp,
+        whois,
+        vars,
+        errors: Vec::new(),
+        whois_cache: HashMap::new(),
+        rib_lookups: 0,
+    };
+    let data = exec.query(&sel).await;
+    let mut ret = json!({ "data": data });
+    if !exec.errors.is_empty() {
+        ret["errors"] = Value::Array(
+            exec.errors
+                .into_iter()
+                .map(|e| json!({ "message": e }))
+                .collect(),
+        );
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(q: &str) -> GraphQlRequest {
+        GraphQlRequest {
+            query: q.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_graphql_parse() {
+        let mut r = req(r#"query Q($p: String!, $n: Int = 5) {
+            p: prefix(prefix: $p) { prefix history(limit: $n) { start aspath } origins { asn name } }
+        }"#);
+        r.variables = Some(json!({"p": "10.0.0.0/8"}).as_object().unwrap().clone());
+        let (sel, vars) = prepare(&r).unwrap();
+        assert_eq!(sel.len(), 1);
+        assert_eq!(sel[0].key(), "p");
+        assert_eq!(sel[0].arg("prefix", &vars), json!("10.0.0.0/8"));
+        assert_eq!(sel[0].selection[1].arg("limit", &vars), json!(5));
+        assert_eq!(sel[0].selection[2].selection[1].name, "name");
+        assert!(prepare(&req("{ prefix { ...F } }")).is_err());
+        assert!(prepare(&req("mutation { x }")).is_err());
+        assert!(prepare(&req("{ prefix(prefix: \"x) { prefix } }")).is_err());
+        assert!(prepare(&req("query A { a } query B { b }")).is_err());
+        let deep = format!(
+            "{{ prefix(prefix: {}1{}) {{ prefix }} }}",
+            "[".repeat(100),
+            "]".repeat(100)
+        );
+        assert!(prepare(&req(&deep)).is_err());
+        let wide = format!(
+            "{{ {} }}",
+            "a: prefix(prefix: \"10.0.0.0/8\") { prefix } ".repeat(200)
+        );
+        assert!(prepare(&req(&wide)).is_err());
+        let rows = json!([{"start": 1, "aspath": "64500 64501", "med": null}]);
+        let (sel, _) = prepare(&req("{ start path: aspath }")).unwrap();
+        assert_eq!(
+            project(&rows, &sel),
+            json!([{"start": 1, "path": "64500 64501"}])
+        );
+        assert_eq!(row_origin(&rows[0]), Some(64501));
+        let roas =
+            RoaTable::parse(r#"{"roas": [{"asn": 64501, "prefix": "10.0.0.0/8"}]}"#).unwrap();
+        let p = parse_prefix("10.0.0.0/8").unwrap();
+        assert_eq!(rpki_json(&roas, p, &[Some(64501)])["state"], json!("valid"));
+        assert_eq!(
+            rpki_json(&roas, p, &[Some(64501), Some(64502)])["state"],
+            json!("invalid")
+        );
+        assert_eq!(rpki_json(&roas, p, &[])["state"], Value::Null);
+        assert_eq!(rpki_json(&roas, p, &[])["roas"][0]["maxLength"], json!(8));
+        assert_eq!(
+            parse_prefix("2001:db8::1"),
+            Some(("2001:db8::1".parse().unwrap(), 128))
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Generalized TTL Security Mechanism (RFC 5082): BGP packets are sent with TTL (hop
+//! limit) 255 and kernel drops received ones with TTL below ttlsecurity, so packets
+//! spoofed from more than 255 - ttlsecurity hops away never reach the session
+use std::io;
+
+/// TTL of packets sent by GTSM protected session
+pub const GTSM_TTL: u32 = 255;
+
+#[cfg(target_os = "linux")]
+const IP_MINTTL: libc::c_int = 21;
+#[cfg(target_os = "linux")]
+const IPV6_MINHOPCOUNT: libc::c_int = 73;
+
+/// minimum TTL option needs kernel support, other platforms fail with Unsupported
+#[cfg(target_os = "linux")]
+fn set_min_ttl(fd: std::os::unix::io::RawFd, v6: bool, min_ttl: u8) -> io::Result<()> {
+    let (level, name) = if v6 {
+        (libc::IPPROTO_IPV6, IPV6_MINHOPCOUNT)
+    } else {
+        (libc::IPPROTO_IP, IP_MINTTL)
+    };
+    let val: libc::c_int = min_ttl as libc::c_int;
+    let r = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &val as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if r != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_min_ttl(_fd: i32, _v6: bool, _min_ttl: u8) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "ttlsecurity is supported on Linux only",
+    ))
+}
+
+/// sets outgoing TTL (IPv6 hop limit) of socket, for ebgp_multihop
+#[cfg(unix)]
+pub fn set_ttl<S: std::os::unix::io::AsFd>(sock: &S, v6: bool, ttl: u32) -> io::Result<()> {
+    let s = socket2::SockRef::from(sock);
+    if v6 {
+        s.set_unicast_hops_v6(ttl)
+    } else {
+        s.set_ttl(ttl)
+    }
+}
+
+/// sets outgoing TTL and minimum accepted TTL of socket, listening sockets pass both
+/// to accepted connections
+#[cfg(unix)]
+pub fn apply<S: std::os::unix::io::AsFd>(sock: &S, v6: bool, min_ttl: u8) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    set_ttl(sock, v6, GTSM_TTL)?;
+    set_min_ttl(socket2::SockRef::from(sock).as_raw_fd(), v6, min_ttl)
+}
+
+#[cfg(not(unix))]
+pub fn set_ttl<S>(_sock: &S, _v6: bool, _ttl: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "ebgp_multihop is not supported on this platform",
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn apply<S>(_sock: &S, _v6: bool, _min_ttl: u8) -> io::Result<()> {
+    set_min_ttl(0, false, 0)
+}
@@ -0,0 +1,192 @@
+//! full retained history of one prefix as ndjson or csv rows for offline analysis, one row
+//! per interval while path kept the same state and attributes, withdrawn intervals included
+use crate::bgprib::*;
+use crate::bgpsvc::BgpSessionId;
+use crate::jsonfmt::{time_key, time_value, TimeFormat};
+use crate::timeline::history_epochs;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use zettabgp::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    NdJson,
+    Csv,
+}
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(ExportFormat::NdJson),
+            "csv" => Ok(ExportFormat::Csv),
+            x => Err(format!("unknown export format {}", x)),
+        }
+    }
+}
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::NdJson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryRow {
+    pub prefix: String,
+    pub session: BgpSessionId,
+    pub pathid: BgpPathId,
+    pub start: Timestamp,
+    /// None for current interval
+    pub end: Option<Timestamp>,
+    /// false for withdrawn interval, attributes are the last announced ones
+    pub active: bool,
+    pub origin: String,
+    pub nexthop: String,
+    pub aspath: String,
+    pub med: Option<u32>,
+    pub localpref: Option<u32>,
+    pub communities: String,
+    pub large_communities: String,
+    pub ext_communities: String,
+}
+
+impl HistoryRow {
+    /// ndjson form, start and end in time format
+    pub fn to_json(&self, time: TimeFormat) -> serde_json::Value {
+        let mut v = serde_json::to_value(self).unwrap_or_default();
+        v["start"] = time_value(&self.start, time);
+        v["end"] = match self.end {
+            Some(ref t) => time_value(t, time),
+            None => serde_json::Value::Null,
+        };
+        v
+    }
+}
+
+const CSV_HEADER: &str = "prefix,session,pathid,start,end,active,origin,nexthop,aspath,med,localpref,communities,large_communities,ext_communities";
+
+/// intervals of all pathes of prefix ordered by start time
+pub fn rows(prefix: &str, entry: &BgpSessionEntry) -> Vec<HistoryRow> {
+    let mut ret = Vec::new();
+    for (sid, pe) in entry.items.iter() {
+        for (pid, hist) in pe.items.iter() {
+            for ep in history_epochs(hist) {
+                ret.push(HistoryRow {
+                    prefix: prefix.to_string(),
+                    session: *sid,
+                    pathid: *pid,
+                    start: ep.start,
+                    end: ep.end,
+                    active: ep.active,
+                    origin: ep.attrs.origin.to_string(),
+                    nexthop: ep.attrs.nexthop.to_string(),
+                    aspath: ep.attrs.aspath.to_string(),
+                    med: ep.attrs.med,
+                    localpref: ep.attrs.localpref,
+                    communities: ep.attrs.comms.to_string(),
+                    large_communities: ep.attrs.lcomms.to_string(),
+                    ext_communities: ep.attrs.extcomms.to_string(),
+                });
+            }
+        }
+    }
+    ret.sort_by_key(|a| (a.start, a.session, a.pathid));
+    ret
+}
+
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn opt<T: std::string::ToString>(v: &Option<T>) -> String {
+    v.as_ref().map(|x| x.to_string()).unwrap_or_default()
+}
+
+/// ndjson line or csv record of each row, csv with header, times as jsonfmt time_key
+pub fn render(rows: &[HistoryRow], fmt: ExportFormat, time: TimeFormat) -> String {
+    let mut out = String::new();
+    match fmt {
+        ExportFormat::NdJson => {
+            for r in rows.iter() {
+                out.push_str(r.to_json(time).to_string().as_str());
+                out.push('\n');
+            }
+        }
+        ExportFormat::Csv => {
+            out.push_str(CSV_HEADER);
+            out.push('\n');
+            for r in rows.iter() {
+                let fields = [
+                    r.prefix.clone(),
+                    r.session.to_string(),
+                    r.pathid.to_string(),
+                    time_key(&r.start, time),
+                    r.end
+                        .as_ref()
+                        .map(|t| time_key(t, time))
+                        .unwrap_or_default(),
+                    r.active.to_string(),
+                    r.origin.clone(),
+                    r.nexthop.clone(),
+                    r.aspath.clone(),
+                    opt(&r.med),
+                    opt(&r.localpref),
+                    r.communities.clone(),
+                    r.large_communities.clone(),
+                    r.ext_communities.clone(),
+                ];
+                let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+                out.push_str(line.join(",").as_str());
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgpattrs::{BgpAttrEntry, BgpAttrs};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_history_export() {
+        let mut entry = BgpSessionEntry::new();
+        let attrs = Arc::new(BgpAttrs::new());
+        entry.insert(
+            1,
+            0,
+            Timestamp::from_unix(1700000000, 0),
+            BgpAttrEntry::new(true, attrs.clone(), None),
+        );
+        entry.insert(
+            1,
+            0,
+            Timestamp::from_unix(1700000060, 0),
+            BgpAttrEntry::new(false, attrs, None),
+        );
+        let rows = rows("10.0.0.0/8", &entry);
+        assert_eq!(rows.len(), 2);
+        assert!(!rows[1].active);
+        assert_eq!(rows[0].end, Some(rows[1].start));
+        let csv = render(&rows, ExportFormat::Csv, TimeFormat::Millis);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("10.0.0.0/8,1,0,1700000000000,1700000060000,true,"));
+        let ndjson = render(&rows, ExportFormat::NdJson, TimeFormat::Unix);
+        assert_eq!(ndjson.lines().count(), 2);
+        let first: serde_json::Value =
+            serde_json::from_str(ndjson.lines().next().unwrap()).unwrap();
+        assert_eq!(first["start"], 1700000000);
+        assert_eq!(first["end"], 1700000060);
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert!("xml".parse::<ExportFormat>().is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! inbound prefix and origin AS lists limiting stored unicast routes
+use crate::bgpattrs::BgpAttrs;
+use crate::script::RoutePrefix;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use zettabgp::prelude::*;
+
+/// prefixes of one list, route matches when it is equal to or more specific than entry
+#[derive(Debug, Clone, Default)]
+pub struct PrefixList {
+    v4: Vec<BgpAddrV4>,
+    v6: Vec<BgpAddrV6>,
+}
+impl PrefixList {
+    /// comma or space separated prefixes, host address means /32 or /128
+    pub fn parse(s: &str) -> Result<PrefixList, String> {
+        let mut ret = PrefixList::default();
+        for item in s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|x| !x.is_empty())
+        {
+            let (addr, len) = match item.split_once('/') {
+                Some((a, l)) => (a, Some(l)),
+                None => (item, None),
+            };
+            let addr: IpAddr = addr
+                .parse()
+                .map_err(|_| format!("invalid prefix {}", item))?;
+            let len: Option<u8> = match len {
+                None => None,
+                Some(l) => Some(l.parse().map_err(|_| format!("invalid prefix {}", item))?),
+            };
+            match addr {
+                IpAddr::V4(a) => {
+                    let len = len.unwrap_or(32);
+                    if len > 32 {
+                        return Err(format!("invalid prefix {}", item));
+                    }
+                    ret.v4.push(BgpAddrV4::new(a, len));
+                }
+                IpAddr::V6(a) => {
+                    let len = len.unwrap_or(128);
+                    if len > 128 {
+                        return Err(format!("invalid prefix {}", item));
+                    }
+                    ret.v6.push(BgpAddrV6::new(a, len));
+                }
+            }
+        }
+        Ok(ret)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_empty() && self.v6.is_empty()
+    }
+    /// entries as address/length
+    pub fn items(&self) -> Vec<String> {
+        self.v4
+            .iter()
+            .map(|p| p.to_string())
+            .chain(self.v6.iter().map(|p| p.to_string()))
+            .collect()
+    }
+    pub fn covers(&self, p: &RoutePrefix) -> bool {
+        match p {
+            RoutePrefix::V4(r) => self
+                .v4
+                .iter()
+                .any(|n| n.prefixlen <= r.prefixlen && n.in_subnet(&r.addr)),
+            RoutePrefix::V6(r) => self
+                .v6
+                .iter()
+                .any(|n| n.prefixlen <= r.prefixlen && n.in_subnet(&r.addr)),
+        }
+    }
+}
+
+/// acceptprefixes/rejectprefixes/acceptorigins lists
+#[derive(Debug, Clone, Default)]
+pub struct InboundFilter {
+    pub accept: PrefixList,
+    pub reject: PrefixList,
+    pub origins: BTreeSet<u32>,
+}
+impl InboundFilter {
+    /// comma or space separated AS numbers, AS prefix is allowed
+    pub fn parse_origins(s: &str) -> Result<BTreeSet<u32>, String> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                x.trim_start_matches("AS")
+                    .trim_start_matches("as")
+                    .parse()
+                    .map_err(|_| format!("invalid AS {}", x))
+            })
+            .collect()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.accept.is_empty() && self.reject.is_empty() && self.origins.is_empty()
+    }
+    /// whether route should be stored
+    pub fn accepts(&self, p: &RoutePrefix, attrs: &BgpAttrs) -> bool {
+        self.rejected_by(p, attrs).is_none()
+    }
+    /// name of list which drops route
+    pub fn rejected_by(&self, p: &RoutePrefix, attrs: &BgpAttrs) -> Option<&'static str> {
+        if self.reject.covers(p) {
+            return Some("rejectprefixes");
+        }
+        if !self.accept.is_empty() && !self.accept.covers(p) {
+            return Some("acceptprefixes");
+        }
+        if !self.origins.is_empty() {
+            let known = match attrs.aspath.value.last() {
+                Some(asn) => self.origins.contains(&asn.value),
+                None => false,
+            };
+            if !known {
+                return Some("acceptorigins");
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::CompactASPath;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_inbound_filter() {
+        let flt = InboundFilter {
+            accept: PrefixList::parse("10.0.0.0/8, 2001:db8::/32").unwrap(),
+            reject: PrefixList::parse("10.1.0.0/16").unwrap(),
+            origins: InboundFilter::parse_origins("AS64500 64501").unwrap(),
+        };
+        assert!(PrefixList::parse("10.0.0.0/33").is_err());
+        assert!(InboundFilter::parse_origins("ASx").is_err());
+        let mut attrs = BgpAttrs::new();
+        attrs.aspath = Arc::new(CompactASPath::from(vec![64501]));
+        let p = |a, b, l| RoutePrefix::V4(BgpAddrV4::new(Ipv4Addr::new(10, a, b, 0), l));
+        assert!(flt.accepts(&p(2, 0, 16), &attrs));
+        assert!(!flt.accepts(&p(1, 2, 24), &attrs));
+        assert_eq!(
+            flt.rejected_by(&p(1, 2, 24), &attrs),
+            Some("rejectprefixes")
+        );
+        assert!(!flt.accepts(
+            &RoutePrefix::V4(BgpAddrV4::new(Ipv4Addr::new(192, 168, 0, 0), 16)),
+            &attrs
+        ));
+        // 10.0.0.0/7 is less specific than accepted entry
+        assert!(!flt.accepts(
+            &RoutePrefix::V4(BgpAddrV4::new(Ipv4Addr::new(10, 0, 0, 0), 7)),
+            &attrs
+        ));
+        attrs.aspath = Arc::new(CompactASPath::new());
+        assert!(!flt.accepts(&p(2, 0, 16), &attrs));
+    }
+}
@@ -0,0 +1,376 @@
+//! incident evidence bundle: route history, MOAS and flap records of prefix or origin AS
+//! within time range, packed with whois data and session events into zip archive
+use crate::bgprib::*;
+use crate::bgpsvc::BgpSessionId;
+use crate::histexport::{rows, HistoryRow};
+use crate::inbound::PrefixList;
+use crate::processor::SessionEvent;
+use crate::report::{ReportFlapper, ReportOriginChange};
+use crate::script::RoutePrefix;
+use crate::timestamp::Timestamp;
+use chrono::{Datelike, Timelike};
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::Mutex;
+
+/// routes taken into one bundle
+pub const MAX_ROUTES: usize = 10000;
+/// whois lookups of queried prefixes and AS and of seen origin ASes
+pub const WHOIS_LIMIT: usize = 10;
+/// session events kept in memory
+const SESSION_LOG_SIZE: usize = 1000;
+
+/// from/to URL parameter: milliseconds since epoch or RFC 3339 time
+pub fn parse_time(s: &str) -> Option<Timestamp> {
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        let ms: i64 = s.parse().ok()?;
+        return Some(Timestamp::from_unix(ms / 1000, ((ms % 1000) * 1000) as u32));
+    }
+    let dt = chrono::DateTime::parse_from_rfc3339(s).ok()?;
+    Some(Timestamp::from_unix(
+        dt.timestamp(),
+        dt.timestamp_subsec_micros(),
+    ))
+}
+
+/// routes of interest: covered by prefixes or originated by asn
+pub struct IncidentQuery {
+    pub prefixes: Option<PrefixList>,
+    pub asn: Option<u32>,
+    pub from: Timestamp,
+    pub to: Timestamp,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct IncidentRoutes {
+    #[serde(skip)]
+    pub history: Vec<HistoryRow>,
+    pub routes: usize,
+    /// routes with more than one origin AS during range
+    pub moas: Vec<ReportOriginChange>,
+    /// routes with more than one history record during range
+    pub flaps: Vec<ReportFlapper>,
+    /// origin ASes seen during range
+    pub origins: BTreeSet<u32>,
+    /// MAX_ROUTES was reached
+    pub truncated: bool,
+}
+
+fn origin_of(e: &crate::bgpattrs::BgpAttrEntry) -> Option<u32> {
+    e.attrs.aspath.value.last().map(|x| x.value)
+}
+
+impl IncidentRoutes {
+    /// adds matching routes of rib, prefix gives IP prefix of route key, routes without
+    /// one match only origin AS queries. Routes of other RIBs than ipv4u and ipv6u are
+    /// named with RIB name in front
+    pub fn collect<T: BgpRIBKey + std::fmt::Display, F: Fn(&T) -> Option<RoutePrefix>>(
+        &mut self,
+        rib: &str,
+        safi: &BgpRIBSafi<T>,
+        q: &IncidentQuery,
+        prefix: F,
+    ) {
+        let candidates: Box<dyn Iterator<Item = (&T, &BgpSessionEntry)>> = match q.asn {
+            Some(asn) => match safi.idx_aspath.idx.get(&zettabgp::prelude::BgpAS::new(asn)) {
+                Some(keys) => Box::new(
+                    keys.iter()
+                        .filter_map(|k| safi.items.get(k).map(|e| (k, e))),
+                ),
+                None => return,
+            },
+            None => Box::new(safi.items.iter()),
+        };
+        for (k, entry) in candidates {
+            if let Some(pl) = q.prefixes.as_ref() {
+                match prefix(k) {
+                    Some(p) if pl.covers(&p) => {}
+                    _ => continue,
+                }
+            }
+            let mut origins = BTreeSet::new();
+            let mut changes = 0;
+            for pe in entry.items.values() {
+                for hist in pe.items.values() {
+                    // state at range start counts as well
+                    if let Some((_, rec)) = hist.items.range(..q.from).last() {
+                        if rec.active {
+                            origins.extend(origin_of(rec));
+                        }
+                    }
+                    for (_, rec) in hist.items.range(q.from..=q.to) {
+                        changes += 1;
+                        if rec.active {
+                            origins.extend(origin_of(rec));
+                        }
+                    }
+                }
+            }
+            if origins.is_empty() && changes == 0 {
+                continue;
+            }
+            if let Some(asn) = q.asn {
+                if !origins.contains(&asn) {
+                    continue;
+                }
+            }
+            if self.routes >= MAX_ROUTES {
+                self.truncated = true;
+                return;
+            }
+            self.routes += 1;
+            let route = match rib {
+                "ipv4u" | "ipv6u" => k.to_string(),
+                _ => format!("{} {}", rib, k),
+            };
+            self.history.extend(
+                rows(route.as_str(), entry)
+                    .into_iter()
+                    .filter(|r| r.start <= q.to && r.end.map(|e| e >= q.from).unwrap_or(true)),
+            );
+            if origins.len() > 1 {
+                self.moas.push(ReportOriginChange {
+                    route: route.clone(),
+                    origins: origins.iter().cloned().collect(),
+                });
+            }
+            if changes > 1 {
+                self.flaps.push(ReportFlapper { route, changes });
+            }
+            self.origins.extend(origins);
+        }
+    }
+    pub fn finish(&mut self) {
+        self.flaps.sort_by(|a, b| {
+            b.changes
+                .cmp(&a.changes)
+                .then_with(|| a.route.cmp(&b.route))
+        });
+        self.history
+            .sort_by(|a, b| (a.start, &a.prefix).cmp(&(b.start, &b.prefix)));
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionLogEntry {
+    pub time: Timestamp,
+    pub session: Option<BgpSessionId>,
+    pub event: String,
+}
+
+/// recent session starts and collector state changes
+#[derive(Default)]
+pub struct SessionLog {
+    items: Mutex<VecDeque<SessionLogEntry>>,
+}
+impl SessionLog {
+    pub fn record(&self, evt: &SessionEvent) {
+        let (session, event) = match evt {
+            SessionEvent::Started(sid, d) => (
+                Some(*sid),
+                format!(
+                    "started {} AS{} - {} AS{}",
+                    d.peer1.addr, d.peer1.bom.as_num, d.peer2.addr, d.peer2.bom.as_num
+                ),
+            ),
+            SessionEvent::StateChanged(was, now) => (None, format!("state {:?} -> {:?}", was, now)),
+        };
+        let mut items = self.items.lock().unwrap();
+        if items.len() >= SESSION_LOG_SIZE {
+            items.pop_front();
+        }
+        items.push_back(SessionLogEntry {
+            time: Timestamp::now(),
+            session,
+            event,
+        });
+    }
+    pub fn between(&self, from: Timestamp, to: Timestamp) -> Vec<SessionLogEntry> {
+        self.items
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.time >= from && e.time <= to)
+            .cloned()
+            .collect()
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for b in data.iter() {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// sizes and offsets from this value on are kept in ZIP64 extra fields
+const ZIP64_LIMIT: u64 = 0xffffffff;
+
+/// uncompressed (stored) zip archive, with ZIP64 records when sizes or entry count do not
+/// fit in zip fields
+pub struct ZipWriter {
+    out: Vec<u8>,
+    central: Vec<u8>,
+    count: u64,
+    time: u16,
+    date: u16,
+    limit: u64,
+}
+impl ZipWriter {
+    /// entries get modification time of when
+    pub fn new(when: Timestamp) -> ZipWriter {
+        ZipWriter {
+            out: Vec::new(),
+            central: Vec::new(),
+            count: 0,
+            time: ((when.hour() << 11) | (when.minute() << 5) | (when.second() / 2)) as u16,
+            date: ((((when.year() - 1980).max(0) as u32) << 9) | (when.month() << 5) | when.day())
+                as u16,
+            limit: ZIP64_LIMIT,
+        }
+    }
+    /// name is cut to 65535 bytes
+    pub fn add(&mut self, name: &str, data: &[u8]) {
+        let name = &name.as_bytes()[..name.len().min(0xffff)];
+        let offset = self.out.len() as u64;
+        let crc = crc32(data);
+        let size = data.len() as u64;
+        let zip64 = size >= self.limit || offset >= self.limit;
+        let version: u16 = if zip64 { 45 } else { 20 };
+        let mut local = Vec::with_capacity(30 + name.len() + 20);
+        local.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        // version needed, flags, method stored
+        local.extend_from_slice(&version.to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes());
+        local.extend_from_slice(&self.time.to_le_bytes());
+        local.extend_from_slice(&self.date.to_le_bytes());
+        local.extend_from_slice(&crc.to_le_bytes());
+        let size32 = if zip64 { 0xffffffff } else { size as u32 };
+        local.extend_from_slice(&size32.to_le_bytes());
+        local.extend_from_slice(&size32.to_le_bytes());
+        local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local.extend_from_slice(&(if zip64 { 20u16 } else { 0 }).to_le_bytes());
+        self.central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        // version made by
+        self.central.extend_from_slice(&version.to_le_bytes());
+        self.central.extend_from_slice(&local[4..28]);
+        self.central
+            .extend_from_slice(&(if zip64 { 32u16 } else { 0 }).to_le_bytes());
+        // comment length, disk, internal and external attributes
+        self.central.extend_from_slice(&[0u8; 10]);
+        let offset32 = if zip64 { 0xffffffff } else { offset as u32 };
+        self.central.extend_from_slice(&offset32.to_le_bytes());
+        self.central.extend_from_slice(name);
+        self.out.extend_from_slice(&local);
+        self.out.extend_from_slice(name);
+        if zip64 {
+            // ZIP64 extended information: sizes, and offset in central directory
+            self.out.extend_from_slice(&1u16.to_le_bytes());
+            self.out.extend_from_slice(&16u16.to_le_bytes());
+            self.out.extend_from_slice(&size.to_le_bytes());
+            self.out.extend_from_slice(&size.to_le_bytes());
+            self.central.extend_from_slice(&1u16.to_le_bytes());
+            self.central.extend_from_slice(&28u16.to_le_bytes());
+            self.central.extend_from_slice(&size.to_le_bytes());
+            self.central.extend_from_slice(&size.to_le_bytes());
+            self.central.extend_from_slice(&offset.to_le_bytes());
+        }
+        self.out.extend_from_slice(data);
+        self.count += 1;
+    }
+    pub fn finish(mut self) -> Vec<u8> {
+        let offset = self.out.len() as u64;
+        let size = self.central.len() as u64;
+        self.out.extend_from_slice(&self.central);
+        let zip64 = offset >= self.limit || size >= self.limit || self.count >= 0xffff;
+        if zip64 {
+            let record = self.out.len() as u64;
+            // ZIP64 end of central directory record and locator
+            self.out.extend_from_slice(&0x06064b50u32.to_le_bytes());
+            self.out.extend_from_slice(&44u64.to_le_bytes());
+            self.out.extend_from_slice(&45u16.to_le_bytes());
+            self.out.extend_from_slice(&45u16.to_le_bytes());
+            self.out.extend_from_slice(&[0u8; 8]);
+            self.out.extend_from_slice(&self.count.to_le_bytes());
+            self.out.extend_from_slice(&self.count.to_le_bytes());
+            self.out.extend_from_slice(&size.to_le_bytes());
+            self.out.extend_from_slice(&offset.to_le_bytes());
+            self.out.extend_from_slice(&0x07064b50u32.to_le_bytes());
+            self.out.extend_from_slice(&0u32.to_le_bytes());
+            self.out.extend_from_slice(&record.to_le_bytes());
+            self.out.extend_from_slice(&1u32.to_le_bytes());
+        }
+        let count = if zip64 { 0xffff } else { self.count as u16 };
+        let (size, offset) = if zip64 {
+            (0xffffffff, 0xffffffff)
+        } else {
+            (size as u32, offset as u32)
+        };
+        self.out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        self.out.extend_from_slice(&[0u8; 4]);
+        self.out.extend_from_slice(&count.to_le_bytes());
+        self.out.extend_from_slice(&count.to_le_bytes());
+        self.out.extend_from_slice(&size.to_le_bytes());
+        self.out.extend_from_slice(&offset.to_le_bytes());
+        self.out.extend_from_slice(&0u16.to_le_bytes());
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_zip_writer() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        let mut z = ZipWriter::new(Timestamp::from_unix(1700000000, 0));
+        z.add("a.txt", b"hello");
+        z.add("b.json", b"{}");
+        let data = z.finish();
+        assert_eq!(&data[0..4], &[0x50, 0x4b, 0x03, 0x04]);
+        let eocd = &data[data.len() - 22..];
+        assert_eq!(&eocd[0..4], &[0x50, 0x4b, 0x05, 0x06]);
+        assert_eq!(u16::from_le_bytes([eocd[10], eocd[11]]), 2);
+        let cdoff = u32::from_le_bytes([eocd[16], eocd[17], eocd[18], eocd[19]]) as usize;
+        assert_eq!(&data[cdoff..cdoff + 4], &[0x50, 0x4b, 0x01, 0x02]);
+        // every entry in ZIP64 records
+        let mut z = ZipWriter::new(Timestamp::from_unix(1700000000, 0));
+        z.limit = 0;
+        z.add("a.txt", b"hello");
+        let data = z.finish();
+        // local header sizes and extra field
+        assert_eq!(&data[18..26], &[0xff; 8]);
+        assert_eq!(u16::from_le_bytes([data[28], data[29]]), 20);
+        assert_eq!(&data[35..39], &[1, 0, 16, 0]);
+        assert_eq!(&data[55..60], b"hello");
+        let eocd = &data[data.len() - 22..];
+        assert_eq!(&eocd[8..20], &[0xff; 12]);
+        let locator = &data[data.len() - 42..data.len() - 22];
+        assert_eq!(&locator[0..4], &[0x50, 0x4b, 0x06, 0x07]);
+        let record = u64::from_le_bytes(locator[8..16].try_into().unwrap()) as usize;
+        assert_eq!(&data[record..record + 4], &[0x50, 0x4b, 0x06, 0x06]);
+        let cdoff = u64::from_le_bytes(data[record + 48..record + 56].try_into().unwrap()) as usize;
+        assert_eq!(cdoff, 60);
+        assert_eq!(&data[cdoff..cdoff + 4], &[0x50, 0x4b, 0x01, 0x02]);
+        // offset of entry in central directory extra field
+        assert_eq!(&data[cdoff + 51..cdoff + 55], &[1, 0, 28, 0]);
+        assert_eq!(&data[cdoff + 71..cdoff + 79], &[0; 8]);
+        assert_eq!(
+            parse_time("1700000000250"),
+            Some(Timestamp::from_unix(1700000000, 250000))
+        );
+        assert!(parse_time("2023-11-14T22:13:20Z").is_some());
+        assert!(parse_time("yesterday").is_none());
+    }
+}
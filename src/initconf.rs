@@ -0,0 +1,64 @@
+//! `bgpexplorer init`: annotated example configuration with all options and their defaults
+use std::io::Write;
+
+pub const EXAMPLE: &str = include_str!("../bgpexplorer.example.ini");
+
+/// writes example configuration to path, "-" is stdout; existing file is overwritten only with force
+pub fn write_example(path: &str, force: bool) -> std::io::Result<()> {
+    if path == "-" {
+        return std::io::stdout().write_all(EXAMPLE.as_bytes());
+    }
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true);
+    if force {
+        opts.create(true).truncate(true);
+    } else {
+        opts.create_new(true);
+    }
+    opts.open(path)?.write_all(EXAMPLE.as_bytes())
+}
+
+/// handles `init [--force] [path]` arguments, default path is bgpexplorer.ini
+pub fn run(args: &[String]) -> Result<String, String> {
+    let mut force = false;
+    let mut path = None;
+    for a in args {
+        match a.as_str() {
+            "-f" | "--force" => force = true,
+            p if path.is_none() && (p == "-" || !p.starts_with('-')) => path = Some(p),
+            _ => return Err("usage: bgpexplorer init [--force] [path|-]".to_string()),
+        }
+    }
+    let path = path.unwrap_or("bgpexplorer.ini");
+    write_example(path, force).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => {
+            format!("{} already exists, use --force to overwrite", path)
+        }
+        _ => format!("{}: {}", path, e),
+    })?;
+    Ok(path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example() {
+        let conf = inistr!(EXAMPLE);
+        assert_eq!(
+            conf["main"]["whoisjsonconfig"].as_deref(),
+            Some("whois.json")
+        );
+        assert_eq!(conf["bgppassive"]["mode"].as_deref(), Some("bgppassive"));
+        let dir = std::env::temp_dir().join("bgpexplorer_init_test");
+        let _ = std::fs::create_dir(&dir);
+        let path = dir.join("bgpexplorer.ini").to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(run(&[path.clone()]), Ok(path.clone()));
+        assert!(run(&[path.clone()]).is_err());
+        assert!(run(&["--force".to_string(), path.clone()]).is_ok());
+        assert!(run(&["--bogus".to_string()]).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
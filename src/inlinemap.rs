@@ -0,0 +1,243 @@
+//! ordered map for small nested RIB tables (sessions of route, pathes of session, history of
+//! path). Entries live in one sorted array, the first one inside the map itself, so route
+//! learned from one session with one path and one history record needs no heap allocation
+//! besides its top level tree slot, where each BTreeMap level used to allocate a tree node.
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use smallvec::SmallVec;
+use std::borrow::Borrow;
+use std::iter::FromIterator;
+use std::ops::{Bound, RangeBounds};
+
+pub struct InlineMap<K, V> {
+    items: SmallVec<[(K, V); 1]>,
+}
+impl<K, V> Default for InlineMap<K, V> {
+    fn default() -> Self {
+        InlineMap {
+            items: SmallVec::new(),
+        }
+    }
+}
+impl<K: Ord, V> InlineMap<K, V> {
+    pub fn new() -> InlineMap<K, V> {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+    fn find<Q: Ord + ?Sized>(&self, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+    {
+        self.items.binary_search_by(|(k, _)| k.borrow().cmp(key))
+    }
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key).ok().map(|i| &self.items[i].1)
+    }
+    pub fn get_key_value<Q: Ord + ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key)
+            .ok()
+            .map(|i| (&self.items[i].0, &self.items[i].1))
+    }
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        match self.find(key) {
+            Ok(i) => Some(&mut self.items[i].1),
+            Err(_) => None,
+        }
+    }
+    pub fn contains_key<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.find(key).is_ok()
+    }
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.find(&key) {
+            Ok(i) => Some(std::mem::replace(&mut self.items[i].1, value)),
+            Err(i) => {
+                self.items.insert(i, (key, value));
+                None
+            }
+        }
+    }
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        match self.find(key) {
+            Ok(i) => Some(self.items.remove(i).1),
+            Err(_) => None,
+        }
+    }
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
+        self.items.retain(|(k, v)| f(k, v));
+    }
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator + Clone {
+        self.items.iter().map(|(k, v)| (k, v))
+    }
+    pub fn iter_mut(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = (&K, &mut V)> + ExactSizeIterator {
+        self.items.iter_mut().map(|(k, v)| (&*k, v))
+    }
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &K> + ExactSizeIterator + Clone {
+        self.items.iter().map(|(k, _)| k)
+    }
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &V> + ExactSizeIterator + Clone {
+        self.items.iter().map(|(_, v)| v)
+    }
+    pub fn values_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut V> + ExactSizeIterator {
+        self.items.iter_mut().map(|(_, v)| v)
+    }
+    /// entries with keys in range, in key order
+    pub fn range<Q: Ord + ?Sized, R: RangeBounds<Q>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator + Clone
+    where
+        K: Borrow<Q>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(s) => self.items.partition_point(|(k, _)| k.borrow() < s),
+            Bound::Excluded(s) => self.items.partition_point(|(k, _)| k.borrow() <= s),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(e) => self.items.partition_point(|(k, _)| k.borrow() <= e),
+            Bound::Excluded(e) => self.items.partition_point(|(k, _)| k.borrow() < e),
+            Bound::Unbounded => self.items.len(),
+        };
+        self.items[start..end.max(start)]
+            .iter()
+            .map(|(k, v)| (k, v))
+    }
+}
+impl<K: Ord + Borrow<Q>, Q: Ord + ?Sized, V> std::ops::Index<&Q> for InlineMap<K, V> {
+    type Output = V;
+    /// panics when key is not present, same as BTreeMap
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+impl<K: std::fmt::Debug, V: std::fmt::Debug> std::fmt::Debug for InlineMap<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.items.iter().map(|(k, v)| (k, v)))
+            .finish()
+    }
+}
+impl<K: Clone, V: Clone> Clone for InlineMap<K, V> {
+    fn clone(&self) -> Self {
+        InlineMap {
+            items: self.items.clone(),
+        }
+    }
+}
+impl<K: Ord, V> FromIterator<(K, V)> for InlineMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut ret = InlineMap::new();
+        for (k, v) in iter {
+            ret.insert(k, v);
+        }
+        ret
+    }
+}
+impl<K, V> IntoIterator for InlineMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = smallvec::IntoIter<[(K, V); 1]>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+impl<K: serde::Serialize, V: serde::Serialize> serde::Serialize for InlineMap<K, V> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.items.len()))?;
+        for (k, v) in self.items.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+struct InlineMapVisitor<K, V>(std::marker::PhantomData<(K, V)>);
+impl<'de, K, V> Visitor<'de> for InlineMapVisitor<K, V>
+where
+    K: Ord + serde::Deserialize<'de>,
+    V: serde::Deserialize<'de>,
+{
+    type Value = InlineMap<K, V>;
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("map")
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut ret = InlineMap::new();
+        while let Some((k, v)) = access.next_entry()? {
+            ret.insert(k, v);
+        }
+        Ok(ret)
+    }
+}
+impl<'de, K, V> serde::Deserialize<'de> for InlineMap<K, V>
+where
+    K: Ord + serde::Deserialize<'de>,
+    V: serde::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(InlineMapVisitor(std::marker::PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_inlinemap() {
+        let mut m = InlineMap::new();
+        assert_eq!(m.insert(3u32, "c"), None);
+        assert!(!m.items.spilled());
+        m.insert(1, "a");
+        m.insert(2, "b");
+        assert_eq!(m.insert(2, "B"), Some("b"));
+        assert_eq!(m.keys().cloned().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(m.get(&2), Some(&"B"));
+        assert_eq!(
+            m.range(2..).map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(m.range(..=1).count(), 1);
+        assert_eq!(m.range(5..).count(), 0);
+        assert_eq!(m.iter().last(), Some((&3, &"c")));
+        // same encoding as BTreeMap, so snapshots are compatible
+        let bt: BTreeMap<u32, &str> = m.iter().map(|(k, v)| (*k, *v)).collect();
+        let json = serde_json::to_string(&m).unwrap();
+        assert_eq!(json, serde_json::to_string(&bt).unwrap());
+        let back: InlineMap<u32, String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.len(), 3);
+        assert_eq!(m.remove(&1), Some("a"));
+        m.retain(|k, _| *k != 3);
+        assert_eq!(m.keys().cloned().collect::<Vec<_>>(), vec![2]);
+    }
+}
@@ -0,0 +1,277 @@
+//! periodic RIB invariant checks to catch corruption of long-running state
+use crate::bgprib::*;
+use crate::bgpsvc::BgpSvr;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IntegrityReport {
+    pub checked: Option<Timestamp>,
+    pub routes: usize,
+    /// session, path or history containers without records
+    pub empty_entries: usize,
+    /// histories longer than retention depth
+    pub overlong_histories: usize,
+    /// records received later than check time
+    pub future_records: usize,
+    /// index keys pointing to missing routes
+    pub dangling_index: usize,
+    /// route attributes without index entry
+    pub missing_index: usize,
+    /// interned attributes not referenced by any route, removed by purge
+    pub orphaned_interns: usize,
+    pub repaired: bool,
+}
+impl IntegrityReport {
+    /// orphaned interns are expected between purges and are not counted
+    pub fn problems(&self) -> usize {
+        self.empty_entries
+            + self.overlong_histories
+            + self.future_records
+            + self.dangling_index
+            + self.missing_index
+    }
+}
+
+fn check_index<K: Ord + Clone, T: BgpRIBKey>(
+    idx: &BgpRIBIndex<K, T>,
+    safi: &BgpRIBSafi<T>,
+    rep: &mut IntegrityReport,
+) {
+    for keys in idx.idx.values() {
+        rep.dangling_index += keys.iter().filter(|k| !safi.items.contains_key(k)).count();
+    }
+}
+
+fn indexed<K: Ord + Clone, T: BgpRIBKey>(idx: &BgpRIBIndex<K, T>, k: &K, t: &T) -> bool {
+    idx.idx.get(k).map(|s| s.contains(t)).unwrap_or(false)
+}
+
+impl<T: BgpRIBKey> BgpRIBSafi<T> {
+    pub fn check_integrity(&self, now: Timestamp, rep: &mut IntegrityReport) {
+        rep.routes += self.items.len();
+        for (k, sess) in self.items.iter() {
+            if sess.items.is_empty() {
+                rep.empty_entries += 1;
+            }
+            let sorted = crate::sortindex::entry_values(Some(sess));
+            if sorted.iter().any(|(key, v)| match v {
+                Some(v) => !indexed(self.idx_sort.get(*key), v, k),
+                None => self.idx_sort.missing(*key).is_some_and(|m| !m.contains(k)),
+            }) {
+                rep.missing_index += 1;
+            }
+            for pe in sess.items.values() {
+                if pe.items.is_empty() {
+                    rep.empty_entries += 1;
+                }
+                for hist in pe.items.values() {
+                    let (ts, last) = match hist.items.iter().last() {
+                        Some(x) => x,
+                        None => {
+                            rep.empty_entries += 1;
+                            continue;
+                        }
+                    };
+                    if hist.items.len() > self.retention.depth(&last.attrs, self.log_size) {
+                        rep.overlong_histories += 1;
+                    }
+                    if *ts > now {
+                        rep.future_records += 1;
+                    }
+                    for rec in hist.items.values() {
+                        let a = &rec.attrs;
+                        if a.aspath
+                            .value
+                            .iter()
+                            .any(|x| !indexed(&self.idx_aspath, x, k))
+                            || a.comms
+                                .value
+                                .iter()
+                                .any(|x| !indexed(&self.idx_community, x, k))
+                            || a.extcomms
+                                .value
+                                .iter()
+                                .filter(|x| x.subtype == 2)
+                                .any(|x| !indexed(&self.idx_extcommunity, x, k))
+                        {
+                            rep.missing_index += 1;
+                        }
+                    }
+                }
+            }
+        }
+        check_index(&self.idx_aspath, self, rep);
+        check_index(&self.idx_community, self, rep);
+        check_index(&self.idx_extcommunity, self, rep);
+        check_index(&self.idx_sort.med, self, rep);
+        check_index(&self.idx_sort.localpref, self, rep);
+        check_index(&self.idx_sort.pathlen, self, rep);
+        for missing in [&self.idx_sort.med_missing, &self.idx_sort.localpref_missing] {
+            rep.dangling_index += missing
+                .iter()
+                .filter(|k| !self.items.contains_key(k))
+                .count();
+        }
+    }
+    /// drops empty containers, trims histories to retention depth and rebuilds indexes
+    pub fn repair(&mut self) {
+        let log_size = self.log_size;
+        let retention = &self.retention;
+        for sess in self.items.values_mut() {
+            for pe in sess.items.values_mut() {
+                for hist in pe.items.values_mut() {
+                    let depth = match hist.items.values().last() {
+                        Some(last) => retention.depth(&last.attrs, log_size),
+                        None => continue,
+                    };
+                    while hist.items.len() > depth {
+                        let first = *hist.items.keys().next().unwrap();
+                        hist.items.remove(&first);
+                    }
+                }
+                pe.items.retain(|_, hist| !hist.items.is_empty());
+            }
+            sess.items.retain(|_, pe| !pe.items.is_empty());
+        }
+        self.items.retain(|_, sess| !sess.items.is_empty());
+        let items = std::mem::take(&mut self.items);
+        self.idx_aspath.clear();
+        self.idx_community.clear();
+        self.idx_extcommunity.clear();
+        self.idx_sort.clear();
+        self.assign(items);
+    }
+}
+
+impl BgpRIB {
+    /// checks all address families without changing them
+    pub fn check_integrity(&self) -> IntegrityReport {
+        let now = Timestamp::now();
+        let mut rep = IntegrityReport {
+            checked: Some(now),
+            ..Default::default()
+        };
+        self.ipv4u.check_integrity(now, &mut rep);
+        self.ipv4m.check_integrity(now, &mut rep);
+        self.ipv4lu.check_integrity(now, &mut rep);
+        self.vpnv4u.check_integrity(now, &mut rep);
+        self.vpnv4m.check_integrity(now, &mut rep);
+        self.ipv6u.check_integrity(now, &mut rep);
+        self.ipv6m.check_integrity(now, &mut rep);
+        self.ipv6lu.check_integrity(now, &mut rep);
+        self.vpnv6u.check_integrity(now, &mut rep);
+        self.vpnv6m.check_integrity(now, &mut rep);
+        self.l2vpls.check_integrity(now, &mut rep);
+        self.mvpn.check_integrity(now, &mut rep);
+        self.evpn.check_integrity(now, &mut rep);
+        self.fs4u.check_integrity(now, &mut rep);
+        self.ipv4mdt.check_integrity(now, &mut rep);
+        self.ipv6mdt.check_integrity(now, &mut rep);
+        rep.orphaned_interns = self.attrs.items.iter().filter(|x| x.is_empty()).count()
+            + self.pathes.items.iter().filter(|x| x.is_empty()).count()
+            + self.comms.items.iter().filter(|x| x.is_empty()).count()
+            + self.lcomms.items.iter().filter(|x| x.is_empty()).count()
+            + self.extcomms.items.iter().filter(|x| x.is_empty()).count();
+        rep
+    }
+    /// checks again and repairs address families and purges interns when needed
+    pub fn repair_integrity(&mut self) -> IntegrityReport {
+        let mut rep = self.check_integrity();
+        if rep.problems() > 0 {
+            self.ipv4u.repair();
+            self.ipv4m.repair();
+            self.ipv4lu.repair();
+            self.vpnv4u.repair();
+            self.vpnv4m.repair();
+            self.ipv6u.repair();
+            self.ipv6m.repair();
+            self.ipv6lu.repair();
+            self.vpnv6u.repair();
+            self.vpnv6m.repair();
+            self.l2vpls.repair();
+            self.mvpn.repair();
+            self.evpn.repair();
+            self.fs4u.repair();
+            self.ipv4mdt.repair();
+            self.ipv6mdt.repair();
+            self.recalc_peer_stats();
+            self.invalidate_snapshot_diffs();
+            self.purge();
+            rep.repaired = true;
+        }
+        rep
+    }
+}
+
+/// runs check every integrity_check_every, keeps last report in BgpSvr
+pub async fn run(svr: Arc<BgpSvr>) {
+    let every = match svr.config.integrity_check_every {
+        Some(d) => d,
+        None => return,
+    };
+    loop {
+        tokio::time::sleep(every).await;
+        // check only run keeps RIB readable, write lock is taken when there is something
+        // to repair
+        let mut rep = svr.rib.rib.read().await.check_integrity();
+        if svr.config.integrity_repair && rep.problems() > 0 {
+            rep = svr.rib.rib.write().await.repair_integrity();
+        }
+        if rep.problems() > 0 {
+            warn!(
+                "RIB integrity: {} problems ({} empty, {} overlong, {} future, {} dangling index, {} missing index){}",
+                rep.problems(),
+                rep.empty_entries,
+                rep.overlong_histories,
+                rep.future_records,
+                rep.dangling_index,
+                rep.missing_index,
+                if rep.repaired { ", repaired" } else { "" }
+            );
+        } else {
+            debug!("RIB integrity ok, {} routes", rep.routes);
+        }
+        *svr.integrity.lock().unwrap() = rep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgpattrs::{BgpAttrEntry, BgpAttrs};
+    use crate::compact::CompactASPath;
+    use crate::config::HistoryChangeMode;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_integrity() {
+        let mut safi: BgpRIBSafi<BgpAddrV4> = BgpRIBSafi::new(2, HistoryChangeMode::EveryUpdate);
+        let pfx = BgpAddrV4::new(std::net::Ipv4Addr::new(192, 0, 2, 0), 24);
+        let mut attrs = BgpAttrs::new();
+        attrs.aspath = Arc::new(CompactASPath::from(vec![64500]));
+        let attrs = Arc::new(attrs);
+        let mut entry = BgpSessionEntry::new();
+        for s in 1..4 {
+            entry.insert(
+                0,
+                0,
+                Timestamp::from_unix(s, 0),
+                BgpAttrEntry::new(true, attrs.clone(), None),
+            );
+        }
+        safi.items.insert(pfx.clone(), entry);
+        let now = Timestamp::from_unix(10, 0);
+        let mut rep = IntegrityReport::default();
+        safi.check_integrity(now, &mut rep);
+        assert_eq!(rep.overlong_histories, 1);
+        // three records missing from aspath index, route missing from sort indexes
+        assert_eq!(rep.missing_index, 4);
+        safi.repair();
+        let mut rep = IntegrityReport::default();
+        safi.check_integrity(now, &mut rep);
+        assert_eq!(rep.problems(), 0);
+        assert_eq!(safi.items[&pfx].items[&0].items[&0].items.len(), 2);
+    }
+}
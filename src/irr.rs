@@ -0,0 +1,427 @@
+//! AS-SET expansion from IRR and comparison of registered route objects with RIB
+use crate::bgprib::{BgpRIBKey, BgpRIBSafi};
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use zettabgp::prelude::*;
+
+/// as-set objects queried during one expansion at most
+const MAX_SETS: usize = 64;
+/// member ASNs expanded and queried for route objects at most
+pub const MAX_ASNS: usize = 256;
+/// parallel route object queries
+const CONCURRENCY: usize = 8;
+/// IRR answer size limit
+const MAX_RESPONSE: u64 = 4 * 1024 * 1024;
+/// cached IRR answers at most
+const MAX_CACHED: usize = 4096;
+
+/// member of as-set object
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AsSetMember {
+    Asn(u32),
+    Set(String),
+}
+impl AsSetMember {
+    pub fn parse(s: &str) -> Option<AsSetMember> {
+        let s = s.trim().to_uppercase();
+        if s.is_empty() {
+            return None;
+        }
+        if let Some(n) = s.strip_prefix("AS").and_then(|x| x.parse::<u32>().ok()) {
+            return Some(AsSetMember::Asn(n));
+        }
+        if s.len() <= 255
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ':')
+            && s.split(':').any(|x| x.starts_with("AS-"))
+        {
+            return Some(AsSetMember::Set(s));
+        }
+        None
+    }
+}
+
+/// strips comments and joins continuation lines of RPSL text into (attribute, value) pairs
+fn rpsl_attrs(text: &str) -> Vec<(String, String)> {
+    let mut ret: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        if line.starts_with('%') || line.starts_with('#') {
+            continue;
+        }
+        let line = line.split('#').next().unwrap_or("");
+        if line.starts_with(' ') || line.starts_with('\t') || line.starts_with('+') {
+            if let Some(last) = ret.last_mut() {
+                last.1.push(' ');
+                last.1.push_str(line.trim_start_matches('+').trim());
+            }
+            continue;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            ret.push((k.trim().to_lowercase(), v.trim().to_string()));
+        }
+    }
+    ret
+}
+
+/// members of as-set object, None if text holds no as-set
+pub fn parse_as_set(text: &str) -> Option<Vec<AsSetMember>> {
+    let attrs = rpsl_attrs(text);
+    if !attrs.iter().any(|(k, _)| k == "as-set") {
+        return None;
+    }
+    let mut ret = Vec::new();
+    for (_, v) in attrs
+        .iter()
+        .filter(|(k, _)| k == "members" || k == "mp-members")
+    {
+        for m in v.split(|c: char| c == ',' || c.is_whitespace()) {
+            if let Some(m) = AsSetMember::parse(m) {
+                if !ret.contains(&m) {
+                    ret.push(m);
+                }
+            }
+        }
+    }
+    Some(ret)
+}
+
+/// canonical prefix text, the same as RIB keys are printed
+fn normalize_prefix(s: &str) -> Option<String> {
+    let (a, l) = s.trim().split_once('/')?;
+    let addr: std::net::IpAddr = a.parse().ok()?;
+    let len: u8 = l.parse().ok()?;
+    Some(format!("{}/{}", addr, len))
+}
+
+/// prefixes of route and route6 objects
+pub fn parse_routes(text: &str) -> BTreeSet<String> {
+    rpsl_attrs(text)
+        .into_iter()
+        .filter(|(k, _)| k == "route" || k == "route6")
+        .filter_map(|(_, v)| normalize_prefix(v.as_str()))
+        .collect()
+}
+
+/// ASNs of recursively expanded as-set
+#[derive(Debug, Default)]
+pub struct AsSetExpansion {
+    pub asns: BTreeSet<u32>,
+    pub sets: BTreeSet<String>,
+    /// sets which query failed or returned no object
+    pub unresolved: BTreeSet<String>,
+    /// expansion stopped at MAX_SETS or member limit
+    pub truncated: bool,
+}
+
+/// plain whois client for IRR server, successful answers are cached
+pub struct IrrClient {
+    server: String,
+    timeout: std::time::Duration,
+    cache_valid: std::time::Duration,
+    cache: std::sync::Mutex<HashMap<String, (std::time::Instant, String)>>,
+}
+impl IrrClient {
+    pub fn new(
+        server: &str,
+        timeout: std::time::Duration,
+        cache_valid: std::time::Duration,
+    ) -> IrrClient {
+        IrrClient {
+            server: if server.contains(':') {
+                server.to_string()
+            } else {
+                format!("{}:43", server)
+            },
+            timeout,
+            cache_valid,
+            cache: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+    fn cached(&self, q: &str) -> Option<String> {
+        let cache = self.cache.lock().unwrap();
+        match cache.get(q) {
+            Some((t, text)) if t.elapsed() < self.cache_valid => Some(text.clone()),
+            _ => None,
+        }
+    }
+    fn store(&self, q: &str, text: &str) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED {
+            let valid = self.cache_valid;
+            cache.retain(|_, (t, _)| t.elapsed() < valid);
+            if cache.len() >= MAX_CACHED {
+                cache.clear();
+            }
+        }
+        cache.insert(q.to_string(), (std::time::Instant::now(), text.to_string()));
+    }
+    pub async fn query(&self, q: &str) -> std::io::Result<String> {
+        if let Some(text) = self.cached(q) {
+            return Ok(text);
+        }
+        let req = async {
+            let mut sock = tokio::net::TcpStream::connect(self.server.as_str()).await?;
+            sock.write_all(format!("{}\r\n", q).as_bytes()).await?;
+            let mut buf = Vec::new();
+            sock.take(MAX_RESPONSE + 1).read_to_end(&mut buf).await?;
+            if buf.len() as u64 > MAX_RESPONSE {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "IRR answer is too large",
+                ));
+            }
+            Ok(String::from_utf8_lossy(&buf).to_string())
+        };
+        let text = match tokio::time::timeout(self.timeout, req).await {
+            Ok(r) => r?,
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "IRR query timed out",
+                ))
+            }
+        };
+        self.store(q, text.as_str());
+        Ok(text)
+    }
+    /// breadth-first expansion of nested sets, loops are visited once, max_asns is capped at MAX_ASNS
+    pub async fn expand_as_set(&self, name: &str, max_asns: usize) -> AsSetExpansion {
+        let max_asns = max_asns.min(MAX_ASNS);
+        let mut ret = AsSetExpansion::default();
+        let mut queue: VecDeque<String> = VecDeque::new();
+        queue.push_back(name.trim().to_uppercase());
+        while let Some(set) = queue.pop_front() {
+            if ret.sets.contains(&set) || ret.unresolved.contains(&set) {
+                continue;
+            }
+            if ret.sets.len() + ret.unresolved.len() >= MAX_SETS {
+                ret.truncated = true;
+                break;
+            }
+            let members = match self.query(set.as_str()).await {
+                Ok(text) => parse_as_set(text.as_str()),
+                Err(e) => {
+                    warn!("IRR query {} failed: {}", set, e);
+                    None
+                }
+            };
+            let members = match members {
+                Some(m) => m,
+                None => {
+                    ret.unresolved.insert(set);
+                    continue;
+                }
+            };
+            ret.sets.insert(set);
+            for m in members {
+                match m {
+                    AsSetMember::Asn(n) => {
+                        if ret.asns.len() >= max_asns {
+                            ret.truncated = true;
+                            continue;
+                        }
+                        ret.asns.insert(n);
+                    }
+                    AsSetMember::Set(s) => queue.push_back(s),
+                }
+            }
+        }
+        ret
+    }
+    /// registered prefixes for each ASN, missing for failed queries
+    pub async fn route_objects(&self, asns: &BTreeSet<u32>) -> BTreeMap<u32, BTreeSet<String>> {
+        futures::stream::iter(asns.iter().cloned())
+            .map(|asn| async move {
+                match self.query(format!("-i origin AS{}", asn).as_str()).await {
+                    Ok(text) => Some((asn, parse_routes(text.as_str()))),
+                    Err(e) => {
+                        warn!("IRR route query AS{} failed: {}", asn, e);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(CONCURRENCY)
+            .filter_map(|x| async move { x })
+            .collect()
+            .await
+    }
+}
+
+/// active prefixes originated by ASNs, candidates come from AS path index
+pub fn originated<T: BgpRIBKey + std::string::ToString>(
+    safi: &BgpRIBSafi<T>,
+    asns: &BTreeSet<u32>,
+    out: &mut BTreeMap<u32, BTreeSet<String>>,
+) {
+    for asn in asns.iter() {
+        let keys = match safi.idx_aspath.idx.get(&BgpAS::new(*asn)) {
+            Some(k) => k,
+            None => continue,
+        };
+        for k in keys.iter() {
+            let entry = match safi.items.get(k) {
+                Some(e) => e,
+                None => continue,
+            };
+            let originates = entry.items.values().any(|pe| {
+                pe.items
+                    .values()
+                    .any(|hist| match hist.items.values().last() {
+                        Some(rec) => {
+                            rec.active
+                                && rec.attrs.aspath.value.last().map(|x| x.value) == Some(*asn)
+                        }
+                        None => false,
+                    })
+            });
+            if originates {
+                out.entry(*asn).or_default().insert(k.to_string());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AsMemberReport {
+    pub asn: u32,
+    /// route objects query succeeded
+    pub registered: bool,
+    /// registered prefixes seen originated by ASN
+    pub present: Vec<String>,
+    /// registered prefixes not seen in RIB
+    pub absent: Vec<String>,
+    /// originated prefixes without route object
+    pub unregistered: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AsSetReport {
+    pub asset: String,
+    pub sets: Vec<String>,
+    pub unresolved: Vec<String>,
+    pub truncated: bool,
+    pub present: usize,
+    pub absent: usize,
+    /// member ASNs without any originated prefix in RIB
+    pub invisible: Vec<u32>,
+    pub members: Vec<AsMemberReport>,
+}
+impl AsSetReport {
+    pub fn new(
+        asset: &str,
+        exp: &AsSetExpansion,
+        irr: &BTreeMap<u32, BTreeSet<String>>,
+        rib: &BTreeMap<u32, BTreeSet<String>>,
+    ) -> AsSetReport {
+        let empty = BTreeSet::new();
+        let mut ret = AsSetReport {
+            asset: asset.trim().to_uppercase(),
+            sets: exp.sets.iter().cloned().collect(),
+            unresolved: exp.unresolved.iter().cloned().collect(),
+            truncated: exp.truncated,
+            present: 0,
+            absent: 0,
+            invisible: Vec::new(),
+            members: Vec::new(),
+        };
+        for asn in exp.asns.iter() {
+            let seen = rib.get(asn).unwrap_or(&empty);
+            let routes = irr.get(asn);
+            let reg = routes.unwrap_or(&empty);
+            let m = AsMemberReport {
+                asn: *asn,
+                registered: routes.is_some(),
+                present: reg.intersection(seen).cloned().collect(),
+                absent: reg.difference(seen).cloned().collect(),
+                unregistered: if routes.is_some() {
+                    seen.difference(reg).cloned().collect()
+                } else {
+                    Vec::new()
+                },
+            };
+            ret.present += m.present.len();
+            ret.absent += m.absent.len();
+            if seen.is_empty() {
+                ret.invisible.push(*asn);
+            }
+            ret.members.push(m);
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_as_set() {
+        let text = "% comment\nas-set:  AS-EXAMPLE\nmembers: AS64500, AS64501 # cust\n+        AS-NESTED\nmp-members: AS64502:AS-CUST\nmembers: AS64500\nsource: RADB\n";
+        assert_eq!(
+            parse_as_set(text).unwrap(),
+            vec![
+                AsSetMember::Asn(64500),
+                AsSetMember::Asn(64501),
+                AsSetMember::Set("AS-NESTED".to_string()),
+                AsSetMember::Set("AS64502:AS-CUST".to_string()),
+            ]
+        );
+        assert!(parse_as_set("%  No entries found\n").is_none());
+        assert_eq!(
+            AsSetMember::parse("as-example"),
+            Some(AsSetMember::Set("AS-EXAMPLE".to_string()))
+        );
+        assert!(AsSetMember::parse("AS-X\r\n-i origin AS1").is_none());
+        assert!(AsSetMember::parse("AS-X AS-Y").is_none());
+        assert!(AsSetMember::parse("RS-EXAMPLE").is_none());
+    }
+
+    #[test]
+    fn test_irr_cache() {
+        let irr = IrrClient::new(
+            "192.0.2.1",
+            std::time::Duration::from_secs(1),
+            std::time::Duration::from_secs(60),
+        );
+        assert!(irr.cached("AS-X").is_none());
+        irr.store("AS-X", "as-set: AS-X\n");
+        assert_eq!(irr.cached("AS-X").unwrap(), "as-set: AS-X\n");
+        let expired = IrrClient::new(
+            "192.0.2.1",
+            std::time::Duration::from_secs(1),
+            std::time::Duration::from_secs(0),
+        );
+        expired.store("AS-X", "as-set: AS-X\n");
+        assert!(expired.cached("AS-X").is_none());
+    }
+
+    #[test]
+    fn test_report() {
+        let routes = parse_routes(
+            "route: 192.0.2.0/24\norigin: AS64500\n\nroute6: 2001:DB8::/32\norigin: AS64500\n",
+        );
+        assert!(routes.contains("2001:db8::/32"));
+        let mut exp = AsSetExpansion::default();
+        exp.asns.extend(vec![64500, 64501]);
+        let mut irr = BTreeMap::new();
+        irr.insert(64500, routes);
+        let mut rib = BTreeMap::new();
+        rib.insert(
+            64500u32,
+            vec!["192.0.2.0/24".to_string(), "198.51.100.0/24".to_string()]
+                .into_iter()
+                .collect::<BTreeSet<String>>(),
+        );
+        let r = AsSetReport::new("as-example", &exp, &irr, &rib);
+        assert_eq!(r.present, 1);
+        assert_eq!(r.absent, 1);
+        assert_eq!(r.invisible, vec![64501]);
+        assert_eq!(
+            r.members[0].unregistered,
+            vec!["198.51.100.0/24".to_string()]
+        );
+        assert!(!r.members[1].registered);
+    }
+}
@@ -0,0 +1,184 @@
+//! timestamp and prefix rendering of route JSON responses
+use crate::config::ErrorConfig;
+use crate::timestamp::Timestamp;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeFormat {
+    /// milliseconds since epoch
+    #[default]
+    Millis,
+    /// seconds since epoch
+    Unix,
+    Rfc3339,
+}
+impl FromStr for TimeFormat {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "millis" => Ok(TimeFormat::Millis),
+            "unix" => Ok(TimeFormat::Unix),
+            "rfc3339" => Ok(TimeFormat::Rfc3339),
+            _ => Err(ErrorConfig::from_str("invalid time format")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrefixFormat {
+    /// "a.b.c.d/len" keys
+    #[default]
+    Combined,
+    /// items are list of objects with separate address and length
+    Split,
+}
+impl FromStr for PrefixFormat {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "combined" => Ok(PrefixFormat::Combined),
+            "split" => Ok(PrefixFormat::Split),
+            _ => Err(ErrorConfig::from_str("invalid prefix format")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HistoryFormat {
+    /// every history record carries all attributes
+    #[default]
+    Full,
+    /// records following older one carry Changed names and old and new values of changed
+    /// attributes only
+    Diff,
+}
+impl FromStr for HistoryFormat {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "full" => Ok(HistoryFormat::Full),
+            "diff" => Ok(HistoryFormat::Diff),
+            _ => Err(ErrorConfig::from_str("invalid history format")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JsonFormat {
+    pub time: TimeFormat,
+    pub prefix: PrefixFormat,
+    pub history: HistoryFormat,
+}
+
+/// timestamp as JSON map key, unix and rfc3339 keys keep microseconds, so records
+/// received within one second do not share key
+pub fn time_key(ts: &Timestamp, fmt: TimeFormat) -> String {
+    match fmt {
+        TimeFormat::Millis => ts.timestamp_millis().to_string(),
+        TimeFormat::Unix => format!("{}.{:06}", ts.timestamp(), ts.timestamp_subsec_micros()),
+        TimeFormat::Rfc3339 => ts.to_rfc3339_opts(chrono::SecondsFormat::Micros, false),
+    }
+}
+
+/// timestamp as JSON value
+pub fn time_value(ts: &Timestamp, fmt: TimeFormat) -> serde_json::Value {
+    match fmt {
+        TimeFormat::Millis => ts.timestamp_millis().into(),
+        TimeFormat::Unix => ts.timestamp().into(),
+        TimeFormat::Rfc3339 => ts.to_rfc3339().into(),
+    }
+}
+
+/// address and length of route key, length is None for keys which are not prefixes
+pub fn split_prefix(key: &str) -> (&str, Option<u8>) {
+    match key.rsplit_once('/') {
+        Some((a, l)) => match l.parse() {
+            Ok(l) => (a, Some(l)),
+            Err(_) => (key, None),
+        },
+        None => (key, None),
+    }
+}
+
+/// route list item in split prefix format
+pub struct SplitItem<'a, V: serde::Serialize> {
+    pub key: &'a str,
+    pub value: &'a V,
+}
+impl<'a, V: serde::Serialize> serde::Serialize for SplitItem<'a, V> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let (address, length) = split_prefix(self.key);
+        let mut state = serializer.serialize_struct("SplitItem", 3)?;
+        state.serialize_field("address", address)?;
+        state.serialize_field("length", &length)?;
+        state.serialize_field("sessions", self.value)?;
+        state.end()
+    }
+}
+
+/// route list as map keyed by prefix or, in split prefix format, as list of SplitItem
+pub fn serialize_items<S, V>(
+    serializer: S,
+    items: Vec<(String, V)>,
+    fmt: PrefixFormat,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    V: serde::Serialize,
+{
+    use serde::ser::{SerializeMap, SerializeSeq};
+    match fmt {
+        PrefixFormat::Combined => {
+            let mut state = serializer.serialize_map(Some(items.len()))?;
+            for (k, v) in items.iter() {
+                state.serialize_entry(k, v)?;
+            }
+            state.end()
+        }
+        PrefixFormat::Split => {
+            let mut state = serializer.serialize_seq(Some(items.len()))?;
+            for (k, v) in items.iter() {
+                state.serialize_element(&SplitItem {
+                    key: k.as_str(),
+                    value: v,
+                })?;
+            }
+            state.end()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let ts = Timestamp::from_unix(1700000000, 250000);
+        assert_eq!(time_key(&ts, TimeFormat::Millis), "1700000000250");
+        assert_eq!(time_key(&ts, TimeFormat::Unix), "1700000000.250000");
+        assert!(time_key(&ts, TimeFormat::Rfc3339).contains(":20.250000"));
+        assert_eq!(
+            time_value(&ts, TimeFormat::Unix),
+            serde_json::json!(1700000000)
+        );
+        assert_eq!(split_prefix("10.0.0.0/8"), ("10.0.0.0", Some(8)));
+        assert_eq!(split_prefix("2001:db8::/32"), ("2001:db8::", Some(32)));
+        assert_eq!(split_prefix("65000:1"), ("65000:1", None));
+        assert_eq!(
+            serde_json::to_string(&SplitItem {
+                key: "10.0.0.0/8",
+                value: &1
+            })
+            .unwrap(),
+            r#"{"address":"10.0.0.0","length":8,"sessions":1}"#
+        );
+    }
+}
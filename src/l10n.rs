@@ -0,0 +1,235 @@
+//! Web UI locale bundles: built-in English strings and translations from l10n_dir
+//! as <lang>.json (key to text map) or <lang>.po (gettext, msgctxt is the key)
+use crate::config::SvcConfig;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// English strings, also the fallback for keys missing in translations
+const BUILTIN: &str = include_str!("../contrib/l10n/en.json");
+
+pub type Bundle = BTreeMap<String, String>;
+
+pub struct Catalog {
+    pub default: String,
+    bundles: BTreeMap<String, Bundle>,
+}
+impl Catalog {
+    pub fn new(default: &str) -> Catalog {
+        let mut bundles = BTreeMap::new();
+        bundles.insert(
+            "en".to_string(),
+            serde_json::from_str::<Bundle>(BUILTIN).unwrap_or_default(),
+        );
+        Catalog {
+            default: default.to_string(),
+            bundles,
+        }
+    }
+    /// loads bundles from l10n_dir, broken files are skipped with warning
+    pub fn load(cfg: &SvcConfig) -> Catalog {
+        let mut ret = Catalog::new(cfg.ui_language.as_str());
+        let dir = match std::fs::read_dir(cfg.l10n_dir.as_str()) {
+            Ok(d) => d,
+            Err(_) => return ret,
+        };
+        for entry in dir.flatten() {
+            let path = entry.path();
+            let (lang, ext) = match (path.file_stem(), path.extension()) {
+                (Some(s), Some(e)) => (s.to_string_lossy().to_string(), e.to_string_lossy()),
+                _ => continue,
+            };
+            let parsed = match ext.as_ref() {
+                "json" => read(&path).and_then(|s| {
+                    serde_json::from_str::<Bundle>(s.as_str()).map_err(|e| e.to_string())
+                }),
+                "po" => read(&path).and_then(|s| parse_po(s.as_str())),
+                _ => continue,
+            };
+            match parsed {
+                Ok(b) => ret.insert(lang.as_str(), b),
+                Err(e) => warn!("Locale bundle {}: {}", path.display(), e),
+            }
+        }
+        if !ret.bundles.contains_key(&ret.default) {
+            warn!("ui_language {} has no bundle, using en", ret.default);
+            ret.default = "en".to_string();
+        }
+        ret
+    }
+    pub fn insert(&mut self, lang: &str, bundle: Bundle) {
+        self.bundles
+            .entry(lang.to_string())
+            .or_default()
+            .extend(bundle);
+    }
+    pub fn languages(&self) -> Vec<&str> {
+        self.bundles.keys().map(|s| s.as_str()).collect()
+    }
+    pub fn has(&self, lang: &str) -> bool {
+        self.bundles.contains_key(lang)
+    }
+    /// strings of language over English ones, so untranslated keys still render
+    pub fn bundle(&self, lang: &str) -> Bundle {
+        let mut ret = self.bundles.get("en").cloned().unwrap_or_default();
+        if let Some(b) = self.bundles.get(lang) {
+            ret.extend(b.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        ret
+    }
+    /// first available language of Accept-Language header, default otherwise
+    pub fn negotiate(&self, accept: Option<&str>) -> &str {
+        let mut langs: Vec<(f32, &str)> = accept
+            .unwrap_or("")
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let tag = parts.next()?.trim();
+                let q = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((q, tag))
+            })
+            .collect();
+        langs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        for (_, tag) in langs {
+            let tag = tag.to_lowercase();
+            let primary = tag.split('-').next().unwrap_or("");
+            for cand in [tag.as_str(), primary] {
+                if let Some((k, _)) = self.bundles.get_key_value(cand) {
+                    return k.as_str();
+                }
+            }
+        }
+        self.default.as_str()
+    }
+    /// gettext template for translators: msgctxt is the key, msgid English text,
+    /// msgstr current translation of lang
+    pub fn to_po(&self, lang: &str) -> String {
+        let en = self.bundles.get("en");
+        let tr = self.bundles.get(lang);
+        let mut ret = format!(
+            "msgid \"\"\nmsgstr \"\"\n\"Language: {}\\n\"\n\"Content-Type: text/plain; charset=UTF-8\\n\"\n",
+            po_escape(lang)
+        );
+        for (k, v) in en.iter().flat_map(|b| b.iter()) {
+            let t = match tr {
+                Some(b) if lang != "en" => b.get(k).map(|s| s.as_str()).unwrap_or(""),
+                _ => "",
+            };
+            ret.push_str(&format!(
+                "\nmsgctxt \"{}\"\nmsgid \"{}\"\nmsgstr \"{}\"\n",
+                po_escape(k),
+                po_escape(v),
+                po_escape(t)
+            ));
+        }
+        ret
+    }
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
+fn po_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn po_unquote(s: &str) -> Result<String, String> {
+    let inner = s
+        .trim()
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("unquoted string {}", s))?;
+    let mut ret = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some(c) => ret.push(c),
+            None => return Err("dangling escape".to_string()),
+        }
+    }
+    Ok(ret)
+}
+
+/// translated entries of PO file, untranslated and entries without msgctxt are skipped
+pub fn parse_po(text: &str) -> Result<Bundle, String> {
+    let mut ret = Bundle::new();
+    let mut ctxt: Option<String> = None;
+    let mut msgstr: Option<String> = None;
+    // field being continued by following quoted lines
+    let mut cur: Option<&str> = None;
+    let mut flush = |ctxt: &mut Option<String>, msgstr: &mut Option<String>| {
+        if let (Some(k), Some(v)) = (ctxt.take(), msgstr.take()) {
+            if !v.is_empty() {
+                ret.insert(k, v);
+            }
+        }
+    };
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let err = |e: String| format!("line {}: {}", n + 1, e);
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(v) = line.strip_prefix("msgctxt ") {
+            flush(&mut ctxt, &mut msgstr);
+            ctxt = Some(po_unquote(v).map_err(err)?);
+            cur = Some("msgctxt");
+        } else if line.starts_with("msgid ") {
+            // entry without msgctxt ends the previous one
+            if msgstr.is_some() {
+                flush(&mut ctxt, &mut msgstr);
+            }
+            cur = Some("msgid");
+        } else if let Some(v) = line.strip_prefix("msgstr ") {
+            msgstr = Some(po_unquote(v).map_err(err)?);
+            cur = Some("msgstr");
+        } else if line.starts_with('"') {
+            let v = po_unquote(line).map_err(err)?;
+            match cur {
+                Some("msgctxt") => ctxt.get_or_insert_with(String::new).push_str(&v),
+                Some("msgstr") => msgstr.get_or_insert_with(String::new).push_str(&v),
+                _ => {}
+            }
+        } else {
+            return Err(err(format!("unexpected {}", line)));
+        }
+    }
+    flush(&mut ctxt, &mut msgstr);
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog() {
+        let mut c = Catalog::new("en");
+        assert_eq!(c.bundle("en")["ui.no_ribs"], "No RIBs yet...");
+        let po = c.to_po("de");
+        assert!(po.contains("msgctxt \"ui.no_ribs\"\nmsgid \"No RIBs yet...\"\nmsgstr \"\""));
+        let de = parse_po(&po.replace(
+            "msgid \"No RIBs yet...\"\nmsgstr \"\"",
+            "msgid \"No RIBs yet...\"\nmsgstr \"Noch keine \"\n\"RIBs...\"",
+        ))
+        .unwrap();
+        assert_eq!(de.len(), 1);
+        c.insert("de", de);
+        assert_eq!(c.bundle("de")["ui.no_ribs"], "Noch keine RIBs...");
+        assert_eq!(c.bundle("de")["ui.title"], "Routes");
+        assert_eq!(c.negotiate(Some("fr;q=0.9, de-AT, en;q=0.5")), "de");
+        assert_eq!(c.negotiate(Some("fr")), "en");
+        assert_eq!(c.negotiate(None), "en");
+    }
+}
@@ -0,0 +1,120 @@
+//! UPDATE ingestion latency from socket read to RIB commit, exported at /metrics
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// histogram bucket upper bounds in seconds, last bucket is +Inf
+const BUCKETS: [f64; 14] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+];
+
+pub struct Histogram {
+    counts: [AtomicU64; 15],
+    sum_us: AtomicU64,
+}
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: Default::default(),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+}
+impl Histogram {
+    pub fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        let idx = BUCKETS
+            .iter()
+            .position(|b| secs <= *b)
+            .unwrap_or(BUCKETS.len());
+        self.counts[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(d.as_micros() as u64, Ordering::Relaxed);
+    }
+    pub fn count(&self) -> u64 {
+        self.counts.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+    /// Prometheus text format series with cumulative buckets, labels are "k=\"v\"" pairs
+    pub fn write_prometheus(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut total = 0;
+        for (i, c) in self.counts.iter().enumerate() {
+            total += c.load(Ordering::Relaxed);
+            let le = match BUCKETS.get(i) {
+                Some(b) => b.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, sep, le, total
+            );
+        }
+        let braces = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(
+            out,
+            "{}_sum{} {}",
+            name,
+            braces,
+            self.sum_us.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(out, "{}_count{} {}", name, braces, total);
+    }
+}
+
+/// time of UPDATE spent waiting in queue and for RIB lock, and total up to RIB commit
+#[derive(Default)]
+pub struct UpdateLatency {
+    pub queued: Histogram,
+    pub total: Histogram,
+}
+impl UpdateLatency {
+    /// read - message read from socket, locked - RIB write lock taken, committed - RIB updated
+    pub fn record(
+        &self,
+        read: std::time::Instant,
+        locked: std::time::Instant,
+        committed: std::time::Instant,
+    ) {
+        self.queued.observe(locked.saturating_duration_since(read));
+        self.total
+            .observe(committed.saturating_duration_since(read));
+    }
+    pub fn write_prometheus(&self, out: &mut String) {
+        let name = "bgpexplorer_update_latency_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} UPDATE latency from socket read, stage queued is until RIB lock is taken, total is until RIB commit",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        self.queued.write_prometheus(out, name, "stage=\"queued\"");
+        self.total.write_prometheus(out, name, "stage=\"total\"");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let h = Histogram::default();
+        h.observe(Duration::from_micros(50));
+        h.observe(Duration::from_millis(3));
+        h.observe(Duration::from_secs(7));
+        assert_eq!(h.count(), 3);
+        let mut out = String::new();
+        h.write_prometheus(&mut out, "t", "stage=\"total\"");
+        assert!(out.contains("t_bucket{stage=\"total\",le=\"0.0001\"} 1\n"));
+        assert!(out.contains("t_bucket{stage=\"total\",le=\"0.005\"} 2\n"));
+        assert!(out.contains("t_bucket{stage=\"total\",le=\"5\"} 2\n"));
+        assert!(out.contains("t_bucket{stage=\"total\",le=\"+Inf\"} 3\n"));
+        assert!(out.contains("t_sum{stage=\"total\"} 7.00305\n"));
+        assert!(out.contains("t_count{stage=\"total\"} 3\n"));
+    }
+}
@@ -0,0 +1,155 @@
+//! bgpexplorer collection engine: BGP/BMP session drivers, RIB with change history and
+//! the services built on top of it.
+//!
+//! The HTTP front end lives in the bgpexplorer binary, programs embedding the engine
+//! only need [`SvcConfig`] and [`BgpSvr`]:
+//!
+//! ```no_run
+//! use bgpexplorer::{BgpEvent, BgpSvr, SvcConfig};
+//! use std::sync::Arc;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let conf = Arc::new(SvcConfig::from_inifile("bgpexplorer.ini")?);
+//!     let token = tokio_util::sync::CancellationToken::new();
+//!     let mut svr = BgpSvr::new(conf, token.clone())?;
+//!     svr.start_updates().await;
+//!     let svr = Arc::new(svr);
+//!     let mut events = svr.subscribe_bgp().await;
+//!     tokio::spawn(svr.clone().run());
+//!     while let Ok(evt) = events.recv().await {
+//!         if let BgpEvent::Withdraw(sid, addrs) = evt {
+//!             println!("session {} withdrew {:?}", sid, addrs);
+//!         }
+//!     }
+//!     token.cancel();
+//!     Ok(())
+//! }
+//! ```
+//!
+//! RIB contents are reached through [`BgpSvr::rib`], which wraps [`BgpRIB`] with lock
+//! timeouts; wire decoding helpers are in [`wireparse`]. Custom sinks implement
+//! [`RouteProcessor`] and are attached with [`BgpSvr::add_processor`] or registered by
+//! name with [`register_processor`] for the processors option.
+extern crate async_trait;
+extern crate futures;
+extern crate futures_util;
+extern crate hyper;
+extern crate tokio;
+extern crate websocket_codec;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate ini;
+extern crate url;
+#[macro_use]
+extern crate log;
+
+use hyper::{Body, Request, Response, StatusCode};
+use std::sync::Arc;
+use tokio::*;
+
+pub mod addrclass;
+pub mod afireport;
+pub mod allocator;
+pub mod anonymize;
+pub mod archive;
+pub mod asgraph;
+pub mod audit;
+pub mod bench;
+pub mod bestpath;
+pub mod bgpattrs;
+pub mod bgppeer;
+pub mod bgprib;
+use bgprib::*;
+pub mod blackhole;
+pub mod bmppeer;
+pub mod service;
+use service::*;
+pub mod bgpsvc;
+use bgpsvc::*;
+pub mod whoissvc;
+pub mod compact;
+pub mod confed;
+pub mod config;
+use config::*;
+pub mod changefeed;
+pub mod check;
+pub mod clockskew;
+pub mod coalesce;
+pub mod communities;
+pub mod ctl;
+pub mod daemon;
+pub mod dbexport;
+pub mod federation;
+pub mod generation;
+pub mod graphql;
+pub mod gtsm;
+pub mod histexport;
+pub mod inbound;
+pub mod incident;
+pub mod initconf;
+pub mod inlinemap;
+pub mod integrity;
+pub mod irr;
+pub mod jsonfmt;
+pub mod l10n;
+pub mod latency;
+pub mod liveness;
+#[cfg(any(test, feature = "mockpeer"))]
+pub mod mockpeer;
+pub mod nexthop;
+pub mod objstore;
+pub mod pcap;
+pub mod peerstats;
+pub mod prefixlen;
+pub mod prefixsid;
+pub mod preload;
+pub mod processor;
+pub mod protostats;
+pub mod querycost;
+pub mod querydsl;
+pub mod quirks;
+pub mod ratelimit;
+pub mod rawupdates;
+pub mod reflection;
+pub mod replay;
+pub mod replication;
+pub mod report;
+pub mod retention;
+pub mod ribfilter;
+pub mod ribservice;
+pub mod rpki;
+pub mod sanity;
+pub mod script;
+pub mod search;
+pub mod secrets;
+pub mod sessionctl;
+pub mod settings;
+pub mod shadow;
+pub mod smtp;
+pub mod snmp;
+pub mod sortindex;
+pub mod stability;
+pub mod subscriber;
+pub mod syslog;
+pub mod systemd;
+pub mod tableversion;
+pub mod tenancy;
+pub mod testpeer;
+pub mod timeline;
+pub mod timestamp;
+pub mod tls;
+pub mod trace;
+pub mod traffic;
+pub mod uiconfig;
+pub mod views;
+pub mod watch;
+pub mod wireparse;
+pub mod withdrawburst;
+
+pub use bgprib::{BgpEvent, BgpRIB};
+pub use bgpsvc::{BgpSessionId, BgpSvr, BgpUpdateHandler};
+pub use config::SvcConfig;
+pub use processor::{register_processor, RouteProcessor};
+pub use ribservice::BgpRIBts;
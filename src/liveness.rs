@@ -0,0 +1,82 @@
+//! session liveness counters, dead peers are torn down by session drivers
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LivenessEvent {
+    /// any message received
+    Message,
+    Keepalive,
+    /// BGP peer sent nothing within negotiated hold time
+    HoldExpired,
+    /// BMP connection idle longer than bmp_idle_timeout or BGP OPEN wait longer than passive_idle_timeout
+    IdleTimeout,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LivenessStats {
+    pub messages: u64,
+    pub keepalives: u64,
+    pub hold_expired: u64,
+    pub idle_closed: u64,
+    pub last_message: Option<Timestamp>,
+}
+
+#[derive(Default)]
+pub struct Liveness {
+    messages: AtomicU64,
+    keepalives: AtomicU64,
+    hold_expired: AtomicU64,
+    idle_closed: AtomicU64,
+    last_message: Mutex<Option<Timestamp>>,
+}
+impl Liveness {
+    pub fn record(&self, evt: LivenessEvent) {
+        match evt {
+            LivenessEvent::Message => {
+                self.messages.fetch_add(1, Ordering::Relaxed);
+                *self.last_message.lock().unwrap() = Some(Timestamp::now());
+            }
+            LivenessEvent::Keepalive => {
+                self.keepalives.fetch_add(1, Ordering::Relaxed);
+            }
+            LivenessEvent::HoldExpired => {
+                self.hold_expired.fetch_add(1, Ordering::Relaxed);
+            }
+            LivenessEvent::IdleTimeout => {
+                self.idle_closed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    pub fn stats(&self) -> LivenessStats {
+        LivenessStats {
+            messages: self.messages.load(Ordering::Relaxed),
+            keepalives: self.keepalives.load(Ordering::Relaxed),
+            hold_expired: self.hold_expired.load(Ordering::Relaxed),
+            idle_closed: self.idle_closed.load(Ordering::Relaxed),
+            last_message: *self.last_message.lock().unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_liveness() {
+        let l = Liveness::default();
+        assert!(l.stats().last_message.is_none());
+        l.record(LivenessEvent::Message);
+        l.record(LivenessEvent::Keepalive);
+        l.record(LivenessEvent::HoldExpired);
+        let s = l.stats();
+        assert_eq!(s.messages, 1);
+        assert_eq!(s.keepalives, 1);
+        assert_eq!(s.hold_expired, 1);
+        assert_eq!(s.idle_closed, 0);
+        assert!(s.last_message.is_some());
+    }
+}
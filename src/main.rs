@@ -1,15 +1,9 @@
-extern crate async_trait;
+extern crate bgpexplorer;
 extern crate futures;
-extern crate futures_util;
 extern crate hyper;
 extern crate tokio;
 extern crate websocket_codec;
 #[macro_use]
-extern crate lazy_static;
-#[macro_use]
-extern crate ini;
-extern crate url;
-#[macro_use]
 extern crate log;
 extern crate pretty_env_logger;
 #[cfg(unix)]
@@ -26,34 +20,31 @@ use tokio::fs::File;
 use tokio::*;
 use tokio_util::codec::{BytesCodec, Decoder, Framed, FramedRead};
 
-mod bgpattrs;
-mod bgppeer;
-mod bgprib;
-use bgprib::*;
-mod bmppeer;
-mod service;
-use service::*;
-mod bgpsvc;
-use bgpsvc::*;
-mod whoissvc;
-use whoissvc::*;
-mod config;
-use config::*;
-mod ribfilter;
-mod ribservice;
-mod subscriber;
-mod timestamp;
+use bgpexplorer::bgpsvc::*;
+use bgpexplorer::config::*;
+use bgpexplorer::service::*;
+use bgpexplorer::whoissvc::*;
+use bgpexplorer::{
+    anonymize, bench, check, ctl, daemon, graphql, histexport, inbound, incident, initconf,
+    integrity, irr, jsonfmt, objstore, ratelimit, replication, report, search, snmp, subscriber,
+    systemd, testpeer, timestamp::Timestamp, trace,
+};
 
 use std::sync::Arc;
 
-static NOTFOUND: &[u8] = b"Not Found";
-
-/// HTTP status code 404
-fn not_found() -> Response<Body> {
-    Response::builder()
-        .status(StatusCode::NOT_FOUND)
-        .body(NOTFOUND.into())
-        .unwrap()
+/// response which keeps rate limit permit until its body is sent
+fn hold_permit(rsp: Response<Body>, permit: ratelimit::LimitPermit) -> Response<Body> {
+    use hyper::body::HttpBody;
+    let (parts, body) = rsp.into_parts();
+    let stream = futures::stream::unfold(Some((body, permit)), |state| async move {
+        let (mut body, permit) = state?;
+        match body.data().await {
+            Some(Ok(chunk)) => Some((Ok(chunk), Some((body, permit)))),
+            Some(Err(e)) => Some((Err(e), None)),
+            None => None,
+        }
+    });
+    Response::from_parts(parts, Body::wrap_stream(stream))
 }
 
 async fn simple_file_send(filename: &str) -> Result<Response<Body>, hyper::Error> {
@@ -97,10 +88,15 @@ impl Svc {
             let _ = client.send(Message::close(None)).await;
             return;
         }
-        let rcv = self.bgp.as_ref().unwrap().subscribe_bgp().await;
-        subscriber::on_subscriber_client(rcv, client).await;
+        let bgp = self.bgp.as_ref().unwrap();
+        let rcv = bgp.subscribe_bgp().await;
+        subscriber::on_subscriber_client(rcv, client, bgp.anonymizer.clone()).await;
     }
-    async fn server_upgrade(&self, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    async fn server_upgrade(
+        &self,
+        req: Request<Body>,
+        permit: Option<ratelimit::LimitPermit>,
+    ) -> Result<Response<Body>, hyper::Error> {
         let mut res = Response::new(Body::empty());
 
         let ws_accept = if let Ok(req) = ClientRequest::parse(|name| {
@@ -114,6 +110,7 @@ impl Svc {
         };
         let slf = self.clone();
         task::spawn(async move {
+            let _permit = permit;
             match hyper::upgrade::on(req).await {
                 Ok(upgraded) => {
                     let client = MessageCodec::server().framed(upgraded);
@@ -134,37 +131,438 @@ impl Svc {
         );
         Ok(res)
     }
-    pub async fn response_fn(&self, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
-        if req.method() != Method::GET {
+    /// denial response for requests outside API token scope, route queries are checked by BgpSvr
+    fn check_token(&self, req: &Request<Body>, unrestricted: bool) -> Option<Response<Body>> {
+        match self.bgp.as_ref()?.tokens.authorize(req) {
+            Err(code) => Some(access_denied(code)),
+            Ok(Some(_)) if unrestricted => Some(access_denied(StatusCode::FORBIDDEN)),
+            Ok(_) => None,
+        }
+    }
+    async fn say_search(&self, req: &Request<Body>) -> Result<Response<Body>, hyper::http::Error> {
+        let params = get_url_params(req);
+        let q = match get_url_param::<String>(&params, "q") {
+            Some(s) if !s.trim().is_empty() => s,
+            _ => return bad_request("Missing q parameter"),
+        };
+        let limit = get_url_param::<usize>(&params, "limit").unwrap_or(100);
+        let mut ret = Vec::new();
+        if let Some(bgp) = self.bgp.as_ref() {
+            match bgp.rib.search(q.as_str(), limit).await {
+                Some(r) => ret.extend(r),
+                None => return timed_out(),
+            }
+        }
+        if let search::SearchQuery::Text(t) = search::SearchQuery::parse(q.as_str()) {
+            for (asn, name) in self.whois.search_as_names(t.as_str(), limit).await {
+                ret.push(search::SearchResult::AsName { asn, name });
+            }
+        }
+        ret.truncate(limit);
+        json_response(&ret)
+    }
+    /// zip with history, MOAS and flap records, whois data and session events of prefix
+    /// or origin AS between from and to
+    async fn say_incident(
+        &self,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let bgp = match self.bgp.as_ref() {
+            Some(b) => b,
+            None => return Ok(not_found()),
+        };
+        let params = get_url_params(req);
+        let prefix = get_url_param::<String>(&params, "prefix");
+        let asn = get_url_param::<String>(&params, "asn").map(|s| {
+            s.trim_start_matches("AS")
+                .trim_start_matches("as")
+                .parse::<u32>()
+        });
+        let asn = match asn {
+            None => None,
+            Some(Ok(n)) => Some(n),
+            Some(Err(_)) => return bad_request("Invalid asn"),
+        };
+        let prefixes = match prefix.as_ref().map(|p| inbound::PrefixList::parse(p)) {
+            None => None,
+            Some(Ok(pl)) => Some(pl),
+            Some(Err(e)) => return bad_request(e.as_str()),
+        };
+        if prefixes.is_none() && asn.is_none() {
+            return bad_request("Missing prefix or asn parameter");
+        }
+        let time = |name: &str| -> Result<Option<Timestamp>, String> {
+            match get_url_param::<String>(&params, name) {
+                None => Ok(None),
+                Some(s) => incident::parse_time(s.as_str())
+                    .map(Some)
+                    .ok_or_else(|| format!("Invalid {}", name)),
+            }
+        };
+        let (from, to) = match (time("from"), time("to")) {
+            (Ok(f), Ok(t)) => {
+                let to = t.unwrap_or_else(Timestamp::now);
+                let from = f.unwrap_or_else(|| {
+                    Timestamp::from_unix(to.timestamp() - 86400, to.timestamp_subsec_micros())
+                });
+                (from, to)
+            }
+            (Err(e), _) | (_, Err(e)) => return bad_request(e.as_str()),
+        };
+        if from > to {
+            return bad_request("from is after to");
+        }
+        let q = incident::IncidentQuery {
+            prefixes,
+            asn,
+            from,
+            to,
+        };
+        let routes = match bgp.rib.incident_routes(&q).await {
+            Some(r) => r,
+            None => return timed_out(),
+        };
+        let sessions = match bgp.incident_sessions(from, to).await {
+            Some(s) => s,
+            None => return timed_out(),
+        };
+        let whois_queries: Vec<String> = q
+            .prefixes
+            .iter()
+            .flat_map(|pl| pl.items())
+            .chain(
+                asn.iter()
+                    .chain(routes.origins.iter().filter(|o| Some(**o) != asn))
+                    .map(|a| format!("AS{}", a)),
+            )
+            .take(incident::WHOIS_LIMIT)
+            .collect();
+        // lookups run together, bundle waits 10 seconds at most
+        let lookups = whois_queries.iter().map(|wq| async move {
+            match tokio::time::timeout(
+                std::time::Duration::from_secs(10),
+                self.whois.query_whois(wq.clone(), Arc::new(None)),
+            )
+            .await
+            {
+                Ok(Ok(s)) => s,
+                Ok(Err(e)) => format!("% error: {:?}", e),
+                Err(_) => "% timed out".to_string(),
+            }
+        });
+        let mut whois = String::new();
+        for (wq, rsp) in whois_queries
+            .iter()
+            .zip(futures::future::join_all(lookups).await)
+        {
+            whois.push_str(format!("% query {}\n{}\n\n", wq, rsp).as_str());
+        }
+        let summary = serde_json::json!({
+            "prefix": prefix,
+            "asn": asn,
+            "from": from,
+            "to": to,
+            "generated": Timestamp::now(),
+            "history_rows": routes.history.len(),
+            "routes": &routes,
+        });
+        let mut zip = incident::ZipWriter::new(Timestamp::now());
+        let json = |v: &serde_json::Value| serde_json::to_vec_pretty(v).unwrap_or_default();
+        zip.add("summary.json", &json(&summary));
+        zip.add(
+            "history.ndjson",
+            histexport::render(
+                &routes.history,
+                histexport::ExportFormat::NdJson,
+                jsonfmt::TimeFormat::Millis,
+            )
+            .as_bytes(),
+        );
+        zip.add(
+            "history.csv",
+            histexport::render(
+                &routes.history,
+                histexport::ExportFormat::Csv,
+                jsonfmt::TimeFormat::Millis,
+            )
+            .as_bytes(),
+        );
+        zip.add("moas.json", &json(&serde_json::json!(routes.moas)));
+        zip.add("flaps.json", &json(&serde_json::json!(routes.flaps)));
+        zip.add("whois.txt", whois.as_bytes());
+        zip.add("sessions.json", &json(&sessions));
+        let name = match (prefix.as_ref(), asn) {
+            (Some(p), _) => p.replace('/', "_"),
+            (None, Some(a)) => format!("AS{}", a),
+            (None, None) => String::new(),
+        };
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-type", "application/zip")
+            .header(
+                "Content-Disposition",
+                format!("attachment; filename=\"incident-{}.zip\"", name),
+            )
+            .body(zip.finish().into())
+    }
+    /// AS-SET members expanded from IRR with their route objects checked against RIB
+    async fn say_asset(
+        &self,
+        req: &Request<Body>,
+        name: &str,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let bgp = match self.bgp.as_ref() {
+            Some(b) => b,
+            None => return Ok(not_found()),
+        };
+        if name.is_empty() {
+            return bad_request("Missing AS-SET name");
+        }
+        if !matches!(
+            irr::AsSetMember::parse(name),
+            Some(irr::AsSetMember::Set(_))
+        ) {
+            return bad_request("Invalid AS-SET name");
+        }
+        let limit = get_url_param::<usize>(&get_url_params(req), "limit").unwrap_or(irr::MAX_ASNS);
+        let exp = self.whois.irr.expand_as_set(name, limit).await;
+        if exp.sets.is_empty() {
             return Ok(not_found());
         }
+        let routes = self.whois.irr.route_objects(&exp.asns).await;
+        let seen = match bgp.rib.originated(&exp.asns).await {
+            Some(r) => r,
+            None => return timed_out(),
+        };
+        json_response(&irr::AsSetReport::new(name, &exp, &routes, &seen))
+    }
+    /// GET /api/graphql?query=..&variables=.. or POST with GraphQL request JSON
+    async fn say_graphql(&self, req: Request<Body>) -> Result<Response<Body>, hyper::http::Error> {
+        let bgp = match self.bgp.as_ref() {
+            Some(b) if b.config.graphql => b,
+            _ => return Ok(not_found()),
+        };
+        let greq = if req.method() == Method::POST {
+            match read_body(req.into_body(), graphql::MAX_BODY)
+                .await
+                .and_then(|b| {
+                    serde_json::from_slice::<graphql::GraphQlRequest>(&b).map_err(|e| e.to_string())
+                }) {
+                Ok(r) => r,
+                Err(e) => return bad_request(format!("Invalid body: {}", e).as_str()),
+            }
+        } else {
+            let params = get_url_params(&req);
+            let variables = match params.get("variables").map(|v| serde_json::from_str(v)) {
+                None => None,
+                Some(Ok(v)) => Some(v),
+                Some(Err(e)) => return bad_request(format!("Invalid variables: {}", e).as_str()),
+            };
+            graphql::GraphQlRequest {
+                query: match get_url_param::<String>(&params, "query") {
+                    Some(q) => q,
+                    None => return bad_request("Missing query parameter"),
+                },
+                operation_name: get_url_param(&params, "operationName"),
+                variables,
+            }
+        };
+        match graphql::execute(&greq, Some(bgp.as_ref()), &self.whois).await {
+            Ok(v) => json_response(&v),
+            Err(e) => Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-type", "application/json")
+                .body(
+                    serde_json::json!({"errors": [{"message": e}]})
+                        .to_string()
+                        .into(),
+                ),
+        }
+    }
+    /// POST /api/check with JSON array of route assertions
+    async fn say_check(&self, req: Request<Body>) -> Result<Response<Body>, hyper::http::Error> {
+        let bgp = match self.bgp.as_ref() {
+            Some(b) => b,
+            None => return Ok(not_found()),
+        };
+        if req.method() != Method::POST {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("Allow", "POST")
+                .body(Body::empty());
+        }
+        let body = match read_body(req.into_body(), check::MAX_BODY).await {
+            Ok(b) => b,
+            Err(e) => return bad_request(format!("Body read error: {}", e).as_str()),
+        };
+        let items: Vec<check::CheckItem> = match serde_json::from_slice(&body) {
+            Ok(v) => v,
+            Err(e) => return bad_request(format!("Invalid check list: {}", e).as_str()),
+        };
+        if items.len() > check::MAX_ITEMS {
+            return bad_request("Too many items");
+        }
+        match bgp.rib.check(&items).await {
+            Some(r) => json_response(&r),
+            None => timed_out(),
+        }
+    }
+    /// API and metrics responses are rewritten when anonymization is configured, line
+    /// oriented bodies as they stream, bodies which can not be rewritten are refused
+    pub async fn response_fn(&self, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+        let path = req.uri().path();
+        let anon = match self.bgp.as_ref().and_then(|b| b.anonymizer.clone()) {
+            Some(a) if path.starts_with("/api/") || path == "/metrics" => a,
+            _ => return self.route_fn(req).await,
+        };
+        let rsp = self.route_fn(req).await?;
+        if rsp.status() == StatusCode::SWITCHING_PROTOCOLS {
+            return Ok(rsp);
+        }
+        let (mut parts, body) = rsp.into_parts();
+        let ctype = parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("text/plain")
+            .to_string();
+        if let Some(rw) = anonymize::LineRewriter::new(anon.clone(), &ctype) {
+            use hyper::body::HttpBody;
+            parts.headers.remove(header::CONTENT_LENGTH);
+            let stream = futures::stream::unfold(Some((body, rw)), |state| async move {
+                let (mut body, mut rw) = state?;
+                match body.data().await {
+                    Some(Ok(chunk)) => Some((Ok(rw.push(&chunk)), Some((body, rw)))),
+                    Some(Err(e)) => Some((Err(e), None)),
+                    None => Some((Ok(rw.finish()), None)),
+                }
+            });
+            return Ok(Response::from_parts(parts, Body::wrap_stream(stream)));
+        }
+        let body = hyper::body::to_bytes(body).await?;
+        if body.is_empty() {
+            return Ok(Response::from_parts(parts, Body::empty()));
+        }
+        match anon.body(&ctype, &body) {
+            Some(b) => {
+                parts.headers.remove(header::CONTENT_LENGTH);
+                Ok(Response::from_parts(parts, b.into()))
+            }
+            None => Ok(access_denied(StatusCode::FORBIDDEN)),
+        }
+    }
+    async fn route_fn(&self, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
         let requri = req.uri().path();
+        // only admin actions change state, route checks carry list in body
+        if req.method() != Method::GET
+            && !(req.method() == Method::POST
+                && (requri.starts_with("/api/admin/")
+                    || requri == "/api/check"
+                    || requri == "/api/graphql"))
+            && !(req.method() == Method::PATCH && requri == "/api/admin/settings")
+            && !((req.method() == Method::POST || req.method() == Method::DELETE)
+                && requri.starts_with("/api/subscriptions"))
+            && !((req.method() == Method::PUT || req.method() == Method::DELETE)
+                && requri.starts_with("/api/views/"))
+        {
+            return Ok(not_found());
+        }
         if requri.len() > 5 && requri[..5] == "/api/"[..5] {
             let urlparts: Vec<&str> = requri.split('/').collect();
             if urlparts.len() > 2 {
-                match urlparts[2] {
-                    "whois" => {
-                        return self.whois.response_fn(&req).await;
-                    }
-                    "dns" => {
-                        return self.whois.response_fn(&req).await;
+                let denied = match urlparts[2] {
+                    "whois" | "dns" => self.check_token(&req, false),
+                    "search" | "ws" | "asset" | "check" | "subscriptions" | "incident"
+                    | "graphql" => self.check_token(&req, true),
+                    _ => None,
+                };
+                if let Some(rsp) = denied {
+                    return Ok(rsp);
+                }
+                // unauthenticated requests are refused before they use class budgets;
+                // permit is held until response body is sent, websocket keeps it while
+                // connected
+                let permit = match (self.bgp.as_ref(), ratelimit::QueryClass::of_path(requri)) {
+                    (Some(bgp), Some(class)) => {
+                        let client = if bgp.tokens.is_empty() {
+                            req.extensions()
+                                .get::<std::net::SocketAddr>()
+                                .map(|a| a.ip().to_string())
+                                .unwrap_or_default()
+                        } else {
+                            match bgp.tokens.identify(&req) {
+                                Some(scope) => scope.name.clone(),
+                                None => return Ok(access_denied(StatusCode::UNAUTHORIZED)),
+                            }
+                        };
+                        match bgp.limits.acquire(class, &client) {
+                            Ok(p) => Some(p),
+                            Err(e) => return Ok(too_many_requests(e.retry_after())),
+                        }
                     }
-                    "ping" => {
-                        return Ok(Response::new(Body::from("pong")));
+                    _ => None,
+                };
+                let rsp = match urlparts[2] {
+                    "whois" => self.whois.response_fn(&req).await?,
+                    "dns" => self.whois.response_fn(&req).await?,
+                    "search" => self.say_search(&req).await.unwrap_or_else(|_| not_found()),
+                    "incident" => self
+                        .say_incident(&req)
+                        .await
+                        .unwrap_or_else(|_| not_found()),
+                    "asset" => {
+                        let name = urlparts.get(3).cloned().unwrap_or("");
+                        self.say_asset(&req, name)
+                            .await
+                            .unwrap_or_else(|_| not_found())
                     }
+                    "check" => self.say_check(req).await.unwrap_or_else(|_| not_found()),
+                    "graphql" => self.say_graphql(req).await.unwrap_or_else(|_| not_found()),
+                    "ping" => Response::new(Body::from("pong")),
                     "ws" => {
-                        return self.server_upgrade(req).await;
+                        return self.server_upgrade(req, permit).await;
                     }
+                    "subscriptions" => match &self.bgp {
+                        Some(bgpr) => bgpr
+                            .say_subscriptions(req)
+                            .await
+                            .unwrap_or_else(|_| not_found()),
+                        None => not_found(),
+                    },
+                    "views" if req.method() != Method::GET => match &self.bgp {
+                        Some(bgpr) => bgpr
+                            .say_view_change(req)
+                            .await
+                            .unwrap_or_else(|_| not_found()),
+                        None => not_found(),
+                    },
+                    "admin" if urlparts.get(3) == Some(&"settings") => match &self.bgp {
+                        Some(bgpr) => bgpr.say_settings(req).await.unwrap_or_else(|_| not_found()),
+                        None => not_found(),
+                    },
                     _ => {
                         if let Some(bgpr) = &self.bgp {
-                            return bgpr.response_fn(&req).await;
+                            bgpr.response_fn(&req).await?
                         } else {
                             //panic!("No service")
-                            return Ok(Response::new(Body::from("No service")));
+                            Response::new(Body::from("No service"))
                         }
                     }
-                }
+                };
+                return Ok(match permit {
+                    Some(permit) => hold_permit(rsp, permit),
+                    None => rsp,
+                });
+            }
+        }
+        if requri == "/metrics" {
+            if let Some(rsp) = self.check_token(&req, true) {
+                return Ok(rsp);
             }
+            return Ok(match &self.bgp {
+                Some(bgpr) => bgpr.say_metrics().unwrap_or_else(|_| not_found()),
+                None => not_found(),
+            });
         }
         let filepath = self.httproot.to_string()
             + (match requri {
@@ -175,19 +573,105 @@ impl Svc {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    pretty_env_logger::init_timed();
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|s| s.as_str()) == Some("init") {
+        match initconf::run(&args[1..]) {
+            Ok(path) if path == "-" => {}
+            Ok(path) => eprintln!("Example configuration written to {}", path),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if args.first().map(|s| s.as_str()) == Some("ctl") {
+        match ctl::run(&args[1..]) {
+            Ok(out) => println!("{}", out),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if args.first().map(|s| s.as_str()) == Some("test-peer") {
+        match testpeer::run(&args[1..]) {
+            Ok((report, established)) => {
+                println!("{}", report);
+                if !established {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if args.first().map(|s| s.as_str()) == Some("bench") {
+        match bench::run(&args[1..]) {
+            Ok(report) => println!("{}", report),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    // logger passes every level not limited by RUST_LOG module directives, global level is
+    // log::max_level, so log_level of /api/admin/settings can raise it at runtime
+    let rust_log = std::env::var("RUST_LOG").unwrap_or_default();
+    let initial_level = pretty_env_logger::env_logger::filter::Builder::new()
+        .parse(&rust_log)
+        .build()
+        .filter();
+    pretty_env_logger::formatted_timed_builder()
+        .parse_filters(&rust_log)
+        .filter_level(log::LevelFilter::Trace)
+        .init();
+    log::set_max_level(initial_level);
     let conf = match SvcConfig::from_inifile("bgpexplorer.ini") {
-        Ok(sc) => Arc::new(sc),
+        Ok(mut sc) => {
+            if sc.daemonize {
+                sc.resolve_paths(&std::env::current_dir()?);
+            }
+            Arc::new(sc)
+        }
         Err(e) => {
             error!("{}", e);
             return Ok(());
         }
     };
+    // everything here should be done before runtime threads are started
+    if conf.daemonize || conf.user.is_some() || conf.group.is_some() {
+        daemon::prebind(&conf)?;
+    }
+    if conf.daemonize {
+        daemon::daemonize()?;
+    }
+    if let Some(ref pidfile) = conf.pidfile {
+        daemon::write_pidfile(pidfile, conf.user.as_deref(), conf.group.as_deref())?;
+    }
+    if conf.user.is_some() || conf.group.is_some() {
+        daemon::drop_privileges(conf.user.as_deref(), conf.group.as_deref())?;
+    }
+    let ret = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(conf.clone()));
+    if let Some(ref pidfile) = conf.pidfile {
+        daemon::remove_pidfile(pidfile);
+    }
+    ret
+}
 
+async fn run(conf: Arc<SvcConfig>) -> Result<(), Box<dyn std::error::Error>> {
+    trace::init(conf.protocoltrace, conf.protocoltrace_buffer);
     let token = tokio_util::sync::CancellationToken::new();
-    let mut svr = BgpSvr::new(conf.clone(), token.clone());
+    let mut svr = BgpSvr::new(conf.clone(), token.clone())?;
     svr.start_updates().await;
     let msvr = Arc::new(svr);
     let svc = Svc::new(
@@ -196,12 +680,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Arc::new(WhoisSvr::new(&conf)),
     );
 
-    let tck1 = {
-        let mut _svr = msvr.clone();
-        tokio::spawn(async move {
-            _svr.run().await;
-        })
-    };
     let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(10);
     #[cfg(unix)]
     {
@@ -288,32 +766,154 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
     });
+    match conf.replication {
+        ReplicationMode::Primary => {
+            let rcfg = conf.clone();
+            let rrib = msvr.rib.rib.clone();
+            let rtoken = token.clone();
+            let listen = conf.replication_listen.unwrap();
+            tokio::spawn(async move {
+                select! {
+                    _ = rtoken.cancelled() => {}
+                    r = replication::run_primary(rcfg, rrib, listen) => {
+                        if let Err(e) = r {
+                            error!("Replication error: {}", e);
+                        }
+                    }
+                }
+            });
+        }
+        ReplicationMode::Secondary => {
+            let primary = conf.replication_peer.unwrap();
+            info!("Standby mode, mirroring {}", primary);
+            select! {
+                _ = rx.recv() => {
+                    info!("shutdown in standby mode");
+                    token.cancel();
+                    svc.shutdown().await;
+                    return Ok(());
+                }
+                _ = replication::run_secondary(conf.clone(), msvr.rib.rib.clone(), primary) => {
+                    warn!("Primary {} is lost, taking over", primary);
+                }
+            }
+        }
+        ReplicationMode::Off => {}
+    }
+    if let Some(ref master) = conf.snmp_agentx {
+        let agent = snmp::SnmpAgent::new(msvr.clone());
+        let master = master.clone();
+        let base = conf.snmp_base_oid.clone();
+        let stoken = token.clone();
+        tokio::spawn(async move {
+            select! {
+                _ = stoken.cancelled() => {}
+                _ = agent.run(master, base) => {}
+            }
+        });
+    }
+    if conf.report_dir.is_some() {
+        let rcfg = conf.clone();
+        let rrib = msvr.rib.rib.clone();
+        let rtoken = token.clone();
+        tokio::spawn(async move {
+            select! {
+                _ = rtoken.cancelled() => {}
+                _ = report::run_reports(rcfg, rrib) => {}
+            }
+        });
+    }
+    if conf.objstore.is_some() {
+        let ocfg = conf.clone();
+        let otoken = token.clone();
+        tokio::spawn(async move {
+            select! {
+                _ = otoken.cancelled() => {}
+                _ = objstore::run(ocfg) => {}
+            }
+        });
+    }
+    if conf.integrity_check_every.is_some() {
+        let isvr = msvr.clone();
+        let itoken = token.clone();
+        tokio::spawn(async move {
+            select! {
+                _ = itoken.cancelled() => {}
+                _ = integrity::run(isvr) => {}
+            }
+        });
+    }
+    let tck1 = {
+        let mut _svr = msvr.clone();
+        tokio::spawn(async move {
+            _svr.run().await;
+        })
+    };
     {
-        //let mksvc = make_service_fn(|_| async { Ok::<_, hyper::Error>(service_fn(response_fn)) });
-        let _svc = svc.clone();
-        let service = {
-            make_service_fn(|_| {
-                let _svc1 = _svc.clone();
-                async move {
-                    let _svc2 = _svc1.clone();
-                    Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
-                        let _svc3 = _svc2.clone();
-                        async move { _svc3.response_fn(req).await }
-                    }))
+        let stop = tokio_util::sync::CancellationToken::new();
+        // IPv6 addresses are bound IPv6-only when listed with others, so [::] does not clash with 0.0.0.0
+        let only_v6 = conf.httplisten.len() > 1;
+        let mut servers = Vec::new();
+        for addr in conf.httplisten.iter() {
+            let _svc = svc.clone();
+            let service = {
+                make_service_fn(move |conn: &hyper::server::conn::AddrStream| {
+                    let remote = conn.remote_addr();
+                    let _svc1 = _svc.clone();
+                    async move {
+                        let _svc2 = _svc1.clone();
+                        Ok::<_, hyper::Error>(service_fn(move |mut req: Request<Body>| {
+                            let _svc3 = _svc2.clone();
+                            // client address for per-client query limits
+                            req.extensions_mut().insert(remote);
+                            async move { _svc3.response_fn(req).await }
+                        }))
+                    }
+                })
+            };
+            let listener = match systemd::take_listener(addr) {
+                Some(l) => {
+                    info!("Listening on http://{} (socket activated)", addr);
+                    l
                 }
-            })
-        };
-        info!("Listening on http://{}", conf.httplisten);
-        let server = Server::bind(&conf.httplisten).serve(service);
-        let graceful = server.with_graceful_shutdown(async {
+                None => {
+                    info!("Listening on http://{}", addr);
+                    daemon::bind_listener(addr, only_v6)?
+                }
+            };
+            let mut builder = Server::from_tcp(listener)?
+                .http1_keepalive(conf.http_keepalive)
+                .http2_max_concurrent_streams(conf.http2_max_streams);
+            match conf.httpversion {
+                HttpVersionMode::Auto => {}
+                HttpVersionMode::Http1Only => builder = builder.http1_only(true),
+                HttpVersionMode::Http2Only => builder = builder.http2_only(true),
+            }
+            if let Some(idle) = conf.http_idle_timeout {
+                builder = builder
+                    .http1_header_read_timeout(idle)
+                    .http2_keep_alive_interval(Some(idle))
+                    .http2_keep_alive_timeout(idle);
+            }
+            let stopc = stop.clone();
+            let server = builder
+                .serve(service)
+                .with_graceful_shutdown(async move { stopc.cancelled().await });
+            let addr = *addr;
+            servers.push(async move {
+                if let Err(e) = server.await {
+                    error!("server {} error: {}", addr, e);
+                }
+                info!("Server done: {}", addr);
+            });
+        }
+        tokio::spawn(async move {
             let _ = rx.recv().await;
             info!("shutdown graceful");
+            systemd::stopping();
+            stop.cancel();
         });
-
-        if let Err(e) = graceful.await {
-            error!("server error: {}", e);
-        }
-        info!("Server done: {}", conf.httplisten);
+        futures::future::join_all(servers).await;
         token.cancel();
     };
     svc.shutdown().await;
@@ -0,0 +1,265 @@
+//! scripted BGP speaker for end-to-end tests of session code over loopback sockets,
+//! available to other crates with mockpeer feature
+use crate::bgpsvc::*;
+use async_trait::async_trait;
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use zettabgp::prelude::*;
+
+pub struct MockPeer {
+    pub params: BgpSessionParams,
+    sock: TcpStream,
+}
+impl MockPeer {
+    /// connects to passive bgpexplorer session
+    pub async fn connect(addr: SocketAddr, params: BgpSessionParams) -> Result<MockPeer, BgpError> {
+        let sock = TcpStream::connect(addr).await?;
+        Ok(MockPeer::from_stream(sock, params))
+    }
+    /// takes connection accepted from active bgpexplorer session
+    pub fn from_stream(sock: TcpStream, params: BgpSessionParams) -> MockPeer {
+        MockPeer { params, sock }
+    }
+    /// sends message with body already encoded
+    pub async fn send_raw(&mut self, msgtype: BgpMessageType, body: &[u8]) -> Result<(), BgpError> {
+        let mut buf = vec![255u8; 19 + body.len()];
+        buf[19..].copy_from_slice(body);
+        let len = self
+            .params
+            .prepare_message_buf(&mut buf, msgtype, body.len())?;
+        self.sock.write_all(&buf[0..len]).await?;
+        Ok(())
+    }
+    /// OPEN made from params
+    pub async fn send_open(&mut self) -> Result<(), BgpError> {
+        let bom = self.params.open_message();
+        let mut body = [0u8; 4096];
+        let sz = bom.encode_to(&self.params, &mut body)?;
+        self.send_raw(BgpMessageType::Open, &body[0..sz]).await
+    }
+    pub async fn send_keepalive(&mut self) -> Result<(), BgpError> {
+        self.send_raw(BgpMessageType::Keepalive, &[]).await
+    }
+    /// sends UPDATE, decoder's internal attributes are not put on the wire
+    pub async fn send_update(&mut self, upd: &BgpUpdateMessage) -> Result<(), BgpError> {
+        let mut body = [0u8; 4096];
+        let sz = if upd.attrs.iter().any(crate::wireparse::is_internal) {
+            let mut upd = BgpUpdateMessage {
+                updates: upd.updates.clone(),
+                withdraws: upd.withdraws.clone(),
+                attrs: upd.attrs.clone(),
+            };
+            crate::wireparse::strip_internal(&mut upd);
+            upd.encode_to(&self.params, &mut body)?
+        } else {
+            upd.encode_to(&self.params, &mut body)?
+        };
+        self.send_raw(BgpMessageType::Update, &body[0..sz]).await
+    }
+    pub async fn send_notification(&mut self, code: u8, subcode: u8) -> Result<(), BgpError> {
+        self.send_raw(BgpMessageType::Notification, &[code, subcode])
+            .await
+    }
+    /// next message type and body
+    pub async fn recv(&mut self) -> Result<(BgpMessageType, Vec<u8>), BgpError> {
+        let mut head = [0u8; 19];
+        self.sock.read_exact(&mut head).await?;
+        let (msgtype, len) = self.params.decode_message_head(&head)?;
+        let mut body = vec![0u8; len];
+        self.sock.read_exact(&mut body).await?;
+        Ok((msgtype, body))
+    }
+    /// waits for OPEN skipping keepalives and negotiates capabilities from it
+    pub async fn expect_open(&mut self) -> Result<BgpOpenMessage, BgpError> {
+        loop {
+            match self.recv().await? {
+                (BgpMessageType::Keepalive, _) => continue,
+                (BgpMessageType::Open, body) => {
+                    let bom = crate::wireparse::decode_open(&self.params, &body)?;
+                    self.params.match_caps(&bom.caps);
+                    return Ok(bom);
+                }
+                (t, _) => return Err(BgpError::from_string(format!("Expected OPEN, got {:?}", t))),
+            }
+        }
+    }
+    pub async fn close(mut self) {
+        let _ = self.sock.shutdown().await;
+    }
+}
+
+/// UPDATE body announcing IPv4 prefixes with IGP origin, AS_SEQUENCE path and next hop;
+/// as4 - 4-byte AS numbers, when ASN32 capability is negotiated.
+/// Long pathes are split into several segments, values not fitting the wire format are errors
+pub fn ipv4_update_body(
+    prefixes: &[(Ipv4Addr, u8)],
+    aspath: &[u32],
+    nexthop: Ipv4Addr,
+    as4: bool,
+) -> Result<Vec<u8>, BgpError> {
+    let mut path = Vec::new();
+    for seg in aspath.chunks(255) {
+        path.extend_from_slice(&[2u8, seg.len() as u8]);
+        for asn in seg {
+            if as4 {
+                path.extend_from_slice(&asn.to_be_bytes());
+            } else {
+                let asn = u16::try_from(*asn).map_err(|_| {
+                    BgpError::from_string(format!("AS {} does not fit 2-byte AS path", asn))
+                })?;
+                path.extend_from_slice(&asn.to_be_bytes());
+            }
+        }
+    }
+    let pathlen = u16::try_from(path.len())
+        .map_err(|_| BgpError::static_str("AS path does not fit attribute"))?;
+    // extended length
+    let mut attrs = vec![0x40, 1, 1, 0, 0x50, 2];
+    attrs.extend_from_slice(&pathlen.to_be_bytes());
+    attrs.extend_from_slice(&path);
+    attrs.extend_from_slice(&[0x40, 3, 4]);
+    attrs.extend_from_slice(&nexthop.octets());
+    let attrlen = u16::try_from(attrs.len())
+        .map_err(|_| BgpError::static_str("attributes do not fit update"))?;
+    let mut body = vec![0, 0];
+    body.extend_from_slice(&attrlen.to_be_bytes());
+    body.extend_from_slice(&attrs);
+    for (addr, len) in prefixes {
+        if *len > 32 {
+            return Err(BgpError::from_string(format!(
+                "invalid prefix length {}/{}",
+                addr, len
+            )));
+        }
+        body.push(*len);
+        body.extend_from_slice(&addr.octets()[0..(*len as usize).div_ceil(8)]);
+    }
+    Ok(body)
+}
+
+/// update handler keeping everything session code passes to it
+#[derive(Default)]
+pub struct RecordingHandler {
+    pub sessions: Mutex<Vec<Arc<BgpSessionDesc>>>,
+    pub updates: Mutex<Vec<(BgpSessionId, BgpUpdateMessage)>>,
+}
+#[async_trait]
+impl BgpUpdateHandler for RecordingHandler {
+    async fn handle_update(&self, peerid: BgpSessionId, upd: BgpUpdateMessage) {
+        self.updates.lock().unwrap().push((peerid, upd));
+    }
+    async fn register_session(&self, sess: Arc<BgpSessionDesc>) -> BgpSessionId {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.push(sess);
+        sessions.len() as BgpSessionId
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgppeer::BgpPeer;
+
+    fn params(asn: u32, router_id: Ipv4Addr, caps: Vec<BgpCapability>) -> BgpSessionParams {
+        BgpSessionParams::new(asn, 90, BgpTransportMode::IPv4, router_id, caps)
+    }
+
+    #[tokio::test]
+    async fn test_passive_session() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mock = tokio::spawn(async move {
+            let mut mock = MockPeer::connect(
+                addr,
+                params(
+                    64500,
+                    Ipv4Addr::new(10, 0, 0, 1),
+                    vec![BgpCapability::SafiIPv4u],
+                ),
+            )
+            .await
+            .unwrap();
+            mock.send_open().await.unwrap();
+            let open = mock.expect_open().await.unwrap();
+            mock.send_keepalive().await.unwrap();
+            let body = ipv4_update_body(
+                &[(Ipv4Addr::new(198, 51, 100, 0), 24)],
+                &[64500, 64496],
+                Ipv4Addr::new(10, 0, 0, 1),
+                false,
+            )
+            .unwrap();
+            mock.send_raw(BgpMessageType::Update, &body).await.unwrap();
+            // cease
+            mock.send_notification(6, 0).await.unwrap();
+            // unread keepalive reply would turn close into reset
+            while mock.recv().await.is_ok() {}
+            open
+        });
+        let handler = RecordingHandler::default();
+        let (sock, _) = listener.accept().await.unwrap();
+        let mut peer = BgpPeer::new(
+            params(
+                64500,
+                Ipv4Addr::new(10, 0, 0, 2),
+                vec![BgpCapability::SafiIPv4u, BgpCapability::SafiIPv6u],
+            ),
+            sock,
+            &handler,
+        );
+        peer.start_passive().await.unwrap();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            peer.lifecycle(tokio_util::sync::CancellationToken::new()),
+        )
+        .await
+        .unwrap();
+        peer.close().await;
+        let open = mock.await.unwrap();
+        assert_eq!(open.as_num, 64500);
+        assert_eq!(open.router_id, Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(peer.params.hold_time, 90);
+        assert_eq!(handler.sessions.lock().unwrap().len(), 1);
+        let updates = handler.updates.lock().unwrap();
+        assert_eq!(updates.len(), 1);
+        let (sid, upd) = &updates[0];
+        assert_eq!(*sid, 1);
+        match &upd.updates {
+            BgpAddrs::IPV4U(v) => {
+                assert_eq!(v, &vec![BgpAddrV4::new(Ipv4Addr::new(198, 51, 100, 0), 24)])
+            }
+            x => panic!("unexpected NLRI {:?}", x),
+        }
+        assert_eq!(
+            upd.get_attr_aspath()
+                .map(|p| crate::compact::CompactASPath::from_aspath(p).value.len()),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_ipv4_update_body() {
+        let nh = Ipv4Addr::new(10, 0, 0, 1);
+        let prefixes = [(Ipv4Addr::new(198, 51, 100, 0), 24)];
+        assert!(ipv4_update_body(&prefixes, &[4200000001], nh, false).is_err());
+        assert!(ipv4_update_body(&[(nh, 33)], &[64500], nh, true).is_err());
+        let aspath: Vec<u32> = (0..300).map(|i| 4200000000 + i).collect();
+        let body = ipv4_update_body(&prefixes, &aspath, nh, true).unwrap();
+        let mut params = BgpSessionParams::new(
+            64500,
+            90,
+            BgpTransportMode::IPv4,
+            nh,
+            vec![BgpCapability::SafiIPv4u],
+        );
+        params.has_as32bit = true;
+        let mut upd = BgpUpdateMessage::new();
+        upd.decode_from(&params, &body).unwrap();
+        let path = crate::compact::CompactASPath::from_aspath(upd.get_attr_aspath().unwrap());
+        assert_eq!(path.value.len(), 300);
+        assert_eq!(path.value[299].value, 4200000299);
+    }
+}
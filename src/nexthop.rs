@@ -0,0 +1,282 @@
+use crate::bgpattrs::BgpAttrEntry;
+use crate::bgprib::*;
+use crate::ribfilter::{FilterMatchRoute, RouteFilter};
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use zettabgp::prelude::*;
+
+/// maximum recursion depth for next hop resolving
+pub const NEXTHOP_MAX_DEPTH: usize = 8;
+
+/// One step of recursive next hop lookup
+#[derive(Debug, Clone, Serialize)]
+pub struct NexthopStep {
+    pub nexthop: String,
+    pub rib: String,
+    pub covering: Option<String>,
+    pub nexthops: Vec<String>,
+}
+
+/// Resolution chain for one route next hop
+#[derive(Debug, Clone, Serialize)]
+pub struct NexthopResolution {
+    pub nexthop: String,
+    pub resolved: bool,
+    pub looped: bool,
+    pub chain: Vec<NexthopStep>,
+}
+
+/// Route with its next hops resolved against the RIB
+#[derive(Debug, Clone, Serialize)]
+pub struct NexthopResponse {
+    pub route: String,
+    pub nexthops: Vec<NexthopResolution>,
+}
+
+/// active routes count for each distinct next hop of RIB, maintained on updates
+#[derive(Debug, Default, Clone)]
+pub struct NexthopInventory {
+    /// routes count and time when next hop got its first route
+    items: BTreeMap<BgpAddr, (u64, Timestamp)>,
+}
+impl NexthopInventory {
+    /// account route changing from previous last record to the next active next hop
+    pub fn track(&mut self, prev: Option<&BgpAttrEntry>, next: Option<&BgpAddr>) {
+        if let Some(p) = prev.filter(|p| p.active) {
+            if let Some(e) = self.items.get_mut(&p.attrs.nexthop) {
+                e.0 = e.0.saturating_sub(1);
+                if e.0 == 0 {
+                    self.items.remove(&p.attrs.nexthop);
+                }
+            }
+        }
+        if let Some(nh) = next {
+            match self.items.get_mut(nh) {
+                Some(e) => e.0 += 1,
+                None => {
+                    self.items.insert(nh.clone(), (1, Timestamp::now()));
+                }
+            }
+        }
+    }
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    pub fn report(&self, rib: &'static str, out: &mut Vec<NexthopUsage>) {
+        out.extend(self.items.iter().map(|(nh, (routes, since))| NexthopUsage {
+            rib,
+            nexthop: nh.to_string(),
+            routes: *routes,
+            since: *since,
+        }));
+    }
+}
+
+/// next hop from /api/nexthops inventory
+#[derive(Debug, Clone, Serialize)]
+pub struct NexthopUsage {
+    pub rib: &'static str,
+    pub nexthop: String,
+    pub routes: u64,
+    /// next hop has active routes since
+    pub since: Timestamp,
+}
+
+/// next hops from last active history records of all sessions and pathes
+pub fn active_nexthops(sess: &BgpSessionEntry) -> Vec<BgpAddr> {
+    let mut ret = Vec::new();
+    for (_, pe) in sess.items.iter() {
+        for (_, hist) in pe.items.iter() {
+            if let Some((_, last)) = hist.items.iter().last() {
+                if last.active && !ret.contains(&last.attrs.nexthop) {
+                    ret.push(last.attrs.nexthop.clone());
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// best (longest) route matching filter with its active next hops
+pub fn find_covering<T: FilterMatchRoute + BgpRIBKey>(
+    safi: &BgpRIBSafi<T>,
+    filter: &RouteFilter,
+) -> Option<(String, Vec<BgpAddr>)> {
+    filter
+        .find_best_supernet(safi, usize::MAX, true)
+        .map(|(k, v)| (k.to_string(), active_nexthops(v)))
+}
+
+fn nexthop_ip(nh: &BgpAddr) -> Option<std::net::IpAddr> {
+    match nh {
+        BgpAddr::V4(a) => Some(std::net::IpAddr::V4(*a)),
+        BgpAddr::V6(a) => Some(std::net::IpAddr::V6(*a)),
+        BgpAddr::V4RD(a) => Some(std::net::IpAddr::V4(a.addr)),
+        BgpAddr::V6RD(a) => Some(std::net::IpAddr::V6(a.addr)),
+        _ => None,
+    }
+}
+
+impl BgpRIB {
+    /// looks up route in specified RIB, returns route key and active next hops
+    pub fn route_nexthops(
+        &self,
+        ribname: &str,
+        filter: &RouteFilter,
+    ) -> Option<(String, Vec<BgpAddr>)> {
+        match ribname {
+            "ipv4u" => find_covering(&self.ipv4u, filter),
+            "ipv4m" => find_covering(&self.ipv4m, filter),
+            "ipv4lu" => find_covering(&self.ipv4lu, filter),
+            "vpnv4u" => find_covering(&self.vpnv4u, filter),
+            "vpnv4m" => find_covering(&self.vpnv4m, filter),
+            "ipv6u" => find_covering(&self.ipv6u, filter),
+            "ipv6m" => find_covering(&self.ipv6m, filter),
+            "ipv6lu" => find_covering(&self.ipv6lu, filter),
+            "vpnv6u" => find_covering(&self.vpnv6u, filter),
+            "vpnv6m" => find_covering(&self.vpnv6m, filter),
+            _ => None,
+        }
+    }
+    /// recursive next hop lookup in unicast RIBs
+    pub fn resolve_nexthop(&self, nh: &BgpAddr) -> NexthopResolution {
+        resolve(nh, |ribname, flt| self.route_nexthops(ribname, flt))
+    }
+}
+
+/// recursive next hop lookup, lookup gives covering route and its next hops in named RIB
+fn resolve<F>(nh: &BgpAddr, lookup: F) -> NexthopResolution
+where
+    F: Fn(&str, &RouteFilter) -> Option<(String, Vec<BgpAddr>)>,
+{
+    let mut ret = NexthopResolution {
+        nexthop: nh.to_string(),
+        resolved: false,
+        looped: false,
+        chain: Vec::new(),
+    };
+    let mut seen: BTreeSet<String> = BTreeSet::new();
+    let mut current = nh.clone();
+    while ret.chain.len() < NEXTHOP_MAX_DEPTH {
+        let ip = match nexthop_ip(&current) {
+            None => break,
+            Some(ip) => ip,
+        };
+        let ribname = if ip.is_ipv4() { "ipv4u" } else { "ipv6u" };
+        let flt = RouteFilter::fromstr(ip.to_string().as_str());
+        let covering = lookup(ribname, &flt);
+        let mut step = NexthopStep {
+            nexthop: current.to_string(),
+            rib: ribname.to_string(),
+            covering: None,
+            nexthops: Vec::new(),
+        };
+        let (pfx, nhs) = match covering {
+            None => {
+                ret.chain.push(step);
+                break;
+            }
+            Some(c) => c,
+        };
+        ret.resolved = true;
+        step.covering = Some(pfx.clone());
+        step.nexthops = nhs.iter().map(|x| x.to_string()).collect();
+        ret.chain.push(step);
+        if !seen.insert(pfx) {
+            ret.looped = true;
+            break;
+        }
+        // next hop is local or points to itself - recursion done
+        match nhs.first() {
+            None => break,
+            Some(n) => {
+                if *n == current || nexthop_ip(n).map(|x| x.is_unspecified()).unwrap_or(true) {
+                    break;
+                }
+                current = n.clone();
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgpattrs::BgpAttrs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_nexthop_inventory() {
+        let mut inv = NexthopInventory::default();
+        let nh1 = BgpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+        let nh2 = BgpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+        let mut attrs = BgpAttrs::new();
+        attrs.nexthop = nh1.clone();
+        let rec = BgpAttrEntry::new(true, Arc::new(attrs), None);
+        inv.track(None, Some(&nh1));
+        inv.track(None, Some(&nh1));
+        // route moved to another next hop
+        inv.track(Some(&rec), Some(&nh2));
+        let mut out = Vec::new();
+        inv.report("ipv4u", &mut out);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].routes, 1);
+        // withdraw
+        inv.track(Some(&rec), None);
+        assert_eq!(inv.len(), 1);
+        let withdrawn = BgpAttrEntry::new(false, rec.attrs.clone(), None);
+        inv.track(Some(&withdrawn), None);
+        assert_eq!(inv.len(), 1);
+    }
+
+    #[test]
+    fn test_resolve() {
+        use crate::config::HistoryChangeMode;
+        use std::net::Ipv4Addr;
+        let mut safi = BgpRIBSafi::<BgpAddrV4>::new(10, HistoryChangeMode::EveryUpdate);
+        let mut add = |net: [u8; 4], len: u8, nh: [u8; 4]| {
+            let mut attrs = BgpAttrs::new();
+            attrs.nexthop = BgpAddr::V4(Ipv4Addr::from(nh));
+            safi.handle_updates_afi(
+                1,
+                &[BgpAddrV4::new(Ipv4Addr::from(net), len)],
+                Arc::new(attrs),
+            );
+        };
+        add([192, 0, 2, 0], 24, [198, 51, 100, 1]);
+        // connected
+        add([198, 51, 100, 0], 24, [0, 0, 0, 0]);
+        add([203, 0, 113, 0], 24, [203, 0, 113, 5]);
+        let lookup = |rib: &str, flt: &RouteFilter| {
+            assert_eq!(rib, "ipv4u");
+            find_covering(&safi, flt)
+        };
+        let nh = |a: [u8; 4]| BgpAddr::V4(Ipv4Addr::from(a));
+
+        let r = resolve(&nh([192, 0, 2, 1]), lookup);
+        assert!(r.resolved && !r.looped);
+        let covering: Vec<_> = r.chain.iter().map(|s| s.covering.as_deref()).collect();
+        assert_eq!(
+            covering,
+            vec![Some("192.0.2.0/24"), Some("198.51.100.0/24")]
+        );
+        assert_eq!(r.chain[0].nexthops, vec!["198.51.100.1".to_string()]);
+
+        let r = resolve(&nh([203, 0, 113, 7]), lookup);
+        assert!(r.resolved && r.looped);
+        assert_eq!(r.chain.len(), 2);
+
+        let r = resolve(&nh([100, 64, 0, 1]), lookup);
+        assert!(!r.resolved);
+        assert_eq!(r.chain.len(), 1);
+        assert_eq!(r.chain[0].covering, None);
+    }
+}
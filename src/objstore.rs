@@ -0,0 +1,470 @@
+//! uploads of snapshots and reports to S3-compatible object storage, signing needs s3 feature
+use crate::tls::BoxStream;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpStream;
+
+/// largest object store response body read, list pages are well below it
+const MAX_RESPONSE: usize = 16 * 1024 * 1024;
+
+/// bucket and credentials
+#[derive(Debug, Clone)]
+pub struct ObjectStoreSettings {
+    /// http://host:port or https://host, path-style requests are used
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// key prefix of uploaded objects
+    pub prefix: String,
+    /// objects older than this are deleted, None - kept forever
+    pub retention: Option<chrono::Duration>,
+    pub sync_every: Duration,
+}
+
+fn other_err<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// whether endpoint is https and its host[:port]; checked at config load too
+pub fn parse_endpoint(endpoint: &str) -> Result<(bool, String), String> {
+    let (tls, rest) = if let Some(r) = endpoint.strip_prefix("https://") {
+        (true, r)
+    } else if let Some(r) = endpoint.strip_prefix("http://") {
+        (false, r)
+    } else {
+        return Err("object store endpoint should be http:// or https:// URL".to_string());
+    };
+    let host = rest.trim_end_matches('/');
+    if host.is_empty() || host.contains('/') {
+        return Err("object store endpoint should have host and no path".to_string());
+    }
+    Ok((tls, host.to_string()))
+}
+
+/// object key of uploaded file version: prefix, file name and modification time
+fn object_key(prefix: &str, name: &str, modified: &chrono::DateTime<chrono::Utc>) -> String {
+    format!("{}{}.{}", prefix, name, modified.format("%Y%m%d%H%M%S"))
+}
+
+/// whether object was uploaded by sync: key made by object_key of one of file names
+/// or of differential snapshot of snapshot_name, other objects under prefix are kept
+pub fn is_own_object(prefix: &str, key: &str, names: &[String], snapshot: Option<&str>) -> bool {
+    let rest = match key.strip_prefix(prefix) {
+        Some(r) => r,
+        None => return false,
+    };
+    let name = match rest.rsplit_once('.') {
+        Some((n, stamp)) if stamp.len() == 14 && stamp.bytes().all(|b| b.is_ascii_digit()) => n,
+        _ => return false,
+    };
+    if names.iter().any(|n| n == name) {
+        return true;
+    }
+    match (snapshot, name.rsplit_once(".diff.")) {
+        (Some(s), Some((base, seq))) => {
+            base == s && !seq.is_empty() && seq.bytes().all(|b| b.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+/// RFC 3986 percent-encoding used by SigV4, slashes are kept in paths
+pub fn uri_encode(s: &str, path: bool) -> String {
+    let mut ret = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                ret.push(b as char)
+            }
+            b'/' if path => ret.push('/'),
+            _ => ret.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    ret
+}
+
+#[cfg(feature = "s3")]
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::Digest;
+    hex(&sha2::Sha256::digest(data))
+}
+
+#[cfg(feature = "s3")]
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    use hmac::Mac;
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(key).expect("any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(feature = "s3")]
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// object listed by ListObjectsV2
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub modified: chrono::DateTime<chrono::Utc>,
+}
+
+/// objects and continuation token of ListObjectsV2 response
+pub fn parse_list(xml: &str) -> (Vec<ObjectInfo>, Option<String>) {
+    lazy_static! {
+        static ref RE_CONTENTS: Regex = Regex::new(r"(?s)<Contents>(.*?)</Contents>").unwrap();
+        static ref RE_KEY: Regex = Regex::new(r"<Key>(.*?)</Key>").unwrap();
+        static ref RE_MODIFIED: Regex = Regex::new(r"<LastModified>(.*?)</LastModified>").unwrap();
+        static ref RE_NEXT: Regex =
+            Regex::new(r"<NextContinuationToken>(.*?)</NextContinuationToken>").unwrap();
+    }
+    let objs = RE_CONTENTS
+        .captures_iter(xml)
+        .filter_map(|c| {
+            let body = c.get(1)?.as_str();
+            let key = RE_KEY.captures(body)?.get(1)?.as_str();
+            let modified = RE_MODIFIED.captures(body)?.get(1)?.as_str();
+            Some(ObjectInfo {
+                key: html_escape::decode_html_entities(key).to_string(),
+                modified: chrono::DateTime::parse_from_rfc3339(modified)
+                    .ok()?
+                    .with_timezone(&chrono::Utc),
+            })
+        })
+        .collect();
+    let next = RE_NEXT
+        .captures(xml)
+        .and_then(|c| c.get(1))
+        .map(|x| html_escape::decode_html_entities(x.as_str()).to_string());
+    (objs, next)
+}
+
+pub struct ObjectStore {
+    settings: ObjectStoreSettings,
+    tls: bool,
+    /// host[:port] for connection and Host header
+    host: String,
+}
+impl ObjectStore {
+    pub fn new(settings: ObjectStoreSettings) -> io::Result<ObjectStore> {
+        let (tls, host) = parse_endpoint(&settings.endpoint).map_err(other_err)?;
+        Ok(ObjectStore {
+            settings,
+            tls,
+            host,
+        })
+    }
+    #[cfg(feature = "s3")]
+    fn authorization(
+        &self,
+        method: &str,
+        path: &str,
+        query: &str,
+        payload_hash: &str,
+        amz_date: &str,
+    ) -> io::Result<String> {
+        let date = &amz_date[0..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.settings.region);
+        let canonical = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, path, query, self.host, payload_hash, amz_date, payload_hash
+        );
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical.as_bytes())
+        );
+        let mut key = hmac_sha256(
+            format!("AWS4{}", self.settings.secret_key).as_bytes(),
+            date.as_bytes(),
+        );
+        for part in [self.settings.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        Ok(format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.settings.access_key,
+            scope,
+            hex(&hmac_sha256(&key, to_sign.as_bytes()))
+        ))
+    }
+    #[cfg(not(feature = "s3"))]
+    fn authorization(
+        &self,
+        _method: &str,
+        _path: &str,
+        _query: &str,
+        _payload_hash: &str,
+        _amz_date: &str,
+    ) -> io::Result<String> {
+        Err(other_err("built without s3 feature"))
+    }
+    #[cfg(feature = "s3")]
+    fn payload_hash(body: &[u8]) -> String {
+        sha256_hex(body)
+    }
+    #[cfg(not(feature = "s3"))]
+    fn payload_hash(_body: &[u8]) -> String {
+        String::new()
+    }
+    /// signed request over new connection, returns status and body
+    async fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &str,
+        body: &[u8],
+    ) -> io::Result<(u16, Vec<u8>)> {
+        let path = format!(
+            "/{}/{}",
+            uri_encode(&self.settings.bucket, false),
+            uri_encode(key, true)
+        );
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = Self::payload_hash(body);
+        let auth = self.authorization(method, &path, query, &payload_hash, &amz_date)?;
+        let addr = if self.host.contains(':') {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, if self.tls { 443 } else { 80 })
+        };
+        let mut stream: BoxStream = Box::new(TcpStream::connect(addr.as_str()).await?);
+        if self.tls {
+            stream = crate::tls::connect(crate::tls::host_of(&addr), stream).await?;
+        }
+        let (mut sender, conn) = hyper::client::conn::handshake(stream)
+            .await
+            .map_err(other_err)?;
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                debug!("Object store connection: {}", e);
+            }
+        });
+        let uri = if query.is_empty() {
+            path
+        } else {
+            format!("{}?{}", path, query)
+        };
+        let req = hyper::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(hyper::header::HOST, self.host.as_str())
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header(hyper::header::AUTHORIZATION, auth)
+            .header(hyper::header::CONTENT_LENGTH, body.len())
+            .body(hyper::Body::from(body.to_vec()))
+            .map_err(other_err)?;
+        let rsp = sender.send_request(req).await.map_err(other_err)?;
+        let status = rsp.status().as_u16();
+        let body = crate::service::read_body(rsp.into_body(), MAX_RESPONSE)
+            .await
+            .map_err(other_err)?;
+        Ok((status, body))
+    }
+    pub async fn put(&self, key: &str, body: &[u8]) -> io::Result<()> {
+        match self.request("PUT", key, "", body).await? {
+            (200..=299, _) => Ok(()),
+            (code, b) => Err(other_err(format!(
+                "PUT {} - {} {}",
+                key,
+                code,
+                String::from_utf8_lossy(&b)
+            ))),
+        }
+    }
+    pub async fn delete(&self, key: &str) -> io::Result<()> {
+        match self.request("DELETE", key, "", &[]).await? {
+            (200..=299, _) => Ok(()),
+            (code, _) => Err(other_err(format!("DELETE {} - {}", key, code))),
+        }
+    }
+    /// all objects under prefix
+    pub async fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        let mut ret = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            // canonical query has parameters sorted by name
+            let query = match token {
+                Some(ref t) => format!(
+                    "continuation-token={}&list-type=2&prefix={}",
+                    uri_encode(t, false),
+                    uri_encode(prefix, false)
+                ),
+                None => format!("list-type=2&prefix={}", uri_encode(prefix, false)),
+            };
+            let (code, body) = self.request("GET", "", query.as_str(), &[]).await?;
+            if code != 200 {
+                return Err(other_err(format!("list {} - {}", prefix, code)));
+            }
+            let (objs, next) = parse_list(String::from_utf8_lossy(&body).as_ref());
+            ret.extend(objs);
+            match next {
+                Some(t) => token = Some(t),
+                None => return Ok(ret),
+            }
+        }
+    }
+}
+
+/// uploaded report file of report::store_report naming, report-YYYYmmdd-HHMM.json/html
+fn is_report_object(prefix: &str, key: &str) -> bool {
+    lazy_static! {
+        static ref RE_REPORT: Regex =
+            Regex::new(r"^report-[0-9]{8}-[0-9]{4}\.(json|html)\.[0-9]{14}$").unwrap();
+    }
+    key.strip_prefix(prefix)
+        .map(|r| RE_REPORT.is_match(r))
+        .unwrap_or(false)
+}
+
+/// snapshot file, its differential snapshots and report files
+fn candidates(cfg: &crate::config::SvcConfig) -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+    if let Some(ref snap) = cfg.snapshot_file {
+        let snap = Path::new(snap);
+        ret.push(snap.to_path_buf());
+        let dir = match snap.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let diffpfx = format!(
+            "{}.diff.",
+            snap.file_name().unwrap_or_default().to_string_lossy()
+        );
+        if let Ok(rd) = std::fs::read_dir(&dir) {
+            for e in rd.filter_map(|e| e.ok()) {
+                let name = e.file_name().to_string_lossy().to_string();
+                if name.starts_with(&diffpfx) && !name.ends_with(".tmp") {
+                    ret.push(e.path());
+                }
+            }
+        }
+    }
+    if let Some(ref dir) = cfg.report_dir {
+        if let Ok(rd) = std::fs::read_dir(dir) {
+            ret.extend(rd.filter_map(|e| e.ok()).map(|e| e.path()));
+        }
+    }
+    ret
+}
+
+/// uploads new and changed files, then removes objects past retention
+async fn sync_once(
+    store: &ObjectStore,
+    cfg: &crate::config::SvcConfig,
+    uploaded: &mut BTreeMap<PathBuf, SystemTime>,
+) -> io::Result<()> {
+    for path in candidates(cfg) {
+        let modified = match std::fs::metadata(&path).and_then(|m| m.modified()) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        if uploaded.get(&path) == Some(&modified) {
+            continue;
+        }
+        let data = tokio::fs::read(&path).await?;
+        let key = object_key(
+            &store.settings.prefix,
+            &path.file_name().unwrap_or_default().to_string_lossy(),
+            &modified.into(),
+        );
+        store.put(&key, &data).await?;
+        debug!("Uploaded {} as {}", path.display(), key);
+        uploaded.insert(path, modified);
+    }
+    if let Some(retention) = store.settings.retention {
+        let cutoff = chrono::Utc::now() - retention;
+        let snapshot = cfg.snapshot_file.as_ref().and_then(|s| {
+            Path::new(s)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+        });
+        // report files may be gone locally, their names are still recognized by pattern
+        let names: Vec<String> = candidates(cfg)
+            .iter()
+            .filter_map(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+            .collect();
+        for obj in store.list(&store.settings.prefix).await? {
+            let own = is_own_object(
+                &store.settings.prefix,
+                &obj.key,
+                &names,
+                snapshot.as_deref(),
+            ) || is_report_object(&store.settings.prefix, &obj.key);
+            if own && obj.modified < cutoff {
+                store.delete(&obj.key).await?;
+                debug!("Removed expired object {}", obj.key);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// periodic upload task
+pub async fn run(cfg: Arc<crate::config::SvcConfig>) {
+    let settings = match cfg.objstore {
+        Some(ref s) => s.clone(),
+        None => return,
+    };
+    let store = match ObjectStore::new(settings) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Object store: {}", e);
+            return;
+        }
+    };
+    let mut uploaded = BTreeMap::new();
+    loop {
+        if let Err(e) = sync_once(&store, &cfg, &mut uploaded).await {
+            warn!("Object store sync error: {}", e);
+        }
+        tokio::time::sleep(store.settings.sync_every).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_objstore_helpers() {
+        assert_eq!(uri_encode("snap/rib.bgp 1", true), "snap/rib.bgp%201");
+        assert_eq!(uri_encode("a/b", false), "a%2Fb");
+        let (objs, next) = parse_list(
+            "<ListBucketResult><Contents><Key>bgpexplorer/rib.20240101</Key><LastModified>2024-01-01T00:00:00.000Z</LastModified></Contents><IsTruncated>true</IsTruncated><NextContinuationToken>abc</NextContinuationToken></ListBucketResult>",
+        );
+        assert_eq!(objs.len(), 1);
+        assert_eq!(objs[0].key, "bgpexplorer/rib.20240101");
+        assert_eq!(next, Some("abc".to_string()));
+        assert_eq!(
+            parse_endpoint("https://s3.example.com/"),
+            Ok((true, "s3.example.com".to_string()))
+        );
+        assert!(parse_endpoint("s3.example.com").is_err());
+        assert!(parse_endpoint("http://host/bucket").is_err());
+    }
+
+    #[test]
+    fn test_objstore_own_objects() {
+        let names = vec!["rib.bgp".to_string()];
+        let own = |k: &str| {
+            is_own_object("bgpexplorer/", k, &names, Some("rib.bgp"))
+                || is_report_object("bgpexplorer/", k)
+        };
+        assert!(own("bgpexplorer/rib.bgp.20240101000000"));
+        assert!(own("bgpexplorer/rib.bgp.diff.000003.20240101000000"));
+        assert!(own("bgpexplorer/report-20240101-0000.html.20240102000000"));
+        assert!(!own("bgpexplorer/rib.bgp"));
+        assert!(!own("bgpexplorer/backup.tar.20240101000000"));
+        assert!(!own("bgpexplorer/sub/rib.bgp.20240101000000"));
+        assert!(!own("other/rib.bgp.20240101000000"));
+    }
+}
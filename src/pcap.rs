@@ -0,0 +1,466 @@
+//! offline ingestion of BGP sessions captured in pcap files
+use crate::bgpsvc::*;
+use crate::config::ProtoPeer;
+use crate::replay::{decode_or_withdraw, inject_update, peer_desc};
+use crate::timestamp::Timestamp;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, BufReader};
+use zettabgp::prelude::*;
+
+const BGP_PORT: u16 = 179;
+/// out-of-order data kept per direction while waiting for gap to be filled
+const MAX_PENDING: usize = 4 * 1024 * 1024;
+/// largest captured frame accepted, libpcap maximum snapshot length
+const MAX_CAPLEN: usize = 262144;
+/// connections reassembled at once, segments of further connections are skipped
+const MAX_FLOWS: usize = 65536;
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+/// TCP segment extracted from captured frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct TcpSegment {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+fn be16(b: &[u8], pos: usize) -> Option<u16> {
+    let s = b.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([s[0], s[1]]))
+}
+
+fn be32(b: &[u8], pos: usize) -> Option<u32> {
+    let s = b.get(pos..pos + 4)?;
+    Some(u32::from_be_bytes([s[0], s[1], s[2], s[3]]))
+}
+
+fn parse_tcp(src: IpAddr, dst: IpAddr, b: &[u8]) -> Option<TcpSegment> {
+    let doff = ((*b.get(12)? >> 4) as usize) * 4;
+    if doff < 20 {
+        return None;
+    }
+    Some(TcpSegment {
+        src: SocketAddr::new(src, be16(b, 0)?),
+        dst: SocketAddr::new(dst, be16(b, 2)?),
+        seq: be32(b, 4)?,
+        flags: *b.get(13)?,
+        payload: b.get(doff..)?.to_vec(),
+    })
+}
+
+fn parse_ip(b: &[u8]) -> Option<TcpSegment> {
+    match *b.first()? >> 4 {
+        4 => {
+            let ihl = ((b[0] & 0x0f) as usize) * 4;
+            let total = be16(b, 2)? as usize;
+            // fragments are not reassembled
+            if *b.get(9)? != 6 || be16(b, 6)? & 0x3fff != 0 || total < ihl {
+                return None;
+            }
+            let s = b.get(12..16)?;
+            let d = b.get(16..20)?;
+            parse_tcp(
+                IpAddr::V4(Ipv4Addr::new(s[0], s[1], s[2], s[3])),
+                IpAddr::V4(Ipv4Addr::new(d[0], d[1], d[2], d[3])),
+                b.get(ihl..total.min(b.len()))?,
+            )
+        }
+        6 => {
+            let plen = be16(b, 4)? as usize;
+            let mut next = *b.get(6)?;
+            let mut sa = [0u8; 16];
+            sa.copy_from_slice(b.get(8..24)?);
+            let mut da = [0u8; 16];
+            da.copy_from_slice(b.get(24..40)?);
+            let mut pos = 40;
+            let end = (40 + plen).min(b.len());
+            // skip hop-by-hop, routing and destination options headers
+            while next == 0 || next == 43 || next == 60 {
+                next = *b.get(pos)?;
+                pos += (*b.get(pos + 1)? as usize + 1) * 8;
+            }
+            if next != 6 || pos > end {
+                return None;
+            }
+            parse_tcp(
+                IpAddr::V6(Ipv6Addr::from(sa)),
+                IpAddr::V6(Ipv6Addr::from(da)),
+                &b[pos..end],
+            )
+        }
+        _ => None,
+    }
+}
+
+/// extracts TCP segment from frame of specified pcap link type
+pub fn parse_frame(linktype: u32, b: &[u8]) -> Option<TcpSegment> {
+    let ip = match linktype {
+        // ethernet, with optional VLAN tags
+        1 => {
+            let mut pos = 12;
+            let mut etype = be16(b, pos)?;
+            while etype == 0x8100 || etype == 0x88a8 {
+                pos += 4;
+                etype = be16(b, pos)?;
+            }
+            if etype != 0x0800 && etype != 0x86dd {
+                return None;
+            }
+            b.get(pos + 2..)?
+        }
+        // BSD loopback
+        0 => b.get(4..)?,
+        // raw IP
+        12 | 101 => b,
+        // Linux cooked capture
+        113 => b.get(16..)?,
+        276 => b.get(20..)?,
+        _ => return None,
+    };
+    parse_ip(ip)
+}
+
+/// reassembled data of one TCP direction
+#[derive(Default)]
+struct TcpFlow {
+    next_seq: Option<u32>,
+    pending: BTreeMap<u32, Vec<u8>>,
+    pending_size: usize,
+    buf: Vec<u8>,
+}
+impl TcpFlow {
+    fn push(&mut self, seq: u32, mut data: Vec<u8>) {
+        let next = *self.next_seq.get_or_insert(seq);
+        let offset = seq.wrapping_sub(next) as i32;
+        if offset > 0 {
+            if self.pending_size + data.len() <= MAX_PENDING {
+                self.pending_size += data.len();
+                self.pending.insert(seq, data);
+            } else {
+                warn!("Pcap: too much out of order data, gap skipped");
+                self.pending.clear();
+                self.pending_size = 0;
+                self.next_seq = None;
+                self.buf.clear();
+            }
+            return;
+        }
+        // distance back from next expected byte, i32::MIN has no positive counterpart
+        let skip = next.wrapping_sub(seq) as usize;
+        if skip >= data.len() {
+            return;
+        }
+        data.drain(0..skip);
+        self.next_seq = Some(next.wrapping_add(data.len() as u32));
+        self.buf.extend_from_slice(&data);
+        // pending segments that became contiguous
+        loop {
+            let next = self.next_seq.unwrap();
+            let seq = match self
+                .pending
+                .keys()
+                .find(|s| s.wrapping_sub(next) as i32 <= 0)
+            {
+                Some(s) => *s,
+                None => break,
+            };
+            let d = self.pending.remove(&seq).unwrap();
+            self.pending_size -= d.len();
+            self.push(seq, d);
+        }
+    }
+    /// complete BGP messages from reassembled data
+    fn messages(&mut self) -> Vec<Vec<u8>> {
+        let mut ret = Vec::new();
+        loop {
+            if self.buf.len() < 19 {
+                break;
+            }
+            if self.buf[0..16].iter().any(|x| *x != 255) {
+                // lost sync, look for next marker
+                match self
+                    .buf
+                    .windows(16)
+                    .position(|w| w.iter().all(|x| *x == 255))
+                {
+                    Some(p) => {
+                        self.buf.drain(0..p);
+                        continue;
+                    }
+                    None => {
+                        let keep = self.buf.len() - 15;
+                        self.buf.drain(0..keep);
+                        break;
+                    }
+                }
+            }
+            let len = u16::from_be_bytes([self.buf[16], self.buf[17]]) as usize;
+            if len < 19 {
+                self.buf.drain(0..16);
+                continue;
+            }
+            if self.buf.len() < len {
+                break;
+            }
+            ret.push(self.buf.drain(0..len).collect());
+        }
+        ret
+    }
+}
+
+/// TCP reassembly for all captured BGP connections
+#[derive(Default)]
+pub struct PcapStreams {
+    flows: HashMap<(SocketAddr, SocketAddr), TcpFlow>,
+    /// segments dropped because flow table was full
+    pub skipped: u64,
+}
+impl PcapStreams {
+    /// feeds segment, returns complete BGP messages sent by segment source
+    pub fn feed(&mut self, seg: TcpSegment) -> Vec<Vec<u8>> {
+        if seg.src.port() != BGP_PORT && seg.dst.port() != BGP_PORT {
+            return Vec::new();
+        }
+        let key = (seg.src, seg.dst);
+        if self.flows.len() >= MAX_FLOWS && !self.flows.contains_key(&key) {
+            if self.skipped == 0 {
+                warn!(
+                    "Pcap: more than {} connections, skipping new ones",
+                    MAX_FLOWS
+                );
+            }
+            self.skipped += 1;
+            return Vec::new();
+        }
+        if seg.flags & TCP_SYN != 0 {
+            self.flows.insert(
+                key,
+                TcpFlow {
+                    next_seq: Some(seg.seq.wrapping_add(1)),
+                    ..Default::default()
+                },
+            );
+            return Vec::new();
+        }
+        let flow = self.flows.entry(key).or_default();
+        if !seg.payload.is_empty() {
+            flow.push(seg.seq, seg.payload);
+        }
+        let ret = flow.messages();
+        if seg.flags & (TCP_FIN | TCP_RST) != 0 {
+            self.flows.remove(&key);
+        }
+        ret
+    }
+}
+
+fn params_for(
+    sender: Option<&BgpOpenMessage>,
+    receiver: Option<&BgpOpenMessage>,
+) -> BgpSessionParams {
+    // without OPEN assume 4-byte AS numbers and no ADD-PATH
+    let defcaps: Vec<BgpCapability> = ProtoPeer::all_caps(0)
+        .into_iter()
+        .filter(|c| !matches!(c, BgpCapability::CapAddPath(_)))
+        .collect();
+    let mut params = BgpSessionParams::new(
+        receiver.map(|o| o.as_num).unwrap_or(0),
+        180,
+        BgpTransportMode::IPv4,
+        Ipv4Addr::new(0, 0, 0, 0),
+        receiver
+            .map(|o| o.caps.clone())
+            .unwrap_or_else(|| defcaps.clone()),
+    );
+    params.match_caps(sender.map(|o| &o.caps).unwrap_or(&defcaps));
+    params
+}
+
+/// reads pcap file and feeds captured BGP updates into RIB
+pub async fn run_pcap(svr: Arc<BgpSvr>, path: String, original_time: bool) -> io::Result<()> {
+    let mut rd = BufReader::new(tokio::fs::File::open(path.as_str()).await?);
+    let mut ghdr = [0u8; 24];
+    rd.read_exact(&mut ghdr).await?;
+    let (le, nanos) = match ghdr[0..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (true, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (false, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (true, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (false, true),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a pcap file (pcapng is not supported)",
+            ))
+        }
+    };
+    let rd32 = |b: &[u8]| {
+        let a = [b[0], b[1], b[2], b[3]];
+        if le {
+            u32::from_le_bytes(a)
+        } else {
+            u32::from_be_bytes(a)
+        }
+    };
+    let linktype = rd32(&ghdr[20..24]);
+    let mut streams = PcapStreams::default();
+    let mut opens: HashMap<(SocketAddr, SocketAddr), BgpOpenMessage> = HashMap::new();
+    let mut sessions: HashMap<(SocketAddr, SocketAddr), BgpSessionId> = HashMap::new();
+    let (mut packets, mut updates) = (0u64, 0u64);
+    info!("Reading pcap {}", path);
+    loop {
+        let mut phdr = [0u8; 16];
+        match rd.read_exact(&mut phdr).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let secs = rd32(&phdr[0..4]);
+        let frac = rd32(&phdr[4..8]);
+        let caplen = rd32(&phdr[8..12]) as usize;
+        if caplen > MAX_CAPLEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("pcap packet {} length {} is too large", packets + 1, caplen),
+            ));
+        }
+        let mut frame = vec![0u8; caplen];
+        rd.read_exact(&mut frame).await?;
+        packets += 1;
+        let seg = match parse_frame(linktype, &frame) {
+            Some(s) => s,
+            None => continue,
+        };
+        let key = (seg.src, seg.dst);
+        let ts = Timestamp::from_unix(secs as i64, if nanos { frac / 1000 } else { frac });
+        for msg in streams.feed(seg) {
+            let params = params_for(opens.get(&key), opens.get(&(key.1, key.0)));
+            let (mtype, mlen) = match params.decode_message_head(&msg[0..19]) {
+                Ok(h) => h,
+                Err(e) => {
+                    warn!("Pcap packet {}: {:?}", packets, e);
+                    continue;
+                }
+            };
+            let body = &msg[19..(19 + mlen).min(msg.len())];
+            match mtype {
+                BgpMessageType::Open => {
+                    match crate::wireparse::decode_open(&params, body) {
+                        Ok(bom) => {
+                            opens.insert(key, bom);
+                            // new OPEN starts new session
+                            sessions.remove(&key);
+                        }
+                        Err(e) => warn!("Pcap packet {} OPEN: {}", packets, e),
+                    }
+                }
+                BgpMessageType::Update => {
+                    let upds = match decode_or_withdraw(&svr, &params, body) {
+                        Ok(u) => u,
+                        Err(e) => {
+                            warn!("Pcap packet {} UPDATE: {}", packets, e);
+                            continue;
+                        }
+                    };
+                    let sid = match sessions.get(&key) {
+                        Some(s) => *s,
+                        None => {
+                            let sender_as = opens.get(&key).map(|o| o.as_num).unwrap_or(0);
+                            let receiver_as =
+                                opens.get(&(key.1, key.0)).map(|o| o.as_num).unwrap_or(0);
+                            // unspecified local address keeps both directions apart
+                            let sid = svr
+                                .register_session(Arc::new(BgpSessionDesc::new(
+                                    peer_desc(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), receiver_as),
+                                    peer_desc(key.0.ip(), sender_as),
+                                )))
+                                .await;
+                            sessions.insert(key, sid);
+                            sid
+                        }
+                    };
+                    for upd in upds {
+                        inject_update(&svr, sid, if original_time { Some(ts) } else { None }, upd)
+                            .await;
+                    }
+                    updates += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+    info!(
+        "Pcap {} done: {} packets, {} updates, {} segments skipped",
+        path, packets, updates, streams.skipped
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keepalive() -> Vec<u8> {
+        let mut m = vec![255u8; 16];
+        m.extend_from_slice(&[0, 19, 4]);
+        m
+    }
+
+    #[test]
+    fn test_pcap_frame() {
+        let mut f = vec![0u8; 12];
+        f.extend_from_slice(&[0x08, 0x00]);
+        let mut ip = vec![
+            0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+        ];
+        let tcp = [
+            0, 179, 0x30, 0x39, 0, 0, 0, 100, 0, 0, 0, 0, 0x50, 0x18, 0, 0, 0, 0, 0, 0,
+        ];
+        let total = (ip.len() + tcp.len() + 19) as u16;
+        ip[2..4].copy_from_slice(&total.to_be_bytes());
+        f.extend_from_slice(&ip);
+        f.extend_from_slice(&tcp);
+        f.extend_from_slice(&keepalive());
+        let seg = parse_frame(1, &f).unwrap();
+        assert_eq!(seg.src, "10.0.0.1:179".parse().unwrap());
+        assert_eq!(seg.dst, "10.0.0.2:12345".parse().unwrap());
+        assert_eq!(seg.seq, 100);
+        assert_eq!(seg.payload, keepalive());
+    }
+
+    #[test]
+    fn test_pcap_reassembly() {
+        let mut streams = PcapStreams::default();
+        let seg = |seq: u32, flags: u8, payload: &[u8]| TcpSegment {
+            src: "10.0.0.1:179".parse().unwrap(),
+            dst: "10.0.0.2:12345".parse().unwrap(),
+            seq,
+            flags,
+            payload: payload.to_vec(),
+        };
+        let mut data = keepalive();
+        data.extend_from_slice(&keepalive());
+        assert!(streams.feed(seg(999, TCP_SYN, &[])).is_empty());
+        // second part arrives first
+        assert!(streams.feed(seg(1010, 0, &data[10..])).is_empty());
+        let msgs = streams.feed(seg(1000, 0, &data[0..10]));
+        assert_eq!(msgs, vec![keepalive(), keepalive()]);
+        // retransmission is ignored
+        assert!(streams.feed(seg(1000, 0, &data[0..10])).is_empty());
+        // segment exactly 2^31 behind is old data, not an overflow
+        assert!(streams
+            .feed(seg(
+                1000u32.wrapping_add(38).wrapping_sub(1 << 31),
+                0,
+                &data
+            ))
+            .is_empty());
+        assert_eq!(streams.feed(seg(1038, 0, &keepalive())), vec![keepalive()]);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::bgprib::BgpRibKind;
+use crate::timestamp::Timestamp;
+use serde::ser::SerializeStruct;
+use std::collections::BTreeMap;
+
+/// Change of active routes made by update or withdraw handling
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BgpRibDelta {
+    /// routes became active minus routes became inactive
+    pub active: i64,
+    /// AS path length change over active routes
+    pub pathlen: i64,
+}
+impl BgpRibDelta {
+    /// account transition of one route from previous state to the next one
+    pub fn track(&mut self, prev: Option<(bool, usize)>, next: Option<usize>) {
+        if let Some((true, plen)) = prev {
+            self.active -= 1;
+            self.pathlen -= plen as i64;
+        }
+        if let Some(plen) = next {
+            self.active += 1;
+            self.pathlen += plen as i64;
+        }
+    }
+    pub fn merge(&mut self, other: &BgpRibDelta) {
+        self.active += other.active;
+        self.pathlen += other.pathlen;
+    }
+}
+
+/// Per RIB counters for one peer
+#[derive(Debug, Default, Clone)]
+pub struct BgpPeerRibStats {
+    pub prefixes: u64,
+    pub updates: u64,
+    pub withdraws: u64,
+    pathlen_sum: u64,
+}
+impl BgpPeerRibStats {
+    fn apply(&mut self, delta: &BgpRibDelta) {
+        self.prefixes = (self.prefixes as i64 + delta.active).max(0) as u64;
+        self.pathlen_sum = (self.pathlen_sum as i64 + delta.pathlen).max(0) as u64;
+    }
+    pub fn avg_path_len(&self) -> f64 {
+        if self.prefixes > 0 {
+            self.pathlen_sum as f64 / self.prefixes as f64
+        } else {
+            0.0
+        }
+    }
+}
+impl serde::Serialize for BgpPeerRibStats {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("BgpPeerRibStats", 4)?;
+        state.serialize_field("prefixes", &self.prefixes)?;
+        state.serialize_field("updates", &self.updates)?;
+        state.serialize_field("withdraws", &self.withdraws)?;
+        state.serialize_field("avg_path_len", &self.avg_path_len())?;
+        state.end()
+    }
+}
+
+/// Counters for one peer (BGP session)
+#[derive(Debug, Default, Clone)]
+pub struct BgpPeerStats {
+    pub last_update: Option<Timestamp>,
+    pub ribs: BTreeMap<String, BgpPeerRibStats>,
+}
+impl BgpPeerStats {
+    fn rib_mut(&mut self, kind: BgpRibKind) -> &mut BgpPeerRibStats {
+        self.ribs.entry(kind.to_string()).or_default()
+    }
+    pub fn on_updates(&mut self, kind: BgpRibKind, count: usize, delta: &BgpRibDelta) {
+        self.last_update = Some(Timestamp::now());
+        let r = self.rib_mut(kind);
+        r.updates += count as u64;
+        r.apply(delta);
+    }
+    pub fn on_withdraws(&mut self, kind: BgpRibKind, count: usize, delta: &BgpRibDelta) {
+        self.last_update = Some(Timestamp::now());
+        let r = self.rib_mut(kind);
+        r.withdraws += count as u64;
+        r.apply(delta);
+    }
+    /// replace active counters with recalculated ones, used after snapshot load
+    pub fn reset_active(&mut self, kind: BgpRibKind, delta: &BgpRibDelta) {
+        let r = self.rib_mut(kind);
+        r.prefixes = 0;
+        r.pathlen_sum = 0;
+        r.apply(delta);
+    }
+    pub fn updates(&self) -> u64 {
+        self.ribs.values().map(|x| x.updates).sum()
+    }
+    pub fn withdraws(&self) -> u64 {
+        self.ribs.values().map(|x| x.withdraws).sum()
+    }
+}
+impl serde::Serialize for BgpPeerStats {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("BgpPeerStats", 4)?;
+        state.serialize_field("last_update", &self.last_update)?;
+        state.serialize_field("updates", &self.updates())?;
+        state.serialize_field("withdraws", &self.withdraws())?;
+        state.serialize_field("ribs", &self.ribs)?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peerstats_delta() {
+        let mut d = BgpRibDelta::default();
+        d.track(None, Some(3));
+        d.track(Some((true, 3)), Some(4));
+        d.track(Some((false, 2)), Some(2));
+        assert_eq!(
+            d,
+            BgpRibDelta {
+                active: 2,
+                pathlen: 6
+            }
+        );
+        let mut ps = BgpPeerStats::default();
+        ps.on_updates(BgpRibKind::IpV4u, 3, &d);
+        let mut w = BgpRibDelta::default();
+        w.track(Some((true, 4)), None);
+        ps.on_withdraws(BgpRibKind::IpV4u, 1, &w);
+        let r = &ps.ribs["ipv4u"];
+        assert_eq!(r.prefixes, 1);
+        assert_eq!(r.avg_path_len(), 2.0);
+        assert_eq!(ps.updates(), 3);
+        assert_eq!(ps.withdraws(), 1);
+    }
+}
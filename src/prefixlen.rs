@@ -0,0 +1,68 @@
+//! prefix length distribution of unicast and multicast RIBs, counts prefixes with at least
+//! one active route and is maintained by update and withdraw handling
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Clone)]
+pub struct PrefixLenStats {
+    counts: BTreeMap<u8, u64>,
+}
+impl PrefixLenStats {
+    /// account prefix which had active route (was) and has it after change (now)
+    pub fn track(&mut self, len: u8, was: bool, now: bool) {
+        match (was, now) {
+            (false, true) => *self.counts.entry(len).or_default() += 1,
+            (true, false) => {
+                if let Some(n) = self.counts.get_mut(&len) {
+                    *n = n.saturating_sub(1);
+                    if *n == 0 {
+                        self.counts.remove(&len);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+    /// boundary is the longest prefix usually accepted, 24 for IPv4 and 48 for IPv6
+    pub fn report(&self, boundary: u8) -> PrefixLenReport {
+        PrefixLenReport {
+            total: self.counts.values().sum(),
+            boundary,
+            more_specific: self.counts.range(boundary + 1..).map(|(_, n)| n).sum(),
+            lengths: self.counts.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PrefixLenReport {
+    pub total: u64,
+    pub boundary: u8,
+    /// prefixes longer than boundary
+    pub more_specific: u64,
+    pub lengths: BTreeMap<u8, u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefixlen_stats() {
+        let mut st = PrefixLenStats::default();
+        st.track(24, false, true);
+        st.track(24, false, true);
+        st.track(25, false, true);
+        st.track(24, true, true);
+        st.track(16, true, false);
+        let r = st.report(24);
+        assert_eq!(r.total, 3);
+        assert_eq!(r.more_specific, 1);
+        assert_eq!(r.lengths.get(&24), Some(&2));
+        st.track(25, true, false);
+        assert!(st.report(24).lengths.get(&25).is_none());
+    }
+}
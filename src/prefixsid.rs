@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use zettabgp::prelude::*;
+
+/// BGP Prefix-SID path attribute type code (RFC 8669)
+pub const BGP_ATTR_PREFIX_SID: u8 = 40;
+
+/// Segment routing global block range from Originator SRGB TLV
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Serialize, Deserialize)]
+pub struct BgpSrgbRange {
+    pub base: u32,
+    pub range: u32,
+}
+
+/// Prefix-SID TLV not decoded by bgpexplorer, kept as is
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Serialize, Deserialize)]
+pub struct BgpPrefixSidTlv {
+    pub tlvtype: u8,
+    pub value: Vec<u8>,
+}
+
+/// Decoded BGP Prefix-SID attribute
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Serialize, Deserialize)]
+pub struct BgpPrefixSid {
+    pub label_index: Option<u32>,
+    pub srgb_flags: u16,
+    pub srgb: Vec<BgpSrgbRange>,
+    pub other: Vec<BgpPrefixSidTlv>,
+}
+impl Default for BgpPrefixSid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl BgpPrefixSid {
+    pub fn new() -> BgpPrefixSid {
+        BgpPrefixSid {
+            label_index: None,
+            srgb_flags: 0,
+            srgb: Vec::new(),
+            other: Vec::new(),
+        }
+    }
+    fn getu24(buf: &[u8]) -> u32 {
+        ((buf[0] as u32) << 16) | ((buf[1] as u32) << 8) | (buf[2] as u32)
+    }
+    /// decodes attribute value, TLVs sequence
+    pub fn decode_from(buf: &[u8]) -> Result<BgpPrefixSid, BgpError> {
+        let mut ret = BgpPrefixSid::new();
+        let mut pos: usize = 0;
+        while pos < buf.len() {
+            if pos + 3 > buf.len() {
+                return Err(BgpError::static_str("Prefix-SID TLV header truncated"));
+            }
+            let tlvtype = buf[pos];
+            let tlvlen = ((buf[pos + 1] as usize) << 8) | (buf[pos + 2] as usize);
+            pos += 3;
+            if pos + tlvlen > buf.len() {
+                return Err(BgpError::static_str("Prefix-SID TLV value truncated"));
+            }
+            let tlv = &buf[pos..pos + tlvlen];
+            match tlvtype {
+                1 => {
+                    // reserved(1), flags(2), label index(4)
+                    if tlvlen != 7 {
+                        return Err(BgpError::static_str("Invalid Label-Index TLV length"));
+                    }
+                    ret.label_index = Some(
+                        ((tlv[3] as u32) << 24)
+                            | ((tlv[4] as u32) << 16)
+                            | ((tlv[5] as u32) << 8)
+                            | (tlv[6] as u32),
+                    );
+                }
+                3 => {
+                    // flags(2), then (base(3), range(3)) pairs
+                    if tlvlen < 2 || !(tlvlen - 2).is_multiple_of(6) {
+                        return Err(BgpError::static_str("Invalid Originator SRGB TLV length"));
+                    }
+                    ret.srgb_flags = ((tlv[0] as u16) << 8) | (tlv[1] as u16);
+                    for r in tlv[2..].chunks(6) {
+                        ret.srgb.push(BgpSrgbRange {
+                            base: Self::getu24(&r[0..3]),
+                            range: Self::getu24(&r[3..6]),
+                        });
+                    }
+                }
+                _ => {
+                    ret.other.push(BgpPrefixSidTlv {
+                        tlvtype,
+                        value: tlv.to_vec(),
+                    });
+                }
+            }
+            pos += tlvlen;
+        }
+        Ok(ret)
+    }
+    /// MPLS label derived from label index and first SRGB range
+    pub fn label(&self) -> Option<u32> {
+        let idx = self.label_index?;
+        let rng = self.srgb.first()?;
+        if idx < rng.range {
+            Some(rng.base + idx)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefixsid_decode() {
+        let buf = [
+            1, 0, 7, 0, 0, 0, 0, 0, 0, 100, // label index 100
+            3, 0, 8, 0, 0, 0, 0x3e, 0x80, 0, 0x1f, 0x40, // srgb 16000/8000
+        ];
+        let psid = BgpPrefixSid::decode_from(&buf).unwrap();
+        assert_eq!(psid.label_index, Some(100));
+        assert_eq!(
+            psid.srgb,
+            vec![BgpSrgbRange {
+                base: 16000,
+                range: 8000
+            }]
+        );
+        assert_eq!(psid.label(), Some(16100));
+        assert!(psid.other.is_empty());
+    }
+    #[test]
+    fn test_prefixsid_truncated() {
+        assert!(BgpPrefixSid::decode_from(&[1, 0, 7, 0, 0]).is_err());
+        assert!(BgpPrefixSid::decode_from(&[3, 0, 3, 0, 0, 1]).is_err());
+    }
+}
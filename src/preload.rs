@@ -0,0 +1,192 @@
+//! startup RIB preload from snapshot of other bgpexplorer or MRT file served over HTTP(S),
+//! runs in background while sessions are already up
+use crate::bgprib::BgpRIB;
+use crate::bgpsvc::BgpSvr;
+use crate::replay::ReplaySpeed;
+use crate::tls::BoxStream;
+use futures::{Stream, StreamExt, TryStreamExt};
+use hyper::body::Bytes;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// content type of GET /api/snapshot, anything else is treated as MRT
+pub const SNAPSHOT_CONTENT_TYPE: &str = "application/x-bgpexplorer-snapshot";
+
+/// whole transfer limit
+const PRELOAD_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// how preloaded content is interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreloadFormat {
+    Snapshot,
+    Mrt,
+}
+impl PreloadFormat {
+    pub fn from_content_type(ct: Option<&str>) -> PreloadFormat {
+        match ct.and_then(|s| s.split(';').next()) {
+            Some(s) if s.trim().eq_ignore_ascii_case(SNAPSHOT_CONTENT_TYPE) => {
+                PreloadFormat::Snapshot
+            }
+            _ => PreloadFormat::Mrt,
+        }
+    }
+}
+
+fn other_err<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// only RIB without routes is preloaded, local snapshot wins
+fn rib_empty(rib: &BgpRIB) -> bool {
+    rib.ipv4u.is_empty()
+        && rib.ipv6u.is_empty()
+        && rib.vpnv4u.is_empty()
+        && rib.vpnv6u.is_empty()
+        && rib.evpn.is_empty()
+}
+
+/// GET over plain or TLS connection, returns content format and body
+async fn fetch(url: &str) -> io::Result<(PreloadFormat, hyper::Response<hyper::Body>)> {
+    let uri: hyper::Uri = url.parse().map_err(other_err)?;
+    let tls = match uri.scheme_str() {
+        Some("http") => false,
+        Some("https") => true,
+        _ => return Err(other_err("preload_url should be http:// or https://")),
+    };
+    let authority = uri
+        .authority()
+        .ok_or_else(|| other_err("preload_url without host"))?
+        .clone();
+    let addr = format!(
+        "{}:{}",
+        authority.host(),
+        authority.port_u16().unwrap_or(if tls { 443 } else { 80 })
+    );
+    let mut stream: BoxStream = Box::new(TcpStream::connect(addr.as_str()).await?);
+    if tls {
+        stream = crate::tls::connect(crate::tls::host_of(&addr), stream).await?;
+    }
+    let (mut sender, conn) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(other_err)?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            debug!("Preload connection: {}", e);
+        }
+    });
+    let req = hyper::Request::get(uri.path_and_query().map(|p| p.as_str()).unwrap_or("/"))
+        .header(hyper::header::HOST, authority.as_str())
+        .body(hyper::Body::empty())
+        .map_err(other_err)?;
+    let rsp = sender.send_request(req).await.map_err(other_err)?;
+    if !rsp.status().is_success() {
+        return Err(other_err(format!("HTTP status {}", rsp.status())));
+    }
+    let fmt = PreloadFormat::from_content_type(
+        rsp.headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok()),
+    );
+    Ok((fmt, rsp))
+}
+
+/// body chunks, failing once more than max bytes arrived
+fn capped_body(body: hyper::Body, max: usize) -> impl Stream<Item = io::Result<Bytes>> {
+    let mut total = 0usize;
+    body.map(move |r| {
+        let b = r.map_err(other_err)?;
+        total = total.saturating_add(b.len());
+        if total > max {
+            return Err(other_err(format!(
+                "body exceeds preload_max_size_mb ({} bytes)",
+                max
+            )));
+        }
+        Ok(b)
+    })
+}
+
+async fn preload(svr: &Arc<BgpSvr>, url: &str) -> io::Result<()> {
+    let max = svr.config.preload_max_size;
+    let (fmt, rsp) = fetch(url).await?;
+    if let Some(len) = rsp
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        if len > max as u64 {
+            return Err(other_err(format!(
+                "Content-Length {} exceeds preload_max_size_mb",
+                len
+            )));
+        }
+    }
+    let body = capped_body(rsp.into_body(), max);
+    match fmt {
+        PreloadFormat::Snapshot => {
+            let buf = body
+                .try_fold(Vec::new(), |mut v, b| async move {
+                    v.extend_from_slice(&b);
+                    Ok(v)
+                })
+                .await?;
+            let cfg = svr.config.clone();
+            let rib = tokio::task::spawn_blocking(move || {
+                BgpRIB::read_snapshot(&cfg, &buf[..]).map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(other_err)?
+            .map_err(other_err)?;
+            let mut cur = svr.rib.rib.write().await;
+            // sessions are running meanwhile, their routes are not thrown away
+            if !rib_empty(&cur) {
+                return Err(other_err(
+                    "RIB got routes from sessions during transfer, snapshot not applied",
+                ));
+            }
+            cur.replace_from(rib);
+        }
+        PreloadFormat::Mrt => {
+            // MRT updates are fed through the normal update path as they arrive
+            let rd = tokio_util::io::StreamReader::new(body);
+            crate::replay::replay_stream(svr.clone(), rd, url, ReplaySpeed::Max, true).await?;
+        }
+    }
+    Ok(())
+}
+
+/// loads preload_url into empty RIB, started along with sessions
+pub async fn run_preload(svr: Arc<BgpSvr>, url: String) -> io::Result<()> {
+    if !rib_empty(&*svr.rib.rib.read().await) {
+        info!("RIB is not empty, skipping preload from {}", url);
+        return Ok(());
+    }
+    info!("Preloading RIB from {}", url);
+    match tokio::time::timeout(PRELOAD_TIMEOUT, preload(&svr, url.as_str())).await {
+        Ok(r) => r?,
+        Err(_) => return Err(other_err("preload timed out")),
+    }
+    info!("Preload from {} done", url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            PreloadFormat::from_content_type(Some("application/x-bgpexplorer-snapshot")),
+            PreloadFormat::Snapshot
+        );
+        assert_eq!(
+            PreloadFormat::from_content_type(Some("application/octet-stream")),
+            PreloadFormat::Mrt
+        );
+        assert_eq!(PreloadFormat::from_content_type(None), PreloadFormat::Mrt);
+    }
+}
@@ -0,0 +1,317 @@
+//! route processor plugins: sinks and filters fed with RIB changes and session events
+use crate::bgpattrs::BgpAttrs;
+use crate::bgprib::BgpEvent;
+use crate::bgpsvc::{BgpSessionDesc, BgpSessionId, BgpSessionState};
+use crate::config::{ErrorConfig, SvcConfig};
+use crate::ribfilter::{FilterItemMatchResult, RouteFilter};
+use crate::withdrawburst::WithdrawBatch;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use zettabgp::prelude::*;
+
+/// session lifecycle notification
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// BGP session or BMP monitored peer got its id
+    Started(BgpSessionId, Arc<BgpSessionDesc>),
+    /// collector state transition, old and new
+    StateChanged(BgpSessionState, BgpSessionState),
+}
+
+/// Plugin receiving routes after they were applied to RIB.
+/// Callbacks run on shared dispatch task and must not block,
+/// slow sinks should queue work to their own tasks.
+pub trait RouteProcessor: Send + Sync {
+    fn on_update(&self, _sid: BgpSessionId, _attrs: &BgpAttrs, _addrs: &BgpAddrs) {}
+    fn on_withdraw(&self, _sid: BgpSessionId, _addrs: &BgpAddrs) {}
+    /// coalesced withdraw burst, passed to on_withdraw by default for sinks which keep
+    /// every route; sinks which only need totals override it
+    fn on_withdraw_batch(&self, sid: BgpSessionId, batch: &WithdrawBatch) {
+        for addrs in batch.addrs.iter() {
+            self.on_withdraw(sid, addrs);
+        }
+    }
+    fn on_session_event(&self, _evt: &SessionEvent) {}
+}
+
+/// creates processor from service config and its own options,
+/// options are main section keys prefixed with processor name and dot
+pub type ProcessorFactory =
+    fn(&SvcConfig, &BTreeMap<String, String>) -> Result<Arc<dyn RouteProcessor>, ErrorConfig>;
+
+/// processor listed in config
+#[derive(Debug, Clone)]
+pub struct ProcessorConfig {
+    pub name: String,
+    pub options: BTreeMap<String, String>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<BTreeMap<String, ProcessorFactory>> = {
+        let mut m: BTreeMap<String, ProcessorFactory> = BTreeMap::new();
+        m.insert("log".to_string(), LogProcessor::create);
+        m.insert("sqlite".to_string(), crate::archive::SqliteArchive::create);
+        m.insert(
+            "clickhouse".to_string(),
+            crate::dbexport::DbExport::create_clickhouse,
+        );
+        m.insert(
+            "postgres".to_string(),
+            crate::dbexport::DbExport::create_postgres,
+        );
+        Mutex::new(m)
+    };
+}
+
+/// makes processor available for processors option, embedders call it before loading config
+pub fn register_processor(name: &str, factory: ProcessorFactory) {
+    REGISTRY.lock().unwrap().insert(name.to_string(), factory);
+}
+
+pub fn is_registered(name: &str) -> bool {
+    REGISTRY.lock().unwrap().contains_key(name)
+}
+
+/// instantiates configured processors, any failure is a config error
+pub fn build(cfg: &SvcConfig) -> Result<Vec<Arc<dyn RouteProcessor>>, ErrorConfig> {
+    let mut ret = Vec::new();
+    for pc in cfg.processors.iter() {
+        let factory = match REGISTRY.lock().unwrap().get(&pc.name) {
+            Some(f) => *f,
+            None => {
+                return Err(ErrorConfig::from_string(format!(
+                    "Unknown route processor {}",
+                    pc.name
+                )))
+            }
+        };
+        let p = factory(cfg, &pc.options).map_err(|e| {
+            ErrorConfig::from_string(format!("Invalid route processor {} - {}", pc.name, e))
+        })?;
+        ret.push(match pc.options.get("filter") {
+            Some(f) => FilteredProcessor::wrap(pc.name.as_str(), f.as_str(), p)?,
+            None => p,
+        });
+    }
+    Ok(ret)
+}
+
+/// processor fed only with ipv4u/ipv6u routes matching its filter option,
+/// session events pass unfiltered
+pub struct FilteredProcessor {
+    filter: RouteFilter,
+    inner: Arc<dyn RouteProcessor>,
+}
+impl FilteredProcessor {
+    pub fn wrap(
+        name: &str,
+        filter: &str,
+        inner: Arc<dyn RouteProcessor>,
+    ) -> Result<Arc<dyn RouteProcessor>, ErrorConfig> {
+        if let Some(c) = RouteFilter::unknown_community(filter) {
+            return Err(ErrorConfig::from_string(format!(
+                "Invalid {}.filter - unknown community {}",
+                name, c
+            )));
+        }
+        Ok(Arc::new(FilteredProcessor {
+            filter: RouteFilter::fromstr(filter),
+            inner,
+        }))
+    }
+    /// matching routes of addrs, None when nothing matches
+    fn matching(&self, attrs: &BgpAttrs, addrs: &BgpAddrs) -> Option<BgpAddrs> {
+        let f = &self.filter;
+        let yes = FilterItemMatchResult::Yes;
+        let ret = match addrs {
+            BgpAddrs::IPV4U(v) => BgpAddrs::IPV4U(
+                v.iter()
+                    .filter(|r| f.match_route(*r, attrs) == yes)
+                    .cloned()
+                    .collect(),
+            ),
+            BgpAddrs::IPV4UP(v) => BgpAddrs::IPV4UP(
+                v.iter()
+                    .filter(|r| f.match_route(&r.nlri, attrs) == yes)
+                    .cloned()
+                    .collect(),
+            ),
+            BgpAddrs::IPV6U(v) => BgpAddrs::IPV6U(
+                v.iter()
+                    .filter(|r| f.match_route(*r, attrs) == yes)
+                    .cloned()
+                    .collect(),
+            ),
+            BgpAddrs::IPV6UP(v) => BgpAddrs::IPV6UP(
+                v.iter()
+                    .filter(|r| f.match_route(&r.nlri, attrs) == yes)
+                    .cloned()
+                    .collect(),
+            ),
+            _ => return None,
+        };
+        if ret.is_empty() {
+            None
+        } else {
+            Some(ret)
+        }
+    }
+}
+impl RouteProcessor for FilteredProcessor {
+    fn on_update(&self, sid: BgpSessionId, attrs: &BgpAttrs, addrs: &BgpAddrs) {
+        if let Some(a) = self.matching(attrs, addrs) {
+            self.inner.on_update(sid, attrs, &a);
+        }
+    }
+    fn on_withdraw(&self, sid: BgpSessionId, addrs: &BgpAddrs) {
+        if let Some(a) = self.matching(&BgpAttrs::new(), addrs) {
+            self.inner.on_withdraw(sid, &a);
+        }
+    }
+    fn on_withdraw_batch(&self, sid: BgpSessionId, batch: &WithdrawBatch) {
+        let noattrs = BgpAttrs::new();
+        let addrs: Vec<Arc<BgpAddrs>> = batch
+            .addrs
+            .iter()
+            .filter_map(|a| self.matching(&noattrs, a).map(Arc::new))
+            .collect();
+        if addrs.is_empty() {
+            return;
+        }
+        let mut b = batch.clone();
+        b.count = addrs.iter().map(|a| a.len()).sum();
+        b.counts = BTreeMap::new();
+        for a in addrs.iter() {
+            let rib = match a.as_ref() {
+                BgpAddrs::IPV4U(_) | BgpAddrs::IPV4UP(_) => "ipv4u",
+                _ => "ipv6u",
+            };
+            *b.counts.entry(rib.to_string()).or_insert(0) += a.len();
+        }
+        b.addrs = addrs;
+        self.inner.on_withdraw_batch(sid, &b);
+    }
+    fn on_session_event(&self, evt: &SessionEvent) {
+        self.inner.on_session_event(evt);
+    }
+}
+
+/// passes RIB events to processors, events lost by lagging behind are counted in dropped
+pub async fn run_dispatch(
+    processors: Vec<Arc<dyn RouteProcessor>>,
+    mut rcv: tokio::sync::broadcast::Receiver<BgpEvent>,
+    dropped: Arc<AtomicU64>,
+) {
+    loop {
+        let evt = match rcv.recv().await {
+            Ok(e) => e,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                warn!("Route processors skipped {} events", n);
+                dropped.fetch_add(n, Ordering::Relaxed);
+                continue;
+            }
+            Err(_) => return,
+        };
+        for p in processors.iter() {
+            match evt {
+                BgpEvent::Update(sid, ref attrs, ref addrs) => p.on_update(sid, attrs, addrs),
+                BgpEvent::Withdraw(sid, ref addrs) => p.on_withdraw(sid, addrs),
+                BgpEvent::WithdrawBatch(sid, ref batch) => p.on_withdraw_batch(sid, batch),
+            }
+        }
+    }
+}
+
+/// built-in "log": logs events, restricted to ipv4u/ipv6u routes matching log.filter when set
+pub struct LogProcessor;
+impl LogProcessor {
+    pub fn create(
+        _cfg: &SvcConfig,
+        _opts: &BTreeMap<String, String>,
+    ) -> Result<Arc<dyn RouteProcessor>, ErrorConfig> {
+        Ok(Arc::new(LogProcessor))
+    }
+}
+impl RouteProcessor for LogProcessor {
+    fn on_update(&self, sid: BgpSessionId, _attrs: &BgpAttrs, addrs: &BgpAddrs) {
+        info!("processor log: session {} update {:?}", sid, addrs);
+    }
+    fn on_withdraw(&self, sid: BgpSessionId, addrs: &BgpAddrs) {
+        info!("processor log: session {} withdraw {:?}", sid, addrs);
+    }
+    fn on_withdraw_batch(&self, sid: BgpSessionId, batch: &WithdrawBatch) {
+        info!(
+            "processor log: session {} withdraw burst {} routes {:?}",
+            sid, batch.count, batch.counts
+        );
+    }
+    fn on_session_event(&self, evt: &SessionEvent) {
+        info!("processor log: {:?}", evt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Noop;
+    impl RouteProcessor for Noop {}
+
+    fn noop(
+        _cfg: &SvcConfig,
+        _opts: &BTreeMap<String, String>,
+    ) -> Result<Arc<dyn RouteProcessor>, ErrorConfig> {
+        Ok(Arc::new(Noop))
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        updates: Mutex<Vec<BgpAddrs>>,
+        batches: Mutex<Vec<usize>>,
+    }
+    impl RouteProcessor for Recorder {
+        fn on_update(&self, _sid: BgpSessionId, _attrs: &BgpAttrs, addrs: &BgpAddrs) {
+            self.updates.lock().unwrap().push(addrs.clone());
+        }
+        fn on_withdraw_batch(&self, _sid: BgpSessionId, batch: &WithdrawBatch) {
+            self.batches.lock().unwrap().push(batch.count);
+        }
+    }
+
+    #[test]
+    fn test_filtered_processor() {
+        let rec = Arc::new(Recorder::default());
+        let p = FilteredProcessor::wrap("test", "10.0.0.0/8", rec.clone()).unwrap();
+        let inside = BgpAddrV4::new(std::net::Ipv4Addr::new(10, 1, 0, 0), 16);
+        let outside = BgpAddrV4::new(std::net::Ipv4Addr::new(192, 0, 2, 0), 24);
+        let attrs = BgpAttrs::new();
+        p.on_update(
+            1,
+            &attrs,
+            &BgpAddrs::IPV4U(vec![inside.clone(), outside.clone()]),
+        );
+        p.on_update(1, &attrs, &BgpAddrs::IPV4U(vec![outside.clone()]));
+        assert_eq!(
+            *rec.updates.lock().unwrap(),
+            vec![BgpAddrs::IPV4U(vec![inside.clone()])]
+        );
+        let mut batch = WithdrawBatch::new(1, crate::timestamp::Timestamp::now());
+        batch.count = 2;
+        batch.addrs = vec![
+            Arc::new(BgpAddrs::IPV4U(vec![inside])),
+            Arc::new(BgpAddrs::IPV4U(vec![outside])),
+        ];
+        p.on_withdraw_batch(1, &batch);
+        assert_eq!(*rec.batches.lock().unwrap(), vec![1]);
+        assert!(FilteredProcessor::wrap("test", "community:nosuchname", rec).is_err());
+    }
+
+    #[test]
+    fn test_registry() {
+        assert!(is_registered("log"));
+        assert!(!is_registered("test_noop"));
+        register_processor("test_noop", noop);
+        assert!(is_registered("test_noop"));
+    }
+}
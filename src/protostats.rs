@@ -0,0 +1,254 @@
+//! BGP message statistics by type, path attribute occurrence and NLRI count per UPDATE
+use crate::wireparse::be16;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// upper bounds of NLRI per UPDATE histogram buckets, last one is open
+const NLRI_BUCKETS: [u64; 5] = [0, 1, 10, 100, 1000];
+
+pub fn msgtype_name(t: u8) -> &'static str {
+    match t {
+        1 => "open",
+        2 => "update",
+        3 => "notification",
+        4 => "keepalive",
+        5 => "route_refresh",
+        _ => "unknown",
+    }
+}
+
+pub fn attr_name(code: u8) -> String {
+    match code {
+        1 => "origin".to_string(),
+        2 => "as_path".to_string(),
+        3 => "next_hop".to_string(),
+        4 => "med".to_string(),
+        5 => "local_pref".to_string(),
+        6 => "atomic_aggregate".to_string(),
+        7 => "aggregator".to_string(),
+        8 => "communities".to_string(),
+        9 => "originator_id".to_string(),
+        10 => "cluster_list".to_string(),
+        14 => "mp_reach_nlri".to_string(),
+        15 => "mp_unreach_nlri".to_string(),
+        16 => "ext_communities".to_string(),
+        17 => "as4_path".to_string(),
+        18 => "as4_aggregator".to_string(),
+        22 => "pmsi_tunnel".to_string(),
+        26 => "aigp".to_string(),
+        29 => "bgp_ls".to_string(),
+        32 => "large_communities".to_string(),
+        40 => "prefix_sid".to_string(),
+        c => format!("type_{}", c),
+    }
+}
+
+/// prefix count of NLRI field, EVPN and flowspec have own length encoding
+fn count_nlri(mut b: &[u8], safi: u8, addpath: bool) -> Option<usize> {
+    let mut n = 0;
+    while !b.is_empty() {
+        if addpath {
+            b = b.get(4..)?;
+        }
+        let skip = match safi {
+            70 => 2 + *b.get(1)? as usize,
+            133 | 134 => {
+                let l = *b.first()? as usize;
+                if l >= 0xf0 {
+                    2 + (((l & 0x0f) << 8) | *b.get(1)? as usize)
+                } else {
+                    1 + l
+                }
+            }
+            _ => 1 + (*b.first()? as usize).div_ceil(8),
+        };
+        b = b.get(skip..)?;
+        n += 1;
+    }
+    Some(n)
+}
+
+/// attribute type codes and announced plus withdrawn prefix count of UPDATE body
+pub fn update_shape(body: &[u8], addpath: bool) -> Option<(Vec<u8>, usize)> {
+    let wlen = be16(body, 0)?;
+    let mut nlri = count_nlri(body.get(2..2 + wlen)?, 1, addpath)?;
+    let alen = be16(body, 2 + wlen)?;
+    let attrs = body.get(4 + wlen..4 + wlen + alen)?;
+    nlri += count_nlri(&body[4 + wlen + alen..], 1, addpath)?;
+    let mut codes = Vec::new();
+    let mut pos = 0;
+    while pos < attrs.len() {
+        let flags = *attrs.get(pos)?;
+        let code = *attrs.get(pos + 1)?;
+        let (len, hlen) = if flags & 0x10 != 0 {
+            (be16(attrs, pos + 2)?, 4)
+        } else {
+            (*attrs.get(pos + 2)? as usize, 3)
+        };
+        let value = attrs.get(pos + hlen..pos + hlen + len)?;
+        pos += hlen + len;
+        codes.push(code);
+        let mp = match code {
+            // next hop length, next hop, reserved byte
+            14 => value.get(5 + *value.get(3)? as usize..),
+            15 => value.get(3..),
+            _ => None,
+        };
+        if let Some(mp) = mp {
+            nlri += count_nlri(mp, value[2], addpath).unwrap_or(0);
+        }
+    }
+    Some((codes, nlri))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NlriHistogram {
+    /// bucket label to UPDATE count
+    pub buckets: BTreeMap<String, u64>,
+    pub total: u64,
+    pub max: u64,
+    pub avg: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtocolStatsReport {
+    pub received: BTreeMap<&'static str, u64>,
+    pub sent: BTreeMap<&'static str, u64>,
+    /// attribute name to number of UPDATE messages carrying it
+    pub attributes: BTreeMap<String, u64>,
+    pub nlri_per_update: NlriHistogram,
+    /// UPDATE messages which could not be walked
+    pub malformed_updates: u64,
+}
+
+pub struct ProtocolStats {
+    received: [AtomicU64; 6],
+    sent: [AtomicU64; 6],
+    attributes: Vec<AtomicU64>,
+    nlri_buckets: [AtomicU64; 6],
+    nlri_total: AtomicU64,
+    nlri_max: AtomicU64,
+    updates: AtomicU64,
+    malformed: AtomicU64,
+}
+impl Default for ProtocolStats {
+    fn default() -> Self {
+        ProtocolStats {
+            received: Default::default(),
+            sent: Default::default(),
+            attributes: (0..256).map(|_| AtomicU64::new(0)).collect(),
+            nlri_buckets: Default::default(),
+            nlri_total: AtomicU64::new(0),
+            nlri_max: AtomicU64::new(0),
+            updates: AtomicU64::new(0),
+            malformed: AtomicU64::new(0),
+        }
+    }
+}
+impl ProtocolStats {
+    pub fn record_message(&self, sent: bool, msgtype: u8) {
+        let idx = if msgtype <= 5 { msgtype as usize } else { 0 };
+        let counters = if sent { &self.sent } else { &self.received };
+        counters[idx].fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn record_update(&self, body: &[u8], addpath: bool) {
+        let (codes, nlri) = match update_shape(body, addpath) {
+            Some(s) => s,
+            None => {
+                self.malformed.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        for c in codes {
+            self.attributes[c as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        let nlri = nlri as u64;
+        let bucket = NLRI_BUCKETS
+            .iter()
+            .position(|b| nlri <= *b)
+            .unwrap_or(NLRI_BUCKETS.len());
+        self.nlri_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.nlri_total.fetch_add(nlri, Ordering::Relaxed);
+        self.nlri_max.fetch_max(nlri, Ordering::Relaxed);
+        self.updates.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn report(&self) -> ProtocolStatsReport {
+        let types = |c: &[AtomicU64; 6]| -> BTreeMap<&'static str, u64> {
+            c.iter()
+                .enumerate()
+                .map(|(i, v)| (msgtype_name(i as u8), v.load(Ordering::Relaxed)))
+                .filter(|(_, v)| *v > 0)
+                .collect()
+        };
+        let mut buckets = BTreeMap::new();
+        let mut low = 0;
+        for (i, v) in self.nlri_buckets.iter().enumerate() {
+            let label = match NLRI_BUCKETS.get(i) {
+                Some(high) if *high == low => high.to_string(),
+                Some(high) => format!("{}-{}", low, high),
+                None => format!("{}+", low),
+            };
+            if let Some(high) = NLRI_BUCKETS.get(i) {
+                low = high + 1;
+            }
+            buckets.insert(label, v.load(Ordering::Relaxed));
+        }
+        let updates = self.updates.load(Ordering::Relaxed);
+        let total = self.nlri_total.load(Ordering::Relaxed);
+        ProtocolStatsReport {
+            received: types(&self.received),
+            sent: types(&self.sent),
+            attributes: self
+                .attributes
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (attr_name(i as u8), v.load(Ordering::Relaxed)))
+                .filter(|(_, v)| *v > 0)
+                .collect(),
+            nlri_per_update: NlriHistogram {
+                buckets,
+                total,
+                max: self.nlri_max.load(Ordering::Relaxed),
+                avg: if updates > 0 {
+                    total as f64 / updates as f64
+                } else {
+                    0.0
+                },
+            },
+            malformed_updates: self.malformed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_stats() {
+        // withdrawn 10.0.0.0/8, ORIGIN, MP_REACH ipv6u with two prefixes, NLRI 192.168.0.0/16
+        let mut body = vec![0, 2, 8, 10, 0, 0];
+        let attrs = [
+            0x40, 1, 1, 0, 0x80, 14, 13, 0, 2, 1, 0, 0, 32, 0x20, 0x01, 0x0d, 0xb8, 16, 0x20, 0x01,
+            16, 192, 168,
+        ];
+        body[5] = (attrs.len() - 3) as u8;
+        body.extend_from_slice(&attrs);
+        assert_eq!(update_shape(&body, false), Some((vec![1, 14], 4)));
+        let s = ProtocolStats::default();
+        s.record_message(false, 2);
+        s.record_message(true, 4);
+        s.record_message(false, 9);
+        s.record_update(&body, false);
+        s.record_update(&body[0..5], false);
+        let r = s.report();
+        assert_eq!(r.received["update"], 1);
+        assert_eq!(r.received["unknown"], 1);
+        assert_eq!(r.sent["keepalive"], 1);
+        assert_eq!(r.attributes["mp_reach_nlri"], 1);
+        assert_eq!(r.nlri_per_update.buckets["2-10"], 1);
+        assert_eq!(r.nlri_per_update.buckets["1001+"], 0);
+        assert_eq!(r.malformed_updates, 1);
+    }
+}
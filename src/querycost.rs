@@ -0,0 +1,75 @@
+//! rough cost of RIB query, pathological requests are refused above query_budget
+use crate::bgprib::{BgpRIBKey, BgpRIBSafi};
+use crate::ribfilter::{FilterItem, RouteFilter};
+
+/// cost of matching one route against regular expression of given source
+fn regex_weight(re: &str) -> u64 {
+    let repeats = re
+        .chars()
+        .filter(|c| matches!(c, '*' | '+' | '{' | '|'))
+        .count() as u64;
+    8 + re.len() as u64 + 4 * repeats
+}
+
+/// routes to examine: smallest index set of indexed terms or whole table
+pub fn candidates<T: BgpRIBKey>(safi: &BgpRIBSafi<T>, filter: &RouteFilter) -> u64 {
+    let mut ret = safi.items.len();
+    for asn in filter.find_aspath_item().iter() {
+        ret = ret.min(safi.idx_aspath.idx.get(asn).map(|s| s.len()).unwrap_or(0));
+    }
+    for c in filter.find_community_item().iter() {
+        ret = ret.min(safi.idx_community.idx.get(c).map(|s| s.len()).unwrap_or(0));
+    }
+    for c in filter.find_extcommunity_item().iter() {
+        ret = ret.min(
+            safi.idx_extcommunity
+                .idx
+                .get(c)
+                .map(|s| s.len())
+                .unwrap_or(0),
+        );
+    }
+    for (key, ord, n) in filter.find_bound_items() {
+        ret = ret.min(safi.idx_sort.count(key, ord, n));
+    }
+    ret as u64
+}
+
+/// candidates times per-route match cost plus size of requested output
+pub fn estimate<T: BgpRIBKey>(
+    safi: &BgpRIBSafi<T>,
+    filter: &RouteFilter,
+    limit: usize,
+    maxdepth: usize,
+) -> u64 {
+    let per_route: u64 = 1 + filter
+        .terms
+        .iter()
+        .map(|t| match t.item {
+            FilterItem::Regexp(ref r) => regex_weight(r.restr.as_str()),
+            _ => 1,
+        })
+        .sum::<u64>();
+    let output = (limit as u64).saturating_mul(maxdepth.max(1) as u64);
+    candidates(safi, filter)
+        .saturating_mul(per_route)
+        .saturating_add(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HistoryChangeMode;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_estimate() {
+        let safi: BgpRIBSafi<BgpAddrV4> = BgpRIBSafi::new(10, HistoryChangeMode::OnlyDiffer);
+        let plain = RouteFilter::fromstr("10.0.0.0/8");
+        assert_eq!(estimate(&safi, &plain, 100, 10), 1000);
+        assert!(regex_weight("^(64500 )+.*$") > regex_weight("64500"));
+        let flt = RouteFilter::fromstr("community:65000:1");
+        assert_eq!(flt.terms.len(), 1);
+        assert_eq!(candidates(&safi, &flt), 0);
+    }
+}
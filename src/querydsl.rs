@@ -0,0 +1,294 @@
+//! route query language of `q` parameter: conjunction of comparisons like
+//! `origin == 64496 && community has 64496:666 && pathlen > 5`, compiled into filter
+//! terms so AS path, community and attribute indexes still narrow the scan
+use crate::compact::CompactASPath;
+use crate::ribfilter::{FilterASPath, FilterItem, FilterItemMatchResult, FilterTerm};
+use crate::sortindex::SortKey;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Op(&'static str),
+}
+
+const OPS: [&str; 9] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!"];
+
+fn tokenize(q: &str) -> Result<Vec<Token>, String> {
+    let mut ret = Vec::new();
+    let mut rest = q.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPS.iter().find(|op| rest.starts_with(*op)) {
+            ret.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if let Some(s) = rest.strip_prefix('"') {
+            let end = s
+                .find('"')
+                .ok_or_else(|| "unterminated quoted value".to_string())?;
+            ret.push(Token::Word(s[..end].to_string()));
+            rest = &s[end + 1..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "&|=!<>\"".contains(c))
+                .unwrap_or(rest.len());
+            ret.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(ret)
+}
+
+fn term(item: FilterItem, negated: bool) -> FilterTerm {
+    FilterTerm {
+        item,
+        predicate: if negated {
+            FilterItemMatchResult::No
+        } else {
+            FilterItemMatchResult::Unknown
+        },
+    }
+}
+
+fn number(field: &str, v: &str) -> Result<u32, String> {
+    v.parse()
+        .map_err(|_| format!("{} needs number, got '{}'", field, v))
+}
+
+fn asns(field: &str, v: &str) -> Result<CompactASPath, String> {
+    let v = v
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| number(field, s))
+        .collect::<Result<Vec<u32>, String>>()?;
+    Ok(CompactASPath::from(v))
+}
+
+/// value parsed with filter item syntax, like "community:" prefix for communities
+fn item(field: &str, prefix: &str, v: &str) -> Result<FilterItem, String> {
+    let i = FilterItem::parse(format!("{}{}", prefix, v).as_str());
+    let valid = match field {
+        "community" => matches!(i, FilterItem::Community(_)),
+        "extcommunity" | "rt" => matches!(i, FilterItem::ExtCommunity(_)),
+        "nexthop" => matches!(i, FilterItem::NHV4(_) | FilterItem::NHV6(_)),
+        "rd" => matches!(i, FilterItem::RD(_)),
+        _ => matches!(
+            i,
+            FilterItem::V4(_) | FilterItem::V6(_) | FilterItem::MCV4(_) | FilterItem::MCV6(_)
+        ),
+    };
+    if valid {
+        Ok(i)
+    } else {
+        Err(format!("invalid {} value '{}'", field, v))
+    }
+}
+
+fn bound(key: SortKey, op: &str, n: u32) -> Result<FilterTerm, String> {
+    Ok(match op {
+        "==" => term(FilterItem::Bound(key, Ordering::Equal, n), false),
+        "!=" => term(FilterItem::Bound(key, Ordering::Equal, n), true),
+        "<" => term(FilterItem::Bound(key, Ordering::Less, n), false),
+        ">" => term(FilterItem::Bound(key, Ordering::Greater, n), false),
+        "<=" => match n.checked_add(1) {
+            Some(m) => term(FilterItem::Bound(key, Ordering::Less, m), false),
+            None => term(FilterItem::Bound(key, Ordering::Greater, n), true),
+        },
+        ">=" => match n.checked_sub(1) {
+            Some(m) => term(FilterItem::Bound(key, Ordering::Greater, m), false),
+            None => term(FilterItem::Bound(key, Ordering::Less, n), true),
+        },
+        x => {
+            return Err(format!(
+                "operator {} is not supported for {}",
+                x,
+                key.name()
+            ))
+        }
+    })
+}
+
+/// one comparison, may give several terms
+fn clause(field: &str, op: &str, v: &str) -> Result<Vec<FilterTerm>, String> {
+    let unsupported = || Err(format!("operator {} is not supported for {}", op, field));
+    let eq_or_ne = |i: FilterItem| match op {
+        "==" => Ok(vec![term(i, false)]),
+        "!=" => Ok(vec![term(i, true)]),
+        _ => unsupported(),
+    };
+    match field {
+        "origin" => eq_or_ne(FilterItem::ASPath(FilterASPath::EndsWith(asns(field, v)?))),
+        "peeras" => eq_or_ne(FilterItem::PeerAS(number(field, v)?, Default::default())),
+        "transit" => match op {
+            "has" | "==" => Ok(vec![term(
+                FilterItem::ASPath(FilterASPath::Transit(asns(field, v)?)),
+                false,
+            )]),
+            _ => unsupported(),
+        },
+        "aspath" => {
+            let p = asns(field, v)?;
+            match op {
+                "has" => Ok(vec![term(
+                    FilterItem::ASPath(FilterASPath::Contains(p)),
+                    false,
+                )]),
+                _ if p.value.is_empty() => eq_or_ne(FilterItem::ASPath(FilterASPath::Empty)),
+                _ => eq_or_ne(FilterItem::ASPath(FilterASPath::FullMatch(p))),
+            }
+        }
+        "community" | "extcommunity" | "rt" => {
+            if op != "has" {
+                return unsupported();
+            }
+            let prefix = if field == "community" {
+                "community:"
+            } else {
+                "rt:"
+            };
+            Ok(vec![term(item(field, prefix, v)?, false)])
+        }
+        "nexthop" => match op {
+            "==" | "in" => Ok(vec![term(item(field, "nh:", v)?, false)]),
+            "!=" => Ok(vec![term(item(field, "nh:", v)?, true)]),
+            _ => unsupported(),
+        },
+        "prefix" => {
+            let i = item(field, "", v)?;
+            let len = match i {
+                FilterItem::V4(ref n) | FilterItem::MCV4(ref n) => n.prefixlen as u64,
+                FilterItem::V6(ref n) | FilterItem::MCV6(ref n) => n.prefixlen as u64,
+                _ => 0,
+            };
+            match op {
+                "in" => Ok(vec![term(i, false)]),
+                "==" => Ok(vec![term(i, false), term(FilterItem::Num(len), false)]),
+                _ => unsupported(),
+            }
+        }
+        "prefixlen" => match op {
+            "==" => Ok(vec![term(FilterItem::Num(number(field, v)? as u64), false)]),
+            _ => unsupported(),
+        },
+        "rd" => eq_or_ne(item(field, "rd:", v)?),
+        "aggregator" => eq_or_ne(FilterItem::Aggregator(Some(number(field, v)?))),
+        "med" | "localpref" | "pathlen" => {
+            let key: SortKey = field.parse()?;
+            Ok(vec![bound(key, op, number(field, v)?)?])
+        }
+        x => Err(format!("unknown field {}", x)),
+    }
+}
+
+/// filter terms of query, all of them have to match
+pub fn parse(q: &str) -> Result<Vec<FilterTerm>, String> {
+    let tokens = tokenize(q)?;
+    let mut ret = Vec::new();
+    let mut it = tokens.into_iter().peekable();
+    loop {
+        let mut negated = false;
+        loop {
+            match it.peek() {
+                Some(Token::Op("!")) => {}
+                Some(Token::Word(w)) if w == "not" => {}
+                _ => break,
+            }
+            negated = !negated;
+            it.next();
+        }
+        let field = match it.next() {
+            Some(Token::Word(w)) => w.to_lowercase(),
+            Some(Token::Op(op)) => return Err(format!("unexpected {}", op)),
+            None => return Err("expression expected".to_string()),
+        };
+        let mut terms = if field == "aggregated" {
+            vec![term(FilterItem::Aggregator(None), false)]
+        } else {
+            let op = match it.next() {
+                Some(Token::Op(op)) => op.to_string(),
+                Some(Token::Word(w)) if w == "has" || w == "in" => w,
+                _ => return Err(format!("operator expected after {}", field)),
+            };
+            let v = match it.next() {
+                Some(Token::Word(w)) => w,
+                _ => return Err(format!("value expected after {} {}", field, op)),
+            };
+            clause(field.as_str(), op.as_str(), v.as_str())?
+        };
+        if negated {
+            if terms.len() != 1 {
+                return Err(format!("{} comparison can not be negated", field));
+            }
+            let t = &mut terms[0];
+            t.predicate = match t.predicate {
+                FilterItemMatchResult::No => FilterItemMatchResult::Unknown,
+                _ => FilterItemMatchResult::No,
+            };
+        }
+        ret.append(&mut terms);
+        match it.next() {
+            None => return Ok(ret),
+            Some(Token::Op("&&")) => {}
+            Some(Token::Word(w)) if w == "and" => {}
+            Some(Token::Word(w)) if w == "or" => {
+                return Err("or is not supported, run separate queries".to_string())
+            }
+            Some(Token::Op("||")) => {
+                return Err("|| is not supported, run separate queries".to_string())
+            }
+            Some(Token::Word(w)) => return Err(format!("&& expected before {}", w)),
+            Some(Token::Op(op)) => return Err(format!("&& expected before {}", op)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_query_dsl() {
+        let t = parse("origin == 64496 && community has 64496:666 && pathlen > 5").unwrap();
+        assert_eq!(t.len(), 3);
+        assert_eq!(
+            t[0].item,
+            FilterItem::ASPath(FilterASPath::EndsWith(CompactASPath::from(vec![64496])))
+        );
+        assert_eq!(
+            t[1].item,
+            FilterItem::Community(BgpCommunity::from(64496, 666))
+        );
+        assert_eq!(
+            t[2].item,
+            FilterItem::Bound(SortKey::PathLen, Ordering::Greater, 5)
+        );
+        let t = parse("med<=10 and !aggregated and prefix == 10.0.0.0/8").unwrap();
+        assert_eq!(
+            t[0].item,
+            FilterItem::Bound(SortKey::Med, Ordering::Less, 11)
+        );
+        assert_eq!(t[1].predicate, FilterItemMatchResult::No);
+        assert_eq!(t.len(), 4);
+        assert_eq!(t[3].item, FilterItem::Num(8));
+        assert!(parse("origin == 1 || origin == 2").is_err());
+        assert!(parse("color == 5").is_err());
+        assert!(parse("origin > 5").is_err());
+        assert!(parse("pathlen >").is_err());
+        assert!(parse("aspath == \"64500 64501\"").is_ok());
+        // peer AS is matched by session, not by first AS of path
+        let peers = vec![(1, 64500), (2, 64501)].into_iter().collect();
+        let mut f = crate::ribfilter::RouteFilter::new();
+        f.parse_query("peeras == 64500").unwrap();
+        f.resolve_peers(&peers);
+        assert!(f.match_session(1) && !f.match_session(2));
+        let mut f = crate::ribfilter::RouteFilter::new();
+        f.parse_query("peeras != 64500").unwrap();
+        f.resolve_peers(&peers);
+        assert!(!f.match_session(1) && f.match_session(2));
+        assert_eq!(
+            f.match_attr(&crate::bgpattrs::BgpAttrs::new()),
+            FilterItemMatchResult::Yes
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! per-session workarounds for platform deviations from standards, enabled by vendor_quirks
+//! peer option, so parsers stay strict for sessions which do not need them
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// drop vendor-specific capabilities (codes 128-255) from received OPEN
+    pub open_private_caps: bool,
+    /// keep first of repeated path attributes of UPDATE instead of failing it
+    pub repeated_attrs: bool,
+    /// drop information TLVs following OPEN messages of BMP Peer Up
+    pub bmp_peerup_tlvs: bool,
+    /// ignore BMP per-peer header timestamps of routers stamping local time instead of UTC
+    pub bmp_local_time: bool,
+}
+
+impl std::str::FromStr for Quirks {
+    type Err = String;
+    /// comma separated quirk names or vendor presets
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut q = Quirks::default();
+        for name in s.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+            match name {
+                "open_private_caps" => q.open_private_caps = true,
+                "repeated_attrs" => q.repeated_attrs = true,
+                "bmp_peerup_tlvs" => q.bmp_peerup_tlvs = true,
+                "bmp_local_time" => q.bmp_local_time = true,
+                "cisco" => q.open_private_caps = true,
+                "juniper" => q.bmp_peerup_tlvs = true,
+                "huawei" => {
+                    q.repeated_attrs = true;
+                    q.bmp_peerup_tlvs = true;
+                    q.bmp_local_time = true;
+                }
+                "none" => {}
+                x => return Err(format!("unknown quirk {}", x)),
+            }
+        }
+        Ok(q)
+    }
+}
+
+impl Quirks {
+    /// received OPEN body to decode
+    pub fn open<'a>(&self, body: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.open_private_caps {
+            if let Some(b) = crate::wireparse::strip_private_caps(body) {
+                return Cow::Owned(b);
+            }
+        }
+        Cow::Borrowed(body)
+    }
+    /// received UPDATE body to decode
+    pub fn update<'a>(&self, body: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.repeated_attrs {
+            if let Some(b) = crate::wireparse::dedup_attrs(body) {
+                return Cow::Owned(b);
+            }
+        }
+        Cow::Borrowed(body)
+    }
+    /// BMP message (from message type on) to decode, UPDATE of route monitoring and OPENs
+    /// of Peer Up get the same workarounds as on BGP session
+    pub fn bmp<'a>(&self, msg: &'a [u8]) -> Cow<'a, [u8]> {
+        let fixed = match msg.first() {
+            Some(0) if self.repeated_attrs => {
+                crate::wireparse::bmp_map_update(msg, crate::wireparse::dedup_attrs)
+            }
+            Some(3) if self.open_private_caps => {
+                crate::wireparse::peerup_map_opens(msg, crate::wireparse::strip_private_caps)
+            }
+            _ => None,
+        };
+        match fixed {
+            Some(b) => Cow::Owned(b),
+            None => Cow::Borrowed(msg),
+        }
+    }
+    /// length of BMP message (from message type on) to decode
+    pub fn bmp_len(&self, msg: &[u8]) -> usize {
+        if self.bmp_peerup_tlvs {
+            if let Some(l) = crate::wireparse::peerup_without_tlvs(msg) {
+                return l;
+            }
+        }
+        msg.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quirks_parse() {
+        let q: Quirks = "huawei".parse().unwrap();
+        assert!(q.bmp_local_time && q.repeated_attrs && !q.open_private_caps);
+        let q: Quirks = "cisco, bmp_local_time".parse().unwrap();
+        assert!(q.open_private_caps && q.bmp_local_time);
+        assert_eq!("".parse::<Quirks>(), Ok(Quirks::default()));
+        assert!("arista".parse::<Quirks>().is_err());
+        let body = [0u8, 0, 0, 0];
+        assert!(matches!(q.update(&body), Cow::Borrowed(_)));
+    }
+}
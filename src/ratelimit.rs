@@ -0,0 +1,344 @@
+//! per query class limits of HTTP API: concurrent requests and requests per minute of cheap
+//! lookups, streaming subscriptions and heavy analytics are limited separately, so one
+//! class can not starve the others on busy public instance. Limits apply to each client
+//! (API token or address) on its own, so one client can not starve the others either.
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryClass {
+    Lookup,
+    Stream,
+    Analytics,
+}
+impl std::str::FromStr for QueryClass {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lookup" => Ok(QueryClass::Lookup),
+            "stream" => Ok(QueryClass::Stream),
+            "analytics" => Ok(QueryClass::Analytics),
+            x => Err(format!("unknown query class {}", x)),
+        }
+    }
+}
+impl QueryClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueryClass::Lookup => "lookup",
+            QueryClass::Stream => "stream",
+            QueryClass::Analytics => "analytics",
+        }
+    }
+    /// class of API path, None for paths which are not limited
+    pub fn of_path(path: &str) -> Option<QueryClass> {
+        let mut parts = path.split('/');
+        if parts.next() != Some("") || parts.next() != Some("api") {
+            return None;
+        }
+        Some(match parts.next()? {
+            "ping" | "admin" | "audit" => return None,
+            "ws" | "subscriptions" | "changes" => QueryClass::Stream,
+            "json" | "federated" | "range" | "subtree" | "timeline" | "graph" | "incident"
+            | "asset" | "search" | "graphql" | "views" | "check" | "nexthops" | "blackholes"
+            | "clusterpaths" | "statistics" => QueryClass::Analytics,
+            "prefix" if path.ends_with("/history") => QueryClass::Analytics,
+            _ => QueryClass::Lookup,
+        })
+    }
+}
+
+/// 0 - unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ClassLimit {
+    pub concurrency: usize,
+    pub per_minute: u32,
+}
+
+/// comma separated class=concurrency/per_minute items, e.g. "lookup=64/1200, analytics=4/60"
+pub fn parse_limits(s: &str) -> Result<BTreeMap<QueryClass, ClassLimit>, String> {
+    let mut ret = BTreeMap::new();
+    for item in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let (class, limit) = item
+            .split_once('=')
+            .ok_or_else(|| format!("{} is not class=concurrency/per_minute", item))?;
+        let (c, m) = limit.split_once('/').unwrap_or((limit, "0"));
+        let num = |v: &str| -> Result<u64, String> {
+            v.trim()
+                .parse()
+                .map_err(|_| format!("invalid limit {} of {}", v, item))
+        };
+        ret.insert(
+            class.trim().parse()?,
+            ClassLimit {
+                concurrency: num(c)? as usize,
+                per_minute: num(m)? as u32,
+            },
+        );
+    }
+    Ok(ret)
+}
+
+/// limits in parse_limits syntax
+pub fn format_limits(limits: &BTreeMap<QueryClass, ClassLimit>) -> String {
+    limits
+        .iter()
+        .map(|(class, l)| format!("{}={}/{}", class.name(), l.concurrency, l.per_minute))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Concurrency,
+    /// seconds until next request is allowed
+    Rate(u64),
+}
+impl LimitExceeded {
+    pub fn retry_after(&self) -> u64 {
+        match self {
+            LimitExceeded::Concurrency => 1,
+            LimitExceeded::Rate(s) => *s,
+        }
+    }
+}
+
+/// client states kept per class before idle ones are dropped
+const MAX_CLIENTS: usize = 4096;
+
+/// held while request is served and its body is sent, websocket keeps it for connection
+/// lifetime
+pub struct LimitPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// limits of one client in one class
+struct ClientState {
+    semaphore: Option<Arc<Semaphore>>,
+    bucket: Bucket,
+}
+impl ClientState {
+    fn new(limit: &ClassLimit, now: Instant) -> ClientState {
+        ClientState {
+            semaphore: if limit.concurrency > 0 {
+                Some(Arc::new(Semaphore::new(limit.concurrency)))
+            } else {
+                None
+            },
+            bucket: Bucket {
+                tokens: limit.per_minute as f64,
+                updated: now,
+            },
+        }
+    }
+    fn active(&self, limit: &ClassLimit) -> usize {
+        self.semaphore
+            .as_ref()
+            .map(|s| limit.concurrency - s.available_permits())
+            .unwrap_or(0)
+    }
+    fn refill(&mut self, limit: &ClassLimit, now: Instant) {
+        let per_minute = limit.per_minute as f64;
+        let elapsed = now
+            .saturating_duration_since(self.bucket.updated)
+            .as_secs_f64();
+        self.bucket.tokens = (self.bucket.tokens + elapsed * per_minute / 60.0).min(per_minute);
+        self.bucket.updated = now;
+    }
+    /// no request in flight and full bucket, same as new state
+    fn is_idle(&mut self, limit: &ClassLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        self.active(limit) == 0 && self.bucket.tokens >= limit.per_minute as f64
+    }
+}
+
+struct ClassState {
+    limit: ClassLimit,
+    clients: Mutex<HashMap<String, ClientState>>,
+    rejected: AtomicU64,
+}
+impl ClassState {
+    fn new(limit: ClassLimit, rejected: u64) -> ClassState {
+        ClassState {
+            limit,
+            clients: Mutex::new(HashMap::new()),
+            rejected: AtomicU64::new(rejected),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClassReport {
+    #[serde(flatten)]
+    pub limit: ClassLimit,
+    pub active: usize,
+    pub clients: usize,
+    pub rejected: u64,
+}
+
+/// limits can be changed at runtime, requests in flight keep permits of previous limits
+#[derive(Default)]
+pub struct QueryLimits {
+    classes: RwLock<BTreeMap<QueryClass, Arc<ClassState>>>,
+}
+impl QueryLimits {
+    pub fn new(cfg: &BTreeMap<QueryClass, ClassLimit>) -> QueryLimits {
+        let ret = QueryLimits::default();
+        ret.set_limits(cfg);
+        ret
+    }
+    /// replaces limits, client states of changed classes start over, rejected counters are kept
+    pub fn set_limits(&self, cfg: &BTreeMap<QueryClass, ClassLimit>) {
+        let mut classes = self.classes.write().unwrap();
+        let updated = cfg
+            .iter()
+            .map(|(class, limit)| {
+                let st = match classes.get(class) {
+                    Some(st) if st.limit == *limit => st.clone(),
+                    Some(st) => {
+                        Arc::new(ClassState::new(*limit, st.rejected.load(Ordering::Relaxed)))
+                    }
+                    None => Arc::new(ClassState::new(*limit, 0)),
+                };
+                (*class, st)
+            })
+            .collect();
+        *classes = updated;
+    }
+    /// client is API token name or client address
+    pub fn acquire(&self, class: QueryClass, client: &str) -> Result<LimitPermit, LimitExceeded> {
+        self.acquire_at(class, client, Instant::now())
+    }
+    fn acquire_at(
+        &self,
+        class: QueryClass,
+        client: &str,
+        now: Instant,
+    ) -> Result<LimitPermit, LimitExceeded> {
+        let st = match self.classes.read().unwrap().get(&class) {
+            Some(s) => s.clone(),
+            None => return Ok(LimitPermit { _permit: None }),
+        };
+        let limit = &st.limit;
+        let mut clients = st.clients.lock().unwrap();
+        if clients.len() >= MAX_CLIENTS && !clients.contains_key(client) {
+            clients.retain(|_, c| !c.is_idle(limit, now));
+        }
+        let cs = clients
+            .entry(client.to_string())
+            .or_insert_with(|| ClientState::new(limit, now));
+        if limit.per_minute > 0 {
+            cs.refill(limit, now);
+            if cs.bucket.tokens < 1.0 {
+                st.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(LimitExceeded::Rate(
+                    ((1.0 - cs.bucket.tokens) * 60.0 / limit.per_minute as f64).ceil() as u64,
+                ));
+            }
+        }
+        let permit = match cs.semaphore.as_ref().map(|s| s.clone().try_acquire_owned()) {
+            None => None,
+            Some(Ok(p)) => Some(p),
+            Some(Err(_)) => {
+                st.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(LimitExceeded::Concurrency);
+            }
+        };
+        if limit.per_minute > 0 {
+            cs.bucket.tokens -= 1.0;
+        }
+        Ok(LimitPermit { _permit: permit })
+    }
+    pub fn report(&self) -> BTreeMap<QueryClass, ClassReport> {
+        self.classes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(class, st)| {
+                let clients = st.clients.lock().unwrap();
+                (
+                    *class,
+                    ClassReport {
+                        limit: st.limit,
+                        active: clients.values().map(|c| c.active(&st.limit)).sum(),
+                        clients: clients.len(),
+                        rejected: st.rejected.load(Ordering::Relaxed),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_limits() {
+        assert_eq!(
+            QueryClass::of_path("/api/json/ipv4u"),
+            Some(QueryClass::Analytics)
+        );
+        assert_eq!(QueryClass::of_path("/api/ws"), Some(QueryClass::Stream));
+        assert_eq!(
+            QueryClass::of_path("/api/whois/as"),
+            Some(QueryClass::Lookup)
+        );
+        assert_eq!(QueryClass::of_path("/api/ping"), None);
+        assert_eq!(QueryClass::of_path("/index.html"), None);
+        let cfg = parse_limits("analytics=1/2, lookup=0/60").unwrap();
+        assert_eq!(
+            cfg[&QueryClass::Lookup],
+            ClassLimit {
+                concurrency: 0,
+                per_minute: 60
+            }
+        );
+        assert!(parse_limits("heavy=1/1").is_err());
+        let limits = QueryLimits::new(&cfg);
+        let now = Instant::now();
+        let p = limits.acquire_at(QueryClass::Analytics, "a", now).unwrap();
+        assert_eq!(
+            limits.acquire_at(QueryClass::Analytics, "a", now).err(),
+            Some(LimitExceeded::Concurrency)
+        );
+        drop(p);
+        assert!(limits.acquire_at(QueryClass::Analytics, "a", now).is_ok());
+        assert_eq!(
+            limits.acquire_at(QueryClass::Analytics, "a", now).err(),
+            Some(LimitExceeded::Rate(30))
+        );
+        assert!(limits
+            .acquire_at(
+                QueryClass::Analytics,
+                "a",
+                now + std::time::Duration::from_secs(30)
+            )
+            .is_ok());
+        assert!(limits.acquire_at(QueryClass::Stream, "a", now).is_ok());
+        // other client has its own budget
+        let p = limits.acquire_at(QueryClass::Analytics, "b", now).unwrap();
+        assert_eq!(limits.report()[&QueryClass::Analytics].active, 1);
+        assert_eq!(limits.report()[&QueryClass::Analytics].clients, 2);
+        drop(p);
+        assert_eq!(limits.report()[&QueryClass::Analytics].rejected, 2);
+        assert_eq!(format_limits(&cfg), "lookup=0/60,analytics=1/2");
+        assert_eq!(parse_limits(&format_limits(&cfg)).unwrap(), cfg);
+        limits.set_limits(&parse_limits("analytics=2/2").unwrap());
+        assert!(limits.acquire_at(QueryClass::Analytics, "a", now).is_ok());
+        assert!(limits.acquire_at(QueryClass::Lookup, "a", now).is_ok());
+        assert_eq!(limits.report()[&QueryClass::Analytics].rejected, 2);
+        assert_eq!(limits.report().len(), 1);
+    }
+}
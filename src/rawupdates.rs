@@ -0,0 +1,117 @@
+//! raw wire bytes of last UPDATE for each unicast prefix, kept for protocol debugging
+use crate::bgpsvc::BgpSessionId;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use zettabgp::prelude::*;
+
+pub struct RawUpdate {
+    pub ts: Timestamp,
+    /// full BGP message including marker and header
+    pub data: Arc<Vec<u8>>,
+}
+
+#[derive(Serialize)]
+pub struct RawUpdateView {
+    pub session: BgpSessionId,
+    pub time: Timestamp,
+    pub length: usize,
+    pub hex: String,
+}
+
+fn unicast_prefixes(addrs: &BgpAddrs, out: &mut Vec<String>) {
+    match addrs {
+        BgpAddrs::IPV4U(v) => out.extend(v.iter().map(|r| r.to_string())),
+        BgpAddrs::IPV4UP(v) => out.extend(v.iter().map(|r| r.nlri.to_string())),
+        BgpAddrs::IPV6U(v) => out.extend(v.iter().map(|r| r.to_string())),
+        BgpAddrs::IPV6UP(v) => out.extend(v.iter().map(|r| r.nlri.to_string())),
+        _ => {}
+    }
+}
+
+/// canonical prefix text as produced by RIB keys, None for invalid prefix
+pub fn normalize_prefix(s: &str) -> Option<String> {
+    let (addr, len) = s.split_once('/')?;
+    let addr: std::net::IpAddr = addr.parse().ok()?;
+    let len: u8 = len.parse().ok()?;
+    Some(format!("{}/{}", addr, len))
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Default)]
+pub struct RawUpdateStore {
+    items: HashMap<String, BTreeMap<BgpSessionId, RawUpdate>>,
+}
+impl RawUpdateStore {
+    /// remembers message for every ipv4u/ipv6u prefix it announces or withdraws
+    pub fn record(&mut self, sid: BgpSessionId, upd: &BgpUpdateMessage, raw: Vec<u8>) {
+        let mut prefixes = Vec::new();
+        unicast_prefixes(&upd.updates, &mut prefixes);
+        unicast_prefixes(&upd.withdraws, &mut prefixes);
+        for a in upd.attrs.iter() {
+            match a {
+                BgpAttrItem::MPUpdates(n) => unicast_prefixes(&n.addrs, &mut prefixes),
+                BgpAttrItem::MPWithdraws(n) => unicast_prefixes(&n.addrs, &mut prefixes),
+                _ => {}
+            }
+        }
+        if prefixes.is_empty() {
+            return;
+        }
+        let ts = Timestamp::now();
+        let data = Arc::new(raw);
+        for p in prefixes.into_iter() {
+            self.items.entry(p).or_default().insert(
+                sid,
+                RawUpdate {
+                    ts,
+                    data: data.clone(),
+                },
+            );
+        }
+    }
+    pub fn get(&self, prefix: &str) -> Vec<RawUpdateView> {
+        match self.items.get(prefix) {
+            None => Vec::new(),
+            Some(m) => m
+                .iter()
+                .map(|(sid, r)| RawUpdateView {
+                    session: *sid,
+                    time: r.ts,
+                    length: r.data.len(),
+                    hex: hex(&r.data),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_updates() {
+        assert_eq!(hex(&[0, 0xff, 0x1a]), "00ff1a");
+        assert_eq!(
+            normalize_prefix("2001:0db8::/32"),
+            Some("2001:db8::/32".to_string())
+        );
+        assert_eq!(normalize_prefix("10.0.0.0"), None);
+        let mut store = RawUpdateStore::default();
+        let mut upd = BgpUpdateMessage::new();
+        upd.updates = BgpAddrs::IPV4U(vec![BgpAddrV4::new(
+            std::net::Ipv4Addr::new(10, 0, 0, 0),
+            8,
+        )]);
+        store.record(1, &upd, vec![1, 2, 3]);
+        let v = store.get("10.0.0.0/8");
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].hex, "010203");
+        assert!(store.get("10.0.0.0/9").is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+//! route reflection view: active routes carrying ORIGINATOR_ID or CLUSTER_LIST (RFC 4456)
+//! grouped by cluster path, to spot reflection loops and routes reflected over longer
+//! cluster pathes than necessary
+use crate::bgprib::{BgpRIBKey, BgpRIBSafi};
+use crate::bgpsvc::BgpSessionId;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+
+/// examples of prefixes kept per cluster path
+const EXAMPLES: usize = 5;
+/// cluster paths kept at most, routes of further paths are skipped
+pub const MAX_GROUPS: usize = 10000;
+/// RIB entries scanned between deadline checks
+const DEADLINE_CHECK: usize = 1024;
+
+#[derive(Debug, Serialize)]
+pub struct ClusterPath {
+    pub rib: &'static str,
+    pub originator: Option<IpAddr>,
+    /// most recent reflector first
+    pub cluster_list: Vec<IpAddr>,
+    pub routes: usize,
+    pub sessions: BTreeSet<BgpSessionId>,
+    pub examples: Vec<String>,
+    /// cluster id repeated in cluster list
+    pub reflection_loop: bool,
+    /// routes also received from the same originator over shorter cluster list
+    pub suboptimal: usize,
+}
+
+/// cluster list repeats a cluster id
+pub fn has_loop(clusters: &[IpAddr]) -> bool {
+    clusters
+        .iter()
+        .enumerate()
+        .any(|(i, c)| clusters[i + 1..].contains(c))
+}
+
+/// adds cluster paths of RIB to out, false when scan was cut by deadline or MAX_GROUPS
+pub fn find<T: BgpRIBKey + std::string::ToString>(
+    rib: &'static str,
+    safi: &BgpRIBSafi<T>,
+    deadline: Option<std::time::Instant>,
+    out: &mut Vec<ClusterPath>,
+) -> bool {
+    let mut groups: BTreeMap<(Option<IpAddr>, Vec<IpAddr>), ClusterPath> = BTreeMap::new();
+    let mut complete = true;
+    for (n, (k, entry)) in safi.items.iter().enumerate() {
+        if n % DEADLINE_CHECK == 0 && deadline.map(|d| std::time::Instant::now() >= d) == Some(true)
+        {
+            complete = false;
+            break;
+        }
+        let mut paths: Vec<(BgpSessionId, Option<IpAddr>, Vec<IpAddr>)> = Vec::new();
+        for (sid, pe) in entry.items.iter() {
+            for hist in pe.items.values() {
+                let rec = match hist.items.iter().last() {
+                    Some((_, r)) if r.active => r,
+                    _ => continue,
+                };
+                if rec.attrs.originator.is_none() && rec.attrs.clusterlist.is_none() {
+                    continue;
+                }
+                let clusters = rec
+                    .attrs
+                    .clusterlist
+                    .as_ref()
+                    .map(|c| c.value.clone())
+                    .unwrap_or_default();
+                paths.push((*sid, rec.attrs.originator, clusters));
+            }
+        }
+        for (sid, originator, clusters) in paths.iter() {
+            let shortest = paths
+                .iter()
+                .filter(|p| p.1 == *originator)
+                .map(|p| p.2.len())
+                .min()
+                .unwrap_or(0);
+            let key = (*originator, clusters.clone());
+            if !groups.contains_key(&key) && out.len() + groups.len() >= MAX_GROUPS {
+                complete = false;
+                continue;
+            }
+            let g = groups.entry(key).or_insert_with(|| ClusterPath {
+                rib,
+                originator: *originator,
+                cluster_list: clusters.clone(),
+                routes: 0,
+                sessions: BTreeSet::new(),
+                examples: Vec::new(),
+                reflection_loop: has_loop(clusters),
+                suboptimal: 0,
+            });
+            g.routes += 1;
+            g.sessions.insert(*sid);
+            if g.examples.len() < EXAMPLES {
+                g.examples.push(k.to_string());
+            }
+            if clusters.len() > shortest {
+                g.suboptimal += 1;
+            }
+        }
+    }
+    out.extend(groups.into_values());
+    complete
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_loop() {
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+        assert!(!has_loop(&[]));
+        assert!(!has_loop(&[a, b]));
+        assert!(has_loop(&[a, b, a]));
+    }
+
+    #[test]
+    fn test_find() {
+        use crate::bgpattrs::BgpAttrs;
+        use crate::config::HistoryChangeMode;
+        use std::sync::Arc;
+        use zettabgp::prelude::*;
+        let mut safi = BgpRIBSafi::<BgpAddrV4>::new(10, HistoryChangeMode::EveryUpdate);
+        let net = |o: u8| BgpAddrV4::new(std::net::Ipv4Addr::new(10, o, 0, 0), 16);
+        let orig: IpAddr = "192.0.2.1".parse().unwrap();
+        let rr1: IpAddr = "192.0.2.10".parse().unwrap();
+        let rr2: IpAddr = "192.0.2.20".parse().unwrap();
+        let reflected = |clusters: Vec<IpAddr>| {
+            let mut a = BgpAttrs::new();
+            a.originator = Some(orig);
+            a.clusterlist = Some(Arc::new(BgpClusterList { value: clusters }));
+            Arc::new(a)
+        };
+        safi.handle_updates_afi(1, &[net(1), net(2)], reflected(vec![rr1]));
+        safi.handle_updates_afi(2, &[net(1)], reflected(vec![rr2, rr1]));
+        safi.handle_updates_afi(3, &[net(3)], reflected(vec![rr1, rr2, rr1]));
+        safi.handle_updates_afi(1, &[net(4)], Arc::new(BgpAttrs::new()));
+        let mut out = Vec::new();
+        assert!(find("ipv4u", &safi, None, &mut out));
+        assert_eq!(out.len(), 3);
+        let short = out.iter().find(|g| g.cluster_list == vec![rr1]).unwrap();
+        assert_eq!((short.routes, short.suboptimal), (2, 0));
+        let long = out
+            .iter()
+            .find(|g| g.cluster_list == vec![rr2, rr1])
+            .unwrap();
+        assert_eq!((long.routes, long.suboptimal), (1, 1));
+        assert!(out.iter().any(|g| g.reflection_loop));
+        // expired deadline stops scan
+        let mut out = Vec::new();
+        assert!(!find(
+            "ipv4u",
+            &safi,
+            Some(std::time::Instant::now()),
+            &mut out
+        ));
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,529 @@
+//! replay of recorded MRT (RFC 6396) BGP4MP update files and TABLE_DUMP_V2 RIB dumps
+use crate::bgpsvc::*;
+use crate::config::{ErrorConfig, ProtoPeer};
+use crate::timestamp::Timestamp;
+use crate::wireparse::ParseError;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use zettabgp::prelude::*;
+
+const MRT_BGP4MP: u16 = 16;
+const MRT_BGP4MP_ET: u16 = 17;
+const MRT_TABLE_DUMP_V2: u16 = 13;
+const TDV2_PEER_INDEX_TABLE: u16 = 1;
+/// MRT record length accepted, larger one means corrupt file
+const MAX_RECORD: usize = 16 * 1024 * 1024;
+
+/// replay pace
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// as fast as possible
+    Max,
+    /// multiple of recorded pace
+    Factor(f64),
+}
+impl FromStr for ReplaySpeed {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "max" {
+            return Ok(ReplaySpeed::Max);
+        }
+        match s.strip_suffix('x').unwrap_or(s).parse::<f64>() {
+            Ok(f) if f > 0.0 => Ok(ReplaySpeed::Factor(f)),
+            _ => Err(ErrorConfig::from_str("should be max or positive factor")),
+        }
+    }
+}
+
+/// BGP message recorded in BGP4MP record
+#[derive(Debug, Clone, PartialEq)]
+pub struct MrtBgpMessage {
+    pub peer_as: u32,
+    pub local_as: u32,
+    pub peer_ip: IpAddr,
+    pub local_ip: IpAddr,
+    pub as4: bool,
+    pub addpath: bool,
+    /// full BGP message with marker
+    pub msg: Vec<u8>,
+}
+
+fn read_ip(buf: &[u8], v6: bool) -> Option<IpAddr> {
+    if v6 {
+        let mut a = [0u8; 16];
+        a.copy_from_slice(buf.get(0..16)?);
+        Some(IpAddr::V6(Ipv6Addr::from(a)))
+    } else {
+        let b = buf.get(0..4)?;
+        Some(IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3])))
+    }
+}
+
+/// parses BGP4MP message record body, None for state changes and other subtypes
+pub fn parse_bgp4mp(subtype: u16, body: &[u8]) -> Option<MrtBgpMessage> {
+    let (as4, addpath) = match subtype {
+        1 | 6 => (false, false),
+        4 | 7 => (true, false),
+        8 | 10 => (false, true),
+        9 | 11 => (true, true),
+        _ => return None,
+    };
+    let mut pos = 0;
+    let (peer_as, local_as) = if as4 {
+        let b = body.get(0..8)?;
+        pos += 8;
+        (
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            u32::from_be_bytes([b[4], b[5], b[6], b[7]]),
+        )
+    } else {
+        let b = body.get(0..4)?;
+        pos += 4;
+        (
+            u16::from_be_bytes([b[0], b[1]]) as u32,
+            u16::from_be_bytes([b[2], b[3]]) as u32,
+        )
+    };
+    // skip interface index
+    let afi = body.get(pos + 2..pos + 4)?;
+    pos += 4;
+    let v6 = u16::from_be_bytes([afi[0], afi[1]]) == 2;
+    let alen = if v6 { 16 } else { 4 };
+    let peer_ip = read_ip(body.get(pos..)?, v6)?;
+    let local_ip = read_ip(body.get(pos + alen..)?, v6)?;
+    pos += alen * 2;
+    Some(MrtBgpMessage {
+        peer_as,
+        local_as,
+        peer_ip,
+        local_ip,
+        as4,
+        addpath,
+        msg: body.get(pos..)?.to_vec(),
+    })
+}
+
+/// peer of TABLE_DUMP_V2 PEER_INDEX_TABLE
+#[derive(Debug, Clone, PartialEq)]
+pub struct MrtPeer {
+    pub ip: IpAddr,
+    pub asn: u32,
+}
+
+/// parses PEER_INDEX_TABLE record body into collector BGP ID and peers
+pub fn parse_peer_index(body: &[u8]) -> Option<(Ipv4Addr, Vec<MrtPeer>)> {
+    let b = body.get(0..4)?;
+    let collector = Ipv4Addr::new(b[0], b[1], b[2], b[3]);
+    let vlen = u16::from_be_bytes([*body.get(4)?, *body.get(5)?]) as usize;
+    let mut pos = 6 + vlen;
+    let count = u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]);
+    pos += 2;
+    let mut peers = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let ptype = *body.get(pos)?;
+        // peer type, then BGP ID
+        pos += 5;
+        let v6 = ptype & 1 != 0;
+        let ip = read_ip(body.get(pos..)?, v6)?;
+        pos += if v6 { 16 } else { 4 };
+        let asn = if ptype & 2 != 0 {
+            let b = body.get(pos..pos + 4)?;
+            pos += 4;
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            let b = body.get(pos..pos + 2)?;
+            pos += 2;
+            u16::from_be_bytes([b[0], b[1]]) as u32
+        };
+        peers.push(MrtPeer { ip, asn });
+    }
+    Some((collector, peers))
+}
+
+/// converts RIB_IPV4_UNICAST/RIB_IPV6_UNICAST record (or its RFC 8050 ADDPATH variant) into
+/// one UPDATE per RIB entry, as if received from entry peer. Abbreviated MP_REACH_NLRI of dump
+/// entry is expanded to full attribute carrying the prefix. None for other subtypes.
+pub fn parse_rib_entries(
+    subtype: u16,
+    body: &[u8],
+    collector: Ipv4Addr,
+    peers: &[MrtPeer],
+) -> Option<Vec<MrtBgpMessage>> {
+    let (afi, addpath): (u16, bool) = match subtype {
+        2 => (1, false),
+        4 => (2, false),
+        8 => (1, true),
+        10 => (2, true),
+        _ => return None,
+    };
+    let plen = *body.get(4)?;
+    let pbytes = body.get(5..5 + (plen as usize).div_ceil(8))?;
+    let mut pos = 5 + pbytes.len();
+    let count = u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]);
+    pos += 2;
+    let mut ret = Vec::new();
+    for _ in 0..count {
+        let peer = peers.get(u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]) as usize);
+        // peer index and originated time
+        pos += 6;
+        let mut nlri = Vec::new();
+        if addpath {
+            nlri.extend_from_slice(body.get(pos..pos + 4)?);
+            pos += 4;
+        }
+        nlri.push(plen);
+        nlri.extend_from_slice(pbytes);
+        let alen = u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]) as usize;
+        let attrs = body.get(pos + 2..pos + 2 + alen)?;
+        pos += 2 + alen;
+        let peer = match peer {
+            Some(p) => p,
+            None => continue,
+        };
+        if let Some(msg) = rib_entry_update(afi, attrs, &nlri) {
+            ret.push(MrtBgpMessage {
+                peer_as: peer.asn,
+                local_as: 0,
+                peer_ip: peer.ip,
+                local_ip: IpAddr::V4(collector),
+                as4: true,
+                addpath,
+                msg,
+            });
+        }
+    }
+    Some(ret)
+}
+
+/// full UPDATE message from dump entry attributes and NLRI
+fn rib_entry_update(afi: u16, attrs: &[u8], nlri: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(attrs.len() + nlri.len() + 32);
+    let mut mpreach = false;
+    let mut pos = 0;
+    while pos + 3 <= attrs.len() {
+        let (flags, code) = (attrs[pos], attrs[pos + 1]);
+        let (hlen, vlen) = if flags & 0x10 != 0 {
+            (
+                4,
+                u16::from_be_bytes([attrs[pos + 2], *attrs.get(pos + 3)?]) as usize,
+            )
+        } else {
+            (3, attrs[pos + 2] as usize)
+        };
+        let value = attrs.get(pos + hlen..pos + hlen + vlen)?;
+        pos += hlen + vlen;
+        if code != 14 {
+            out.extend_from_slice(&attrs[pos - hlen - vlen..pos]);
+            continue;
+        }
+        // dump keeps only next hop length and next hop
+        let nh = value.get(1..1 + *value.first()? as usize)?;
+        let mut v = afi.to_be_bytes().to_vec();
+        v.push(1);
+        v.push(nh.len() as u8);
+        v.extend_from_slice(nh);
+        v.push(0);
+        v.extend_from_slice(nlri);
+        out.extend_from_slice(&[0x90, 14]);
+        out.extend_from_slice(&(v.len() as u16).to_be_bytes());
+        out.extend_from_slice(&v);
+        mpreach = true;
+    }
+    if afi != 1 && !mpreach {
+        return None;
+    }
+    let mut msg = vec![0xffu8; 16];
+    let total = 19 + 4 + out.len() + if mpreach { 0 } else { nlri.len() };
+    if total > u16::MAX as usize {
+        return None;
+    }
+    msg.extend_from_slice(&(total as u16).to_be_bytes());
+    msg.push(2);
+    msg.extend_from_slice(&[0, 0]);
+    msg.extend_from_slice(&(out.len() as u16).to_be_bytes());
+    msg.extend_from_slice(&out);
+    if !mpreach {
+        msg.extend_from_slice(nlri);
+    }
+    Some(msg)
+}
+
+fn session_params(m: &MrtBgpMessage) -> BgpSessionParams {
+    let caps: Vec<BgpCapability> = ProtoPeer::all_caps(m.peer_as)
+        .into_iter()
+        .filter(|c| match c {
+            BgpCapability::CapASN32(_) => m.as4,
+            BgpCapability::CapAddPath(_) => m.addpath,
+            _ => true,
+        })
+        .collect();
+    let router_id = match m.peer_ip {
+        IpAddr::V4(a) => a,
+        IpAddr::V6(_) => Ipv4Addr::new(0, 0, 0, 0),
+    };
+    let mut params = BgpSessionParams::new(
+        m.local_as,
+        180,
+        if m.peer_ip.is_ipv4() {
+            BgpTransportMode::IPv4
+        } else {
+            BgpTransportMode::IPv6
+        },
+        router_id,
+        caps.clone(),
+    );
+    params.match_caps(&caps);
+    params
+}
+
+pub fn peer_desc(addr: IpAddr, asn: u32) -> BgpPeerDesc {
+    let mut bom = BgpOpenMessage::new();
+    bom.as_num = asn;
+    if let IpAddr::V4(a) = addr {
+        bom.router_id = a;
+    }
+    BgpPeerDesc::new(addr, bom)
+}
+
+/// feeds recorded update into RIB, stamped with recorded time when specified
+pub async fn inject_update(
+    svr: &BgpSvr,
+    sid: BgpSessionId,
+    ts: Option<Timestamp>,
+    upd: BgpUpdateMessage,
+) {
+    let decisions = svr.rib.decide(sid, &upd);
+    let mut rib = svr.rib.rib.write().await;
+    let res = match ts {
+        Some(ts) => Timestamp::with_clock(ts, || rib.handle_update_decided(sid, upd, &decisions)),
+        None => rib.handle_update_decided(sid, upd, &decisions),
+    };
+    if let Err(e) = res {
+        warn!("Replay handle_update: {:?}", e);
+    }
+}
+
+/// decodes recorded UPDATE body, malformed one yields withdraws of its prefixes unless strictparsing is set
+pub fn decode_or_withdraw(
+    svr: &BgpSvr,
+    params: &BgpSessionParams,
+    body: &[u8],
+) -> Result<Vec<BgpUpdateMessage>, ParseError> {
+    match crate::wireparse::decode_update(params, body) {
+        Ok(upd) => Ok(vec![upd]),
+        Err(e) if svr.config.strictparsing => Err(e),
+        Err(e) => {
+            warn!("{}, withdrawing carried prefixes", e);
+            let addpath = crate::wireparse::has_addpath(params);
+            Ok(crate::wireparse::treat_as_withdraw(body, addpath)
+                .into_iter()
+                .map(|w| {
+                    let mut upd = BgpUpdateMessage::new();
+                    upd.withdraws = w;
+                    upd
+                })
+                .collect())
+        }
+    }
+}
+
+/// reads MRT file and feeds recorded updates into RIB at configured pace
+pub async fn run_replay(
+    svr: Arc<BgpSvr>,
+    path: String,
+    speed: ReplaySpeed,
+    original_time: bool,
+) -> io::Result<()> {
+    let rd = BufReader::new(tokio::fs::File::open(path.as_str()).await?);
+    replay_stream(svr, rd, path.as_str(), speed, original_time).await
+}
+
+/// feeds MRT records from stream, name is used for logging
+pub async fn replay_stream<R: AsyncRead + Unpin>(
+    svr: Arc<BgpSvr>,
+    mut rd: R,
+    path: &str,
+    speed: ReplaySpeed,
+    original_time: bool,
+) -> io::Result<()> {
+    let mut sessions: BTreeMap<(IpAddr, u32, IpAddr, u32), BgpSessionId> = BTreeMap::new();
+    let mut start: Option<(f64, tokio::time::Instant)> = None;
+    let (mut records, mut updates) = (0u64, 0u64);
+    let mut peer_index: Option<(Ipv4Addr, Vec<MrtPeer>)> = None;
+    info!("Replaying {} at {:?}", path, speed);
+    loop {
+        let mut hdr = [0u8; 12];
+        match rd.read_exact(&mut hdr).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let secs = u32::from_be_bytes([hdr[0], hdr[1], hdr[2], hdr[3]]);
+        let rtype = u16::from_be_bytes([hdr[4], hdr[5]]);
+        let subtype = u16::from_be_bytes([hdr[6], hdr[7]]);
+        let len = u32::from_be_bytes([hdr[8], hdr[9], hdr[10], hdr[11]]) as usize;
+        if len > MAX_RECORD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("MRT record {} length {} is too large", records + 1, len),
+            ));
+        }
+        let mut body = vec![0u8; len];
+        rd.read_exact(&mut body).await?;
+        records += 1;
+        let (micros, msgs) = match rtype {
+            MRT_BGP4MP => (0, parse_bgp4mp(subtype, &body).into_iter().collect()),
+            MRT_BGP4MP_ET if len >= 4 => (
+                u32::from_be_bytes([body[0], body[1], body[2], body[3]]),
+                parse_bgp4mp(subtype, &body[4..]).into_iter().collect(),
+            ),
+            MRT_TABLE_DUMP_V2 if subtype == TDV2_PEER_INDEX_TABLE => {
+                peer_index = parse_peer_index(&body);
+                if peer_index.is_none() {
+                    warn!("Replay record {}: invalid PEER_INDEX_TABLE", records);
+                }
+                continue;
+            }
+            MRT_TABLE_DUMP_V2 => match peer_index.as_ref() {
+                Some((collector, peers)) => (
+                    0,
+                    parse_rib_entries(subtype, &body, *collector, peers).unwrap_or_default(),
+                ),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let msgs: Vec<MrtBgpMessage> = msgs;
+        if msgs.is_empty() {
+            continue;
+        }
+        if let ReplaySpeed::Factor(f) = speed {
+            let t = secs as f64 + micros as f64 / 1e6;
+            match start {
+                None => start = Some((t, tokio::time::Instant::now())),
+                Some((t0, w0)) => {
+                    let delay = ((t - t0) / f).max(0.0);
+                    tokio::time::sleep_until(w0 + Duration::from_secs_f64(delay)).await;
+                }
+            }
+        }
+        let ts = Timestamp::from_unix(secs as i64, micros);
+        for m in msgs {
+            let params = session_params(&m);
+            // header length below 19 would underflow in decode_message_head
+            if m.msg.len() < 19 || u16::from_be_bytes([m.msg[16], m.msg[17]]) < 19 {
+                continue;
+            }
+            let (mtype, mlen) = match params.decode_message_head(&m.msg[0..19]) {
+                Ok(h) => h,
+                Err(e) => {
+                    warn!("Replay record {}: {:?}", records, e);
+                    continue;
+                }
+            };
+            if mtype != BgpMessageType::Update || m.msg.len() < 19 + mlen {
+                continue;
+            }
+            let upds = match decode_or_withdraw(&svr, &params, &m.msg[19..19 + mlen]) {
+                Ok(u) => u,
+                Err(e) => {
+                    warn!("Replay record {}: {}", records, e);
+                    continue;
+                }
+            };
+            let key = (m.peer_ip, m.peer_as, m.local_ip, m.local_as);
+            let sid = match sessions.get(&key) {
+                Some(s) => *s,
+                None => {
+                    let sid = svr
+                        .register_session(Arc::new(BgpSessionDesc::new(
+                            peer_desc(m.local_ip, m.local_as),
+                            peer_desc(m.peer_ip, m.peer_as),
+                        )))
+                        .await;
+                    sessions.insert(key, sid);
+                    sid
+                }
+            };
+            for upd in upds {
+                inject_update(&svr, sid, if original_time { Some(ts) } else { None }, upd).await;
+            }
+            updates += 1;
+        }
+    }
+    info!(
+        "Replay of {} done: {} records, {} updates",
+        path, records, updates
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_parse() {
+        assert_eq!(
+            "10x".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Factor(10.0)
+        );
+        assert_eq!("max".parse::<ReplaySpeed>().unwrap(), ReplaySpeed::Max);
+        assert!("0".parse::<ReplaySpeed>().is_err());
+        let mut body = vec![0, 0, 0xfd, 0xe8, 0, 0, 0xfd, 0xe9, 0, 0, 0, 1];
+        body.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        body.extend_from_slice(&[0xff; 16]);
+        let m = parse_bgp4mp(4, &body).unwrap();
+        assert_eq!(m.peer_as, 65000);
+        assert_eq!(m.local_as, 65001);
+        assert_eq!(m.peer_ip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(m.local_ip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        assert!(m.as4 && !m.addpath);
+        assert_eq!(m.msg.len(), 16);
+        assert!(parse_bgp4mp(0, &body).is_none());
+    }
+    #[test]
+    fn test_table_dump_v2() {
+        // collector 10.0.0.9, empty view name, peers 10.0.0.1 AS65000 and 2001:db8::1 AS4200000000
+        let mut pit = vec![10, 0, 0, 9, 0, 0, 0, 2];
+        pit.extend_from_slice(&[0, 10, 0, 0, 1, 10, 0, 0, 1, 0xfd, 0xe8]);
+        pit.extend_from_slice(&[3, 10, 0, 0, 2, 0x20, 0x01, 0x0d, 0xb8]);
+        pit.extend_from_slice(&[0; 11]);
+        pit.extend_from_slice(&[1, 0xfa, 0x56, 0xea, 0]);
+        let (collector, peers) = parse_peer_index(&pit).unwrap();
+        assert_eq!(collector, Ipv4Addr::new(10, 0, 0, 9));
+        assert_eq!(peers[0].asn, 65000);
+        assert_eq!(peers[1].ip, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(peers[1].asn, 4200000000);
+        assert!(parse_peer_index(&pit[..pit.len() - 1]).is_none());
+        // 2001:db8:1::/48 from peer 1: ORIGIN, AS_PATH, abbreviated MP_REACH_NLRI
+        let mut rib = vec![
+            0, 0, 0, 1, 48, 0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 1, 0, 1, 0, 0, 0, 0,
+        ];
+        let attrs = [
+            0x40, 1, 1, 0, 0x40, 2, 6, 2, 1, 0xfa, 0x56, 0xea, 0, 0x80, 14, 17, 16, 0x20, 0x01,
+            0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+        ];
+        rib.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+        rib.extend_from_slice(&attrs);
+        let msgs = parse_rib_entries(4, &rib, collector, &peers).unwrap();
+        assert_eq!(msgs.len(), 1);
+        let m = &msgs[0];
+        assert_eq!(m.peer_as, 4200000000);
+        assert_eq!(m.local_ip, IpAddr::V4(collector));
+        let upd = crate::wireparse::decode_update(&session_params(m), &m.msg[19..]).unwrap();
+        match upd.get_mpupdates() {
+            Some(mp) => assert_eq!(mp.addrs.len(), 1),
+            None => panic!("no MP_REACH_NLRI"),
+        }
+        assert!(parse_rib_entries(4, &rib[..rib.len() - 1], collector, &peers).is_none());
+        assert!(parse_rib_entries(6, &rib, collector, &peers).is_none());
+    }
+}
@@ -0,0 +1,246 @@
+//! hot standby replication: primary streams RIB snapshots to secondary over TCP.
+//!
+//! Stream starts with magic and 32 bytes nonce, secondary answers with HMAC-SHA256 of nonce
+//! keyed by replication_secret. Then primary sends frames of one byte type, 8 bytes
+//! big-endian payload length and payload. Snapshot frame payload has the same
+//! format as snapshot file. Heartbeat frame is sent when RIB was not changed.
+use crate::bgprib::BgpRIB;
+use crate::config::SvcConfig;
+use hmac::Mac;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+
+const REPL_MAGIC: &[u8; 4] = b"BXR1";
+const FRAME_SNAPSHOT: u8 = 1;
+const FRAME_HEARTBEAT: u8 = 2;
+const NONCE_LEN: usize = 32;
+
+fn auth_mac(secret: &str, nonce: &[u8]) -> hmac::Hmac<sha2::Sha256> {
+    let mut mac =
+        hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(nonce);
+    mac
+}
+
+/// unique per connection: process random hasher keys, time and counter
+fn new_nonce() -> [u8; NONCE_LEN] {
+    use sha2::Digest;
+    use std::hash::{BuildHasher, Hasher};
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let mut h = std::collections::hash_map::RandomState::new().build_hasher();
+    h.write_u64(COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+    let mut d = sha2::Sha256::new();
+    d.update(h.finish().to_be_bytes());
+    d.update(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_be_bytes(),
+    );
+    d.finalize().into()
+}
+
+fn secret(cfg: &SvcConfig) -> io::Result<&str> {
+    cfg.replication_secret.as_deref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "replication_secret is not set",
+        )
+    })
+}
+
+async fn write_frame(stream: &mut TcpStream, ftype: u8, payload: &[u8]) -> io::Result<()> {
+    let mut hdr = [0u8; 9];
+    hdr[0] = ftype;
+    hdr[1..].copy_from_slice(&(payload.len() as u64).to_be_bytes());
+    stream.write_all(&hdr).await?;
+    stream.write_all(payload).await?;
+    stream.flush().await
+}
+
+async fn read_frame(stream: &mut TcpStream, max: usize) -> io::Result<(u8, Vec<u8>)> {
+    let mut hdr = [0u8; 9];
+    stream.read_exact(&mut hdr).await?;
+    let mut lenbuf = [0u8; 8];
+    lenbuf.copy_from_slice(&hdr[1..]);
+    let len = u64::from_be_bytes(lenbuf);
+    if len > max as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("replication frame of {} bytes exceeds limit {}", len, max),
+        ));
+    }
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok((hdr[0], payload))
+}
+
+/// last encoded snapshot with RIB change counter it was taken at, shared by replicas
+type SnapshotCache = Arc<tokio::sync::Mutex<Option<(u64, Arc<Vec<u8>>)>>>;
+
+/// encodes snapshot RIB by RIB off the runtime, read lock is released between RIBs
+/// so updates are not held for whole snapshot; returns change counter at start
+async fn encode_snapshot(rib: &Arc<RwLock<BgpRIB>>) -> io::Result<(u64, Vec<u8>)> {
+    let counter = rib.read().await.generations.get(None).counter;
+    let mut buf = Vec::new();
+    for part in 0..crate::bgprib::SNAPSHOT_PARTS {
+        let r = rib.clone().read_owned().await;
+        buf = tokio::task::spawn_blocking(move || {
+            r.write_snapshot_part(part, &mut buf)
+                .map(|_| buf)
+                .map_err(|e| io::Error::other(format!("{:?}", e)))
+        })
+        .await
+        .map_err(io::Error::other)??;
+    }
+    Ok((counter, buf))
+}
+
+/// snapshot at current RIB change counter, encoded once for all replicas
+async fn current_snapshot(
+    rib: &Arc<RwLock<BgpRIB>>,
+    cache: &SnapshotCache,
+) -> io::Result<(u64, Arc<Vec<u8>>)> {
+    let mut c = cache.lock().await;
+    let counter = rib.read().await.generations.get(None).counter;
+    if let Some((cnt, ref buf)) = *c {
+        if cnt == counter {
+            return Ok((cnt, buf.clone()));
+        }
+    }
+    let (cnt, buf) = encode_snapshot(rib).await?;
+    let buf = Arc::new(buf);
+    *c = Some((cnt, buf.clone()));
+    Ok((cnt, buf))
+}
+
+/// streams snapshots to one secondary while connection is alive
+async fn serve_replica(
+    mut stream: TcpStream,
+    rib: Arc<RwLock<BgpRIB>>,
+    cfg: Arc<SvcConfig>,
+    cache: SnapshotCache,
+) -> io::Result<()> {
+    let nonce = new_nonce();
+    stream.write_all(REPL_MAGIC).await?;
+    stream.write_all(&nonce).await?;
+    let mut answer = [0u8; NONCE_LEN];
+    tokio::time::timeout(cfg.replication_timeout, stream.read_exact(&mut answer)).await??;
+    if auth_mac(secret(&cfg)?, &nonce)
+        .verify_slice(&answer)
+        .is_err()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "authentication failed",
+        ));
+    }
+    let interval = cfg.replication_interval;
+    let mut sent: Option<u64> = None;
+    loop {
+        let counter = rib.read().await.generations.get(None).counter;
+        let (counter, snapshot) = if sent == Some(counter) {
+            (counter, None)
+        } else {
+            let (cnt, buf) = current_snapshot(&rib, &cache).await?;
+            (cnt, Some(buf))
+        };
+        match snapshot {
+            Some(buf) => write_frame(&mut stream, FRAME_SNAPSHOT, &buf).await?,
+            None => write_frame(&mut stream, FRAME_HEARTBEAT, &[]).await?,
+        }
+        sent = Some(counter);
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// accepts secondaries on primary
+pub async fn run_primary(
+    cfg: Arc<SvcConfig>,
+    rib: Arc<RwLock<BgpRIB>>,
+    listen: SocketAddr,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(listen).await?;
+    info!("Replication listening on {}", listen);
+    let cache = SnapshotCache::default();
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        info!("Replica connected from {}", peer);
+        let rib = rib.clone();
+        let cfg = cfg.clone();
+        let cache = cache.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_replica(stream, rib, cfg, cache).await {
+                info!("Replica {} disconnected: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// mirrors primary RIB until connection is lost, last_seen is updated on every frame
+pub async fn mirror(
+    cfg: &Arc<SvcConfig>,
+    rib: &Arc<RwLock<BgpRIB>>,
+    primary: SocketAddr,
+    last_seen: &mut Instant,
+) -> io::Result<()> {
+    let frame_timeout = cfg.replication_timeout.max(cfg.replication_interval * 2);
+    let mut stream = tokio::time::timeout(frame_timeout, TcpStream::connect(primary)).await??;
+    let mut magic = [0u8; 4];
+    tokio::time::timeout(frame_timeout, stream.read_exact(&mut magic)).await??;
+    if &magic != REPL_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid replication stream",
+        ));
+    }
+    let mut nonce = [0u8; NONCE_LEN];
+    tokio::time::timeout(frame_timeout, stream.read_exact(&mut nonce)).await??;
+    let answer = auth_mac(secret(cfg)?, &nonce).finalize().into_bytes();
+    stream.write_all(&answer).await?;
+    info!("Mirroring primary {}", primary);
+    loop {
+        let (ftype, payload) = tokio::time::timeout(
+            frame_timeout,
+            read_frame(&mut stream, cfg.replication_max_frame),
+        )
+        .await??;
+        *last_seen = Instant::now();
+        match ftype {
+            FRAME_SNAPSHOT => {
+                let scfg = cfg.clone();
+                let newrib = tokio::task::spawn_blocking(move || {
+                    BgpRIB::read_snapshot(&scfg, io::Cursor::new(payload))
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .map_err(io::Error::other)?
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                rib.write().await.replace_from(newrib);
+                debug!("Replica snapshot applied");
+            }
+            FRAME_HEARTBEAT => {}
+            t => warn!("Unknown replication frame type {}", t),
+        }
+    }
+}
+
+/// runs secondary, returns when primary is unreachable longer than replication_timeout
+pub async fn run_secondary(cfg: Arc<SvcConfig>, rib: Arc<RwLock<BgpRIB>>, primary: SocketAddr) {
+    let mut last_seen = Instant::now();
+    loop {
+        if let Err(e) = mirror(&cfg, &rib, primary, &mut last_seen).await {
+            warn!("Replication from {} failed: {}", primary, e);
+        }
+        if last_seen.elapsed() > cfg.replication_timeout {
+            return;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,334 @@
+//! scheduled routing summary reports
+use crate::bgprib::*;
+use crate::config::{ErrorConfig, SvcConfig};
+use crate::timestamp::Timestamp;
+use chrono::{Datelike, Timelike};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// maximum routes listed in each report section
+const REPORT_LIST_LIMIT: usize = 1000;
+
+/// report schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportPeriod {
+    Daily,
+    Weekly,
+}
+impl FromStr for ReportPeriod {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "daily" => Ok(ReportPeriod::Daily),
+            "weekly" => Ok(ReportPeriod::Weekly),
+            _ => Err(ErrorConfig::from_str("invalid report period")),
+        }
+    }
+}
+impl ReportPeriod {
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            ReportPeriod::Daily => chrono::Duration::days(1),
+            ReportPeriod::Weekly => chrono::Duration::weeks(1),
+        }
+    }
+    /// time left until next local midnight, or next Monday midnight for weekly reports
+    pub fn until_next(&self) -> std::time::Duration {
+        let now = chrono::Local::now();
+        let mut secs = 86400 - now.num_seconds_from_midnight() as u64;
+        if *self == ReportPeriod::Weekly {
+            secs += (6 - now.weekday().num_days_from_monday() as u64) * 86400;
+        }
+        std::time::Duration::from_secs(secs)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportFlapper {
+    pub route: String,
+    pub changes: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportOriginChange {
+    pub route: String,
+    pub origins: Vec<u32>,
+}
+
+/// summary for one RIB
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RibReport {
+    pub routes: usize,
+    pub new_count: usize,
+    pub new_prefixes: Vec<String>,
+    pub withdrawn_count: usize,
+    pub withdrawn_prefixes: Vec<String>,
+    pub top_flappers: Vec<ReportFlapper>,
+    pub origin_changes_count: usize,
+    pub origin_changes: Vec<ReportOriginChange>,
+    /// active prefixes in special-use and transition blocks
+    pub special: crate::addrclass::AddrClassReport,
+}
+
+/// routing summary for period
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteReport {
+    pub period: ReportPeriod,
+    pub from: Timestamp,
+    pub to: Timestamp,
+    pub ribs: BTreeMap<String, RibReport>,
+    /// RPKI validation is not available, always None
+    pub rpki_invalid: Option<u64>,
+}
+
+fn origin_as(e: &crate::bgpattrs::BgpAttrEntry) -> Option<u32> {
+    e.attrs.aspath.value.last().map(|x| x.value)
+}
+
+/// builds RIB summary for changes made since specified time
+pub fn rib_report<T: BgpRIBKey + std::string::ToString>(
+    safi: &BgpRIBSafi<T>,
+    since: &Timestamp,
+    top: usize,
+) -> RibReport {
+    let mut ret = RibReport {
+        routes: safi.items.len(),
+        special: safi.addrclass.report(),
+        ..Default::default()
+    };
+    let mut flappers: Vec<(usize, String)> = Vec::new();
+    for (route, bse) in safi.items.iter() {
+        let mut first: Option<Timestamp> = None;
+        let mut last: Option<Timestamp> = None;
+        let mut active = false;
+        let mut changes = 0;
+        let mut origins = BTreeSet::new();
+        for (_, pe) in bse.items.iter() {
+            for (_, hist) in pe.items.iter() {
+                if let Some((ts, _)) = hist.items.iter().next() {
+                    first = Some(first.map_or(*ts, |f| f.min(*ts)));
+                }
+                if let Some((ts, rec)) = hist.items.iter().last() {
+                    last = Some(last.map_or(*ts, |l| l.max(*ts)));
+                    active |= rec.active;
+                }
+                // state at period start counts as well
+                if let Some((_, rec)) = hist.items.range(..*since).last() {
+                    if rec.active {
+                        origins.extend(origin_as(rec));
+                    }
+                }
+                for (_, rec) in hist.items.range(*since..) {
+                    changes += 1;
+                    if rec.active {
+                        origins.extend(origin_as(rec));
+                    }
+                }
+            }
+        }
+        if changes == 0 {
+            continue;
+        }
+        if active && first.map(|f| f >= *since).unwrap_or(false) {
+            ret.new_count += 1;
+            if ret.new_prefixes.len() < REPORT_LIST_LIMIT {
+                ret.new_prefixes.push(route.to_string());
+            }
+        }
+        if !active && last.map(|l| l >= *since).unwrap_or(false) {
+            ret.withdrawn_count += 1;
+            if ret.withdrawn_prefixes.len() < REPORT_LIST_LIMIT {
+                ret.withdrawn_prefixes.push(route.to_string());
+            }
+        }
+        if origins.len() > 1 {
+            ret.origin_changes_count += 1;
+            if ret.origin_changes.len() < REPORT_LIST_LIMIT {
+                ret.origin_changes.push(ReportOriginChange {
+                    route: route.to_string(),
+                    origins: origins.into_iter().collect(),
+                });
+            }
+        }
+        if changes > 1 {
+            flappers.push((changes, route.to_string()));
+        }
+    }
+    flappers.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    ret.top_flappers = flappers
+        .into_iter()
+        .take(top)
+        .map(|(changes, route)| ReportFlapper { route, changes })
+        .collect();
+    ret
+}
+
+impl BgpRIB {
+    /// routing summary of unicast RIBs for period ending now
+    pub fn route_report(&self, period: ReportPeriod, top: usize) -> RouteReport {
+        let to = Timestamp::now();
+        let from = Timestamp::from(*to - period.duration());
+        let mut ribs = BTreeMap::new();
+        ribs.insert("ipv4u".to_string(), rib_report(&self.ipv4u, &from, top));
+        ribs.insert("ipv6u".to_string(), rib_report(&self.ipv6u, &from, top));
+        RouteReport {
+            period,
+            from,
+            to,
+            ribs,
+            rpki_invalid: None,
+        }
+    }
+}
+
+fn html_list(out: &mut String, title: &str, count: usize, items: &[String]) {
+    out.push_str(&format!("<h3>{} ({})</h3>\n<ul>\n", title, count));
+    for i in items.iter() {
+        out.push_str(&format!("<li>{}</li>\n", html_escape::encode_text(i)));
+    }
+    out.push_str("</ul>\n");
+}
+
+/// renders report as standalone HTML page
+pub fn render_html(r: &RouteReport) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>bgpexplorer routing report</title></head><body>\n");
+    out.push_str(&format!(
+        "<h1>Routing report {} - {}</h1>\n",
+        html_escape::encode_text(&r.from.to_string()),
+        html_escape::encode_text(&r.to.to_string())
+    ));
+    for (name, rib) in r.ribs.iter() {
+        out.push_str(&format!("<h2>{} ({} routes)</h2>\n", name, rib.routes));
+        html_list(&mut out, "New prefixes", rib.new_count, &rib.new_prefixes);
+        html_list(
+            &mut out,
+            "Withdrawn prefixes",
+            rib.withdrawn_count,
+            &rib.withdrawn_prefixes,
+        );
+        out.push_str("<h3>Top flappers</h3>\n<table><tr><th>Route</th><th>Changes</th></tr>\n");
+        for f in rib.top_flappers.iter() {
+            out.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td></tr>\n",
+                html_escape::encode_text(&f.route),
+                f.changes
+            ));
+        }
+        out.push_str("</table>\n");
+        out.push_str(&format!(
+            "<h3>Origin changes ({})</h3>\n<table><tr><th>Route</th><th>Origins</th></tr>\n",
+            rib.origin_changes_count
+        ));
+        for o in rib.origin_changes.iter() {
+            out.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td></tr>\n",
+                html_escape::encode_text(&o.route),
+                o.origins
+                    .iter()
+                    .map(|a| format!("AS{}", a))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+        out.push_str("</table>\n");
+        out.push_str(&format!(
+            "<h3>Special-use prefixes ({}, transition {})</h3>\n<table><tr><th>Class</th><th>Prefixes</th></tr>\n",
+            rib.special.special, rib.special.transition
+        ));
+        for (class, n) in rib.special.classes.iter() {
+            out.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td></tr>\n",
+                serde_json::to_value(class)
+                    .ok()
+                    .and_then(|v| v.as_str().map(|s| s.to_string()))
+                    .unwrap_or_default(),
+                n
+            ));
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("</body></html>\n");
+    out
+}
+
+/// writes JSON and HTML report files, returns rendered HTML
+fn store_report(dir: &str, r: &RouteReport) -> std::io::Result<String> {
+    let stem = format!("{}/report-{}", dir, r.to.format("%Y%m%d-%H%M"));
+    let json = serde_json::to_vec_pretty(r).map_err(std::io::Error::other)?;
+    std::fs::write(stem.clone() + ".json", json)?;
+    let html = render_html(r);
+    std::fs::write(stem.clone() + ".html", html.as_bytes())?;
+    info!("Report stored: {}.json", stem);
+    Ok(html)
+}
+
+/// renders reports on schedule
+pub async fn run_reports(cfg: Arc<SvcConfig>, rib: Arc<RwLock<BgpRIB>>) {
+    let dir = match cfg.report_dir.as_ref() {
+        Some(d) => d.clone(),
+        None => return,
+    };
+    loop {
+        tokio::time::sleep(cfg.report_period.until_next()).await;
+        let report = rib
+            .read()
+            .await
+            .route_report(cfg.report_period, cfg.report_top);
+        let html = match store_report(dir.as_str(), &report) {
+            Ok(h) => h,
+            Err(e) => {
+                warn!("Unable to store report: {}", e);
+                continue;
+            }
+        };
+        if cfg.report_email {
+            if let Some(smtp) = cfg.smtp.as_ref() {
+                let subject = format!("bgpexplorer {:?} routing report", cfg.report_period);
+                if let Err(e) =
+                    crate::smtp::send_mail(smtp, subject.as_str(), html.as_str(), true).await
+                {
+                    warn!("Unable to email report: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgpattrs::*;
+    use crate::compact::CompactASPath;
+    use crate::config::HistoryChangeMode;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_report_rib() {
+        let mut safi: BgpRIBSafi<BgpAddrV4> = BgpRIBSafi::new(10, HistoryChangeMode::EveryUpdate);
+        let since = Timestamp::now();
+        let mut attrs = BgpAttrs::new();
+        attrs.aspath = Arc::new(CompactASPath::from(vec![100, 200]));
+        let mut hist = BgpAttrHistory::new();
+        hist.items.insert(
+            Timestamp::now(),
+            BgpAttrEntry::new(true, Arc::new(attrs), None),
+        );
+        let mut pe = BgpPathEntry::new();
+        pe.items.insert(0, hist);
+        let mut bse = BgpSessionEntry::new();
+        bse.items.insert(1, pe);
+        safi.items
+            .insert(BgpAddrV4::new(std::net::Ipv4Addr::new(10, 0, 0, 0), 8), bse);
+        let r = rib_report(&safi, &since, 10);
+        assert_eq!(r.routes, 1);
+        assert_eq!(r.new_count, 1);
+        assert_eq!(r.new_prefixes, vec!["10.0.0.0/8".to_string()]);
+        assert_eq!(r.withdrawn_count, 0);
+    }
+}
@@ -0,0 +1,89 @@
+//! attribute-based history depth, overrides historydepth for matching routes
+use crate::bgpattrs::BgpAttrs;
+use crate::ribfilter::{FilterItemMatchResult, RouteFilter};
+
+/// history depth for routes with attributes matching filter
+#[derive(Debug, Clone)]
+pub struct RetentionRule {
+    pub filter: String,
+    pub depth: usize,
+}
+
+/// parses "filter => depth; filter => depth", depth is number of kept changes or full
+pub fn parse_rules(s: &str) -> Result<Vec<RetentionRule>, String> {
+    let mut ret = Vec::new();
+    for rule in s.split(';').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let (filter, depth) = rule
+            .rsplit_once("=>")
+            .ok_or_else(|| format!("missing => in rule '{}'", rule))?;
+        let depth = match depth.trim() {
+            "full" => usize::MAX,
+            d => match d.parse::<usize>() {
+                Ok(n) if n > 0 => n,
+                _ => return Err(format!("invalid depth in rule '{}'", rule)),
+            },
+        };
+        // filter without recognized terms would match every route
+        if RouteFilter::fromstr(filter.trim()).terms.is_empty() {
+            return Err(format!("invalid filter in rule '{}'", rule));
+        }
+        ret.push(RetentionRule {
+            filter: filter.trim().to_string(),
+            depth,
+        });
+    }
+    Ok(ret)
+}
+
+/// compiled rules, first rule matching route attributes wins
+#[derive(Default)]
+pub struct RetentionRules {
+    rules: Vec<(RouteFilter, usize)>,
+}
+impl RetentionRules {
+    pub fn new(rules: &[RetentionRule]) -> RetentionRules {
+        RetentionRules {
+            rules: rules
+                .iter()
+                .map(|r| (RouteFilter::fromstr(r.filter.as_str()), r.depth))
+                .collect(),
+        }
+    }
+    /// history depth for route with specified attributes
+    pub fn depth(&self, attrs: &BgpAttrs, default: usize) -> usize {
+        for (flt, depth) in self.rules.iter() {
+            if flt.match_attr(attrs) == FilterItemMatchResult::Yes {
+                return *depth;
+            }
+        }
+        default
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::CompactCommunityList;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_retention_rules() {
+        let rules = parse_rules("community:65000:100 => full; community:65000:200 => 5").unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].filter, "community:65000:100");
+        assert_eq!(rules[0].depth, usize::MAX);
+        assert_eq!(rules[1].depth, 5);
+        assert!(parse_rules("community:65000:100").is_err());
+        assert!(parse_rules("community:65000:100 => 0").is_err());
+        assert!(parse_rules("c:65000:100 => full").is_err());
+        assert!(parse_rules(" => full").is_err());
+        let rr = RetentionRules::new(&rules);
+        assert_eq!(rr.depth(&BgpAttrs::new(), 2), 2);
+        let mut attrs = BgpAttrs::new();
+        attrs.comms =
+            std::sync::Arc::new(CompactCommunityList::from_vec(vec![BgpCommunity::from(
+                65000, 200,
+            )]));
+        assert_eq!(rr.depth(&attrs, 2), 5);
+    }
+}
@@ -1,7 +1,10 @@
 use crate::bgpattrs::BgpAttrs;
 use crate::bgprib::{BgpRIBKey, BgpRIBSafi, BgpSessionEntry, ClonableIterator};
+use crate::bgpsvc::BgpSessionId;
 use crate::clone_iter;
+use crate::compact::CompactASPath;
 use crate::service::*;
+use crate::sortindex::SortKey;
 use regex::Regex;
 use std::collections::BTreeSet;
 use std::ops::RangeInclusive;
@@ -13,7 +16,7 @@ pub struct SortIter<T> {
 }
 impl<T> SortIter<T> {
     pub fn new(
-        srciter: &mut (dyn std::iter::Iterator<Item = T>),
+        srciter: &mut dyn std::iter::Iterator<Item = T>,
         fnc: &dyn Fn(&T, &T) -> std::cmp::Ordering,
     ) -> SortIter<T> {
         let mut v = Vec::<T>::new();
@@ -111,10 +114,12 @@ impl From<bool> for FilterItemMatchResult {
 #[derive(Debug, PartialEq, Eq)]
 pub enum FilterASPath {
     Empty,
-    Contains(BgpASpath),
-    StartsWith(BgpASpath),
-    EndsWith(BgpASpath),
-    FullMatch(BgpASpath),
+    Contains(CompactASPath),
+    StartsWith(CompactASPath),
+    EndsWith(CompactASPath),
+    FullMatch(CompactASPath),
+    /// path traverses sequence, but not as origin
+    Transit(CompactASPath),
 }
 pub struct FilterRegex {
     pub restr: std::string::String,
@@ -172,8 +177,19 @@ pub enum FilterItem {
     Num(u64),
     Regexp(FilterRegex),
     ExtCommunity(FilterExtComm),
+    /// AGGREGATOR or ATOMIC_AGGREGATE present, with AS - AGGREGATOR of that AS
+    Aggregator(Option<u32>),
+    /// attribute value compares to number as given, routes without attribute do not match
+    Bound(SortKey, std::cmp::Ordering, u32),
+    /// peer AS of session route was received from, with sessions of that AS filled by
+    /// RouteFilter::resolve_peers; matched per session, not by attributes
+    PeerAS(u32, BTreeSet<BgpSessionId>),
 }
 impl FilterItem {
+    /// matched by RouteFilter::match_session instead of route and attributes
+    pub fn is_session(&self) -> bool {
+        matches!(self, FilterItem::PeerAS(..))
+    }
     pub fn kind(&self) -> FilterItemKind {
         match self {
             FilterItem::None => FilterItemKind::Attr,
@@ -213,6 +229,13 @@ pub trait FilterMatchRoute:
     fn get_supernet_range(_fi: &FilterItem) -> Option<RangeInclusive<Self>> {
         None
     }
+    /// keys range for routes with network address between start and end
+    fn get_addr_range(
+        _start: &std::net::IpAddr,
+        _end: &std::net::IpAddr,
+    ) -> Option<RangeInclusive<Self>> {
+        None
+    }
 }
 impl FilterMatchRoute for std::net::IpAddr {
     fn match_item(&self, fi: &FilterItem) -> FilterItemMatchResult {
@@ -273,6 +296,17 @@ impl FilterMatchRoute for BgpAddrV4 {
             _ => None,
         }
     }
+    fn get_addr_range(
+        start: &std::net::IpAddr,
+        end: &std::net::IpAddr,
+    ) -> Option<RangeInclusive<Self>> {
+        match (start, end) {
+            (std::net::IpAddr::V4(s), std::net::IpAddr::V4(e)) if s <= e => {
+                Some(BgpAddrV4::new(*s, 0)..=BgpAddrV4::new(*e, 32))
+            }
+            _ => None,
+        }
+    }
 }
 impl FilterMatchRoute for BgpAddrV6 {
     fn match_item(&self, fi: &FilterItem) -> FilterItemMatchResult {
@@ -301,6 +335,17 @@ impl FilterMatchRoute for BgpAddrV6 {
             _ => None,
         }
     }
+    fn get_addr_range(
+        start: &std::net::IpAddr,
+        end: &std::net::IpAddr,
+    ) -> Option<RangeInclusive<Self>> {
+        match (start, end) {
+            (std::net::IpAddr::V6(s), std::net::IpAddr::V6(e)) if s <= e => {
+                Some(BgpAddrV6::new(*s, 0)..=BgpAddrV6::new(*e, 128))
+            }
+            _ => None,
+        }
+    }
 }
 impl FilterMatchRoute for BgpAddrL2 {
     fn match_item(&self, fi: &FilterItem) -> FilterItemMatchResult {
@@ -499,6 +544,13 @@ impl<T: BgpItem<T> + FilterMatchRoute + Clone> FilterMatchRoute for Labeled<T> {
         T::get_supernet_range(fi)
             .map(|r| Self::new_nl((*(r.start())).clone())..=Self::new_nl((*(r.end())).clone()))
     }
+    fn get_addr_range(
+        start: &std::net::IpAddr,
+        end: &std::net::IpAddr,
+    ) -> Option<RangeInclusive<Self>> {
+        T::get_addr_range(start, end)
+            .map(|r| Self::new_nl((*(r.start())).clone())..=Self::new_nl((*(r.end())).clone()))
+    }
 }
 impl FilterMatchRoute for BgpExtCommunity {
     fn match_item(&self, fi: &FilterItem) -> FilterItemMatchResult {
@@ -583,6 +635,42 @@ impl<'a, 'b, T: FilterMatchRoute + BgpRIBKey> RouteFilterSubnets<'a, 'b, T> {
         }
     }
 }
+impl<'a, 'b, T: FilterMatchRoute + BgpRIBKey> RouteFilterSubnets<'a, 'b, T> {
+    /// entries in order of sort index, each one at its own lowest (highest when desc)
+    /// value, followed by entries whose active records lack attribute
+    pub fn sorted(
+        filter: &'a RouteFilter,
+        maxdepth: usize,
+        onlyactive: bool,
+        srcafi: &'b BgpRIBSafi<T>,
+        key: SortKey,
+        desc: bool,
+    ) -> RouteFilterSubnets<'a, 'b, T> {
+        let idx = &srcafi.idx_sort.get(key).idx;
+        let vals: Box<dyn Iterator<Item = (&'b u32, &'b BTreeSet<T>)> + 'b> = if desc {
+            Box::new(idx.iter().rev())
+        } else {
+            Box::new(idx.iter())
+        };
+        let missing = srcafi
+            .idx_sort
+            .missing(key)
+            .into_iter()
+            .flat_map(|m| m.iter())
+            .filter_map(move |k| srcafi.items.get_key_value(k))
+            .filter(move |(_, e)| key.entry_value(e, desc).is_none());
+        Self {
+            filter: RouteFilterParams::new(filter, maxdepth, onlyactive),
+            srcitr: clone_iter!(vals
+                .flat_map(move |(v, keys)| {
+                    keys.iter()
+                        .filter_map(move |k| srcafi.items.get_key_value(k))
+                        .filter(move |(_, e)| key.entry_value(e, desc) == Some(*v))
+                })
+                .chain(missing)),
+        }
+    }
+}
 impl<'a, 'b, T: FilterMatchRoute + BgpRIBKey> std::iter::Iterator
     for RouteFilterSubnets<'a, 'b, T>
 {
@@ -592,29 +680,34 @@ impl<'a, 'b, T: FilterMatchRoute + BgpRIBKey> std::iter::Iterator
             match self.srcitr.next() {
                 None => break,
                 Some(q) => {
-                    if q.1.items.iter().any(|ssitr| {
-                        ssitr.1.items.iter().any(|pitr| {
-                            pitr.1
-                                .items
-                                .iter()
-                                .filter(|hr| {
-                                    if self.filter.onlyactive {
-                                        hr.1.active
+                    if q.1
+                        .items
+                        .iter()
+                        .filter(|ssitr| self.filter.filter.match_session(*ssitr.0))
+                        .any(|ssitr| {
+                            ssitr.1.items.iter().any(|pitr| {
+                                pitr.1
+                                    .items
+                                    .iter()
+                                    .filter(|hr| {
+                                        if self.filter.onlyactive {
+                                            hr.1.active
+                                        } else {
+                                            true
+                                        }
+                                    })
+                                    .skip(if pitr.1.items.len() > self.filter.maxdepth {
+                                        pitr.1.items.len() - self.filter.maxdepth
                                     } else {
-                                        true
-                                    }
-                                })
-                                .skip(if pitr.1.items.len() > self.filter.maxdepth {
-                                    pitr.1.items.len() - self.filter.maxdepth
-                                } else {
-                                    0
-                                })
-                                .any(|histitem| {
-                                    self.filter.filter.match_route(q.0, &histitem.1.attrs)
-                                        == FilterItemMatchResult::Yes
-                                })
+                                        0
+                                    })
+                                    .any(|histitem| {
+                                        self.filter.filter.match_route(q.0, &histitem.1.attrs)
+                                            == FilterItemMatchResult::Yes
+                                    })
+                            })
                         })
-                    }) {
+                    {
                         return Some(q);
                     }
                 }
@@ -658,29 +751,34 @@ impl<'a, 'b, T: FilterMatchRoute + BgpRIBKey> std::iter::Iterator
             match self.srcitr.next() {
                 None => break,
                 Some(q) => {
-                    if q.1.items.iter().any(|ssitr| {
-                        ssitr.1.items.iter().any(|pitr| {
-                            pitr.1
-                                .items
-                                .iter()
-                                .filter(|hr| {
-                                    if self.filter.onlyactive {
-                                        hr.1.active
+                    if q.1
+                        .items
+                        .iter()
+                        .filter(|ssitr| self.filter.filter.match_session(*ssitr.0))
+                        .any(|ssitr| {
+                            ssitr.1.items.iter().any(|pitr| {
+                                pitr.1
+                                    .items
+                                    .iter()
+                                    .filter(|hr| {
+                                        if self.filter.onlyactive {
+                                            hr.1.active
+                                        } else {
+                                            true
+                                        }
+                                    })
+                                    .skip(if pitr.1.items.len() > self.filter.maxdepth {
+                                        pitr.1.items.len() - self.filter.maxdepth
                                     } else {
-                                        true
-                                    }
-                                })
-                                .skip(if pitr.1.items.len() > self.filter.maxdepth {
-                                    pitr.1.items.len() - self.filter.maxdepth
-                                } else {
-                                    0
-                                })
-                                .any(|histitem| {
-                                    self.filter.filter.match_super_route(q.0, &histitem.1.attrs)
-                                        == FilterItemMatchResult::Yes
-                                })
+                                        0
+                                    })
+                                    .any(|histitem| {
+                                        self.filter.filter.match_super_route(q.0, &histitem.1.attrs)
+                                            == FilterItemMatchResult::Yes
+                                    })
+                            })
                         })
-                    }) {
+                    {
                         return Some(q);
                     }
                 }
@@ -698,6 +796,14 @@ impl RouteFilter {
     pub fn new() -> RouteFilter {
         RouteFilter { terms: Vec::new() }
     }
+    /// first c: or community: item of filter string which is neither asn:value nor
+    /// known community name
+    pub fn unknown_community(st: &str) -> Option<&str> {
+        st.split(' ')
+            .map(|s| s.trim_start_matches(['+', '-']))
+            .filter(|s| s.starts_with("c:") || s.starts_with("community:"))
+            .find(|s| !matches!(FilterItem::parse(s), FilterItem::Community(_)))
+    }
     pub fn parse(&mut self, st: &str) {
         for s in st.split(' ') {
             if let Some(t) = FilterTerm::parse(s) {
@@ -705,6 +811,89 @@ impl RouteFilter {
             }
         }
     }
+    /// adds terms of route query language expression, see querydsl
+    pub fn parse_query(&mut self, q: &str) -> Result<(), String> {
+        self.terms.append(&mut crate::querydsl::parse(q)?);
+        Ok(())
+    }
+    /// adds AS path terms from transit_as and peer_as URL parameters, community term
+    /// from community parameter (asn:value or symbolic name) and aggregated term;
+    /// unknown community name is an error
+    pub fn extract_params(
+        &mut self,
+        hashmap: &std::collections::HashMap<String, String>,
+    ) -> Result<(), String> {
+        if let Some(n) = get_url_param::<u32>(hashmap, "transit_as") {
+            self.terms.push(FilterTerm {
+                predicate: FilterItemMatchResult::Unknown,
+                item: FilterItem::ASPath(FilterASPath::Transit(CompactASPath::from(vec![n]))),
+            });
+        };
+        if let Some(s) = get_url_param::<String>(hashmap, "community") {
+            match FilterItem::parse(&format!("community:{}", s)) {
+                FilterItem::Community(c) => self.terms.push(FilterTerm {
+                    predicate: FilterItemMatchResult::Unknown,
+                    item: FilterItem::Community(c),
+                }),
+                _ => return Err(format!("unknown community {}", s)),
+            }
+        };
+        if let Some(b) = get_url_param::<bool>(hashmap, "aggregated") {
+            self.terms.push(FilterTerm {
+                predicate: if b {
+                    FilterItemMatchResult::Unknown
+                } else {
+                    FilterItemMatchResult::No
+                },
+                item: FilterItem::Aggregator(None),
+            });
+        };
+        for key in [SortKey::Med, SortKey::LocalPref, SortKey::PathLen].iter() {
+            for (sfx, ord) in [
+                ("gt", std::cmp::Ordering::Greater),
+                ("lt", std::cmp::Ordering::Less),
+                ("eq", std::cmp::Ordering::Equal),
+            ]
+            .iter()
+            {
+                let name = format!("{}_{}", key.name(), sfx);
+                if let Some(n) = get_url_param::<u32>(hashmap, &name) {
+                    self.terms.push(FilterTerm {
+                        predicate: FilterItemMatchResult::Unknown,
+                        item: FilterItem::Bound(*key, *ord, n),
+                    });
+                }
+            }
+        }
+        if let Some(n) = get_url_param::<u32>(hashmap, "peer_as") {
+            self.terms.push(FilterTerm {
+                predicate: FilterItemMatchResult::Unknown,
+                item: FilterItem::ASPath(FilterASPath::StartsWith(CompactASPath::from(vec![n]))),
+            });
+        };
+        Ok(())
+    }
+    /// fills sessions of PeerAS terms from peer AS of known sessions
+    pub fn resolve_peers(&mut self, peers: &std::collections::BTreeMap<BgpSessionId, u32>) {
+        for t in self.terms.iter_mut() {
+            if let FilterItem::PeerAS(asn, ref mut sessions) = t.item {
+                *sessions = peers
+                    .iter()
+                    .filter(|(_, a)| **a == asn)
+                    .map(|(s, _)| *s)
+                    .collect();
+            }
+        }
+    }
+    /// whether routes of session pass PeerAS terms
+    pub fn match_session(&self, sess: BgpSessionId) -> bool {
+        self.terms.iter().all(|t| match t.item {
+            FilterItem::PeerAS(_, ref sessions) => {
+                sessions.contains(&sess) != (t.predicate == FilterItemMatchResult::No)
+            }
+            _ => true,
+        })
+    }
     pub fn fromstr(st: &str) -> RouteFilter {
         let mut ret = Self::new();
         ret.parse(st);
@@ -718,6 +907,16 @@ impl RouteFilter {
     ) -> RouteFilterSubnets<'a, 'a, T> {
         RouteFilterSubnets::new(self, takemaxdepth, takeonlyactive, safi)
     }
+    pub fn iter_sorted<'a, T: FilterMatchRoute + BgpRIBKey>(
+        &'a self,
+        safi: &'a BgpRIBSafi<T>,
+        takemaxdepth: usize,
+        takeonlyactive: bool,
+        key: SortKey,
+        desc: bool,
+    ) -> RouteFilterSubnets<'a, 'a, T> {
+        RouteFilterSubnets::sorted(self, takemaxdepth, takeonlyactive, safi, key, desc)
+    }
     pub fn iter_super_nets<'a, T: FilterMatchRoute + BgpRIBKey>(
         &'a self,
         safi: &'a BgpRIBSafi<T>,
@@ -753,6 +952,9 @@ impl RouteFilter {
         }
         let mut cnt: usize = 0;
         for i in self.terms.iter() {
+            if i.item.is_session() {
+                continue;
+            }
             if i.item.kind() == FilterItemKind::Attr {
                 match i.match_attr(attr) {
                     FilterItemMatchResult::Unknown => {}
@@ -779,6 +981,9 @@ impl RouteFilter {
         let mut result_route = FilterItemMatchResult::Yes;
         let mut result_attr = FilterItemMatchResult::Yes;
         for i in self.terms.iter() {
+            if i.item.is_session() {
+                continue;
+            }
             if i.item.kind() == FilterItemKind::Attr {
                 match i.match_route(route, attr) {
                     FilterItemMatchResult::Unknown => result_attr = FilterItemMatchResult::Unknown,
@@ -814,6 +1019,9 @@ impl RouteFilter {
         let mut result_route = FilterItemMatchResult::Yes;
         let mut result_attr = FilterItemMatchResult::Yes;
         for i in self.terms.iter() {
+            if i.item.is_session() {
+                continue;
+            }
             if i.item.kind() == FilterItemKind::Attr {
                 match i.match_super_route(route, attr) {
                     FilterItemMatchResult::Unknown => result_attr = FilterItemMatchResult::Unknown,
@@ -894,6 +1102,9 @@ impl RouteFilter {
                     FilterASPath::FullMatch(p) => p.value.iter().for_each(|x| {
                         ret.insert(*x);
                     }),
+                    FilterASPath::Transit(p) => p.value.iter().for_each(|x| {
+                        ret.insert(*x);
+                    }),
                     FilterASPath::Empty => {}
                 },
                 _ => {}
@@ -916,6 +1127,17 @@ impl RouteFilter {
         }
         ret
     }
+    /// numeric bounds served by sort indexes
+    pub fn find_bound_items(&self) -> Vec<(SortKey, std::cmp::Ordering, u32)> {
+        self.terms
+            .iter()
+            .filter(|t| t.predicate != FilterItemMatchResult::No)
+            .filter_map(|t| match t.item {
+                FilterItem::Bound(k, o, n) => Some((k, o, n)),
+                _ => None,
+            })
+            .collect()
+    }
     pub fn find_extcommunity_item<'a>(&'a self) -> BTreeSet<BgpExtCommunity> {
         let mut ret: BTreeSet<BgpExtCommunity> = BTreeSet::new();
         for i in self.terms.iter() {
@@ -941,6 +1163,11 @@ impl RouteFilter {
     }
 }
 
+impl Default for FilterItem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl FilterItem {
     pub fn new() -> FilterItem {
         FilterItem::None
@@ -957,6 +1184,7 @@ impl FilterItem {
             static ref RE_RDIP: Regex =
                 Regex::new(r"^rd:([0-9]+)\.([0-9]+)\.([0-9]+)\.([0-9]+):([0-9]+)$").unwrap();
             static ref RE_AS: Regex = Regex::new(r"^as:(\^*)([0-9,]+)*(\$)*$").unwrap();
+            static ref RE_TRANSIT: Regex = Regex::new(r"^transit:([0-9,]+)$").unwrap();
             static ref RE_C: Regex = Regex::new(r"^c[^:]*:([0-9]+):([0-9]+)$").unwrap();
             static ref RE_NUM: Regex = Regex::new(r"^([0-9]+)$").unwrap();
             static ref RE_RE: Regex = Regex::new(r"^re:(.*)$").unwrap();
@@ -1130,9 +1358,24 @@ impl FilterItem {
             }
             _ => {}
         };
+        if let Some(caps) = RE_TRANSIT.captures(itemstr) {
+            let sa = CompactASPath::from(
+                caps.get(1)
+                    .map(|sv| {
+                        sv.as_str()
+                            .split(',')
+                            .filter_map(|s| s.parse().ok())
+                            .collect::<Vec<u32>>()
+                    })
+                    .unwrap_or_default(),
+            );
+            if !sa.value.is_empty() {
+                return FilterItem::ASPath(FilterASPath::Transit(sa));
+            }
+        };
         match RE_AS.captures(itemstr) {
             Some(caps) => {
-                let sa = BgpASpath::from(match caps.get(2) {
+                let sa = CompactASPath::from(match caps.get(2) {
                     Some(sv) => {
                         let mut v: Vec<u32> = Vec::new();
                         for s in sv.as_str().split(',') {
@@ -1188,6 +1431,22 @@ impl FilterItem {
             }
             _ => {}
         };
+        if let Some(c) = itemstr
+            .strip_prefix("c:")
+            .or_else(|| itemstr.strip_prefix("community:"))
+            .and_then(crate::communities::lookup)
+        {
+            return FilterItem::Community(c);
+        }
+        if itemstr == "aggregated" {
+            return FilterItem::Aggregator(None);
+        }
+        if let Some(n) = itemstr
+            .strip_prefix("aggregator:")
+            .and_then(|s| s.parse().ok())
+        {
+            return FilterItem::Aggregator(Some(n));
+        }
         match RE_RE.captures(itemstr) {
             Some(caps) => {
                 match caps.get(1) {
@@ -1443,6 +1702,20 @@ impl FilterItem {
                 _ => FilterItemMatchResult::Unknown,
             },
             FilterItem::Community(cflt) => attr.comms.value.contains(cflt).into(),
+            FilterItem::Aggregator(None) => {
+                (attr.aggregatoras.is_some() || attr.atomicaggregate.is_some()).into()
+            }
+            FilterItem::Aggregator(Some(asn)) => attr
+                .aggregatoras
+                .as_ref()
+                .map(|a| a.asn == *asn)
+                .unwrap_or(false)
+                .into(),
+            FilterItem::Bound(key, ord, n) => key
+                .value(attr)
+                .map(|v| v.cmp(n) == *ord)
+                .unwrap_or(false)
+                .into(),
             FilterItem::ExtCommunity(_) => {
                 let mut ret = FilterItemMatchResult::Unknown;
                 if attr.extcomms.value.is_empty() {
@@ -1473,15 +1746,15 @@ impl FilterItem {
             ),
             FilterItem::ASPath(aspflt) => match aspflt {
                 FilterASPath::Empty => (attr.aspath.value.len() == 0).into(),
-                FilterASPath::FullMatch(asp) => (attr.aspath.value == asp.value).into(),
+                FilterASPath::FullMatch(asp) => (attr.aspath.value[..] == asp.value[..]).into(),
                 FilterASPath::Contains(asp) => {
                     if asp.value.len() > attr.aspath.value.len() {
                         FilterItemMatchResult::No
                     } else if asp.value.len() == attr.aspath.value.len() {
-                        (attr.aspath.value == asp.value).into()
+                        (attr.aspath.value[..] == asp.value[..]).into()
                     } else {
                         for idx in 0..(attr.aspath.value.len() - asp.value.len() + 1) {
-                            if attr.aspath.value[idx..(idx + asp.value.len())] == asp.value {
+                            if attr.aspath.value[idx..(idx + asp.value.len())] == asp.value[..] {
                                 return FilterItemMatchResult::Yes;
                             }
                         }
@@ -1492,18 +1765,30 @@ impl FilterItem {
                     if asp.value.len() > attr.aspath.value.len() {
                         FilterItemMatchResult::No
                     } else {
-                        (asp.value == attr.aspath.value[0..asp.value.len()]).into()
+                        (asp.value[..] == attr.aspath.value[0..asp.value.len()]).into()
                     }
                 }
                 FilterASPath::EndsWith(asp) => {
                     if asp.value.len() > attr.aspath.value.len() {
                         FilterItemMatchResult::No
                     } else {
-                        (asp.value
+                        (asp.value[..]
                             == attr.aspath.value[attr.aspath.value.len() - asp.value.len()..])
                             .into()
                     }
                 }
+                FilterASPath::Transit(asp) => {
+                    if asp.value.len() >= attr.aspath.value.len() {
+                        FilterItemMatchResult::No
+                    } else {
+                        // sequence should not end at origin AS
+                        (0..(attr.aspath.value.len() - asp.value.len()))
+                            .any(|idx| {
+                                attr.aspath.value[idx..(idx + asp.value.len())] == asp.value[..]
+                            })
+                            .into()
+                    }
+                }
             },
             _ => FilterItemMatchResult::Unknown,
         }
@@ -1653,6 +1938,7 @@ impl FilterTerm {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compact::{CompactASPath, CompactCommunityList};
     use crate::config::*;
 
     #[test]
@@ -1733,26 +2019,102 @@ mod tests {
         );
         assert_eq!(
             FilterItem::parse("as:2345"),
-            FilterItem::ASPath(FilterASPath::Contains(BgpASpath::from(vec![2345])))
+            FilterItem::ASPath(FilterASPath::Contains(CompactASPath::from(vec![2345])))
         );
         assert_eq!(
             FilterItem::parse("as:100,2345"),
-            FilterItem::ASPath(FilterASPath::Contains(BgpASpath::from(vec![100, 2345])))
+            FilterItem::ASPath(FilterASPath::Contains(CompactASPath::from(vec![100, 2345])))
         );
         assert_eq!(
             FilterItem::parse("as:^2345"),
-            FilterItem::ASPath(FilterASPath::StartsWith(BgpASpath::from(vec![2345])))
+            FilterItem::ASPath(FilterASPath::StartsWith(CompactASPath::from(vec![2345])))
         );
         assert_eq!(
             FilterItem::parse("as:2345$"),
-            FilterItem::ASPath(FilterASPath::EndsWith(BgpASpath::from(vec![2345])))
+            FilterItem::ASPath(FilterASPath::EndsWith(CompactASPath::from(vec![2345])))
         );
         assert_eq!(
             FilterItem::parse("as:^100,2345$"),
-            FilterItem::ASPath(FilterASPath::FullMatch(BgpASpath::from(vec![100, 2345])))
+            FilterItem::ASPath(FilterASPath::FullMatch(CompactASPath::from(vec![
+                100, 2345
+            ])))
         );
     }
     #[test]
+    fn test_ribfilter_fi_transit() {
+        assert_eq!(
+            FilterItem::parse("transit:2345"),
+            FilterItem::ASPath(FilterASPath::Transit(CompactASPath::from(vec![2345])))
+        );
+        let fi = FilterItem::parse("transit:2345");
+        let mut attr = BgpAttrs::new();
+        attr.aspath = Arc::new(CompactASPath::from(vec![100, 2345, 300]));
+        assert_eq!(fi.match_attr(&attr), FilterItemMatchResult::Yes);
+        attr.aspath = Arc::new(CompactASPath::from(vec![100, 2345]));
+        assert_eq!(fi.match_attr(&attr), FilterItemMatchResult::No);
+    }
+    #[test]
+    fn test_ribfilter_community_param() {
+        let mut flt = RouteFilter::new();
+        let mut params = std::collections::HashMap::new();
+        params.insert("community".to_string(), "64496:666".to_string());
+        flt.extract_params(&params).unwrap();
+        assert_eq!(flt.terms.len(), 1);
+        let rt = BgpAddrV4::new(std::net::Ipv4Addr::new(10, 0, 0, 0), 8);
+        let mut attr = BgpAttrs::new();
+        assert_eq!(flt.match_route(&rt, &attr), FilterItemMatchResult::No);
+        attr.comms = Arc::new(CompactCommunityList::from_vec(vec![BgpCommunity::from(
+            64496, 666,
+        )]));
+        assert_eq!(flt.match_route(&rt, &attr), FilterItemMatchResult::Yes);
+        params.insert("community".to_string(), "no-such-name".to_string());
+        assert!(RouteFilter::new().extract_params(&params).is_err());
+        params.insert("community".to_string(), "no-export".to_string());
+        assert!(RouteFilter::new().extract_params(&params).is_ok());
+        assert_eq!(
+            RouteFilter::unknown_community("10.0.0.0/8 -c:no-such-name"),
+            Some("c:no-such-name")
+        );
+        assert_eq!(
+            RouteFilter::unknown_community("c:no-export community:64496:666"),
+            None
+        );
+    }
+    #[test]
+    fn test_ribfilter_fi_aggregated() {
+        assert_eq!(
+            FilterItem::parse("aggregator:64500"),
+            FilterItem::Aggregator(Some(64500))
+        );
+        let fi = FilterItem::parse("aggregated");
+        assert_eq!(fi, FilterItem::Aggregator(None));
+        let mut attr = BgpAttrs::new();
+        assert_eq!(fi.match_attr(&attr), FilterItemMatchResult::No);
+        attr.atomicaggregate = Some(std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)));
+        assert_eq!(fi.match_attr(&attr), FilterItemMatchResult::Yes);
+        let mut flt = RouteFilter::new();
+        let mut params = std::collections::HashMap::new();
+        params.insert("aggregated".to_string(), "false".to_string());
+        flt.extract_params(&params).unwrap();
+        assert_eq!(flt.terms[0].match_attr(&attr), FilterItemMatchResult::No);
+    }
+    #[test]
+    fn test_ribfilter_bounds() {
+        let mut flt = RouteFilter::new();
+        let mut params = std::collections::HashMap::new();
+        params.insert("med_gt".to_string(), "100".to_string());
+        params.insert("pathlen_lt".to_string(), "3".to_string());
+        flt.extract_params(&params).unwrap();
+        assert_eq!(flt.find_bound_items().len(), 2);
+        let mut attr = BgpAttrs::new();
+        assert_eq!(flt.match_attr(&attr), FilterItemMatchResult::No);
+        attr.med = Some(150);
+        let rt = BgpAddrV4::new(std::net::Ipv4Addr::new(10, 0, 0, 0), 8);
+        assert_eq!(flt.match_route(&rt, &attr), FilterItemMatchResult::Yes);
+        attr.med = Some(100);
+        assert_eq!(flt.match_route(&rt, &attr), FilterItemMatchResult::No);
+    }
+    #[test]
     fn test_ribfilter_fi_com() {
         assert_eq!(
             FilterItem::parse("community:100:2345"),
@@ -1989,8 +2351,10 @@ mod tests {
                 originator: None,
                 clusterlist: None,
                 pmsi_ta: None,
-                aspath: Arc::new(BgpASpath::new()),
-                comms: Arc::new(BgpCommunityList::new()),
+                prefix_sid: None,
+                confed: None,
+                aspath: Arc::new(CompactASPath::new()),
+                comms: Arc::new(CompactCommunityList::new()),
                 lcomms: Arc::new(BgpLargeCommunityList::new()),
                 extcomms: Arc::new(BgpExtCommunityList::from_vec(vec![BgpExtCommunity {
                     ctype: 0,
@@ -2026,8 +2390,8 @@ mod tests {
             let attrs2 = BgpAttrs {
                 origin: BgpAttrOrigin::Incomplete,
                 nexthop: BgpAddr::None,
-                aspath: Arc::new(BgpASpath::new()),
-                comms: Arc::new(BgpCommunityList::new()),
+                aspath: Arc::new(CompactASPath::new()),
+                comms: Arc::new(CompactCommunityList::new()),
                 lcomms: Arc::new(BgpLargeCommunityList::new()),
                 extcomms: Arc::new(BgpExtCommunityList::from_vec(vec![BgpExtCommunity {
                     ctype: 0,
@@ -2042,6 +2406,8 @@ mod tests {
                 clusterlist: None,
                 originator: None,
                 pmsi_ta: None,
+                prefix_sid: None,
+                confed: None,
             };
             safi.handle_updates_afi(
                 0,
@@ -2102,8 +2468,10 @@ mod tests {
             originator: None,
             clusterlist: None,
             pmsi_ta: None,
-            aspath: Arc::new(BgpASpath::new()),
-            comms: Arc::new(BgpCommunityList::new()),
+            prefix_sid: None,
+            confed: None,
+            aspath: Arc::new(CompactASPath::new()),
+            comms: Arc::new(CompactCommunityList::new()),
             lcomms: Arc::new(BgpLargeCommunityList::new()),
             extcomms: Arc::new(BgpExtCommunityList::from_vec(vec![BgpExtCommunity {
                 ctype: 0,
@@ -2156,8 +2524,10 @@ mod tests {
             originator: None,
             clusterlist: None,
             pmsi_ta: None,
-            aspath: Arc::new(BgpASpath::new()),
-            comms: Arc::new(BgpCommunityList::new()),
+            prefix_sid: None,
+            confed: None,
+            aspath: Arc::new(CompactASPath::new()),
+            comms: Arc::new(CompactCommunityList::new()),
             lcomms: Arc::new(BgpLargeCommunityList::new()),
             extcomms: Arc::new(BgpExtCommunityList::from_vec(vec![BgpExtCommunity {
                 ctype: 0,
@@ -2198,8 +2568,10 @@ mod tests {
             originator: None,
             clusterlist: None,
             pmsi_ta: None,
-            aspath: Arc::new(BgpASpath::new()),
-            comms: Arc::new(BgpCommunityList::new()),
+            prefix_sid: None,
+            confed: None,
+            aspath: Arc::new(CompactASPath::new()),
+            comms: Arc::new(CompactCommunityList::new()),
             lcomms: Arc::new(BgpLargeCommunityList::new()),
             extcomms: Arc::new(BgpExtCommunityList::from_vec(vec![BgpExtCommunity {
                 ctype: 0,
@@ -2308,8 +2680,10 @@ mod tests {
             originator: None,
             clusterlist: None,
             pmsi_ta: None,
-            aspath: Arc::new(BgpASpath::new()),
-            comms: Arc::new(BgpCommunityList::new()),
+            prefix_sid: None,
+            confed: None,
+            aspath: Arc::new(CompactASPath::new()),
+            comms: Arc::new(CompactCommunityList::new()),
             lcomms: Arc::new(BgpLargeCommunityList::new()),
             extcomms: Arc::new(BgpExtCommunityList::from_vec(vec![
                 BgpExtCommunity::rt_asn(1, 1),
@@ -2325,8 +2699,10 @@ mod tests {
             originator: None,
             clusterlist: None,
             pmsi_ta: None,
-            aspath: Arc::new(BgpASpath::new()),
-            comms: Arc::new(BgpCommunityList::new()),
+            prefix_sid: None,
+            confed: None,
+            aspath: Arc::new(CompactASPath::new()),
+            comms: Arc::new(CompactCommunityList::new()),
             lcomms: Arc::new(BgpLargeCommunityList::new()),
             extcomms: Arc::new(BgpExtCommunityList::from_vec(vec![
                 BgpExtCommunity::rt_asn(1, 2),
@@ -2369,8 +2745,10 @@ mod tests {
             originator: None,
             clusterlist: None,
             pmsi_ta: None,
-            aspath: Arc::new(BgpASpath::new()),
-            comms: Arc::new(BgpCommunityList::new()),
+            prefix_sid: None,
+            confed: None,
+            aspath: Arc::new(CompactASPath::new()),
+            comms: Arc::new(CompactCommunityList::new()),
             lcomms: Arc::new(BgpLargeCommunityList::new()),
             extcomms: Arc::new(BgpExtCommunityList::from_vec(vec![
                 BgpExtCommunity::rt_asn(1, 1),
@@ -2386,8 +2764,10 @@ mod tests {
             originator: None,
             clusterlist: None,
             pmsi_ta: None,
-            aspath: Arc::new(BgpASpath::new()),
-            comms: Arc::new(BgpCommunityList::new()),
+            prefix_sid: None,
+            confed: None,
+            aspath: Arc::new(CompactASPath::new()),
+            comms: Arc::new(CompactCommunityList::new()),
             lcomms: Arc::new(BgpLargeCommunityList::new()),
             extcomms: Arc::new(BgpExtCommunityList::from_vec(vec![
                 BgpExtCommunity::rt_asn(1, 2),
@@ -1,9 +1,14 @@
+use crate::asgraph::*;
 use crate::bgprib::*;
+use crate::blackhole::BlackholeCommunities;
+use crate::federation::*;
+use crate::nexthop::*;
 use crate::service::*;
+use crate::timestamp::Timestamp;
 use crate::*;
 use chrono::prelude::*;
 use futures::executor::block_on;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc::*;
@@ -17,6 +22,22 @@ pub struct RibResponseParams {
     pub limit: usize,
     pub maxdepth: usize,
     pub onlyactive: bool,
+    pub bestpath: BestPathMode,
+    /// lowercase names of route fields to return, all if None
+    pub fields: Option<Arc<BTreeSet<String>>>,
+    /// API token visibility scope, everything is visible if None
+    pub scope: Option<Arc<crate::tenancy::ApiScope>>,
+    /// communities marking history records as blackholed
+    pub blackhole: Arc<BlackholeCommunities>,
+    /// RIB scans stop after deadline and response is marked partial
+    pub deadline: Option<std::time::Instant>,
+    /// query_budget, queries estimated above it are refused unless force=true
+    pub budget: Option<u64>,
+    /// timestamp and prefix rendering
+    pub format: crate::jsonfmt::JsonFormat,
+    /// sort=med|localpref|pathlen and order=desc, served by sort indexes
+    pub sort: Option<(crate::sortindex::SortKey, bool)>,
+    partial: Arc<std::sync::atomic::AtomicBool>,
 }
 impl RibResponseParams {
     pub fn new(skip: usize, limit: usize, maxdepth: usize, onlyactive: bool) -> RibResponseParams {
@@ -25,6 +46,41 @@ impl RibResponseParams {
             limit,
             maxdepth,
             onlyactive,
+            bestpath: BestPathMode::All,
+            fields: None,
+            scope: None,
+            blackhole: Default::default(),
+            deadline: None,
+            budget: None,
+            format: Default::default(),
+            sort: None,
+            partial: Default::default(),
+        }
+    }
+    /// whether deadline passed, remembers it for is_partial
+    pub fn expired(&self) -> bool {
+        match self.deadline {
+            Some(d) if std::time::Instant::now() >= d => {
+                self.partial
+                    .store(true, std::sync::atomic::Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+    /// some scan was cut by deadline
+    pub fn is_partial(&self) -> bool {
+        self.partial.load(std::sync::atomic::Ordering::Relaxed)
+    }
+    /// whether route is visible within token scope
+    pub fn in_scope<T: ribfilter::FilterMatchRoute>(
+        &self,
+        route: &T,
+        entry: &BgpSessionEntry,
+    ) -> bool {
+        match self.scope {
+            None => true,
+            Some(ref s) => s.allows_route(route, entry),
         }
     }
     pub fn extract_params(&mut self, hashmap: &HashMap<String, String>) {
@@ -40,47 +96,185 @@ impl RibResponseParams {
         if let Some(n) = get_url_param(hashmap, "onlyactive") {
             self.onlyactive = n;
         };
+        if let Some(n) = get_url_param(hashmap, "bestpath") {
+            self.bestpath = n;
+        };
+        if let Some(n) = get_url_param(hashmap, "time_format") {
+            self.format.time = n;
+        };
+        if let Some(n) = get_url_param(hashmap, "prefix_format") {
+            self.format.prefix = n;
+        };
+        if let Some(n) = get_url_param(hashmap, "history_format") {
+            self.format.history = n;
+        };
+        if let Some(key) = get_url_param(hashmap, "sort") {
+            let desc = get_url_param::<String>(hashmap, "order").as_deref() == Some("desc");
+            self.sort = Some((key, desc));
+        };
+        if get_url_param::<bool>(hashmap, "force").unwrap_or(false) {
+            self.budget = None;
+        };
+        if let Some(s) = get_url_param::<String>(hashmap, "fields") {
+            self.fields = Some(Arc::new(
+                s.split(',')
+                    .map(|f| f.trim().to_lowercase())
+                    .filter(|f| !f.is_empty())
+                    .collect(),
+            ));
+        };
     }
 }
 
+fn rib_in_scope(scope: &Option<Arc<crate::tenancy::ApiScope>>, rib: &str) -> bool {
+    match scope {
+        None => true,
+        Some(s) => s.allows_rib(rib),
+    }
+}
+
+/// entry of prefix in unicast or multicast RIB
+fn prefix_entry<'a>(
+    rib: &'a BgpRIB,
+    addr: std::net::IpAddr,
+    len: u8,
+    ribname: &str,
+) -> Result<Option<&'a BgpSessionEntry>, &'static str> {
+    Ok(match (addr, ribname) {
+        (std::net::IpAddr::V4(a), "ipv4u") => rib.ipv4u.items.get(&BgpAddrV4::new(a, len)),
+        (std::net::IpAddr::V4(a), "ipv4m") => rib.ipv4m.items.get(&BgpAddrV4::new(a, len)),
+        (std::net::IpAddr::V6(a), "ipv6u") => rib.ipv6u.items.get(&BgpAddrV6::new(a, len)),
+        (std::net::IpAddr::V6(a), "ipv6m") => rib.ipv6m.items.get(&BgpAddrV6::new(a, len)),
+        _ => return Err("Unsupported RIB for prefix"),
+    })
+}
+
+/// BgpRIB behind lock with timeout, used by API queries
 pub struct BgpRIBts {
     pub locktimeout: Duration,
+    pub bestpath: BestPathMode,
+    pub blackhole: Arc<BlackholeCommunities>,
+    /// query_budget, 0 - unlimited
+    pub budget: std::sync::atomic::AtomicU64,
+    /// default json_time_format and json_prefix_format
+    pub format: crate::jsonfmt::JsonFormat,
     pub rib: Arc<RwLock<BgpRIB>>,
+    pub latency: Arc<crate::latency::UpdateLatency>,
+    /// peer AS of registered sessions, for peeras queries
+    pub peer_as: std::sync::RwLock<BTreeMap<BgpSessionId, u32>>,
+    /// update script, run by updates handler before RIB write lock is taken
+    pub script: Option<Arc<std::sync::Mutex<crate::script::UpdateScript>>>,
+    confed_in_aspath: bool,
 }
 impl BgpRIBts {
     pub fn new(cfg: &SvcConfig, rib: BgpRIB) -> BgpRIBts {
         BgpRIBts {
             locktimeout: Duration::from_secs(cfg.httptimeout),
+            bestpath: cfg.bestpath,
+            blackhole: Arc::new(cfg.blackhole_communities.clone()),
+            budget: std::sync::atomic::AtomicU64::new(cfg.query_budget),
+            format: crate::jsonfmt::JsonFormat {
+                time: cfg.json_time_format,
+                prefix: cfg.json_prefix_format,
+                history: cfg.json_history_format,
+            },
             rib: Arc::new(RwLock::new(rib)),
+            latency: Default::default(),
+            peer_as: Default::default(),
+            script: cfg.update_script.as_ref().and_then(
+                |p| match crate::script::UpdateScript::load(p.as_str()) {
+                    Ok(s) => Some(Arc::new(std::sync::Mutex::new(s))),
+                    Err(e) => {
+                        warn!("Update script error: {}", e);
+                        None
+                    }
+                },
+            ),
+            confed_in_aspath: cfg.confed_in_aspath,
         }
     }
+    /// update script verdicts for update, empty without script
+    pub fn script_decisions(
+        script: Option<&std::sync::Mutex<crate::script::UpdateScript>>,
+        confed_in_aspath: bool,
+        sid: BgpSessionId,
+        upd: &BgpUpdateMessage,
+    ) -> crate::script::Decisions {
+        match script {
+            Some(s) => {
+                let mut s = s.lock().unwrap();
+                s.reload_if_changed();
+                s.decide_update(sid, upd, confed_in_aspath)
+            }
+            None => Default::default(),
+        }
+    }
+    /// update script verdicts for update, taken without RIB lock
+    pub fn decide(&self, sid: BgpSessionId, upd: &BgpUpdateMessage) -> crate::script::Decisions {
+        BgpRIBts::script_decisions(self.script.as_deref(), self.confed_in_aspath, sid, upd)
+    }
     pub async fn shutdown(&self) {
         self.rib.read().await.shutdown().await;
     }
-    pub fn run(
-        &self,
-        mut rx: Receiver<Option<(BgpSessionId, BgpUpdateMessage)>>,
-    ) -> std::thread::JoinHandle<()> {
+    pub fn run(&self, mut rx: Receiver<Option<QueuedUpdate>>) -> std::thread::JoinHandle<()> {
         let ribc = self.rib.clone();
         let builderp = std::thread::Builder::new().name("bgp_garbage_collector".into());
         builderp
-            .spawn(move || loop {
-                std::thread::sleep(time::Duration::from_secs(10));
-                if !block_on(ribc.read()).needs_purge() {
-                    continue;
+            .spawn(move || {
+                // withdraw batches are due every second, purge and snapshots every 10
+                let mut tick = 0;
+                loop {
+                    std::thread::sleep(time::Duration::from_secs(1));
+                    tick += 1;
+                    if block_on(ribc.read()).needs_withdraw_flush() {
+                        block_on(ribc.write()).publish_withdraw_batches(Timestamp::now());
+                    }
+                    if tick < 10 {
+                        continue;
+                    }
+                    tick = 0;
+                    let (purge, diff) = {
+                        let r = block_on(ribc.read());
+                        (r.needs_purge(), r.needs_snapshot_diff())
+                    };
+                    if purge {
+                        block_on(ribc.write()).purge();
+                    } else if diff {
+                        if let Err(e) = block_on(ribc.write()).store_snapshot_diff() {
+                            warn!("store_snapshot_diff error: {}", e);
+                        }
+                    }
                 }
-                block_on(ribc.write()).purge();
             })
             .unwrap();
         let ribc = self.rib.clone();
+        let latency = self.latency.clone();
+        let script = self.script.clone();
+        let confed_in_aspath = self.confed_in_aspath;
         let builderu = std::thread::Builder::new().name("bgp_updates_handler".into());
         builderu
             .spawn(move || {
                 while let Some(updmsg) = rx.blocking_recv() {
                     match updmsg {
-                        Some(updm) => {
+                        Some((sid, upd, received, peer_time, read)) => {
                             let time_started = Local::now();
-                            if let Err(e) = block_on(ribc.write()).handle_update(updm.0, updm.1) {
+                            let decisions = BgpRIBts::script_decisions(
+                                script.as_deref(),
+                                confed_in_aspath,
+                                sid,
+                                &upd,
+                            );
+                            let mut rib = block_on(ribc.write());
+                            let locked = std::time::Instant::now();
+                            // history is keyed by receive time, not by dequeue time
+                            let res = Timestamp::with_clock(received, || {
+                                Timestamp::with_peer_clock(peer_time, || {
+                                    rib.handle_update_decided(sid, upd, &decisions)
+                                })
+                            });
+                            drop(rib);
+                            latency.record(read, locked, std::time::Instant::now());
+                            if let Err(e) = res {
                                 warn!("RIB handle_update: {:?}", e);
                             };
                             let time_done = Local::now();
@@ -114,6 +308,8 @@ impl BgpRIBts {
         m.insert("extcomms", rib.extcomms.len() as u64);
         m.insert("attrs", rib.attrs.len() as u64);
         m.insert("clusters", rib.clusters.len() as u64);
+        m.insert("prefix_sids", rib.prefix_sids.len() as u64);
+        m.insert("confeds", rib.confeds.len() as u64);
         rsp.insert("stores", m);
         let mut m: std::collections::HashMap<&str, u64> = std::collections::HashMap::new();
         m.insert("ipv4u", rib.ipv4u.len() as u64);
@@ -122,6 +318,7 @@ impl BgpRIBts {
         m.insert("vpnv4u", rib.vpnv4u.len() as u64);
         m.insert("vpnv4m", rib.vpnv4m.len() as u64);
         m.insert("ipv6u", rib.ipv6u.len() as u64);
+        m.insert("ipv6m", rib.ipv6m.len() as u64);
         m.insert("ipv6lu", rib.ipv6lu.len() as u64);
         m.insert("vpnv6u", rib.vpnv6u.len() as u64);
         m.insert("vpnv6m", rib.vpnv6m.len() as u64);
@@ -135,6 +332,7 @@ impl BgpRIBts {
         let mut m: std::collections::HashMap<&str, u64> = std::collections::HashMap::new();
         m.insert("updates", rib.cnt_updates);
         m.insert("withdraws", rib.cnt_withdraws);
+        m.insert("deduplicated", rib.dedup_suppressed());
         rsp.insert("counters", m);
         match serde_json::to_vec(&rsp) {
             Ok(v) => Response::builder()
@@ -154,6 +352,18 @@ impl BgpRIBts {
         filter: &ribfilter::RouteFilter,
         params: RibResponseParams,
     ) -> Result<Response<Body>, hyper::http::Error> {
+        if let Some(budget) = params.budget {
+            let cost = crate::querycost::estimate(rib, filter, params.limit, params.maxdepth);
+            if cost > budget {
+                return bad_request(
+                    format!(
+                        "Query cost {} exceeds budget {}, narrow the filter or add force=true",
+                        cost, budget
+                    )
+                    .as_str(),
+                );
+            }
+        }
         let rsp = RibResponse::<T>::new(rib, filter, params);
         match serde_json::to_vec(&rsp) {
             Ok(v) => Response::builder()
@@ -170,7 +380,12 @@ impl BgpRIBts {
         &self,
         queryrib: &str,
         req: &Request<Body>,
+        scope: Option<Arc<crate::tenancy::ApiScope>>,
     ) -> Result<Response<Body>, hyper::http::Error> {
+        if !rib_in_scope(&scope, queryrib) {
+            return Ok(access_denied(StatusCode::FORBIDDEN));
+        }
+        let deadline = std::time::Instant::now() + self.locktimeout;
         let rib = match timeout(self.locktimeout, self.rib.read()).await {
             Ok(r) => r,
             Err(_) => {
@@ -181,12 +396,33 @@ impl BgpRIBts {
             }
         };
         let mut params = RibResponseParams::new(0, 1000, 10, false);
+        params.bestpath = self.bestpath;
+        params.format = self.format;
+        params.blackhole = self.blackhole.clone();
+        params.scope = scope;
+        params.deadline = Some(deadline);
+        params.budget = match self.budget.load(std::sync::atomic::Ordering::Relaxed) {
+            0 => None,
+            n => Some(n),
+        };
         let mut filter = ribfilter::RouteFilter::new();
         let paramshm = get_url_params(req);
         params.extract_params(&paramshm);
         if let Some(s) = get_url_param::<String>(&paramshm, "filter") {
+            if let Some(c) = ribfilter::RouteFilter::unknown_community(s.as_str()) {
+                return bad_request(format!("Invalid filter: unknown community {}", c).as_str());
+            }
             filter.parse(s.as_str());
         };
+        if let Err(e) = filter.extract_params(&paramshm) {
+            return bad_request(format!("Invalid parameter: {}", e).as_str());
+        }
+        if let Some(q) = get_url_param::<String>(&paramshm, "q") {
+            if let Err(e) = filter.parse_query(q.as_str()) {
+                return bad_request(format!("Invalid query: {}", e).as_str());
+            }
+        }
+        filter.resolve_peers(&self.peer_as.read().unwrap());
         match queryrib {
             "ipv4u" => BgpRIBts::jsontabrib(&rib.ipv4u, &filter, params),
             "ipv4m" => BgpRIBts::jsontabrib(&rib.ipv4m, &filter, params),
@@ -194,6 +430,7 @@ impl BgpRIBts {
             "vpnv4u" => BgpRIBts::jsontabrib(&rib.vpnv4u, &filter, params),
             "vpnv4m" => BgpRIBts::jsontabrib(&rib.vpnv4m, &filter, params),
             "ipv6u" => BgpRIBts::jsontabrib(&rib.ipv6u, &filter, params),
+            "ipv6m" => BgpRIBts::jsontabrib(&rib.ipv6m, &filter, params),
             "ipv6lu" => BgpRIBts::jsontabrib(&rib.ipv6lu, &filter, params),
             "vpnv6u" => BgpRIBts::jsontabrib(&rib.vpnv6u, &filter, params),
             "vpnv6m" => BgpRIBts::jsontabrib(&rib.vpnv6m, &filter, params),
@@ -206,4 +443,594 @@ impl BgpRIBts {
             _ => BgpRIBts::jsontabrib(&rib.ipv4u, &filter, params),
         }
     }
+    fn rangetabrib<
+        T: serde::Serialize + ribfilter::FilterMatchRoute + BgpRIBKey + std::string::ToString,
+    >(
+        rib: &BgpRIBSafi<T>,
+        range: Option<std::ops::RangeInclusive<T>>,
+        within: Option<ribfilter::FilterItem>,
+        params: RibResponseParams,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        match range {
+            None => bad_request("Invalid range for this RIB"),
+            Some(r) => json_response(&RangeResponse::<T>::new(rib, r, within, params)),
+        }
+    }
+    pub async fn say_range(
+        &self,
+        req: &Request<Body>,
+        scope: Option<Arc<crate::tenancy::ApiScope>>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let paramshm = get_url_params(req);
+        let (start, end) = match (
+            get_url_param::<std::net::IpAddr>(&paramshm, "start"),
+            get_url_param::<std::net::IpAddr>(&paramshm, "end"),
+        ) {
+            (Some(s), Some(e)) => (s, e),
+            _ => return bad_request("Missing or invalid start/end parameters"),
+        };
+        let ribname = get_url_param::<String>(&paramshm, "rib")
+            .unwrap_or_else(|| if start.is_ipv4() { "ipv4u" } else { "ipv6u" }.to_string());
+        if !rib_in_scope(&scope, ribname.as_str()) {
+            return Ok(access_denied(StatusCode::FORBIDDEN));
+        }
+        let mut params = RibResponseParams::new(0, 1000, 10, false);
+        params.bestpath = self.bestpath;
+        params.format = self.format;
+        params.blackhole = self.blackhole.clone();
+        params.scope = scope;
+        params.deadline = Some(std::time::Instant::now() + self.locktimeout);
+        params.extract_params(&paramshm);
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        macro_rules! range {
+            ($safi:expr) => {
+                BgpRIBts::rangetabrib(
+                    &$safi,
+                    ribfilter::FilterMatchRoute::get_addr_range(&start, &end),
+                    None,
+                    params,
+                )
+            };
+        }
+        match ribname.as_str() {
+            "ipv4u" => range!(rib.ipv4u),
+            "ipv4m" => range!(rib.ipv4m),
+            "ipv4lu" => range!(rib.ipv4lu),
+            "ipv6u" => range!(rib.ipv6u),
+            "ipv6m" => range!(rib.ipv6m),
+            "ipv6lu" => range!(rib.ipv6lu),
+            _ => bad_request("Range queries are not supported for this RIB"),
+        }
+    }
+    pub async fn say_subtree(
+        &self,
+        prefix: &str,
+        req: &Request<Body>,
+        scope: Option<Arc<crate::tenancy::ApiScope>>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let paramshm = get_url_params(req);
+        let fi = ribfilter::FilterItem::parse(prefix);
+        let ribname = get_url_param::<String>(&paramshm, "rib").unwrap_or_else(|| {
+            match fi {
+                ribfilter::FilterItem::V6(_) => "ipv6u",
+                _ => "ipv4u",
+            }
+            .to_string()
+        });
+        if !rib_in_scope(&scope, ribname.as_str()) {
+            return Ok(access_denied(StatusCode::FORBIDDEN));
+        }
+        let mut params = RibResponseParams::new(0, 1000, 10, false);
+        params.bestpath = self.bestpath;
+        params.format = self.format;
+        params.blackhole = self.blackhole.clone();
+        params.scope = scope;
+        params.deadline = Some(std::time::Instant::now() + self.locktimeout);
+        params.extract_params(&paramshm);
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        macro_rules! subtree {
+            ($safi:expr) => {
+                BgpRIBts::rangetabrib(
+                    &$safi,
+                    ribfilter::FilterMatchRoute::get_subnet_range(&fi),
+                    Some(ribfilter::FilterItem::parse(prefix)),
+                    params,
+                )
+            };
+        }
+        match ribname.as_str() {
+            "ipv4u" => subtree!(rib.ipv4u),
+            "ipv4m" => subtree!(rib.ipv4m),
+            "ipv4lu" => subtree!(rib.ipv4lu),
+            "vpnv4u" => subtree!(rib.vpnv4u),
+            "vpnv4m" => subtree!(rib.vpnv4m),
+            "ipv6u" => subtree!(rib.ipv6u),
+            "ipv6m" => subtree!(rib.ipv6m),
+            "ipv6lu" => subtree!(rib.ipv6lu),
+            "vpnv6u" => subtree!(rib.vpnv6u),
+            "vpnv6m" => subtree!(rib.vpnv6m),
+            _ => bad_request("Subtree queries are not supported for this RIB"),
+        }
+    }
+    pub async fn say_nexthop(
+        &self,
+        queryrib: &str,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let paramshm = get_url_params(req);
+        let route = match get_url_param::<String>(&paramshm, "route") {
+            Some(s) => s,
+            None => return bad_request("Missing route parameter"),
+        };
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let filter = ribfilter::RouteFilter::fromstr(route.as_str());
+        let (routekey, nexthops) = match rib.route_nexthops(queryrib, &filter) {
+            Some(r) => r,
+            None => return Ok(not_found()),
+        };
+        json_response(&NexthopResponse {
+            route: routekey,
+            nexthops: nexthops.iter().map(|nh| rib.resolve_nexthop(nh)).collect(),
+        })
+    }
+    /// entity tag and last change time for RIB scope, None if RIB lock timed out
+    pub async fn generation_tag(
+        &self,
+        kind: Option<BgpRibKind>,
+        query: &str,
+    ) -> Option<(String, Timestamp)> {
+        let rib = timeout(self.locktimeout, self.rib.read()).await.ok()?;
+        Some((
+            rib.generations.etag(kind, query),
+            rib.generations.get(kind).changed,
+        ))
+    }
+    /// RIB part of universal search, None if RIB lock timed out
+    pub async fn search(&self, q: &str, limit: usize) -> Option<Vec<crate::search::SearchResult>> {
+        let rib = timeout(self.locktimeout, self.rib.read()).await.ok()?;
+        Some(rib.search(q, limit))
+    }
+    /// route assertions of POST /api/check, None if RIB lock timed out
+    pub async fn check(
+        &self,
+        items: &[crate::check::CheckItem],
+    ) -> Option<crate::check::CheckResponse> {
+        let rib = timeout(self.locktimeout, self.rib.read()).await.ok()?;
+        Some(rib.check(items))
+    }
+    /// active unicast prefixes originated by each ASN, None if RIB lock timed out
+    pub async fn originated(
+        &self,
+        asns: &std::collections::BTreeSet<u32>,
+    ) -> Option<std::collections::BTreeMap<u32, std::collections::BTreeSet<String>>> {
+        let rib = timeout(self.locktimeout, self.rib.read()).await.ok()?;
+        let mut ret = std::collections::BTreeMap::new();
+        crate::irr::originated(&rib.ipv4u, asns, &mut ret);
+        crate::irr::originated(&rib.ipv6u, asns, &mut ret);
+        Some(ret)
+    }
+    /// ipv4u/ipv6u routes of incident query, None on lock timeout
+    pub async fn incident_routes(
+        &self,
+        q: &crate::incident::IncidentQuery,
+    ) -> Option<crate::incident::IncidentRoutes> {
+        let rib = timeout(self.locktimeout, self.rib.read()).await.ok()?;
+        use crate::script::RoutePrefix;
+        let mut ret = crate::incident::IncidentRoutes::default();
+        ret.collect("ipv4u", &rib.ipv4u, q, |k| Some(RoutePrefix::V4(k.clone())));
+        ret.collect("ipv6u", &rib.ipv6u, q, |k| Some(RoutePrefix::V6(k.clone())));
+        ret.collect("ipv4m", &rib.ipv4m, q, |k| Some(RoutePrefix::V4(k.clone())));
+        ret.collect("ipv6m", &rib.ipv6m, q, |k| Some(RoutePrefix::V6(k.clone())));
+        ret.collect("ipv4lu", &rib.ipv4lu, q, |k| {
+            Some(RoutePrefix::V4(k.prefix.clone()))
+        });
+        ret.collect("ipv6lu", &rib.ipv6lu, q, |k| {
+            Some(RoutePrefix::V6(k.prefix.clone()))
+        });
+        ret.collect("vpnv4u", &rib.vpnv4u, q, |k| {
+            Some(RoutePrefix::V4(k.prefix.prefix.clone()))
+        });
+        ret.collect("vpnv4m", &rib.vpnv4m, q, |k| {
+            Some(RoutePrefix::V4(k.prefix.prefix.clone()))
+        });
+        ret.collect("vpnv6u", &rib.vpnv6u, q, |k| {
+            Some(RoutePrefix::V6(k.prefix.prefix.clone()))
+        });
+        ret.collect("vpnv6m", &rib.vpnv6m, q, |k| {
+            Some(RoutePrefix::V6(k.prefix.prefix.clone()))
+        });
+        ret.collect("l2vpls", &rib.l2vpls, q, |_| None);
+        ret.collect("mvpn", &rib.mvpn, q, |_| None);
+        ret.collect("evpn", &rib.evpn, q, |_| None);
+        ret.collect("fs4u", &rib.fs4u, q, |_| None);
+        ret.collect("ipv4mdt", &rib.ipv4mdt, q, |_| None);
+        ret.collect("ipv6mdt", &rib.ipv6mdt, q, |_| None);
+        ret.finish();
+        Some(ret)
+    }
+    pub async fn say_graph(
+        &self,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let paramshm = get_url_params(req);
+        let ribname =
+            get_url_param::<String>(&paramshm, "rib").unwrap_or_else(|| "ipv4u".to_string());
+        let limit = get_url_param::<usize>(&paramshm, "limit").unwrap_or(1000);
+        let prefix = get_url_param::<String>(&paramshm, "prefix");
+        let asn = get_url_param::<u32>(&paramshm, "asn");
+        if prefix.is_none() && asn.is_none() {
+            return bad_request("Missing prefix or asn parameter");
+        }
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let graph: Option<AsGraph> = match (prefix, asn) {
+            (Some(p), _) => {
+                let filter = ribfilter::RouteFilter::fromstr(p.as_str());
+                rib.as_graph_by_prefix(ribname.as_str(), &filter, limit)
+            }
+            (None, Some(a)) => rib.as_graph_by_asn(ribname.as_str(), a, limit),
+            (None, None) => None,
+        };
+        match graph {
+            Some(g) => json_response(&g),
+            None => Ok(not_found()),
+        }
+    }
+    /// unicast routes currently carrying blackhole communities
+    pub async fn say_blackholes(&self) -> Result<Response<Body>, hyper::http::Error> {
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let mut ret = Vec::new();
+        crate::blackhole::find("ipv4u", &rib.ipv4u, &self.blackhole, &mut ret);
+        crate::blackhole::find("ipv6u", &rib.ipv6u, &self.blackhole, &mut ret);
+        ret.sort_by_key(|a| std::cmp::Reverse(a.since));
+        json_response(&ret)
+    }
+    pub async fn say_nexthops(
+        &self,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let paramshm = get_url_params(req);
+        let limit = get_url_param::<usize>(&paramshm, "limit").unwrap_or(1000);
+        let queryrib = get_url_param::<String>(&paramshm, "rib");
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let mut ret = Vec::new();
+        for (name, inv) in [
+            ("ipv4u", &rib.ipv4u.nexthops),
+            ("ipv4m", &rib.ipv4m.nexthops),
+            ("ipv4lu", &rib.ipv4lu.nexthops),
+            ("vpnv4u", &rib.vpnv4u.nexthops),
+            ("vpnv4m", &rib.vpnv4m.nexthops),
+            ("ipv6u", &rib.ipv6u.nexthops),
+            ("ipv6m", &rib.ipv6m.nexthops),
+            ("ipv6lu", &rib.ipv6lu.nexthops),
+            ("vpnv6u", &rib.vpnv6u.nexthops),
+            ("vpnv6m", &rib.vpnv6m.nexthops),
+            ("l2vpls", &rib.l2vpls.nexthops),
+            ("mvpn", &rib.mvpn.nexthops),
+            ("evpn", &rib.evpn.nexthops),
+            ("fs4u", &rib.fs4u.nexthops),
+            ("ipv4mdt", &rib.ipv4mdt.nexthops),
+            ("ipv6mdt", &rib.ipv6mdt.nexthops),
+        ]
+        .iter()
+        {
+            if queryrib.as_deref().map(|q| q == *name).unwrap_or(true) {
+                inv.report(name, &mut ret);
+            }
+        }
+        ret.sort_by_key(|a| std::cmp::Reverse(a.routes));
+        ret.truncate(limit);
+        json_response(&ret)
+    }
+    /// RIB mutations after version since, see tableversion
+    pub async fn say_changes(
+        &self,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let paramshm = get_url_params(req);
+        let since = match get_url_param::<u64>(&paramshm, "since") {
+            Some(v) => v,
+            None => return bad_request("since parameter is required"),
+        };
+        let epoch = match get_url_param::<i64>(&paramshm, "epoch") {
+            Some(v) => v,
+            None => return bad_request("epoch parameter is required"),
+        };
+        let limit = get_url_param::<usize>(&paramshm, "limit")
+            .unwrap_or(10000)
+            .min(100_000);
+        let slice = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(rib) => rib.versions.changes_since(epoch, since, limit),
+            Err(_) => return timed_out(),
+        };
+        match slice {
+            Ok(s) => json_response(&s.changes()),
+            // versions of client are from other RIB content
+            Err(info) => json_response(&info).map(|mut r| {
+                *r.status_mut() = hyper::StatusCode::GONE;
+                r
+            }),
+        }
+    }
+    /// current table version, None when RIB is locked for too long
+    pub async fn table_version(&self) -> Option<crate::tableversion::TableVersionInfo> {
+        timeout(self.locktimeout, self.rib.read())
+            .await
+            .ok()
+            .map(|r| r.versions.info())
+    }
+    /// last withdraw bursts coalesced by withdraw_burst_threshold
+    pub async fn say_withdraw_bursts(&self) -> Result<Response<Body>, hyper::http::Error> {
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        json_response(&rib.withdraw_bursts.log)
+    }
+    /// shadow table of routes dropped by inbound lists and update script
+    pub async fn say_filtered(
+        &self,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let params = get_url_params(req);
+        let within = match params
+            .get("prefix")
+            .map(|p| crate::inbound::PrefixList::parse(p))
+        {
+            None => None,
+            Some(Ok(l)) => Some(l),
+            Some(Err(e)) => return bad_request(format!("Invalid prefix: {}", e).as_str()),
+        };
+        let origin = get_url_param::<u32>(&params, "origin");
+        let reason = params.get("reason").cloned();
+        let skip = get_url_param::<usize>(&params, "skip").unwrap_or(0);
+        let limit = get_url_param::<usize>(&params, "limit")
+            .unwrap_or(1000)
+            .min(crate::shadow::MAX_LIST);
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let matching = rib.filtered.list(|e| {
+            within.as_ref().map(|l| l.covers(&e.route)).unwrap_or(true)
+                && origin.map(|o| e.origin == Some(o)).unwrap_or(true)
+                && reason
+                    .as_ref()
+                    .map(|r| e.reason == r.as_str())
+                    .unwrap_or(true)
+        });
+        let (mut found, mut items) = (0, Vec::new());
+        for e in matching {
+            if found >= skip && items.len() < limit {
+                items.push(e);
+            }
+            found += 1;
+        }
+        json_response(&serde_json::json!({
+            "size": rib.filtered.size(),
+            "count": rib.filtered.len(),
+            "evicted": rib.filtered.evicted(),
+            "found": found,
+            "items": items,
+        }))
+    }
+    pub async fn say_prefixlen(&self) -> Result<Response<Body>, hyper::http::Error> {
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let mut ret = std::collections::BTreeMap::new();
+        ret.insert("ipv4u", rib.ipv4u.prefixlen.report(24));
+        ret.insert("ipv4m", rib.ipv4m.prefixlen.report(24));
+        ret.insert("ipv6u", rib.ipv6u.prefixlen.report(48));
+        ret.insert("ipv6m", rib.ipv6m.prefixlen.report(48));
+        json_response(&ret)
+    }
+    /// prefixes with active routes in special-use and transition blocks
+    pub async fn say_addrclass(&self) -> Result<Response<Body>, hyper::http::Error> {
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let mut ret = std::collections::BTreeMap::new();
+        ret.insert("ipv4u", rib.ipv4u.addrclass.report());
+        ret.insert("ipv4m", rib.ipv4m.addrclass.report());
+        ret.insert("ipv6u", rib.ipv6u.addrclass.report());
+        ret.insert("ipv6m", rib.ipv6m.addrclass.report());
+        json_response(&ret)
+    }
+    pub async fn say_clusterpaths(
+        &self,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let limit = get_url_param::<usize>(&get_url_params(req), "limit").unwrap_or(1000);
+        let deadline = std::time::Instant::now() + self.locktimeout;
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let mut ret = Vec::new();
+        let complete = crate::reflection::find("ipv4u", &rib.ipv4u, Some(deadline), &mut ret)
+            && crate::reflection::find("ipv6u", &rib.ipv6u, Some(deadline), &mut ret);
+        drop(rib);
+        ret.sort_by_key(|g| std::cmp::Reverse(g.routes));
+        ret.truncate(limit);
+        json_response(&serde_json::json!({
+            "partial": !complete,
+            "groups": ret,
+        }))
+    }
+    /// retained history of exact prefix from rib (ipv4u/ipv6u by default) as ndjson or csv
+    pub async fn say_prefix_history(
+        &self,
+        prefix: &str,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let paramshm = get_url_params(req);
+        let format = match get_url_param::<String>(&paramshm, "format") {
+            None => crate::histexport::ExportFormat::NdJson,
+            Some(s) => match s.parse::<crate::histexport::ExportFormat>() {
+                Ok(f) => f,
+                Err(e) => return bad_request(e.as_str()),
+            },
+        };
+        let (addr, len) = match prefix
+            .split_once('/')
+            .and_then(|(a, l)| Some((a.parse::<std::net::IpAddr>().ok()?, l.parse::<u8>().ok()?)))
+        {
+            Some(p) => p,
+            None => return bad_request("Invalid prefix"),
+        };
+        let ribname = get_url_param::<String>(&paramshm, "rib")
+            .unwrap_or_else(|| if addr.is_ipv4() { "ipv4u" } else { "ipv6u" }.to_string());
+        let mut fmt = self.format;
+        if let Some(n) = get_url_param(&paramshm, "time_format") {
+            fmt.time = n;
+        };
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let entry = match prefix_entry(&rib, addr, len, ribname.as_str()) {
+            Ok(e) => e,
+            Err(e) => return bad_request(e),
+        };
+        let body = match entry {
+            Some(e) => {
+                let rows = crate::histexport::rows(format!("{}/{}", addr, len).as_str(), e);
+                crate::histexport::render(&rows, format, fmt.time)
+            }
+            None => return Ok(not_found()),
+        };
+        let ext = match format {
+            crate::histexport::ExportFormat::NdJson => "ndjson",
+            crate::histexport::ExportFormat::Csv => "csv",
+        };
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-type", format.content_type())
+            .header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}_{}-history.{}\"", addr, len, ext),
+            )
+            .body(body.into())
+    }
+    /// history rows of prefix as JSON, None if RIB lock timed out
+    pub async fn prefix_history_json(
+        &self,
+        addr: std::net::IpAddr,
+        len: u8,
+        ribname: &str,
+    ) -> Option<Result<Option<Vec<serde_json::Value>>, &'static str>> {
+        let rib = timeout(self.locktimeout, self.rib.read()).await.ok()?;
+        let entry = match prefix_entry(&rib, addr, len, ribname) {
+            Ok(Some(e)) => e,
+            Ok(None) => return Some(Ok(None)),
+            Err(e) => return Some(Err(e)),
+        };
+        let rows = crate::histexport::rows(format!("{}/{}", addr, len).as_str(), entry);
+        Some(Ok(Some(
+            rows.iter().map(|r| r.to_json(self.format.time)).collect(),
+        )))
+    }
+    pub async fn say_timeline(
+        &self,
+        queryrib: &str,
+        req: &Request<Body>,
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let paramshm = get_url_params(req);
+        let route = match get_url_param::<String>(&paramshm, "route") {
+            Some(s) => s,
+            None => return bad_request("Missing route parameter"),
+        };
+        let rib = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let filter = ribfilter::RouteFilter::fromstr(route.as_str());
+        match rib.route_timeline(queryrib, &filter) {
+            Some(r) => json_response(&r),
+            None => Ok(not_found()),
+        }
+    }
+    /// merged view of the same query over local and federated RIBs, sessions of every
+    /// prefix are tagged with source name
+    pub async fn say_federated(
+        &self,
+        queryrib: &str,
+        req: &Request<Body>,
+        localname: &str,
+        sources: &[Arc<FederationSource>],
+    ) -> Result<Response<Body>, hyper::http::Error> {
+        let mut params = RibResponseParams::new(0, 1000, 10, false);
+        params.bestpath = self.bestpath;
+        params.format = self.format;
+        params.blackhole = self.blackhole.clone();
+        let mut filter = ribfilter::RouteFilter::new();
+        let paramshm = get_url_params(req);
+        params.extract_params(&paramshm);
+        if let Some(s) = get_url_param::<String>(&paramshm, "filter") {
+            if let Some(c) = ribfilter::RouteFilter::unknown_community(s.as_str()) {
+                return bad_request(format!("Invalid filter: unknown community {}", c).as_str());
+            }
+            filter.parse(s.as_str());
+        };
+        if let Err(e) = filter.extract_params(&paramshm) {
+            return bad_request(format!("Invalid parameter: {}", e).as_str());
+        }
+        if let Some(q) = get_url_param::<String>(&paramshm, "q") {
+            if let Err(e) = filter.parse_query(q.as_str()) {
+                return bad_request(format!("Invalid query: {}", e).as_str());
+            }
+        }
+        filter.resolve_peers(&self.peer_as.read().unwrap());
+        let local = match timeout(self.locktimeout, self.rib.read()).await {
+            Ok(r) => r,
+            Err(_) => return timed_out(),
+        };
+        let mut guards = Vec::new();
+        let mut errors = Vec::new();
+        for src in sources.iter() {
+            match timeout(self.locktimeout, src.rib.read()).await {
+                Ok(rib) => guards.push((src.name.as_str(), rib)),
+                Err(_) => {
+                    errors.push(serde_json::json!({"source": src.name, "error": "timed out"}))
+                }
+            }
+        }
+        let mut ribs: Vec<(&str, &BgpRIB)> = vec![(localname, &local)];
+        ribs.extend(guards.iter().map(|(n, g)| (*n, &**g)));
+        let mut ret = match merged_json(&ribs, queryrib, &filter, &params) {
+            Some(v) => v,
+            None => return bad_request("Unknown RIB"),
+        };
+        ret["rib"] = queryrib.into();
+        ret["sources"] = ribs.iter().map(|(n, _)| *n).collect::<Vec<_>>().into();
+        ret["skip"] = params.skip.into();
+        ret["limit"] = params.limit.into();
+        if !errors.is_empty() {
+            ret["errors"] = errors.into();
+        }
+        if params.is_partial() {
+            ret["partial"] = true.into();
+        }
+        json_response(&ret)
+    }
 }
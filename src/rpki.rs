@@ -0,0 +1,160 @@
+//! route origin validation (RFC 6811) against ROAs of rpki_roas option, a JSON export of
+//! validated ROA payloads as written by rpki-client, Routinator or Fort:
+//! {"roas": [{"asn": "AS64500", "prefix": "192.0.2.0/24", "maxLength": 24}, ...]}
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RpkiState {
+    Valid,
+    Invalid,
+    NotFound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Roa {
+    pub asn: u32,
+    pub prefix: String,
+    #[serde(rename = "maxLength")]
+    pub max_len: u8,
+}
+
+#[derive(Deserialize)]
+struct RoaRecord {
+    asn: serde_json::Value,
+    prefix: String,
+    #[serde(rename = "maxLength")]
+    max_length: Option<u8>,
+}
+#[derive(Deserialize)]
+struct RoaExport {
+    roas: Vec<RoaRecord>,
+}
+
+fn mask(a: IpAddr, len: u8) -> IpAddr {
+    match a {
+        IpAddr::V4(a) => {
+            let m = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            IpAddr::V4((u32::from(a) & m).into())
+        }
+        IpAddr::V6(a) => {
+            let m = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            IpAddr::V6((u128::from(a) & m).into())
+        }
+    }
+}
+
+/// ROAs by network and length
+#[derive(Debug, Default)]
+pub struct RoaTable {
+    roas: BTreeMap<(IpAddr, u8), Vec<(u8, u32)>>,
+}
+impl RoaTable {
+    pub fn load(path: &str) -> Result<RoaTable, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        RoaTable::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+    pub fn parse(text: &str) -> Result<RoaTable, String> {
+        let export: RoaExport = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let mut ret = RoaTable::default();
+        for r in export.roas {
+            let asn = match &r.asn {
+                serde_json::Value::Number(n) => n.as_u64(),
+                serde_json::Value::String(s) => s
+                    .trim_start_matches("AS")
+                    .trim_start_matches("as")
+                    .parse()
+                    .ok(),
+                _ => None,
+            }
+            .filter(|n| *n <= u32::MAX as u64)
+            .ok_or_else(|| format!("invalid asn {} of {}", r.asn, r.prefix))?;
+            let (addr, len) = r
+                .prefix
+                .split_once('/')
+                .and_then(|(a, l)| Some((a.parse::<IpAddr>().ok()?, l.parse::<u8>().ok()?)))
+                .ok_or_else(|| format!("invalid prefix {}", r.prefix))?;
+            let bits = if addr.is_ipv4() { 32 } else { 128 };
+            let max_len = r.max_length.unwrap_or(len);
+            if len > bits || max_len < len || max_len > bits {
+                return Err(format!("invalid length of {}", r.prefix));
+            }
+            ret.roas
+                .entry((mask(addr, len), len))
+                .or_default()
+                .push((max_len, asn as u32));
+        }
+        Ok(ret)
+    }
+    pub fn len(&self) -> usize {
+        self.roas.values().map(|v| v.len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.roas.is_empty()
+    }
+    /// ROAs with prefix covering given one
+    pub fn covering(&self, addr: IpAddr, len: u8) -> Vec<Roa> {
+        let mut ret = Vec::new();
+        if len > if addr.is_ipv4() { 32 } else { 128 } {
+            return ret;
+        }
+        for l in 0..=len {
+            let net = mask(addr, l);
+            if let Some(v) = self.roas.get(&(net, l)) {
+                ret.extend(v.iter().map(|(max_len, asn)| Roa {
+                    asn: *asn,
+                    prefix: format!("{}/{}", net, l),
+                    max_len: *max_len,
+                }));
+            }
+        }
+        ret
+    }
+    /// RFC 6811 state of route with given origin, None for origin of AS_SET
+    pub fn validate(&self, addr: IpAddr, len: u8, origin: Option<u32>) -> RpkiState {
+        let covering = self.covering(addr, len);
+        if covering.is_empty() {
+            return RpkiState::NotFound;
+        }
+        match origin {
+            Some(asn) if asn != 0 && covering.iter().any(|r| r.asn == asn && len <= r.max_len) => {
+                RpkiState::Valid
+            }
+            _ => RpkiState::Invalid,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rpki_validate() {
+        let t = RoaTable::parse(
+            r#"{"roas": [
+                {"asn": "AS64500", "prefix": "192.0.2.0/23", "maxLength": 24, "ta": "test"},
+                {"asn": 64501, "prefix": "2001:db8::/32"},
+                {"asn": "AS0", "prefix": "198.51.100.0/24", "maxLength": 24}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(t.len(), 3);
+        let a: IpAddr = "192.0.3.0".parse().unwrap();
+        assert_eq!(t.validate(a, 24, Some(64500)), RpkiState::Valid);
+        assert_eq!(t.validate(a, 25, Some(64500)), RpkiState::Invalid);
+        assert_eq!(t.validate(a, 24, Some(64502)), RpkiState::Invalid);
+        assert_eq!(t.validate(a, 24, None), RpkiState::Invalid);
+        let a: IpAddr = "2001:db8:1::".parse().unwrap();
+        assert_eq!(t.validate(a, 48, Some(64501)), RpkiState::Invalid);
+        assert_eq!(t.validate(a, 32, Some(64501)), RpkiState::Valid);
+        let a: IpAddr = "198.51.100.0".parse().unwrap();
+        assert_eq!(t.validate(a, 24, Some(0)), RpkiState::Invalid);
+        let a: IpAddr = "203.0.113.0".parse().unwrap();
+        assert_eq!(t.validate(a, 24, Some(64500)), RpkiState::NotFound);
+        assert_eq!(t.covering("192.0.2.128".parse().unwrap(), 25).len(), 1);
+        assert!(RoaTable::parse(r#"{"roas": [{"asn": 1, "prefix": "10.0.0.0/33"}]}"#).is_err());
+    }
+}
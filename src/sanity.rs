@@ -0,0 +1,170 @@
+//! eBGP sanity checks of received routes. Transparent route servers do not put their AS
+//! into path, so checks reject routes only when enabled in ebgp_checks; violations are
+//! counted either way.
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use zettabgp::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Check {
+    /// first AS in path is not peer AS
+    FirstAs,
+    /// path is empty
+    EmptyPath,
+    /// path contains local AS
+    OwnAs,
+}
+impl Check {
+    const ALL: [Check; 3] = [Check::FirstAs, Check::EmptyPath, Check::OwnAs];
+
+    /// comma separated first_as, empty_path, own_as; "all" or "none"
+    pub fn parse_list(s: &str) -> Result<Vec<Check>, String> {
+        let mut ret = Vec::new();
+        for item in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            match item {
+                "all" => ret.extend_from_slice(&Check::ALL),
+                "none" => {}
+                "first_as" => ret.push(Check::FirstAs),
+                "empty_path" => ret.push(Check::EmptyPath),
+                "own_as" => ret.push(Check::OwnAs),
+                x => return Err(format!("unknown check {}", x)),
+            }
+        }
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
+    /// first violated check of path received over eBGP session
+    pub fn violated(local_as: u32, peer_as: u32, path: &[u32]) -> Option<Check> {
+        if local_as == peer_as {
+            return None;
+        }
+        match path.first() {
+            None => Some(Check::EmptyPath),
+            Some(first) if *first != peer_as => Some(Check::FirstAs),
+            _ if path.contains(&local_as) => Some(Check::OwnAs),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SanityStats {
+    /// updates violating each check, whether enforced or not
+    pub first_as: u64,
+    pub empty_path: u64,
+    pub own_as: u64,
+    /// updates treated as withdraw by enforced checks
+    pub rejected: u64,
+}
+
+#[derive(Default)]
+pub struct SanityChecks {
+    enforced: Vec<Check>,
+    first_as: AtomicU64,
+    empty_path: AtomicU64,
+    own_as: AtomicU64,
+    rejected: AtomicU64,
+}
+impl SanityChecks {
+    pub fn new(enforced: &[Check]) -> SanityChecks {
+        SanityChecks {
+            enforced: enforced.to_vec(),
+            ..Default::default()
+        }
+    }
+    /// counts violation of update carrying routes, true when it should be treated as withdraw
+    pub fn reject(&self, local_as: u32, peer_as: u32, upd: &BgpUpdateMessage) -> bool {
+        if upd.updates.is_empty()
+            && !upd
+                .attrs
+                .iter()
+                .any(|a| matches!(a, BgpAttrItem::MPUpdates(_)))
+        {
+            return false;
+        }
+        let path: Vec<u32> = upd
+            .get_attr_aspath()
+            .map(|p| {
+                crate::compact::CompactASPath::from_aspath(p)
+                    .value
+                    .iter()
+                    .map(|a| a.value)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let check = match Check::violated(local_as, peer_as, &path) {
+            None => return false,
+            Some(c) => c,
+        };
+        match check {
+            Check::FirstAs => &self.first_as,
+            Check::EmptyPath => &self.empty_path,
+            Check::OwnAs => &self.own_as,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+        if !self.enforced.contains(&check) {
+            return false;
+        }
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+    pub fn stats(&self) -> SanityStats {
+        SanityStats {
+            first_as: self.first_as.load(Ordering::Relaxed),
+            empty_path: self.empty_path.load(Ordering::Relaxed),
+            own_as: self.own_as.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// withdraw-only updates for routes announced and withdrawn by rejected update
+pub fn treat_as_withdraw(upd: BgpUpdateMessage) -> Vec<BgpUpdateMessage> {
+    let mut lists = vec![upd.withdraws, upd.updates];
+    for a in upd.attrs.into_iter() {
+        match a {
+            BgpAttrItem::MPUpdates(n) => lists.push(n.addrs),
+            BgpAttrItem::MPWithdraws(n) => lists.push(n.addrs),
+            _ => {}
+        }
+    }
+    lists
+        .into_iter()
+        .filter(|a| !a.is_empty())
+        .map(|a| {
+            let mut w = BgpUpdateMessage::new();
+            w.withdraws = a;
+            w
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks() {
+        assert_eq!(
+            Check::parse_list("own_as, first_as").unwrap(),
+            vec![Check::FirstAs, Check::OwnAs]
+        );
+        assert_eq!(Check::parse_list("all").unwrap().len(), 3);
+        assert!(Check::parse_list("none").unwrap().is_empty());
+        assert!(Check::parse_list("bogon").is_err());
+        // route server 64510 does not prepend itself
+        assert_eq!(
+            Check::violated(64500, 64510, &[64496, 64497]),
+            Some(Check::FirstAs)
+        );
+        assert_eq!(Check::violated(64500, 64510, &[64510, 64496]), None);
+        assert_eq!(Check::violated(64500, 64510, &[]), Some(Check::EmptyPath));
+        assert_eq!(
+            Check::violated(64500, 64510, &[64510, 64500]),
+            Some(Check::OwnAs)
+        );
+        // iBGP
+        assert_eq!(Check::violated(64500, 64500, &[]), None);
+    }
+}
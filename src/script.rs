@@ -0,0 +1,462 @@
+//! user script deciding on inbound unicast routes before RIB insertion, available with scripting feature
+//!
+//! Script defines `fn filter(route)`, route is a map with session, prefix, origin, nexthop,
+//! aspath, communities, med and localpref. It returns true to keep route, false to drop it
+//! (dropped route is withdrawn from session) or modified route map to keep route with
+//! rewritten med, localpref and communities, keys missing from returned map are left as received.
+//! Script runs before RIB write lock is taken, only its decisions are applied under the lock.
+use crate::bgpattrs::BgpAttrs;
+use crate::bgpsvc::BgpSessionId;
+use crate::compact::CompactCommunityList;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime};
+use zettabgp::prelude::*;
+
+/// how often script file is checked for changes
+const RELOAD_CHECK: Duration = Duration::from_secs(5);
+
+/// attributes changed by script, None fields are left as received
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AttrEdit {
+    pub med: Option<Option<u32>>,
+    pub localpref: Option<Option<u32>>,
+    pub comms: Option<CompactCommunityList>,
+}
+impl AttrEdit {
+    pub fn is_empty(&self) -> bool {
+        *self == AttrEdit::default()
+    }
+    pub fn apply(&self, attrs: &BgpAttrs) -> BgpAttrs {
+        let mut a = attrs.clone();
+        if let Some(med) = self.med {
+            a.med = med;
+        }
+        if let Some(lp) = self.localpref {
+            a.localpref = lp;
+        }
+        if let Some(ref c) = self.comms {
+            a.comms = std::sync::Arc::new(c.clone());
+        }
+        a
+    }
+}
+
+/// script decision for one route
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Keep,
+    Drop,
+    Rewrite(AttrEdit),
+}
+
+/// script verdicts of update routes by prefix, taken before RIB lock, routes missing are kept
+pub type Decisions = BTreeMap<String, Verdict>;
+
+/// routes grouped by attribute changes (None for unchanged) and dropped routes
+pub type ScriptResult = (Vec<(Option<AttrEdit>, BgpAddrs)>, Option<BgpAddrs>);
+
+/// unicast route prefix passed to filters
+#[derive(Debug, Clone)]
+pub enum RoutePrefix {
+    V4(BgpAddrV4),
+    V6(BgpAddrV6),
+}
+impl std::fmt::Display for RoutePrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoutePrefix::V4(a) => write!(f, "{}", a),
+            RoutePrefix::V6(a) => write!(f, "{}", a),
+        }
+    }
+}
+
+fn split_vec<T>(
+    items: Vec<T>,
+    prefix: impl Fn(&T) -> RoutePrefix,
+    decide: &mut dyn FnMut(&RoutePrefix) -> Verdict,
+) -> (BTreeMap<Option<AttrEdit>, Vec<T>>, Vec<T>) {
+    let mut groups: BTreeMap<Option<AttrEdit>, Vec<T>> = BTreeMap::new();
+    let mut dropped = Vec::new();
+    for item in items.into_iter() {
+        match decide(&prefix(&item)) {
+            Verdict::Keep => groups.entry(None).or_default().push(item),
+            Verdict::Drop => dropped.push(item),
+            Verdict::Rewrite(a) => groups.entry(Some(a)).or_default().push(item),
+        }
+    }
+    (groups, dropped)
+}
+
+/// applies decision to ipv4u/ipv6u routes, other families are kept as is
+pub fn split_addrs(
+    addrs: BgpAddrs,
+    decide: &mut dyn FnMut(&RoutePrefix) -> Verdict,
+) -> ScriptResult {
+    macro_rules! split {
+        ($v:expr, $ctor:path, $pfx:expr) => {{
+            let (groups, dropped) = split_vec($v, $pfx, decide);
+            (
+                groups.into_iter().map(|(a, v)| (a, $ctor(v))).collect(),
+                if dropped.is_empty() {
+                    None
+                } else {
+                    Some($ctor(dropped))
+                },
+            )
+        }};
+    }
+    match addrs {
+        BgpAddrs::IPV4U(v) => split!(v, BgpAddrs::IPV4U, |r: &BgpAddrV4| RoutePrefix::V4(
+            r.clone()
+        )),
+        BgpAddrs::IPV6U(v) => split!(v, BgpAddrs::IPV6U, |r: &BgpAddrV6| RoutePrefix::V6(
+            r.clone()
+        )),
+        BgpAddrs::IPV4UP(v) => split!(v, BgpAddrs::IPV4UP, |r: &WithPathId<BgpAddrV4>| {
+            RoutePrefix::V4(r.nlri.clone())
+        }),
+        BgpAddrs::IPV6UP(v) => split!(v, BgpAddrs::IPV6UP, |r: &WithPathId<BgpAddrV6>| {
+            RoutePrefix::V6(r.nlri.clone())
+        }),
+        other => (vec![(None, other)], None),
+    }
+}
+
+/// ipv4u/ipv6u prefixes of addrs, other families are not passed to script
+pub fn prefixes(addrs: &BgpAddrs) -> Vec<RoutePrefix> {
+    match addrs {
+        BgpAddrs::IPV4U(v) => v.iter().map(|r| RoutePrefix::V4(r.clone())).collect(),
+        BgpAddrs::IPV6U(v) => v.iter().map(|r| RoutePrefix::V6(r.clone())).collect(),
+        BgpAddrs::IPV4UP(v) => v.iter().map(|r| RoutePrefix::V4(r.nlri.clone())).collect(),
+        BgpAddrs::IPV6UP(v) => v.iter().map(|r| RoutePrefix::V6(r.nlri.clone())).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// attributes script sees for routes of update, built without RIB attribute stores
+fn script_attrs(upd: &BgpUpdateMessage, confed_in_aspath: bool) -> BgpAttrs {
+    let mut a = BgpAttrs::new();
+    if let Some(n) = upd.get_attr_origin() {
+        a.origin = n.value;
+    }
+    if let Some(n) = upd.get_attr_nexthop() {
+        a.nexthop = match n.value {
+            std::net::IpAddr::V4(v) => BgpAddr::V4(v),
+            std::net::IpAddr::V6(v) => BgpAddr::V6(v),
+        };
+    }
+    if let Some(n) = upd.get_attr_aspath() {
+        a.aspath = std::sync::Arc::new(crate::compact::CompactASPath::from_aspath(n));
+    }
+    if let Some(n) = upd.get_attr_communitylist() {
+        a.comms = std::sync::Arc::new(n.into());
+    }
+    for i in upd.attrs.iter() {
+        match i {
+            BgpAttrItem::MED(n) => a.med = Some(n.value),
+            BgpAttrItem::LocalPref(n) => a.localpref = Some(n.value),
+            BgpAttrItem::Unknown(n)
+                if confed_in_aspath && n.params.typecode == crate::wireparse::ATTR_CONFED_PATH =>
+            {
+                if let Ok(confed) = crate::confed::BgpConfedPath::decode_from(&n.value) {
+                    let mut path = confed.leading_asns();
+                    path.extend(a.aspath.value.iter().map(|x| x.value));
+                    a.aspath = std::sync::Arc::new(path.into());
+                }
+            }
+            _ => {}
+        }
+    }
+    a
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// compiled script, recompiled when file changes
+pub struct UpdateScript {
+    path: String,
+    modified: Option<SystemTime>,
+    checked: Instant,
+    #[cfg(feature = "scripting")]
+    engine: rhai::Engine,
+    #[cfg(feature = "scripting")]
+    ast: rhai::AST,
+}
+impl UpdateScript {
+    #[cfg(feature = "scripting")]
+    pub fn load(path: &str) -> Result<UpdateScript, String> {
+        let mut engine = rhai::Engine::new();
+        // runaway scripts must not stall RIB updates
+        engine.set_max_operations(100_000);
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|e| format!("{}: {}", path, e))?;
+        Ok(UpdateScript {
+            path: path.to_string(),
+            modified: modified(path),
+            checked: Instant::now(),
+            engine,
+            ast,
+        })
+    }
+    #[cfg(not(feature = "scripting"))]
+    pub fn load(_path: &str) -> Result<UpdateScript, String> {
+        Err("built without scripting feature".to_string())
+    }
+    /// recompiles script when file was changed, checked at most every few seconds
+    pub fn reload_if_changed(&mut self) {
+        if self.checked.elapsed() < RELOAD_CHECK {
+            return;
+        }
+        self.checked = Instant::now();
+        let m = modified(self.path.as_str());
+        if m == self.modified {
+            return;
+        }
+        match UpdateScript::load(self.path.as_str()) {
+            Ok(s) => {
+                info!("Update script {} reloaded", self.path);
+                *self = s;
+            }
+            Err(e) => {
+                // keep running previous version until file is fixed
+                warn!("Update script reload error: {}", e);
+                self.modified = m;
+            }
+        }
+    }
+    /// runs filter for route
+    #[cfg(feature = "scripting")]
+    pub fn decide(&self, sid: BgpSessionId, prefix: &RoutePrefix, attrs: &BgpAttrs) -> Verdict {
+        let route = route_map(sid, prefix.to_string(), attrs);
+        match self.engine.call_fn::<rhai::Dynamic>(
+            &mut rhai::Scope::new(),
+            &self.ast,
+            "filter",
+            (route,),
+        ) {
+            Ok(res) => verdict(attrs, res),
+            Err(e) => {
+                // fail open, erroneous script should not empty RIB
+                warn!("Update script error on {}: {}", prefix, e);
+                Verdict::Keep
+            }
+        }
+    }
+    #[cfg(not(feature = "scripting"))]
+    pub fn decide(&self, _sid: BgpSessionId, _prefix: &RoutePrefix, _attrs: &BgpAttrs) -> Verdict {
+        Verdict::Keep
+    }
+    /// runs filter for every ipv4u/ipv6u route of update, kept routes are left out
+    pub fn decide_update(
+        &self,
+        sid: BgpSessionId,
+        upd: &BgpUpdateMessage,
+        confed_in_aspath: bool,
+    ) -> Decisions {
+        let mut ret = Decisions::new();
+        let mut attrs = script_attrs(upd, confed_in_aspath);
+        let mut run = |attrs: &BgpAttrs, addrs: &BgpAddrs| {
+            for p in prefixes(addrs) {
+                match self.decide(sid, &p, attrs) {
+                    Verdict::Keep => {}
+                    v => {
+                        ret.insert(p.to_string(), v);
+                    }
+                }
+            }
+        };
+        run(&attrs, &upd.updates);
+        for i in upd.attrs.iter() {
+            if let BgpAttrItem::MPUpdates(n) = i {
+                attrs.nexthop = n.nexthop.clone();
+                run(&attrs, &n.addrs);
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn route_map(sid: BgpSessionId, prefix: String, a: &BgpAttrs) -> rhai::Map {
+    use rhai::Dynamic;
+    let opt = |v: Option<u32>| v.map(|x| Dynamic::from(x as i64)).unwrap_or(Dynamic::UNIT);
+    let mut m = rhai::Map::new();
+    m.insert("session".into(), Dynamic::from(sid as i64));
+    m.insert("prefix".into(), Dynamic::from(prefix));
+    m.insert(
+        "origin".into(),
+        Dynamic::from(format!("{:?}", a.origin).to_lowercase()),
+    );
+    m.insert("nexthop".into(), Dynamic::from(a.nexthop.to_string()));
+    m.insert(
+        "aspath".into(),
+        Dynamic::from(
+            a.aspath
+                .value
+                .iter()
+                .map(|x| Dynamic::from(x.value as i64))
+                .collect::<rhai::Array>(),
+        ),
+    );
+    m.insert(
+        "communities".into(),
+        Dynamic::from(
+            a.comms
+                .value
+                .iter()
+                .map(|c| Dynamic::from(c.to_string()))
+                .collect::<rhai::Array>(),
+        ),
+    );
+    m.insert("med".into(), opt(a.med));
+    m.insert("localpref".into(), opt(a.localpref));
+    m
+}
+
+#[cfg(feature = "scripting")]
+fn verdict(orig: &BgpAttrs, res: rhai::Dynamic) -> Verdict {
+    if let Ok(keep) = res.as_bool() {
+        return if keep { Verdict::Keep } else { Verdict::Drop };
+    }
+    let m = match res.try_cast::<rhai::Map>() {
+        Some(m) => m,
+        None => {
+            warn!("Update script filter returned neither bool nor route");
+            return Verdict::Keep;
+        }
+    };
+    // absent key keeps attribute, unit clears it
+    let num = |k: &str, cur: Option<u32>| -> Option<Option<u32>> {
+        let v = m.get(k)?;
+        let n = if v.is_unit() {
+            None
+        } else {
+            match v.as_int() {
+                Ok(n) => Some(n as u32),
+                Err(_) => {
+                    warn!("Update script returned invalid {}", k);
+                    return None;
+                }
+            }
+        };
+        if n == cur {
+            None
+        } else {
+            Some(n)
+        }
+    };
+    let mut edit = AttrEdit {
+        med: num("med", orig.med),
+        localpref: num("localpref", orig.localpref),
+        comms: None,
+    };
+    if let Some(arr) = m
+        .get("communities")
+        .and_then(|v| v.clone().try_cast::<rhai::Array>())
+    {
+        let mut cl = Vec::new();
+        for c in arr.into_iter().filter_map(|c| c.into_string().ok()) {
+            match c
+                .split_once(':')
+                .and_then(|(h, l)| Some((h.parse().ok()?, l.parse().ok()?)))
+            {
+                Some((h, l)) => {
+                    cl.push(BgpCommunity::from(h, l));
+                }
+                None => warn!("Update script returned invalid community {}", c),
+            }
+        }
+        let cl = CompactCommunityList::from_vec(cl);
+        if cl != *orig.comms {
+            edit.comms = Some(cl);
+        }
+    }
+    if edit.is_empty() {
+        Verdict::Keep
+    } else {
+        Verdict::Rewrite(edit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_split_addrs() {
+        let addrs = BgpAddrs::IPV4U(vec![
+            BgpAddrV4::new(Ipv4Addr::new(10, 0, 0, 0), 8),
+            BgpAddrV4::new(Ipv4Addr::new(192, 168, 0, 0), 16),
+            BgpAddrV4::new(Ipv4Addr::new(172, 16, 0, 0), 12),
+        ]);
+        let (groups, dropped) = split_addrs(addrs, &mut |p| {
+            if p.to_string().starts_with("192.168.") {
+                Verdict::Drop
+            } else {
+                Verdict::Keep
+            }
+        });
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].0, None);
+        assert_eq!(groups[0].1.len(), 2);
+        assert_eq!(dropped.map(|d| d.len()), Some(1));
+        // other families bypass script
+        let (groups, dropped) = split_addrs(BgpAddrs::None, &mut |_| Verdict::Drop);
+        assert_eq!(groups.len(), 1);
+        assert!(dropped.is_none());
+    }
+
+    #[test]
+    fn test_attr_edit() {
+        let mut orig = BgpAttrs::new();
+        orig.med = Some(10);
+        orig.localpref = Some(100);
+        let edit = AttrEdit {
+            localpref: Some(Some(200)),
+            ..Default::default()
+        };
+        let a = edit.apply(&orig);
+        assert_eq!((a.med, a.localpref), (Some(10), Some(200)));
+        let edit = AttrEdit {
+            med: Some(None),
+            ..Default::default()
+        };
+        assert_eq!(edit.apply(&orig).med, None);
+        assert!(AttrEdit::default().is_empty());
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn test_verdict() {
+        let mut orig = BgpAttrs::new();
+        orig.med = Some(10);
+        orig.localpref = Some(100);
+        let engine = rhai::Engine::new();
+        let run = |s: &str| verdict(&orig, engine.eval::<rhai::Dynamic>(s).unwrap());
+        assert_eq!(run("true"), Verdict::Keep);
+        assert_eq!(run("false"), Verdict::Drop);
+        // untouched attributes are kept
+        assert_eq!(
+            run("#{communities: [\"64500:1\"]}"),
+            Verdict::Rewrite(AttrEdit {
+                comms: Some(CompactCommunityList::from_vec(vec![BgpCommunity::from(
+                    64500, 1
+                )])),
+                ..Default::default()
+            })
+        );
+        assert_eq!(run("#{med: 10, localpref: 100}"), Verdict::Keep);
+        assert_eq!(
+            run("#{med: (), localpref: 200}"),
+            Verdict::Rewrite(AttrEdit {
+                med: Some(None),
+                localpref: Some(Some(200)),
+                comms: None,
+            })
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::bgprib::*;
+use crate::ribfilter::{FilterMatchRoute, RouteFilter};
+use serde::Serialize;
+use zettabgp::prelude::*;
+
+/// Typed result of universal search
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SearchResult {
+    /// route found by address containment or prefix text
+    Prefix { rib: String, prefix: String },
+    /// AS number seen in AS pathes
+    Asn { asn: u32, routes: usize },
+    /// AS name from whois cache
+    AsName { asn: u32, name: String },
+    /// community value seen in routes
+    Community {
+        rib: String,
+        community: String,
+        routes: usize,
+    },
+}
+
+/// what search query looks like
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchQuery {
+    Asn(u32),
+    Community(u16, u16),
+    Address(String),
+    Text(String),
+}
+impl SearchQuery {
+    pub fn parse(q: &str) -> SearchQuery {
+        let q = q.trim();
+        let lq = q.to_lowercase();
+        let asnum = lq.strip_prefix("as").unwrap_or(lq.as_str());
+        if let Ok(n) = asnum.parse::<u32>() {
+            return SearchQuery::Asn(n);
+        }
+        let parts: Vec<&str> = q.split(':').collect();
+        if parts.len() == 2 {
+            if let (Ok(h), Ok(l)) = (parts[0].parse::<u16>(), parts[1].parse::<u16>()) {
+                return SearchQuery::Community(h, l);
+            }
+        }
+        let addr = q.split('/').next().unwrap_or("");
+        if addr.parse::<std::net::IpAddr>().is_ok() {
+            return SearchQuery::Address(q.to_string());
+        }
+        SearchQuery::Text(q.to_string())
+    }
+}
+
+/// routes containing address or contained in prefix
+pub fn search_prefix<T: FilterMatchRoute + BgpRIBKey>(
+    ribname: &str,
+    safi: &BgpRIBSafi<T>,
+    q: &str,
+    limit: usize,
+    ret: &mut Vec<SearchResult>,
+) {
+    let filter = RouteFilter::fromstr(q);
+    let mut found = 0;
+    for (k, _) in filter.iter_nets(safi, 1, true).take(limit) {
+        ret.push(SearchResult::Prefix {
+            rib: ribname.to_string(),
+            prefix: k.to_string(),
+        });
+        found += 1;
+    }
+    if found < 1 {
+        if let Some((k, _)) = filter.find_best_supernet(safi, 1, true) {
+            ret.push(SearchResult::Prefix {
+                rib: ribname.to_string(),
+                prefix: k.to_string(),
+            });
+        }
+    }
+}
+
+/// routes which textual form starts with query
+pub fn search_prefix_text<T: BgpRIBKey + std::string::ToString>(
+    ribname: &str,
+    safi: &BgpRIBSafi<T>,
+    q: &str,
+    limit: usize,
+    ret: &mut Vec<SearchResult>,
+) {
+    for k in safi
+        .items
+        .keys()
+        .map(|k| k.to_string())
+        .filter(|k| k.starts_with(q))
+        .take(limit)
+    {
+        ret.push(SearchResult::Prefix {
+            rib: ribname.to_string(),
+            prefix: k,
+        });
+    }
+}
+
+impl BgpRIB {
+    /// searches unicast RIBs for prefixes, AS numbers and communities
+    pub fn search(&self, q: &str, limit: usize) -> Vec<SearchResult> {
+        let mut ret = Vec::new();
+        match SearchQuery::parse(q) {
+            SearchQuery::Asn(asn) => {
+                let k = BgpAS::new(asn);
+                let routes = self
+                    .ipv4u
+                    .idx_aspath
+                    .idx
+                    .get(&k)
+                    .map(|x| x.len())
+                    .unwrap_or(0)
+                    + self
+                        .ipv6u
+                        .idx_aspath
+                        .idx
+                        .get(&k)
+                        .map(|x| x.len())
+                        .unwrap_or(0);
+                if routes > 0 {
+                    ret.push(SearchResult::Asn { asn, routes });
+                }
+            }
+            SearchQuery::Community(h, l) => {
+                let c = BgpCommunity::from(h, l);
+                for (ribname, routes) in [
+                    (
+                        "ipv4u",
+                        self.ipv4u.idx_community.idx.get(&c).map(|x| x.len()),
+                    ),
+                    (
+                        "ipv6u",
+                        self.ipv6u.idx_community.idx.get(&c).map(|x| x.len()),
+                    ),
+                ] {
+                    if let Some(routes) = routes {
+                        ret.push(SearchResult::Community {
+                            rib: ribname.to_string(),
+                            community: format!("{}:{}", h, l),
+                            routes,
+                        });
+                    }
+                }
+            }
+            SearchQuery::Address(a) => {
+                if a.contains(':') {
+                    search_prefix("ipv6u", &self.ipv6u, a.as_str(), limit, &mut ret);
+                } else {
+                    search_prefix("ipv4u", &self.ipv4u, a.as_str(), limit, &mut ret);
+                }
+            }
+            SearchQuery::Text(t) => {
+                if !t.is_empty()
+                    && t.chars()
+                        .all(|c| c.is_ascii_hexdigit() || c == '.' || c == ':')
+                {
+                    search_prefix_text("ipv4u", &self.ipv4u, t.as_str(), limit, &mut ret);
+                    search_prefix_text("ipv6u", &self.ipv6u, t.as_str(), limit, &mut ret);
+                }
+            }
+        }
+        ret.truncate(limit);
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_query_parse() {
+        assert_eq!(SearchQuery::parse("AS65000"), SearchQuery::Asn(65000));
+        assert_eq!(SearchQuery::parse("13335"), SearchQuery::Asn(13335));
+        assert_eq!(
+            SearchQuery::parse("65000:100"),
+            SearchQuery::Community(65000, 100)
+        );
+        assert_eq!(
+            SearchQuery::parse("10.0.0.0/8"),
+            SearchQuery::Address("10.0.0.0/8".to_string())
+        );
+        assert_eq!(
+            SearchQuery::parse("2001:db8::1"),
+            SearchQuery::Address("2001:db8::1".to_string())
+        );
+        assert_eq!(
+            SearchQuery::parse("cloudflare"),
+            SearchQuery::Text("cloudflare".to_string())
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! secret-bearing options given as `file:/path` or `env:VAR` references instead of plaintext
+use crate::config::ErrorConfig;
+use std::collections::HashMap;
+
+/// option names holding secrets, in any section
+const SECRET_OPTIONS: &[&str] = &[
+    "token",
+    "smtp_user",
+    "smtp_password",
+    "objstore_access_key",
+    "objstore_secret_key",
+    "replication_secret",
+    "anonymize_key",
+];
+
+/// last name components holding secrets in prefixed options like clickhouse.password
+const SECRET_SUFFIXES: &[&str] = &[
+    "password",
+    "secret",
+    "secret_key",
+    "access_key",
+    "dsn",
+    "token",
+];
+
+pub fn is_secret_option(key: &str) -> bool {
+    SECRET_OPTIONS.contains(&key)
+        || key
+            .rsplit_once('.')
+            .map(|(_, last)| SECRET_SUFFIXES.contains(&last))
+            .unwrap_or(false)
+}
+
+/// value of reference, trailing line break of secret files is dropped;
+/// None for plain values
+pub fn resolve(value: &str) -> Option<Result<String, String>> {
+    if let Some(path) = value.strip_prefix("file:") {
+        return Some(
+            std::fs::read_to_string(path.trim())
+                .map(|s| s.trim_end_matches(['\n', '\r']).to_string())
+                .map_err(|e| format!("file {} - {}", path.trim(), e)),
+        );
+    }
+    if let Some(var) = value.strip_prefix("env:") {
+        return Some(
+            std::env::var(var.trim()).map_err(|e| format!("environment {} - {}", var.trim(), e)),
+        );
+    }
+    None
+}
+
+/// replaces references in secret options of all sections
+pub fn resolve_secrets(
+    conf: &mut HashMap<String, HashMap<String, Option<String>>>,
+) -> Result<(), ErrorConfig> {
+    for (sname, section) in conf.iter_mut() {
+        for (key, value) in section.iter_mut() {
+            if !is_secret_option(key) {
+                continue;
+            }
+            let resolved = match value.as_deref().and_then(resolve) {
+                Some(r) => r.map_err(|e| {
+                    ErrorConfig::from_string(format!("Secret {} in [{}]: {}", key, sname, e))
+                })?,
+                None => continue,
+            };
+            *value = Some(resolved);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_secrets() {
+        assert!(is_secret_option("smtp_password"));
+        assert!(is_secret_option("replication_secret"));
+        assert!(is_secret_option("anonymize_key"));
+        assert!(is_secret_option("clickhouse.password"));
+        assert!(!is_secret_option("snapshot"));
+        let path = std::env::temp_dir().join("bgpexplorer_secret_test");
+        std::fs::write(&path, "s3cr3t\n").unwrap();
+        std::env::set_var("BGPEXPLORER_SECRET_TEST", "fromenv");
+        let mut main = HashMap::new();
+        main.insert(
+            "smtp_password".to_string(),
+            Some(format!("file:{}", path.display())),
+        );
+        main.insert(
+            "clickhouse.password".to_string(),
+            Some("env:BGPEXPLORER_SECRET_TEST".to_string()),
+        );
+        main.insert("snapshot".to_string(), Some("file:x".to_string()));
+        let mut conf = HashMap::new();
+        conf.insert("main".to_string(), main);
+        resolve_secrets(&mut conf).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(conf["main"]["smtp_password"].as_deref(), Some("s3cr3t"));
+        assert_eq!(
+            conf["main"]["clickhouse.password"].as_deref(),
+            Some("fromenv")
+        );
+        assert_eq!(conf["main"]["snapshot"].as_deref(), Some("file:x"));
+        conf.get_mut("main").unwrap().insert(
+            "token".to_string(),
+            Some("env:BGPEXPLORER_NO_SUCH_VAR".to_string()),
+        );
+        assert!(resolve_secrets(&mut conf).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::timestamp::Timestamp;
 use hyper::{Body, Request};
 use std::collections::HashMap;
 use std::string::String;
@@ -24,6 +25,60 @@ pub fn get_url_param<T: std::str::FromStr>(
         },
     }
 }
+/// size limit of JSON bodies of API requests
+pub const MAX_API_BODY: usize = 64 * 1024;
+/// reads request body up to limit bytes
+pub async fn read_body(mut body: Body, limit: usize) -> Result<Vec<u8>, String> {
+    use hyper::body::HttpBody;
+    let mut ret = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        if ret.len() + chunk.len() > limit {
+            return Err(format!("body is larger than {} bytes", limit));
+        }
+        ret.extend_from_slice(&chunk);
+    }
+    Ok(ret)
+}
+/// HTTP-date (RFC 7231) representation of timestamp
+pub fn http_date(ts: &Timestamp) -> String {
+    ts.with_timezone(&chrono::Utc)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+/// checks conditional request headers against entity tag and last change time
+pub fn is_not_modified(req: &Request<Body>, etag: &str, changed: &Timestamp) -> bool {
+    if let Some(inm) = req.headers().get(hyper::header::IF_NONE_MATCH) {
+        return match inm.to_str() {
+            Ok(s) => s
+                .split(',')
+                .map(|x| x.trim())
+                .any(|x| x == "*" || x == etag),
+            Err(_) => false,
+        };
+    }
+    if let Some(ims) = req.headers().get(hyper::header::IF_MODIFIED_SINCE) {
+        if let Some(t) = ims
+            .to_str()
+            .ok()
+            .and_then(|s| chrono::DateTime::parse_from_rfc2822(s).ok())
+        {
+            return changed.timestamp() <= t.timestamp();
+        }
+    }
+    false
+}
+/// HTTP 304 response for conditional request
+pub fn not_modified(
+    etag: &str,
+    changed: &Timestamp,
+) -> Result<hyper::Response<Body>, hyper::http::Error> {
+    hyper::Response::builder()
+        .status(hyper::StatusCode::NOT_MODIFIED)
+        .header(hyper::header::ETAG, etag)
+        .header(hyper::header::LAST_MODIFIED, http_date(changed))
+        .body(Body::empty())
+}
 pub fn is_multicast(a: &std::net::IpAddr) -> bool {
     match a {
         std::net::IpAddr::V4(va) => is_multicast_v4(va),
@@ -36,3 +91,62 @@ pub fn is_multicast_v4(a: &std::net::Ipv4Addr) -> bool {
 pub fn is_multicast_v6(a: &std::net::Ipv6Addr) -> bool {
     a.octets()[0] == 255
 }
+/// JSON response for serializable object
+pub fn json_response<T: serde::Serialize>(
+    v: &T,
+) -> Result<hyper::Response<Body>, hyper::http::Error> {
+    match serde_json::to_vec(v) {
+        Ok(v) => hyper::Response::builder()
+            .status(hyper::StatusCode::OK)
+            .header("Content-type", "text/json")
+            .body(v.into()),
+        Err(e) => hyper::Response::builder()
+            .status(hyper::StatusCode::from_u16(500).unwrap())
+            .header("Content-type", "text/plain")
+            .body(format!("Error: {:?}", e).into()),
+    }
+}
+/// HTTP 408 response for lock timeouts
+pub fn timed_out() -> Result<hyper::Response<Body>, hyper::http::Error> {
+    hyper::Response::builder()
+        .status(hyper::StatusCode::from_u16(408).unwrap())
+        .header("Content-type", "text/plain")
+        .body("Operation timed out".into())
+}
+/// HTTP 400 response with explanation text
+pub fn bad_request(msg: &str) -> Result<hyper::Response<Body>, hyper::http::Error> {
+    hyper::Response::builder()
+        .status(hyper::StatusCode::BAD_REQUEST)
+        .header("Content-type", "text/plain")
+        .body(msg.to_string().into())
+}
+/// HTTP 401/403 response for API token checks
+pub fn access_denied(code: hyper::StatusCode) -> hyper::Response<Body> {
+    let mut b = hyper::Response::builder()
+        .status(code)
+        .header("Content-type", "text/plain");
+    if code == hyper::StatusCode::UNAUTHORIZED {
+        b = b.header("WWW-Authenticate", "Bearer");
+    }
+    b.body(code.canonical_reason().unwrap_or("Denied").into())
+        .unwrap()
+}
+
+pub fn too_many_requests(retry_after: u64) -> hyper::Response<Body> {
+    hyper::Response::builder()
+        .status(hyper::StatusCode::TOO_MANY_REQUESTS)
+        .header("Content-type", "text/plain")
+        .header("Retry-After", retry_after.to_string())
+        .body("Too Many Requests".into())
+        .unwrap()
+}
+
+static NOTFOUND: &[u8] = b"Not Found";
+
+/// HTTP status code 404
+pub fn not_found() -> hyper::Response<Body> {
+    hyper::Response::builder()
+        .status(hyper::StatusCode::NOT_FOUND)
+        .body(NOTFOUND.into())
+        .unwrap()
+}
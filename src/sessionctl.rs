@@ -0,0 +1,327 @@
+//! router-style session control: administrative shutdown, hard clear (session torn down
+//! with Cease NOTIFICATION and reconnected) and soft clear (ROUTE-REFRESH sent to peer)
+use crate::bgpsvc::BgpSessionState;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use zettabgp::prelude::*;
+
+/// Cease subcodes, RFC 4486
+pub const CEASE_ADMIN_SHUTDOWN: u8 = 2;
+pub const CEASE_ADMIN_RESET: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ClearMode {
+    Soft,
+    Hard,
+}
+impl std::str::FromStr for ClearMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "soft" => Ok(ClearMode::Soft),
+            "hard" => Ok(ClearMode::Hard),
+            x => Err(format!("unknown clear mode {}", x)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClearRecord {
+    pub time: Timestamp,
+    pub action: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerFsm {
+    pub admin_shutdown: bool,
+    pub last_clear: Option<ClearRecord>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionFsm {
+    pub state: String,
+    /// time of last state change
+    pub since: Timestamp,
+    /// any peer is shut down
+    pub admin_shutdown: bool,
+    /// latest clear of any peer
+    pub last_clear: Option<ClearRecord>,
+    pub peers: BTreeMap<IpAddr, PeerFsm>,
+}
+
+/// control state of sessions with one peer
+pub struct PeerControl {
+    admin_down: AtomicBool,
+    /// hard clear requested for running session
+    reset: AtomicBool,
+    /// token of running session, child of service cancellation token
+    token: Mutex<CancellationToken>,
+    /// soft clear requests of running session, consumed by established BGP session
+    refresh: Mutex<Arc<Notify>>,
+    /// running session peer advertised route refresh capability
+    route_refresh: AtomicBool,
+    enabled: Notify,
+    last_clear: Mutex<Option<ClearRecord>>,
+}
+impl Default for PeerControl {
+    fn default() -> Self {
+        PeerControl {
+            admin_down: AtomicBool::new(false),
+            reset: AtomicBool::new(false),
+            token: Mutex::new(CancellationToken::new()),
+            refresh: Mutex::new(Arc::new(Notify::new())),
+            route_refresh: AtomicBool::new(false),
+            enabled: Notify::new(),
+            last_clear: Mutex::new(None),
+        }
+    }
+}
+impl PeerControl {
+    pub fn is_down(&self) -> bool {
+        self.admin_down.load(Ordering::Relaxed)
+    }
+    /// soft clear requests for new session; requests left from earlier session, which
+    /// Notify keeps as permit, are dropped with its Notify
+    pub fn session_refresh(&self) -> Arc<Notify> {
+        let n = Arc::new(Notify::new());
+        *self.refresh.lock().unwrap() = n.clone();
+        self.route_refresh.store(false, Ordering::Relaxed);
+        n
+    }
+    /// established session peer advertised route refresh capability (RFC 2918)
+    pub fn set_route_refresh(&self, supported: bool) {
+        self.route_refresh.store(supported, Ordering::Relaxed);
+    }
+    /// cancellation token for new session, cancelled by shutdown, hard clear or parent
+    pub fn session_token(&self, parent: &CancellationToken) -> CancellationToken {
+        let t = parent.child_token();
+        self.reset.store(false, Ordering::Relaxed);
+        *self.token.lock().unwrap() = t.clone();
+        t
+    }
+    /// Cease subcode to send when session token was cancelled by operator
+    pub fn cease_subcode(&self) -> Option<u8> {
+        if self.is_down() {
+            Some(CEASE_ADMIN_SHUTDOWN)
+        } else if self.reset.load(Ordering::Relaxed) {
+            Some(CEASE_ADMIN_RESET)
+        } else {
+            None
+        }
+    }
+    /// resolves when session is not administratively shut down
+    pub async fn wait_enabled(&self) {
+        loop {
+            let n = self.enabled.notified();
+            if !self.is_down() {
+                return;
+            }
+            n.await;
+        }
+    }
+    fn note(&self, action: &str) {
+        *self.last_clear.lock().unwrap() = Some(ClearRecord {
+            time: Timestamp::now(),
+            action: action.to_string(),
+        });
+    }
+    pub fn shutdown(&self) {
+        self.admin_down.store(true, Ordering::Relaxed);
+        self.token.lock().unwrap().cancel();
+        self.note("shutdown");
+    }
+    pub fn enable(&self) {
+        self.admin_down.store(false, Ordering::Relaxed);
+        self.enabled.notify_waiters();
+        self.note("enable");
+    }
+    /// soft clear needs established BGP session with peer supporting route refresh, BMP
+    /// feeds can not be refreshed
+    pub fn clear(&self, mode: ClearMode, state: BgpSessionState) -> Result<(), String> {
+        if self.is_down() {
+            return Err("session is administratively shut down".to_string());
+        }
+        match mode {
+            ClearMode::Hard => {
+                self.reset.store(true, Ordering::Relaxed);
+                self.token.lock().unwrap().cancel();
+                self.note("clear hard");
+            }
+            ClearMode::Soft if state != BgpSessionState::Established => {
+                return Err(format!("soft clear needs BGP session, state {:?}", state))
+            }
+            ClearMode::Soft if !self.route_refresh.load(Ordering::Relaxed) => {
+                return Err("peer did not advertise route refresh capability".to_string())
+            }
+            ClearMode::Soft => {
+                self.refresh.lock().unwrap().notify_one();
+                self.note("clear soft");
+            }
+        }
+        Ok(())
+    }
+    fn fsm(&self) -> PeerFsm {
+        PeerFsm {
+            admin_shutdown: self.is_down(),
+            last_clear: self.last_clear.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// control state of each peer, by peer address
+pub struct SessionControl {
+    peers: Mutex<BTreeMap<IpAddr, Arc<PeerControl>>>,
+    since: Mutex<Timestamp>,
+}
+impl Default for SessionControl {
+    fn default() -> Self {
+        SessionControl {
+            peers: Mutex::new(BTreeMap::new()),
+            since: Mutex::new(Timestamp::now()),
+        }
+    }
+}
+impl SessionControl {
+    /// control of peer, created on first use so peer can be shut down before it connects
+    pub fn peer(&self, addr: IpAddr) -> Arc<PeerControl> {
+        self.peers.lock().unwrap().entry(addr).or_default().clone()
+    }
+    /// control of peer given by operator, may be left out when only one peer is known
+    pub fn select(&self, addr: Option<IpAddr>) -> Result<Arc<PeerControl>, String> {
+        if let Some(a) = addr {
+            return Ok(self.peer(a));
+        }
+        let peers = self.peers.lock().unwrap();
+        match peers.len() {
+            1 => Ok(peers.values().next().unwrap().clone()),
+            0 => Err("no peer is known yet, give peer parameter".to_string()),
+            _ => Err("several peers are known, give peer parameter".to_string()),
+        }
+    }
+    pub fn is_down(&self, addr: IpAddr) -> bool {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(&addr)
+            .map(|p| p.is_down())
+            .unwrap_or(false)
+    }
+    pub fn state_changed(&self) {
+        *self.since.lock().unwrap() = Timestamp::now();
+    }
+    pub fn fsm(&self, state: BgpSessionState) -> SessionFsm {
+        let peers: BTreeMap<IpAddr, PeerFsm> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(a, p)| (*a, p.fsm()))
+            .collect();
+        SessionFsm {
+            state: format!("{:?}", state),
+            since: *self.since.lock().unwrap(),
+            admin_shutdown: peers.values().any(|p| p.admin_shutdown),
+            last_clear: peers
+                .values()
+                .filter_map(|p| p.last_clear.clone())
+                .max_by_key(|c| c.time),
+            peers,
+        }
+    }
+}
+
+/// AFI/SAFI of multiprotocol capability
+pub fn afi_safi(cap: &BgpCapability) -> Option<(u16, u8)> {
+    Some(match cap {
+        BgpCapability::SafiIPv4u => (1, 1),
+        BgpCapability::SafiIPv4m => (1, 2),
+        BgpCapability::SafiIPv4lu => (1, 4),
+        BgpCapability::SafiVPNv4u => (1, 128),
+        BgpCapability::SafiVPNv4m => (1, 129),
+        BgpCapability::SafiIPv6u => (2, 1),
+        BgpCapability::SafiIPv6lu => (2, 4),
+        BgpCapability::SafiVPNv6u => (2, 128),
+        BgpCapability::SafiVPNv6m => (2, 129),
+        BgpCapability::SafiVPLS => (25, 65),
+        BgpCapability::SafiEVPN => (25, 70),
+        _ => return None,
+    })
+}
+
+fn message(msgtype: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![255u8; 16];
+    buf.extend_from_slice(&((19 + body.len()) as u16).to_be_bytes());
+    buf.push(msgtype);
+    buf.extend_from_slice(body);
+    buf
+}
+
+/// ROUTE-REFRESH message, RFC 2918
+pub fn route_refresh(afi: u16, safi: u8) -> Vec<u8> {
+    let afi = afi.to_be_bytes();
+    message(5, &[afi[0], afi[1], 0, safi])
+}
+
+/// Cease NOTIFICATION with subcode
+pub fn cease(subcode: u8) -> Vec<u8> {
+    message(3, &[6, subcode])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_control() {
+        let parent = CancellationToken::new();
+        let ctls = SessionControl::default();
+        assert!(ctls.select(None).is_err());
+        let addr: IpAddr = "192.0.2.1".parse().unwrap();
+        let ctl = ctls.peer(addr);
+        assert!(Arc::ptr_eq(&ctls.select(None).unwrap(), &ctl));
+        let other = ctls.peer("192.0.2.2".parse().unwrap());
+        assert!(ctls.select(None).is_err());
+        let t = ctl.session_token(&parent);
+        let ot = other.session_token(&parent);
+        assert!(ctl.clear(ClearMode::Soft, BgpSessionState::BMP).is_err());
+        // no route refresh capability
+        let refresh = ctl.session_refresh();
+        assert!(ctl
+            .clear(ClearMode::Soft, BgpSessionState::Established)
+            .is_err());
+        ctl.set_route_refresh(true);
+        ctl.clear(ClearMode::Soft, BgpSessionState::Established)
+            .unwrap();
+        // permit of cleared session does not reach next one
+        let next = ctl.session_refresh();
+        assert!(!Arc::ptr_eq(&refresh, &next));
+        ctl.clear(ClearMode::Hard, BgpSessionState::Established)
+            .unwrap();
+        assert!(t.is_cancelled());
+        assert!(!ot.is_cancelled());
+        assert_eq!(ctl.cease_subcode(), Some(CEASE_ADMIN_RESET));
+        assert_eq!(other.cease_subcode(), None);
+        let t = ctl.session_token(&parent);
+        assert_eq!(ctl.cease_subcode(), None);
+        ctl.shutdown();
+        assert!(t.is_cancelled());
+        assert_eq!(ctl.cease_subcode(), Some(CEASE_ADMIN_SHUTDOWN));
+        assert!(ctls.is_down(addr));
+        let fsm = ctls.fsm(BgpSessionState::Idle);
+        assert!(fsm.admin_shutdown);
+        assert!(!fsm.peers[&"192.0.2.2".parse::<IpAddr>().unwrap()].admin_shutdown);
+        ctl.enable();
+        assert!(!ctl.is_down());
+        assert!(!parent.is_cancelled());
+        let rr = route_refresh(2, 1);
+        assert_eq!(&rr[16..], &[0, 23, 5, 0, 2, 0, 1]);
+        assert_eq!(&cease(CEASE_ADMIN_RESET)[16..], &[0, 21, 3, 6, 4]);
+        assert_eq!("hard".parse::<ClearMode>(), Ok(ClearMode::Hard));
+    }
+}
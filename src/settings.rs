@@ -0,0 +1,252 @@
+//! runtime knobs changed with /api/admin/settings, overrides are kept in settings_file
+//! so they survive restarts
+use crate::config::{parse_duration, HistoryChangeMode, SvcConfig};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// settings overridden at runtime, None keeps configured value
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub historydepth: Option<usize>,
+    /// differ or every
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub historymode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purge_after_withdraws: Option<u64>,
+    /// seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purge_every: Option<u64>,
+    /// off, error, warn, info, debug or trace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    /// 0 - unlimited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_budget: Option<u64>,
+    /// query_limits syntax, class=concurrency/per_minute items
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_limits: Option<String>,
+}
+impl SettingsOverrides {
+    /// applies PATCH body object, null value drops override; nothing is changed on error
+    pub fn patch(&mut self, patch: &serde_json::Value) -> Result<(), String> {
+        let items = patch
+            .as_object()
+            .ok_or_else(|| "settings patch should be JSON object".to_string())?;
+        let mut ret = self.clone();
+        for (k, v) in items.iter() {
+            let err = |e: String| format!("Invalid {} - {}", k, e);
+            let num = || {
+                v.as_u64()
+                    .ok_or_else(|| err("expected non-negative number".to_string()))
+            };
+            let text = || {
+                v.as_str()
+                    .map(|s| s.trim().to_lowercase())
+                    .ok_or_else(|| err("expected string".to_string()))
+            };
+            let set = !v.is_null();
+            match k.as_str() {
+                "historydepth" => ret.historydepth = if set { Some(num()? as usize) } else { None },
+                "historymode" => {
+                    ret.historymode = if set {
+                        let s = text()?;
+                        HistoryChangeMode::from_str(&s).map_err(|e| err(e.to_string()))?;
+                        Some(s)
+                    } else {
+                        None
+                    }
+                }
+                "purge_after_withdraws" => {
+                    ret.purge_after_withdraws = if set { Some(num()?) } else { None }
+                }
+                "purge_every" => {
+                    ret.purge_every = match v {
+                        serde_json::Value::Null => None,
+                        serde_json::Value::String(s) => {
+                            Some(parse_duration(s).map_err(|e| err(e.to_string()))?.as_secs())
+                        }
+                        _ => Some(num()?),
+                    };
+                    if ret.purge_every == Some(0) {
+                        return Err(err("should be positive".to_string()));
+                    }
+                }
+                "log_level" => {
+                    ret.log_level = if set {
+                        let s = text()?;
+                        log::LevelFilter::from_str(&s).map_err(|e| err(e.to_string()))?;
+                        Some(s)
+                    } else {
+                        None
+                    }
+                }
+                "query_budget" => ret.query_budget = if set { Some(num()?) } else { None },
+                "query_limits" => {
+                    ret.query_limits = if set {
+                        let limits = crate::ratelimit::parse_limits(&text()?).map_err(err)?;
+                        Some(crate::ratelimit::format_limits(&limits))
+                    } else {
+                        None
+                    }
+                }
+                _ => return Err(format!("Unknown setting {}", k)),
+            }
+        }
+        *self = ret;
+        Ok(())
+    }
+}
+
+/// settings in effect
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Settings {
+    pub historydepth: usize,
+    pub historymode: &'static str,
+    pub purge_after_withdraws: u64,
+    pub purge_every: u64,
+    pub log_level: String,
+    pub query_budget: u64,
+    pub query_limits: String,
+}
+impl Settings {
+    pub fn history_mode(&self) -> HistoryChangeMode {
+        match self.historymode {
+            "every" => HistoryChangeMode::EveryUpdate,
+            _ => HistoryChangeMode::OnlyDiffer,
+        }
+    }
+    pub fn log_filter(&self) -> log::LevelFilter {
+        log::LevelFilter::from_str(&self.log_level).unwrap_or(log::LevelFilter::Info)
+    }
+    pub fn limits(
+        &self,
+    ) -> std::collections::BTreeMap<crate::ratelimit::QueryClass, crate::ratelimit::ClassLimit>
+    {
+        crate::ratelimit::parse_limits(&self.query_limits).unwrap_or_default()
+    }
+}
+
+pub struct RuntimeSettings {
+    file: Option<String>,
+    /// log level set from environment at startup
+    initial_log_level: log::LevelFilter,
+    overrides: Mutex<SettingsOverrides>,
+}
+impl RuntimeSettings {
+    /// restores overrides from settings_file, unreadable file is ignored with warning
+    pub fn new(cfg: &SvcConfig) -> RuntimeSettings {
+        let overrides = match cfg.settings_file.as_ref() {
+            Some(f) => match std::fs::read_to_string(f) {
+                Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                    warn!("Settings file {}: {}", f, e);
+                    SettingsOverrides::default()
+                }),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+                Err(e) => {
+                    warn!("Settings file {}: {}", f, e);
+                    Default::default()
+                }
+            },
+            None => Default::default(),
+        };
+        RuntimeSettings {
+            file: cfg.settings_file.clone(),
+            initial_log_level: log::max_level(),
+            overrides: Mutex::new(overrides),
+        }
+    }
+    pub fn overrides(&self) -> SettingsOverrides {
+        self.overrides.lock().unwrap().clone()
+    }
+    pub fn effective(&self, cfg: &SvcConfig) -> Settings {
+        let o = self.overrides();
+        Settings {
+            historydepth: o.historydepth.unwrap_or(cfg.historydepth),
+            historymode: match o.historymode.as_deref().map(HistoryChangeMode::from_str) {
+                Some(Ok(HistoryChangeMode::EveryUpdate)) => "every",
+                Some(Ok(HistoryChangeMode::OnlyDiffer)) => "differ",
+                _ => match cfg.historymode {
+                    HistoryChangeMode::EveryUpdate => "every",
+                    HistoryChangeMode::OnlyDiffer => "differ",
+                },
+            },
+            purge_after_withdraws: o.purge_after_withdraws.unwrap_or(cfg.purge_after_withdraws),
+            purge_every: o
+                .purge_every
+                .unwrap_or(cfg.purge_every.num_seconds().max(0) as u64),
+            log_level: o
+                .log_level
+                .unwrap_or_else(|| self.initial_log_level.to_string().to_lowercase()),
+            query_budget: o.query_budget.unwrap_or(cfg.query_budget),
+            query_limits: o
+                .query_limits
+                .unwrap_or_else(|| crate::ratelimit::format_limits(&cfg.query_limits)),
+        }
+    }
+    /// changes overrides and writes them to settings_file
+    pub fn patch(&self, patch: &serde_json::Value) -> Result<(), String> {
+        let mut o = self.overrides.lock().unwrap();
+        let mut changed = o.clone();
+        changed.patch(patch)?;
+        if let Some(f) = self.file.as_ref() {
+            let tmp = format!("{}.tmp", f);
+            serde_json::to_vec_pretty(&changed)
+                .map_err(|e| e.to_string())
+                .and_then(|b| std::fs::write(&tmp, b).map_err(|e| e.to_string()))
+                .and_then(|_| std::fs::rename(&tmp, f).map_err(|e| e.to_string()))
+                .map_err(|e| format!("Settings file {}: {}", f, e))?;
+        }
+        *o = changed;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch() {
+        let mut o = SettingsOverrides::default();
+        o.patch(
+            &serde_json::json!({"historydepth": 20, "purge_every": "2m", "log_level": "Debug"}),
+        )
+        .unwrap();
+        assert_eq!(o.historydepth, Some(20));
+        assert_eq!(o.purge_every, Some(120));
+        assert_eq!(o.log_level.as_deref(), Some("debug"));
+        o.patch(&serde_json::json!({"historydepth": null, "query_budget": 0}))
+            .unwrap();
+        assert_eq!(o.historydepth, None);
+        assert_eq!(o.query_budget, Some(0));
+        let before = o.clone();
+        assert!(o
+            .patch(&serde_json::json!({"historymode": "sometimes", "historydepth": 5}))
+            .is_err());
+        assert!(o.patch(&serde_json::json!({"color": "red"})).is_err());
+        assert!(o.patch(&serde_json::json!({"purge_every": 0})).is_err());
+        assert!(o
+            .patch(&serde_json::json!({"query_limits": "heavy=1/1"}))
+            .is_err());
+        let mut l = SettingsOverrides::default();
+        l.patch(&serde_json::json!({"query_limits": "analytics=4/60, lookup=64"}))
+            .unwrap();
+        assert_eq!(
+            l.query_limits.as_deref(),
+            Some("lookup=64/0,analytics=4/60")
+        );
+        assert_eq!(o, before);
+        let stored = serde_json::to_string(&o).unwrap();
+        assert_eq!(
+            stored,
+            r#"{"purge_every":120,"log_level":"debug","query_budget":0}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<SettingsOverrides>(&stored).unwrap(),
+            o
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! shadow table of routes dropped by inbound lists and update script: instead of discarding
+//! them silently compact entry per prefix and origin is kept with drop count and last seen
+//! time, served by /api/filtered so operators can check their filters
+use crate::bgpsvc::BgpSessionId;
+use crate::script::RoutePrefix;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowEntry {
+    #[serde(skip)]
+    pub route: RoutePrefix,
+    pub prefix: String,
+    pub origin: Option<u32>,
+    /// acceptprefixes, rejectprefixes, acceptorigins or update_script
+    pub reason: &'static str,
+    /// session of last drop
+    pub session: BgpSessionId,
+    pub count: u64,
+    pub first_seen: Timestamp,
+    pub last_seen: Timestamp,
+}
+
+type ShadowKey = (String, Option<u32>);
+
+/// entries returned by one /api/filtered request
+pub const MAX_LIST: usize = 10000;
+
+/// entries bounded by size, least recently seen ones are evicted
+#[derive(Debug, Default)]
+pub struct ShadowTable {
+    size: usize,
+    entries: HashMap<ShadowKey, ShadowEntry>,
+    by_seen: BTreeSet<(Timestamp, ShadowKey)>,
+    evicted: u64,
+}
+impl ShadowTable {
+    /// size 0 - disabled
+    pub fn new(size: usize) -> ShadowTable {
+        ShadowTable {
+            size,
+            ..Default::default()
+        }
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.size > 0
+    }
+    pub fn record(
+        &mut self,
+        route: &RoutePrefix,
+        origin: Option<u32>,
+        reason: &'static str,
+        session: BgpSessionId,
+        now: Timestamp,
+    ) {
+        if self.size == 0 {
+            return;
+        }
+        let key = (route.to_string(), origin);
+        if let Some(e) = self.entries.get_mut(&key) {
+            self.by_seen.remove(&(e.last_seen, key.clone()));
+            e.count += 1;
+            e.last_seen = now;
+            e.reason = reason;
+            e.session = session;
+            self.by_seen.insert((now, key));
+            return;
+        }
+        while self.entries.len() >= self.size {
+            match self.by_seen.iter().next().cloned() {
+                Some(oldest) => {
+                    self.by_seen.remove(&oldest);
+                    self.entries.remove(&oldest.1);
+                    self.evicted += 1;
+                }
+                None => break,
+            }
+        }
+        self.by_seen.insert((now, key.clone()));
+        self.entries.insert(
+            key.clone(),
+            ShadowEntry {
+                route: route.clone(),
+                prefix: key.0,
+                origin,
+                reason,
+                session,
+                count: 1,
+                first_seen: now,
+                last_seen: now,
+            },
+        );
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.by_seen.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+    /// entries matching predicate, most recently seen first
+    pub fn list<'a>(
+        &'a self,
+        pred: impl Fn(&ShadowEntry) -> bool + 'a,
+    ) -> impl Iterator<Item = &'a ShadowEntry> + 'a {
+        self.by_seen
+            .iter()
+            .rev()
+            .filter_map(move |(_, k)| self.entries.get(k))
+            .filter(move |e| pred(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_shadow_table() {
+        let p = |b| RoutePrefix::V4(BgpAddrV4::new(Ipv4Addr::new(10, b, 0, 0), 16));
+        let t0 = Timestamp::from_unix(1700000000, 0);
+        let t = |s: i64| Timestamp::from_unix(1700000000 + s, 0);
+        let mut tbl = ShadowTable::new(2);
+        tbl.record(&p(1), Some(64500), "rejectprefixes", 1, t0);
+        tbl.record(&p(2), Some(64500), "rejectprefixes", 1, t(1));
+        tbl.record(&p(1), Some(64500), "rejectprefixes", 1, t(2));
+        tbl.record(&p(3), None, "acceptorigins", 1, t(3));
+        assert_eq!(tbl.len(), 2);
+        assert_eq!(tbl.evicted(), 1);
+        let items: Vec<&ShadowEntry> = tbl.list(|_| true).collect();
+        assert_eq!(items[0].prefix, "10.3.0.0/16");
+        assert_eq!(items[1].count, 2);
+        assert_eq!(items[1].first_seen, t0);
+        assert_eq!(tbl.list(|e| e.origin == Some(64500)).count(), 1);
+        assert!(!ShadowTable::new(0).is_enabled());
+    }
+}
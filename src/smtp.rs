@@ -0,0 +1,356 @@
+//! minimal SMTP client for sending reports and watchlist alerts
+use crate::bgprib::BgpEvent;
+use crate::config::ErrorConfig;
+use crate::ribfilter::RouteFilter;
+use crate::tls::BoxStream;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// SMTP relay connection security
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    None,
+    StartTls,
+    /// implicit TLS, usually port 465
+    Tls,
+}
+impl FromStr for SmtpTls {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "none" => Ok(SmtpTls::None),
+            "starttls" => Ok(SmtpTls::StartTls),
+            "tls" => Ok(SmtpTls::Tls),
+            _ => Err(ErrorConfig::from_str("should be none, starttls or tls")),
+        }
+    }
+}
+
+/// SMTP relay settings
+#[derive(Debug, Clone)]
+pub struct SmtpSettings {
+    /// relay host:port
+    pub relay: String,
+    pub tls: SmtpTls,
+    /// AUTH PLAIN credentials
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// email alert settings for watchlist and hijack events
+#[derive(Debug, Clone)]
+pub struct SmtpAlertSettings {
+    /// watchlist route filter
+    pub watch: Option<String>,
+    /// announcements of these prefixes or more specifics from origins other than
+    /// hijack_origins are reported as possible hijacks
+    pub hijack_prefixes: crate::inbound::PrefixList,
+    pub hijack_origins: std::collections::BTreeSet<u32>,
+    pub subject: String,
+    pub body: String,
+    /// events are collected for this long after first one before mail is sent
+    pub batch: Duration,
+    /// maximum events listed in one mail, rest are only counted
+    pub max_events: usize,
+}
+
+/// reads possibly multiline reply, returns code and last line
+async fn read_reply<R: AsyncBufReadExt + Unpin>(rd: &mut R) -> io::Result<(u16, String)> {
+    loop {
+        let mut line = String::new();
+        if rd.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "SMTP connection closed",
+            ));
+        }
+        // code is checked as bytes, line may be short or start with multibyte char
+        let b = line.as_bytes();
+        let code = match b.get(0..4) {
+            Some(c) if c[0..3].iter().all(u8::is_ascii_digit) => c[0..3]
+                .iter()
+                .fold(0u16, |n, d| n * 10 + (*d - b'0') as u16),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid SMTP reply: {}", line.trim_end()),
+                ))
+            }
+        };
+        if b[3] != b'-' {
+            return Ok((code, line.trim_end().to_string()));
+        }
+    }
+}
+
+async fn command(stream: &mut BufReader<BoxStream>, cmd: &str, expect: u16) -> io::Result<()> {
+    command_any(stream, cmd, &[expect]).await
+}
+
+/// command accepting any of expected reply codes
+async fn command_any(
+    stream: &mut BufReader<BoxStream>,
+    cmd: &str,
+    expect: &[u16],
+) -> io::Result<()> {
+    stream.get_mut().write_all(cmd.as_bytes()).await?;
+    stream.get_mut().write_all(b"\r\n").await?;
+    let (code, line) = read_reply(stream).await?;
+    if !expect.contains(&code) {
+        return Err(io::Error::other(format!(
+            "SMTP {} failed: {}",
+            cmd.split(' ').next().unwrap_or(cmd),
+            line
+        )));
+    }
+    Ok(())
+}
+
+/// message text with headers, CRLF line endings and dot-stuffing
+pub fn format_message(settings: &SmtpSettings, subject: &str, body: &str, html: bool) -> String {
+    let mut msg = String::new();
+    msg.push_str(&format!("From: {}\r\n", settings.from));
+    msg.push_str(&format!("To: {}\r\n", settings.to.join(", ")));
+    msg.push_str(&format!(
+        "Subject: {}\r\n",
+        subject.replace(['\r', '\n'], " ")
+    ));
+    msg.push_str(&format!("Date: {}\r\n", chrono::Local::now().to_rfc2822()));
+    msg.push_str("MIME-Version: 1.0\r\n");
+    msg.push_str(&format!(
+        "Content-Type: text/{}; charset=utf-8\r\n",
+        if html { "html" } else { "plain" }
+    ));
+    msg.push_str("Content-Transfer-Encoding: 8bit\r\n\r\n");
+    for line in body.lines() {
+        if line.starts_with('.') {
+            msg.push('.');
+        }
+        msg.push_str(line);
+        msg.push_str("\r\n");
+    }
+    msg
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// sends message via relay
+pub async fn send_mail(
+    settings: &SmtpSettings,
+    subject: &str,
+    body: &str,
+    html: bool,
+) -> io::Result<()> {
+    let host = crate::tls::host_of(settings.relay.as_str());
+    let mut conn: BoxStream = Box::new(TcpStream::connect(settings.relay.as_str()).await?);
+    if settings.tls == SmtpTls::Tls {
+        conn = crate::tls::connect(host, conn).await?;
+    }
+    let mut stream = BufReader::new(conn);
+    let (code, line) = read_reply(&mut stream).await?;
+    if code != 220 {
+        return Err(io::Error::other(format!("SMTP greeting: {}", line)));
+    }
+    let helo = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    command(&mut stream, &format!("EHLO {}", helo), 250).await?;
+    if settings.tls == SmtpTls::StartTls {
+        command(&mut stream, "STARTTLS", 220).await?;
+        stream = BufReader::new(crate::tls::connect(host, stream.into_inner()).await?);
+        command(&mut stream, &format!("EHLO {}", helo), 250).await?;
+    }
+    if let (Some(user), Some(password)) = (settings.user.as_ref(), settings.password.as_ref()) {
+        // credentials are never sent over plaintext connection
+        if settings.tls == SmtpTls::None {
+            return Err(io::Error::other("SMTP AUTH requires starttls or tls"));
+        }
+        let token = base64(format!("\0{}\0{}", user, password).as_bytes());
+        command(&mut stream, &format!("AUTH PLAIN {}", token), 235).await?;
+    }
+    command(&mut stream, &format!("MAIL FROM:<{}>", settings.from), 250).await?;
+    for rcpt in settings.to.iter() {
+        // 251: user not local, relay forwards
+        command_any(&mut stream, &format!("RCPT TO:<{}>", rcpt), &[250, 251]).await?;
+    }
+    command(&mut stream, "DATA", 354).await?;
+    let msg = format_message(settings, subject, body, html);
+    stream.get_mut().write_all(msg.as_bytes()).await?;
+    command(&mut stream, ".", 250).await?;
+    let _ = command(&mut stream, "QUIT", 221).await;
+    Ok(())
+}
+
+/// substitutes {name} placeholders, unknown ones are left as is
+pub fn render_template(tpl: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = tpl.replace("\\n", "\n");
+    for (k, v) in vars.iter() {
+        out = out.replace(&format!("{{{}}}", k), v);
+    }
+    out
+}
+
+/// sends one mail per batch of alert events, total counts events beyond listed ones
+async fn send_alerts(
+    settings: &SmtpSettings,
+    alerts: &SmtpAlertSettings,
+    events: &[String],
+    total: usize,
+) {
+    let hostname = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    let count = total.to_string();
+    let mut listed = events.to_vec();
+    if total > events.len() {
+        listed.push(format!("... and {} more", total - events.len()));
+    }
+    let listed = listed.join("\n");
+    let vars = [
+        ("count", count.as_str()),
+        ("hostname", hostname.as_str()),
+        ("events", listed.as_str()),
+    ];
+    let subject = render_template(alerts.subject.as_str(), &vars);
+    let body = render_template(alerts.body.as_str(), &vars);
+    if let Err(e) = send_mail(settings, subject.as_str(), body.as_str(), false).await {
+        warn!("Unable to email {} alerts: {}", count, e);
+    }
+}
+
+/// emails batched watchlist and hijack alerts; batch keeps at most max_events texts
+pub async fn run_alerts(
+    settings: SmtpSettings,
+    alerts: SmtpAlertSettings,
+    mut rcv: tokio::sync::broadcast::Receiver<BgpEvent>,
+) {
+    let watch = alerts.watch.as_deref().map(RouteFilter::fromstr);
+    let mut events: Vec<String> = Vec::new();
+    let mut total: usize = 0;
+    let mut deadline: Option<tokio::time::Instant> = None;
+    loop {
+        let evt = match deadline {
+            None => rcv.recv().await,
+            Some(d) => {
+                tokio::select! {
+                    r = rcv.recv() => r,
+                    _ = tokio::time::sleep_until(d) => {
+                        send_alerts(&settings, &alerts, &events, total).await;
+                        events.clear();
+                        total = 0;
+                        deadline = None;
+                        continue;
+                    }
+                }
+            }
+        };
+        let mut found: Vec<String> = Vec::new();
+        match evt {
+            Ok(e) => {
+                if let Some(w) = watch.as_ref() {
+                    found.extend(
+                        crate::watch::match_event(w, &e)
+                            .into_iter()
+                            .map(|a| a.to_string()),
+                    );
+                }
+                if !alerts.hijack_prefixes.is_empty() {
+                    found.extend(
+                        crate::watch::match_hijack(
+                            &alerts.hijack_prefixes,
+                            &alerts.hijack_origins,
+                            &e,
+                        )
+                        .into_iter()
+                        .map(|a| a.to_string()),
+                    );
+                }
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                warn!("SMTP alerts skipped {} events", n);
+                found.push(format!("{} BGP events were skipped", n));
+            }
+            Err(_) => return,
+        }
+        for alert in found {
+            total += 1;
+            if events.len() < alerts.max_events {
+                events.push(format!(
+                    "{} {}",
+                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    alert
+                ));
+            }
+        }
+        if total > 0 && deadline.is_none() {
+            deadline = Some(tokio::time::Instant::now() + alerts.batch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smtp_format() {
+        let s = SmtpSettings {
+            relay: "127.0.0.1:25".to_string(),
+            tls: SmtpTls::None,
+            user: None,
+            password: None,
+            from: "bgp@example.com".to_string(),
+            to: vec!["noc@example.com".to_string()],
+        };
+        let m = format_message(&s, "report", "line\n.dot", false);
+        assert!(m.contains("To: noc@example.com\r\n"));
+        assert!(m.ends_with("\r\n\r\nline\r\n..dot\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_smtp_read_reply() {
+        let mut rd = BufReader::new(&b"250-first\r\n250 last\r\n"[..]);
+        assert_eq!(
+            read_reply(&mut rd).await.unwrap(),
+            (250, "250 last".to_string())
+        );
+        for bad in ["25\n", "\u{e9}50 x\r\n", "2x0 x\r\n"].iter() {
+            let mut rd = BufReader::new(bad.as_bytes());
+            assert!(read_reply(&mut rd).await.is_err());
+        }
+    }
+
+    #[test]
+    fn test_smtp_alert_helpers() {
+        assert_eq!(base64(b"\0user\0pass"), "AHVzZXIAcGFzcw==");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(
+            render_template(
+                "{count} alerts\\n{events} {other}",
+                &[("count", "2"), ("events", "x")]
+            ),
+            "2 alerts\nx {other}"
+        );
+    }
+}
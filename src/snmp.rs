@@ -0,0 +1,576 @@
+//! SNMP AgentX subagent (RFC 2741) exporting session and RIB metrics
+use crate::bgprib::BgpRIB;
+use crate::bgpsvc::*;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// BGP4-MIB root, 1.3.6.1.2.1.15
+pub const BGP4_MIB: &[u32] = &[1, 3, 6, 1, 2, 1, 15];
+
+const AGENTX_VERSION: u8 = 1;
+const FLAG_NON_DEFAULT_CONTEXT: u8 = 0x08;
+const FLAG_NETWORK_BYTE_ORDER: u8 = 0x10;
+
+const PDU_OPEN: u8 = 1;
+const PDU_CLOSE: u8 = 2;
+const PDU_REGISTER: u8 = 3;
+const PDU_GET: u8 = 5;
+const PDU_GETNEXT: u8 = 6;
+const PDU_GETBULK: u8 = 7;
+const PDU_TESTSET: u8 = 8;
+const PDU_COMMITSET: u8 = 9;
+const PDU_UNDOSET: u8 = 10;
+const PDU_CLEANUPSET: u8 = 11;
+const PDU_RESPONSE: u8 = 18;
+
+const ERR_NOT_WRITABLE: u16 = 17;
+
+/// exported variable value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnmpValue {
+    Integer(i32),
+    OctetString(Vec<u8>),
+    IpAddress(Ipv4Addr),
+    Counter32(u32),
+    Gauge32(u32),
+    Counter64(u64),
+}
+impl SnmpValue {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            SnmpValue::Integer(v) => buf.extend_from_slice(&v.to_be_bytes()),
+            SnmpValue::OctetString(v) => encode_octets(v, buf),
+            SnmpValue::IpAddress(v) => encode_octets(&v.octets(), buf),
+            SnmpValue::Counter32(v) | SnmpValue::Gauge32(v) => {
+                buf.extend_from_slice(&v.to_be_bytes())
+            }
+            SnmpValue::Counter64(v) => buf.extend_from_slice(&v.to_be_bytes()),
+        }
+    }
+    fn type_code(&self) -> u16 {
+        match self {
+            SnmpValue::Integer(_) => 2,
+            SnmpValue::OctetString(_) => 4,
+            SnmpValue::IpAddress(_) => 64,
+            SnmpValue::Counter32(_) => 65,
+            SnmpValue::Gauge32(_) => 66,
+            SnmpValue::Counter64(_) => 70,
+        }
+    }
+}
+
+/// varbind exceptions
+const VB_NO_SUCH_OBJECT: u16 = 128;
+const VB_END_OF_MIB_VIEW: u16 = 130;
+
+/// snapshot of exported variables, ordered by OID
+pub type SnmpMib = BTreeMap<Vec<u32>, SnmpValue>;
+
+fn encode_octets(v: &[u8], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(v.len() as u32).to_be_bytes());
+    buf.extend_from_slice(v);
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+fn encode_oid(oid: &[u32], include: bool, buf: &mut Vec<u8>) {
+    buf.push(oid.len() as u8);
+    buf.push(0);
+    buf.push(include as u8);
+    buf.push(0);
+    for s in oid {
+        buf.extend_from_slice(&s.to_be_bytes());
+    }
+}
+
+/// payload reader honoring PDU byte order
+struct PduReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    be: bool,
+}
+impl<'a> PduReader<'a> {
+    fn new(buf: &'a [u8], be: bool) -> PduReader<'a> {
+        PduReader { buf, pos: 0, be }
+    }
+    fn eof(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated AgentX PDU",
+            ));
+        }
+        let r = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(r)
+    }
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.take(2)?;
+        let a = [b[0], b[1]];
+        Ok(if self.be {
+            u16::from_be_bytes(a)
+        } else {
+            u16::from_le_bytes(a)
+        })
+    }
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        let a = [b[0], b[1], b[2], b[3]];
+        Ok(if self.be {
+            u32::from_be_bytes(a)
+        } else {
+            u32::from_le_bytes(a)
+        })
+    }
+    /// returns OID and include flag
+    fn oid(&mut self) -> io::Result<(Vec<u32>, bool)> {
+        let n = self.u8()?;
+        let prefix = self.u8()?;
+        let include = self.u8()? != 0;
+        self.u8()?;
+        let mut ret = Vec::with_capacity(n as usize + 5);
+        if prefix != 0 {
+            ret.extend_from_slice(&[1, 3, 6, 1, prefix as u32]);
+        }
+        for _ in 0..n {
+            ret.push(self.u32()?);
+        }
+        Ok((ret, include))
+    }
+    fn octets(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        let r = self.take(len)?;
+        self.take((4 - len % 4) % 4)?;
+        Ok(r)
+    }
+}
+
+/// AgentX PDU header
+#[derive(Debug, Clone, Copy)]
+struct PduHeader {
+    ptype: u8,
+    flags: u8,
+    session: u32,
+    transaction: u32,
+    packet: u32,
+}
+
+fn encode_pdu(hdr: &PduHeader, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(20 + payload.len());
+    buf.push(AGENTX_VERSION);
+    buf.push(hdr.ptype);
+    buf.push(hdr.flags | FLAG_NETWORK_BYTE_ORDER);
+    buf.push(0);
+    buf.extend_from_slice(&hdr.session.to_be_bytes());
+    buf.extend_from_slice(&hdr.transaction.to_be_bytes());
+    buf.extend_from_slice(&hdr.packet.to_be_bytes());
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+async fn read_pdu<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<(PduHeader, Vec<u8>)> {
+    let mut h = [0u8; 20];
+    stream.read_exact(&mut h).await?;
+    let flags = h[2];
+    let mut rd = PduReader::new(&h[4..], flags & FLAG_NETWORK_BYTE_ORDER != 0);
+    let hdr = PduHeader {
+        ptype: h[1],
+        flags,
+        session: rd.u32()?,
+        transaction: rd.u32()?,
+        packet: rd.u32()?,
+    };
+    let len = rd.u32()? as usize;
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+    Ok((hdr, payload))
+}
+
+/// first variable after oid (or at oid if include) and before end
+pub fn mib_next<'a>(
+    mib: &'a SnmpMib,
+    oid: &[u32],
+    include: bool,
+    end: &[u32],
+) -> Option<(&'a Vec<u32>, &'a SnmpValue)> {
+    mib.range(oid.to_vec()..)
+        .find(|(k, _)| include || k.as_slice() != oid)
+        .filter(|(k, _)| end.is_empty() || k.as_slice() < end)
+}
+
+fn encode_varbind(oid: &[u32], val: Option<&SnmpValue>, exception: u16, buf: &mut Vec<u8>) {
+    let tcode = val.map(|v| v.type_code()).unwrap_or(exception);
+    buf.extend_from_slice(&tcode.to_be_bytes());
+    buf.extend_from_slice(&[0, 0]);
+    encode_oid(oid, false, buf);
+    if let Some(v) = val {
+        v.encode(buf);
+    }
+}
+
+/// builds Response payload for Get/GetNext/GetBulk request
+fn answer_request(
+    ptype: u8,
+    flags: u8,
+    payload: &[u8],
+    mib: &SnmpMib,
+    uptime: u32,
+) -> io::Result<Vec<u8>> {
+    let mut rd = PduReader::new(payload, flags & FLAG_NETWORK_BYTE_ORDER != 0);
+    if flags & FLAG_NON_DEFAULT_CONTEXT != 0 {
+        rd.octets()?;
+    }
+    let (nonrep, maxrep) = if ptype == PDU_GETBULK {
+        (rd.u16()? as usize, rd.u16()? as usize)
+    } else {
+        (0, 0)
+    };
+    let mut ranges = Vec::new();
+    while !rd.eof() {
+        let (start, include) = rd.oid()?;
+        let (end, _) = rd.oid()?;
+        ranges.push((start, include, end));
+    }
+    let mut vbs = Vec::new();
+    for (i, (start, include, end)) in ranges.iter().enumerate() {
+        if ptype == PDU_GET {
+            encode_varbind(start, mib.get(start), VB_NO_SUCH_OBJECT, &mut vbs);
+            continue;
+        }
+        let reps = if ptype == PDU_GETBULK && i >= nonrep {
+            maxrep.max(1)
+        } else {
+            1
+        };
+        let mut cur = start.clone();
+        let mut inc = *include;
+        for _ in 0..reps {
+            match mib_next(mib, &cur, inc, end) {
+                Some((k, v)) => {
+                    encode_varbind(k, Some(v), 0, &mut vbs);
+                    cur = k.clone();
+                    inc = false;
+                }
+                None => {
+                    encode_varbind(&cur, None, VB_END_OF_MIB_VIEW, &mut vbs);
+                    break;
+                }
+            }
+        }
+    }
+    let mut ret = Vec::with_capacity(8 + vbs.len());
+    ret.extend_from_slice(&uptime.to_be_bytes());
+    ret.extend_from_slice(&[0, 0, 0, 0]);
+    ret.extend_from_slice(&vbs);
+    Ok(ret)
+}
+
+fn ipv4_index(oid: &[u32], col: u32, a: &Ipv4Addr) -> Vec<u32> {
+    let mut ret = oid.to_vec();
+    ret.push(col);
+    ret.extend(a.octets().iter().map(|x| *x as u32));
+    ret
+}
+
+fn with_suffix(oid: &[u32], suffix: &[u32]) -> Vec<u32> {
+    let mut ret = oid.to_vec();
+    ret.extend_from_slice(suffix);
+    ret
+}
+
+/// BGP4-MIB bgpPeerState value for service session state
+fn peer_state(state: &BgpSessionState) -> i32 {
+    match state {
+        BgpSessionState::Idle => 1,
+        BgpSessionState::Connect => 2,
+        BgpSessionState::Active => 3,
+        BgpSessionState::OpenSent => 4,
+        BgpSessionState::OpenConfirm => 5,
+        BgpSessionState::Established | BgpSessionState::BMP => 6,
+    }
+}
+
+/// builds exported variables from sessions and RIB
+pub fn build_mib(
+    base: &[u32],
+    localas: u32,
+    state: &BgpSessionState,
+    sessions: &[(BgpSessionId, Arc<BgpSessionDesc>)],
+    rib: &BgpRIB,
+) -> SnmpMib {
+    let mut mib = SnmpMib::new();
+    mib.insert(
+        with_suffix(BGP4_MIB, &[1, 0]),
+        SnmpValue::OctetString(vec![0x10]),
+    );
+    mib.insert(
+        with_suffix(BGP4_MIB, &[2, 0]),
+        SnmpValue::Integer(localas as i32),
+    );
+    let peertable = with_suffix(BGP4_MIB, &[3, 1]);
+    let sesstable = with_suffix(base, &[4, 1]);
+    for (sid, desc) in sessions.iter() {
+        let stats = rib.peer_stats.get(sid);
+        let updates = stats.map(|s| s.updates()).unwrap_or(0);
+        let withdraws = stats.map(|s| s.withdraws()).unwrap_or(0);
+        let prefixes: u64 = stats
+            .map(|s| s.ribs.values().map(|r| r.prefixes).sum())
+            .unwrap_or(0);
+        // session storage keeps remote side as peer1
+        let remote = &desc.peer1;
+        let sidx = &[*sid as u32];
+        mib.insert(
+            with_suffix(&with_suffix(&sesstable, &[1]), sidx),
+            SnmpValue::OctetString(remote.addr.to_string().into_bytes()),
+        );
+        mib.insert(
+            with_suffix(&with_suffix(&sesstable, &[2]), sidx),
+            SnmpValue::Gauge32(remote.bom.as_num),
+        );
+        mib.insert(
+            with_suffix(&with_suffix(&sesstable, &[3]), sidx),
+            SnmpValue::Gauge32(prefixes.min(u32::MAX as u64) as u32),
+        );
+        mib.insert(
+            with_suffix(&with_suffix(&sesstable, &[4]), sidx),
+            SnmpValue::Counter64(updates),
+        );
+        mib.insert(
+            with_suffix(&with_suffix(&sesstable, &[5]), sidx),
+            SnmpValue::Counter64(withdraws),
+        );
+        // BGP4-MIB peer table is indexed by IPv4 address only
+        let a = match remote.addr {
+            IpAddr::V4(a) => a,
+            IpAddr::V6(_) => continue,
+        };
+        mib.insert(
+            ipv4_index(&peertable, 1, &a),
+            SnmpValue::IpAddress(remote.bom.router_id),
+        );
+        mib.insert(
+            ipv4_index(&peertable, 2, &a),
+            SnmpValue::Integer(peer_state(state)),
+        );
+        mib.insert(ipv4_index(&peertable, 7, &a), SnmpValue::IpAddress(a));
+        mib.insert(
+            ipv4_index(&peertable, 9, &a),
+            SnmpValue::Integer(remote.bom.as_num as i32),
+        );
+        mib.insert(
+            ipv4_index(&peertable, 10, &a),
+            SnmpValue::Counter32((updates + withdraws) as u32),
+        );
+        if let Some(lu) = stats.and_then(|s| s.last_update.as_ref()) {
+            let elapsed = (chrono::Local::now() - **lu).num_seconds().max(0);
+            mib.insert(
+                ipv4_index(&peertable, 24, &a),
+                SnmpValue::Gauge32(elapsed as u32),
+            );
+        }
+    }
+    let ribs: [(&str, usize); 16] = [
+        ("ipv4u", rib.ipv4u.items.len()),
+        ("ipv4m", rib.ipv4m.items.len()),
+        ("ipv4lu", rib.ipv4lu.items.len()),
+        ("vpnv4u", rib.vpnv4u.items.len()),
+        ("vpnv4m", rib.vpnv4m.items.len()),
+        ("ipv6u", rib.ipv6u.items.len()),
+        ("ipv6lu", rib.ipv6lu.items.len()),
+        ("vpnv6u", rib.vpnv6u.items.len()),
+        ("vpnv6m", rib.vpnv6m.items.len()),
+        ("l2vpls", rib.l2vpls.items.len()),
+        ("mvpn", rib.mvpn.items.len()),
+        ("evpn", rib.evpn.items.len()),
+        ("fs4u", rib.fs4u.items.len()),
+        ("ipv4mdt", rib.ipv4mdt.items.len()),
+        ("ipv6mdt", rib.ipv6mdt.items.len()),
+        ("ipv6m", rib.ipv6m.items.len()),
+    ];
+    let ribtable = with_suffix(base, &[1, 1]);
+    for (i, (name, len)) in ribs.iter().enumerate() {
+        let idx = &[i as u32 + 1];
+        mib.insert(
+            with_suffix(&with_suffix(&ribtable, &[1]), idx),
+            SnmpValue::OctetString(name.as_bytes().to_vec()),
+        );
+        mib.insert(
+            with_suffix(&with_suffix(&ribtable, &[2]), idx),
+            SnmpValue::Gauge32(*len as u32),
+        );
+    }
+    mib.insert(
+        with_suffix(base, &[2, 0]),
+        SnmpValue::Counter64(rib.cnt_updates),
+    );
+    mib.insert(
+        with_suffix(base, &[3, 0]),
+        SnmpValue::Counter64(rib.cnt_withdraws),
+    );
+    mib
+}
+
+/// parses dotted OID
+pub fn parse_oid(s: &str) -> Option<Vec<u32>> {
+    s.trim()
+        .trim_start_matches('.')
+        .split('.')
+        .map(|x| x.parse::<u32>().ok())
+        .collect()
+}
+
+/// AgentX subagent connected to master agent
+pub struct SnmpAgent {
+    svr: Arc<BgpSvr>,
+    started: Instant,
+}
+impl SnmpAgent {
+    pub fn new(svr: Arc<BgpSvr>) -> SnmpAgent {
+        SnmpAgent {
+            svr,
+            started: Instant::now(),
+        }
+    }
+    fn uptime(&self) -> u32 {
+        (self.started.elapsed().as_millis() / 10) as u32
+    }
+    async fn request<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        hdr: PduHeader,
+        payload: &[u8],
+    ) -> io::Result<PduHeader> {
+        stream.write_all(&encode_pdu(&hdr, payload)).await?;
+        let (rhdr, rpayload) = read_pdu(stream).await?;
+        let mut rd = PduReader::new(&rpayload, rhdr.flags & FLAG_NETWORK_BYTE_ORDER != 0);
+        rd.u32()?;
+        let err = rd.u16()?;
+        if rhdr.ptype != PDU_RESPONSE || err != 0 {
+            return Err(io::Error::other(format!(
+                "AgentX request {} failed with error {}",
+                hdr.ptype, err
+            )));
+        }
+        Ok(rhdr)
+    }
+    /// serves one master agent connection
+    async fn session<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut stream: S,
+        base: &[u32],
+    ) -> io::Result<()> {
+        let mut hdr = PduHeader {
+            ptype: PDU_OPEN,
+            flags: 0,
+            session: 0,
+            transaction: 0,
+            packet: 1,
+        };
+        let mut payload = vec![0u8, 0, 0, 0];
+        encode_oid(&[], false, &mut payload);
+        encode_octets(b"bgpexplorer", &mut payload);
+        hdr.session = self.request(&mut stream, hdr, &payload).await?.session;
+        for subtree in [BGP4_MIB, base] {
+            hdr.ptype = PDU_REGISTER;
+            hdr.packet += 1;
+            let mut payload = vec![0u8, 127, 0, 0];
+            encode_oid(subtree, false, &mut payload);
+            self.request(&mut stream, hdr, &payload).await?;
+        }
+        info!("AgentX session {} registered", hdr.session);
+        loop {
+            let (req, payload) = read_pdu(&mut stream).await?;
+            let body = match req.ptype {
+                PDU_GET | PDU_GETNEXT | PDU_GETBULK => {
+                    let mib = self.svr.snmp_mib(base).await;
+                    answer_request(req.ptype, req.flags, &payload, &mib, self.uptime())?
+                }
+                PDU_TESTSET | PDU_COMMITSET | PDU_UNDOSET | PDU_CLEANUPSET => {
+                    let mut r = self.uptime().to_be_bytes().to_vec();
+                    r.extend_from_slice(&ERR_NOT_WRITABLE.to_be_bytes());
+                    r.extend_from_slice(&[0, 0]);
+                    r
+                }
+                PDU_CLOSE => return Ok(()),
+                PDU_RESPONSE => continue,
+                t => {
+                    debug!("Unsupported AgentX PDU {}", t);
+                    continue;
+                }
+            };
+            let rsp = PduHeader {
+                ptype: PDU_RESPONSE,
+                flags: 0,
+                ..req
+            };
+            stream.write_all(&encode_pdu(&rsp, &body)).await?;
+        }
+    }
+    async fn connect_and_serve(&self, master: &str, base: &[u32]) -> io::Result<()> {
+        if let Some(tcp) = master.strip_prefix("tcp:") {
+            let stream = tokio::net::TcpStream::connect(tcp).await?;
+            return self.session(stream, base).await;
+        }
+        #[cfg(unix)]
+        {
+            let stream = tokio::net::UnixStream::connect(master).await?;
+            self.session(stream, base).await
+        }
+        #[cfg(not(unix))]
+        {
+            Err(io::Error::other(
+                "only tcp: AgentX transport is supported on this platform",
+            ))
+        }
+    }
+    /// keeps connection to master agent, reconnects on failures
+    pub async fn run(self, master: String, base: Vec<u32>) {
+        loop {
+            if let Err(e) = self.connect_and_serve(master.as_str(), &base).await {
+                warn!("AgentX {}: {}", master, e);
+            }
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snmp_getnext() {
+        let mut mib = SnmpMib::new();
+        mib.insert(vec![1, 3, 6, 1, 2, 1, 15, 1, 0], SnmpValue::Integer(1));
+        mib.insert(vec![1, 3, 6, 1, 2, 1, 15, 2, 0], SnmpValue::Integer(2));
+        let (k, _) = mib_next(&mib, &[1, 3, 6, 1, 2, 1, 15], false, &[]).unwrap();
+        assert_eq!(k, &vec![1, 3, 6, 1, 2, 1, 15, 1, 0]);
+        let (k, _) = mib_next(&mib, &[1, 3, 6, 1, 2, 1, 15, 1, 0], false, &[]).unwrap();
+        assert_eq!(k, &vec![1, 3, 6, 1, 2, 1, 15, 2, 0]);
+        assert!(mib_next(&mib, &[1, 3, 6, 1, 2, 1, 15, 2, 0], false, &[]).is_none());
+        assert!(mib_next(&mib, &[1, 3, 6, 1, 2, 1, 15, 2, 0], true, &[]).is_some());
+        assert!(mib_next(&mib, &[1, 3], false, &[1, 3, 6, 1, 2, 1, 15, 1, 0]).is_none());
+        assert_eq!(parse_oid(".1.3.6.1"), Some(vec![1, 3, 6, 1]));
+    }
+
+    #[test]
+    fn test_snmp_oid_roundtrip() {
+        let mut buf = Vec::new();
+        encode_oid(&[1, 3, 6, 1, 2, 1, 15], true, &mut buf);
+        let mut rd = PduReader::new(&buf, true);
+        assert_eq!(rd.oid().unwrap(), (vec![1, 3, 6, 1, 2, 1, 15], true));
+        assert!(rd.eof());
+    }
+}
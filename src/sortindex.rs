@@ -0,0 +1,222 @@
+//! numeric attribute indexes behind sort=med|localpref|pathlen and med_gt alike URL
+//! parameters, kept sorted by attribute value so listings don't sort full scans
+use crate::bgpattrs::{BgpAttrEntry, BgpAttrs};
+use crate::bgprib::{BgpRIBIndex, BgpRIBKey, BgpSessionEntry};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::ops::Bound;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Med,
+    LocalPref,
+    PathLen,
+}
+impl std::str::FromStr for SortKey {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "med" => Ok(SortKey::Med),
+            "localpref" => Ok(SortKey::LocalPref),
+            "pathlen" => Ok(SortKey::PathLen),
+            x => Err(format!("unknown sort key {}", x)),
+        }
+    }
+}
+impl SortKey {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortKey::Med => "med",
+            SortKey::LocalPref => "localpref",
+            SortKey::PathLen => "pathlen",
+        }
+    }
+    /// None when route does not carry attribute
+    pub fn value(&self, attrs: &BgpAttrs) -> Option<u32> {
+        match self {
+            SortKey::Med => attrs.med,
+            SortKey::LocalPref => attrs.localpref,
+            SortKey::PathLen => Some(attrs.aspath.value.len() as u32),
+        }
+    }
+    /// key of entry in sorted listing: lowest (highest when descending) value of active
+    /// last records of its pathes
+    pub fn entry_value(&self, entry: &BgpSessionEntry, desc: bool) -> Option<u32> {
+        let vals = active_records(entry).filter_map(|rec| self.value(&rec.attrs));
+        if desc {
+            vals.max()
+        } else {
+            vals.min()
+        }
+    }
+    /// entry has active last record with value matching ord against n
+    pub fn entry_matches(&self, entry: &BgpSessionEntry, ord: Ordering, n: u32) -> bool {
+        active_records(entry)
+            .filter_map(|rec| self.value(&rec.attrs))
+            .any(|v| v.cmp(&n) == ord)
+    }
+}
+
+fn active_records(entry: &BgpSessionEntry) -> impl Iterator<Item = &BgpAttrEntry> {
+    entry
+        .items
+        .values()
+        .flat_map(|pe| pe.items.values())
+        .filter_map(|hist| hist.items.values().last())
+        .filter(|rec| rec.active)
+}
+
+/// indexed values of route, from active last records of its pathes; None for records
+/// without med or localpref
+pub fn entry_values(entry: Option<&BgpSessionEntry>) -> Vec<(SortKey, Option<u32>)> {
+    let mut ret = Vec::new();
+    for rec in entry.into_iter().flat_map(active_records) {
+        for key in [SortKey::Med, SortKey::LocalPref, SortKey::PathLen] {
+            ret.push((key, key.value(&rec.attrs)));
+        }
+    }
+    ret.sort_by_key(|(k, v)| (k.name(), *v));
+    ret.dedup();
+    ret
+}
+
+/// value bounds of Ordering against n, as for FilterItem::Bound
+pub fn bounds(ord: Ordering, n: u32) -> (Bound<u32>, Bound<u32>) {
+    match ord {
+        Ordering::Greater => (Bound::Excluded(n), Bound::Unbounded),
+        Ordering::Less => (Bound::Unbounded, Bound::Excluded(n)),
+        Ordering::Equal => (Bound::Included(n), Bound::Included(n)),
+    }
+}
+
+/// values of active last records of routes, updated on every change of route; routes
+/// with active record lacking med or localpref are kept in missing sets, listed last
+pub struct SortIndex<T: BgpRIBKey> {
+    pub med: BgpRIBIndex<u32, T>,
+    pub localpref: BgpRIBIndex<u32, T>,
+    pub pathlen: BgpRIBIndex<u32, T>,
+    pub med_missing: BTreeSet<T>,
+    pub localpref_missing: BTreeSet<T>,
+}
+impl<T: BgpRIBKey> Default for SortIndex<T> {
+    fn default() -> Self {
+        SortIndex {
+            med: BgpRIBIndex::new(),
+            localpref: BgpRIBIndex::new(),
+            pathlen: BgpRIBIndex::new(),
+            med_missing: BTreeSet::new(),
+            localpref_missing: BTreeSet::new(),
+        }
+    }
+}
+impl<T: BgpRIBKey> SortIndex<T> {
+    pub fn get(&self, key: SortKey) -> &BgpRIBIndex<u32, T> {
+        match key {
+            SortKey::Med => &self.med,
+            SortKey::LocalPref => &self.localpref,
+            SortKey::PathLen => &self.pathlen,
+        }
+    }
+    /// routes without attribute, always empty for pathlen
+    pub fn missing(&self, key: SortKey) -> Option<&BTreeSet<T>> {
+        match key {
+            SortKey::Med => Some(&self.med_missing),
+            SortKey::LocalPref => Some(&self.localpref_missing),
+            SortKey::PathLen => None,
+        }
+    }
+    fn parts(&mut self, key: SortKey) -> (&mut BgpRIBIndex<u32, T>, Option<&mut BTreeSet<T>>) {
+        match key {
+            SortKey::Med => (&mut self.med, Some(&mut self.med_missing)),
+            SortKey::LocalPref => (&mut self.localpref, Some(&mut self.localpref_missing)),
+            SortKey::PathLen => (&mut self.pathlen, None),
+        }
+    }
+    /// moves route from old entry_values to new ones
+    pub fn update(
+        &mut self,
+        t: &T,
+        old: &[(SortKey, Option<u32>)],
+        new: &[(SortKey, Option<u32>)],
+    ) {
+        for (key, v) in old.iter().filter(|x| !new.contains(x)) {
+            match (v, self.parts(*key)) {
+                (Some(v), (idx, _)) => idx.remove(v, t),
+                (None, (_, Some(missing))) => {
+                    missing.remove(t);
+                }
+                (None, (_, None)) => {}
+            }
+        }
+        for (key, v) in new.iter().filter(|x| !old.contains(x)) {
+            match (v, self.parts(*key)) {
+                (Some(v), (idx, _)) => idx.set(v, t),
+                (None, (_, Some(missing))) => {
+                    missing.insert(t.clone());
+                }
+                (None, (_, None)) => {}
+            }
+        }
+    }
+    pub fn clear(&mut self) {
+        self.med.clear();
+        self.localpref.clear();
+        self.pathlen.clear();
+        self.med_missing.clear();
+        self.localpref_missing.clear();
+    }
+    /// keys indexed for any value matching ord against n
+    pub fn count(&self, key: SortKey, ord: Ordering, n: u32) -> usize {
+        self.get(key)
+            .idx
+            .range(bounds(ord, n))
+            .map(|(_, s)| s.len())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgprib::BgpRIBSafi;
+    use crate::config::HistoryChangeMode;
+    use crate::compact::CompactASPath;
+    use std::sync::Arc;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_sort_index() {
+        let mut safi = BgpRIBSafi::<BgpAddrV4>::new(10, HistoryChangeMode::EveryUpdate);
+        let mut a = BgpAttrs::new();
+        a.med = Some(50);
+        a.aspath = Arc::new(CompactASPath::from(vec![64500, 64501]));
+        let n1 = BgpAddrV4::new(std::net::Ipv4Addr::new(10, 0, 0, 0), 8);
+        let n2 = BgpAddrV4::new(std::net::Ipv4Addr::new(10, 1, 0, 0), 16);
+        let n3 = BgpAddrV4::new(std::net::Ipv4Addr::new(10, 2, 0, 0), 16);
+        safi.handle_updates_afi(0, &[n1.clone()], Arc::new(a.clone()));
+        a.med = Some(200);
+        safi.handle_updates_afi(0, &[n2.clone()], Arc::new(a.clone()));
+        a.med = None;
+        safi.handle_updates_afi(0, &[n3.clone()], Arc::new(a.clone()));
+        let idx = &safi.idx_sort;
+        assert!(idx.localpref.idx.is_empty());
+        assert_eq!(idx.localpref_missing.len(), 3);
+        assert_eq!(idx.med_missing, vec![n3.clone()].into_iter().collect());
+        assert_eq!(idx.pathlen.idx.get(&2).map(|s| s.len()), Some(3));
+        assert_eq!(idx.count(SortKey::Med, Ordering::Less, 200), 1);
+        assert_eq!(idx.count(SortKey::Med, Ordering::Equal, 200), 1);
+        let e2 = safi.items.get(&n2).unwrap();
+        assert!(SortKey::Med.entry_matches(e2, Ordering::Greater, 100));
+        assert!(!SortKey::Med.entry_matches(e2, Ordering::Less, 100));
+        // changed value replaces old one, withdrawn route leaves index
+        a.med = Some(70);
+        safi.handle_updates_afi(0, &[n1.clone()], Arc::new(a));
+        safi.handle_withdraws_afi(0, &[n2.clone(), n3.clone()], false);
+        let idx = &safi.idx_sort;
+        assert_eq!(idx.med.idx.keys().cloned().collect::<Vec<_>>(), vec![70]);
+        assert!(idx.med_missing.is_empty());
+        assert_eq!(idx.pathlen.idx.get(&2).map(|s| s.len()), Some(1));
+        assert_eq!("localpref".parse::<SortKey>(), Ok(SortKey::LocalPref));
+        assert!("origin".parse::<SortKey>().is_err());
+    }
+}
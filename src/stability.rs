@@ -0,0 +1,106 @@
+//! route age and churn counters of one path, kept apart from history which is trimmed
+//! to historydepth, so flapping routes can be told without walking history
+use crate::bgpattrs::BgpAttrEntry;
+use crate::timestamp::Timestamp;
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RouteStability {
+    pub first_seen: Option<Timestamp>,
+    pub last_changed: Option<Timestamp>,
+    /// attribute or reachability changes since first seen
+    pub changes: u64,
+    /// changes during local calendar day
+    changes_day: u64,
+    day: (i32, u32),
+    /// hash of reachability and attributes of last record, recomputed after snapshot load
+    #[serde(skip)]
+    last: u64,
+}
+
+fn day_of(ts: &Timestamp) -> (i32, u32) {
+    (ts.year(), ts.ordinal())
+}
+
+fn state_hash(entry: &BgpAttrEntry) -> u64 {
+    let mut h = DefaultHasher::new();
+    entry.active.hash(&mut h);
+    entry.attrs.hash(&mut h);
+    h.finish()
+}
+
+impl RouteStability {
+    /// counters derived from history, for records restored from snapshot
+    pub fn from_history<'a, I: Iterator<Item = (&'a Timestamp, &'a BgpAttrEntry)>>(
+        items: I,
+    ) -> RouteStability {
+        let mut st = RouteStability::default();
+        for (when, entry) in items {
+            st.record(*when, entry);
+        }
+        st
+    }
+    /// accounts record inserted into history, repeated identical records are not changes
+    pub fn record(&mut self, when: Timestamp, entry: &BgpAttrEntry) {
+        let h = state_hash(entry);
+        if self.first_seen.is_none() {
+            self.first_seen = Some(when);
+            self.last_changed = Some(when);
+            self.day = day_of(&when);
+            self.last = h;
+            return;
+        }
+        if h == self.last {
+            return;
+        }
+        self.last = h;
+        self.last_changed = Some(when);
+        self.changes += 1;
+        let day = day_of(&when);
+        if day != self.day {
+            self.day = day;
+            self.changes_day = 0;
+        }
+        self.changes_day += 1;
+    }
+    /// restores last record hash of counters read from snapshot
+    pub fn resync(&mut self, last: &BgpAttrEntry) {
+        self.last = state_hash(last);
+    }
+    /// changes made today
+    pub fn changes_today(&self) -> u64 {
+        if day_of(&Timestamp::now()) == self.day {
+            self.changes_day
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgpattrs::BgpAttrs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_route_stability() {
+        let attrs = Arc::new(BgpAttrs::new());
+        let mut other = BgpAttrs::new();
+        other.med = Some(10);
+        let other = Arc::new(other);
+        let now = Timestamp::now();
+        let mut st = RouteStability::default();
+        st.record(now, &BgpAttrEntry::new(true, attrs.clone(), None));
+        st.record(now, &BgpAttrEntry::new(true, attrs.clone(), None));
+        assert_eq!(st.changes, 0);
+        st.record(now, &BgpAttrEntry::new(false, attrs.clone(), None));
+        st.record(now, &BgpAttrEntry::new(true, other, None));
+        assert_eq!(st.changes, 2);
+        assert_eq!(st.changes_today(), 2);
+        assert_eq!(st.first_seen, Some(now));
+    }
+}
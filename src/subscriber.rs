@@ -55,10 +55,38 @@ impl serde::Serialize for EventWithdraw {
         map.end()
     }
 }
+/// withdraw burst of subscribed RIB, routes are not listed
+struct EventWithdrawBatch {
+    sessionid: BgpSessionId,
+    batch: Arc<crate::withdrawburst::WithdrawBatch>,
+    count: usize,
+}
+impl serde::Serialize for EventWithdrawBatch {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_struct("WithdrawBatch", 5)?;
+        map.serialize_field("sessionid", &self.sessionid)?;
+        map.serialize_field("withdrawn", &self.count)?;
+        map.serialize_field("started", &self.batch.started)?;
+        map.serialize_field("until", &self.batch.until)?;
+        map.serialize_field("last", &self.batch.last)?;
+        map.end()
+    }
+}
 pub async fn on_subscriber_client(
     mut rcv: tokio::sync::broadcast::Receiver<BgpEvent>,
     mut client: Framed<Upgraded, MessageCodec>,
+    anon: Option<Arc<crate::anonymize::Anonymizer>>,
 ) {
+    let anonymized = |vl: String| match anon.as_ref() {
+        Some(a) => a
+            .body("text/json", vl.as_bytes())
+            .map(|b| String::from_utf8_lossy(&b).to_string())
+            .unwrap_or(vl),
+        None => vl,
+    };
     let mut rib: BgpRibKind = BgpRibKind::IpV4u;
     let mut filter = RouteFilter::new();
     loop {
@@ -76,7 +104,7 @@ pub async fn on_subscriber_client(
                                 if let Some(uk) = BgpRibKind::from_bgp_addrs(&addrs) {
                                     if uk==rib {
                                         if let Ok(vl) = serde_json::to_string(&EventUpdate{sessionid,attrs,addrs}) {
-                                            let _ = client.send(Message::text(vl)).await;
+                                            let _ = client.send(Message::text(anonymized(vl))).await;
                                         }
                                     }
                                 }
@@ -85,11 +113,19 @@ pub async fn on_subscriber_client(
                                 if let Some(uk) = BgpRibKind::from_bgp_addrs(&addrs) {
                                     if uk==rib {
                                         if let Ok(vl) = serde_json::to_string(&EventWithdraw{sessionid,addrs}) {
-                                            let _ = client.send(Message::text(vl)).await;
+                                            let _ = client.send(Message::text(anonymized(vl))).await;
                                         }
                                     }
                                 }
                             }
+                            BgpEvent::WithdrawBatch(sessionid, batch) => {
+                                let count = batch.counts.get(&rib.to_string()).copied().unwrap_or(0);
+                                if count > 0 || batch.last {
+                                    if let Ok(vl) = serde_json::to_string(&EventWithdrawBatch{sessionid,batch,count}) {
+                                        let _ = client.send(Message::text(anonymized(vl))).await;
+                                    }
+                                }
+                            }
                         }
                     }
                 }
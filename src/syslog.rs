@@ -0,0 +1,209 @@
+//! RFC 5424 syslog sink for session transitions and watchlist alerts
+use crate::bgprib::BgpEvent;
+use crate::config::ErrorConfig;
+use crate::ribfilter::RouteFilter;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// syslog transport
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTarget {
+    Udp(String),
+    Tcp(String),
+    Tls(String),
+}
+impl FromStr for SyslogTarget {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(a) = s.strip_prefix("udp://") {
+            Ok(SyslogTarget::Udp(a.to_string()))
+        } else if let Some(a) = s.strip_prefix("tcp://") {
+            Ok(SyslogTarget::Tcp(a.to_string()))
+        } else if let Some(a) = s.strip_prefix("tls://") {
+            Ok(SyslogTarget::Tls(a.to_string()))
+        } else {
+            Err(ErrorConfig::from_str(
+                "syslog target should be udp://, tcp:// or tls:// host:port",
+            ))
+        }
+    }
+}
+
+/// syslog facility by name or number
+pub fn parse_facility(s: &str) -> Option<u8> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<u8>() {
+        return if n < 24 { Some(n) } else { None };
+    }
+    match s {
+        "kern" => Some(0),
+        "user" => Some(1),
+        "daemon" => Some(3),
+        "auth" => Some(4),
+        "local0" => Some(16),
+        "local1" => Some(17),
+        "local2" => Some(18),
+        "local3" => Some(19),
+        "local4" => Some(20),
+        "local5" => Some(21),
+        "local6" => Some(22),
+        "local7" => Some(23),
+        _ => None,
+    }
+}
+
+pub const SEVERITY_WARNING: u8 = 4;
+pub const SEVERITY_NOTICE: u8 = 5;
+
+/// formats RFC 5424 message
+pub fn format_message(
+    facility: u8,
+    severity: u8,
+    hostname: &str,
+    msgid: &str,
+    msg: &str,
+) -> String {
+    format!(
+        "<{}>1 {} {} bgpexplorer {} {} - {}",
+        facility as u32 * 8 + severity as u32,
+        chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+        hostname,
+        std::process::id(),
+        msgid,
+        msg
+    )
+}
+
+/// queues messages to background writer, so it can be used from sync code
+pub struct SyslogSink {
+    facility: u8,
+    hostname: String,
+    tx: UnboundedSender<String>,
+}
+impl SyslogSink {
+    /// creates sink and spawns writer task
+    pub fn start(target: SyslogTarget, facility: u8) -> Arc<SyslogSink> {
+        let (tx, rx) = unbounded_channel();
+        tokio::spawn(run_writer(target, rx));
+        Arc::new(SyslogSink {
+            facility,
+            hostname: std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string()),
+            tx,
+        })
+    }
+    pub fn send(&self, severity: u8, msgid: &str, msg: &str) {
+        let _ = self.tx.send(format_message(
+            self.facility,
+            severity,
+            self.hostname.as_str(),
+            msgid,
+            msg,
+        ));
+    }
+    /// emits alerts for routes matching watchlist filter
+    pub async fn run_watch(
+        self: Arc<Self>,
+        watch: RouteFilter,
+        mut rcv: tokio::sync::broadcast::Receiver<BgpEvent>,
+    ) {
+        loop {
+            let evt = match rcv.recv().await {
+                Ok(e) => e,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Syslog watchlist skipped {} events", n);
+                    continue;
+                }
+                Err(_) => return,
+            };
+            for alert in crate::watch::match_event(&watch, &evt) {
+                self.send(SEVERITY_WARNING, "ALERT", alert.to_string().as_str());
+            }
+        }
+    }
+}
+
+/// sends queued messages, reconnects stream transports on failures
+async fn run_writer(target: SyslogTarget, mut rx: UnboundedReceiver<String>) {
+    let tls = matches!(target, SyslogTarget::Tls(_));
+    match target {
+        SyslogTarget::Udp(addr) => {
+            let sock = match tokio::net::UdpSocket::bind("[::]:0").await {
+                Ok(s) => s,
+                Err(_) => match tokio::net::UdpSocket::bind("0.0.0.0:0").await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        error!("Syslog socket error: {}", e);
+                        return;
+                    }
+                },
+            };
+            while let Some(msg) = rx.recv().await {
+                if let Err(e) = sock.send_to(msg.as_bytes(), addr.as_str()).await {
+                    warn!("Syslog send error: {}", e);
+                }
+            }
+        }
+        SyslogTarget::Tcp(addr) | SyslogTarget::Tls(addr) => {
+            let mut pending: Option<String> = None;
+            loop {
+                let mut stream = match connect_stream(addr.as_str(), tls).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        warn!("Syslog connect to {} error: {}", addr, e);
+                        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                        continue;
+                    }
+                };
+                loop {
+                    let msg = match pending.take() {
+                        Some(m) => m,
+                        None => match rx.recv().await {
+                            Some(m) => m,
+                            None => return,
+                        },
+                    };
+                    // RFC 6587 octet counting framing
+                    let frame = format!("{} {}", msg.len(), msg);
+                    if let Err(e) = stream.write_all(frame.as_bytes()).await {
+                        warn!("Syslog send error: {}", e);
+                        pending = Some(msg);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn connect_stream(addr: &str, tls: bool) -> std::io::Result<crate::tls::BoxStream> {
+    let tcp = tokio::net::TcpStream::connect(addr).await?;
+    if !tls {
+        return Ok(Box::new(tcp));
+    }
+    crate::tls::connect(crate::tls::host_of(addr), Box::new(tcp)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syslog_format() {
+        assert_eq!(
+            "udp://10.0.0.1:514".parse::<SyslogTarget>().unwrap(),
+            SyslogTarget::Udp("10.0.0.1:514".to_string())
+        );
+        assert!("10.0.0.1:514".parse::<SyslogTarget>().is_err());
+        assert_eq!(parse_facility("local0"), Some(16));
+        assert_eq!(parse_facility("3"), Some(3));
+        assert_eq!(parse_facility("24"), None);
+        let m = format_message(16, SEVERITY_NOTICE, "host", "SESSION", "text");
+        assert!(m.starts_with("<133>1 "));
+        let tail = format!(" host bgpexplorer {} SESSION - text", std::process::id());
+        assert!(m.ends_with(tail.as_str()));
+    }
+}
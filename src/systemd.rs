@@ -0,0 +1,200 @@
+//! systemd integration: socket activation and sd_notify protocol
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// first file descriptor passed by socket activation
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+lazy_static! {
+    static ref LISTENERS: Mutex<Vec<TcpListener>> = Mutex::new(inherited_listeners());
+}
+
+static READY_SENT: AtomicBool = AtomicBool::new(false);
+
+/// collects TCP listeners passed via LISTEN_PID/LISTEN_FDS
+#[cfg(unix)]
+fn inherited_listeners() -> Vec<TcpListener> {
+    use std::os::unix::io::FromRawFd;
+    let pid = match std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok())
+    {
+        Some(p) => p,
+        None => return Vec::new(),
+    };
+    if pid != std::process::id() {
+        return Vec::new();
+    }
+    let fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|s| s.parse::<i32>().ok())
+        .unwrap_or(0);
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    let mut ret = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + fds {
+        let l = unsafe { TcpListener::from_raw_fd(fd) };
+        match l.local_addr() {
+            Ok(a) => {
+                info!("Socket activated listener {} (fd {})", a, fd);
+                ret.push(l);
+            }
+            Err(e) => {
+                warn!("Socket activated fd {} is not TCP listener: {}", fd, e);
+                std::mem::forget(l);
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(not(unix))]
+fn inherited_listeners() -> Vec<TcpListener> {
+    Vec::new()
+}
+
+/// checks whether listener for specified address was passed or pre-bound
+pub fn has_listener(sockaddr: &SocketAddr) -> bool {
+    let lst = LISTENERS.lock().unwrap();
+    lst.iter().any(|l| match l.local_addr() {
+        Ok(a) => a == *sockaddr,
+        Err(_) => false,
+    })
+}
+
+/// adds pre-bound listener, it will be used instead of binding later
+pub fn put_listener(l: TcpListener) {
+    LISTENERS.lock().unwrap().push(l);
+}
+
+/// takes socket activated or pre-bound listener for specified address, if any
+pub fn take_listener(sockaddr: &SocketAddr) -> Option<TcpListener> {
+    let mut lst = LISTENERS.lock().unwrap();
+    let pos = lst.iter().position(|l| match l.local_addr() {
+        Ok(a) => a == *sockaddr,
+        Err(_) => false,
+    })?;
+    Some(lst.remove(pos))
+}
+
+/// sends state string to service manager, does nothing when NOTIFY_SOCKET is unset
+#[cfg(unix)]
+pub fn notify(state: &str) -> bool {
+    use std::os::unix::net::UnixDatagram;
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(p) => p,
+        None => return false,
+    };
+    let sock = match UnixDatagram::unbound() {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("sd_notify socket error: {}", e);
+            return false;
+        }
+    };
+    let path = path.to_string_lossy().to_string();
+    let res = if let Some(abs) = path.strip_prefix('@') {
+        send_abstract(&sock, abs, state)
+    } else {
+        sock.send_to(state.as_bytes(), path.as_str()).map(|_| ())
+    };
+    match res {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("sd_notify error: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(
+    sock: &std::os::unix::net::UnixDatagram,
+    name: &str,
+    state: &str,
+) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+    sock.send_to_addr(state.as_bytes(), &addr).map(|_| ())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_abstract(
+    _sock: &std::os::unix::net::UnixDatagram,
+    _name: &str,
+    _state: &str,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "abstract sockets are not supported",
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) -> bool {
+    false
+}
+
+/// watchdog interval requested by service manager, half of WATCHDOG_USEC
+pub fn watchdog_interval() -> Option<std::time::Duration> {
+    if let Some(pid) = std::env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok())
+    {
+        if pid != std::process::id() {
+            return None;
+        }
+    }
+    let usec = std::env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())?;
+    if usec < 2 {
+        return None;
+    }
+    Some(std::time::Duration::from_micros(usec / 2))
+}
+
+/// reports readiness once and starts watchdog pings; ping is sent only when check
+/// passes, otherwise its reason is logged and set as service status, so service manager
+/// restarts stalled process and journal tells why
+pub fn ready<F: FnMut() -> Result<(), String> + Send + 'static>(mut check: F) {
+    if READY_SENT.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    if !notify("READY=1") {
+        return;
+    }
+    info!("Notified service manager about readiness");
+    if let Some(interval) = watchdog_interval() {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut failing = false;
+            loop {
+                ticker.tick().await;
+                match check() {
+                    Ok(()) => {
+                        if failing {
+                            info!("Watchdog check passes again");
+                            notify("STATUS=");
+                            failing = false;
+                        }
+                        notify("WATCHDOG=1");
+                    }
+                    Err(reason) => {
+                        warn!("Watchdog ping withheld: {}", reason);
+                        notify(format!("STATUS=stalled: {}", reason).as_str());
+                        failing = true;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// reports shutdown in progress
+pub fn stopping() {
+    notify("STOPPING=1");
+}
@@ -0,0 +1,344 @@
+//! table version: every route mutation of RIB (announce or withdraw of one prefix) gets
+//! next sequence number. Recent mutations are kept in journal for /api/changes?since=N,
+//! clients behind journal or from other epoch (restart, flush) reload full table.
+use crate::bgpattrs::BgpAttrs;
+use crate::bgpsvc::BgpSessionId;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use zettabgp::prelude::*;
+
+fn strs<T: ToString>(v: &[T]) -> Vec<String> {
+    v.iter().map(|r| r.to_string()).collect()
+}
+fn strs_pathid<T: ToString + Clone + Eq + PartialOrd>(v: &[WithPathId<T>]) -> Vec<String> {
+    v.iter().map(|r| r.nlri.to_string()).collect()
+}
+
+/// route keys of NLRI as shown by /api/json
+pub fn prefix_strings(addrs: &BgpAddrs) -> Vec<String> {
+    match addrs {
+        BgpAddrs::IPV4U(v) | BgpAddrs::IPV4M(v) => strs(v),
+        BgpAddrs::IPV4UP(v) | BgpAddrs::IPV4MP(v) => strs_pathid(v),
+        BgpAddrs::IPV4LU(v) => strs(v),
+        BgpAddrs::IPV4LUP(v) => strs_pathid(v),
+        BgpAddrs::VPNV4U(v) | BgpAddrs::VPNV4M(v) => strs(v),
+        BgpAddrs::VPNV4UP(v) | BgpAddrs::VPNV4MP(v) => strs_pathid(v),
+        BgpAddrs::IPV6U(v) | BgpAddrs::IPV6M(v) => strs(v),
+        BgpAddrs::IPV6UP(v) | BgpAddrs::IPV6MP(v) => strs_pathid(v),
+        BgpAddrs::IPV6LU(v) => strs(v),
+        BgpAddrs::IPV6LUP(v) => strs_pathid(v),
+        BgpAddrs::VPNV6U(v) | BgpAddrs::VPNV6M(v) => strs(v),
+        BgpAddrs::VPNV6UP(v) | BgpAddrs::VPNV6MP(v) => strs_pathid(v),
+        BgpAddrs::IPV4MDT(v) => strs(v),
+        BgpAddrs::IPV6MDT(v) => strs(v),
+        BgpAddrs::L2VPLS(v) => strs(v),
+        BgpAddrs::MVPN(v) => strs(v),
+        BgpAddrs::EVPN(v) => strs(v),
+        BgpAddrs::FS4U(v) => strs(v),
+        _ => Vec::new(),
+    }
+}
+
+/// routes of addrs except ones at sorted positions skip
+pub fn without(addrs: &Arc<BgpAddrs>, skip: &[usize]) -> Arc<BgpAddrs> {
+    if skip.is_empty() {
+        return addrs.clone();
+    }
+    macro_rules! keep {
+        ($v:expr, $ctor:path) => {
+            $ctor(
+                $v.iter()
+                    .enumerate()
+                    .filter(|(i, _)| skip.binary_search(i).is_err())
+                    .map(|(_, r)| r.clone())
+                    .collect(),
+            )
+        };
+    }
+    Arc::new(match addrs.as_ref() {
+        BgpAddrs::IPV4U(v) => keep!(v, BgpAddrs::IPV4U),
+        BgpAddrs::IPV4M(v) => keep!(v, BgpAddrs::IPV4M),
+        BgpAddrs::IPV4UP(v) => keep!(v, BgpAddrs::IPV4UP),
+        BgpAddrs::IPV4MP(v) => keep!(v, BgpAddrs::IPV4MP),
+        BgpAddrs::IPV4LU(v) => keep!(v, BgpAddrs::IPV4LU),
+        BgpAddrs::IPV4LUP(v) => keep!(v, BgpAddrs::IPV4LUP),
+        BgpAddrs::VPNV4U(v) => keep!(v, BgpAddrs::VPNV4U),
+        BgpAddrs::VPNV4M(v) => keep!(v, BgpAddrs::VPNV4M),
+        BgpAddrs::VPNV4UP(v) => keep!(v, BgpAddrs::VPNV4UP),
+        BgpAddrs::VPNV4MP(v) => keep!(v, BgpAddrs::VPNV4MP),
+        BgpAddrs::IPV6U(v) => keep!(v, BgpAddrs::IPV6U),
+        BgpAddrs::IPV6M(v) => keep!(v, BgpAddrs::IPV6M),
+        BgpAddrs::IPV6UP(v) => keep!(v, BgpAddrs::IPV6UP),
+        BgpAddrs::IPV6MP(v) => keep!(v, BgpAddrs::IPV6MP),
+        BgpAddrs::IPV6LU(v) => keep!(v, BgpAddrs::IPV6LU),
+        BgpAddrs::IPV6LUP(v) => keep!(v, BgpAddrs::IPV6LUP),
+        BgpAddrs::VPNV6U(v) => keep!(v, BgpAddrs::VPNV6U),
+        BgpAddrs::VPNV6M(v) => keep!(v, BgpAddrs::VPNV6M),
+        BgpAddrs::VPNV6UP(v) => keep!(v, BgpAddrs::VPNV6UP),
+        BgpAddrs::VPNV6MP(v) => keep!(v, BgpAddrs::VPNV6MP),
+        BgpAddrs::IPV4MDT(v) => keep!(v, BgpAddrs::IPV4MDT),
+        BgpAddrs::IPV6MDT(v) => keep!(v, BgpAddrs::IPV6MDT),
+        BgpAddrs::L2VPLS(v) => keep!(v, BgpAddrs::L2VPLS),
+        BgpAddrs::MVPN(v) => keep!(v, BgpAddrs::MVPN),
+        BgpAddrs::EVPN(v) => keep!(v, BgpAddrs::EVPN),
+        BgpAddrs::FS4U(v) => keep!(v, BgpAddrs::FS4U),
+        other => other.clone(),
+    })
+}
+
+/// mutations of one UPDATE part, versions first..first+addrs.len()
+#[derive(Clone)]
+struct JournalEntry {
+    first: u64,
+    time: Timestamp,
+    session: BgpSessionId,
+    /// None for withdraws
+    attrs: Option<Arc<BgpAttrs>>,
+    addrs: Arc<BgpAddrs>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionedChange {
+    pub version: u64,
+    pub time: Timestamp,
+    pub session: BgpSessionId,
+    pub rib: String,
+    pub prefix: String,
+    /// announced/withdrawn
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aspath: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nexthop: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TableVersionInfo {
+    /// start of version sequence in ms, changes when RIB is replaced
+    pub epoch: i64,
+    pub version: u64,
+    /// oldest version kept in journal, changes since oldest-1 or later can be requested
+    pub oldest: u64,
+}
+
+/// journal entries after since, taken under RIB lock and expanded to changes after it
+pub struct JournalSlice {
+    info: TableVersionInfo,
+    since: u64,
+    reset: bool,
+    limit: usize,
+    entries: Vec<JournalEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangesSince {
+    #[serde(flatten)]
+    pub info: TableVersionInfo,
+    pub since: u64,
+    /// since is not covered by journal, full table has to be reloaded
+    pub reset: bool,
+    pub changes: Vec<VersionedChange>,
+    /// more changes are waiting beyond limit
+    pub more: bool,
+}
+
+pub struct TableVersion {
+    epoch: Timestamp,
+    version: u64,
+    journal: VecDeque<JournalEntry>,
+    /// routes in journal
+    routes: usize,
+    capacity: usize,
+}
+impl TableVersion {
+    pub fn new(capacity: usize) -> TableVersion {
+        TableVersion {
+            epoch: Timestamp::now(),
+            version: 0,
+            journal: VecDeque::new(),
+            routes: 0,
+            capacity,
+        }
+    }
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+    /// assigns versions to routes of UPDATE part, attrs are None for withdraws
+    pub fn record(
+        &mut self,
+        session: BgpSessionId,
+        addrs: &Arc<BgpAddrs>,
+        attrs: Option<&Arc<BgpAttrs>>,
+        now: Timestamp,
+    ) {
+        let n = addrs.len();
+        if n == 0 {
+            return;
+        }
+        let first = self.version + 1;
+        self.version += n as u64;
+        if self.capacity == 0 {
+            return;
+        }
+        self.journal.push_back(JournalEntry {
+            first,
+            time: now,
+            session,
+            attrs: attrs.cloned(),
+            addrs: addrs.clone(),
+        });
+        self.routes += n;
+        while self.routes > self.capacity {
+            match self.journal.pop_front() {
+                Some(e) => self.routes -= e.addrs.len(),
+                None => break,
+            }
+        }
+    }
+    /// RIB content was replaced outside of updates, journal is no longer valid
+    pub fn reset(&mut self) {
+        self.epoch = Timestamp::now();
+        self.journal.clear();
+        self.routes = 0;
+    }
+    pub fn info(&self) -> TableVersionInfo {
+        TableVersionInfo {
+            epoch: self.epoch.timestamp_millis(),
+            version: self.version,
+            oldest: self
+                .journal
+                .front()
+                .map(|e| e.first)
+                .unwrap_or(self.version + 1),
+        }
+    }
+    /// journal entries holding up to limit changes after version since, Err with current
+    /// version info when epoch of client's version is not the current one
+    pub fn changes_since(
+        &self,
+        epoch: i64,
+        since: u64,
+        limit: usize,
+    ) -> Result<JournalSlice, TableVersionInfo> {
+        let info = self.info();
+        if epoch != info.epoch {
+            return Err(info);
+        }
+        let mut ret = JournalSlice {
+            info,
+            since,
+            reset: since > info.version || since + 1 < info.oldest,
+            limit,
+            entries: Vec::new(),
+        };
+        if ret.reset {
+            return Ok(ret);
+        }
+        let mut routes = 0;
+        for e in self.journal.iter() {
+            let end = e.first + e.addrs.len() as u64;
+            if end <= since + 1 {
+                continue;
+            }
+            // one more than limit tells there are more changes
+            if routes > limit {
+                break;
+            }
+            routes += (end - e.first.max(since + 1)) as usize;
+            ret.entries.push(e.clone());
+        }
+        Ok(ret)
+    }
+}
+impl JournalSlice {
+    /// changes of entries, done without RIB lock
+    pub fn changes(self) -> ChangesSince {
+        let mut ret = ChangesSince {
+            info: self.info,
+            since: self.since,
+            reset: self.reset,
+            changes: Vec::new(),
+            more: false,
+        };
+        for e in self.entries.iter() {
+            let rib = crate::bgprib::BgpRibKind::from_bgp_addrs(&e.addrs)
+                .map(|k| k.to_string())
+                .unwrap_or_default();
+            for (i, prefix) in prefix_strings(&e.addrs).into_iter().enumerate() {
+                let version = e.first + i as u64;
+                if version <= self.since {
+                    continue;
+                }
+                if ret.changes.len() >= self.limit {
+                    ret.more = true;
+                    return ret;
+                }
+                ret.changes.push(VersionedChange {
+                    version,
+                    time: e.time,
+                    session: e.session,
+                    rib: rib.clone(),
+                    prefix,
+                    kind: if e.attrs.is_some() {
+                        "announced"
+                    } else {
+                        "withdrawn"
+                    },
+                    aspath: e.attrs.as_ref().map(|a| a.aspath.to_string()),
+                    nexthop: e.attrs.as_ref().map(|a| a.nexthop.to_string()),
+                });
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(n: u8) -> Arc<BgpAddrs> {
+        Arc::new(BgpAddrs::IPV4U(
+            (0..n)
+                .map(|i| BgpAddrV4::new(std::net::Ipv4Addr::new(10, i, 0, 0), 16))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn test_table_version() {
+        let mut tv = TableVersion::new(5);
+        let now = Timestamp::now();
+        let attrs = Arc::new(BgpAttrs::new());
+        tv.record(1, &addrs(3), Some(&attrs), now);
+        tv.record(1, &addrs(2), None, now);
+        assert_eq!(tv.version(), 5);
+        let epoch = tv.info().epoch;
+        let changes = |since, limit| tv.changes_since(epoch, since, limit).unwrap().changes();
+        let c = changes(3, 10);
+        assert!(!c.reset);
+        let v: Vec<(u64, &str)> = c.changes.iter().map(|c| (c.version, c.kind)).collect();
+        assert_eq!(v, vec![(4, "withdrawn"), (5, "withdrawn")]);
+        assert_eq!(c.changes[0].prefix, "10.0.0.0/16");
+        let c = changes(0, 2);
+        assert_eq!((c.changes.len(), c.more), (2, true));
+        let c = changes(0, 5);
+        assert_eq!((c.changes.len(), c.more), (5, false));
+        tv.record(2, &addrs(1), None, now);
+        let changes = |since, limit| tv.changes_since(epoch, since, limit).unwrap().changes();
+        assert_eq!(tv.info().oldest, 4);
+        assert!(changes(0, 10).reset);
+        assert!(!changes(3, 10).reset);
+        assert!(tv.changes_since(epoch + 1, 5, 10).is_err());
+        tv.reset();
+        let epoch = tv.info().epoch;
+        let changes = |since, limit| tv.changes_since(epoch, since, limit).unwrap().changes();
+        assert!(changes(6, 10).changes.is_empty());
+        assert!(changes(5, 10).reset);
+        // routes which did not change RIB get no version
+        let kept = without(&addrs(3), &[0, 2]);
+        assert_eq!(prefix_strings(&kept), vec!["10.1.0.0/16".to_string()]);
+    }
+}
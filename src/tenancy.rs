@@ -0,0 +1,246 @@
+//! API tokens with visibility scopes for shared looking glass deployments
+//!
+//! Each `[token:name]` ini section defines bearer token and optionally RIBs and routes
+//! visible with it. When any token is configured API requires one of them.
+use crate::bgprib::BgpSessionEntry;
+use crate::config::ErrorConfig;
+use crate::ribfilter::{FilterItemMatchResult, FilterMatchRoute, RouteFilter};
+use hyper::{Body, Request, StatusCode};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// token section as configured
+#[derive(Debug, Clone)]
+pub struct TokenConfig {
+    pub name: String,
+    pub token: String,
+    /// visible RIB names, all if None
+    pub ribs: Option<BTreeSet<String>>,
+    /// route filters in query syntax, route is visible when it matches any of them
+    pub routes: Vec<String>,
+    /// allows /api/admin actions and /api/audit
+    pub admin: bool,
+}
+impl TokenConfig {
+    pub fn from_ini(
+        name: &str,
+        section: &HashMap<String, Option<String>>,
+    ) -> Result<TokenConfig, ErrorConfig> {
+        let token = match section.get("token") {
+            Some(Some(s)) if !s.trim().is_empty() => s.trim().to_string(),
+            _ => {
+                return Err(ErrorConfig::from_string(format!(
+                    "Missing token for token:{}",
+                    name
+                )))
+            }
+        };
+        let list = |key: &str, sep: char| -> Vec<String> {
+            match section.get(key) {
+                Some(Some(s)) => s
+                    .split(sep)
+                    .map(|x| x.trim().to_string())
+                    .filter(|x| !x.is_empty())
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+        let ribs = list("ribs", ',');
+        let routes = list("routes", ';');
+        // unrecognized item would be dropped and widen visibility, empty filter shows all
+        for flt in routes.iter() {
+            let items = flt.split(' ').filter(|x| !x.is_empty()).count();
+            let terms = RouteFilter::fromstr(flt).terms.len();
+            if terms == 0 || terms != items {
+                return Err(ErrorConfig::from_string(format!(
+                    "Invalid routes filter '{}' for token:{}",
+                    flt, name
+                )));
+            }
+        }
+        Ok(TokenConfig {
+            name: name.to_string(),
+            token,
+            ribs: if ribs.is_empty() {
+                None
+            } else {
+                Some(ribs.into_iter().collect())
+            },
+            routes,
+            admin: match section.get("admin") {
+                Some(Some(s)) => s.parse().map_err(|_| {
+                    ErrorConfig::from_string(format!("Invalid admin for token:{}", name))
+                })?,
+                _ => false,
+            },
+        })
+    }
+}
+
+/// tokens from sections named token:<name>
+pub fn parse_tokens(
+    conf: &HashMap<String, HashMap<String, Option<String>>>,
+) -> Result<Vec<TokenConfig>, ErrorConfig> {
+    let mut ret = Vec::new();
+    for (name, section) in conf.iter() {
+        if let Some(n) = name.strip_prefix("token:") {
+            ret.push(TokenConfig::from_ini(n, section)?);
+        }
+    }
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(ret)
+}
+
+/// visibility restrictions of authenticated request
+pub struct ApiScope {
+    pub name: String,
+    pub admin: bool,
+    ribs: Option<BTreeSet<String>>,
+    routes: Vec<RouteFilter>,
+}
+impl ApiScope {
+    pub fn new(cfg: &TokenConfig) -> ApiScope {
+        ApiScope {
+            name: cfg.name.clone(),
+            admin: cfg.admin,
+            ribs: cfg.ribs.clone(),
+            routes: cfg
+                .routes
+                .iter()
+                .map(|s| RouteFilter::fromstr(s.as_str()))
+                .collect(),
+        }
+    }
+    /// whether scope hides anything
+    pub fn is_restricted(&self) -> bool {
+        self.ribs.is_some() || !self.routes.is_empty()
+    }
+    pub fn allows_rib(&self, rib: &str) -> bool {
+        match self.ribs {
+            None => true,
+            Some(ref r) => r.contains(rib),
+        }
+    }
+    /// whether any history record of route matches scope
+    pub fn allows_route<T: FilterMatchRoute>(&self, route: &T, entry: &BgpSessionEntry) -> bool {
+        if self.routes.is_empty() {
+            return true;
+        }
+        entry.items.values().any(|pe| {
+            pe.items.values().any(|ah| {
+                ah.items.values().any(|h| {
+                    self.routes
+                        .iter()
+                        .any(|f| f.match_route(route, &h.attrs) == FilterItemMatchResult::Yes)
+                })
+            })
+        })
+    }
+}
+
+/// configured tokens
+#[derive(Default)]
+pub struct ApiTokens {
+    tokens: HashMap<String, Arc<ApiScope>>,
+}
+impl ApiTokens {
+    pub fn new(cfg: &[TokenConfig]) -> ApiTokens {
+        ApiTokens {
+            tokens: cfg
+                .iter()
+                .map(|t| (t.token.clone(), Arc::new(ApiScope::new(t))))
+                .collect(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+    /// scope of request token from Authorization: Bearer header or token parameter,
+    /// None for missing or unknown token
+    pub fn identify(&self, req: &Request<Body>) -> Option<Arc<ApiScope>> {
+        let token = req
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.trim().to_string())
+            .or_else(|| {
+                crate::service::get_url_param::<String>(
+                    &crate::service::get_url_params(req),
+                    "token",
+                )
+            })?;
+        self.tokens.get(&token).cloned()
+    }
+    /// scope of query request, Ok(None) means unrestricted access
+    pub fn authorize(&self, req: &Request<Body>) -> Result<Option<Arc<ApiScope>>, StatusCode> {
+        if self.tokens.is_empty() {
+            return Ok(None);
+        }
+        match self.identify(req) {
+            None => Err(StatusCode::UNAUTHORIZED),
+            Some(s) if s.is_restricted() => Ok(Some(s)),
+            Some(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        let mut sect = HashMap::new();
+        sect.insert("token".to_string(), Some("secret".to_string()));
+        sect.insert("ribs".to_string(), Some("ipv4u, ipv6u".to_string()));
+        sect.insert(
+            "routes".to_string(),
+            Some("192.0.2.0/24; 2001:db8::/32".to_string()),
+        );
+        let mut conf = HashMap::new();
+        conf.insert("token:cust1".to_string(), sect);
+        conf.insert("main".to_string(), HashMap::new());
+        let cfg = parse_tokens(&conf).unwrap();
+        assert_eq!(cfg.len(), 1);
+        assert_eq!(cfg[0].routes.len(), 2);
+        let tokens = ApiTokens::new(&cfg);
+        let req = |auth: Option<&str>, uri: &str| {
+            let mut b = Request::builder().uri(uri);
+            if let Some(a) = auth {
+                b = b.header("Authorization", a);
+            }
+            b.body(Body::empty()).unwrap()
+        };
+        assert_eq!(
+            tokens.authorize(&req(None, "/api/json/ipv4u")).err(),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        let scope = tokens
+            .authorize(&req(Some("Bearer secret"), "/api/json/ipv4u"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(scope.name, "cust1");
+        assert!(scope.allows_rib("ipv6u"));
+        assert!(!scope.allows_rib("vpnv4u"));
+        assert!(tokens
+            .authorize(&req(None, "/api/json/ipv4u?token=secret"))
+            .is_ok());
+        assert!(
+            !tokens
+                .identify(&req(Some("Bearer secret"), "/api/audit"))
+                .unwrap()
+                .admin
+        );
+        for bad in ["c:65000:1", "192.0.2.0/24 bogus"] {
+            let mut sect = HashMap::new();
+            sect.insert("token".to_string(), Some("secret".to_string()));
+            sect.insert("routes".to_string(), Some(bad.to_string()));
+            assert!(TokenConfig::from_ini("cust2", &sect).is_err());
+        }
+        assert!(ApiTokens::default()
+            .authorize(&req(None, "/api/json/ipv4u"))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,216 @@
+//! `bgpexplorer test-peer`: connects to configured bgpactive peer, does OPEN and KEEPALIVE
+//! exchange, reports capabilities and NOTIFICATION and closes session with Cease
+use crate::bgppeer::BgpPeer;
+use crate::bgpsvc::*;
+use crate::config::{PeerMode, ProtoPeer, SvcConfig};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use zettabgp::prelude::*;
+
+pub const USAGE: &str = "usage: bgpexplorer test-peer [--config FILE] [--timeout SECONDS] [PEER]
+  checks bgpactive peer from FILE (bgpexplorer.ini by default), PEER is peer address
+  and may be omitted when only one bgpactive peer is configured";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestPeerArgs {
+    pub config: String,
+    pub timeout: Duration,
+    pub peer: Option<std::net::IpAddr>,
+}
+impl TestPeerArgs {
+    pub fn from_args(args: &[String]) -> Result<TestPeerArgs, String> {
+        let mut ret = TestPeerArgs {
+            config: "bgpexplorer.ini".to_string(),
+            timeout: Duration::from_secs(10),
+            peer: None,
+        };
+        let mut it = args.iter();
+        while let Some(a) = it.next() {
+            match a.as_str() {
+                "--config" | "--timeout" => {
+                    let v = it
+                        .next()
+                        .ok_or_else(|| format!("Missing value for {}\n{}", a, USAGE))?;
+                    if a == "--config" {
+                        ret.config = v.clone();
+                    } else {
+                        ret.timeout = Duration::from_secs(
+                            v.parse()
+                                .map_err(|e| format!("Invalid value for {} - {}", a, e))?,
+                        );
+                    }
+                }
+                x if ret.peer.is_none() && !x.starts_with('-') => {
+                    ret.peer = Some(x.parse().map_err(|e| format!("Invalid peer - {}", e))?)
+                }
+                _ => return Err(USAGE.to_string()),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// picks bgpactive peer by address or the only one configured
+pub fn select_peer(
+    peers: &[Arc<ProtoPeer>],
+    addr: Option<std::net::IpAddr>,
+) -> Result<Arc<ProtoPeer>, String> {
+    let active: Vec<&Arc<ProtoPeer>> = peers
+        .iter()
+        .filter(|p| p.mode == PeerMode::BgpActive)
+        .collect();
+    match addr {
+        Some(a) => active
+            .into_iter()
+            .find(|p| p.peer.map(|s| s.ip()) == Some(a))
+            .cloned()
+            .ok_or_else(|| format!("No bgpactive peer {} configured", a)),
+        None if active.len() == 1 => Ok(active[0].clone()),
+        None if active.is_empty() => Err("No bgpactive peer configured".to_string()),
+        None => Err(format!(
+            "{} bgpactive peers configured, specify peer address",
+            active.len()
+        )),
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TestPeerReport {
+    pub peer: String,
+    pub local: Option<String>,
+    pub connected: bool,
+    /// OPEN received
+    pub opened: bool,
+    /// KEEPALIVE received after OPEN
+    pub established: bool,
+    pub remote_as: Option<u32>,
+    pub router_id: Option<String>,
+    pub hold_time: Option<u16>,
+    pub advertised_caps: Vec<String>,
+    pub received_caps: Vec<String>,
+    pub negotiated_caps: Vec<String>,
+    pub error: Option<String>,
+}
+
+fn caps_strings(caps: &[BgpCapability]) -> Vec<String> {
+    caps.iter().map(|c| format!("{:?}", c)).collect()
+}
+
+/// keeps OPEN received from peer
+#[derive(Default)]
+struct OpenRecorder {
+    remote: Mutex<Option<BgpOpenMessage>>,
+}
+#[async_trait]
+impl BgpUpdateHandler for OpenRecorder {
+    async fn handle_update(&self, _peerid: BgpSessionId, _upd: BgpUpdateMessage) {}
+    async fn register_session(&self, sess: Arc<BgpSessionDesc>) -> BgpSessionId {
+        *self.remote.lock().unwrap() = Some(sess.peer2.bom.clone());
+        1
+    }
+}
+
+async fn test_peer(fpeer: &ProtoPeer, peeraddr: SocketAddr, rpt: &mut TestPeerReport) {
+    let sock = match connect_active(fpeer, peeraddr).await {
+        Ok(s) => s,
+        Err(e) => {
+            rpt.error = Some(format!("connect: {}", e));
+            return;
+        }
+    };
+    rpt.connected = true;
+    rpt.local = sock.local_addr().ok().map(|a| a.to_string());
+    let handler = OpenRecorder::default();
+    let params = fpeer.get_session_params();
+    rpt.advertised_caps = caps_strings(&params.caps);
+    let mut peer = BgpPeer::new(params, sock, &handler);
+    peer.ext_nexthop = fpeer.ext_nexthop;
+    peer.localas = fpeer.localas;
+    peer.quirks = fpeer.vendor_quirks;
+    let res = match peer.start_active().await {
+        Ok(_) => {
+            rpt.opened = true;
+            rpt.negotiated_caps = caps_strings(&peer.params.caps);
+            peer.confirm().await
+        }
+        Err(e) => Err(e),
+    };
+    if let Some(bom) = handler.remote.lock().unwrap().take() {
+        rpt.remote_as = Some(crate::wireparse::open_as(&bom));
+        rpt.router_id = Some(bom.router_id.to_string());
+        rpt.hold_time = Some(bom.hold_time);
+        rpt.received_caps = caps_strings(&bom.caps);
+    }
+    match res {
+        Ok(_) => {
+            rpt.established = true;
+            if let Err(e) = peer
+                .send_cease(crate::sessionctl::CEASE_ADMIN_SHUTDOWN)
+                .await
+            {
+                rpt.error = Some(format!("{:?}", e));
+            }
+        }
+        Err(e) => rpt.error = Some(e.to_string()),
+    }
+    peer.close().await;
+}
+
+/// runs `test-peer` arguments, returns JSON report and whether session came up
+pub fn run(args: &[String]) -> Result<(String, bool), String> {
+    let args = TestPeerArgs::from_args(args)?;
+    let conf = SvcConfig::from_inifile(args.config.as_str()).map_err(|e| e.to_string())?;
+    let fpeer = select_peer(&conf.peers, args.peer)?;
+    let peeraddr = fpeer.peer.ok_or_else(|| "No peer parameter".to_string())?;
+    let mut rpt = TestPeerReport {
+        peer: peeraddr.to_string(),
+        ..Default::default()
+    };
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?
+        .block_on(async {
+            if tokio::time::timeout(args.timeout, test_peer(&fpeer, peeraddr, &mut rpt))
+                .await
+                .is_err()
+            {
+                rpt.error = Some(format!("timed out after {}s", args.timeout.as_secs()));
+            }
+        });
+    let ok = rpt.established;
+    serde_json::to_string_pretty(&rpt)
+        .map(|s| (s, ok))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(mode: &str, addr: &str) -> Arc<ProtoPeer> {
+        let mut sect = std::collections::HashMap::new();
+        sect.insert("mode".to_string(), Some(mode.to_string()));
+        sect.insert("peer".to_string(), Some(addr.to_string()));
+        Arc::new(ProtoPeer::from_ini(&sect).unwrap())
+    }
+
+    #[test]
+    fn test_select_peer() {
+        let peers = vec![
+            peer("bmpactive", "192.0.2.9"),
+            peer("bgpactive", "192.0.2.1"),
+        ];
+        assert_eq!(
+            select_peer(&peers, None).unwrap().peer,
+            Some("192.0.2.1:179".parse().unwrap())
+        );
+        assert!(select_peer(&peers, Some("192.0.2.9".parse().unwrap())).is_err());
+        let a = TestPeerArgs::from_args(&["--timeout".to_string(), "3".to_string()]).unwrap();
+        assert_eq!(a.timeout, Duration::from_secs(3));
+        assert!(TestPeerArgs::from_args(&["x".to_string()]).is_err());
+    }
+}
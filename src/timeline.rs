@@ -0,0 +1,173 @@
+//! route history timeline built from RIB change history
+use crate::bgpattrs::*;
+use crate::bgprib::*;
+use crate::bgpsvc::BgpSessionId;
+use crate::ribfilter::{FilterMatchRoute, RouteFilter};
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::sync::Arc;
+use zettabgp::prelude::*;
+
+/// Period of time while path had the same attributes and state
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEpoch {
+    pub start: Timestamp,
+    /// None for current epoch
+    pub end: Option<Timestamp>,
+    pub active: bool,
+    /// bit mask of fields changed from previous epoch, bits are positions in TimelineResponse::fields
+    pub changed: u32,
+    #[serde(serialize_with = "serialize_attrs")]
+    pub attrs: Arc<BgpAttrs>,
+    pub labels: Option<MplsLabels>,
+}
+
+fn serialize_attrs<S>(attrs: &Arc<BgpAttrs>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    attrs.as_ref().serialize(serializer)
+}
+
+/// Epochs of one path from one session
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelinePath {
+    pub session: BgpSessionId,
+    pub path: BgpPathId,
+    pub epochs: Vec<TimelineEpoch>,
+}
+
+/// Route history shaped for timeline rendering
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineResponse {
+    pub route: String,
+    pub fields: Vec<&'static str>,
+    pub paths: Vec<TimelinePath>,
+}
+
+/// names of timeline change mask bits
+pub fn timeline_fields() -> Vec<&'static str> {
+    let mut ret = BgpAttrs::field_names().to_vec();
+    ret.push("Active");
+    ret.push("Labels");
+    ret
+}
+
+/// folds history records into epochs, consecutive equal records are merged
+pub fn history_epochs(hist: &BgpAttrHistory) -> Vec<TimelineEpoch> {
+    let activebit = BgpAttrs::field_names().len();
+    let mut ret: Vec<TimelineEpoch> = Vec::new();
+    for (ts, rec) in hist.items.iter() {
+        let changed = match ret.last() {
+            None => 0,
+            Some(prev) => {
+                let mut m = prev.attrs.diff_mask(rec.attrs.as_ref());
+                if prev.active != rec.active {
+                    m |= 1 << activebit;
+                }
+                if prev.labels.as_ref() != rec.labels.as_deref() {
+                    m |= 1 << (activebit + 1);
+                }
+                if m == 0 {
+                    continue;
+                }
+                m
+            }
+        };
+        if let Some(prev) = ret.last_mut() {
+            prev.end = Some(*ts);
+        }
+        ret.push(TimelineEpoch {
+            start: *ts,
+            end: None,
+            active: rec.active,
+            changed,
+            attrs: rec.attrs.clone(),
+            labels: rec.labels.as_deref().cloned(),
+        });
+    }
+    ret
+}
+
+/// timeline for best matching route
+pub fn find_timeline<T: FilterMatchRoute + BgpRIBKey>(
+    safi: &BgpRIBSafi<T>,
+    filter: &RouteFilter,
+) -> Option<TimelineResponse> {
+    let (route, sess) = filter.find_best_supernet(safi, usize::MAX, false)?;
+    let mut paths = Vec::new();
+    for (sid, pe) in sess.items.iter() {
+        for (pid, hist) in pe.items.iter() {
+            paths.push(TimelinePath {
+                session: *sid,
+                path: *pid,
+                epochs: history_epochs(hist),
+            });
+        }
+    }
+    Some(TimelineResponse {
+        route: route.to_string(),
+        fields: timeline_fields(),
+        paths,
+    })
+}
+
+impl BgpRIB {
+    /// looks up route in specified RIB and returns its history timeline
+    pub fn route_timeline(&self, ribname: &str, filter: &RouteFilter) -> Option<TimelineResponse> {
+        match ribname {
+            "ipv4u" => find_timeline(&self.ipv4u, filter),
+            "ipv4m" => find_timeline(&self.ipv4m, filter),
+            "ipv4lu" => find_timeline(&self.ipv4lu, filter),
+            "vpnv4u" => find_timeline(&self.vpnv4u, filter),
+            "vpnv4m" => find_timeline(&self.vpnv4m, filter),
+            "ipv6u" => find_timeline(&self.ipv6u, filter),
+            "ipv6m" => find_timeline(&self.ipv6m, filter),
+            "ipv6lu" => find_timeline(&self.ipv6lu, filter),
+            "vpnv6u" => find_timeline(&self.vpnv6u, filter),
+            "vpnv6m" => find_timeline(&self.vpnv6m, filter),
+            "l2vpls" => find_timeline(&self.l2vpls, filter),
+            "mvpn" => find_timeline(&self.mvpn, filter),
+            "evpn" => find_timeline(&self.evpn, filter),
+            "fs4u" => find_timeline(&self.fs4u, filter),
+            "ipv4mdt" => find_timeline(&self.ipv4mdt, filter),
+            "ipv6mdt" => find_timeline(&self.ipv6mdt, filter),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_epochs() {
+        let fields = timeline_fields();
+        let bit = |name: &str| 1u32 << fields.iter().position(|f| *f == name).unwrap();
+        let attrs = Arc::new(BgpAttrs::new());
+        let mut changed = BgpAttrs::new();
+        changed.med = Some(10);
+        let changed = Arc::new(changed);
+        let ts = Timestamp::from_micros;
+        let mut hist = BgpAttrHistory::new();
+        hist.items
+            .insert(ts(1), BgpAttrEntry::new(true, attrs.clone(), None));
+        // same attributes are folded into one epoch
+        hist.items
+            .insert(ts(2), BgpAttrEntry::new(true, attrs.clone(), None));
+        hist.items
+            .insert(ts(3), BgpAttrEntry::new(true, changed.clone(), None));
+        hist.items
+            .insert(ts(4), BgpAttrEntry::new(false, changed, None));
+        let epochs = history_epochs(&hist);
+        let spans: Vec<_> = epochs.iter().map(|e| (e.start, e.end)).collect();
+        assert_eq!(
+            spans,
+            vec![(ts(1), Some(ts(3))), (ts(3), Some(ts(4))), (ts(4), None)]
+        );
+        let masks: Vec<u32> = epochs.iter().map(|e| e.changed).collect();
+        assert_eq!(masks, vec![0, bit("Med"), bit("Active")]);
+        assert!(!epochs[2].active);
+    }
+}
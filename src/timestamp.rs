@@ -1,15 +1,72 @@
 use chrono::prelude::*;
 use chrono::{Local, LocalResult, TimeZone};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+thread_local! {
+    /// clock override for replayed updates
+    static CLOCK: std::cell::Cell<Option<Timestamp>> = const { std::cell::Cell::new(None) };
+    /// router side time of update being processed, from BMP per-peer header
+    static PEER_CLOCK: std::cell::Cell<Option<Timestamp>> = const { std::cell::Cell::new(None) };
+}
+
+/// last time returned by now() in microseconds, shared by all threads, so history records
+/// keep receive order when wall clock steps back
+static LAST_MICROS: AtomicI64 = AtomicI64::new(i64::MIN);
+
+/// serialized values below it are milliseconds from snapshots made before microsecond keys
+const MICROS_MIN: i64 = 100_000_000_000_000;
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Timestamp(DateTime<Local>);
 impl Timestamp {
     pub fn now() -> Self {
-        Timestamp(Local::now())
+        CLOCK.with(|c| c.get()).unwrap_or_else(Timestamp::monotonic)
+    }
+    /// wall clock with microsecond precision, strictly increasing across threads: after
+    /// wall clock steps back time advances by 1us per call until wall clock catches up,
+    /// forward steps are followed at once
+    fn monotonic() -> Self {
+        let wall_us = Timestamp(Local::now()).timestamp_micros();
+        let next = |last: i64| wall_us.max(last.saturating_add(1));
+        let prev = LAST_MICROS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| Some(next(last)))
+            .unwrap_or_else(|last| last);
+        Timestamp::from_micros(next(prev))
+    }
+    /// runs f with now() returning specified time on current thread
+    pub fn with_clock<R, F: FnOnce() -> R>(ts: Timestamp, f: F) -> R {
+        CLOCK.with(|c| c.set(Some(ts)));
+        let r = f();
+        CLOCK.with(|c| c.set(None));
+        r
+    }
+    /// runs f with peer_clock() returning specified time on current thread
+    pub fn with_peer_clock<R, F: FnOnce() -> R>(ts: Option<Timestamp>, f: F) -> R {
+        PEER_CLOCK.with(|c| c.set(ts));
+        let r = f();
+        PEER_CLOCK.with(|c| c.set(None));
+        r
+    }
+    pub fn peer_clock() -> Option<Timestamp> {
+        PEER_CLOCK.with(|c| c.get())
+    }
+    pub fn from_micros(us: i64) -> Self {
+        Timestamp::from_unix(us.div_euclid(1_000_000), us.rem_euclid(1_000_000) as u32)
+    }
+    /// micros above 999999 are clamped, so nanoseconds do not overflow
+    pub fn from_unix(secs: i64, micros: u32) -> Self {
+        Timestamp(match Local.timestamp_opt(secs, micros.min(999_999) * 1000) {
+            LocalResult::Single(dt) => dt,
+            LocalResult::Ambiguous(t1, _) => t1,
+            LocalResult::None => Local::now(),
+        })
     }
     pub fn timestamp_millis(&self) -> i64 {
         self.0.timestamp_millis()
     }
+    pub fn timestamp_micros(&self) -> i64 {
+        self.0.timestamp() * 1_000_000 + self.0.timestamp_subsec_micros() as i64
+    }
 }
 impl std::fmt::Display for Timestamp {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -37,7 +94,13 @@ impl serde::Serialize for Timestamp {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_i64(self.timestamp_millis())
+        // API responses carry milliseconds, json_time_format is applied by response code;
+        // snapshots (CBOR) keep full precision
+        if serializer.is_human_readable() {
+            serializer.serialize_i64(self.timestamp_millis())
+        } else {
+            serializer.serialize_i64(self.timestamp_micros())
+        }
     }
 }
 impl<'de> serde::de::Deserialize<'de> for Timestamp {
@@ -45,12 +108,60 @@ impl<'de> serde::de::Deserialize<'de> for Timestamp {
     where
         D: serde::de::Deserializer<'de>,
     {
-        Ok(Timestamp(
-            match Local.timestamp_millis_opt(i64::deserialize(deserializer)?) {
+        let human = deserializer.is_human_readable();
+        let v = i64::deserialize(deserializer)?;
+        Ok(Timestamp::from_serialized(v, human))
+    }
+}
+impl Timestamp {
+    /// value written by Serialize, for deserializers reading the integer themselves
+    pub fn from_serialized(v: i64, human: bool) -> Timestamp {
+        if human || v.unsigned_abs() < MICROS_MIN as u64 {
+            Timestamp(match Local.timestamp_millis_opt(v) {
                 LocalResult::Single(dt) => dt,
                 LocalResult::Ambiguous(t1, _) => t1,
                 LocalResult::None => Local::now(),
-            },
-        ))
+            })
+        } else {
+            Timestamp::from_micros(v)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clocks() {
+        let a = Timestamp::now();
+        let b = Timestamp::now();
+        assert!(a < b);
+        let ts = Timestamp::from_unix(1700000000, 123456);
+        assert_eq!(ts.timestamp_micros(), 1700000000123456);
+        assert_eq!(
+            Timestamp::from_unix(1700000000, u32::MAX).timestamp_micros(),
+            1700000000999999
+        );
+        assert_eq!(Timestamp::peer_clock(), None);
+        assert_eq!(
+            Timestamp::with_peer_clock(Some(ts), Timestamp::peer_clock),
+            Some(ts)
+        );
+        assert_eq!(Timestamp::peer_clock(), None);
+    }
+    #[test]
+    fn test_timestamp_serde() {
+        let ts = Timestamp::from_unix(1700000000, 123456);
+        assert_eq!(serde_json::to_string(&ts).unwrap(), "1700000000123");
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(&ts, &mut buf).unwrap();
+        let back: Timestamp = ciborium::de::from_reader(buf.as_slice()).unwrap();
+        assert_eq!(back, ts);
+        // snapshot written with millisecond keys
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(&1700000000123i64, &mut buf).unwrap();
+        let old: Timestamp = ciborium::de::from_reader(buf.as_slice()).unwrap();
+        assert_eq!(old.timestamp_micros(), 1700000000123000);
     }
 }
@@ -0,0 +1,31 @@
+//! TLS client streams for alert sinks, available with tls feature
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub trait IoStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IoStream for T {}
+
+/// plain or TLS stream
+pub type BoxStream = Box<dyn IoStream>;
+
+/// host part of host:port for certificate verification
+pub fn host_of(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map(|x| x.0).unwrap_or(addr);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// wraps established stream into TLS session
+#[cfg(feature = "tls")]
+pub async fn connect(host: &str, stream: BoxStream) -> std::io::Result<BoxStream> {
+    let connector = native_tls::TlsConnector::new().map_err(std::io::Error::other)?;
+    let connector = tokio_native_tls::TlsConnector::from(connector);
+    let stream = connector
+        .connect(host, stream)
+        .await
+        .map_err(std::io::Error::other)?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(feature = "tls"))]
+pub async fn connect(_host: &str, _stream: BoxStream) -> std::io::Result<BoxStream> {
+    Err(std::io::Error::other("built without tls feature"))
+}
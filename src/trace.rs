@@ -0,0 +1,112 @@
+//! per-message protocol tracing, costs one atomic load per message when disabled
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static RING_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref RING: Mutex<VecDeque<TraceRecord>> = Mutex::new(VecDeque::new());
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceRecord {
+    pub time: Timestamp,
+    pub proto: &'static str,
+    /// in/out
+    pub dir: &'static str,
+    pub peer: IpAddr,
+    pub msgtype: String,
+    /// whole message length including header
+    pub length: usize,
+    pub hex: String,
+}
+
+#[derive(Serialize)]
+pub struct TraceDump {
+    pub enabled: bool,
+    pub buffer: usize,
+    pub records: Vec<TraceRecord>,
+}
+
+/// enables tracing, ring keeps last ring_size messages with hexdump
+pub fn init(enabled: bool, ring_size: usize) {
+    RING_SIZE.store(ring_size, Ordering::Relaxed);
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// logs message and keeps it in ring, callers should check enabled() first
+pub fn record(
+    proto: &'static str,
+    dir: &'static str,
+    peer: IpAddr,
+    msgtype: &str,
+    length: usize,
+    data: &[u8],
+) {
+    info!(
+        "trace {} {} {} {} length {}",
+        proto, dir, peer, msgtype, length
+    );
+    let ring_size = RING_SIZE.load(Ordering::Relaxed);
+    if ring_size == 0 {
+        return;
+    }
+    let rec = TraceRecord {
+        time: Timestamp::now(),
+        proto,
+        dir,
+        peer,
+        msgtype: msgtype.to_string(),
+        length,
+        hex: crate::rawupdates::hex(data),
+    };
+    let mut ring = RING.lock().unwrap();
+    while ring.len() >= ring_size {
+        ring.pop_front();
+    }
+    ring.push_back(rec);
+}
+
+/// BGP message type name from full message with header
+pub fn bgp_msgtype(msg: &[u8]) -> &'static str {
+    match msg.get(18) {
+        Some(1) => "Open",
+        Some(2) => "Update",
+        Some(3) => "Notification",
+        Some(4) => "Keepalive",
+        Some(5) => "RouteRefresh",
+        _ => "Unknown",
+    }
+}
+
+/// BMP message type name
+pub fn bmp_msgtype(t: u8) -> &'static str {
+    match t {
+        0 => "RouteMonitoring",
+        1 => "StatisticsReport",
+        2 => "PeerDown",
+        3 => "PeerUp",
+        4 => "Initiation",
+        5 => "Termination",
+        6 => "RouteMirroring",
+        _ => "Unknown",
+    }
+}
+
+pub fn dump() -> TraceDump {
+    TraceDump {
+        enabled: enabled(),
+        buffer: RING_SIZE.load(Ordering::Relaxed),
+        records: RING.lock().unwrap().iter().cloned().collect(),
+    }
+}
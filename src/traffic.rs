@@ -0,0 +1,307 @@
+//! per session traffic accounting: messages and bytes received and sent over BGP sessions
+//! and BMP feeds (per router and per monitored peer) by message type, BMP route monitoring is counted by type of BGP message it
+//! carries, so UPDATE and KEEPALIVE volume can be told apart when sizing BMP export
+use crate::bgpsvc::BgpSessionId;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// type of BMP v3 message, route monitoring by type of BGP message it carries
+pub fn bmp_msgtype(msg: &[u8]) -> &'static str {
+    match msg.first() {
+        // type byte, 42 bytes of per-peer header, BGP message
+        Some(0) => match msg.get(43 + 18) {
+            Some(t) => crate::protostats::msgtype_name(*t),
+            None => "unknown",
+        },
+        Some(1) => "statistics_report",
+        Some(2) => "peer_down",
+        Some(3) => "peer_up",
+        Some(4) => "initiation",
+        Some(5) => "termination",
+        Some(6) => "route_mirroring",
+        _ => "unknown",
+    }
+}
+
+/// message types counted, bgp ones first
+const TYPES: [&str; 12] = [
+    "open",
+    "update",
+    "notification",
+    "keepalive",
+    "route_refresh",
+    "statistics_report",
+    "peer_down",
+    "peer_up",
+    "initiation",
+    "termination",
+    "route_mirroring",
+    "unknown",
+];
+
+fn type_index(msgtype: &str) -> usize {
+    TYPES
+        .iter()
+        .position(|t| *t == msgtype)
+        .unwrap_or(TYPES.len() - 1)
+}
+
+fn now_micros() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+/// monitored peer of BMP message with per-peer header (route monitoring, statistics
+/// report, peer down and up)
+pub fn bmp_monitored(msg: &[u8]) -> Option<IpAddr> {
+    match msg.first() {
+        Some(0..=3) => zettabgp::bmp::prelude::BmpMessagePeerHeader::decode_from(msg.get(1..)?)
+            .ok()
+            .map(|(p, _)| p.peeraddress),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct TrafficCounter {
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+/// counters of one direction by message type
+#[derive(Default)]
+struct Counters {
+    messages: [AtomicU64; TYPES.len()],
+    bytes: [AtomicU64; TYPES.len()],
+}
+impl Counters {
+    fn by_type(&self) -> BTreeMap<&'static str, TrafficCounter> {
+        TYPES
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let c = TrafficCounter {
+                    messages: self.messages[i].load(Ordering::Relaxed),
+                    bytes: self.bytes[i].load(Ordering::Relaxed),
+                };
+                (*t, c)
+            })
+            .filter(|(_, c)| c.messages > 0)
+            .collect()
+    }
+}
+
+/// counters of one BGP session or BMP feed, updated by its connection without locking
+pub struct SessionTraffic {
+    id: u64,
+    proto: &'static str,
+    peer: IpAddr,
+    monitored: Option<IpAddr>,
+    /// session id once session is registered, 0 before
+    session: AtomicU32,
+    first: Timestamp,
+    last: AtomicI64,
+    received: Counters,
+    sent: Counters,
+}
+impl SessionTraffic {
+    pub fn record(&self, sent: bool, msgtype: &str, bytes: usize) {
+        let i = type_index(msgtype);
+        let c = if sent { &self.sent } else { &self.received };
+        c.messages[i].fetch_add(1, Ordering::Relaxed);
+        c.bytes[i].fetch_add(bytes as u64, Ordering::Relaxed);
+        self.last.store(now_micros(), Ordering::Relaxed);
+    }
+    pub fn set_session(&self, sid: BgpSessionId) {
+        self.session.store(sid as u32, Ordering::Relaxed);
+    }
+    fn view(&self) -> SessionTrafficView {
+        let received_by_type = self.received.by_type();
+        let sent_by_type = self.sent.by_type();
+        let total = |m: &BTreeMap<&'static str, TrafficCounter>| {
+            m.values()
+                .fold(TrafficCounter::default(), |a, c| TrafficCounter {
+                    messages: a.messages + c.messages,
+                    bytes: a.bytes + c.bytes,
+                })
+        };
+        let last = self.last.load(Ordering::Relaxed);
+        SessionTrafficView {
+            proto: self.proto,
+            peer: self.peer,
+            monitored: self.monitored,
+            session: match self.session.load(Ordering::Relaxed) {
+                0 => None,
+                s => Some(s as BgpSessionId),
+            },
+            first: self.first,
+            last: Timestamp::from_unix(
+                last.div_euclid(1_000_000),
+                last.rem_euclid(1_000_000) as u32,
+            ),
+            received: total(&received_by_type),
+            sent: total(&sent_by_type),
+            received_by_type,
+            sent_by_type,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionTrafficView {
+    /// bgp or bmp
+    pub proto: &'static str,
+    /// BGP peer or BMP router
+    pub peer: IpAddr,
+    /// peer monitored by BMP router, None for BGP session and BMP messages of router itself
+    pub monitored: Option<IpAddr>,
+    pub session: Option<BgpSessionId>,
+    pub first: Timestamp,
+    pub last: Timestamp,
+    pub received: TrafficCounter,
+    pub sent: TrafficCounter,
+    /// message type to counters
+    pub received_by_type: BTreeMap<&'static str, TrafficCounter>,
+    pub sent_by_type: BTreeMap<&'static str, TrafficCounter>,
+}
+
+/// counters of live sessions, started with session connection or BMP peer up and dropped
+/// when it ends, so reconnected session starts from zero
+#[derive(Default)]
+pub struct TrafficStats {
+    next: AtomicU64,
+    sessions: Mutex<BTreeMap<u64, Arc<SessionTraffic>>>,
+}
+impl TrafficStats {
+    pub fn start(
+        &self,
+        proto: &'static str,
+        peer: IpAddr,
+        monitored: Option<IpAddr>,
+    ) -> Arc<SessionTraffic> {
+        let now = now_micros();
+        let t = Arc::new(SessionTraffic {
+            id: self.next.fetch_add(1, Ordering::Relaxed),
+            proto,
+            peer,
+            monitored,
+            session: AtomicU32::new(0),
+            first: Timestamp::from_unix(
+                now.div_euclid(1_000_000),
+                now.rem_euclid(1_000_000) as u32,
+            ),
+            last: AtomicI64::new(now),
+            received: Default::default(),
+            sent: Default::default(),
+        });
+        self.sessions.lock().unwrap().insert(t.id, t.clone());
+        t
+    }
+    pub fn end(&self, t: &SessionTraffic) {
+        self.sessions.lock().unwrap().remove(&t.id);
+    }
+    pub fn report(&self) -> Vec<SessionTrafficView> {
+        let sessions: Vec<Arc<SessionTraffic>> =
+            self.sessions.lock().unwrap().values().cloned().collect();
+        let mut ret: Vec<SessionTrafficView> = sessions.iter().map(|s| s.view()).collect();
+        ret.sort_by(|a, b| (a.proto, a.peer, a.monitored).cmp(&(b.proto, b.peer, b.monitored)));
+        ret
+    }
+    pub fn write_prometheus(&self, out: &mut String) {
+        let sessions = self.report();
+        for (name, help, bytes) in [
+            (
+                "bgpexplorer_session_messages_total",
+                "BGP/BMP messages received and sent per session by message type",
+                false,
+            ),
+            (
+                "bgpexplorer_session_bytes_total",
+                "BGP/BMP bytes received and sent per session by message type",
+                true,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for s in sessions.iter() {
+                let monitored = s.monitored.map(|m| m.to_string()).unwrap_or_default();
+                for (dir, by_type) in [("in", &s.received_by_type), ("out", &s.sent_by_type)] {
+                    for (t, c) in by_type.iter() {
+                        let _ = writeln!(
+                            out,
+                            "{}{{proto=\"{}\",peer=\"{}\",monitored=\"{}\",direction=\"{}\",type=\"{}\"}} {}",
+                            name,
+                            s.proto,
+                            s.peer,
+                            monitored,
+                            dir,
+                            t,
+                            if bytes { c.bytes } else { c.messages }
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traffic_stats() {
+        let st = TrafficStats::default();
+        let router: IpAddr = "192.0.2.1".parse().unwrap();
+        let peer: IpAddr = "198.51.100.1".parse().unwrap();
+        let bmp = st.start("bmp", router, Some(peer));
+        bmp.record(false, "update", 120);
+        bmp.record(false, "update", 80);
+        bmp.record(false, "keepalive", 19);
+        let bgp = st.start("bgp", router, None);
+        bgp.record(true, "keepalive", 19);
+        bgp.set_session(3);
+        let r = st.report();
+        assert_eq!(r.len(), 2);
+        let b = r.iter().find(|s| s.proto == "bmp").unwrap();
+        assert_eq!(b.monitored, Some(peer));
+        assert_eq!(b.received.messages, 3);
+        assert_eq!(b.received.bytes, 219);
+        assert_eq!(b.received_by_type["update"].bytes, 200);
+        assert_eq!(b.sent.messages, 0);
+        assert_eq!(
+            r.iter().find(|s| s.proto == "bgp").unwrap().session,
+            Some(3)
+        );
+        let mut out = String::new();
+        st.write_prometheus(&mut out);
+        assert!(out.contains(
+            "bgpexplorer_session_bytes_total{proto=\"bmp\",peer=\"192.0.2.1\",monitored=\"198.51.100.1\",direction=\"in\",type=\"update\"} 200\n"
+        ));
+        assert!(out.contains(
+            "bgpexplorer_session_messages_total{proto=\"bgp\",peer=\"192.0.2.1\",monitored=\"\",direction=\"out\",type=\"keepalive\"} 1\n"
+        ));
+        let mut rm = vec![0u8; 43 + 19];
+        rm[43 + 18] = 4;
+        assert_eq!(bmp_msgtype(&rm), "keepalive");
+        assert_eq!(bmp_msgtype(&[3]), "peer_up");
+        // ended session is dropped, reconnected one starts from zero
+        st.end(&bgp);
+        assert_eq!(st.report().len(), 1);
+        let bgp = st.start("bgp", router, None);
+        let r = st.report();
+        let b = r.iter().find(|s| s.proto == "bgp").unwrap();
+        assert_eq!(b.sent.messages, 0);
+        assert_eq!(b.session, None);
+        st.end(&bgp);
+        st.end(&bmp);
+        assert!(st.report().is_empty());
+    }
+}
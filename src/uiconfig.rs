@@ -0,0 +1,65 @@
+//! Web UI branding and theme served to the frontend at /api/ui/config
+use crate::config::ErrorConfig;
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UiTheme {
+    /// follows browser prefers-color-scheme
+    #[default]
+    Auto,
+    Light,
+    Dark,
+}
+impl FromStr for UiTheme {
+    type Err = ErrorConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "auto" => Ok(UiTheme::Auto),
+            "light" => Ok(UiTheme::Light),
+            "dark" => Ok(UiTheme::Dark),
+            _ => Err(ErrorConfig::from_str("invalid theme")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UiSettings {
+    pub theme: UiTheme,
+    /// page title and header text, frontend default if None
+    pub brand_name: Option<String>,
+    pub logo_url: Option<String>,
+    /// extra stylesheet loaded after built-in styles
+    pub stylesheet_url: Option<String>,
+    /// RIB and filter shown when URL has none
+    pub default_rib: Option<String>,
+    pub default_filter: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ui_settings() {
+        assert_eq!("dark".parse::<UiTheme>().unwrap(), UiTheme::Dark);
+        assert!("blue".parse::<UiTheme>().is_err());
+        let s = UiSettings {
+            brand_name: Some("AS64500 looking glass".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&s).unwrap(),
+            serde_json::json!({
+                "theme": "auto",
+                "brand_name": "AS64500 looking glass",
+                "logo_url": null,
+                "stylesheet_url": null,
+                "default_rib": null,
+                "default_filter": null
+            })
+        );
+    }
+}
@@ -0,0 +1,382 @@
+//! named views: saved route queries defined by `[view:name]` ini sections or created via
+//! /api/views admin API, served at /api/views/<name> as /api/json over view's RIB
+//! with view's query and filter, responses are cached per view for its cache time
+use crate::bgprib::BgpRibKind;
+use crate::config::ErrorConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// cached responses of one view (different paging, scopes)
+pub const VIEW_CACHE_ENTRIES: usize = 16;
+/// bytes of cached responses of all views, oldest are dropped over it
+pub const VIEW_CACHE_BYTES: usize = 64 * 1024 * 1024;
+/// larger responses are not cached
+pub const VIEW_CACHE_MAX_BODY: usize = 8 * 1024 * 1024;
+
+fn default_cache() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ViewConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub rib: String,
+    /// route query language expression
+    #[serde(default)]
+    pub q: String,
+    /// textual filter as in /api/json filter parameter
+    #[serde(default)]
+    pub filter: String,
+    /// seconds responses are cached, 0 - not cached
+    #[serde(default = "default_cache")]
+    pub cache: u64,
+    /// defined in config file, can not be changed with API
+    #[serde(default, skip_deserializing)]
+    pub configured: bool,
+}
+impl ViewConfig {
+    pub fn from_ini(
+        name: &str,
+        section: &HashMap<String, Option<String>>,
+    ) -> Result<ViewConfig, ErrorConfig> {
+        let get = |key: &str| match section.get(key) {
+            Some(Some(s)) => s.trim().to_string(),
+            _ => String::new(),
+        };
+        let ret = ViewConfig {
+            name: name.to_string(),
+            description: get("description"),
+            rib: match get("rib") {
+                s if s.is_empty() => "ipv4u".to_string(),
+                s => s,
+            },
+            q: get("q"),
+            filter: get("filter"),
+            cache: match section.get("cache") {
+                Some(Some(s)) => crate::config::parse_duration(s)
+                    .map_err(|e| {
+                        ErrorConfig::from_string(format!("Invalid cache for view:{} - {}", name, e))
+                    })?
+                    .as_secs(),
+                _ => default_cache(),
+            },
+            configured: true,
+        };
+        ret.validate()
+            .map_err(|e| ErrorConfig::from_string(format!("Invalid view:{} - {}", name, e)))?;
+        Ok(ret)
+    }
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        {
+            return Err(format!("invalid view name '{}'", self.name));
+        }
+        if self.rib.parse::<BgpRibKind>().is_err() {
+            return Err(format!("unknown rib {}", self.rib));
+        }
+        if !self.q.is_empty() {
+            crate::querydsl::parse(self.q.as_str())?;
+        }
+        if let Some(c) = crate::ribfilter::RouteFilter::unknown_community(self.filter.as_str()) {
+            return Err(format!("unknown community {} in filter", c));
+        }
+        Ok(())
+    }
+    /// /api/json URI of view with client's parameters, view's query and filter win
+    pub fn json_uri(&self, query: Option<&str>) -> String {
+        let mut s = url::form_urlencoded::Serializer::new(String::new());
+        for (k, v) in url::form_urlencoded::parse(query.unwrap_or("").as_bytes()) {
+            if k != "q" && k != "filter" {
+                s.append_pair(&k, &v);
+            }
+        }
+        if !self.q.is_empty() {
+            s.append_pair("q", &self.q);
+        }
+        if !self.filter.is_empty() {
+            s.append_pair("filter", &self.filter);
+        }
+        format!("/api/json/{}?{}", self.rib, s.finish())
+    }
+}
+
+/// views from sections named view:<name>
+pub fn parse_views(
+    conf: &HashMap<String, HashMap<String, Option<String>>>,
+) -> Result<Vec<ViewConfig>, ErrorConfig> {
+    let mut ret = Vec::new();
+    for (name, section) in conf.iter() {
+        if let Some(n) = name.strip_prefix("view:") {
+            ret.push(ViewConfig::from_ini(n, section)?);
+        }
+    }
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(ret)
+}
+
+struct CachedResponse {
+    key: String,
+    built: Instant,
+    body: hyper::body::Bytes,
+}
+
+pub struct Views {
+    file: Option<String>,
+    views: BTreeMap<String, ViewConfig>,
+    cache: HashMap<String, VecDeque<CachedResponse>>,
+    cache_bytes: usize,
+}
+impl Views {
+    /// configured views and ones created via API restored from file, unreadable file is
+    /// ignored with warning
+    pub fn new(cfg: &[ViewConfig], file: Option<String>) -> Views {
+        let mut ret = Views {
+            file,
+            views: cfg.iter().map(|v| (v.name.clone(), v.clone())).collect(),
+            cache: HashMap::new(),
+            cache_bytes: 0,
+        };
+        let stored: Vec<ViewConfig> = match ret.file.as_ref() {
+            None => Vec::new(),
+            Some(f) => match std::fs::read_to_string(f) {
+                Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                    warn!("Views file {}: {}", f, e);
+                    Vec::new()
+                }),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => {
+                    warn!("Views file {}: {}", f, e);
+                    Vec::new()
+                }
+            },
+        };
+        for v in stored.into_iter() {
+            match v.validate() {
+                Ok(_) if ret.views.contains_key(&v.name) => {
+                    warn!(
+                        "View {} is defined in config, stored one is ignored",
+                        v.name
+                    )
+                }
+                Ok(_) => {
+                    ret.views.insert(v.name.clone(), v);
+                }
+                Err(e) => warn!("View {}: {}", v.name, e),
+            }
+        }
+        ret
+    }
+    /// views file name and its contents with views created via API, None without file;
+    /// written with write_file out of lock of Views
+    pub fn stored(&self) -> Result<Option<(String, Vec<u8>)>, String> {
+        let f = match self.file.as_ref() {
+            None => return Ok(None),
+            Some(f) => f,
+        };
+        let api: Vec<&ViewConfig> = self.views.values().filter(|v| !v.configured).collect();
+        serde_json::to_vec_pretty(&api)
+            .map(|b| Some((f.clone(), b)))
+            .map_err(|e| format!("Views file {}: {}", f, e))
+    }
+    /// replaces views file with data through temporary file
+    pub fn write_file(f: &str, data: &[u8]) -> Result<(), String> {
+        let tmp = format!("{}.tmp", f);
+        std::fs::write(&tmp, data)
+            .and_then(|_| std::fs::rename(&tmp, f))
+            .map_err(|e| format!("Views file {}: {}", f, e))
+    }
+    /// views visible to scope: all for unrestricted one, ones over allowed RIBs without
+    /// their query and filter for restricted one
+    pub fn list(&self, scope: Option<&crate::tenancy::ApiScope>) -> Vec<ViewConfig> {
+        match scope.filter(|s| s.is_restricted()) {
+            None => self.views.values().cloned().collect(),
+            Some(s) => self
+                .views
+                .values()
+                .filter(|v| s.allows_rib(v.rib.as_str()))
+                .map(|v| ViewConfig {
+                    q: String::new(),
+                    filter: String::new(),
+                    ..v.clone()
+                })
+                .collect(),
+        }
+    }
+    pub fn get(&self, name: &str) -> Option<ViewConfig> {
+        self.views.get(name).cloned()
+    }
+    /// creates or replaces view, returns whether it is new; views file is not written
+    pub fn put(&mut self, mut view: ViewConfig) -> Result<bool, String> {
+        view.configured = false;
+        view.validate()?;
+        let prev = self.views.get(&view.name).cloned();
+        if prev.as_ref().map(|v| v.configured).unwrap_or(false) {
+            return Err(format!("view {} is defined in config", view.name));
+        }
+        let name = view.name.clone();
+        self.views.insert(name.clone(), view);
+        self.drop_cached(&name);
+        Ok(prev.is_none())
+    }
+    /// drops view created via API, returns whether it existed; views file is not written
+    pub fn remove(&mut self, name: &str) -> Result<bool, String> {
+        match self.views.get(name) {
+            None => return Ok(false),
+            Some(v) if v.configured => return Err(format!("view {} is defined in config", name)),
+            Some(_) => {}
+        }
+        self.views.remove(name);
+        self.drop_cached(name);
+        Ok(true)
+    }
+    /// puts back view state from before put or remove which could not be stored
+    pub fn restore(&mut self, name: &str, prev: Option<ViewConfig>) {
+        match prev {
+            Some(v) => self.views.insert(name.to_string(), v),
+            None => self.views.remove(name),
+        };
+        self.drop_cached(name);
+    }
+    fn drop_cached(&mut self, name: &str) {
+        if let Some(c) = self.cache.remove(name) {
+            self.cache_bytes -= c.iter().map(|c| c.body.len()).sum::<usize>();
+        }
+    }
+    /// cached response of view for key if younger than view's cache time
+    pub fn cached(&self, name: &str, key: &str, now: Instant) -> Option<hyper::body::Bytes> {
+        let ttl = Duration::from_secs(self.views.get(name)?.cache);
+        self.cache
+            .get(name)?
+            .iter()
+            .find(|c| c.key == key && now.duration_since(c.built) < ttl)
+            .map(|c| c.body.clone())
+    }
+    /// keeps response, oldest responses of all views are dropped over VIEW_CACHE_BYTES
+    pub fn store_cached(&mut self, name: &str, key: &str, body: hyper::body::Bytes, now: Instant) {
+        match self.views.get(name) {
+            Some(v) if v.cache > 0 && body.len() <= VIEW_CACHE_MAX_BODY => {}
+            _ => return,
+        }
+        let c = self.cache.entry(name.to_string()).or_default();
+        let mut freed = 0;
+        c.retain(|c| {
+            if c.key == key {
+                freed += c.body.len();
+            }
+            c.key != key
+        });
+        self.cache_bytes += body.len();
+        c.push_back(CachedResponse {
+            key: key.to_string(),
+            built: now,
+            body,
+        });
+        while c.len() > VIEW_CACHE_ENTRIES {
+            freed += c.pop_front().map(|c| c.body.len()).unwrap_or(0);
+        }
+        self.cache_bytes -= freed;
+        while self.cache_bytes > VIEW_CACHE_BYTES {
+            let oldest = self
+                .cache
+                .iter()
+                .filter_map(|(n, c)| c.front().map(|r| (r.built, n.clone())))
+                .min();
+            let c = match oldest.and_then(|(_, n)| self.cache.get_mut(&n)) {
+                Some(c) => c,
+                None => break,
+            };
+            self.cache_bytes -= c.pop_front().map(|c| c.body.len()).unwrap_or(0);
+        }
+        self.cache.retain(|_, c| !c.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_views() {
+        let mut sect = HashMap::new();
+        sect.insert("rib".to_string(), Some("ipv6u".to_string()));
+        sect.insert("q".to_string(), Some("origin == 64496".to_string()));
+        sect.insert("cache".to_string(), Some("5m".to_string()));
+        let cone = ViewConfig::from_ini("customer-cone-v6", &sect).unwrap();
+        assert_eq!(cone.cache, 300);
+        assert_eq!(
+            cone.json_uri(Some("limit=10&q=x&token=t")),
+            "/api/json/ipv6u?limit=10&token=t&q=origin+%3D%3D+64496"
+        );
+        sect.insert("q".to_string(), Some("origin > 1".to_string()));
+        assert!(ViewConfig::from_ini("bad", &sect).is_err());
+        let mut views = Views::new(&[cone.clone()], None);
+        assert!(views.put(cone.clone()).is_err());
+        let mut v = cone.clone();
+        v.name = "blackholed".to_string();
+        v.q = "community has blackhole".to_string();
+        assert_eq!(views.put(v.clone()), Ok(true));
+        assert_eq!(views.put(v), Ok(false));
+        assert_eq!(views.list(None).len(), 2);
+        let now = Instant::now();
+        views.store_cached("blackholed", "a", hyper::body::Bytes::from("{}"), now);
+        assert!(views.cached("blackholed", "a", now).is_some());
+        assert!(views.cached("blackholed", "b", now).is_none());
+        assert!(views
+            .cached("blackholed", "a", now + Duration::from_secs(301))
+            .is_none());
+        assert!(views.remove("customer-cone-v6").is_err());
+        assert_eq!(views.remove("blackholed"), Ok(true));
+        assert!(views.cached("blackholed", "a", now).is_none());
+        assert_eq!(views.cache_bytes, 0);
+        views.restore("blackholed", Some(cone.clone()));
+        assert!(views.get("blackholed").is_some());
+        views.restore("blackholed", None);
+        assert!(views.get("blackholed").is_none());
+        let mut bad = cone.clone();
+        bad.name = "bad".to_string();
+        bad.filter = "community:nosuchcommunity".to_string();
+        assert!(views.put(bad).is_err());
+    }
+
+    #[test]
+    fn test_views_cache_bytes() {
+        let mut views = Views::new(&[], None);
+        let v = |n: &str| ViewConfig {
+            name: n.to_string(),
+            description: String::new(),
+            rib: "ipv4u".to_string(),
+            q: String::new(),
+            filter: String::new(),
+            cache: 60,
+            configured: false,
+        };
+        views.put(v("a")).unwrap();
+        views.put(v("b")).unwrap();
+        let now = Instant::now();
+        let body = hyper::body::Bytes::from(vec![b' '; VIEW_CACHE_MAX_BODY]);
+        let big = hyper::body::Bytes::from(vec![b' '; VIEW_CACHE_MAX_BODY + 1]);
+        views.store_cached("a", "x", big, now);
+        assert!(views.cached("a", "x", now).is_none());
+        for i in 0..VIEW_CACHE_BYTES / VIEW_CACHE_MAX_BODY {
+            let n = if i % 2 == 0 { "a" } else { "b" };
+            let t = now + Duration::from_millis(i as u64);
+            views.store_cached(n, &i.to_string(), body.clone(), t);
+        }
+        assert_eq!(views.cache_bytes, VIEW_CACHE_BYTES);
+        views.store_cached("b", "new", body.clone(), now + Duration::from_secs(1));
+        assert_eq!(views.cache_bytes, VIEW_CACHE_BYTES);
+        // oldest response, of view a, is dropped
+        assert!(views.cached("a", "0", now).is_none());
+        assert!(views.cached("b", "1", now).is_some());
+        views.store_cached("b", "new", body, now + Duration::from_secs(1));
+        assert_eq!(views.cache_bytes, VIEW_CACHE_BYTES);
+    }
+}
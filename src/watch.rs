@@ -0,0 +1,196 @@
+//! watchlist matching shared by alert sinks
+use crate::bgpattrs::BgpAttrs;
+use crate::bgprib::BgpEvent;
+use crate::bgpsvc::BgpSessionId;
+use crate::inbound::PrefixList;
+use crate::ribfilter::{FilterItemMatchResult, RouteFilter};
+use crate::script::RoutePrefix;
+use std::collections::BTreeSet;
+use zettabgp::prelude::*;
+
+/// routes of withdraw burst named in its alert, rest are counted
+const BATCH_ROUTES_SHOWN: usize = 10;
+
+/// watched route event
+#[derive(Debug, Clone)]
+pub struct WatchAlert {
+    pub session: BgpSessionId,
+    /// route, or first routes of withdraw burst
+    pub route: String,
+    /// announced/withdrawn
+    pub kind: &'static str,
+    /// watched routes of alert, more than 1 for withdraw burst
+    pub count: usize,
+}
+impl std::fmt::Display for WatchAlert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.count > 1 {
+            return write!(
+                f,
+                "{} watched routes {} by session in withdraw burst {}: {}",
+                self.count, self.kind, self.session, self.route
+            );
+        }
+        write!(
+            f,
+            "watched route {} {} by session {}",
+            self.route, self.kind, self.session
+        )
+    }
+}
+
+/// ipv4u/ipv6u routes from event that match watchlist filter
+pub fn match_event(watch: &RouteFilter, evt: &BgpEvent) -> Vec<WatchAlert> {
+    let noattrs = BgpAttrs::new();
+    let (sid, kind, attrs, addrs) = match evt {
+        BgpEvent::Update(sid, attrs, addrs) => (*sid, "announced", attrs.as_ref(), addrs),
+        BgpEvent::Withdraw(sid, addrs) => (*sid, "withdrawn", &noattrs, addrs),
+        BgpEvent::WithdrawBatch(sid, batch) => {
+            // one alert for all watched routes of batch
+            let routes: Vec<String> = batch
+                .addrs
+                .iter()
+                .flat_map(|a| match_event(watch, &BgpEvent::Withdraw(*sid, a.clone())))
+                .map(|a| a.route)
+                .collect();
+            if routes.is_empty() {
+                return Vec::new();
+            }
+            let mut route = routes[..routes.len().min(BATCH_ROUTES_SHOWN)].join(", ");
+            if routes.len() > BATCH_ROUTES_SHOWN {
+                route.push_str(&format!(" and {} more", routes.len() - BATCH_ROUTES_SHOWN));
+            }
+            return vec![WatchAlert {
+                session: *sid,
+                route,
+                kind: "withdrawn",
+                count: routes.len(),
+            }];
+        }
+    };
+    let mut routes: Vec<String> = Vec::new();
+    match addrs.as_ref() {
+        BgpAddrs::IPV4U(v) => routes.extend(
+            v.iter()
+                .filter(|r| watch.match_route(*r, attrs) == FilterItemMatchResult::Yes)
+                .map(|r| r.to_string()),
+        ),
+        BgpAddrs::IPV4UP(v) => routes.extend(
+            v.iter()
+                .filter(|r| watch.match_route(&r.nlri, attrs) == FilterItemMatchResult::Yes)
+                .map(|r| r.nlri.to_string()),
+        ),
+        BgpAddrs::IPV6U(v) => routes.extend(
+            v.iter()
+                .filter(|r| watch.match_route(*r, attrs) == FilterItemMatchResult::Yes)
+                .map(|r| r.to_string()),
+        ),
+        BgpAddrs::IPV6UP(v) => routes.extend(
+            v.iter()
+                .filter(|r| watch.match_route(&r.nlri, attrs) == FilterItemMatchResult::Yes)
+                .map(|r| r.nlri.to_string()),
+        ),
+        _ => {}
+    }
+    routes
+        .into_iter()
+        .map(|route| WatchAlert {
+            session: sid,
+            route,
+            kind,
+            count: 1,
+        })
+        .collect()
+}
+
+/// announcement of protected prefix from unexpected origin
+#[derive(Debug, Clone)]
+pub struct HijackAlert {
+    pub session: BgpSessionId,
+    pub route: String,
+    /// origin AS, None for empty AS path
+    pub origin: Option<u32>,
+}
+impl std::fmt::Display for HijackAlert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.origin {
+            Some(asn) => write!(
+                f,
+                "possible hijack: route {} originated by AS{} announced by session {}",
+                self.route, asn, self.session
+            ),
+            None => write!(
+                f,
+                "possible hijack: route {} with empty AS path announced by session {}",
+                self.route, self.session
+            ),
+        }
+    }
+}
+
+/// ipv4u/ipv6u announcements of routes equal to or more specific than protected
+/// prefixes whose origin is not one of expected origins
+pub fn match_hijack(
+    prefixes: &PrefixList,
+    origins: &BTreeSet<u32>,
+    evt: &BgpEvent,
+) -> Vec<HijackAlert> {
+    let (sid, attrs, addrs) = match evt {
+        BgpEvent::Update(sid, attrs, addrs) => (*sid, attrs, addrs),
+        _ => return Vec::new(),
+    };
+    let origin = attrs.aspath.value.last().map(|x| x.value);
+    if origin.map(|o| origins.contains(&o)).unwrap_or(false) {
+        return Vec::new();
+    }
+    let routes: Vec<RoutePrefix> = match addrs.as_ref() {
+        BgpAddrs::IPV4U(v) => v.iter().map(|r| RoutePrefix::V4(r.clone())).collect(),
+        BgpAddrs::IPV4UP(v) => v.iter().map(|r| RoutePrefix::V4(r.nlri.clone())).collect(),
+        BgpAddrs::IPV6U(v) => v.iter().map(|r| RoutePrefix::V6(r.clone())).collect(),
+        BgpAddrs::IPV6UP(v) => v.iter().map(|r| RoutePrefix::V6(r.nlri.clone())).collect(),
+        _ => Vec::new(),
+    };
+    routes
+        .into_iter()
+        .filter(|p| prefixes.covers(p))
+        .map(|p| HijackAlert {
+            session: sid,
+            route: p.to_string(),
+            origin,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::CompactASPath;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_match_hijack() {
+        let prefixes = PrefixList::parse("192.0.2.0/24").unwrap();
+        let origins: BTreeSet<u32> = [64500].iter().cloned().collect();
+        let upd = |path: Vec<u32>, net: BgpAddrV4| {
+            let mut attrs = BgpAttrs::new();
+            attrs.aspath = Arc::new(CompactASPath::from(path));
+            BgpEvent::Update(1, Arc::new(attrs), Arc::new(BgpAddrs::IPV4U(vec![net])))
+        };
+        let own = BgpAddrV4::new(Ipv4Addr::new(192, 0, 2, 0), 24);
+        let more = BgpAddrV4::new(Ipv4Addr::new(192, 0, 2, 128), 25);
+        let other = BgpAddrV4::new(Ipv4Addr::new(198, 51, 100, 0), 24);
+        assert!(
+            match_hijack(&prefixes, &origins, &upd(vec![64510, 64500], own.clone())).is_empty()
+        );
+        assert!(match_hijack(&prefixes, &origins, &upd(vec![64510, 64666], other)).is_empty());
+        let a = match_hijack(&prefixes, &origins, &upd(vec![64510, 64666], more));
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].origin, Some(64666));
+        assert_eq!(a[0].route, "192.0.2.128/25");
+        assert_eq!(
+            match_hijack(&prefixes, &origins, &upd(vec![], own)).len(),
+            1
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::coalesce::Coalescer;
 use crate::config::*;
 use crate::*;
 use chrono::prelude::*;
@@ -203,6 +204,22 @@ impl From<WhoisRec> for sled::IVec {
         serde_json::to_vec(&sv).unwrap().into()
     }
 }
+/// AS name of whois AS object: as-name, or descr/orgname when it is missing
+pub fn as_name(whois: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE_ASNAME: Regex =
+            Regex::new(r"(?im)^(as-name|asname|descr|orgname):\s*(.+)$").unwrap();
+    }
+    let names: Vec<(String, &str)> = RE_ASNAME
+        .captures_iter(whois)
+        .filter_map(|c| Some((c.get(1)?.as_str().to_lowercase(), c.get(2)?.as_str().trim())))
+        .collect();
+    names
+        .iter()
+        .find(|(k, _)| k == "as-name" || k == "asname")
+        .or_else(|| names.first())
+        .map(|(_, v)| v.to_string())
+}
 pub struct WhoisSvr {
     whs: WhoIs,
     dns: Vec<std::net::SocketAddr>,
@@ -210,6 +227,10 @@ pub struct WhoisSvr {
     cache_valid: chrono::Duration,
     //cache: RwLock<HashMap<String, WhoisRec>>,
     db: sled::Db,
+    pub irr: crate::irr::IrrClient,
+    /// in-flight whois lookups by query and object kind
+    whois_inflight: Coalescer<(String, String), String>,
+    dns_inflight: Coalescer<String, String>,
 }
 static INVALID_WHOIS: &[u8] = b"Invalid WHOIS query";
 
@@ -225,6 +246,13 @@ impl WhoisSvr {
                 .path(conf.whoisdb.clone())
                 .open()
                 .unwrap(),
+            irr: crate::irr::IrrClient::new(
+                conf.irr_server.as_str(),
+                std::time::Duration::from_secs(conf.whoisreqtimeout),
+                std::time::Duration::from_secs(conf.whoiscachesecs.max(0) as u64),
+            ),
+            whois_inflight: Coalescer::default(),
+            dns_inflight: Coalescer::default(),
         }
     }
     pub fn invalid_query() -> Response<Body> {
@@ -312,7 +340,9 @@ impl WhoisSvr {
                                 {
                                     // run separate task to refresh cache data
                                     let slf = self.clone();
-                                    tokio::spawn(async move { slf.do_query_dns_ptr(target).await });
+                                    tokio::spawn(
+                                        async move { slf.coalesced_dns_ptr(target).await },
+                                    );
                                 }
                                 return Ok(q.val);
                             }
@@ -325,7 +355,18 @@ impl WhoisSvr {
             }
             Err(e) => warn!("sled error: {:?}", e),
         };
-        self.do_query_dns_ptr(target).await
+        self.coalesced_dns_ptr(target).await
+    }
+    /// concurrent lookups of the same address share one DNS query
+    async fn coalesced_dns_ptr(self: &Arc<WhoisSvr>, target: String) -> Result<String, WhoIsError> {
+        self.dns_inflight
+            .run(target.clone(), || async {
+                self.do_query_dns_ptr(target)
+                    .await
+                    .map_err(|e| format!("{:?}", e))
+            })
+            .await
+            .map_err(|e| io::Error::other(e).into())
     }
     pub async fn do_query_dns(
         self: &Arc<WhoisSvr>,
@@ -472,7 +513,7 @@ impl WhoisSvr {
                                 {
                                     let slf = self.clone();
                                     tokio::spawn(async move {
-                                        slf.do_query_whois(target, checkitem).await
+                                        slf.coalesced_whois(target, checkitem).await
                                     });
                                 }
                                 return Ok(q.val);
@@ -486,7 +527,82 @@ impl WhoisSvr {
             }
             Err(e) => warn!("sled error: {:?}", e),
         };
-        self.do_query_whois(target, checkitem).await
+        self.coalesced_whois(target, checkitem).await
+    }
+    /// concurrent identical queries share one lookup and its referral chain
+    async fn coalesced_whois(
+        self: &Arc<WhoisSvr>,
+        target: String,
+        checkitem: Arc<Option<Regex>>,
+    ) -> Result<String, WhoIsError> {
+        let kind = match *checkitem {
+            Some(ref re) => re.as_str().to_string(),
+            None => String::new(),
+        };
+        self.whois_inflight
+            .run((target.clone(), kind), || async {
+                self.do_query_whois(target, checkitem)
+                    .await
+                    .map_err(|e| format!("{:?}", e))
+            })
+            .await
+            .map_err(|e| io::Error::other(e).into())
+    }
+    /// searches cached whois AS objects by AS name or description,
+    /// cache scan runs on blocking thread pool
+    pub async fn search_as_names(&self, q: &str, limit: usize) -> Vec<(u32, String)> {
+        let db = self.db.clone();
+        let q = q.to_string();
+        match tokio::task::spawn_blocking(move || WhoisSvr::scan_as_names(&db, q.as_str(), limit))
+            .await
+        {
+            Ok(ret) => ret,
+            Err(e) => {
+                warn!("whois cache scan: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+    fn scan_as_names(db: &sled::Db, q: &str, limit: usize) -> Vec<(u32, String)> {
+        lazy_static! {
+            static ref RE_ASKEY: Regex = Regex::new(r"(?i)^as(\d+)$").unwrap();
+            static ref RE_ASNAME: Regex =
+                Regex::new(r"(?im)^(as-name|asname|descr|orgname):\s*(.+)$").unwrap();
+        }
+        let lq = q.to_lowercase();
+        let mut ret = Vec::new();
+        for (k, v) in db.iter().filter_map(|x| x.ok()) {
+            let key: serde_json::Value = match serde_json::from_slice(&k) {
+                Ok(key) => key,
+                Err(_) => continue,
+            };
+            let asn: u32 = match key
+                .get("whois")
+                .and_then(|x| x.as_str())
+                .and_then(|x| RE_ASKEY.captures(x))
+                .and_then(|c| c.get(1))
+                .and_then(|x| x.as_str().parse().ok())
+            {
+                Some(n) => n,
+                None => continue,
+            };
+            let rec = match serde_json::from_slice::<WhoisRec>(&v) {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            if let Some(name) = RE_ASNAME
+                .captures_iter(rec.val.as_str())
+                .filter_map(|c| c.get(2))
+                .map(|x| x.as_str().trim())
+                .find(|x| x.to_lowercase().contains(lq.as_str()))
+            {
+                ret.push((asn, name.to_string()));
+                if ret.len() >= limit {
+                    break;
+                }
+            }
+        }
+        ret
     }
     fn filterout_comments(s: &str) -> Vec<&str> {
         s.split('\n')
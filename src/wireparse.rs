@@ -0,0 +1,1361 @@
+//! wire decoders behind bounds checks, so malformed input is rejected before it reaches
+//! decoder indexing, and RFC 7606 style treat-as-withdraw.
+//!
+//! Module depends only on zettabgp and std, so fuzz targets include it directly.
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use zettabgp::bmp::prelude::*;
+use zettabgp::prelude::*;
+
+/// malformed input
+#[derive(Debug)]
+pub enum ParseError {
+    /// decoder rejected message
+    Malformed(BgpError),
+    /// message is shorter or longer than its header claims
+    Length(usize),
+    /// BMP common header version which is not parsed
+    Version(u8),
+}
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Malformed(e) => write!(f, "malformed message: {:?}", e),
+            ParseError::Length(l) => write!(f, "invalid message length {}", l),
+            ParseError::Version(v @ 1..=2) => write!(
+                f,
+                "BMP version {} of pre-RFC 7854 drafts is not supported, configure router to export BMP version 3",
+                v
+            ),
+            ParseError::Version(BMP_V4) => write!(
+                f,
+                "BMP version 4 (draft-ietf-grow-bmp-tlv) is parsed only when built with bmp-v4 feature"
+            ),
+            ParseError::Version(v) => write!(
+                f,
+                "unknown BMP version {}, connection does not carry BMP or is out of sync",
+                v
+            ),
+        }
+    }
+}
+impl std::error::Error for ParseError {}
+impl From<ParseError> for BgpError {
+    fn from(e: ParseError) -> BgpError {
+        match e {
+            ParseError::Malformed(e) => e,
+            e => BgpError::from_string(e.to_string()),
+        }
+    }
+}
+
+/// decodes UPDATE body (without BGP header)
+pub fn decode_update(
+    params: &BgpSessionParams,
+    body: &[u8],
+) -> Result<BgpUpdateMessage, ParseError> {
+    let confed = split_confed(body, has_as4(params));
+    let body = confed.as_deref().unwrap_or(body);
+    // IPv4 NLRI with IPv6 next hop (RFC 8950) is taken out and decoded here
+    let ext = if has_addpath(params) {
+        None
+    } else {
+        ext_nexthop_reach(body)
+    };
+    let body = ext.as_ref().map(|e| e.0.as_slice()).unwrap_or(body);
+    if check_update(params, body).is_none() {
+        return Err(ParseError::Malformed(BgpError::static_str(
+            "UPDATE field out of bounds",
+        )));
+    }
+    let mut upd = BgpUpdateMessage::new();
+    upd.decode_from(params, body)
+        .map_err(ParseError::Malformed)?;
+    if let Some((_, nexthop, addrs)) = ext {
+        upd.attrs.push(BgpAttrItem::MPUpdates(BgpMPUpdates {
+            nexthop: BgpAddr::V6(nexthop),
+            addrs: BgpAddrs::IPV4U(addrs),
+        }));
+    }
+    Ok(upd)
+}
+
+/// decodes OPEN body (without BGP header)
+pub fn decode_open(params: &BgpSessionParams, body: &[u8]) -> Result<BgpOpenMessage, ParseError> {
+    let classic = open_classic(body);
+    if classic.is_none() && is_extended_open(body) {
+        return Err(ParseError::Malformed(BgpError::static_str(
+            "OPEN extended optional parameters do not fit in 255 octets",
+        )));
+    }
+    let body = classic.as_deref().unwrap_or(body);
+    // OPEN decoder checks every length it reads itself
+    let mut bom = BgpOpenMessage::new();
+    bom.decode_from(params, body)
+        .map_err(ParseError::Malformed)?;
+    Ok(bom)
+}
+
+/// speaker AS from 4-octet AS capability or OPEN header
+pub fn open_as(bom: &BgpOpenMessage) -> u32 {
+    bom.caps
+        .iter()
+        .find_map(|c| match c {
+            BgpCapability::CapASN32(n) => Some(*n),
+            _ => None,
+        })
+        .unwrap_or(bom.as_num)
+}
+
+/// BMP version of RFC 7854
+pub const BMP_V3: u8 = 3;
+/// BMP version of draft-ietf-grow-bmp-tlv, parsed with bmp-v4 feature
+pub const BMP_V4: u8 = 4;
+
+/// version and message length of 5 byte BMP common header, message type follows it
+pub fn bmp_common_header(head: &[u8]) -> Result<(u8, usize), ParseError> {
+    if head.len() < 5 {
+        return Err(ParseError::Length(head.len()));
+    }
+    let version = head[0];
+    if version != BMP_V3 && !(cfg!(feature = "bmp-v4") && version == BMP_V4) {
+        return Err(ParseError::Version(version));
+    }
+    Ok((
+        version,
+        u32::from_be_bytes([head[1], head[2], head[3], head[4]]) as usize,
+    ))
+}
+
+/// rewrites BMP v4 route monitoring in buf (from message type on) to v3 layout, returns
+/// its new length. TLVs (type, length of value, index, value) following per-peer header
+/// are walked, BGP message TLV is the one whose value is whole BGP message, per-NLRI TLVs
+/// are dropped; other message types keep v3 layout
+#[cfg(feature = "bmp-v4")]
+pub fn bmp_to_v3(version: u8, buf: &mut [u8], len: usize) -> Result<usize, ParseError> {
+    if version != BMP_V4 || buf.first() != Some(&0) {
+        return Ok(len);
+    }
+    let msg = buf.get(..len).ok_or(ParseError::Length(len))?;
+    let mut pos = 43;
+    let mut bgp = None;
+    while pos < len {
+        let vlen = be16(msg, pos + 2).ok_or(ParseError::Length(len))?;
+        let value = msg
+            .get(pos + 6..pos + 6 + vlen)
+            .ok_or(ParseError::Length(len))?;
+        if bgp.is_none()
+            && vlen >= 19
+            && value[..16].iter().all(|b| *b == 255)
+            && be16(value, 16) == Some(vlen)
+        {
+            bgp = Some(pos + 6);
+        }
+        pos += 6 + vlen;
+    }
+    let start = bgp.ok_or_else(|| {
+        ParseError::Malformed(BgpError::static_str(
+            "BMP v4 route monitoring without BGP message TLV",
+        ))
+    })?;
+    let blen = be16(msg, start + 16).ok_or(ParseError::Length(len))?;
+    buf.copy_within(start..start + blen, 43);
+    Ok(43 + blen)
+}
+
+#[cfg(not(feature = "bmp-v4"))]
+pub fn bmp_to_v3(_version: u8, _buf: &mut [u8], len: usize) -> Result<usize, ParseError> {
+    Ok(len)
+}
+
+/// decodes BMP message following common header
+pub fn decode_bmp(sess: &mut BMPSession, buf: &[u8]) -> Result<BmpMessage, ParseError> {
+    let classic = peerup_map_opens(buf, open_classic);
+    let buf = classic.as_deref().unwrap_or(buf);
+    // route monitoring: type byte, 42 bytes of per-peer header, BGP UPDATE; peer flag A
+    // means 2-octet AS path
+    let update = match (buf.first(), buf.get(43 + 18), be16(buf, 43 + 16)) {
+        (Some(0), Some(2), Some(blen)) => buf.get(43 + 19..43 + blen),
+        _ => None,
+    };
+    let confed = update
+        .and_then(|body| split_confed(body, buf[2] & 0x20 == 0))
+        .map(|body| bmp_with_update(buf, &body));
+    let buf = confed.as_deref().unwrap_or(buf);
+    // IPv4 NLRI with IPv6 next hop (RFC 8950), as in decode_update
+    let ext = match update {
+        Some(_) if !bmp_addpath(sess, buf) => buf
+            .get(43 + 19..43 + be16(buf, 43 + 16).unwrap_or(0))
+            .and_then(ext_nexthop_reach),
+        _ => None,
+    };
+    let extbuf = ext.as_ref().map(|e| bmp_with_update(buf, &e.0));
+    let buf = extbuf.as_deref().unwrap_or(buf);
+    if check_bmp(sess, buf).is_none() {
+        return Err(ParseError::Malformed(BgpError::static_str(
+            "BMP message field out of bounds",
+        )));
+    }
+    let mut msg = sess.decode_from(buf).map_err(ParseError::Malformed)?;
+    if let (Some((_, nexthop, addrs)), BmpMessage::RouteMonitoring(rm)) = (ext, &mut msg) {
+        rm.update.attrs.push(BgpAttrItem::MPUpdates(BgpMPUpdates {
+            nexthop: BgpAddr::V6(nexthop),
+            addrs: BgpAddrs::IPV4U(addrs),
+        }));
+    }
+    Ok(msg)
+}
+
+/// route monitoring message of buf with UPDATE body replaced
+fn bmp_with_update(buf: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut msg = buf[..43 + 19].to_vec();
+    msg[43 + 16..43 + 18].copy_from_slice(&((body.len() + 19) as u16).to_be_bytes());
+    msg.extend_from_slice(body);
+    msg
+}
+
+/// route monitoring message of buf with UPDATE body rewritten by f, None when f leaves it
+pub fn bmp_map_update<F: Fn(&[u8]) -> Option<Vec<u8>>>(buf: &[u8], f: F) -> Option<Vec<u8>> {
+    if *buf.first()? != 0 {
+        return None;
+    }
+    let body = buf.get(43 + 19..43 + be16(buf, 43 + 16)?)?;
+    f(body).map(|b| bmp_with_update(buf, &b))
+}
+
+/// Peer Up message (from message type on) with OPEN bodies rewritten by f, None when f
+/// leaves both
+pub fn peerup_map_opens<F: Fn(&[u8]) -> Option<Vec<u8>>>(msg: &[u8], f: F) -> Option<Vec<u8>> {
+    if *msg.first()? != 3 {
+        return None;
+    }
+    // message type, per-peer header, local address, local and remote ports
+    let mut pos = 1 + 42 + 20;
+    let mut ret = msg.get(..pos)?.to_vec();
+    let mut changed = false;
+    // sent and received OPEN
+    for _ in 0..2 {
+        let blen = be16(msg, pos + 16)?;
+        let open = msg.get(pos..pos + blen)?;
+        match open.get(19..).and_then(&f) {
+            Some(body) => {
+                changed = true;
+                ret.extend_from_slice(&open[..16]);
+                ret.extend_from_slice(&u16::try_from(body.len() + 19).ok()?.to_be_bytes());
+                ret.push(open[18]);
+                ret.extend_from_slice(&body);
+            }
+            None => ret.extend_from_slice(open),
+        }
+        pos += blen;
+    }
+    if !changed {
+        return None;
+    }
+    ret.extend_from_slice(&msg[pos..]);
+    Some(ret)
+}
+
+/// whether ADD-PATH was negotiated on monitored session of route monitoring message, by
+/// OPEN messages of its peer up notification
+fn bmp_addpath(sess: &BMPSession, buf: &[u8]) -> bool {
+    let peer = match BmpMessagePeerHeader::decode_from(&buf[1..]) {
+        Ok((peer, _)) => peer,
+        Err(_) => return false,
+    };
+    sess.sessions
+        .get(&BgpSessionKey::from(&peer))
+        .map(|pu| {
+            has_addpath(&BgpSessionParams::from(&pu.msg1))
+                || has_addpath(&BgpSessionParams::from(&pu.msg2))
+        })
+        .unwrap_or(false)
+}
+
+/// whether 4-octet AS numbers were negotiated
+fn has_as4(params: &BgpSessionParams) -> bool {
+    params
+        .caps
+        .iter()
+        .any(|c| matches!(c, BgpCapability::CapASN32(_)))
+}
+
+/// whether ADD-PATH was negotiated for any family, path identifiers make NLRI ambiguous then
+pub fn has_addpath(params: &BgpSessionParams) -> bool {
+    params
+        .caps
+        .iter()
+        .any(|c| matches!(c, BgpCapability::CapAddPath(_)))
+}
+
+/// big-endian 16 bit field at pos, None when buffer is short
+pub fn be16(b: &[u8], pos: usize) -> Option<usize> {
+    let s = b.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([s[0], s[1]]) as usize)
+}
+
+// Bounds checks below walk message the same way zettabgp decoders do and reject every
+// input on which decoder would index past slice end, overflow a length or loop forever,
+// so decode_from is called only on input it handles with Ok or Err.
+
+/// checks UPDATE body before BgpUpdateMessage::decode_from
+fn check_update(params: &BgpSessionParams, body: &[u8]) -> Option<()> {
+    // withdrawn routes and NLRI are unicast of session transport
+    let afi = match params.peer_mode {
+        BgpTransportMode::IPv4 => 1,
+        BgpTransportMode::IPv6 => 2,
+    };
+    let wlen = be16(body, 0)?;
+    check_nlri(params, afi, 1, body.get(2..2 + wlen)?)?;
+    let alen = be16(body, 2 + wlen)?;
+    check_attrs(params, body.get(4 + wlen..4 + wlen + alen)?)?;
+    check_nlri(params, afi, 1, &body[4 + wlen + alen..])
+}
+
+fn check_attrs(params: &BgpSessionParams, attrs: &[u8]) -> Option<()> {
+    let mut pos = 0;
+    while pos < attrs.len() {
+        let flags = attrs[pos];
+        let code = *attrs.get(pos + 1)?;
+        let (len, hlen) = if flags & 0x10 != 0 {
+            (be16(attrs, pos + 2)?, 4)
+        } else {
+            (*attrs.get(pos + 2)? as usize, 3)
+        };
+        check_attr(params, code, attrs.get(pos + hlen..pos + hlen + len)?)?;
+        pos += hlen + len;
+    }
+    Some(())
+}
+
+fn check_attr(params: &BgpSessionParams, code: u8, value: &[u8]) -> Option<()> {
+    match code {
+        2 => check_aspath(params, value),
+        // COMMUNITIES, LARGE_COMMUNITY items and ORIGINATOR_ID are sliced unchecked
+        8 => value.len().is_multiple_of(4).then_some(()),
+        32 => value.len().is_multiple_of(12).then_some(()),
+        9 => (value.len() >= 4).then_some(()),
+        14 => check_mp_reach(params, value),
+        15 => {
+            let afi = be16(value, 0)? as u16;
+            check_nlri(params, afi, *value.get(2)?, &value[3..])
+        }
+        // PMSI_TUNNEL: flags, tunnel type, label, tunnel identifier; decoder rejects
+        // attribute shorter than label itself
+        22 if value.len() >= 5 => {
+            let min = match value[1] {
+                1 => 17,
+                2 => 6,
+                6 => 9,
+                _ => 0,
+            };
+            (value.len() >= min).then_some(())
+        }
+        // ATTR_SET: origin AS and nested attributes
+        128 if params.has_as32bit && value.len() >= 4 => check_attrs(params, &value[4..]),
+        _ => Some(()),
+    }
+}
+
+/// AS_SET and AS_SEQUENCE segments, each of them complete; shorter path is empty to decoder
+fn check_aspath(params: &BgpSessionParams, mut value: &[u8]) -> Option<()> {
+    if value.len() < 2 {
+        return Some(());
+    }
+    let assize = if params.has_as32bit { 4 } else { 2 };
+    while !value.is_empty() {
+        if value.len() < 2 || (value[0] != 1 && value[0] != 2) {
+            return None;
+        }
+        value = value.get(2 + value[1] as usize * assize..)?;
+    }
+    Some(())
+}
+
+/// MP_REACH_NLRI: AFI, SAFI, next hop, SNPAs, NLRI
+fn check_mp_reach(params: &BgpSessionParams, value: &[u8]) -> Option<()> {
+    let afi = be16(value, 0)? as u16;
+    let safi = *value.get(2)?;
+    let nhlen = *value.get(3)? as usize;
+    let mut pos = 4 + match (afi, safi) {
+        (1, 1 | 2 | 4 | 5 | 66 | 133) | (2, 1 | 2 | 4 | 66) => {
+            value.get(4..4 + nhlen)?;
+            nhlen
+        }
+        // route distinguisher and address
+        (1, 128 | 129 | 134) => value.get(4..4 + 12).map(|_| 12)?,
+        (2, 128 | 129) => value.get(4..4 + 24).map(|_| 24)?,
+        (25, 65 | 70) => {
+            value.get(4..4 + 4)?;
+            nhlen
+        }
+        _ => return None,
+    };
+    let snpas = *value.get(pos)?;
+    pos += 1;
+    for _ in 0..snpas {
+        pos += 1 + *value.get(pos)? as usize;
+    }
+    check_nlri(params, afi, safi, value.get(pos..)?)
+}
+
+/// NLRI of family as BgpAddrs::decode_from dispatches it
+fn check_nlri(params: &BgpSessionParams, afi: u16, safi: u8, nlri: &[u8]) -> Option<()> {
+    let item: fn(u8, &[u8]) -> Option<usize> = match (afi, safi) {
+        (1, 1 | 2) => bits_v4,
+        (2, 1 | 2) => bits_v6,
+        (1, 4) => |bits, b| bits_labeled(bits, b, bits_v4),
+        (2, 4) => |bits, b| bits_labeled(bits, b, bits_v6),
+        (1, 66) => |bits, b| bits_rd(bits, b, bits_mdt_v4),
+        (2, 66) => |bits, b| bits_rd(bits, b, bits_mdt_v6),
+        (1, 128 | 129) => |bits, b| bits_labeled(bits, b, |bits, b| bits_rd(bits, b, bits_v4)),
+        (2, 128 | 129) => |bits, b| bits_labeled(bits, b, |bits, b| bits_rd(bits, b, bits_v6)),
+        (1, 5) => return check_addr_items(nlri, check_mvpn),
+        (1, 133) => return check_addr_items(nlri, |b| check_flowspec(b, fs_v4)),
+        (1, 134) => return check_addr_items(nlri, |b| check_flowspec(b, fs_vpnv4)),
+        (2, 133) => return check_addr_items(nlri, |b| check_flowspec(b, fs_v6)),
+        (25, 70) => return check_addr_items(nlri, check_evpn),
+        (25, 65) => return check_vpls(nlri),
+        _ => return None,
+    };
+    let mut pos = 0;
+    if params.check_addpath_receive(afi, safi)
+        || (params.fuzzy_pathid && nlri.len() >= 5 && nlri[0] == 0 && nlri[1] == 0)
+    {
+        // path identifier before each prefix, trailing bytes are ignored
+        while pos + 4 < nlri.len() {
+            pos += 4;
+            pos += 1 + item(nlri[pos], &nlri[pos + 1..])?;
+        }
+    } else {
+        while pos < nlri.len() {
+            pos += 1 + item(nlri[pos], &nlri[pos + 1..])?;
+        }
+    }
+    Some(())
+}
+
+/// bytes of prefix of given bits length, decoder overflows on bits over 248
+fn bits_prefix(bits: u8, b: &[u8], max: u8) -> Option<usize> {
+    if bits > max {
+        return None;
+    }
+    let bytes = (bits as usize).div_ceil(8);
+    b.get(..bytes).map(|_| bytes)
+}
+
+fn bits_v4(bits: u8, b: &[u8]) -> Option<usize> {
+    bits_prefix(bits, b, 32)
+}
+
+fn bits_v6(bits: u8, b: &[u8]) -> Option<usize> {
+    bits_prefix(bits, b, 128)
+}
+
+/// MPLS label stack ending with bottom-of-stack bit or special label, bits are counted
+/// down by 24 for each label before it is tested
+fn bits_labels(bits: u8, b: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    let mut left = bits;
+    while left > 0 {
+        let l = b.get(pos..pos + 3)?;
+        pos += 3;
+        left = left.checked_sub(24)?;
+        let label = (l[0] as u32) << 12 | (l[1] as u32) << 4 | (l[2] as u32) >> 4;
+        if matches!(label, 0 | 2 | 3 | 524288) || l[2] & 1 != 0 {
+            break;
+        }
+    }
+    Some(pos)
+}
+
+fn bits_labeled(bits: u8, b: &[u8], inner: fn(u8, &[u8]) -> Option<usize>) -> Option<usize> {
+    let labels = bits_labels(bits, b)?;
+    Some(labels + inner(bits - (labels * 8) as u8, &b[labels..])?)
+}
+
+fn bits_rd(bits: u8, b: &[u8], inner: fn(u8, &[u8]) -> Option<usize>) -> Option<usize> {
+    let bits = bits.checked_sub(64)?;
+    Some(8 + inner(bits, b.get(8..)?)?)
+}
+
+/// MDT source prefix and group, decoder copies source into 4 bytes
+fn bits_mdt_v4(bits: u8, b: &[u8]) -> Option<usize> {
+    if !(32..=64).contains(&bits) {
+        return None;
+    }
+    let bytes = (bits as usize - 32).div_ceil(8) + 4;
+    b.get(..bytes).map(|_| bytes)
+}
+
+fn bits_mdt_v6(bits: u8, b: &[u8]) -> Option<usize> {
+    if !(128..=254).contains(&bits) {
+        return None;
+    }
+    let bytes = (bits as usize - 128).div_ceil(8) + 16;
+    b.get(..bytes).map(|_| bytes)
+}
+
+/// items decoded with their own length, each one returns bytes it takes
+fn check_addr_items(nlri: &[u8], item: fn(&[u8]) -> Option<usize>) -> Option<()> {
+    let mut pos = 0;
+    while pos < nlri.len() {
+        pos += item(&nlri[pos..])?;
+    }
+    Some(())
+}
+
+/// MVPN route of IPv4 family: type, length, route of type size
+fn check_mvpn(b: &[u8]) -> Option<usize> {
+    let len = *b.get(1)? as usize;
+    b.get(2..2 + len)?;
+    let size = match b[0] {
+        1 | 2 => 12,
+        3 | 6 | 7 => 22,
+        4 => 28,
+        5 => 18,
+        _ => return None,
+    };
+    (len >= size).then_some(2 + size)
+}
+
+/// EVPN route: type, length, route; route types 1, 2 and 5 end with label stack filling
+/// rest of route
+fn check_evpn(b: &[u8]) -> Option<usize> {
+    let len = *b.get(1)? as usize;
+    let r = b.get(2..2 + len)?;
+    // fixed part up to address length byte at pos - 1
+    let addr = |pos: usize, zero: bool| -> Option<usize> {
+        let a = match *r.get(pos - 1)? {
+            0 if zero => 0,
+            32 => 4,
+            128 => 16,
+            _ => return None,
+        };
+        (pos + a <= len).then_some(pos + a)
+    };
+    let size = match b[0] {
+        // RD, ESI, ethernet tag, labels
+        1 if len >= 22 => 22 + bits_labels((8 * (len - 22)) as u8, &r[22..])?,
+        // RD, ESI, ethernet tag, MAC, IP, labels
+        2 => {
+            let sz = addr(30, true)?;
+            sz + bits_labels((8 * (len - sz)) as u8, &r[sz..])?
+        }
+        // RD, ethernet tag, originator IP
+        3 => addr(13, false)?,
+        // RD, ESI, originator IP
+        4 => addr(19, false)?,
+        // RD, ESI, ethernet tag, prefix, gateway, label
+        5 if len == 34 || len == 58 => len - 3 + bits_labels(24, &r[len - 3..])?,
+        _ => return None,
+    };
+    Some(2 + size)
+}
+
+/// flow specification component: length, type, prefix or operator list
+fn check_flowspec(b: &[u8], prefix: fn(&[u8]) -> Option<usize>) -> Option<usize> {
+    let (pos, nlen) = if b[0] & 0xf0 == 0xf0 {
+        (2, (be16(b, 0)? & 0xfff) + 2)
+    } else {
+        (1, b[0] as usize + 1)
+    };
+    let kind = *b.get(pos)?;
+    let value = b.get(pos + 1..nlen)?;
+    match kind {
+        1 | 2 => Some(pos + 2 + prefix(value)?),
+        3..=13 => {
+            let mut p = 0;
+            while p < value.len() {
+                let n = match (value[p] >> 4) & 3 {
+                    0 => 2,
+                    1 => 3,
+                    2 => 5,
+                    _ => return None,
+                };
+                value.get(p..p + n)?;
+                p += n;
+            }
+            Some(value.len() + pos + 1)
+        }
+        _ => None,
+    }
+}
+
+fn fs_v4(b: &[u8]) -> Option<usize> {
+    Some(1 + bits_v4(*b.first()?, &b[1..])?)
+}
+
+fn fs_v6(b: &[u8]) -> Option<usize> {
+    b.get(1)?;
+    Some(2 + bits_v6(b[0], &b[2..])?)
+}
+
+/// prefix length counting RD, RD, prefix
+fn fs_vpnv4(b: &[u8]) -> Option<usize> {
+    let bits = b.first()?.checked_sub(64)?;
+    Some(9 + bits_v4(bits, b.get(9..)?)?)
+}
+
+/// VPLS NLRI items with 2 byte length: RD, VE ID, block offset and size, labels
+fn check_vpls(mut nlri: &[u8]) -> Option<()> {
+    while !nlri.is_empty() {
+        let len = be16(nlri, 0)?;
+        let item = nlri.get(2..2 + len)?;
+        if len < 17 {
+            return None;
+        }
+        bits_labels(((len - 14) * 8) as u8, &item[14..])?;
+        nlri = &nlri[2 + len..];
+    }
+    Some(())
+}
+
+/// checks BMP message following common header before BMPSession::decode_from
+fn check_bmp(sess: &BMPSession, buf: &[u8]) -> Option<()> {
+    let (kind, msg) = buf.split_first()?;
+    match kind {
+        // route monitoring, session parameters are chosen as decoder does
+        0 => {
+            let (peer, _) = BmpMessagePeerHeader::decode_from(msg).ok()?;
+            let params = match sess.sessions.get(&BgpSessionKey::from(&peer)) {
+                Some(pu) if pu.peer.routerid == pu.msg1.router_id => {
+                    BgpSessionParams::from(&pu.msg1)
+                }
+                Some(pu) => BgpSessionParams::from(&pu.msg2),
+                None => BgpSessionParams::from(&peer),
+            };
+            check_update(&params, bgp_body(msg, 42)?)
+        }
+        // peer down with NOTIFICATION
+        2 if matches!(msg.get(42), Some(1) | Some(3)) => bgp_body(msg, 43).map(|_| ()),
+        // peer up with sent and received OPEN
+        3 => {
+            let sent = bgp_body(msg, 62)?;
+            bgp_body(msg, 62 + 19 + sent.len()).map(|_| ())
+        }
+        // termination, reason TLV is read as 2 bytes whatever its length
+        5 => {
+            let mut tlvs = msg;
+            while tlvs.len() >= 4 {
+                let len = be16(tlvs, 2)?;
+                tlvs.get(4..4 + len)?;
+                if be16(tlvs, 0)? == 1 && len < 2 {
+                    return None;
+                }
+                tlvs = &tlvs[4 + len..];
+            }
+            Some(())
+        }
+        _ => Some(()),
+    }
+}
+
+/// body of BGP message at pos of b, None when its length is below header size or past b
+fn bgp_body(b: &[u8], pos: usize) -> Option<&[u8]> {
+    let len = be16(b, pos + 16)?;
+    if len < 19 {
+        return None;
+    }
+    b.get(pos + 19..pos + len)
+}
+
+fn prefixes_v4(mut b: &[u8]) -> Option<Vec<BgpAddrV4>> {
+    let mut ret = Vec::new();
+    while let Some(len) = b.first() {
+        let len = *len as usize;
+        let bytes = len.div_ceil(8);
+        if len > 32 {
+            return None;
+        }
+        let mut a = [0u8; 4];
+        a[..bytes].copy_from_slice(b.get(1..1 + bytes)?);
+        ret.push(BgpAddrV4::new(Ipv4Addr::from(a), len as u8));
+        b = &b[1 + bytes..];
+    }
+    Some(ret)
+}
+
+fn prefixes_v6(mut b: &[u8]) -> Option<Vec<BgpAddrV6>> {
+    let mut ret = Vec::new();
+    while let Some(len) = b.first() {
+        let len = *len as usize;
+        let bytes = len.div_ceil(8);
+        if len > 128 {
+            return None;
+        }
+        let mut a = [0u8; 16];
+        a[..bytes].copy_from_slice(b.get(1..1 + bytes)?);
+        ret.push(BgpAddrV6::new(Ipv6Addr::from(a), len as u8));
+        b = &b[1 + bytes..];
+    }
+    Some(ret)
+}
+
+/// Extended Next Hop Encoding capability value: IPv4 unicast NLRI with IPv6 next hop
+pub const EXT_NEXTHOP_CAP: [u8; 6] = [0, 1, 0, 1, 0, 2];
+const CAP_EXT_NEXTHOP: u8 = 5;
+
+/// path attributes of UPDATE body, (start, end) of each in body
+fn attr_spans(body: &[u8]) -> Option<Vec<(usize, usize)>> {
+    let wlen = be16(body, 0)?;
+    let alen = be16(body, 2 + wlen)?;
+    let start = 4 + wlen;
+    body.get(start..start + alen)?;
+    let mut ret = Vec::new();
+    let mut pos = start;
+    while pos < start + alen {
+        let flags = *body.get(pos)?;
+        let (len, hlen) = if flags & 0x10 != 0 {
+            (be16(body, pos + 2)?, 4)
+        } else {
+            (*body.get(pos + 2)? as usize, 3)
+        };
+        if pos + hlen + len > start + alen {
+            return None;
+        }
+        ret.push((pos, pos + hlen + len));
+        pos += hlen + len;
+    }
+    Some(ret)
+}
+
+/// MP_REACH_NLRI of IPv4 unicast with IPv6 next hop (global or global and link-local):
+/// UPDATE body without it, global next hop and prefixes
+fn ext_nexthop_reach(body: &[u8]) -> Option<(Vec<u8>, Ipv6Addr, Vec<BgpAddrV4>)> {
+    for (start, end) in attr_spans(body)? {
+        if body[start + 1] != 14 {
+            continue;
+        }
+        let hlen = if body[start] & 0x10 != 0 { 4 } else { 3 };
+        let value = &body[start + hlen..end];
+        let nhlen = *value.get(3)? as usize;
+        if be16(value, 0)? != 1 || *value.get(2)? != 1 || (nhlen != 16 && nhlen != 32) {
+            return None;
+        }
+        let mut nh = [0u8; 16];
+        nh.copy_from_slice(value.get(4..20)?);
+        let addrs = prefixes_v4(value.get(5 + nhlen..)?)?;
+        let wlen = be16(body, 0)?;
+        let alen = be16(body, 2 + wlen)? - (end - start);
+        let mut rest = body[..start].to_vec();
+        rest[2 + wlen..4 + wlen].copy_from_slice(&(alen as u16).to_be_bytes());
+        rest.extend_from_slice(&body[end..]);
+        return Some((rest, Ipv6Addr::from(nh), addrs));
+    }
+    None
+}
+
+/// AS path segment types of confederation (RFC 5065)
+pub const AS_CONFED_SEQUENCE: u8 = 3;
+pub const AS_CONFED_SET: u8 = 4;
+/// internal attribute carrying confederation segments taken out of AS_PATH with 4-octet
+/// members, see BgpConfedPath::decode_from. Type code is reserved for development (RFC 2042),
+/// so the attribute exists only between decoder and RIB: received ones are dropped and
+/// strip_internal removes it before an UPDATE is encoded again
+pub const ATTR_CONFED_PATH: u8 = 255;
+
+/// whether attribute is made by decoder and never goes to the wire
+pub fn is_internal(attr: &BgpAttrItem) -> bool {
+    matches!(attr, BgpAttrItem::Unknown(n) if n.params.typecode == ATTR_CONFED_PATH)
+}
+
+/// removes decoder's internal attributes from UPDATE
+pub fn strip_internal(upd: &mut BgpUpdateMessage) {
+    upd.attrs.retain(|a| !is_internal(a));
+}
+
+/// UPDATE body without path attributes of given type code
+fn drop_attr(body: &[u8], code: u8) -> Option<Vec<u8>> {
+    let spans: Vec<(usize, usize)> = attr_spans(body)?
+        .into_iter()
+        .filter(|(start, _)| body[*start + 1] == code)
+        .collect();
+    if spans.is_empty() {
+        return None;
+    }
+    let wlen = be16(body, 0)?;
+    let alen = be16(body, 2 + wlen)? - spans.iter().map(|(s, e)| e - s).sum::<usize>();
+    let mut ret = Vec::with_capacity(body.len());
+    let mut pos = 0;
+    for (start, end) in spans {
+        ret.extend_from_slice(&body[pos..start]);
+        pos = end;
+    }
+    ret.extend_from_slice(&body[pos..]);
+    ret[2 + wlen..4 + wlen].copy_from_slice(&(alen as u16).to_be_bytes());
+    Some(ret)
+}
+
+/// UPDATE body with AS_CONFED_SEQUENCE and AS_CONFED_SET segments moved from AS_PATH to
+/// ATTR_CONFED_PATH, decoder knows only AS_SET and AS_SEQUENCE. Attributes of that type
+/// sent by peer are dropped, they would be taken for confederation segments
+fn split_confed(body: &[u8], as4: bool) -> Option<Vec<u8>> {
+    let dropped = drop_attr(body, ATTR_CONFED_PATH);
+    move_confed(dropped.as_deref().unwrap_or(body), as4).or(dropped)
+}
+
+fn move_confed(body: &[u8], as4: bool) -> Option<Vec<u8>> {
+    let assize = if as4 { 4 } else { 2 };
+    let (start, end) = attr_spans(body)?
+        .into_iter()
+        .find(|(start, _)| body[*start + 1] == 2)?;
+    let hlen = if body[start] & 0x10 != 0 { 4 } else { 3 };
+    let mut value = &body[start + hlen..end];
+    let mut path = Vec::new();
+    let mut confed = Vec::new();
+    while !value.is_empty() {
+        let seglen = 2 + *value.get(1)? as usize * assize;
+        let seg = value.get(..seglen)?;
+        if seg[0] == AS_CONFED_SEQUENCE || seg[0] == AS_CONFED_SET {
+            confed.extend_from_slice(&seg[..2]);
+            for asn in seg[2..].chunks(assize) {
+                let asn = asn.iter().fold(0u32, |n, b| (n << 8) | *b as u32);
+                confed.extend_from_slice(&asn.to_be_bytes());
+            }
+        } else {
+            path.extend_from_slice(seg);
+        }
+        value = &value[seglen..];
+    }
+    if confed.is_empty() {
+        return None;
+    }
+    // both with extended length
+    let mut attrs = vec![body[start] | 0x10, 2];
+    attrs.extend_from_slice(&(path.len() as u16).to_be_bytes());
+    attrs.extend_from_slice(&path);
+    attrs.extend_from_slice(&[0xd0, ATTR_CONFED_PATH]);
+    attrs.extend_from_slice(&(confed.len() as u16).to_be_bytes());
+    attrs.extend_from_slice(&confed);
+    let wlen = be16(body, 0)?;
+    let alen = be16(body, 2 + wlen)? - (end - start) + attrs.len();
+    if alen > 0xffff {
+        return None;
+    }
+    let mut ret = body[..start].to_vec();
+    ret[2 + wlen..4 + wlen].copy_from_slice(&(alen as u16).to_be_bytes());
+    ret.extend_from_slice(&attrs);
+    ret.extend_from_slice(&body[end..]);
+    Some(ret)
+}
+
+/// optional parameters of OPEN body (type and value)
+/// of both encodings, RFC 9072 extended one with 2 octet lengths included
+fn open_params(body: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let ext = is_extended_open(body);
+    let (mut b, lsize) = if ext {
+        let plen = be16(body, 11)?;
+        (body.get(13..13 + plen)?, 2)
+    } else {
+        let plen = *body.get(9)? as usize;
+        (body.get(10..10 + plen)?, 1)
+    };
+    let mut ret = Vec::new();
+    while !b.is_empty() {
+        let len = if ext {
+            be16(b, 1)?
+        } else {
+            *b.get(1)? as usize
+        };
+        ret.push((b[0], b.get(1 + lsize..1 + lsize + len)?));
+        b = &b[1 + lsize + len..];
+    }
+    Some(ret)
+}
+
+/// whether OPEN body has extended optional parameters length (RFC 9072)
+fn is_extended_open(body: &[u8]) -> bool {
+    body.get(9) == Some(&255) && body.get(10) == Some(&255)
+}
+
+/// OPEN body with header of body and params in 1 octet length encoding, None when
+/// parameters do not fit in 255 octets
+fn open_with_params(body: &[u8], params: &[(u8, &[u8])]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    for (ptype, value) in params.iter() {
+        out.push(*ptype);
+        out.push(u8::try_from(value.len()).ok()?);
+        out.extend_from_slice(value);
+    }
+    let mut ret = body.get(..9)?.to_vec();
+    ret.push(u8::try_from(out.len()).ok()?);
+    ret.extend_from_slice(&out);
+    Some(ret)
+}
+
+/// OPEN body with RFC 9072 extended optional parameters in 1 octet length encoding
+/// decoder knows, None when body is not extended or parameters do not fit
+pub fn open_classic(body: &[u8]) -> Option<Vec<u8>> {
+    if !is_extended_open(body) {
+        return None;
+    }
+    open_with_params(body, &open_params(body)?)
+}
+
+/// OPEN body without vendor-specific capabilities (codes 128-255) decoder may reject,
+/// None when there are none or body is malformed
+pub fn strip_private_caps(body: &[u8]) -> Option<Vec<u8>> {
+    let params = open_params(body)?;
+    let mut kept = Vec::with_capacity(params.len());
+    let mut stripped = false;
+    for (ptype, value) in params.iter() {
+        if *ptype != 2 {
+            continue;
+        }
+        let mut caps = *value;
+        let mut k = Vec::new();
+        while !caps.is_empty() {
+            let cap = caps.get(..2 + *caps.get(1)? as usize)?;
+            if cap[0] >= 128 {
+                stripped = true;
+            } else {
+                k.extend_from_slice(cap);
+            }
+            caps = &caps[cap.len()..];
+        }
+        kept.push(k);
+    }
+    if !stripped {
+        return None;
+    }
+    let mut caps = kept.iter();
+    let params: Vec<(u8, &[u8])> = params
+        .iter()
+        .filter_map(|(ptype, value)| match ptype {
+            2 => caps
+                .next()
+                .filter(|k| !k.is_empty())
+                .map(|k| (2, k.as_slice())),
+            _ => Some((*ptype, *value)),
+        })
+        .collect();
+    open_with_params(body, &params)
+}
+
+/// UPDATE body with repeated path attributes dropped, first one of each type kept,
+/// None when nothing repeats
+pub fn dedup_attrs(body: &[u8]) -> Option<Vec<u8>> {
+    let mut seen = [false; 256];
+    let mut attrs = Vec::new();
+    let mut repeated = false;
+    for (start, end) in attr_spans(body)? {
+        let code = body[start + 1] as usize;
+        if seen[code] {
+            repeated = true;
+            continue;
+        }
+        seen[code] = true;
+        attrs.extend_from_slice(&body[start..end]);
+    }
+    if !repeated {
+        return None;
+    }
+    let wlen = be16(body, 0)?;
+    let alen = be16(body, 2 + wlen)?;
+    let mut ret = body[..4 + wlen].to_vec();
+    ret[2 + wlen..4 + wlen].copy_from_slice(&(attrs.len() as u16).to_be_bytes());
+    ret.extend_from_slice(&attrs);
+    ret.extend_from_slice(&body[4 + wlen + alen..]);
+    Some(ret)
+}
+
+/// length of BMP Peer Up (from message type on) up to end of received OPEN, without
+/// information TLVs following it; None when message is not Peer Up or has no TLVs
+pub fn peerup_without_tlvs(msg: &[u8]) -> Option<usize> {
+    if *msg.first()? != 3 {
+        return None;
+    }
+    // message type, per-peer header, local address, local and remote ports
+    let mut pos = 1 + 42 + 20;
+    // sent and received OPEN
+    for _ in 0..2 {
+        let blen = be16(msg, pos + 16)?;
+        if blen < 19 {
+            return None;
+        }
+        pos += blen;
+    }
+    if pos >= msg.len() {
+        return None;
+    }
+    Some(pos)
+}
+
+/// value of Extended Next Hop Encoding capability in OPEN body, decoder skips it
+pub fn open_ext_nexthop(body: &[u8]) -> Option<Vec<u8>> {
+    for (ptype, mut caps) in open_params(body)? {
+        // capabilities parameter
+        if ptype != 2 {
+            continue;
+        }
+        while caps.len() >= 2 {
+            let len = caps[1] as usize;
+            let value = caps.get(2..2 + len)?;
+            if caps[0] == CAP_EXT_NEXTHOP {
+                return Some(value.to_vec());
+            }
+            caps = &caps[2 + len..];
+        }
+    }
+    None
+}
+
+/// adds Extended Next Hop Encoding capability to OPEN body of len bytes in buf,
+/// new length or None when body is malformed or buffer is short
+pub fn add_ext_nexthop(buf: &mut [u8], len: usize, value: &[u8]) -> Option<usize> {
+    let add = 4 + value.len();
+    let plen = *buf.get(9)? as usize;
+    if len != 10 + plen || plen + add > 255 || value.len() > 253 {
+        return None;
+    }
+    let param = buf.get_mut(len..len + add)?;
+    param[0] = 2;
+    param[1] = (2 + value.len()) as u8;
+    param[2] = CAP_EXT_NEXTHOP;
+    param[3] = value.len() as u8;
+    param[4..].copy_from_slice(value);
+    buf[9] = (plen + add) as u8;
+    Some(len + add)
+}
+
+/// unicast prefixes announced or withdrawn by malformed UPDATE body, to be withdrawn
+/// instead of dropping message. Empty when NLRI itself can not be parsed reliably.
+pub fn treat_as_withdraw(body: &[u8], addpath: bool) -> Vec<BgpAddrs> {
+    if addpath {
+        return Vec::new();
+    }
+    // MP_REACH_NLRI/MP_UNREACH_NLRI unicast prefixes
+    let mp = |attrs: &[u8]| -> Option<(Vec<BgpAddrV4>, Vec<BgpAddrV6>)> {
+        let (mut v4, mut v6) = (Vec::new(), Vec::new());
+        let mut pos = 0;
+        while pos < attrs.len() {
+            let flags = *attrs.get(pos)?;
+            let code = *attrs.get(pos + 1)?;
+            let (len, hlen) = if flags & 0x10 != 0 {
+                (be16(attrs, pos + 2)?, 4)
+            } else {
+                (*attrs.get(pos + 2)? as usize, 3)
+            };
+            let value = attrs.get(pos + hlen..pos + hlen + len)?;
+            pos += hlen + len;
+            let (afi, safi) = (be16(value, 0).unwrap_or(0), value.get(2).copied());
+            if safi != Some(1) || (afi != 1 && afi != 2) {
+                continue;
+            }
+            let nlri = match code {
+                // next hop length, next hop, reserved byte
+                14 => value.get(5 + *value.get(3)? as usize..)?,
+                15 => value.get(3..)?,
+                _ => continue,
+            };
+            if afi == 1 {
+                v4.extend(prefixes_v4(nlri)?);
+            } else {
+                v6.extend(prefixes_v6(nlri)?);
+            }
+        }
+        Some((v4, v6))
+    };
+    let parse = || -> Option<(Vec<BgpAddrV4>, Vec<BgpAddrV6>)> {
+        let wlen = be16(body, 0)?;
+        let mut v4 = prefixes_v4(body.get(2..2 + wlen)?)?;
+        let alen = be16(body, 2 + wlen)?;
+        let attrs = body.get(4 + wlen..4 + wlen + alen)?;
+        v4.extend(prefixes_v4(&body[4 + wlen + alen..])?);
+        // attributes may be malformed themselves, NLRI found so far is still withdrawn
+        let mut v6 = Vec::new();
+        if let Some((m4, m6)) = mp(attrs) {
+            v4.extend(m4);
+            v6 = m6;
+        }
+        Some((v4, v6))
+    };
+    let mut ret = Vec::new();
+    if let Some((v4, v6)) = parse() {
+        if !v4.is_empty() {
+            ret.push(BgpAddrs::IPV4U(v4));
+        }
+        if !v6.is_empty() {
+            ret.push(BgpAddrs::IPV6U(v6));
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bmp_common_header() {
+        assert_eq!(bmp_common_header(&[3, 0, 0, 0, 6]).unwrap(), (3, 6));
+        assert!(matches!(
+            bmp_common_header(&[1, 0, 0, 0, 6]),
+            Err(ParseError::Version(1))
+        ));
+        assert!(bmp_common_header(&[3, 0]).is_err());
+        assert_eq!(
+            bmp_common_header(&[4, 0, 0, 0, 6]).is_ok(),
+            cfg!(feature = "bmp-v4")
+        );
+    }
+
+    #[test]
+    fn test_quirk_rewrites() {
+        // OPEN: version, AS, hold time, router ID, capabilities ASN32 and Cisco 128
+        let mut open = vec![4, 0xfd, 0xe8, 0, 90, 192, 0, 2, 1, 10, 2, 8];
+        open.extend_from_slice(&[65, 4, 0, 0, 0xfd, 0xe8, 128, 0]);
+        let stripped = strip_private_caps(&open).unwrap();
+        assert_eq!(&stripped[9..], &[8, 2, 6, 65, 4, 0, 0, 0xfd, 0xe8]);
+        assert!(strip_private_caps(&stripped).is_none());
+        // UPDATE with ORIGIN twice and no NLRI
+        let upd = vec![0, 0, 0, 8, 0x40, 1, 1, 0, 0x40, 1, 1, 2];
+        assert_eq!(dedup_attrs(&upd).unwrap(), vec![0, 0, 0, 4, 0x40, 1, 1, 0]);
+        assert!(dedup_attrs(&[0, 0, 0, 4, 0x40, 1, 1, 0]).is_none());
+        let mut peerup = vec![3u8];
+        peerup.extend_from_slice(&[0u8; 62]);
+        for _ in 0..2 {
+            peerup.extend_from_slice(&[255u8; 16]);
+            peerup.extend_from_slice(&[0, 19, 1]);
+        }
+        let len = peerup.len();
+        assert_eq!(peerup_without_tlvs(&peerup), None);
+        peerup.extend_from_slice(&[0, 0, 0, 2, b'r', b'1']);
+        assert_eq!(peerup_without_tlvs(&peerup), Some(len));
+        // Peer Up OPENs go through OPEN rewrite, information TLVs are kept
+        let mut peerup = vec![3u8];
+        peerup.extend_from_slice(&[0u8; 62]);
+        for _ in 0..2 {
+            peerup.extend_from_slice(&[255u8; 16]);
+            peerup.extend_from_slice(&[0, 19 + open.len() as u8, 1]);
+            peerup.extend_from_slice(&open);
+        }
+        peerup.extend_from_slice(&[0, 0, 0, 2, b'r', b'1']);
+        let fixed = peerup_map_opens(&peerup, strip_private_caps).unwrap();
+        assert_eq!(fixed.len(), peerup.len() - 4);
+        assert_eq!(&fixed[63 + 16..63 + 19], &[0, 19 + 18, 1]);
+        assert_eq!(&fixed[63 + 19 + 9..63 + 37], &stripped[9..]);
+        assert_eq!(&fixed[fixed.len() - 6..], &[0, 0, 0, 2, b'r', b'1']);
+        assert!(peerup_map_opens(&fixed, strip_private_caps).is_none());
+        // route monitoring UPDATE goes through UPDATE rewrite
+        let mut rm = vec![0u8];
+        rm.extend_from_slice(&[0u8; 42]);
+        rm.extend_from_slice(&[255u8; 16]);
+        rm.extend_from_slice(&[0, 19 + upd.len() as u8, 2]);
+        rm.extend_from_slice(&upd);
+        let fixed = bmp_map_update(&rm, dedup_attrs).unwrap();
+        assert_eq!(&fixed[43 + 16..43 + 19], &[0, 27, 2]);
+        assert_eq!(&fixed[43 + 19..], &[0, 0, 0, 4, 0x40, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_extended_open() {
+        // OPEN with RFC 9072 extended optional parameters: capabilities ASN32 and Cisco 128
+        let mut open = vec![4, 0xfd, 0xe8, 0, 90, 192, 0, 2, 1, 255, 255, 0, 11, 2, 0, 8];
+        open.extend_from_slice(&[65, 4, 0, 0, 0xfd, 0xe8, 128, 0]);
+        let classic = open_classic(&open).unwrap();
+        assert_eq!(&classic[..9], &open[..9]);
+        assert_eq!(&classic[9..], &[10, 2, 8, 65, 4, 0, 0, 0xfd, 0xe8, 128, 0]);
+        assert!(open_classic(&classic).is_none());
+        assert_eq!(
+            &strip_private_caps(&open).unwrap()[9..],
+            &[8, 2, 6, 65, 4, 0, 0, 0xfd, 0xe8]
+        );
+        let params = BgpSessionParams::new(
+            64500,
+            90,
+            BgpTransportMode::IPv4,
+            Ipv4Addr::new(192, 0, 2, 1),
+            vec![],
+        );
+        let bom = decode_open(&params, &open).unwrap();
+        assert_eq!(open_as(&bom), 65000);
+        // parameters over 255 octets can not be decoded
+        let mut long = vec![4, 0xfd, 0xe8, 0, 90, 192, 0, 2, 1, 255, 255, 1, 3, 2, 1, 0];
+        for _ in 0..128 {
+            long.extend_from_slice(&[70, 0]);
+        }
+        assert!(open_classic(&long).is_none());
+        assert!(decode_open(&params, &long).is_err());
+    }
+
+    #[cfg(feature = "bmp-v4")]
+    #[test]
+    fn test_bmp_v4_route_monitoring() {
+        let mut msg = vec![0u8; 43];
+        // TLV type, length and index before BGP KEEPALIVE sized message
+        msg.extend_from_slice(&[0, 1, 0, 19, 0, 0]);
+        msg.extend_from_slice(&[255u8; 16]);
+        msg.extend_from_slice(&[0, 19, 4]);
+        // per-NLRI TLV
+        msg.extend_from_slice(&[0, 2, 0, 1, 0, 0, 7]);
+        let len = msg.len();
+        let n = bmp_to_v3(BMP_V4, &mut msg, len).unwrap();
+        assert_eq!(n, 43 + 19);
+        assert_eq!(&msg[43..59], &[255u8; 16]);
+        assert_eq!(msg[43 + 18], 4);
+        // all-ones value of TLV before BGP message is not taken for marker
+        let mut msg = vec![0u8; 43];
+        msg.extend_from_slice(&[0, 2, 0, 20, 0, 0]);
+        msg.extend_from_slice(&[255u8; 20]);
+        msg.extend_from_slice(&[0, 1, 0, 19, 0, 0]);
+        msg.extend_from_slice(&[255u8; 16]);
+        msg.extend_from_slice(&[0, 19, 4]);
+        let len = msg.len();
+        assert_eq!(bmp_to_v3(BMP_V4, &mut msg, len).unwrap(), 43 + 19);
+        assert_eq!(msg[43 + 18], 4);
+        // TLV running past message end
+        let mut msg = vec![0u8; 43];
+        msg.extend_from_slice(&[0, 1, 0, 19, 0, 0]);
+        msg.extend_from_slice(&[255u8; 16]);
+        let len = msg.len();
+        assert!(bmp_to_v3(BMP_V4, &mut msg, len).is_err());
+    }
+
+    #[test]
+    fn test_treat_as_withdraw() {
+        // withdrawn 10.0.0.0/8, broken attribute, NLRI 192.168.0.0/16
+        let body = [0, 2, 8, 10, 0, 3, 0x40, 1, 5, 16, 192, 168];
+        let w = treat_as_withdraw(&body, false);
+        assert_eq!(w.len(), 1);
+        assert_eq!(w[0].len(), 2);
+        assert!(treat_as_withdraw(&body, true).is_empty());
+        // truncated
+        assert!(treat_as_withdraw(&body[0..5], false).is_empty());
+        assert!(treat_as_withdraw(&[0, 1, 33], false).is_empty());
+    }
+
+    #[test]
+    fn test_ext_nexthop() {
+        // ORIGIN, MP_REACH_NLRI ipv4u with next hop 2001:db8::1 and 192.0.2.0/24
+        let mut body = vec![0, 0, 0, 32, 0x40, 1, 1, 0, 0x80, 14, 25, 0, 1, 1, 16];
+        body.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        body.extend_from_slice(&[0, 24, 192, 0, 2]);
+        let (rest, nh, addrs) = ext_nexthop_reach(&body).unwrap();
+        assert_eq!(rest, vec![0, 0, 0, 4, 0x40, 1, 1, 0]);
+        assert_eq!(nh, "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(addrs, vec![BgpAddrV4::new(Ipv4Addr::new(192, 0, 2, 0), 24)]);
+        // IPv4 next hop is left to decoder
+        let v4nh = [0, 0, 0, 14, 0x80, 14, 11, 0, 1, 1, 4, 10, 0, 0, 1, 0, 8, 10];
+        assert!(ext_nexthop_reach(&v4nh).is_none());
+        // same UPDATE in BMP route monitoring: peer header of 192.0.2.1 AS 64500
+        let mut bmp = vec![0u8; 43];
+        bmp[23..27].copy_from_slice(&[192, 0, 2, 1]);
+        bmp[27..31].copy_from_slice(&64500u32.to_be_bytes());
+        bmp.extend_from_slice(&[255u8; 16]);
+        bmp.extend_from_slice(&((body.len() + 19) as u16).to_be_bytes());
+        bmp.push(2);
+        bmp.extend_from_slice(&body);
+        let upd = match decode_bmp(&mut BMPSession::default(), &bmp).unwrap() {
+            BmpMessage::RouteMonitoring(rm) => rm.update,
+            m => panic!("unexpected {:?}", m),
+        };
+        assert!(upd.attrs.iter().any(|a| matches!(a,
+            BgpAttrItem::MPUpdates(u) if u.nexthop == BgpAddr::V6(nh) && u.addrs == BgpAddrs::IPV4U(addrs.clone()))));
+        // version, AS, hold time, router id, capability ipv4u
+        let mut open = [0u8; 64];
+        let hdr = [4, 0xfb, 0xf4, 0, 90, 10, 0, 0, 1, 8, 2, 6, 1, 4, 0, 1, 0, 1];
+        open[..18].copy_from_slice(&hdr);
+        assert_eq!(open_ext_nexthop(&open[..18]), None);
+        let len = add_ext_nexthop(&mut open, 18, &EXT_NEXTHOP_CAP).unwrap();
+        assert_eq!(len, 28);
+        assert_eq!(open[9], 18);
+        assert_eq!(
+            open_ext_nexthop(&open[..len]),
+            Some(EXT_NEXTHOP_CAP.to_vec())
+        );
+        assert!(add_ext_nexthop(&mut open, 17, &EXT_NEXTHOP_CAP).is_none());
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let params = BgpSessionParams::new(
+            64500,
+            90,
+            BgpTransportMode::IPv4,
+            Ipv4Addr::new(192, 0, 2, 1),
+            vec![BgpCapability::CapASN32(64500)],
+        );
+        let malformed =
+            |body: &[u8]| matches!(decode_update(&params, body), Err(ParseError::Malformed(_)));
+        // ORIGIN, AS_PATH of AS_SEQUENCE 64500, 192.0.2.0/24
+        let body = [
+            0, 0, 0, 13, 0x40, 1, 1, 0, 0x40, 2, 6, 2, 1, 0, 0, 0xfb, 0xf4, 24, 192, 0, 2,
+        ];
+        assert_eq!(decode_update(&params, &body).unwrap().attrs.len(), 2);
+        // AS_PATH segment shorter than its member, or trailing byte after empty one
+        assert!(malformed(&[0, 0, 0, 5, 0x40, 2, 2, 2, 1]));
+        assert!(malformed(&[0, 0, 0, 6, 0x40, 2, 3, 2, 0, 2]));
+        // prefix length overflowing byte count, COMMUNITIES not multiple of 4
+        assert!(malformed(&[0, 0, 0, 0, 250, 1]));
+        assert!(malformed(&[0, 0, 0, 8, 0xc0, 8, 5, 0, 0, 0, 0, 0]));
+        // MP_REACH_NLRI next hop past attribute, labeled prefix shorter than label
+        assert!(malformed(&[0, 0, 0, 8, 0x80, 14, 5, 0, 1, 1, 16, 0]));
+        assert!(malformed(&[
+            0, 0, 0, 17, 0x80, 14, 14, 0, 1, 4, 4, 10, 0, 0, 1, 0, 10, 0, 0, 0x11
+        ]));
+        // MP_UNREACH_NLRI without SAFI
+        assert!(malformed(&[0, 0, 0, 5, 0x80, 15, 2, 0, 1]));
+        // route monitoring with BGP length below header size and past message
+        let mut rm = vec![0u8; 43];
+        rm.extend_from_slice(&[255u8; 16]);
+        rm.extend_from_slice(&[0, 18, 2]);
+        let mut sess = BMPSession::default();
+        assert!(matches!(
+            decode_bmp(&mut sess, &rm),
+            Err(ParseError::Malformed(_))
+        ));
+        rm[43 + 17] = 40;
+        assert!(decode_bmp(&mut sess, &rm).is_err());
+        // peer up with truncated OPEN
+        let mut pu = vec![3u8; 1];
+        pu.extend_from_slice(&[0u8; 62]);
+        pu.extend_from_slice(&[255u8; 16]);
+        pu.extend_from_slice(&[0, 40, 1, 4, 0xfb, 0xf4]);
+        assert!(decode_bmp(&mut sess, &pu).is_err());
+        assert!(decode_bmp(&mut sess, &[]).is_err());
+    }
+
+    #[test]
+    fn test_split_confed() {
+        // ORIGIN, AS_PATH of AS_CONFED_SEQUENCE 65001 65002 and AS_SEQUENCE 64500, 192.0.2.0/24
+        let body = [
+            0, 0, 0, 17, 0x40, 1, 1, 0, 0x40, 2, 10, 3, 2, 0xfd, 0xe9, 0xfd, 0xea, 2, 1, 0xfb,
+            0xf4, 24, 192, 0, 2,
+        ];
+        assert_eq!(
+            split_confed(&body, false).unwrap(),
+            vec![
+                0, 0, 0, 26, 0x40, 1, 1, 0, 0x50, 2, 0, 4, 2, 1, 0xfb, 0xf4, 0xd0, 255, 0, 10, 3,
+                2, 0, 0, 0xfd, 0xe9, 0, 0, 0xfd, 0xea, 24, 192, 0, 2
+            ]
+        );
+        assert!(split_confed(&body[..15], false).is_none());
+        let plain = [0, 0, 0, 9, 0x40, 1, 1, 0, 0x40, 2, 2, 2, 0];
+        assert!(split_confed(&plain, false).is_none());
+        // type 255 from peer is dropped
+        let foreign = [
+            0, 0, 0, 11, 0x40, 1, 1, 0, 0x40, 2, 0, 0xc0, 255, 1, 7, 24, 192, 0, 2,
+        ];
+        assert_eq!(
+            split_confed(&foreign, false).unwrap(),
+            vec![0, 0, 0, 7, 0x40, 1, 1, 0, 0x40, 2, 0, 24, 192, 0, 2]
+        );
+        let mut params = BgpSessionParams::new(
+            64500,
+            180,
+            BgpTransportMode::IPv4,
+            Ipv4Addr::new(192, 0, 2, 1),
+            vec![],
+        );
+        params.has_as32bit = false;
+        let mut upd = decode_update(&params, &body).unwrap();
+        assert!(upd.attrs.iter().any(is_internal));
+        strip_internal(&mut upd);
+        assert!(!upd.attrs.iter().any(is_internal));
+        let mut buf = [0u8; 256];
+        let sz = upd.encode_to(&params, &mut buf).unwrap();
+        assert!(split_confed(&buf[..sz], false).is_none());
+    }
+}
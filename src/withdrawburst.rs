@@ -0,0 +1,239 @@
+//! withdraw burst coalescing: when session withdraws more than withdraw_burst_threshold
+//! routes per second (session reset, router restart), its withdraw events are merged into
+//! WithdrawBatch events with counts published once per second, and the whole burst is kept
+//! as single record in burst log. Route history of burst only records withdraws of routes
+//! which were active
+use crate::bgpsvc::BgpSessionId;
+use crate::timestamp::Timestamp;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use zettabgp::prelude::*;
+
+/// finished bursts kept for /api/stats/bursts
+pub const BURST_LOG_SIZE: usize = 100;
+
+/// withdraws of session coalesced during one second of burst
+#[derive(Debug, Clone, Serialize)]
+pub struct WithdrawBatch {
+    pub session: BgpSessionId,
+    pub started: Timestamp,
+    pub until: Timestamp,
+    pub count: usize,
+    /// withdrawn routes by RIB
+    pub counts: BTreeMap<String, usize>,
+    /// batch ends the burst, may be empty when all withdraws were already published
+    pub last: bool,
+    /// withdraws in arrival order, for consumers following individual routes
+    #[serde(skip)]
+    pub addrs: Vec<Arc<BgpAddrs>>,
+}
+impl WithdrawBatch {
+    pub(crate) fn new(session: BgpSessionId, now: Timestamp) -> WithdrawBatch {
+        WithdrawBatch {
+            session,
+            started: now,
+            until: now,
+            count: 0,
+            counts: BTreeMap::new(),
+            last: false,
+            addrs: Vec::new(),
+        }
+    }
+}
+
+/// burst log record
+#[derive(Debug, Clone, Serialize)]
+pub struct WithdrawBurst {
+    pub session: BgpSessionId,
+    pub started: Timestamp,
+    pub ended: Timestamp,
+    pub count: usize,
+    pub counts: BTreeMap<String, usize>,
+    /// batch events published for burst
+    pub batches: usize,
+}
+
+struct SessionRate {
+    /// unix second of count
+    second: i64,
+    count: usize,
+    burst: Option<WithdrawBurst>,
+    batch: Option<WithdrawBatch>,
+}
+
+#[derive(Default)]
+pub struct WithdrawCoalescer {
+    /// withdrawn routes per second of session starting burst, 0 - disabled
+    threshold: usize,
+    sessions: BTreeMap<BgpSessionId, SessionRate>,
+    ready: Vec<WithdrawBatch>,
+    pub log: VecDeque<WithdrawBurst>,
+}
+impl WithdrawCoalescer {
+    pub fn new(threshold: usize) -> WithdrawCoalescer {
+        WithdrawCoalescer {
+            threshold,
+            ..Default::default()
+        }
+    }
+    /// withdraws to publish as is, None when they were added to batch
+    pub fn on_withdraws(
+        &mut self,
+        session: BgpSessionId,
+        rib: &str,
+        addrs: Arc<BgpAddrs>,
+        now: Timestamp,
+    ) -> Option<Arc<BgpAddrs>> {
+        if self.threshold == 0 {
+            return Some(addrs);
+        }
+        let second = now.timestamp();
+        let s = self.sessions.entry(session).or_insert(SessionRate {
+            second,
+            count: 0,
+            burst: None,
+            batch: None,
+        });
+        if s.second != second {
+            // burst ends with first second below threshold
+            if s.count < self.threshold || s.second + 1 < second {
+                Self::end_burst(s, &mut self.ready, &mut self.log);
+            }
+            s.second = second;
+            s.count = 0;
+        }
+        s.count += addrs.len();
+        if s.burst.is_none() {
+            if s.count < self.threshold {
+                return Some(addrs);
+            }
+            s.burst = Some(WithdrawBurst {
+                session,
+                started: now,
+                ended: now,
+                count: 0,
+                counts: BTreeMap::new(),
+                batches: 0,
+            });
+        }
+        let n = addrs.len();
+        if let Some(ref mut b) = s.burst {
+            b.ended = now;
+            b.count += n;
+            *b.counts.entry(rib.to_string()).or_insert(0) += n;
+        }
+        let batch = s
+            .batch
+            .get_or_insert_with(|| WithdrawBatch::new(session, now));
+        batch.until = now;
+        batch.count += n;
+        *batch.counts.entry(rib.to_string()).or_insert(0) += n;
+        batch.addrs.push(addrs);
+        None
+    }
+    fn end_burst(
+        s: &mut SessionRate,
+        ready: &mut Vec<WithdrawBatch>,
+        log: &mut VecDeque<WithdrawBurst>,
+    ) {
+        let mut burst = match s.burst.take() {
+            None => return,
+            Some(b) => b,
+        };
+        // empty last batch marks end of burst which batches were already published
+        let mut b = s
+            .batch
+            .take()
+            .unwrap_or_else(|| WithdrawBatch::new(burst.session, burst.ended));
+        b.last = true;
+        ready.push(b);
+        burst.batches += 1;
+        info!(
+            "Session {} withdraw burst ended: {} routes from {} to {}",
+            burst.session, burst.count, burst.started, burst.ended
+        );
+        log.push_back(burst);
+        while log.len() > BURST_LOG_SIZE {
+            log.pop_front();
+        }
+    }
+    /// batches older than a second and last batches of quiet sessions
+    pub fn take_ready(&mut self, now: Timestamp) -> Vec<WithdrawBatch> {
+        let second = now.timestamp();
+        for s in self.sessions.values_mut() {
+            if s.burst.is_some() && s.second + 1 < second {
+                Self::end_burst(s, &mut self.ready, &mut self.log);
+            }
+            if let Some(ref b) = s.batch {
+                if *now - *b.started >= chrono::Duration::seconds(1) {
+                    self.ready.extend(s.batch.take());
+                    if let Some(ref mut burst) = s.burst {
+                        burst.batches += 1;
+                    }
+                }
+            }
+        }
+        self.sessions
+            .retain(|_, s| s.burst.is_some() || s.second + 1 >= second);
+        std::mem::take(&mut self.ready)
+    }
+    /// pending batch of session, published before its announcements to keep order
+    pub fn take_session(&mut self, session: BgpSessionId) -> Option<WithdrawBatch> {
+        let s = self.sessions.get_mut(&session)?;
+        let b = s.batch.take()?;
+        if let Some(ref mut burst) = s.burst {
+            burst.batches += 1;
+        }
+        Some(b)
+    }
+    /// session is in withdraw burst
+    pub fn in_burst(&self, session: BgpSessionId) -> bool {
+        self.sessions
+            .get(&session)
+            .map(|s| s.burst.is_some())
+            .unwrap_or(false)
+    }
+    /// bursts in progress, need take_ready even without new withdraws
+    pub fn pending(&self) -> bool {
+        !self.ready.is_empty() || self.sessions.values().any(|s| s.burst.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wdr(n: u8) -> Arc<BgpAddrs> {
+        Arc::new(BgpAddrs::IPV4U(
+            (0..n)
+                .map(|i| BgpAddrV4::new(std::net::Ipv4Addr::new(10, i, 0, 0), 16))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn test_withdraw_burst() {
+        let mut c = WithdrawCoalescer::new(10);
+        let t = |s: i64| Timestamp::from_unix(1_700_000_000 + s, 0);
+        assert!(c.on_withdraws(1, "ipv4u", wdr(4), t(0)).is_some());
+        assert!(c.on_withdraws(1, "ipv4u", wdr(8), t(0)).is_none());
+        assert!(c.on_withdraws(1, "ipv4u", wdr(20), t(0)).is_none());
+        assert!(c.in_burst(1) && !c.in_burst(2));
+        assert!(c.on_withdraws(2, "ipv4u", wdr(2), t(0)).is_some());
+        assert!(c.take_ready(t(0)).is_empty());
+        assert_eq!(c.take_session(1).map(|b| b.count), Some(28));
+        assert!(c.on_withdraws(1, "ipv4u", wdr(30), t(1)).is_none());
+        let ready = c.take_ready(t(2));
+        assert_eq!(ready.len(), 1);
+        assert_eq!((ready[0].count, ready[0].last), (30, false));
+        assert!(c.pending());
+        let ready = c.take_ready(t(5));
+        assert_eq!((ready.len(), ready[0].count, ready[0].last), (1, 0, true));
+        assert!(!c.pending() && !c.in_burst(1));
+        assert_eq!(c.log.len(), 1);
+        assert_eq!(c.log[0].count, 58);
+        assert_eq!(c.log[0].counts["ipv4u"], 58);
+        assert!(c.on_withdraws(1, "ipv4u", wdr(1), t(6)).is_some());
+    }
+}